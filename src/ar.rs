@@ -0,0 +1,626 @@
+//! ar archive backend, for static libraries and the outer layer of .deb packages
+//!
+//! An archive is a flat list of files, each after a 60 byte text header. Names that don't fit
+//! in the header are kept in a table of long names like GNU ar does, and the BSD form of long
+//! names is read as well.
+
+use clap::Args;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::exclude::Exclude;
+use crate::limits::OutputLimits;
+use crate::progress::{
+    progress_bar, ArchiveProgress, ProgressArgs, ProgressDisplay, ProgressHooks, ProgressObserver,
+};
+use crate::utils::*;
+
+const MAGIC: &[u8; 8] = b"!<arch>\n";
+const HEADER_SIZE: usize = 60;
+/// Longest name that fits in the header, with the '/' GNU ar ends names with
+const MAX_SHORT_NAME: usize = 15;
+/// Largest size the 10 digit size field can hold
+const MAX_SIZE: u64 = 9_999_999_999;
+/// Largest table of long names to read, anything larger is corrupt
+const MAX_LONG_NAMES: u64 = 16 * 1024 * 1024;
+
+#[derive(Args, Debug)]
+pub struct ArArgs {
+    #[clap(flatten)]
+    pub common_args: CommonArgs,
+
+    #[clap(flatten)]
+    pub progress_args: ProgressArgs,
+}
+
+#[derive(Default, Clone)]
+pub struct Ar {
+    /// Extract entries with absolute paths or '..' outside of the output directory
+    pub allow_unsafe_paths: bool,
+    /// Files to leave out when archiving, and entries to skip when extracting
+    pub exclude: Exclude,
+    /// Leave out the metadata that differs between runs
+    pub reproducible: Option<Reproducible>,
+    pub progress_args: ProgressArgs,
+    /// Progress observer, cancellation, and limits on the total size of the extracted files
+    pub hooks: ProgressHooks,
+}
+
+impl Ar {
+    pub fn new(args: &ArArgs) -> Ar {
+        Ar {
+            allow_unsafe_paths: args.common_args.allow_unsafe_paths,
+            exclude: Exclude::default(),
+            reproducible: None,
+            progress_args: args.progress_args,
+            hooks: ProgressHooks::default(),
+        }
+    }
+}
+
+impl Compressor for Ar {
+    fn name(&self) -> &str {
+        "ar"
+    }
+
+    /// Static libraries are the most common ar archives
+    fn extension(&self) -> &str {
+        "a"
+    }
+
+    /// Debian packages are ar archives too
+    fn is_archive(&self, in_path: &Path) -> bool {
+        in_path
+            .extension()
+            .is_some_and(|ext| ext == "a" || ext == "ar" || ext == "deb")
+    }
+
+    /// Extraction needs to specify the directory, so use the current directory
+    fn default_extracted_filename(&self, _in_path: &Path) -> String {
+        ".".to_string()
+    }
+
+    fn set_allow_unsafe_paths(&mut self, allow: bool) {
+        self.allow_unsafe_paths = allow;
+    }
+
+    fn set_limits(&mut self, limits: OutputLimits) {
+        self.hooks.limits = limits;
+    }
+
+    fn set_observer(&mut self, observer: Arc<dyn ProgressObserver>) {
+        self.hooks.observer = Some(observer);
+    }
+
+    fn set_progress(&mut self, progress: ProgressDisplay) {
+        self.progress_args.progress = progress;
+    }
+
+    fn progress(&self) -> ProgressDisplay {
+        self.progress_args.progress
+    }
+
+    fn set_exclude(&mut self, exclude: Exclude) {
+        self.exclude = exclude;
+    }
+
+    fn set_reproducible(&mut self, reproducible: Reproducible) {
+        self.reproducible = Some(reproducible);
+    }
+
+    fn compress(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        let paths = match input {
+            CmprssInput::Path(paths) => paths,
+            CmprssInput::Pipe(_) => {
+                return cmprss_error("error: ar does not support stdin as input")
+            }
+            CmprssInput::Reader(_) => {
+                return cmprss_error("error: ar does not support streams as input")
+            }
+        };
+        let files = self.input_files(paths)?;
+        let input_size = files.iter().map(|(_, metadata)| metadata.len()).sum();
+        let bar = progress_bar(
+            Some(input_size),
+            self.progress_args.progress,
+            &output,
+            &self.hooks,
+        );
+        let progress = ArchiveProgress::new(&self.hooks, bar);
+        let mut writer = progress.writer(output.into_stream()?);
+        self.write_archive(&files, &mut writer, &progress)?;
+        writer.flush()?;
+        progress.finish();
+        Ok(())
+    }
+
+    fn extract(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        let out_path = match &output {
+            CmprssOutput::Path(path) if path.is_dir() => path.clone(),
+            _ => return cmprss_error("error: ar can only extract to a directory"),
+        };
+        let (reader, input_size) = open_input(input)?;
+        let bar = progress_bar(
+            input_size,
+            self.progress_args.progress,
+            &output,
+            &self.hooks,
+        );
+        let progress = ArchiveProgress::new(&self.hooks, bar);
+        let mut archive = ArReader::new(progress.reader(reader))?;
+        let mut written = 0u64;
+        while let Some(member) = archive.next_member()? {
+            if !self.exclude.is_extracted(Path::new(&member.name)) {
+                crate::info!("skipping {}, it is excluded", member.name);
+                continue;
+            }
+            crate::debug!("extracting {}", member.name);
+            let target = entry_path(&out_path, Path::new(&member.name), self.allow_unsafe_paths)?;
+            written = written.saturating_add(member.size);
+            self.hooks
+                .limits
+                .check(input_size.unwrap_or(u64::MAX), written)?;
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut file = File::create(&target)?;
+            archive.copy_data(&mut file)?;
+            drop(file);
+            restore_metadata(&target, &member)?;
+            progress.add_output(member.size);
+        }
+        progress.finish();
+        Ok(())
+    }
+
+    fn list(&self, input: CmprssInput) -> Result<Vec<ArchiveEntry>, io::Error> {
+        let (reader, _) = open_input(input)?;
+        let mut archive = ArReader::new(reader)?;
+        let mut entries = Vec::new();
+        while let Some(member) = archive.next_member()? {
+            entries.push(ArchiveEntry {
+                path: member.name,
+                is_dir: false,
+                size: member.size,
+                compressed_size: None,
+                mtime: Some(member.mtime as i64),
+                mode: Some(member.mode & 0o7777),
+                comment: None,
+            });
+        }
+        Ok(entries)
+    }
+
+    fn cat(&self, input: CmprssInput, entry: &str, output: CmprssOutput) -> Result<(), io::Error> {
+        let (reader, _) = open_input(input)?;
+        let mut archive = ArReader::new(reader)?;
+        while let Some(member) = archive.next_member()? {
+            if entry_matches(&member.name, entry) {
+                let mut output = output.into_stream()?;
+                archive.copy_data(&mut output)?;
+                return output.flush();
+            }
+        }
+        Err(io::Error::other(format!("{} is not in the archive", entry)))
+    }
+}
+
+/// Open the single archive to read, with its size if known
+fn open_input(input: CmprssInput) -> Result<(Box<dyn Read + Send>, Option<u64>), io::Error> {
+    if let CmprssInput::Path(paths) = &input {
+        if paths.len() > 1 {
+            return Err(io::Error::other(
+                "only 1 archive can be extracted at a time",
+            ));
+        }
+    }
+    input.into_stream()
+}
+
+/// Restore the modification time and Unix permissions of an extracted file
+fn restore_metadata(path: &Path, member: &Member) -> Result<(), io::Error> {
+    let file = fs::OpenOptions::new().write(true).open(path)?;
+    file.set_modified(UNIX_EPOCH + Duration::from_secs(member.mtime))?;
+    #[cfg(unix)]
+    if member.mode & 0o7777 != 0 {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(member.mode & 0o7777))?;
+    }
+    Ok(())
+}
+
+/// A file in an archive, from its header
+struct Member {
+    name: String,
+    mtime: u64,
+    uid: u32,
+    gid: u32,
+    mode: u32,
+    size: u64,
+}
+
+impl Member {
+    /// The header, with the name field already chosen
+    fn header(&self, name: &str) -> Result<Vec<u8>, io::Error> {
+        if self.size > MAX_SIZE {
+            return Err(io::Error::other(format!(
+                "{} is too large for an ar archive",
+                self.name
+            )));
+        }
+        // Ids that don't fit in the 6 digits are left out
+        let id = |id: u32| if id > 999_999 { 0 } else { id };
+        let header = format!(
+            "{:<16}{:<12}{:<6}{:<6}{:<8o}{:<10}`\n",
+            name,
+            self.mtime.min(999_999_999_999),
+            id(self.uid),
+            id(self.gid),
+            self.mode & 0o7777_7777,
+            self.size
+        );
+        Ok(header.into_bytes())
+    }
+}
+
+/// Parse a number field of a header, which is padded with spaces
+fn parse_field(field: &[u8], radix: u32) -> Result<u64, io::Error> {
+    let text = std::str::from_utf8(field).unwrap_or_default().trim();
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, radix).map_err(|_| io::Error::other("corrupt ar header"))
+}
+
+/// Reads the members of an archive in order
+struct ArReader<R: Read> {
+    inner: R,
+    /// The table of long names, if the archive has one
+    long_names: Vec<u8>,
+    /// Data of the current member that hasn't been read
+    remaining: u64,
+    /// The padding after the current member, to an even offset
+    padding: u64,
+}
+
+impl<R: Read> ArReader<R> {
+    fn new(mut inner: R) -> Result<Self, io::Error> {
+        let mut magic = [0; 8];
+        inner
+            .read_exact(&mut magic)
+            .map_err(|_| io::Error::other("not an ar archive"))?;
+        if &magic != MAGIC {
+            return Err(io::Error::other("not an ar archive"));
+        }
+        Ok(ArReader {
+            inner,
+            long_names: Vec::new(),
+            remaining: 0,
+            padding: 0,
+        })
+    }
+
+    /// Read the header of the next file, skipping the rest of the current one
+    /// Symbol tables and the table of long names are read or skipped as well.
+    fn next_member(&mut self) -> Result<Option<Member>, io::Error> {
+        loop {
+            let skip = self.remaining + self.padding;
+            io::copy(&mut (&mut self.inner).take(skip), &mut io::sink())?;
+            let mut header = [0; HEADER_SIZE];
+            let read = read_full(&mut self.inner, &mut header)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            if read < HEADER_SIZE || &header[58..60] != b"`\n" {
+                return Err(io::Error::other("corrupt ar header"));
+            }
+            let mut size = parse_field(&header[48..58], 10)?;
+            self.remaining = size;
+            self.padding = size % 2;
+            let name = String::from_utf8_lossy(&header[..16])
+                .trim_end()
+                .to_string();
+
+            let name = if name == "//" {
+                if size > MAX_LONG_NAMES {
+                    return Err(io::Error::other("corrupt ar long name table"));
+                }
+                self.long_names = vec![0; size as usize];
+                self.inner.read_exact(&mut self.long_names)?;
+                self.remaining -= size;
+                continue;
+            } else if name == "/" || name == "/SYM64/" || name.starts_with("__.SYMDEF") {
+                continue;
+            } else if let Some(length) = name.strip_prefix("#1/") {
+                // BSD names are at the start of the data
+                let length = parse_field(length.as_bytes(), 10)?;
+                if length > size {
+                    return Err(io::Error::other("corrupt ar header"));
+                }
+                let mut name = vec![0; length as usize];
+                self.inner.read_exact(&mut name)?;
+                self.remaining -= length;
+                size -= length;
+                let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+                String::from_utf8_lossy(&name[..end]).into_owned()
+            } else if let Some(offset) = name.strip_prefix('/') {
+                let offset = parse_field(offset.as_bytes(), 10)? as usize;
+                let long_name = self
+                    .long_names
+                    .get(offset..)
+                    .ok_or_else(|| io::Error::other("corrupt ar long name"))?;
+                let end = long_name
+                    .windows(2)
+                    .position(|end| end == b"/\n")
+                    .ok_or_else(|| io::Error::other("corrupt ar long name"))?;
+                String::from_utf8_lossy(&long_name[..end]).into_owned()
+            } else {
+                name.strip_suffix('/').unwrap_or(&name).to_string()
+            };
+            return Ok(Some(Member {
+                name,
+                mtime: parse_field(&header[16..28], 10)?,
+                uid: parse_field(&header[28..34], 10)? as u32,
+                gid: parse_field(&header[34..40], 10)? as u32,
+                mode: parse_field(&header[40..48], 8)? as u32,
+                size,
+            }));
+        }
+    }
+
+    /// Copy the data of the current member
+    fn copy_data(&mut self, writer: &mut dyn Write) -> Result<(), io::Error> {
+        let size = self.remaining;
+        let copied = io::copy(&mut (&mut self.inner).take(size), writer)?;
+        if copied < size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated ar archive",
+            ));
+        }
+        self.remaining -= size;
+        Ok(())
+    }
+}
+
+/// The name of a member and a reader of its data
+pub(crate) type MemberReader = (String, Box<dyn Read + Send>);
+
+/// Open the data of the first member whose name matches, for reading it without the others
+/// Packages use this, the payload of a .deb is in its data.tar.* member.
+pub(crate) fn open_member<R: Read + Send + 'static>(
+    reader: R,
+    matches: impl Fn(&str) -> bool,
+) -> Result<Option<MemberReader>, io::Error> {
+    let mut archive = ArReader::new(reader)?;
+    while let Some(member) = archive.next_member()? {
+        if matches(&member.name) {
+            let size = archive.remaining;
+            return Ok(Some((member.name, Box::new(archive.inner.take(size)))));
+        }
+    }
+    Ok(None)
+}
+
+/// Read until the buffer is full or the end of the input, returning how much was read
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> Result<usize, io::Error> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match reader.read(&mut buffer[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+impl Ar {
+    /// The files to archive, which have to be files since archives have no directories
+    fn input_files(&self, paths: Vec<PathBuf>) -> Result<Vec<(PathBuf, fs::Metadata)>, io::Error> {
+        let mut files = Vec::new();
+        for path in paths {
+            let Some(name) = path.file_name() else {
+                return Err(io::Error::other(format!(
+                    "{} is not a file",
+                    path.display()
+                )));
+            };
+            if self.exclude.is_excluded(Path::new(name)) {
+                crate::info!("skipping {}, it is excluded", path.display());
+                continue;
+            }
+            let metadata = fs::metadata(&path)?;
+            if !metadata.is_file() {
+                return Err(io::Error::other(format!(
+                    "ar archives only hold files, {} is not a file",
+                    path.display()
+                )));
+            }
+            files.push((path, metadata));
+        }
+        Ok(files)
+    }
+
+    /// The header fields of a file
+    fn member(&self, path: &Path, metadata: &fs::Metadata) -> Member {
+        #[cfg(unix)]
+        let (uid, gid, mode) = {
+            use std::os::unix::fs::MetadataExt;
+            (metadata.uid(), metadata.gid(), metadata.mode())
+        };
+        #[cfg(not(unix))]
+        let (uid, gid, mode) = (0, 0, 0o100644);
+        let mtime = modified_secs(metadata);
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        match self.reproducible {
+            Some(reproducible) => Member {
+                name,
+                mtime: reproducible.clamp(mtime),
+                uid: 0,
+                gid: 0,
+                mode: 0o100000 | reproducible.mode(mode, false),
+                size: metadata.len(),
+            },
+            None => Member {
+                name,
+                mtime,
+                uid,
+                gid,
+                mode,
+                size: metadata.len(),
+            },
+        }
+    }
+
+    /// Write the archive, with a table of long names first if any are needed
+    fn write_archive(
+        &self,
+        files: &[(PathBuf, fs::Metadata)],
+        writer: &mut dyn Write,
+        progress: &ArchiveProgress,
+    ) -> Result<(), io::Error> {
+        let members: Vec<Member> = files
+            .iter()
+            .map(|(path, metadata)| self.member(path, metadata))
+            .collect();
+        let mut long_names = Vec::new();
+        let mut names = Vec::new();
+        for member in &members {
+            if member.name.len() > MAX_SHORT_NAME || member.name.contains([' ', '/']) {
+                names.push(format!("/{}", long_names.len()));
+                long_names.extend_from_slice(member.name.as_bytes());
+                long_names.extend_from_slice(b"/\n");
+            } else {
+                names.push(format!("{}/", member.name));
+            }
+        }
+
+        writer.write_all(MAGIC)?;
+        if !long_names.is_empty() {
+            writer.write_all(format!("{:<48}{:<10}`\n", "//", long_names.len()).as_bytes())?;
+            writer.write_all(&long_names)?;
+            if long_names.len() % 2 == 1 {
+                writer.write_all(b"\n")?;
+            }
+        }
+        for (((path, _), member), name) in files.iter().zip(&members).zip(&names) {
+            crate::debug!("adding {}", member.name);
+            writer.write_all(&member.header(name)?)?;
+            let copied = io::copy(&mut progress.reader(File::open(path)?), writer)?;
+            if copied != member.size {
+                return Err(io::Error::other(format!(
+                    "{} changed size while it was archived",
+                    path.display()
+                )));
+            }
+            if member.size % 2 == 1 {
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use predicates::prelude::*;
+
+    #[test]
+    fn roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Ar::default();
+
+        let dir = assert_fs::TempDir::new()?;
+        let short = dir.child("short.o");
+        short.write_str("odd")?;
+        let long = dir.child("a file with a long name.o");
+        long.write_str("even")?;
+        let archive = dir.child("archive.a");
+
+        compressor.compress(
+            CmprssInput::Path(vec![short.path().to_path_buf(), long.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        archive.assert(predicate::path::is_file());
+
+        let out = dir.child("out");
+        out.create_dir_all()?;
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        out.child("short.o").assert("odd");
+        out.child("a file with a long name.o").assert("even");
+
+        let entries = compressor.list(CmprssInput::Path(vec![archive.path().to_path_buf()]))?;
+        let names: Vec<_> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(names, ["short.o", "a file with a long name.o"]);
+        assert_eq!(entries[1].size, 4);
+        Ok(())
+    }
+
+    /// An archive as written by GNU ar, with a symbol table, and one with BSD long names
+    #[test]
+    fn other_writers() -> Result<(), Box<dyn std::error::Error>> {
+        let header = |name: &str, size: usize| {
+            format!(
+                "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                name, 0, 0, 0, 100644, size
+            )
+        };
+        let mut gnu = MAGIC.to_vec();
+        gnu.extend(header("/", 4).bytes());
+        gnu.extend(b"\0\0\0\0");
+        gnu.extend(header("//", 22).bytes());
+        gnu.extend(b"a_very_long_object.o/\n");
+        gnu.extend(header("/0", 3).bytes());
+        gnu.extend(b"abc\n");
+        gnu.extend(header("b.o/", 2).bytes());
+        gnu.extend(b"de");
+
+        let mut bsd = MAGIC.to_vec();
+        bsd.extend(header("__.SYMDEF", 2).bytes());
+        bsd.extend(b"\0\0");
+        bsd.extend(header("#1/20", 23).bytes());
+        bsd.extend(b"a_very_long_object.oabc\n");
+        bsd.extend(header("b.o", 2).bytes());
+        bsd.extend(b"de");
+
+        for archive in [gnu, bsd] {
+            let mut reader = ArReader::new(&archive[..])?;
+            let mut contents = Vec::new();
+            while let Some(member) = reader.next_member()? {
+                let mut data = Vec::new();
+                reader.copy_data(&mut data)?;
+                contents.push((member.name, data));
+            }
+            assert_eq!(
+                contents,
+                [
+                    ("a_very_long_object.o".to_string(), b"abc".to_vec()),
+                    ("b.o".to_string(), b"de".to_vec())
+                ]
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn directories_are_refused() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = assert_fs::TempDir::new()?;
+        dir.child("dir/file").write_str("data")?;
+        let result = Ar::default().compress(
+            CmprssInput::Path(vec![dir.child("dir").path().to_path_buf()]),
+            CmprssOutput::Path(dir.child("archive.a").path().to_path_buf()),
+        );
+        assert!(result.is_err());
+        assert!(ArReader::new(&b"!<arch"[..]).is_err());
+        Ok(())
+    }
+}
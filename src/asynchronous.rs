@@ -0,0 +1,243 @@
+//! Async versions of the streaming API for the stream compressors, using tokio
+
+use crate::brotli::Brotli;
+#[cfg(feature = "bzip2")]
+use crate::bzip2::Bzip2;
+use crate::gzip::Gzip;
+#[cfg(feature = "xz")]
+use crate::xz::Xz;
+#[cfg(feature = "zstd")]
+use crate::zstd::Zstd;
+use async_compression::tokio::{bufread, write};
+use async_compression::Level;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+#[cfg(feature = "zstd")]
+use std::task::{Context, Poll};
+#[cfg(feature = "zstd")]
+use tokio::io::ReadBuf;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+/// A boxed async input stream
+pub type AsyncReader<'a> = Pin<Box<dyn AsyncRead + Send + 'a>>;
+
+/// A boxed async output stream
+pub type AsyncWriter<'a> = Pin<Box<dyn AsyncWrite + Send + 'a>>;
+
+/// Async counterpart of the streaming methods of [`Compressor`](crate::utils::Compressor)
+pub trait AsyncCompressor {
+    /// Wrap an async writer so that everything written to it is compressed
+    /// The returned writer must be shut down to write the end of the stream.
+    fn wrap_async_writer<'a>(&self, writer: AsyncWriter<'a>) -> AsyncWriter<'a>;
+
+    /// Wrap an async reader so that everything read from it is extracted
+    fn wrap_async_reader<'a>(&self, reader: AsyncReader<'a>) -> AsyncReader<'a>;
+
+    /// Compress everything from the reader into the writer, then shut down the writer
+    fn compress_async<'a>(
+        &self,
+        mut reader: AsyncReader<'a>,
+        writer: AsyncWriter<'a>,
+    ) -> impl Future<Output = Result<(), io::Error>> + Send + 'a {
+        let mut encoder = self.wrap_async_writer(writer);
+        async move {
+            tokio::io::copy(&mut reader, &mut encoder).await?;
+            encoder.shutdown().await
+        }
+    }
+
+    /// Extract everything from the reader into the writer, then flush the writer
+    fn extract_async<'a>(
+        &self,
+        reader: AsyncReader<'a>,
+        mut writer: AsyncWriter<'a>,
+    ) -> impl Future<Output = Result<(), io::Error>> + Send + 'a {
+        let mut decoder = self.wrap_async_reader(reader);
+        async move {
+            tokio::io::copy(&mut decoder, &mut writer).await?;
+            writer.flush().await
+        }
+    }
+}
+
+impl AsyncCompressor for Gzip {
+    fn wrap_async_writer<'a>(&self, writer: AsyncWriter<'a>) -> AsyncWriter<'a> {
+        let level = Level::Precise(self.compression_level as i32);
+        Box::pin(write::GzipEncoder::with_quality(writer, level))
+    }
+
+    fn wrap_async_reader<'a>(&self, reader: AsyncReader<'a>) -> AsyncReader<'a> {
+        let mut decoder = bufread::GzipDecoder::new(BufReader::new(reader));
+        decoder.multiple_members(true);
+        Box::pin(decoder)
+    }
+}
+
+#[cfg(feature = "xz")]
+impl AsyncCompressor for Xz {
+    fn wrap_async_writer<'a>(&self, writer: AsyncWriter<'a>) -> AsyncWriter<'a> {
+        let level = Level::Precise(self.level as i32);
+        Box::pin(write::XzEncoder::with_quality(writer, level))
+    }
+
+    fn wrap_async_reader<'a>(&self, reader: AsyncReader<'a>) -> AsyncReader<'a> {
+        let mut decoder = bufread::XzDecoder::new(BufReader::new(reader));
+        decoder.multiple_members(true);
+        Box::pin(decoder)
+    }
+}
+
+#[cfg(feature = "bzip2")]
+impl AsyncCompressor for Bzip2 {
+    fn wrap_async_writer<'a>(&self, writer: AsyncWriter<'a>) -> AsyncWriter<'a> {
+        let level = Level::Precise(self.level as i32);
+        Box::pin(write::BzEncoder::with_quality(writer, level))
+    }
+
+    fn wrap_async_reader<'a>(&self, reader: AsyncReader<'a>) -> AsyncReader<'a> {
+        Box::pin(bufread::BzDecoder::new(BufReader::new(reader)))
+    }
+}
+
+impl AsyncCompressor for Brotli {
+    fn wrap_async_writer<'a>(&self, writer: AsyncWriter<'a>) -> AsyncWriter<'a> {
+        let level = Level::Precise(self.quality as i32);
+        let params =
+            async_compression::brotli::EncoderParams::default().window_size(self.window as i32);
+        Box::pin(write::BrotliEncoder::with_quality_and_params(
+            writer, level, params,
+        ))
+    }
+
+    fn wrap_async_reader<'a>(&self, reader: AsyncReader<'a>) -> AsyncReader<'a> {
+        Box::pin(bufread::BrotliDecoder::new(BufReader::new(reader)))
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl AsyncCompressor for Zstd {
+    fn wrap_async_writer<'a>(&self, writer: AsyncWriter<'a>) -> AsyncWriter<'a> {
+        let level = Level::Precise(self.level);
+        match &self.dictionary {
+            Some(dictionary) => match write::ZstdEncoder::with_dict(writer, level, dictionary) {
+                Ok(encoder) => Box::pin(encoder),
+                Err(e) => Box::pin(Failed::from(e)),
+            },
+            None => Box::pin(write::ZstdEncoder::with_quality(writer, level)),
+        }
+    }
+
+    fn wrap_async_reader<'a>(&self, reader: AsyncReader<'a>) -> AsyncReader<'a> {
+        let reader = BufReader::new(reader);
+        match &self.dictionary {
+            Some(dictionary) => match bufread::ZstdDecoder::with_dict(reader, dictionary) {
+                Ok(decoder) => Box::pin(decoder),
+                Err(e) => Box::pin(Failed::from(e)),
+            },
+            None => Box::pin(bufread::ZstdDecoder::new(reader)),
+        }
+    }
+}
+
+/// A stream that fails every operation with the error from setting it up
+/// The wrapping methods can't fail, so errors are reported on first use instead.
+#[cfg(feature = "zstd")]
+struct Failed {
+    kind: io::ErrorKind,
+    message: String,
+}
+
+#[cfg(feature = "zstd")]
+impl From<io::Error> for Failed {
+    fn from(e: io::Error) -> Self {
+        Failed {
+            kind: e.kind(),
+            message: e.to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Failed {
+    fn error<T>(&self) -> Poll<Result<T, io::Error>> {
+        Poll::Ready(Err(io::Error::new(self.kind, self.message.clone())))
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl AsyncRead for Failed {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        _: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        self.error()
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl AsyncWrite for Failed {
+    fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, _: &[u8]) -> Poll<io::Result<usize>> {
+        self.error()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.error()
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.error()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Compressor;
+    use std::io::Read;
+
+    /// Compress with the async API and extract with the sync API, then the reverse
+    async fn roundtrip<C: AsyncCompressor + Compressor>(compressor: C) -> Result<(), io::Error> {
+        let data = b"garbage data for testing";
+
+        let mut compressed = Vec::new();
+        compressor
+            .compress_async(Box::pin(&data[..]), Box::pin(&mut compressed))
+            .await?;
+        let mut extracted = Vec::new();
+        compressor
+            .wrap_reader(Box::new(&compressed[..]))?
+            .read_to_end(&mut extracted)?;
+        assert_eq!(extracted, data);
+
+        let mut compressed = Vec::new();
+        compressor.compress_stream(&mut &data[..], &mut compressed)?;
+        let mut extracted = Vec::new();
+        compressor
+            .extract_async(Box::pin(&compressed[..]), Box::pin(&mut extracted))
+            .await?;
+        assert_eq!(extracted, data);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_roundtrip() -> Result<(), io::Error> {
+        roundtrip(Gzip::default()).await?;
+        #[cfg(feature = "xz")]
+        roundtrip(Xz::default()).await?;
+        #[cfg(feature = "bzip2")]
+        roundtrip(Bzip2::default()).await?;
+        roundtrip(Brotli::default()).await?;
+        #[cfg(feature = "zstd")]
+        roundtrip(Zstd::default()).await?;
+        #[cfg(feature = "zstd")]
+        roundtrip(Zstd {
+            dictionary: Some(b"garbage data for a raw content dictionary".to_vec()),
+            ..Zstd::default()
+        })
+        .await?;
+        Ok(())
+    }
+}
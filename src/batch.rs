@@ -0,0 +1,799 @@
+//! Compressing or extracting many files with one command, each to its own output
+
+use crate::checksum;
+use crate::exclude::Exclude;
+use crate::job::{check_overwrite, configure, describe_compressor, forced_output, Action};
+use crate::log::{self, Verbosity};
+use crate::progress::{file_progress_bar, Progress, ProgressObserver};
+use crate::utils::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+
+/// A single file of a batch, and where its output goes
+#[derive(Debug, PartialEq)]
+struct BatchFile {
+    input: PathBuf,
+    output: PathBuf,
+    action: Action,
+}
+
+/// Collect the regular files below a path, with their names relative to the parent of the path
+/// Symlinks and special files are skipped, like `gzip -r` does.
+fn walk(
+    path: &Path,
+    name: &Path,
+    exclude: &Exclude,
+    files: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<(), io::Error> {
+    if exclude.is_excluded(name) {
+        return Ok(());
+    }
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_file() {
+        files.push((path.to_path_buf(), name.to_path_buf()));
+    } else if metadata.is_dir() {
+        let mut children = fs::read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+        children.sort_by_key(|child| child.file_name());
+        for child in children {
+            walk(&child.path(), &name.join(child.file_name()), exclude, files)?;
+        }
+    }
+    Ok(())
+}
+
+/// Name of the output of a single file
+fn output_name(compressor: &dyn Compressor, action: Action, input: &Path) -> String {
+    match action {
+        Action::Extract => compressor.default_extracted_filename(input),
+        _ => compressor.default_compressed_filename(input),
+    }
+}
+
+/// A piece of an --output-template
+#[derive(Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Dir,
+    Name,
+    Stem,
+    Ext,
+    Date,
+    Counter,
+}
+
+/// Names of the outputs of a batch, like '{dir}/{stem}-{date}.{ext}'
+/// The placeholders are filled in from the output each file would get without the template.
+#[derive(Debug)]
+struct OutputTemplate {
+    parts: Vec<Part>,
+    /// The UTC date the batch started, as YYYY-MM-DD
+    date: String,
+}
+
+impl OutputTemplate {
+    /// Parse a template, where '{{' and '}}' are literal braces
+    fn new(template: &str, now: i64) -> Result<OutputTemplate, io::Error> {
+        let invalid = |reason: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid output template {}: {}", template, reason),
+            )
+        };
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => text.push(chars.next().unwrap()),
+                '}' if chars.peek() == Some(&'}') => text.push(chars.next().unwrap()),
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(invalid(format!("{{{} isn't closed", name))),
+                        }
+                    }
+                    let part = match name.as_str() {
+                        "dir" => Part::Dir,
+                        "name" => Part::Name,
+                        "stem" => Part::Stem,
+                        "ext" => Part::Ext,
+                        "date" => Part::Date,
+                        "n" => Part::Counter,
+                        name => return Err(invalid(format!("unknown placeholder {{{}}}", name))),
+                    };
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(part);
+                }
+                '}' => return Err(invalid("unmatched '}', write '}}' for a brace".to_string())),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        let (year, month, day, ..) = civil_from_unix(now);
+        Ok(OutputTemplate {
+            parts,
+            date: format!("{:04}-{:02}-{:02}", year, month, day),
+        })
+    }
+
+    /// The output of the nth file of the batch, counting from 1, instead of its default output
+    /// The stem is the name up to its first dot and the extension is the rest, so that a.log.gz
+    /// has the extension log.gz. A dot before an empty extension is left out.
+    fn render(&self, output: &Path, n: usize) -> PathBuf {
+        let dir = match output.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_string_lossy(),
+            _ => ".".into(),
+        };
+        let name = output
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        let (stem, ext) = match name.char_indices().skip(1).find(|(_, c)| *c == '.') {
+            Some((i, _)) => (&name[..i], &name[i + 1..]),
+            None => (&name[..], ""),
+        };
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Dir => rendered.push_str(&dir),
+                Part::Name => rendered.push_str(&name),
+                Part::Stem => rendered.push_str(stem),
+                Part::Ext if ext.is_empty() => {
+                    if rendered.ends_with('.') {
+                        rendered.pop();
+                    }
+                }
+                Part::Ext => rendered.push_str(ext),
+                Part::Date => rendered.push_str(&self.date),
+                Part::Counter => rendered.push_str(&n.to_string()),
+            }
+        }
+        PathBuf::from(rendered)
+    }
+}
+
+/// Rename the outputs of the files with the --output-template, if one is given
+fn template_outputs(
+    mut files: Vec<BatchFile>,
+    template: Option<&str>,
+) -> Result<Vec<BatchFile>, io::Error> {
+    let Some(template) = template else {
+        return Ok(files);
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64);
+    let template = OutputTemplate::new(template, now)?;
+    let mut outputs = HashMap::new();
+    for (i, file) in files.iter_mut().enumerate() {
+        file.output = template.render(&file.output, i + 1);
+        if let Some(other) = outputs.insert(file.output.clone(), file.input.clone()) {
+            return Err(io::Error::other(format!(
+                "{} and {} would both be written to {}, add {{n}} to the output template",
+                other.display(),
+                file.input.display(),
+                file.output.display()
+            )));
+        }
+    }
+    Ok(files)
+}
+
+/// Find the files to process below the inputs, and name their outputs
+/// Compressing skips the files that are already compressed with the format, and extracting
+/// only takes those.
+fn recursive_files(
+    compressor: &dyn Compressor,
+    action: Action,
+    inputs: &[PathBuf],
+    output_dir: Option<&Path>,
+    exclude: &Exclude,
+) -> Result<Vec<BatchFile>, io::Error> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let name = input.file_name().map(Path::new).unwrap_or(input);
+        walk(input, name, exclude, &mut files)?;
+    }
+    Ok(files
+        .into_iter()
+        .filter(|(path, _)| compressor.is_archive(path) == (action == Action::Extract))
+        .map(|(input, name)| {
+            let output_name = output_name(compressor, action, &input);
+            let output = match output_dir {
+                Some(dir) => dir.join(name).with_file_name(output_name),
+                None => input.with_file_name(output_name),
+            };
+            BatchFile {
+                input,
+                output,
+                action,
+            }
+        })
+        .collect())
+}
+
+/// Name the outputs of the inputs of a batch, which go to the current directory like the
+/// output of a single file, or to the output directory
+/// Inputs that are archives of the format are extracted, unless the action is given.
+fn batch_files(
+    compressor: &dyn Compressor,
+    action: Action,
+    inputs: &[PathBuf],
+    output_dir: Option<&Path>,
+) -> Result<Vec<BatchFile>, io::Error> {
+    let mut files = Vec::new();
+    let mut outputs = HashMap::new();
+    for input in inputs {
+        let action = match action {
+            Action::Unknown if compressor.is_archive(input) => Action::Extract,
+            Action::Unknown => Action::Compress,
+            action => action,
+        };
+        let name = output_name(compressor, action, input);
+        let output = match output_dir {
+            Some(dir) => dir.join(name),
+            None => PathBuf::from(name),
+        };
+        // Files with the same name in different directories can't share an output
+        if let Some(other) = outputs.insert(output.clone(), input) {
+            return Err(io::Error::other(format!(
+                "{} and {} would both be written to {}",
+                other.display(),
+                input.display(),
+                output.display()
+            )));
+        }
+        files.push(BatchFile {
+            input: input.clone(),
+            output,
+            action,
+        });
+    }
+    Ok(files)
+}
+
+/// Process a single file, writing the output under a temporary name until it is complete
+fn run_file(
+    compressor: &dyn Compressor,
+    file: &BatchFile,
+    args: &CommonArgs,
+) -> Result<(), io::Error> {
+    check_overwrite(&file.output, args)?;
+    if args.verify_checksum {
+        checksum::verify_sidecar(&file.input)?;
+    }
+    if let Some(parent) = file.output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    crate::debug!(
+        "{} {} -> {}",
+        file.action,
+        file.input.display(),
+        file.output.display()
+    );
+    let output = AtomicOutput::new(&file.output);
+    let input = CmprssInput::Path(vec![file.input.clone()]);
+    let tmp = CmprssOutput::Path(output.tmp().to_path_buf());
+    let result = match file.action {
+        Action::Extract => compressor.extract(input, tmp),
+        _ => compressor.compress(input, tmp),
+    };
+    output.finish(result)?;
+    if let Some(algorithm) = args.checksum {
+        checksum::write_sidecar(&file.output, algorithm)?;
+    }
+    if args.rm_source {
+        fs::remove_file(&file.input)?;
+    }
+    Ok(())
+}
+
+/// Progress of all the files of a batch, shown as a single bar
+/// Each thread reports the progress of its current file, which is added to the finished files.
+struct BatchProgress {
+    /// The bar, unless --progress hides it
+    bar: Option<Progress>,
+    totals: Mutex<Totals>,
+}
+
+impl BatchProgress {
+    /// Print a message to stderr without breaking the progress bar
+    fn println(&self, message: String) {
+        match &self.bar {
+            Some(bar) => bar.println(message),
+            None => eprintln!("{}", message),
+        }
+    }
+}
+
+/// Bytes read and written by the files of a batch
+#[derive(Default)]
+struct Totals {
+    finished: (u64, u64),
+    current: HashMap<ThreadId, (u64, u64)>,
+}
+
+impl ProgressObserver for BatchProgress {
+    fn update(&self, input_read: u64, output_written: u64) {
+        let mut totals = self.totals.lock().unwrap();
+        totals
+            .current
+            .insert(thread::current().id(), (input_read, output_written));
+        let (read, written) = totals
+            .current
+            .values()
+            .fold(totals.finished, |(r, w), (read, written)| {
+                (r + read, w + written)
+            });
+        if let Some(bar) = &self.bar {
+            bar.update(read, written);
+        }
+    }
+
+    /// Called when a file is finished
+    fn finish(&self) {
+        let mut totals = self.totals.lock().unwrap();
+        if let Some((read, written)) = totals.current.remove(&thread::current().id()) {
+            totals.finished.0 += read;
+            totals.finished.1 += written;
+        }
+    }
+}
+
+/// Process all the files, `--jobs` at a time
+///
+/// The progress of all the files is shown as one bar, and the threads of the compressor are
+/// shared between the jobs unless given.
+/// Failing files are reported and skipped, and the batch fails once all the others are done.
+fn run_files(
+    mut compressor: Box<dyn Compressor>,
+    files: &[BatchFile],
+    args: &CommonArgs,
+) -> Result<(), io::Error> {
+    let jobs = args.jobs.unwrap_or_else(default_threads);
+    if args.threads.is_none() {
+        compressor.set_threads((default_threads() / jobs).max(1));
+    }
+    if args.dry_run {
+        let mut stdout = io::stdout().lock();
+        write!(stdout, "{}", describe_compressor(compressor.as_ref()))?;
+        for file in files {
+            let (input, output) = (file.input.display(), file.output.display());
+            writeln!(stdout, "{}: {} -> {}", file.action, input, output)?;
+        }
+        return Ok(());
+    }
+    let total = files
+        .iter()
+        .filter_map(|file| fs::metadata(&file.input).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    let progress = Arc::new(BatchProgress {
+        bar: file_progress_bar(Some(total), compressor.progress()),
+        totals: Mutex::default(),
+    });
+    compressor.set_observer(progress.clone());
+    let compressor = compressor.as_ref();
+
+    let next = AtomicUsize::new(0);
+    let failed = Mutex::new(0);
+    let work = || {
+        while let Some(file) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+            match run_file(compressor, file, args) {
+                Err(e)
+                    if args.overwrite_args.no_clobber
+                        && e.kind() == io::ErrorKind::AlreadyExists =>
+                {
+                    if log::enabled(Verbosity::Normal) {
+                        progress.println(format!("cmprss: skipping, {}", e));
+                    }
+                }
+                Err(e) => {
+                    progress.println(format!("ERROR(cmprss): {}: {}", file.input.display(), e));
+                    *failed.lock().unwrap() += 1;
+                }
+                Ok(()) => {}
+            }
+        }
+    };
+    let workers = (jobs as usize).min(files.len());
+    thread::scope(|scope| -> Result<(), io::Error> {
+        // Threads may not be available, e.g. on wasm32-wasip1, so work on this one too
+        for _ in 1..workers {
+            thread::Builder::new().spawn_scoped(scope, work)?;
+        }
+        work();
+        Ok(())
+    })?;
+    if let Some(bar) = &progress.bar {
+        bar.finish();
+    }
+
+    let failed = failed.into_inner().unwrap();
+    if failed > 0 {
+        return Err(io::Error::other(format!(
+            "{} of {} files failed",
+            failed,
+            files.len()
+        )));
+    }
+    Ok(())
+}
+
+/// The action given on the command line, if any
+fn given_action(args: &CommonArgs) -> Action {
+    if args.compress {
+        Action::Compress
+    } else if args.extract || args.decompress {
+        Action::Extract
+    } else {
+        Action::Unknown
+    }
+}
+
+/// Configure the compressor for --recursive, which must be a stream format
+fn recursive_compressor(
+    compressor: Option<Box<dyn Compressor>>,
+    args: &CommonArgs,
+) -> Result<Box<dyn Compressor>, io::Error> {
+    let Some(mut compressor) = compressor else {
+        return Err(io::Error::other("--recursive needs the format to be given"));
+    };
+    if !compressor.is_stream() {
+        return Err(io::Error::other(format!(
+            "--recursive only works with stream formats like gzip, not {}",
+            compressor.name()
+        )));
+    }
+    configure(compressor.as_mut(), args)?;
+    Ok(compressor)
+}
+
+/// Compress every file below the input directories to its own file, like `gzip -r`
+///
+/// The outputs are written next to the files, or to the same place below `--output-dir`,
+/// unless `--output-template` names them.
+pub fn recursive(
+    compressor: Option<Box<dyn Compressor>>,
+    args: &CommonArgs,
+) -> Result<(), io::Error> {
+    let compressor = recursive_compressor(compressor, args)?;
+    let action = match given_action(args) {
+        Action::Extract => Action::Extract,
+        _ => Action::Compress,
+    };
+    let inputs: Vec<PathBuf> = args
+        .input
+        .iter()
+        .chain(&args.io_list)
+        .map(PathBuf::from)
+        .collect();
+    if inputs.is_empty() {
+        return Err(io::Error::other("error: no input specified"));
+    }
+    if let Some(missing) = inputs.iter().find(|input| !input.exists()) {
+        return Err(io::Error::other(format!(
+            "Specified input path {} does not exist",
+            missing.display()
+        )));
+    }
+    let exclude = Exclude::new(&args.exclude_args)?;
+    let files = recursive_files(
+        compressor.as_ref(),
+        action,
+        &inputs,
+        args.output_dir.as_deref(),
+        &exclude,
+    )?;
+    let files = template_outputs(files, args.output_template.as_deref())?;
+    run_files(compressor, &files, args)
+}
+
+/// Check if the arguments are several files for a stream format, which are each compressed
+/// or extracted on their own
+/// The last file is the output when it doesn't exist yet, or when it's replaced with --force.
+pub fn is_batch(compressor: &Option<Box<dyn Compressor>>, args: &CommonArgs) -> bool {
+    let Some(stream) = compressor else {
+        return false;
+    };
+    let inputs = args.input.iter().chain(&args.io_list).count();
+    stream.is_stream()
+        && !args.tar
+        && !args.stdout
+        && args.output.is_none()
+        && inputs > 1
+        && args.io_list.iter().all(|path| Path::new(path).is_file())
+        && !(args.overwrite_args.force
+            && args.input.is_none()
+            && forced_output(args.allow_external, &args.io_list, compressor))
+}
+
+/// Compress or extract each input to its own output, like `gzip *.log`
+pub fn batch(compressor: Option<Box<dyn Compressor>>, args: &CommonArgs) -> Result<(), io::Error> {
+    let Some(mut compressor) = compressor else {
+        return Err(io::Error::other("Must specify a compressor"));
+    };
+    configure(compressor.as_mut(), args)?;
+    let inputs: Vec<PathBuf> = args
+        .input
+        .iter()
+        .chain(&args.io_list)
+        .map(PathBuf::from)
+        .collect();
+    let files = batch_files(
+        compressor.as_ref(),
+        given_action(args),
+        &inputs,
+        args.output_dir.as_deref(),
+    )?;
+    let files = template_outputs(files, args.output_template.as_deref())?;
+    run_files(compressor, &files, args)
+}
+
+/// Write a single file of a concatenation to stdout
+fn concatenate_file(
+    compressor: &dyn Compressor,
+    input: &Path,
+    action: Action,
+    args: &CommonArgs,
+) -> Result<(), io::Error> {
+    if args.verify_checksum {
+        checksum::verify_sidecar(input)?;
+    }
+    // '-' is stdin, like `zcat a.gz - b.gz`
+    let stdin = input.as_os_str() == "-";
+    let source = match stdin {
+        true => CmprssInput::Pipe(Box::new(io::stdin())),
+        false => CmprssInput::Path(vec![input.to_path_buf()]),
+    };
+    let output = CmprssOutput::Pipe(Box::new(io::stdout()));
+    match action {
+        Action::Extract => compressor.extract(source, output)?,
+        _ => compressor.compress(source, output)?,
+    }
+    if args.rm_source && !stdin {
+        fs::remove_file(input)?;
+    }
+    Ok(())
+}
+
+/// Check if the arguments are several files for a stream format written one after the other
+/// to stdout, like `zcat a.gz b.gz`
+pub fn is_concatenation(compressor: &Option<Box<dyn Compressor>>, args: &CommonArgs) -> bool {
+    let inputs = args.input.iter().chain(&args.io_list).count();
+    compressor.as_ref().is_some_and(|c| c.is_stream()) && !args.tar && args.stdout && inputs > 1
+}
+
+/// Compress or extract each input in turn to stdout, like `gzip -c a b`
+/// Failing files are reported and skipped, and the batch fails once all the others are done.
+pub fn concatenate(
+    compressor: Option<Box<dyn Compressor>>,
+    args: &CommonArgs,
+) -> Result<(), io::Error> {
+    let Some(mut compressor) = compressor else {
+        return Err(io::Error::other("Must specify a compressor"));
+    };
+    configure(compressor.as_mut(), args)?;
+    if args.checksum.is_some() {
+        return cmprss_error("--checksum needs the output to be a file");
+    }
+    let files: Vec<(PathBuf, Action)> = args
+        .input
+        .iter()
+        .chain(&args.io_list)
+        .map(|input| {
+            let input = PathBuf::from(input);
+            let action = match given_action(args) {
+                Action::Unknown if compressor.is_archive(&input) => Action::Extract,
+                Action::Unknown => Action::Compress,
+                action => action,
+            };
+            (input, action)
+        })
+        .collect();
+    if args.dry_run {
+        let mut stdout = io::stdout().lock();
+        write!(stdout, "{}", describe_compressor(compressor.as_ref()))?;
+        for (input, action) in &files {
+            writeln!(stdout, "{}: {} -> stdout", action, input.display())?;
+        }
+        return Ok(());
+    }
+    if files.iter().any(|(_, action)| *action == Action::Compress)
+        && io::stdout().is_terminal()
+        && !args.overwrite_args.force
+    {
+        return cmprss_error(
+            "refusing to write compressed data to a terminal, use --force to write it anyway",
+        );
+    }
+
+    let mut failed = 0;
+    for (input, action) in &files {
+        let result = concatenate_file(compressor.as_ref(), input, *action, args);
+        if let Err(e) = result {
+            eprintln!("ERROR(cmprss): {}: {}", input.display(), e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Err(io::Error::other(format!(
+            "{} of {} files failed",
+            failed,
+            files.len()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gzip::Gzip;
+    use assert_fs::prelude::*;
+
+    #[test]
+    fn recursive_file_names() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir.child("logs/a.log").write_str("a")?;
+        working_dir.child("logs/old/b.log").write_str("b")?;
+        working_dir.child("logs/old/c.log.gz").write_str("c")?;
+        working_dir.child("logs/skip/d.log").write_str("d")?;
+        let logs = working_dir.child("logs").path().to_path_buf();
+        let exclude = Exclude::from_patterns(&["skip"])?;
+
+        let files = recursive_files(
+            &Gzip::default(),
+            Action::Compress,
+            std::slice::from_ref(&logs),
+            None,
+            &exclude,
+        )?;
+        assert_eq!(
+            files,
+            [
+                BatchFile {
+                    input: logs.join("a.log"),
+                    output: logs.join("a.log.gz"),
+                    action: Action::Compress,
+                },
+                BatchFile {
+                    input: logs.join("old/b.log"),
+                    output: logs.join("old/b.log.gz"),
+                    action: Action::Compress,
+                },
+            ]
+        );
+
+        let out = Path::new("out");
+        let files = recursive_files(
+            &Gzip::default(),
+            Action::Extract,
+            std::slice::from_ref(&logs),
+            Some(out),
+            &exclude,
+        )?;
+        assert_eq!(
+            files,
+            [BatchFile {
+                input: logs.join("old/c.log.gz"),
+                output: out.join("logs/old/c.log"),
+                action: Action::Extract,
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn batch_progress() {
+        let progress = Arc::new(BatchProgress {
+            bar: Some(Progress::new(Some(100))),
+            totals: Mutex::default(),
+        });
+        progress.update(10, 5);
+        progress.finish();
+        let other = progress.clone();
+        thread::spawn(move || other.update(20, 10)).join().unwrap();
+        progress.update(30, 15);
+        let totals = progress.totals.lock().unwrap();
+        assert_eq!(totals.finished, (10, 5));
+        let current: u64 = totals.current.values().map(|(read, _)| read).sum();
+        assert_eq!(current, 50);
+    }
+
+    #[test]
+    fn batch_file_names() -> Result<(), io::Error> {
+        let inputs = [PathBuf::from("logs/a.log"), PathBuf::from("b.log.gz")];
+        let files = batch_files(&Gzip::default(), Action::Unknown, &inputs, None)?;
+        assert_eq!(
+            files,
+            [
+                BatchFile {
+                    input: inputs[0].clone(),
+                    output: PathBuf::from("a.log.gz"),
+                    action: Action::Compress,
+                },
+                BatchFile {
+                    input: inputs[1].clone(),
+                    output: PathBuf::from("b.log"),
+                    action: Action::Extract,
+                },
+            ]
+        );
+
+        let files = batch_files(
+            &Gzip::default(),
+            Action::Compress,
+            &inputs,
+            Some(Path::new("out")),
+        )?;
+        assert_eq!(files[1].output, PathBuf::from("out/b.log.gz.gz"));
+
+        let inputs = [PathBuf::from("a/x.log"), PathBuf::from("b/x.log")];
+        let error = batch_files(&Gzip::default(), Action::Unknown, &inputs, None).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("would both be written to x.log.gz"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn output_template() -> Result<(), io::Error> {
+        // 2024-01-31 12:30:00 UTC
+        let template = OutputTemplate::new("{dir}/{stem}-{date}.{ext}", 1706704200)?;
+        assert_eq!(
+            template.render(Path::new("logs/a.log.gz"), 1),
+            PathBuf::from("logs/a-2024-01-31.log.gz")
+        );
+        assert_eq!(
+            template.render(Path::new("README"), 1),
+            PathBuf::from("./README-2024-01-31")
+        );
+        assert_eq!(
+            template.render(Path::new(".profile.gz"), 1),
+            PathBuf::from("./.profile-2024-01-31.gz")
+        );
+        let template = OutputTemplate::new("out/{{{n}}}_{name}", 0)?;
+        assert_eq!(
+            template.render(Path::new("x/a.log.gz"), 12),
+            PathBuf::from("out/{12}_a.log.gz")
+        );
+
+        assert!(OutputTemplate::new("{dir}/{time}", 0).is_err());
+        assert!(OutputTemplate::new("{stem", 0).is_err());
+        assert!(OutputTemplate::new("stem}", 0).is_err());
+
+        let files = vec![
+            BatchFile {
+                input: PathBuf::from("a/x.log"),
+                output: PathBuf::from("a/x.log.gz"),
+                action: Action::Compress,
+            },
+            BatchFile {
+                input: PathBuf::from("b/x.log"),
+                output: PathBuf::from("b/x.log.gz"),
+                action: Action::Compress,
+            },
+        ];
+        let error = template_outputs(files, Some("{name}")).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("would both be written to x.log.gz"));
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+//! Compare the formats and levels on a sample of a file
+
+use crate::brotli::Brotli;
+#[cfg(feature = "bzip2")]
+use crate::bzip2::Bzip2;
+use crate::gzip::Gzip;
+use crate::progress::ChunkSize;
+use crate::utils::*;
+#[cfg(feature = "xz")]
+use crate::xz::Xz;
+#[cfg(feature = "zstd")]
+use crate::zstd::Zstd;
+use clap::Args;
+use indicatif::HumanBytes;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[derive(Args, Debug)]
+pub struct BenchmarkArgs {
+    /// File to take the sample from
+    pub input: PathBuf,
+
+    /// Size of the sample read from the start of the file, like '64mb'
+    #[arg(long, value_name = "SIZE", default_value = "16mb")]
+    pub sample_size: ChunkSize,
+
+    /// Only benchmark this format, can be repeated
+    #[arg(long = "format", value_name = "FORMAT")]
+    pub formats: Vec<String>,
+}
+
+/// Measurements of one format at one level
+#[derive(Debug)]
+pub struct BenchmarkResult {
+    pub format: String,
+    pub level: i32,
+    /// Size of the sample
+    pub original: u64,
+    /// Size of the compressed sample
+    pub compressed: u64,
+    pub compress_time: Duration,
+    pub extract_time: Duration,
+}
+
+/// Speed in decimal megabytes of the sample per second
+fn megabytes_per_sec(bytes: u64, time: Duration) -> f64 {
+    bytes as f64 / 1e6 / time.as_secs_f64().max(1e-9)
+}
+
+impl BenchmarkResult {
+    pub fn ratio(&self) -> f64 {
+        self.compressed as f64 / self.original.max(1) as f64
+    }
+
+    pub fn compress_speed(&self) -> f64 {
+        megabytes_per_sec(self.original, self.compress_time)
+    }
+
+    pub fn extract_speed(&self) -> f64 {
+        megabytes_per_sec(self.original, self.extract_time)
+    }
+
+    /// The result as a line of JSON, with the speeds in decimal MB/s
+    pub fn json(&self) -> String {
+        format!(
+            r#"{{"format":"{}","level":{},"original_bytes":{},"compressed_bytes":{},"ratio":{:.4},"compress_mb_per_sec":{:.2},"extract_mb_per_sec":{:.2}}}"#,
+            self.format,
+            self.level,
+            self.original,
+            self.compressed,
+            self.ratio(),
+            self.compress_speed(),
+            self.extract_speed()
+        )
+    }
+}
+
+/// Header of the table, aligned with `format_result`
+pub fn table_header() -> String {
+    format!(
+        "{:<8}  {:>5}  {:>12}  {:>8}  {:>12}  {:>12}",
+        "format", "level", "size", "ratio", "compress", "extract"
+    )
+}
+
+/// Format a result as a row of the table
+pub fn format_result(result: &BenchmarkResult) -> String {
+    format!(
+        "{:<8}  {:>5}  {:>12}  {:>7.2}%  {:>7.1} MB/s  {:>7.1} MB/s",
+        result.format,
+        result.level,
+        HumanBytes(result.compressed).to_string(),
+        result.ratio() * 100.0,
+        result.compress_speed(),
+        result.extract_speed()
+    )
+}
+
+/// The stream formats built into cmprss, each at a few levels from fastest to smallest
+/// Everything else uses the defaults of the format, including the threads of zstd and xz.
+pub fn candidates() -> Vec<(i32, Box<dyn Compressor>)> {
+    let mut candidates: Vec<(i32, Box<dyn Compressor>)> = Vec::new();
+    for level in [1, 6, 9] {
+        let gzip = Gzip {
+            compression_level: level,
+            ..Gzip::default()
+        };
+        candidates.push((level as i32, Box::new(gzip)));
+    }
+    #[cfg(feature = "bzip2")]
+    for level in [1, 9] {
+        let bzip2 = Bzip2 {
+            level,
+            ..Bzip2::default()
+        };
+        candidates.push((level as i32, Box::new(bzip2)));
+    }
+    #[cfg(feature = "xz")]
+    for level in [1, 6, 9] {
+        let xz = Xz {
+            level,
+            ..Xz::default()
+        };
+        candidates.push((level as i32, Box::new(xz)));
+    }
+    for quality in [1, 6, 11] {
+        let brotli = Brotli {
+            quality,
+            ..Brotli::default()
+        };
+        candidates.push((quality as i32, Box::new(brotli)));
+    }
+    #[cfg(feature = "zstd")]
+    for level in [1, 3, 9, 19] {
+        let zstd = Zstd {
+            level,
+            ..Zstd::default()
+        };
+        candidates.push((level, Box::new(zstd)));
+    }
+    candidates
+}
+
+/// Compress and extract the sample, checking that it survives the roundtrip
+pub fn measure(
+    compressor: &dyn Compressor,
+    level: i32,
+    sample: &[u8],
+) -> Result<BenchmarkResult, io::Error> {
+    let mut compressed = Vec::new();
+    let start = Instant::now();
+    compressor.compress_stream(&mut &sample[..], &mut compressed)?;
+    let compress_time = start.elapsed();
+
+    let mut extracted = Vec::with_capacity(sample.len());
+    let start = Instant::now();
+    compressor.extract_stream(&mut &compressed[..], &mut extracted)?;
+    let extract_time = start.elapsed();
+    if extracted != sample {
+        return Err(io::Error::other(format!(
+            "{} level {} didn't extract to the original data",
+            compressor.name(),
+            level
+        )));
+    }
+    Ok(BenchmarkResult {
+        format: compressor.name().to_string(),
+        level,
+        original: sample.len() as u64,
+        compressed: compressed.len() as u64,
+        compress_time,
+        extract_time,
+    })
+}
+
+/// Benchmark every format on a sample of the file, printing each result as it finishes
+/// JSON is printed as a line for each result, since the results take a while to come.
+pub fn benchmark(args: &BenchmarkArgs, output: OutputFormat) -> Result<(), io::Error> {
+    let mut sample = Vec::new();
+    File::open(&args.input)?
+        .take(args.sample_size.size_in_bytes as u64)
+        .read_to_end(&mut sample)?;
+    let candidates: Vec<_> = candidates()
+        .into_iter()
+        .filter(|(_, compressor)| {
+            args.formats.is_empty()
+                || args
+                    .formats
+                    .iter()
+                    .any(|format| format == compressor.name() || format == compressor.extension())
+        })
+        .collect();
+    if candidates.is_empty() {
+        return Err(io::Error::other(format!(
+            "no built in stream format matches {}",
+            args.formats.join(", ")
+        )));
+    }
+
+    let mut stdout = io::stdout().lock();
+    if output == OutputFormat::Text {
+        writeln!(
+            stdout,
+            "sample of {} from {}",
+            HumanBytes(sample.len() as u64),
+            args.input.display()
+        )?;
+        writeln!(stdout, "{}", table_header())?;
+    }
+    for (level, compressor) in candidates {
+        let result = measure(compressor.as_ref(), level, &sample)?;
+        match output {
+            OutputFormat::Json => writeln!(stdout, "{}", result.json())?,
+            OutputFormat::Text => writeln!(stdout, "{}", format_result(&result))?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_every_candidate() -> Result<(), io::Error> {
+        let sample = b"garbage data for testing ".repeat(100);
+        for (level, compressor) in candidates() {
+            let result = measure(compressor.as_ref(), level, &sample)?;
+            assert_eq!(result.original, sample.len() as u64);
+            assert!(result.compressed < result.original);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn result_format() {
+        let result = BenchmarkResult {
+            format: "gzip".to_string(),
+            level: 6,
+            original: 4_000_000,
+            compressed: 1_000_000,
+            compress_time: Duration::from_secs(2),
+            extract_time: Duration::from_millis(500),
+        };
+        assert_eq!(
+            format_result(&result),
+            "gzip          6    976.56 KiB    25.00%      2.0 MB/s      8.0 MB/s"
+        );
+        assert_eq!(
+            result.json(),
+            r#"{"format":"gzip","level":6,"original_bytes":4000000,"compressed_bytes":1000000,"ratio":0.2500,"compress_mb_per_sec":2.00,"extract_mb_per_sec":8.00}"#
+        );
+    }
+}
@@ -0,0 +1,324 @@
+//! brotli compression backend
+
+use crate::{
+    limits::OutputLimits,
+    progress::{
+        copy_with_progress, progress_bar, CountingWriter, ProgressArgs, ProgressDisplay,
+        ProgressHooks, ProgressObserver,
+    },
+    utils::*,
+};
+use brotli::{CompressorWriter, Decompressor, DecompressorWriter};
+use clap::Args;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+/// Size of the internal buffers of the brotli encoder and decoder
+const BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Args, Debug)]
+pub struct BrotliArgs {
+    #[clap(flatten)]
+    pub common_args: CommonArgs,
+
+    #[clap(flatten)]
+    pub progress_args: ProgressArgs,
+
+    /// Quality of compression.
+    /// This is an int 0-11, with 0 being the fastest and 11 being the highest compression.
+    #[arg(long, visible_alias = "level", default_value_t = 11, value_parser = clap::value_parser!(u32).range(0..=11))]
+    pub quality: u32,
+
+    /// Base 2 logarithm of the window size, 10-24.
+    /// Larger windows compress better but use more memory to extract.
+    #[arg(long, default_value_t = 22, value_parser = clap::value_parser!(u32).range(10..=24))]
+    pub window: u32,
+}
+
+pub struct Brotli {
+    pub quality: u32, // 0-11
+    pub window: u32,  // 10-24
+    pub progress_args: ProgressArgs,
+    pub hooks: ProgressHooks,
+}
+
+impl Default for Brotli {
+    fn default() -> Self {
+        Brotli {
+            quality: 11,
+            window: 22,
+            progress_args: ProgressArgs::default(),
+            hooks: ProgressHooks::default(),
+        }
+    }
+}
+
+impl Brotli {
+    pub fn new(args: &BrotliArgs) -> Self {
+        Brotli {
+            quality: args.quality,
+            window: args.window,
+            progress_args: args.progress_args,
+            hooks: ProgressHooks::default(),
+        }
+    }
+
+    fn check_params(&self) -> Result<(), io::Error> {
+        if self.quality > 11 {
+            return Err(io::Error::other("Invalid quality. Must be 0-11."));
+        }
+        if !(10..=24).contains(&self.window) {
+            return Err(io::Error::other("Invalid window size. Must be 10-24."));
+        }
+        Ok(())
+    }
+}
+
+/// Writer that keeps the first error from the inner writer
+/// The brotli encoder ignores errors while finishing the stream, so they are checked afterwards.
+struct ErrorCatcher<W> {
+    inner: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> ErrorCatcher<W> {
+    fn new(inner: W) -> Self {
+        ErrorCatcher { inner, error: None }
+    }
+
+    fn finish(self) -> Result<W, io::Error> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.inner),
+        }
+    }
+
+    fn catch<T>(&mut self, result: io::Result<T>) -> io::Result<T> {
+        if let Err(e) = &result {
+            self.error
+                .get_or_insert_with(|| io::Error::new(e.kind(), e.to_string()));
+        }
+        result
+    }
+}
+
+impl<W: Write> Write for ErrorCatcher<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.inner.write(buf);
+        self.catch(result)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = self.inner.flush();
+        self.catch(result)
+    }
+}
+
+impl Compressor for Brotli {
+    /// The standard extension for the brotli format.
+    fn extension(&self) -> &str {
+        "br"
+    }
+
+    /// Full name for brotli.
+    fn name(&self) -> &str {
+        "brotli"
+    }
+
+    fn is_stream(&self) -> bool {
+        true
+    }
+
+    fn set_limits(&mut self, limits: OutputLimits) {
+        self.hooks.limits = limits;
+    }
+
+    fn set_observer(&mut self, observer: Arc<dyn ProgressObserver>) {
+        self.hooks.observer = Some(observer);
+    }
+
+    fn set_level(&mut self, level: i32) {
+        self.quality = level as u32;
+    }
+
+    fn level(&self) -> Option<i32> {
+        Some(self.quality as i32)
+    }
+
+    fn set_progress(&mut self, progress: ProgressDisplay) {
+        self.progress_args.progress = progress;
+    }
+
+    fn progress(&self) -> ProgressDisplay {
+        self.progress_args.progress
+    }
+
+    /// Compress an input file or pipe to a brotli archive
+    fn compress(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        self.check_params()?;
+        let (mut input_stream, file_size) = input.into_stream()?;
+        let bar = progress_bar(file_size, self.progress_args.progress, &output, &self.hooks);
+        let output_stream = output.into_stream()?;
+        let mut encoder = CompressorWriter::new(
+            ErrorCatcher::new(CountingWriter::new(output_stream)),
+            BUFFER_SIZE,
+            self.quality,
+            self.window,
+        );
+        copy_with_progress(
+            &mut input_stream,
+            &mut encoder,
+            self.progress_args.chunk_size.size_in_bytes,
+            &self.hooks,
+            bar.as_ref(),
+            |e| e.get_ref().inner.count(),
+        )?;
+        encoder.into_inner().finish()?.flush()
+    }
+
+    /// Extract a brotli archive to a file or pipe
+    fn extract(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        let (mut input_stream, file_size) = input.into_stream()?;
+        let bar = progress_bar(file_size, self.progress_args.progress, &output, &self.hooks);
+        let output_stream = self.hooks.limits.writer(output.into_stream()?, file_size);
+        let mut decoder = DecompressorWriter::new(CountingWriter::new(output_stream), BUFFER_SIZE);
+        copy_with_progress(
+            &mut input_stream,
+            &mut decoder,
+            self.progress_args.chunk_size.size_in_bytes,
+            &self.hooks,
+            bar.as_ref(),
+            |d| d.get_ref().count(),
+        )?;
+        decoder.close()?;
+        decoder.flush()
+    }
+
+    /// Wrap a writer with a brotli encoder
+    fn wrap_writer<'a>(
+        &self,
+        writer: Box<dyn Write + Send + 'a>,
+    ) -> Result<Box<dyn Write + Send + 'a>, io::Error> {
+        self.check_params()?;
+        Ok(Box::new(CompressorWriter::new(
+            writer,
+            BUFFER_SIZE,
+            self.quality,
+            self.window,
+        )))
+    }
+
+    /// Wrap a reader with a brotli decoder
+    fn wrap_reader<'a>(
+        &self,
+        reader: Box<dyn Read + Send + 'a>,
+    ) -> Result<Box<dyn Read + Send + 'a>, io::Error> {
+        Ok(Box::new(Decompressor::new(reader, BUFFER_SIZE)))
+    }
+
+    /// Compress a stream, checking for errors while finishing the brotli stream
+    fn compress_stream(
+        &self,
+        reader: &mut (dyn Read + Send),
+        writer: &mut (dyn Write + Send),
+    ) -> Result<(), io::Error> {
+        self.check_params()?;
+        let mut encoder = CompressorWriter::new(
+            ErrorCatcher::new(writer),
+            BUFFER_SIZE,
+            self.quality,
+            self.window,
+        );
+        io::copy(reader, &mut encoder)?;
+        encoder.into_inner().finish()?.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use predicates::prelude::*;
+
+    #[test]
+    fn roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Brotli::default();
+
+        let file = assert_fs::NamedTempFile::new("test.txt")?;
+        file.write_str("garbage data for testing")?;
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("archive.".to_owned() + compressor.extension());
+        archive.assert(predicate::path::missing());
+
+        // Roundtrip compress/extract
+        compressor.compress(
+            CmprssInput::Path(vec![file.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        archive.assert(predicate::path::is_file());
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(working_dir.child("test.txt").path().to_path_buf()),
+        )?;
+
+        // Assert the files are identical
+        working_dir
+            .child("test.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn stream_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Brotli {
+            quality: 5,
+            window: 16,
+            ..Brotli::default()
+        };
+        let data = b"garbage data for testing";
+
+        let mut compressed = Vec::new();
+        compressor.compress_stream(&mut &data[..], &mut compressed)?;
+        assert_ne!(compressed, data);
+
+        let mut extracted = Vec::new();
+        compressor
+            .wrap_reader(Box::new(&compressed[..]))?
+            .read_to_end(&mut extracted)?;
+        assert_eq!(extracted, data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_stream() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Brotli::default();
+        let mut compressed = Vec::new();
+        compressor.compress_stream(&mut &b"garbage data for testing"[..], &mut compressed)?;
+        compressed.truncate(compressed.len() / 2);
+
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("archive.br");
+        archive.write_binary(&compressed)?;
+        let result = compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(working_dir.child("test.txt").path().to_path_buf()),
+        );
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    // Fail with a quality of 12
+    #[test]
+    fn invalid_quality() {
+        let compressor = Brotli {
+            quality: 12,
+            ..Brotli::default()
+        };
+        let mut compressed = Vec::new();
+        let result = compressor.compress_stream(&mut &b"data"[..], &mut compressed);
+        assert!(result.is_err());
+    }
+}
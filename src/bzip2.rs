@@ -1,14 +1,18 @@
+//! bzip2 compression backend
+
 use crate::{
-    progress::{progress_bar, ProgressArgs},
+    limits::OutputLimits,
+    progress::{
+        copy_with_progress, progress_bar, ProgressArgs, ProgressDisplay, ProgressHooks,
+        ProgressObserver,
+    },
     utils::*,
 };
 use bzip2::write::{BzDecoder, BzEncoder};
 use bzip2::Compression;
 use clap::Args;
-use std::{
-    fs::File,
-    io::{self, Read, Write},
-};
+use std::io::{self, Read, Write};
+use std::sync::Arc;
 
 #[derive(Args, Debug)]
 pub struct Bzip2Args {
@@ -28,6 +32,7 @@ pub struct Bzip2Args {
 pub struct Bzip2 {
     pub level: u32, // 1-9
     pub progress_args: ProgressArgs,
+    pub hooks: ProgressHooks,
 }
 
 impl Default for Bzip2 {
@@ -35,6 +40,7 @@ impl Default for Bzip2 {
         Bzip2 {
             level: 6,
             progress_args: ProgressArgs::default(),
+            hooks: ProgressHooks::default(),
         }
     }
 }
@@ -44,6 +50,7 @@ impl Bzip2 {
         Bzip2 {
             level: args.level.level,
             progress_args: args.progress_args,
+            hooks: ProgressHooks::default(),
         }
     }
 }
@@ -59,96 +66,108 @@ impl Compressor for Bzip2 {
         "bzip2"
     }
 
+    fn is_stream(&self) -> bool {
+        true
+    }
+
+    fn set_limits(&mut self, limits: OutputLimits) {
+        self.hooks.limits = limits;
+    }
+
+    fn set_observer(&mut self, observer: Arc<dyn ProgressObserver>) {
+        self.hooks.observer = Some(observer);
+    }
+
+    fn set_level(&mut self, level: i32) {
+        self.level = level as u32;
+    }
+
+    fn level(&self) -> Option<i32> {
+        Some(self.level as i32)
+    }
+
+    fn set_progress(&mut self, progress: ProgressDisplay) {
+        self.progress_args.progress = progress;
+    }
+
+    fn progress(&self) -> ProgressDisplay {
+        self.progress_args.progress
+    }
+
     /// Compress an input file or pipe to a bz2 archive
     fn compress(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
         if self.level < 1 || self.level > 9 {
             return cmprss_error("Invalid compression level. Must be 1-9.");
         }
-        let mut file_size = None;
-        let mut input_stream = match input {
-            CmprssInput::Path(paths) => {
-                if paths.len() > 1 {
-                    return cmprss_error("only 1 file can be compressed at a time");
-                }
-                let file = Box::new(File::open(paths[0].as_path())?);
-                // Get the file size for the progress bar
-                if let Ok(metadata) = file.metadata() {
-                    file_size = Some(metadata.len());
-                }
-                file
-            }
-            CmprssInput::Pipe(pipe) => Box::new(pipe) as Box<dyn Read + Send>,
-        };
-        let output_stream: Box<dyn Write + Send> = match &output {
-            CmprssOutput::Path(path) => Box::new(File::create(path)?),
-            CmprssOutput::Pipe(pipe) => Box::new(pipe) as Box<dyn Write + Send>,
-        };
+        let (mut input_stream, file_size) = input.into_stream()?;
+        let bar = progress_bar(file_size, self.progress_args.progress, &output, &self.hooks);
+        let output_stream = output.into_stream()?;
         let mut encoder = BzEncoder::new(output_stream, Compression::new(self.level));
-        let mut bar = progress_bar(file_size, self.progress_args.progress, &output);
-        if let Some(progress) = &mut bar {
-            // Copy the input to the output in chunks so that we can update the progress bar
-            let mut buffer = vec![0; self.progress_args.chunk_size.size_in_bytes];
-            loop {
-                let bytes_read = input_stream.read(&mut buffer)?;
-                if bytes_read == 0 {
-                    break;
-                }
-                encoder.write_all(&buffer[..bytes_read])?;
-                progress.update_input(encoder.total_in());
-                progress.update_output(encoder.total_out());
-            }
-            encoder.flush()?;
-            progress.update_output(encoder.total_out());
-            progress.finish();
-        } else {
-            io::copy(&mut input_stream, &mut encoder)?;
-        }
+        copy_with_progress(
+            &mut input_stream,
+            &mut encoder,
+            self.progress_args.chunk_size.size_in_bytes,
+            &self.hooks,
+            bar.as_ref(),
+            |e| e.total_out(),
+        )?;
+        encoder.finish()?;
         Ok(())
     }
 
     /// Extract a bz2 archive to a file or pipe
     fn extract(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
-        let mut file_size = None;
-        let mut input_stream = match input {
-            CmprssInput::Path(paths) => {
-                if paths.len() > 1 {
-                    return cmprss_error("only 1 file can be extracted at a time");
-                }
-                let file = Box::new(File::open(paths[0].as_path())?);
-                // Get the file size for the progress bar
-                if let Ok(metadata) = file.metadata() {
-                    file_size = Some(metadata.len());
-                }
-                file
-            }
-            CmprssInput::Pipe(pipe) => Box::new(pipe) as Box<dyn Read + Send>,
-        };
-        let output_stream: Box<dyn Write + Send> = match &output {
-            CmprssOutput::Path(path) => Box::new(File::create(path)?),
-            CmprssOutput::Pipe(pipe) => Box::new(pipe) as Box<dyn Write + Send>,
-        };
+        let (mut input_stream, file_size) = input.into_stream()?;
+        let bar = progress_bar(file_size, self.progress_args.progress, &output, &self.hooks);
+        let output_stream = self.hooks.limits.writer(output.into_stream()?, file_size);
         let mut decoder = BzDecoder::new(output_stream);
-        let mut bar = progress_bar(file_size, self.progress_args.progress, &output);
-        if let Some(progress) = &mut bar {
-            // Copy the input to the output in chunks so that we can update the progress bar
-            let mut buffer = vec![0; self.progress_args.chunk_size.size_in_bytes];
-            loop {
-                let bytes_read = input_stream.read(&mut buffer)?;
-                if bytes_read == 0 {
-                    break;
-                }
-                decoder.write_all(&buffer[..bytes_read])?;
-                progress.update_input(decoder.total_in());
-                progress.update_output(decoder.total_out());
-            }
-            decoder.flush()?;
-            progress.update_output(decoder.total_out());
-            progress.finish();
-        } else {
-            io::copy(&mut input_stream, &mut decoder)?;
-        }
+        copy_with_progress(
+            &mut input_stream,
+            &mut decoder,
+            self.progress_args.chunk_size.size_in_bytes,
+            &self.hooks,
+            bar.as_ref(),
+            |d| d.total_out(),
+        )?;
+        decoder.finish()?;
         Ok(())
     }
+
+    /// Wrap a writer with a bzip2 encoder
+    fn wrap_writer<'a>(
+        &self,
+        writer: Box<dyn Write + Send + 'a>,
+    ) -> Result<Box<dyn Write + Send + 'a>, io::Error> {
+        if self.level < 1 || self.level > 9 {
+            return Err(io::Error::other("Invalid compression level. Must be 1-9."));
+        }
+        Ok(Box::new(BzEncoder::new(
+            writer,
+            Compression::new(self.level),
+        )))
+    }
+
+    /// Wrap a reader with a bzip2 decoder
+    fn wrap_reader<'a>(
+        &self,
+        reader: Box<dyn Read + Send + 'a>,
+    ) -> Result<Box<dyn Read + Send + 'a>, io::Error> {
+        Ok(Box::new(bzip2::read::BzDecoder::new(reader)))
+    }
+
+    /// Compress a stream, finishing the bzip2 stream explicitly to catch errors
+    fn compress_stream(
+        &self,
+        reader: &mut (dyn Read + Send),
+        writer: &mut (dyn Write + Send),
+    ) -> Result<(), io::Error> {
+        if self.level < 1 || self.level > 9 {
+            return Err(io::Error::other("Invalid compression level. Must be 1-9."));
+        }
+        let mut encoder = BzEncoder::new(writer, Compression::new(self.level));
+        io::copy(reader, &mut encoder)?;
+        encoder.finish()?.flush()
+    }
 }
 
 #[cfg(test)]
@@ -186,6 +205,24 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn stream_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Bzip2::default();
+        let data = b"garbage data for testing";
+
+        let mut compressed = Vec::new();
+        compressor.compress_stream(&mut &data[..], &mut compressed)?;
+        assert_ne!(compressed, data);
+
+        let mut extracted = Vec::new();
+        compressor
+            .wrap_reader(Box::new(&compressed[..]))?
+            .read_to_end(&mut extracted)?;
+        assert_eq!(extracted, data);
+
+        Ok(())
+    }
+
     // Fail with a compression level of 0
     #[test]
     fn invalid_compression_level_0() {
@@ -0,0 +1,29 @@
+//! Print a single entry of an archive
+
+use crate::job::get_compressor_from_filename;
+use crate::list::{archive_name, open_archive};
+use crate::utils::*;
+use clap::Args;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct CatArgs {
+    /// Archive to read from, a file, the first part of a split archive, or a URL
+    pub input: PathBuf,
+
+    /// Path of the entry inside the archive
+    pub entry: String,
+}
+
+/// Write the contents of an entry of an archive to stdout
+pub fn cat(args: &CatArgs) -> Result<(), io::Error> {
+    let Some(compressor) = get_compressor_from_filename(&archive_name(&args.input)) else {
+        return cmprss_error("unable to determine the format of the archive");
+    };
+    compressor.cat(
+        open_archive(&args.input)?,
+        &args.entry,
+        CmprssOutput::Pipe(Box::new(io::stdout())),
+    )
+}
@@ -0,0 +1,228 @@
+//! Sidecar checksum files, like `archive.tar.gz.sha256`
+//!
+//! Sidecars use the format of `sha256sum`, so they can also be checked with the coreutils tools.
+
+use crate::utils::{cmprss_error, json_string, OutputFormat};
+use clap::Args;
+use sha2::{Digest, Sha256, Sha512};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Hash used for the checksums
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ChecksumAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+impl ChecksumAlgorithm {
+    /// All the algorithms, in the order their sidecars are looked for
+    const ALL: [ChecksumAlgorithm; 2] = [ChecksumAlgorithm::Sha256, ChecksumAlgorithm::Sha512];
+
+    /// Extension of the sidecar files
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Sha512 => "sha512",
+        }
+    }
+
+    /// Hash the contents of a file, as lowercase hex
+    pub fn digest_file(&self, path: &Path) -> Result<String, io::Error> {
+        self.digest(File::open(path)?)
+    }
+
+    /// Hash everything read from the reader, as lowercase hex
+    pub fn digest(&self, reader: impl Read) -> Result<String, io::Error> {
+        match self {
+            ChecksumAlgorithm::Sha256 => digest::<Sha256>(reader),
+            ChecksumAlgorithm::Sha512 => digest::<Sha512>(reader),
+        }
+    }
+}
+
+fn digest<D: Digest>(mut reader: impl Read) -> Result<String, io::Error> {
+    let mut hasher = D::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buffer[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// Path of the sidecar of a file, with the extension of the algorithm appended
+pub fn sidecar_path(path: &Path, algorithm: ChecksumAlgorithm) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".");
+    sidecar.push(algorithm.extension());
+    PathBuf::from(sidecar)
+}
+
+/// A checksum line like `sha256sum` prints, with the file name
+fn checksum_line(path: &Path, algorithm: ChecksumAlgorithm) -> Result<String, io::Error> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::other("file has no name"))?
+        .to_string_lossy();
+    Ok(format!("{}  {}", algorithm.digest_file(path)?, name))
+}
+
+/// The checksum of a file as a line of JSON
+fn checksum_json(path: &Path, algorithm: ChecksumAlgorithm) -> Result<String, io::Error> {
+    Ok(format!(
+        r#"{{"path":{},"algorithm":"{}","checksum":"{}"}}"#,
+        json_string(&path.to_string_lossy()),
+        algorithm.extension(),
+        algorithm.digest_file(path)?
+    ))
+}
+
+/// The result of verifying a file as a line of JSON, with the error if it failed
+fn verify_json(path: &Path, result: &Result<(), io::Error>) -> String {
+    format!(
+        r#"{{"path":{},"ok":{},"error":{}}}"#,
+        json_string(&path.to_string_lossy()),
+        result.is_ok(),
+        result
+            .as_ref()
+            .err()
+            .map_or_else(|| "null".to_string(), |e| json_string(&e.to_string()))
+    )
+}
+
+/// Write the checksum of a file to its sidecar, replacing an older one
+pub fn write_sidecar(path: &Path, algorithm: ChecksumAlgorithm) -> Result<PathBuf, io::Error> {
+    let sidecar = sidecar_path(path, algorithm);
+    let line = checksum_line(path, algorithm)?;
+    fs::write(&sidecar, format!("{}\n", line))?;
+    Ok(sidecar)
+}
+
+/// Check a file against the first sidecar found next to it
+pub fn verify_sidecar(path: &Path) -> Result<(), io::Error> {
+    let Some((algorithm, sidecar)) = ChecksumAlgorithm::ALL
+        .iter()
+        .map(|&algorithm| (algorithm, sidecar_path(path, algorithm)))
+        .find(|(_, sidecar)| sidecar.is_file())
+    else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no checksum file found for {}", path.display()),
+        ));
+    };
+    let contents = fs::read_to_string(&sidecar)?;
+    let expected = contents
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    if algorithm.digest_file(path)? != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} doesn't match its checksum in {}",
+                path.display(),
+                sidecar.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+pub struct ChecksumArgs {
+    /// Files to checksum
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+
+    /// Hash to use
+    #[arg(long, value_enum, default_value = "sha256")]
+    pub algorithm: ChecksumAlgorithm,
+
+    /// Write the checksum of each file to a sidecar file next to it, instead of printing it
+    #[arg(long)]
+    pub write: bool,
+
+    /// Check each file against its sidecar file
+    #[arg(long, conflicts_with_all = ["write", "algorithm"])]
+    pub verify: bool,
+}
+
+/// Print, write or verify the checksums of files
+/// JSON is printed as a line for each file, including the files that fail verification.
+pub fn checksum(args: &ChecksumArgs, output: OutputFormat) -> Result<(), io::Error> {
+    let mut stdout = io::stdout().lock();
+    let mut failed = 0;
+    for file in &args.files {
+        if args.verify {
+            let result = verify_sidecar(file);
+            match (output, &result) {
+                (OutputFormat::Json, _) => writeln!(stdout, "{}", verify_json(file, &result))?,
+                (OutputFormat::Text, Ok(())) => writeln!(stdout, "{}: OK", file.display())?,
+                (OutputFormat::Text, Err(e)) => eprintln!("ERROR(cmprss): {}", e),
+            }
+            if result.is_err() {
+                failed += 1;
+            }
+        } else if args.write {
+            write_sidecar(file, args.algorithm)?;
+        } else {
+            match output {
+                OutputFormat::Json => writeln!(stdout, "{}", checksum_json(file, args.algorithm)?)?,
+                OutputFormat::Text => writeln!(stdout, "{}", checksum_line(file, args.algorithm)?)?,
+            }
+        }
+    }
+    if failed > 0 {
+        return cmprss_error(&format!(
+            "{} of {} files failed verification",
+            failed,
+            args.files.len()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+
+    #[test]
+    fn sidecar_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("archive.gz");
+        file.write_str("garbage data for testing")?;
+
+        assert!(verify_sidecar(file.path()).is_err());
+        let sidecar = write_sidecar(file.path(), ChecksumAlgorithm::Sha256)?;
+        assert_eq!(sidecar, working_dir.child("archive.gz.sha256").path());
+        working_dir.child("archive.gz.sha256").assert(
+            "5f1d08e241279d89d8715f75a33377a9c31556904b17d7d919a781624fa20a51  archive.gz\n",
+        );
+        verify_sidecar(file.path())?;
+
+        file.write_str("modified data")?;
+        let error = verify_sidecar(file.path()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            verify_json(Path::new("archive.gz"), &Ok(())),
+            r#"{"path":"archive.gz","ok":true,"error":null}"#
+        );
+        assert_eq!(
+            verify_json(Path::new("archive.gz"), &Err(io::Error::other("no match"))),
+            r#"{"path":"archive.gz","ok":false,"error":"no match"}"#
+        );
+        Ok(())
+    }
+}
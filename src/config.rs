@@ -0,0 +1,707 @@
+//! Defaults for the command line options, from the config file
+//!
+//! ```toml
+//! # Options of every format
+//! threads = 8
+//! progress = "off"
+//! exclude = ["target", "*.o"]
+//!
+//! # Options of a single format, which take precedence over the ones above
+//! [zstd]
+//! level = 19
+//!
+//! # Options chosen with `--profile backup`, which take precedence over all the ones above
+//! [profile.backup]
+//! format = "tar.zst"
+//! level = 19
+//! checksum = "sha256"
+//! ```
+//!
+//! The keys are the long names of the command line options, and their values become the
+//! defaults of those options. Options given on the command line still take precedence.
+//!
+//! The environment variables in [`ENV_VARS`] and `CMPRSS_FORMAT` also set defaults, which take
+//! precedence over the config file but not over a profile.
+//! Plugins are declared in the same file, see [`plugin`](crate::plugin).
+
+use crate::job::{get_compressor_from_filename, get_compressor_from_name};
+use crate::progress::ProgressDisplay;
+use crate::utils::*;
+use clap::{Arg, ArgAction, Command, ValueEnum};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Options and their default values
+type Defaults = Vec<(String, Vec<String>)>;
+
+/// Environment variables and the options they set the defaults of
+pub const ENV_VARS: [(&str, &str); 2] = [("CMPRSS_LEVEL", "level"), ("CMPRSS_THREADS", "threads")];
+
+/// Environment variable with the format to use when no subcommand or filename names one
+pub const ENV_FORMAT: &str = "CMPRSS_FORMAT";
+
+/// A set of defaults for a workflow, from tables like `[profile.backup]`
+#[derive(Debug, Default, Clone)]
+struct Profile {
+    /// Format used when no subcommand is given, like `tar.zst`
+    format: Option<String>,
+    options: Defaults,
+}
+
+/// The defaults loaded from the config file and the environment
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    /// Defaults of the options of every format, like `threads = 8`
+    options: Defaults,
+    /// Defaults of the options of a single format, from tables like `[zstd]`
+    formats: BTreeMap<String, Defaults>,
+    profiles: BTreeMap<String, Profile>,
+    /// Defaults from the environment variables, for each command that accepts them
+    env: BTreeMap<String, Defaults>,
+    /// Format from CMPRSS_FORMAT
+    env_format: Option<String>,
+    /// The profile chosen with `--profile`
+    profile: Option<Profile>,
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Make the config available to the compressors inferred from filenames
+/// Only the first call has any effect.
+pub fn register(config: Config) {
+    let _ = CONFIG.set(config);
+}
+
+/// The config that was registered, or an empty one
+pub fn registered() -> &'static Config {
+    static EMPTY: Config = Config {
+        options: Vec::new(),
+        formats: BTreeMap::new(),
+        profiles: BTreeMap::new(),
+        env: BTreeMap::new(),
+        env_format: None,
+        profile: None,
+    };
+    CONFIG.get().unwrap_or(&EMPTY)
+}
+
+/// Location of the config file
+/// Uses $XDG_CONFIG_HOME/cmprss/config.toml, falling back to ~/.config/cmprss/config.toml
+pub fn config_path() -> Option<PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("cmprss").join("config.toml"))
+}
+
+/// Find the value of an option like `--config PATH` in the arguments
+/// The config and the profile set the defaults of the other options, so they are found before
+/// parsing the arguments.
+pub fn value_from_args(args: &[String], option: &str) -> Option<String> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == option {
+            return args.next().cloned();
+        }
+        if let Some(value) = arg.strip_prefix(option).and_then(|v| v.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+/// Load the config file
+/// A missing config file is not an error, there are just no defaults.
+pub fn load(path: &Path) -> Result<Config, io::Error> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(e),
+    };
+    parse(&contents).map_err(|e| io::Error::other(format!("{}: {}", path.display(), e)))
+}
+
+fn parse(contents: &str) -> Result<Config, io::Error> {
+    let table: toml::Table = toml::from_str(contents).map_err(io::Error::other)?;
+    let mut config = Config::default();
+    for (key, value) in table {
+        match value {
+            // Loaded by the plugin module
+            _ if key == "plugins" => {}
+            toml::Value::Table(profiles) if key == "profile" => {
+                for (name, profile) in profiles {
+                    let profile = parse_profile(&name, profile)?;
+                    config.profiles.insert(name, profile);
+                }
+            }
+            toml::Value::Table(options) => {
+                let defaults = options
+                    .into_iter()
+                    .map(|(option, value)| {
+                        let values = values(&format!("{}.{}", key, option), value)?;
+                        Ok((option, values))
+                    })
+                    .collect::<Result<_, io::Error>>()?;
+                config.formats.insert(key, defaults);
+            }
+            value => {
+                let values = values(&key, value)?;
+                config.options.push((key, values));
+            }
+        }
+    }
+    Ok(config)
+}
+
+fn parse_profile(name: &str, profile: toml::Value) -> Result<Profile, io::Error> {
+    let toml::Value::Table(table) = profile else {
+        return Err(io::Error::other(format!(
+            "profile.{} must be a table",
+            name
+        )));
+    };
+    let mut profile = Profile::default();
+    for (key, value) in table {
+        match value {
+            toml::Value::String(format) if key == "format" => profile.format = Some(format),
+            value => {
+                let values = values(&format!("profile.{}.{}", name, key), value)?;
+                profile.options.push((key, values));
+            }
+        }
+    }
+    Ok(profile)
+}
+
+/// The values of an option as they would be given on the command line
+fn values(key: &str, value: toml::Value) -> Result<Vec<String>, io::Error> {
+    let scalar = |value: toml::Value| match value {
+        toml::Value::String(s) => Some(s),
+        toml::Value::Integer(i) => Some(i.to_string()),
+        toml::Value::Float(f) => Some(f.to_string()),
+        toml::Value::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    };
+    let values = match value {
+        toml::Value::Array(values) => values.into_iter().map(scalar).collect(),
+        value => scalar(value).map(|value| vec![value]),
+    };
+    values.ok_or_else(|| {
+        io::Error::other(format!(
+            "{} must be a string, number, boolean, or a list of them",
+            key
+        ))
+    })
+}
+
+/// Find an option of a command by its long name or one of its aliases
+fn find_arg<'a>(command: &'a Command, name: &str) -> Option<&'a Arg> {
+    command.get_arguments().find(|arg| {
+        arg.get_long() == Some(name)
+            || arg
+                .get_all_aliases()
+                .is_some_and(|aliases| aliases.contains(&name))
+    })
+}
+
+/// Check that the option accepts the values, like it would on the command line
+fn check_values(
+    command: &Command,
+    key: &str,
+    name: &str,
+    values: &[String],
+) -> Result<bool, io::Error> {
+    let Some(arg) = find_arg(command, name) else {
+        return Ok(false);
+    };
+    if values.len() > 1 && !matches!(arg.get_action(), ArgAction::Append) {
+        return Err(io::Error::other(format!(
+            "{} only takes a single value",
+            key
+        )));
+    }
+    if !arg.get_action().takes_values() {
+        if values.iter().any(|value| value.parse::<bool>().is_err()) {
+            return Err(io::Error::other(format!("{} must be true or false", key)));
+        }
+        return Ok(true);
+    }
+    // Parse them with an option on its own, since the others might be required
+    let option = Arg::new("option")
+        .long("option")
+        .action(ArgAction::Append)
+        .value_parser(arg.get_value_parser().clone());
+    let args = values.iter().map(|value| format!("--option={}", value));
+    Command::new("config")
+        .arg(option)
+        .try_get_matches_from(std::iter::once("config".to_string()).chain(args))
+        .map_err(|_| io::Error::other(format!("invalid value for {}", key)))?;
+    Ok(true)
+}
+
+/// Check an option that applies to every command that has it, or only to `format` if it has it
+fn check_option(
+    command: &Command,
+    format: Option<&Command>,
+    key: &str,
+    name: &str,
+    values: &[String],
+) -> Result<(), io::Error> {
+    if let Some(format) = format {
+        if check_values(format, key, name, values)? {
+            return Ok(());
+        }
+    }
+    let mut found = false;
+    for cmd in std::iter::once(command).chain(command.get_subcommands()) {
+        found |= check_values(cmd, key, name, values)?;
+    }
+    if !found {
+        return Err(io::Error::other(format!(
+            "unknown option {} in the config",
+            key
+        )));
+    }
+    Ok(())
+}
+
+/// Set the default values of the options of a command that it has
+fn set_defaults<'a>(
+    mut command: Command,
+    defaults: impl Iterator<Item = &'a (String, Vec<String>)>,
+) -> Command {
+    for (name, values) in defaults {
+        let Some(id) = find_arg(&command, name).map(|arg| arg.get_id().clone()) else {
+            continue;
+        };
+        let values = values.clone();
+        command = command.mut_arg(id, |arg| arg.default_values(values));
+    }
+    command
+}
+
+impl Config {
+    /// Check that every option in the config is an option of the command or its subcommands,
+    /// and that they accept the values
+    pub fn validate(&self, command: &Command) -> Result<(), io::Error> {
+        for (name, values) in &self.options {
+            check_option(command, None, name, name, values)?;
+        }
+        for (format, defaults) in &self.formats {
+            let Some(cmd) = command.find_subcommand(format) else {
+                return Err(io::Error::other(format!(
+                    "unknown format [{}] in the config",
+                    format
+                )));
+            };
+            for (name, values) in defaults {
+                let key = format!("{}.{}", format, name);
+                if !check_values(cmd, &key, name, values)? {
+                    return Err(io::Error::other(format!(
+                        "{} has no option --{}",
+                        format, name
+                    )));
+                }
+            }
+        }
+        for (profile_name, profile) in &self.profiles {
+            // Options of the format of the profile only need to suit that format
+            let format = match &profile.format {
+                Some(format) => {
+                    if self.profile_compressor(format).is_none() {
+                        return Err(io::Error::other(format!(
+                            "unknown format {} in profile.{}",
+                            format, profile_name
+                        )));
+                    }
+                    format
+                        .rsplit('.')
+                        .next()
+                        .and_then(|f| command.find_subcommand(f))
+                }
+                None => None,
+            };
+            for (name, values) in &profile.options {
+                let key = format!("profile.{}.{}", profile_name, name);
+                check_option(command, format, &key, name, values)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the defaults from the environment variables
+    /// Each value only needs to suit one of the commands, and is only used by the commands it
+    /// suits, like a CMPRSS_LEVEL of 19 for zstd but not gzip.
+    pub fn read_env(&mut self, command: &Command) -> Result<(), io::Error> {
+        self.set_env(command, |name| std::env::var(name).ok())
+    }
+
+    fn set_env(
+        &mut self,
+        command: &Command,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<(), io::Error> {
+        let var = |name| var(name).filter(|value| !value.is_empty());
+        for (name, option) in ENV_VARS {
+            let Some(value) = var(name) else {
+                continue;
+            };
+            let values = vec![value];
+            let mut accepted = false;
+            for cmd in std::iter::once(command).chain(command.get_subcommands()) {
+                if check_values(cmd, name, option, &values).unwrap_or(false) {
+                    let defaults = self.env.entry(cmd.get_name().to_string()).or_default();
+                    defaults.push((option.to_string(), values.clone()));
+                    accepted = true;
+                }
+            }
+            if !accepted {
+                return Err(io::Error::other(format!("invalid value for {}", name)));
+            }
+        }
+        if let Some(format) = var(ENV_FORMAT) {
+            if self.profile_compressor(&format).is_none() {
+                return Err(io::Error::other(format!(
+                    "unknown format {} in {}",
+                    format, ENV_FORMAT
+                )));
+            }
+            self.env_format = Some(format);
+        }
+        Ok(())
+    }
+
+    /// Use the defaults of a profile, on top of the rest of the config
+    pub fn select_profile(&mut self, name: &str) -> Result<(), io::Error> {
+        let Some(profile) = self.profiles.get(name) else {
+            return Err(io::Error::other(format!(
+                "no profile named {} in the config",
+                name
+            )));
+        };
+        self.profile = Some(profile.clone());
+        Ok(())
+    }
+
+    /// The compressor of a format given by name like `gzip`, or by extension like `tar.zst`
+    fn profile_compressor(&self, format: &str) -> Option<Box<dyn Compressor>> {
+        get_compressor_from_name(format)
+            .or_else(|| get_compressor_from_filename(Path::new(&format!("cmprss.{}", format))))
+    }
+
+    /// The compressor for the format of the chosen profile or CMPRSS_FORMAT, if there is one
+    /// Filenames that name a format, like an output of `out.tar.gz`, take precedence, as does
+    /// extracting.
+    pub fn compressor(&self, args: &CommonArgs) -> Option<Box<dyn Compressor>> {
+        let format = (self.profile.as_ref())
+            .and_then(|profile| profile.format.as_ref())
+            .or(self.env_format.as_ref())?;
+        let named = (args.input.iter())
+            .chain(&args.output)
+            .chain(&args.io_list)
+            .any(|path| get_compressor_from_filename(Path::new(path)).is_some());
+        if named || args.extract || args.decompress {
+            return None;
+        }
+        self.profile_compressor(format)
+    }
+
+    /// The options of a command from the environment and the chosen profile, which take
+    /// precedence over the tables of the formats
+    fn override_options<'a>(
+        &'a self,
+        command: &str,
+    ) -> impl Iterator<Item = &'a (String, Vec<String>)> {
+        (self.env.get(command).into_iter().flatten())
+            .chain(self.profile.iter().flat_map(|profile| &profile.options))
+    }
+
+    /// Set the defaults of the command and its subcommands to the values in the config
+    /// Options that a command doesn't have are skipped, so check them with
+    /// [`validate`](Config::validate) first.
+    pub fn apply_defaults(&self, command: Command) -> Command {
+        let name = command.get_name().to_string();
+        let mut command = set_defaults(
+            command,
+            self.options.iter().chain(self.override_options(&name)),
+        );
+        let names: Vec<String> = command
+            .get_subcommands()
+            .map(|sub| sub.get_name().to_string())
+            .collect();
+        for name in names {
+            let format = self
+                .formats
+                .iter()
+                .filter(|(format, _)| {
+                    command.find_subcommand(format).map(Command::get_name) == Some(&name)
+                })
+                .flat_map(|(_, defaults)| defaults);
+            let defaults: Vec<_> = self
+                .options
+                .iter()
+                .chain(format)
+                .chain(self.override_options(&name))
+                .collect();
+            command = command.mut_subcommand(&name, |sub| set_defaults(sub, defaults.into_iter()));
+        }
+        command
+    }
+
+    /// The value of an option of a format, from the profile, the environment, the table of the
+    /// format, or the options of every format
+    fn value(&self, format: &str, name: &str) -> Option<&str> {
+        fn find<'a>(defaults: &'a Defaults, name: &str) -> Option<&'a str> {
+            defaults
+                .iter()
+                .find(|(option, _)| option == name)
+                .and_then(|(_, values)| values.first())
+                .map(String::as_str)
+        }
+        self.profile
+            .as_ref()
+            .and_then(|profile| find(&profile.options, name))
+            .or_else(|| self.env.get(format).and_then(|d| find(d, name)))
+            .or_else(|| self.formats.get(format).and_then(|d| find(d, name)))
+            .or_else(|| find(&self.options, name))
+    }
+
+    /// Set the defaults of a compressor that was inferred from a filename, rather than created
+    /// from the options of its subcommand
+    /// Only the options that aren't shared by all the formats, the level and progress, are set.
+    pub fn apply(&self, compressor: &mut dyn Compressor) {
+        let name = compressor.name().to_string();
+        if let Some(level) = self.value(&name, "level") {
+            let level = level.parse().ok().or_else(|| {
+                CompressionLevel::from_str(level)
+                    .ok()
+                    .map(|l| l.level as i32)
+            });
+            if let Some(level) = level {
+                compressor.set_level(level);
+            }
+        }
+        if let Some(progress) = self.value(&name, "progress") {
+            if let Ok(progress) = ProgressDisplay::from_str(progress, true) {
+                compressor.set_progress(progress);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+
+    #[derive(Parser, Debug)]
+    struct TestArgs {
+        #[command(subcommand)]
+        format: Option<TestFormat>,
+
+        #[clap(flatten)]
+        common: TestCommon,
+    }
+
+    #[derive(Args, Debug)]
+    struct TestCommon {
+        #[arg(long)]
+        threads: Option<u32>,
+
+        #[arg(long)]
+        exclude: Vec<String>,
+    }
+
+    #[derive(Subcommand, Debug)]
+    enum TestFormat {
+        #[clap(visible_alias = "zst")]
+        Zstd {
+            #[clap(flatten)]
+            common: TestCommon,
+
+            #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(i32).range(1..=22))]
+            level: i32,
+        },
+    }
+
+    #[derive(Parser, Debug)]
+    struct CommonArgsParser {
+        #[clap(flatten)]
+        args: CommonArgs,
+    }
+
+    fn parse_args(config: &str, args: &[&str]) -> Result<TestArgs, io::Error> {
+        let config = parse(config)?;
+        config.validate(&TestArgs::command())?;
+        let matches = config
+            .apply_defaults(TestArgs::command())
+            .try_get_matches_from(args)
+            .map_err(io::Error::other)?;
+        TestArgs::from_arg_matches(&matches).map_err(io::Error::other)
+    }
+
+    #[test]
+    fn defaults_beneath_arguments() -> Result<(), io::Error> {
+        let config = r#"
+            threads = 8
+            exclude = ["target", "*.o"]
+
+            [zst]
+            level = 19
+        "#;
+        let args = parse_args(config, &["cmprss"])?;
+        assert_eq!(args.common.threads, Some(8));
+        assert_eq!(args.common.exclude, ["target", "*.o"]);
+
+        let args = parse_args(config, &["cmprss", "zstd", "--threads", "2"])?;
+        let Some(TestFormat::Zstd { common, level }) = args.format else {
+            panic!("expected the zstd subcommand");
+        };
+        assert_eq!(common.threads, Some(2));
+        assert_eq!(common.exclude, ["target", "*.o"]);
+        assert_eq!(level, 19);
+
+        let args = parse_args(config, &["cmprss", "zstd", "--level", "5"])?;
+        assert!(matches!(
+            args.format,
+            Some(TestFormat::Zstd { level: 5, .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(parse_args("threads = 8", &["cmprss"]).is_ok());
+        assert!(parse_args("thread = 8", &["cmprss"]).is_err());
+        assert!(parse_args("threads = \"many\"", &["cmprss"]).is_err());
+        assert!(parse_args("threads = [1, 2]", &["cmprss"]).is_err());
+        assert!(parse_args("[zstd]\nlevel = 30", &["cmprss"]).is_err());
+        assert!(parse_args("[zstd]\nquality = 3", &["cmprss"]).is_err());
+        assert!(parse_args("[lz5]\nlevel = 3", &["cmprss"]).is_err());
+        assert!(parse_args("[zstd]\nlevel = { fast = 1 }", &["cmprss"]).is_err());
+        assert!(parse_args("[plugins.lrzip]\ncompress = [\"lrzip\"]", &["cmprss"]).is_ok());
+    }
+
+    #[test]
+    fn profiles() -> Result<(), io::Error> {
+        let mut config = parse(
+            r#"
+            threads = 8
+
+            [zstd]
+            level = 5
+
+            [profile.backup]
+            format = "tar.gz"
+            level = 19
+            threads = 2
+            "#,
+        )?;
+        config.validate(&TestArgs::command())?;
+        let args = CommonArgsParser::parse_from(["cmprss", "dir"]).args;
+        assert!(config.compressor(&args).is_none());
+        assert!(config.select_profile("quick").is_err());
+        config.select_profile("backup")?;
+        assert_eq!(config.compressor(&args).unwrap().name(), "tar.gz");
+        let args = CommonArgsParser::parse_from(["cmprss", "dir", "dir.zip"]).args;
+        assert!(config.compressor(&args).is_none());
+        assert_eq!(config.value("zstd", "level"), Some("19"));
+        assert_eq!(config.value("gzip", "threads"), Some("2"));
+
+        let matches = config
+            .apply_defaults(TestArgs::command())
+            .try_get_matches_from(["cmprss", "zstd"])
+            .map_err(io::Error::other)?;
+        let args = TestArgs::from_arg_matches(&matches).map_err(io::Error::other)?;
+        let Some(TestFormat::Zstd { common, level }) = args.format else {
+            panic!("expected the zstd subcommand");
+        };
+        assert_eq!((common.threads, level), (Some(2), 19));
+
+        // Formats can also be named like the commands
+        let mut config = parse("[profile.logs]\nformat = \"gzip\"")?;
+        config.validate(&TestArgs::command())?;
+        config.select_profile("logs")?;
+        let args = CommonArgsParser::parse_from(["cmprss", "app.log"]).args;
+        assert_eq!(config.compressor(&args).unwrap().name(), "gzip");
+
+        let invalid = "[profile.quick]\nformat = \"lz5\"";
+        assert!(parse(invalid)?.validate(&TestArgs::command()).is_err());
+        let invalid = "[profile.quick]\nformat = \"tar.gz\"\nlevel = 30";
+        assert!(parse(invalid)?.validate(&TestArgs::command()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn environment() -> Result<(), io::Error> {
+        let env = |vars: &'static [(&str, &str)]| {
+            move |name: &str| {
+                (vars.iter())
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        let mut config = parse("threads = 8\n[zstd]\nlevel = 5\n[profile.fast]\nlevel = 1")?;
+        config.set_env(
+            &TestArgs::command(),
+            env(&[
+                ("CMPRSS_LEVEL", "19"),
+                ("CMPRSS_THREADS", ""),
+                ("CMPRSS_FORMAT", "tar.gz"),
+            ]),
+        )?;
+        assert_eq!(config.value("zstd", "level"), Some("19"));
+        assert_eq!(config.value("zstd", "threads"), Some("8"));
+        let args = CommonArgsParser::parse_from(["cmprss", "dir"]).args;
+        assert_eq!(config.compressor(&args).unwrap().name(), "tar.gz");
+
+        let matches = config
+            .apply_defaults(TestArgs::command())
+            .try_get_matches_from(["cmprss", "zstd"])
+            .map_err(io::Error::other)?;
+        let args = TestArgs::from_arg_matches(&matches).map_err(io::Error::other)?;
+        assert!(matches!(
+            args.format,
+            Some(TestFormat::Zstd { level: 19, .. })
+        ));
+
+        // A profile takes precedence over the environment
+        config.select_profile("fast")?;
+        assert_eq!(config.value("zstd", "level"), Some("1"));
+
+        let mut config = Config::default();
+        let invalid = config.set_env(&TestArgs::command(), env(&[("CMPRSS_LEVEL", "30")]));
+        assert!(invalid.is_err());
+        let invalid = config.set_env(&TestArgs::command(), env(&[("CMPRSS_FORMAT", "lz5")]));
+        assert!(invalid.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn config_argument() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            value_from_args(&args(&["cmprss", "--config", "a.toml", "file"]), "--config"),
+            Some("a.toml".to_string())
+        );
+        assert_eq!(
+            value_from_args(&args(&["cmprss", "gzip", "--config=b.toml"]), "--config"),
+            Some("b.toml".to_string())
+        );
+        assert_eq!(
+            value_from_args(&args(&["cmprss", "--configs", "c.toml"]), "--config"),
+            None
+        );
+        assert_eq!(
+            value_from_args(&args(&["cmprss", "--", "--config"]), "--config"),
+            None
+        );
+    }
+}
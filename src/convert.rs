@@ -0,0 +1,224 @@
+//! Convert an archive or compressed file to another format
+
+use crate::job::{get_compressor_from_filename, stream_compressors};
+use crate::pipeline::pipeline_result;
+use crate::utils::*;
+use crate::zip::Zip;
+use clap::Args;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+#[derive(Args, Debug)]
+pub struct ConvertArgs {
+    /// File to convert
+    pub input: PathBuf,
+
+    /// File to write, the format is inferred from its extension
+    pub output: PathBuf,
+
+    /// Overwrite the output if it already exists
+    #[arg(short, long)]
+    pub force: bool,
+}
+
+/// The archive format holding the entries of a file
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum Archive {
+    Tar,
+    Zip,
+}
+
+/// A format split into its layers, like tar and gzip for a tar.gz
+pub(crate) struct Layers {
+    pub name: String,
+    pub archive: Option<Archive>,
+    pub stream: Option<Box<dyn Compressor>>,
+}
+
+impl Layers {
+    pub fn of(path: &Path) -> Result<Layers, io::Error> {
+        let Some(compressor) = get_compressor_from_filename(path) else {
+            return Err(io::Error::other(format!(
+                "unable to determine the format of {}",
+                path.display()
+            )));
+        };
+        let name = compressor.name().to_string();
+        let (archive, stream) = match name.as_str() {
+            "tar" => (Some(Archive::Tar), None),
+            "zip" => (Some(Archive::Zip), None),
+            _ => match name.strip_prefix("tar.") {
+                Some(extension) => (
+                    Some(Archive::Tar),
+                    stream_compressors()
+                        .into_iter()
+                        .find(|c| c.extension() == extension),
+                ),
+                None => (None, Some(compressor)),
+            },
+        };
+        Ok(Layers {
+            name,
+            archive,
+            stream,
+        })
+    }
+}
+
+/// Write the contents of the input below its stream layer, like the tar inside a tar.gz
+pub(crate) fn decode(
+    stream: Option<Box<dyn Compressor>>,
+    input: PathBuf,
+    mut writer: Box<dyn Write + Send>,
+) -> Result<(), io::Error> {
+    match stream {
+        Some(stream) => stream.extract(
+            CmprssInput::Path(vec![input]),
+            CmprssOutput::Writer(CmprssWrite { writer }),
+        ),
+        None => {
+            io::copy(&mut File::open(input)?, &mut writer)?;
+            writer.flush()
+        }
+    }
+}
+
+/// Write the reader to the output, compressed with its stream layer
+fn encode(
+    stream: Option<Box<dyn Compressor>>,
+    mut reader: Box<dyn Read + Send>,
+    output: &Path,
+) -> Result<(), io::Error> {
+    match stream {
+        Some(stream) => stream.compress(
+            CmprssInput::Reader(CmprssRead {
+                reader,
+                size: None,
+                name: None,
+            }),
+            CmprssOutput::Path(output.to_path_buf()),
+        ),
+        None => {
+            let mut file = File::create(output)?;
+            io::copy(&mut reader, &mut file)?;
+            file.flush()
+        }
+    }
+}
+
+/// Convert a file between formats in one pass, without writing intermediate files
+///
+/// Archives of the same kind, like tar.bz2 and tar.zst, only change the compression around
+/// the archive. Zip and tar archives are rewritten entry by entry.
+pub fn convert(args: &ConvertArgs) -> Result<(), io::Error> {
+    if !args.force && args.output.try_exists()? {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "output path {} already exists, use --force to overwrite it",
+                args.output.display()
+            ),
+        ));
+    }
+    let from = Layers::of(&args.input)?;
+    let to = Layers::of(&args.output)?;
+    let rewrite = match (from.archive, to.archive) {
+        (a, b) if a == b => None,
+        (Some(a), Some(b)) => Some((a, b)),
+        _ => {
+            return Err(io::Error::other(format!(
+                "can't convert {} to {}, only one of them is an archive",
+                from.name, to.name
+            )))
+        }
+    };
+
+    let output = AtomicOutput::new(&args.output);
+    let (reader, writer) = io::pipe()?;
+    let input = args.input.clone();
+    let source = thread::Builder::new().spawn(move || match rewrite {
+        Some((Archive::Zip, Archive::Tar)) => Zip::default()
+            .extract_to_tar(&input, writer)
+            .and_then(|mut writer| writer.flush()),
+        _ => decode(from.stream, input, Box::new(writer)),
+    })?;
+    let result = match rewrite {
+        Some((Archive::Tar, Archive::Zip)) => Zip::default().compress_tar(reader, output.tmp()),
+        _ => encode(to.stream, Box::new(reader), output.tmp()),
+    };
+    output.finish(pipeline_result(result, source.join()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use predicates::prelude::*;
+
+    fn convert_files(input: &Path, output: &Path) -> Result<(), io::Error> {
+        convert(&ConvertArgs {
+            input: input.to_path_buf(),
+            output: output.to_path_buf(),
+            force: false,
+        })
+    }
+
+    #[test]
+    fn archive_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("dir/test.txt")
+            .write_str("garbage data for testing")?;
+        working_dir.child("dir/empty").create_dir_all()?;
+        let tar = working_dir.child("archive.tar.gz");
+        crate::tar::Tar::default().compress(
+            CmprssInput::Path(vec![working_dir.child("dir").path().to_path_buf()]),
+            CmprssOutput::Writer(CmprssWrite {
+                writer: crate::gzip::Gzip::default()
+                    .wrap_writer(Box::new(File::create(tar.path())?))?,
+            }),
+        )?;
+
+        // tar.gz to zip to tar.gz
+        let zip = working_dir.child("archive.zip");
+        convert_files(tar.path(), zip.path())?;
+        let back = working_dir.child("back.tar.gz");
+        convert_files(zip.path(), back.path())?;
+
+        let entries = get_compressor_from_filename(back.path())
+            .unwrap()
+            .list(CmprssInput::Path(vec![back.path().to_path_buf()]))?;
+        let mut paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+        paths.sort();
+        assert_eq!(paths, ["dir/", "dir/empty/", "dir/test.txt"]);
+
+        let out = working_dir.child("out");
+        out.create_dir_all()?;
+        get_compressor_from_filename(back.path()).unwrap().extract(
+            CmprssInput::Path(vec![back.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        out.child("dir/test.txt").assert("garbage data for testing");
+        out.child("dir/empty").assert(predicate::path::is_dir());
+
+        Ok(())
+    }
+
+    #[test]
+    fn mismatched_formats() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let mut compressed = Vec::new();
+        crate::gzip::Gzip::default()
+            .compress_stream(&mut &b"garbage data for testing"[..], &mut compressed)?;
+        let file = working_dir.child("test.txt.gz");
+        file.write_binary(&compressed)?;
+        let output = working_dir.child("test.zip");
+        let error = convert_files(file.path(), output.path()).unwrap_err();
+        assert!(error.to_string().contains("only one of them is an archive"));
+        output.assert(predicate::path::missing());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+//! Compare the entries of an archive with the files of a directory, without extracting it
+
+use crate::checksum::ChecksumAlgorithm;
+use crate::convert::{decode, Archive, Layers};
+use crate::utils::*;
+use clap::Args;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// Archive to compare, like a backup
+    pub archive: PathBuf,
+
+    /// Directory to compare the archive with
+    pub dir: PathBuf,
+}
+
+/// An entry of the archive, to compare with the file at the same path
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Dir,
+    File { size: u64, digest: String },
+    Symlink(PathBuf),
+}
+
+/// How a path differs between the archive and the directory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Change {
+    /// Only in the directory
+    Added,
+    /// Only in the archive
+    Removed,
+    /// A different kind of file, or different contents
+    Modified,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Added => write!(f, "added"),
+            Change::Removed => write!(f, "removed"),
+            Change::Modified => write!(f, "modified"),
+        }
+    }
+}
+
+impl Change {
+    /// Marker printed before the path, like `git diff --name-status`
+    fn marker(&self) -> char {
+        match self {
+            Change::Added => 'A',
+            Change::Removed => 'D',
+            Change::Modified => 'M',
+        }
+    }
+}
+
+/// Path of an entry, without a leading ./ or a trailing /
+fn entry_name(path: &str) -> Option<String> {
+    let mut name = path.trim_end_matches('/');
+    while let Some(rest) = name.strip_prefix("./") {
+        name = rest;
+    }
+    (!name.is_empty() && name != ".").then(|| name.to_string())
+}
+
+/// Read the entries of a tar archive, hashing the contents of the files
+fn read_tar(reader: impl Read) -> Result<BTreeMap<String, Node>, io::Error> {
+    let mut entries = BTreeMap::new();
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let Some(name) = entry_name(&entry.path()?.to_string_lossy()) else {
+            continue;
+        };
+        let link = || -> Result<PathBuf, io::Error> {
+            Ok(entry.link_name()?.unwrap_or_default().into_owned())
+        };
+        let node = match entry.header().entry_type() {
+            tar::EntryType::Directory => Node::Dir,
+            tar::EntryType::Symlink => Node::Symlink(link()?),
+            // Hard links have the contents of the entry they link to
+            tar::EntryType::Link => {
+                let target = entry_name(&link()?.to_string_lossy());
+                match target.and_then(|target| entries.get(&target)) {
+                    Some(node) => Node::clone(node),
+                    None => continue,
+                }
+            }
+            kind if kind.is_file() => Node::File {
+                size: entry.size(),
+                digest: ChecksumAlgorithm::Sha256.digest(&mut entry)?,
+            },
+            _ => continue,
+        };
+        entries.insert(name, node);
+    }
+    Ok(entries)
+}
+
+/// Read the entries of a zip archive, hashing the contents of the files
+fn read_zip(path: &Path) -> Result<BTreeMap<String, Node>, io::Error> {
+    let mut entries = BTreeMap::new();
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let Some(name) = entry_name(file.name()) else {
+            continue;
+        };
+        let node = if file.is_dir() {
+            Node::Dir
+        } else if file.is_symlink() {
+            let mut target = String::new();
+            file.read_to_string(&mut target)?;
+            Node::Symlink(PathBuf::from(target))
+        } else {
+            Node::File {
+                size: file.size(),
+                digest: ChecksumAlgorithm::Sha256.digest(&mut file)?,
+            }
+        };
+        entries.insert(name, node);
+    }
+    Ok(entries)
+}
+
+/// Read the entries of an archive, decompressing it on a separate thread like `convert` does
+fn read_archive(path: &Path) -> Result<BTreeMap<String, Node>, io::Error> {
+    let layers = Layers::of(path)?;
+    match layers.archive {
+        Some(Archive::Zip) => read_zip(path),
+        Some(Archive::Tar) => {
+            let (reader, writer) = io::pipe()?;
+            let input = path.to_path_buf();
+            let source = thread::Builder::new()
+                .spawn(move || decode(layers.stream, input, Box::new(writer)))?;
+            let entries = read_tar(reader);
+            let source = source
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("decompressing thread panicked")));
+            match (entries, source) {
+                // Reading stops at the end of the archive, leaving any padding after it unread
+                (Ok(entries), Err(e)) if e.kind() == io::ErrorKind::BrokenPipe => Ok(entries),
+                (_, Err(e)) if e.kind() != io::ErrorKind::BrokenPipe => Err(e),
+                (entries, _) => entries,
+            }
+        }
+        None => Err(io::Error::other(format!(
+            "{} is not an archive",
+            path.display()
+        ))),
+    }
+}
+
+/// Collect the paths below a directory, with their names relative to it
+/// Symlinks are not followed.
+fn walk(dir: &Path, prefix: &str, files: &mut BTreeMap<String, PathBuf>) -> Result<(), io::Error> {
+    for child in fs::read_dir(dir)? {
+        let child = child?;
+        let name = format!("{}{}", prefix, child.file_name().to_string_lossy());
+        if child.file_type()?.is_dir() {
+            walk(&child.path(), &format!("{}/", name), files)?;
+        }
+        files.insert(name, child.path());
+    }
+    Ok(())
+}
+
+/// Whether the file on disk differs from the entry of the archive
+/// Contents are only hashed when the sizes match.
+fn differs(node: &Node, path: &Path) -> Result<bool, io::Error> {
+    let metadata = fs::symlink_metadata(path)?;
+    Ok(match node {
+        Node::Dir => !metadata.is_dir(),
+        Node::Symlink(target) => !metadata.is_symlink() || fs::read_link(path)? != *target,
+        Node::File { size, digest } => {
+            !metadata.is_file()
+                || metadata.len() != *size
+                || ChecksumAlgorithm::Sha256.digest_file(path)? != *digest
+        }
+    })
+}
+
+/// Compare the archive with the directory, returning the paths that differ in order
+///
+/// Archives of a directory usually start their paths with its name, like `src/main.rs` for
+/// `./src`. When every entry does, that name is left out to match the files below the directory.
+pub fn compare(archive: &Path, dir: &Path) -> Result<Vec<(String, Change)>, io::Error> {
+    if !dir.is_dir() {
+        return Err(io::Error::other(format!(
+            "{} is not a directory",
+            dir.display()
+        )));
+    }
+    let mut entries = read_archive(archive)?;
+    if let Some(root) = fs::canonicalize(dir)?.file_name() {
+        let root = root.to_string_lossy();
+        let prefix = format!("{}/", root);
+        let below_root = |name: &String| *name == root || name.starts_with(&prefix);
+        if !entries.is_empty() && entries.keys().all(below_root) {
+            entries = entries
+                .into_iter()
+                .filter_map(|(name, node)| Some((name.strip_prefix(&prefix)?.to_string(), node)))
+                .collect();
+        }
+    }
+    let mut files = BTreeMap::new();
+    walk(dir, "", &mut files)?;
+
+    let mut changes = Vec::new();
+    for (name, node) in &entries {
+        match files.get(name) {
+            None => changes.push((name.clone(), Change::Removed)),
+            Some(path) if differs(node, path)? => changes.push((name.clone(), Change::Modified)),
+            Some(_) => {}
+        }
+    }
+    for name in files.keys().filter(|name| !entries.contains_key(*name)) {
+        changes.push((name.clone(), Change::Added));
+    }
+    changes.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(changes)
+}
+
+/// Print the paths that differ between the archive and the directory
+/// Fails when there are any, so that scripts can check a backup with the exit code.
+pub fn diff(args: &DiffArgs, output: OutputFormat) -> Result<(), io::Error> {
+    let changes = compare(&args.archive, &args.dir)?;
+    let mut stdout = io::stdout().lock();
+    match output {
+        OutputFormat::Json => {
+            let changes: Vec<String> = changes
+                .iter()
+                .map(|(name, change)| {
+                    format!(r#"{{"path":{},"change":"{}"}}"#, json_string(name), change)
+                })
+                .collect();
+            writeln!(
+                stdout,
+                r#"{{"archive":{},"dir":{},"changes":[{}]}}"#,
+                json_string(&args.archive.to_string_lossy()),
+                json_string(&args.dir.to_string_lossy()),
+                changes.join(",")
+            )?;
+        }
+        OutputFormat::Text => {
+            for (name, change) in &changes {
+                writeln!(stdout, "{} {}", change.marker(), name)?;
+            }
+        }
+    }
+    if !changes.is_empty() {
+        return cmprss_error(&format!(
+            "{} paths differ between {} and {}",
+            changes.len(),
+            args.archive.display(),
+            args.dir.display()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job::get_compressor_from_filename;
+    use assert_fs::prelude::*;
+
+    #[test]
+    fn changes() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let src = working_dir.child("src");
+        src.child("same.txt").write_str("unchanged")?;
+        src.child("edited.txt").write_str("before")?;
+        src.child("sized.txt").write_str("same size")?;
+        src.child("gone/old.txt").write_str("removed later")?;
+        for name in ["backup.tar.gz", "backup.zip"] {
+            let archive = working_dir.child(name);
+            get_compressor_from_filename(archive.path())
+                .unwrap()
+                .compress(
+                    CmprssInput::Path(vec![src.path().to_path_buf()]),
+                    CmprssOutput::Path(archive.path().to_path_buf()),
+                )?;
+            assert_eq!(compare(archive.path(), src.path())?, []);
+        }
+
+        src.child("edited.txt").write_str("after the edit")?;
+        src.child("sized.txt").write_str("same SIZE")?;
+        fs::remove_dir_all(src.child("gone"))?;
+        src.child("new.txt").write_str("added")?;
+        for name in ["backup.tar.gz", "backup.zip"] {
+            let changes = compare(working_dir.child(name).path(), src.path())?;
+            assert_eq!(
+                changes,
+                [
+                    ("edited.txt".to_string(), Change::Modified),
+                    ("gone".to_string(), Change::Removed),
+                    ("gone/old.txt".to_string(), Change::Removed),
+                    ("new.txt".to_string(), Change::Added),
+                    ("sized.txt".to_string(), Change::Modified),
+                ]
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn entry_names() {
+        assert_eq!(entry_name("./src/main.rs"), Some("src/main.rs".to_string()));
+        assert_eq!(entry_name("src/"), Some("src".to_string()));
+        assert_eq!(entry_name("./"), None);
+    }
+}
@@ -0,0 +1,289 @@
+//! Running as `gzip`, `gunzip`, `zcat`, `xz`, `unzstd`... through a symlink, like the tools
+//!
+//! The arguments of those tools are translated to the arguments of the format's command, so
+//! that cmprss can be installed in their place. Files are each compressed next to themselves,
+//! directories only with `-r`, and the inputs are removed by default for the tools that do that.
+
+use std::io;
+use std::path::Path;
+
+/// What a tool does without options
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Compress,
+    Extract,
+    /// Extract to stdout, like zcat
+    Cat,
+}
+
+/// A name cmprss can be run as
+struct DropIn {
+    name: &'static str,
+    /// The format's command
+    format: &'static str,
+    mode: Mode,
+    /// Whether the tool removes its inputs unless given -k, like gzip and xz
+    rm_source: bool,
+}
+
+const fn tool(name: &'static str, format: &'static str, mode: Mode, rm_source: bool) -> DropIn {
+    DropIn {
+        name,
+        format,
+        mode,
+        rm_source,
+    }
+}
+
+const DROP_INS: &[DropIn] = &[
+    tool("gzip", "gzip", Mode::Compress, true),
+    tool("gunzip", "gzip", Mode::Extract, true),
+    tool("zcat", "gzip", Mode::Cat, true),
+    tool("xz", "xz", Mode::Compress, true),
+    tool("unxz", "xz", Mode::Extract, true),
+    tool("xzcat", "xz", Mode::Cat, true),
+    tool("bzip2", "bzip2", Mode::Compress, true),
+    tool("bunzip2", "bzip2", Mode::Extract, true),
+    tool("bzcat", "bzip2", Mode::Cat, true),
+    tool("zstd", "zstd", Mode::Compress, false),
+    tool("unzstd", "zstd", Mode::Extract, false),
+    tool("zstdcat", "zstd", Mode::Cat, false),
+    tool("lz4", "lz4", Mode::Compress, false),
+    tool("unlz4", "lz4", Mode::Extract, false),
+    tool("lz4cat", "lz4", Mode::Cat, false),
+    tool("lzop", "lzop", Mode::Compress, false),
+    tool("brotli", "brotli", Mode::Compress, false),
+    tool("compress", "lzw", Mode::Compress, true),
+    tool("uncompress", "lzw", Mode::Extract, true),
+];
+
+/// Find the tool the binary is run as, from the name of the file without an extension like .exe
+fn find(argv0: &str) -> Option<&'static DropIn> {
+    let name = Path::new(argv0).file_stem()?.to_str()?;
+    DROP_INS.iter().find(|tool| tool.name == name)
+}
+
+/// Translate the arguments when the binary is run as one of the tools, or keep them otherwise
+/// Short options can be grouped like `-dk9`. Long options that aren't the tool's are passed on
+/// as they are, so that the options of cmprss still work, along with their value when
+/// `takes_value` says the option of the format's command has one.
+pub fn translate(
+    argv: Vec<String>,
+    takes_value: impl Fn(&str, &str) -> bool,
+) -> Result<Vec<String>, io::Error> {
+    let Some(tool) = argv.first().and_then(|argv0| find(argv0)) else {
+        return Ok(argv);
+    };
+    let unsupported = |option: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} option {} is not supported by cmprss", tool.name, option),
+        )
+    };
+    let mut options = Vec::new();
+    let mut operands = Vec::new();
+    let mut stdout = tool.mode == Mode::Cat;
+    let mut recursive = false;
+    let mut decompress = tool.mode != Mode::Compress;
+    let mut args = argv.into_iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--" => operands.extend(args.by_ref()),
+            "-" => operands.push(arg),
+            "--decompress" | "--uncompress" => decompress = true,
+            "--compress" => decompress = false,
+            "--stdout" | "--to-stdout" => stdout = true,
+            "--fast" => options.extend(["--level".to_string(), "1".to_string()]),
+            "--best" => options.extend(["--level".to_string(), "9".to_string()]),
+            "--recursive" => recursive = true,
+            long if long.starts_with("--") => {
+                if !long.contains('=') && takes_value(tool.format, &long[2..]) {
+                    let value = args.next().ok_or_else(|| unsupported(long))?;
+                    options.extend([arg, value]);
+                } else {
+                    options.push(arg);
+                }
+            }
+            short if short.starts_with('-') => {
+                let mut flags = short[1..].char_indices().peekable();
+                while let Some((i, flag)) = flags.next() {
+                    match flag {
+                        'd' => decompress = true,
+                        'z' => decompress = false,
+                        'k' => options.push("--keep".to_string()),
+                        'f' => options.push("--force".to_string()),
+                        'q' => options.push("--quiet".to_string()),
+                        'v' => options.push("--verbose".to_string()),
+                        'c' => stdout = true,
+                        'r' => recursive = true,
+                        '0'..='9' => {
+                            let mut level = flag.to_string();
+                            while let Some((_, digit)) = flags.next_if(|(_, c)| c.is_ascii_digit())
+                            {
+                                level.push(digit);
+                            }
+                            options.extend(["--level".to_string(), level]);
+                        }
+                        // Threads are given right after it, or as the next argument
+                        'T' => {
+                            let threads = match &short[i + 2..] {
+                                "" => args.next().ok_or_else(|| unsupported("-T"))?,
+                                threads => threads.to_string(),
+                            };
+                            // 0 is a thread per CPU, which is the default
+                            if threads != "0" {
+                                options.extend(["--threads".to_string(), threads]);
+                            }
+                            break;
+                        }
+                        flag => return Err(unsupported(&format!("-{}", flag))),
+                    }
+                }
+            }
+            _ => operands.push(arg),
+        }
+    }
+
+    // Like the tools, directories are only gone through with -r
+    if !recursive {
+        if let Some(dir) = operands.iter().find(|operand| Path::new(operand).is_dir()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is a directory, give -r to go through its files", dir),
+            ));
+        }
+    }
+
+    let mut translated = vec!["cmprss".to_string(), tool.format.to_string()];
+    // The action is always given, so that files named like archives aren't extracted by gzip
+    translated.push(match decompress {
+        true => "--decompress".to_string(),
+        false => "--compress".to_string(),
+    });
+    // Like the tools, no files or '-' is stdin to stdout, and other files are each written
+    // next to themselves
+    if stdout || operands.is_empty() || operands == ["-"] {
+        translated.push("--stdout".to_string());
+        if operands.is_empty() {
+            operands.push("-".to_string());
+        }
+    } else {
+        // --recursive is what writes each file next to itself, and only goes below the
+        // operands with -r since directories were rejected above otherwise
+        translated.push("--recursive".to_string());
+        if tool.rm_source {
+            translated.push("--rm-source".to_string());
+        }
+    }
+    translated.extend(options);
+    translated.push("--".to_string());
+    translated.extend(operands);
+    Ok(translated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_as(args: &[&str]) -> Result<Vec<String>, io::Error> {
+        let argv = args.iter().map(|arg| arg.to_string()).collect();
+        let takes_value = |_: &str, option: &str| option == "progress";
+        Ok(translate(argv, takes_value)?.into_iter().skip(1).collect())
+    }
+
+    #[test]
+    fn tool_arguments() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(
+            run_as(&["/usr/bin/gzip", "-9k", "a.log", "b.log"])?,
+            [
+                "gzip",
+                "--compress",
+                "--recursive",
+                "--rm-source",
+                "--level",
+                "9",
+                "--keep",
+                "--",
+                "a.log",
+                "b.log"
+            ]
+        );
+        assert_eq!(
+            run_as(&["gunzip", "-f", "a.log.gz"])?,
+            [
+                "gzip",
+                "--decompress",
+                "--recursive",
+                "--rm-source",
+                "--force",
+                "--",
+                "a.log.gz"
+            ]
+        );
+        assert_eq!(
+            run_as(&["zcat.exe"])?,
+            ["gzip", "--decompress", "--stdout", "--", "-"]
+        );
+        assert_eq!(
+            run_as(&["zstd", "-19", "-T4", "-c", "--progress", "off", "big"])?,
+            [
+                "zstd",
+                "--compress",
+                "--stdout",
+                "--level",
+                "19",
+                "--threads",
+                "4",
+                "--progress",
+                "off",
+                "--",
+                "big"
+            ]
+        );
+        assert_eq!(
+            run_as(&["xz", "-T", "0", "-d", "--", "-odd.xz"])?,
+            [
+                "xz",
+                "--decompress",
+                "--recursive",
+                "--rm-source",
+                "--",
+                "-odd.xz"
+            ]
+        );
+        assert_eq!(
+            run_as(&["unzstd", "-d", "-"])?,
+            ["zstd", "--decompress", "--stdout", "--", "-"]
+        );
+        assert_eq!(
+            run_as(&["zcat", "a.gz", "b.gz"])?,
+            ["gzip", "--decompress", "--stdout", "--", "a.gz", "b.gz"]
+        );
+        assert_eq!(
+            run_as(&["gzip", "-c", "a", "b"])?,
+            ["gzip", "--compress", "--stdout", "--", "a", "b"]
+        );
+
+        // Directories are only gone through with -r
+        let dir = assert_fs::TempDir::new()?;
+        let dir = dir.to_str().unwrap();
+        assert!(run_as(&["gzip", dir]).is_err());
+        assert!(run_as(&["zcat", dir]).is_err());
+        assert_eq!(
+            run_as(&["gzip", "-r", dir])?,
+            [
+                "gzip",
+                "--compress",
+                "--recursive",
+                "--rm-source",
+                "--",
+                dir
+            ]
+        );
+
+        // Other names keep their arguments
+        assert_eq!(run_as(&["cmprss", "-c", "a"])?, ["-c", "a"]);
+        assert!(run_as(&["gzip", "-S", ".z", "a"]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,388 @@
+//! Excluding files when archiving directories, and selecting the entries to extract
+
+use clap::Args;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Options for skipping files when archiving
+#[derive(Args, Debug, Clone, Default)]
+pub struct ExcludeArgs {
+    /// Skip files matching a glob pattern when archiving or extracting, like `node_modules`, `target/` or `*.o`.
+    /// Patterns without a '/' match the name of a file or directory anywhere, others match the path in the archive.
+    #[arg(long, value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+
+    /// Read exclude patterns from a file, one per line. Empty lines and lines starting with '#' are ignored.
+    #[arg(long, value_name = "FILE")]
+    pub exclude_from: Vec<PathBuf>,
+
+    /// Only extract the entries matching a glob pattern, like `docs/**/*.md`.
+    /// Patterns match like --exclude, and matching a directory extracts everything in it.
+    #[arg(long, value_name = "PATTERN")]
+    pub include: Vec<String>,
+
+    /// Skip the files ignored by the .gitignore and .ignore files in the directories being
+    /// archived, like build outputs in a source tree
+    #[arg(long)]
+    pub gitignore: bool,
+
+    /// Skip the directories and files of version control systems, like .git, .hg and .svn,
+    /// like `tar --exclude-vcs`
+    #[arg(long)]
+    pub exclude_vcs: bool,
+
+    /// Don't archive the files on other file systems than the directory they are in, like mount
+    /// points, so archiving / leaves out /proc and network mounts
+    #[arg(long)]
+    pub one_file_system: bool,
+}
+
+/// Metadata of version control systems, the same names GNU tar leaves out with --exclude-vcs
+/// `{arch}` is escaped, since braces are alternatives in a glob.
+const VCS_PATTERNS: &[&str] = &[
+    "CVS",
+    ".cvsignore",
+    "RCS",
+    "SCCS",
+    ".svn",
+    ".git",
+    ".gitignore",
+    ".gitattributes",
+    ".gitmodules",
+    ".arch-ids",
+    "[{]arch[}]",
+    "=RELEASE-ID",
+    "=meta-update",
+    "=update",
+    ".bzr",
+    ".bzrignore",
+    ".bzrtags",
+    ".hg",
+    ".hgignore",
+    ".hgtags",
+    "_darcs",
+];
+
+/// Glob patterns matched against entries of an archive
+#[derive(Debug, Clone, Default)]
+struct Patterns {
+    /// Patterns matching the name of a file or directory
+    names: GlobSet,
+    /// Patterns matching the full path inside the archive
+    paths: GlobSet,
+}
+
+impl Patterns {
+    fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Patterns, io::Error> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        for pattern in patterns {
+            // A trailing '/' only marks a directory, which is matched the same way
+            let pattern = pattern.as_ref().trim_end_matches('/');
+            let glob = GlobBuilder::new(pattern.trim_start_matches('/'))
+                .literal_separator(true)
+                .build()
+                .map_err(|e| io::Error::other(format!("invalid pattern: {}", e)))?;
+            if pattern.contains('/') {
+                paths.add(glob);
+            } else {
+                names.add(glob);
+            }
+        }
+        let build = |set: GlobSetBuilder| {
+            set.build()
+                .map_err(|e| io::Error::other(format!("invalid pattern: {}", e)))
+        };
+        Ok(Patterns {
+            names: build(names)?,
+            paths: build(paths)?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.paths.is_empty()
+    }
+
+    fn is_match(&self, name: &Path) -> bool {
+        name.file_name()
+            .is_some_and(|file| self.names.is_match(file))
+            || self.paths.is_match(name)
+    }
+
+    /// Check the path and the directories it is in
+    fn is_match_within(&self, name: &Path) -> bool {
+        name.ancestors()
+            .any(|path| !path.as_os_str().is_empty() && self.is_match(path))
+    }
+}
+
+/// The ignore files of each directory, loaded as the directories are archived
+type IgnoreFiles = Arc<Mutex<HashMap<PathBuf, Gitignore>>>;
+
+/// The patterns for files to leave out of an archive, or entries to leave out when extracting
+#[derive(Debug, Clone, Default)]
+pub struct Exclude {
+    exclude: Patterns,
+    /// The only entries to extract, when any are given
+    include: Option<Patterns>,
+    /// Ignore files found so far, when following them
+    gitignore: Option<IgnoreFiles>,
+    /// Leave out the files on other file systems than their directory
+    one_file_system: bool,
+}
+
+impl Exclude {
+    /// Build the set of patterns from the command line options
+    pub fn new(args: &ExcludeArgs) -> Result<Exclude, io::Error> {
+        let mut patterns = args.exclude.clone();
+        if args.exclude_vcs {
+            patterns.extend(VCS_PATTERNS.iter().map(|pattern| pattern.to_string()));
+        }
+        for path in &args.exclude_from {
+            let contents = fs::read_to_string(path)?;
+            patterns.extend(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(str::to_string),
+            );
+        }
+        let mut exclude = Exclude::from_patterns(&patterns)?;
+        if !args.include.is_empty() {
+            exclude.include = Some(Patterns::new(&args.include)?);
+        }
+        Ok(exclude
+            .gitignore(args.gitignore)
+            .one_file_system(args.one_file_system))
+    }
+
+    /// Build the set from glob patterns
+    pub fn from_patterns<S: AsRef<str>>(patterns: &[S]) -> Result<Exclude, io::Error> {
+        Ok(Exclude {
+            exclude: Patterns::new(patterns)?,
+            include: None,
+            gitignore: None,
+            one_file_system: false,
+        })
+    }
+
+    /// Also leave out the files ignored by .gitignore and .ignore files
+    pub fn gitignore(mut self, gitignore: bool) -> Exclude {
+        self.gitignore = gitignore.then(IgnoreFiles::default);
+        self
+    }
+
+    /// Also leave out the files on other file systems, like mount points
+    pub fn one_file_system(mut self, one_file_system: bool) -> Exclude {
+        self.one_file_system = one_file_system;
+        self
+    }
+
+    /// Only extract the entries matching the glob patterns
+    pub fn include<S: AsRef<str>>(mut self, patterns: &[S]) -> Result<Exclude, io::Error> {
+        self.include = Some(Patterns::new(patterns)?);
+        Ok(self)
+    }
+
+    /// Check if an entry should be left out, given its path inside the archive
+    /// Directories that are excluded are skipped entirely, so only the last component is checked by name.
+    pub fn is_excluded(&self, name: &Path) -> bool {
+        !self.exclude.is_empty() && self.exclude.is_match(name)
+    }
+
+    /// Check if a file below an archived directory should be left out, given its path inside the
+    /// archive and on disk
+    /// Only the ignore files of the archived directory and the directories below it apply, so
+    /// the name tells how far up to look. The closest one matching the file decides, like git.
+    /// Mount points are found by their device differing from the directory they are in.
+    pub fn is_excluded_file(&self, name: &Path, path: &Path) -> bool {
+        if self.is_excluded(name) {
+            return true;
+        }
+        if self.gitignore.is_none() && !self.one_file_system {
+            return false;
+        }
+        let metadata = fs::symlink_metadata(path).ok();
+        if let Some(metadata) = metadata.as_ref().filter(|_| self.one_file_system) {
+            let parent = path
+                .parent()
+                .and_then(|parent| fs::symlink_metadata(parent).ok());
+            if parent.is_some_and(|parent| device(&parent) != device(metadata)) {
+                return true;
+            }
+        }
+        let Some(ignore_files) = &self.gitignore else {
+            return false;
+        };
+        let is_dir = metadata.is_some_and(|metadata| metadata.is_dir());
+        let depth = name.components().count().saturating_sub(1);
+        let mut ignore_files = ignore_files.lock().unwrap();
+        for dir in path.ancestors().skip(1).take(depth) {
+            let ignore_file = ignore_files
+                .entry(dir.to_path_buf())
+                .or_insert_with(|| load_ignore_files(dir));
+            let matched = ignore_file.matched(path, is_dir);
+            if !matched.is_none() {
+                return matched.is_ignore();
+            }
+        }
+        false
+    }
+
+    /// Check if an entry of an archive should be extracted
+    /// Every entry is seen when extracting, so entries inside excluded or included directories
+    /// are matched by those directories.
+    pub fn is_extracted(&self, name: &Path) -> bool {
+        if self.exclude.is_match_within(name) {
+            return false;
+        }
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.is_match_within(name))
+    }
+}
+
+/// The file system a file is on
+#[cfg(unix)]
+fn device(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+/// The file system of a file can't be found through the stable API on other platforms
+#[cfg(not(unix))]
+fn device(_metadata: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// The patterns of the .gitignore and .ignore files of a directory
+/// Patterns of .ignore come later, so they take precedence like they do for ripgrep.
+/// Files that can't be parsed are reported and left out.
+fn load_ignore_files(dir: &Path) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(dir);
+    for name in [".gitignore", ".ignore"] {
+        let file = dir.join(name);
+        if !file.is_file() {
+            continue;
+        }
+        if let Some(e) = builder.add(&file) {
+            crate::warn!("{}: {}", file.display(), e);
+        }
+    }
+    builder.build().unwrap_or_else(|e| {
+        crate::warn!("{}: {}", dir.display(), e);
+        Gitignore::empty()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() -> Result<(), io::Error> {
+        let exclude = Exclude::from_patterns(&["node_modules", "target/", "*.o", "dir/skip.txt"])?;
+        assert!(exclude.is_excluded(Path::new("project/node_modules")));
+        assert!(exclude.is_excluded(Path::new("project/target")));
+        assert!(exclude.is_excluded(Path::new("main.o")));
+        assert!(exclude.is_excluded(Path::new("project/src/main.o")));
+        assert!(exclude.is_excluded(Path::new("dir/skip.txt")));
+        assert!(!exclude.is_excluded(Path::new("other/dir/skip.txt")));
+        assert!(!exclude.is_excluded(Path::new("project/src/main.rs")));
+        assert!(!exclude.is_excluded(Path::new("project/targets")));
+
+        assert!(!Exclude::default().is_excluded(Path::new("anything")));
+        assert!(Exclude::from_patterns(&["[unclosed"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn vcs() -> Result<(), io::Error> {
+        let exclude = Exclude::new(&ExcludeArgs {
+            exclude_vcs: true,
+            ..ExcludeArgs::default()
+        })?;
+        assert!(exclude.is_excluded(Path::new("project/.git")));
+        assert!(exclude.is_excluded(Path::new("project/.gitignore")));
+        assert!(exclude.is_excluded(Path::new("project/lib/.svn")));
+        assert!(exclude.is_excluded(Path::new("project/{arch}")));
+        assert!(!exclude.is_excluded(Path::new("project/arch")));
+        assert!(!exclude.is_excluded(Path::new("project/.github")));
+        assert!(!exclude.is_extracted(Path::new("project/.hg/store/data")));
+        Ok(())
+    }
+
+    #[test]
+    fn extracted_entries() -> Result<(), io::Error> {
+        let exclude = Exclude::from_patterns(&["drafts"])?.include(&["docs/**/*.md", "README"])?;
+        assert!(exclude.is_extracted(Path::new("docs/guide/intro.md")));
+        assert!(exclude.is_extracted(Path::new("docs/index.md")));
+        assert!(exclude.is_extracted(Path::new("README")));
+        assert!(!exclude.is_extracted(Path::new("docs/logo.png")));
+        assert!(!exclude.is_extracted(Path::new("docs")));
+        assert!(!exclude.is_extracted(Path::new("docs/drafts/todo.md")));
+
+        // Everything in a directory that matches is extracted
+        let exclude = Exclude::default().include(&["docs/"])?;
+        assert!(exclude.is_extracted(Path::new("docs/")));
+        assert!(exclude.is_extracted(Path::new("docs/logo.png")));
+        assert!(!exclude.is_extracted(Path::new("src/main.rs")));
+        assert!(Exclude::default().is_extracted(Path::new("anything")));
+        Ok(())
+    }
+
+    #[test]
+    fn gitignore() -> Result<(), Box<dyn std::error::Error>> {
+        use assert_fs::prelude::*;
+
+        let working_dir = assert_fs::TempDir::new()?;
+        let project = working_dir.child("project");
+        project.child(".gitignore").write_str("target/\n*.log\n")?;
+        project.child("docs/.gitignore").write_str("!keep.log\n")?;
+        project.child(".ignore").write_str("notes.txt\n")?;
+        project.child("target").create_dir_all()?;
+        working_dir.child(".gitignore").write_str("*\n")?;
+        let exclude = Exclude::default().gitignore(true);
+        let excluded =
+            |name: &str| exclude.is_excluded_file(Path::new(name), &working_dir.path().join(name));
+        assert!(excluded("project/target"));
+        assert!(excluded("project/debug.log"));
+        assert!(excluded("project/docs/debug.log"));
+        assert!(!excluded("project/docs/keep.log"));
+        assert!(excluded("project/notes.txt"));
+        assert!(!excluded("project/src/main.rs"));
+        // Ignore files above the archived directory don't apply
+        assert!(!excluded("project"));
+
+        assert!(!Exclude::default().is_excluded_file(
+            Path::new("project/debug.log"),
+            &working_dir.path().join("project/debug.log")
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn one_file_system() -> Result<(), Box<dyn std::error::Error>> {
+        use assert_fs::prelude::*;
+
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir.child("dir/file.txt").write_str("data")?;
+        let exclude = Exclude::default().one_file_system(true);
+        let path = working_dir.path().join("dir/file.txt");
+        assert!(!exclude.is_excluded_file(Path::new("dir/file.txt"), &path));
+
+        // /proc is a mount point of its own on Linux
+        let proc = Path::new("/proc");
+        if device(&fs::metadata(proc)?) != device(&fs::metadata("/")?) {
+            assert!(exclude.is_excluded_file(Path::new("root/proc"), proc));
+            assert!(!Exclude::default().is_excluded_file(Path::new("root/proc"), proc));
+        }
+        Ok(())
+    }
+}
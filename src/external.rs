@@ -0,0 +1,145 @@
+//! Fallback to the compression tools installed on the system
+
+use crate::plugin::Plugin;
+use std::path::Path;
+
+/// A format handled by a system tool
+struct Tool {
+    name: &'static str,
+    extension: &'static str,
+    archive: bool,
+    compress: &'static [&'static str],
+    extract: &'static [&'static str],
+}
+
+/// The system tools cmprss knows how to drive, with the same placeholders as plugins
+const TOOLS: &[Tool] = &[
+    Tool {
+        name: "tar",
+        extension: "tar",
+        archive: true,
+        compress: &["tar", "-cf", "-", "{inputs}"],
+        extract: &["tar", "-xf", "-", "-C", "{output}"],
+    },
+    Tool {
+        name: "gzip",
+        extension: "gz",
+        archive: false,
+        compress: &["gzip", "-c"],
+        extract: &["gzip", "-dc"],
+    },
+    Tool {
+        name: "xz",
+        extension: "xz",
+        archive: false,
+        compress: &["xz", "-c"],
+        extract: &["xz", "-dc"],
+    },
+    Tool {
+        name: "bzip2",
+        extension: "bz2",
+        archive: false,
+        compress: &["bzip2", "-c"],
+        extract: &["bzip2", "-dc"],
+    },
+    Tool {
+        name: "zstd",
+        extension: "zst",
+        archive: false,
+        compress: &["zstd", "-q", "-c"],
+        extract: &["zstd", "-q", "-dc"],
+    },
+    Tool {
+        name: "lz4",
+        extension: "lz4",
+        archive: false,
+        compress: &["lz4", "-q", "-c"],
+        extract: &["lz4", "-q", "-dc"],
+    },
+    Tool {
+        name: "brotli",
+        extension: "br",
+        archive: false,
+        compress: &["brotli", "-c"],
+        extract: &["brotli", "-dc"],
+    },
+    Tool {
+        name: "lzop",
+        extension: "lzo",
+        archive: false,
+        compress: &["lzop", "-c"],
+        extract: &["lzop", "-dc"],
+    },
+    Tool {
+        name: "lzw",
+        extension: "Z",
+        archive: false,
+        compress: &["compress", "-c"],
+        extract: &["uncompress", "-c"],
+    },
+    Tool {
+        name: "zip",
+        extension: "zip",
+        archive: true,
+        compress: &["zip", "-qr", "-", "{inputs}"],
+        extract: &["unzip", "-q", "{inputs}", "-d", "{output}"],
+    },
+    Tool {
+        name: "rar",
+        extension: "rar",
+        archive: true,
+        compress: &["rar", "a", "-idq", "{output}", "{inputs}"],
+        extract: &["unrar", "x", "-idq", "-o+", "{inputs}", "{output}/"],
+    },
+    Tool {
+        name: "7z",
+        extension: "7z",
+        archive: true,
+        compress: &["7z", "a", "-bso0", "{output}", "{inputs}"],
+        extract: &["7z", "x", "-bso0", "-y", "-o{output}", "{inputs}"],
+    },
+];
+
+impl Tool {
+    fn plugin(&self) -> Plugin {
+        Plugin {
+            name: self.name.to_string(),
+            extension: self.extension.to_string(),
+            archive: self.archive,
+            compress: self.compress.iter().map(|arg| arg.to_string()).collect(),
+            extract: self.extract.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+}
+
+/// Get the system tool for a format by name
+pub fn find(name: &str) -> Option<Plugin> {
+    TOOLS
+        .iter()
+        .find(|tool| tool.name == name)
+        .map(Tool::plugin)
+}
+
+/// Get the system tool for a file from its extension
+pub fn for_filename(filename: &Path) -> Option<Plugin> {
+    let extension = filename.extension()?;
+    TOOLS
+        .iter()
+        .find(|tool| extension == tool.extension)
+        .map(Tool::plugin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        assert_eq!(find("zstd").unwrap().extension, "zst");
+        assert!(find("zpaq").is_none());
+        assert_eq!(for_filename(Path::new("a/b.tar.zst")).unwrap().name, "zstd");
+        assert!(for_filename(Path::new("backup.7z")).unwrap().archive);
+        assert!(for_filename(Path::new("file.txt")).is_none());
+        assert!(for_filename(Path::new("file")).is_none());
+    }
+}
@@ -0,0 +1,322 @@
+//! Search the files inside archives and compressed files, without extracting them
+
+use crate::convert::{decode, Archive, Layers};
+use crate::exclude::{Exclude, ExcludeArgs};
+use crate::utils::{json_string, OutputFormat};
+use clap::Args;
+use regex::bytes::{Regex, RegexBuilder};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+#[derive(Args, Debug)]
+pub struct GrepArgs {
+    /// Regular expression to search for
+    pub pattern: String,
+
+    /// Archives or compressed files to search
+    #[arg(required = true)]
+    pub archives: Vec<PathBuf>,
+
+    /// Match regardless of case
+    #[arg(short, long)]
+    pub ignore_case: bool,
+
+    /// Search for the pattern as a plain string instead of a regular expression
+    #[arg(short = 'F', long)]
+    pub fixed_strings: bool,
+
+    /// Print the line number of each match
+    #[arg(short = 'n', long)]
+    pub line_number: bool,
+
+    /// Only print the paths of the entries that match
+    #[arg(short = 'l', long)]
+    pub files_with_matches: bool,
+
+    #[clap(flatten)]
+    pub exclude_args: ExcludeArgs,
+}
+
+/// Searches the entries of the archives, printing the matching lines
+struct Searcher<'a, W: Write> {
+    args: &'a GrepArgs,
+    regex: Regex,
+    exclude: Exclude,
+    output: OutputFormat,
+    out: W,
+    /// Number of entries with a match
+    matched: usize,
+}
+
+impl<W: Write> Searcher<'_, W> {
+    /// Print a match in an entry of the archive
+    /// The matching line is left out with -l and for binary entries.
+    fn print(
+        &mut self,
+        archive: &Path,
+        entry: &str,
+        binary: bool,
+        line: Option<(usize, &[u8])>,
+    ) -> Result<(), io::Error> {
+        if self.output == OutputFormat::Json {
+            let (number, text) = match line {
+                Some((number, text)) => (
+                    number.to_string(),
+                    json_string(&String::from_utf8_lossy(text)),
+                ),
+                None => ("null".to_string(), "null".to_string()),
+            };
+            return writeln!(
+                self.out,
+                r#"{{"archive":{},"path":{},"binary":{},"line_number":{},"line":{}}}"#,
+                json_string(&archive.to_string_lossy()),
+                json_string(entry),
+                binary,
+                number,
+                text
+            );
+        }
+        // Entries are prefixed with the archive when searching several
+        let label = match self.args.archives.len() {
+            1 => entry.to_string(),
+            _ => format!("{}:{}", archive.display(), entry),
+        };
+        match line {
+            Some((number, text)) => {
+                match self.args.line_number {
+                    true => write!(self.out, "{}:{}:", label, number)?,
+                    false => write!(self.out, "{}:", label)?,
+                }
+                self.out.write_all(text)?;
+                self.out.write_all(b"\n")
+            }
+            None if binary && !self.args.files_with_matches => {
+                writeln!(self.out, "{}: binary file matches", label)
+            }
+            None => writeln!(self.out, "{}", label),
+        }
+    }
+
+    /// Search a single entry of the archive
+    /// Like grep, entries with a NUL byte at the start are binary and only reported once.
+    fn search(&mut self, archive: &Path, entry: &str, reader: impl Read) -> Result<(), io::Error> {
+        let mut reader = BufReader::new(reader);
+        let binary = reader.fill_buf()?.contains(&0);
+        let mut line = Vec::new();
+        let mut number = 0;
+        let mut found = false;
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            number += 1;
+            let text = line.strip_suffix(b"\n").unwrap_or(&line);
+            let text = text.strip_suffix(b"\r").unwrap_or(text);
+            if !self.regex.is_match(text) {
+                continue;
+            }
+            found = true;
+            if self.args.files_with_matches || binary {
+                self.print(archive, entry, binary, None)?;
+                break;
+            }
+            self.print(archive, entry, binary, Some((number, text)))?;
+        }
+        if found {
+            self.matched += 1;
+        }
+        Ok(())
+    }
+
+    /// Search the regular files of a tar archive
+    fn search_tar(&mut self, archive_path: &Path, reader: impl Read) -> Result<(), io::Error> {
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let path = entry.path()?.into_owned();
+            if !entry.header().entry_type().is_file() || !self.exclude.is_extracted(&path) {
+                continue;
+            }
+            self.search(archive_path, &path.display().to_string(), entry)?;
+        }
+        Ok(())
+    }
+
+    /// Search the regular files of a zip archive
+    fn search_zip(&mut self, path: &Path) -> Result<(), io::Error> {
+        let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+        for i in 0..archive.len() {
+            let file = archive.by_index(i)?;
+            let name = file.name().to_string();
+            if file.is_dir() || file.is_symlink() || !self.exclude.is_extracted(Path::new(&name)) {
+                continue;
+            }
+            self.search(path, &name, file)?;
+        }
+        Ok(())
+    }
+
+    /// Search an archive, or the contents of a compressed file as a single entry
+    fn search_archive(&mut self, path: &Path) -> Result<(), io::Error> {
+        let layers = Layers::of(path)?;
+        if layers.archive == Some(Archive::Zip) {
+            return self.search_zip(path);
+        }
+        let name = match &layers.stream {
+            Some(stream) => stream.default_extracted_filename(path),
+            None => path.display().to_string(),
+        };
+        let (reader, writer) = io::pipe()?;
+        let input = path.to_path_buf();
+        let source =
+            thread::Builder::new().spawn(move || decode(layers.stream, input, Box::new(writer)))?;
+        let result = match layers.archive {
+            Some(_) => self.search_tar(path, reader),
+            None => self.search(path, &name, reader),
+        };
+        let source = source
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("decompressing thread panicked")));
+        match (result, source) {
+            // Searching can stop before the end, like after the first match with -l
+            (Ok(()), Err(e)) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            (_, Err(e)) if e.kind() != io::ErrorKind::BrokenPipe => Err(e),
+            (result, _) => result,
+        }
+    }
+}
+
+/// Print the lines matching the pattern in the files inside the archives, prefixed with the
+/// path of the entry
+/// Fails when nothing matches, like grep.
+pub fn grep(args: &GrepArgs, output: OutputFormat) -> Result<(), io::Error> {
+    let pattern = match args.fixed_strings {
+        true => regex::escape(&args.pattern),
+        false => args.pattern.clone(),
+    };
+    let regex = RegexBuilder::new(&pattern)
+        .case_insensitive(args.ignore_case)
+        .build()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut searcher = Searcher {
+        args,
+        regex,
+        exclude: Exclude::new(&args.exclude_args)?,
+        output,
+        out: io::stdout().lock(),
+        matched: 0,
+    };
+    for archive in &args.archives {
+        searcher.search_archive(archive)?;
+    }
+    if searcher.matched == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("nothing matches {}", args.pattern),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job::get_compressor_from_filename;
+    use crate::utils::*;
+    use assert_fs::prelude::*;
+
+    fn grep_args(pattern: &str, archives: Vec<PathBuf>) -> GrepArgs {
+        GrepArgs {
+            pattern: pattern.to_string(),
+            archives,
+            ignore_case: false,
+            fixed_strings: false,
+            line_number: true,
+            files_with_matches: false,
+            exclude_args: ExcludeArgs::default(),
+        }
+    }
+
+    fn search(args: &GrepArgs, output: OutputFormat) -> Result<String, io::Error> {
+        let mut searcher = Searcher {
+            args,
+            regex: Regex::new(&args.pattern).unwrap(),
+            exclude: Exclude::new(&args.exclude_args)?,
+            output,
+            out: Vec::new(),
+            matched: 0,
+        };
+        for archive in &args.archives {
+            searcher.search_archive(archive)?;
+        }
+        Ok(String::from_utf8(searcher.out).unwrap())
+    }
+
+    #[test]
+    fn matches() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let config = working_dir.child("etc");
+        config
+            .child("app.conf")
+            .write_str("name = app\nport = 8080\n")?;
+        config.child("db.conf").write_str("port = 5432\r\n")?;
+        config.child("blob.bin").write_binary(b"\0port")?;
+        let mut archives = Vec::new();
+        for name in ["etc.tar.gz", "etc.zip"] {
+            let archive = working_dir.child(name);
+            get_compressor_from_filename(archive.path())
+                .unwrap()
+                .compress(
+                    CmprssInput::Path(vec![config.path().to_path_buf()]),
+                    CmprssOutput::Path(archive.path().to_path_buf()),
+                )?;
+            let args = grep_args("port", vec![archive.to_path_buf()]);
+            let mut found: Vec<_> = search(&args, OutputFormat::Text)?
+                .lines()
+                .map(str::to_string)
+                .collect();
+            found.sort();
+            assert_eq!(
+                found,
+                [
+                    "etc/app.conf:2:port = 8080",
+                    "etc/blob.bin: binary file matches",
+                    "etc/db.conf:1:port = 5432",
+                ]
+            );
+            let json = search(&args, OutputFormat::Json)?;
+            assert!(json.contains(&format!(
+                r#"{{"archive":{},"path":"etc/app.conf","binary":false,"line_number":2,"line":"port = 8080"}}"#,
+                json_string(&archive.path().to_string_lossy())
+            )));
+            assert!(json.contains(r#""path":"etc/blob.bin","binary":true,"line_number":null"#));
+            let mut args = args;
+            args.files_with_matches = true;
+            let mut found: Vec<_> = search(&args, OutputFormat::Text)?
+                .lines()
+                .map(str::to_string)
+                .collect();
+            found.sort();
+            assert_eq!(found, ["etc/app.conf", "etc/blob.bin", "etc/db.conf"]);
+            archives.push(archive.to_path_buf());
+        }
+
+        let log = working_dir.child("app.log.gz");
+        let mut compressed = Vec::new();
+        crate::gzip::Gzip::default().compress_stream(&mut &b"ok\nfailed\n"[..], &mut compressed)?;
+        log.write_binary(&compressed)?;
+        archives.push(log.to_path_buf());
+        let mut args = grep_args("fail|8080", archives);
+        args.exclude_args.exclude = vec!["db.conf".to_string()];
+        args.line_number = false;
+        let found = search(&args, OutputFormat::Text)?;
+        assert!(found.contains("etc.tar.gz:etc/app.conf:port = 8080\n"));
+        assert!(found.contains("etc.zip:etc/app.conf:port = 8080\n"));
+        assert!(found.ends_with("app.log.gz:app.log:failed\n"));
+
+        Ok(())
+    }
+}
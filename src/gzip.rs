@@ -1,27 +1,63 @@
-use crate::utils::*;
+//! gzip compression backend
+
+use crate::{
+    limits::OutputLimits,
+    progress::{
+        copy_with_progress, progress_bar, CountingWriter, ProgressArgs, ProgressDisplay,
+        ProgressHooks, ProgressObserver,
+    },
+    utils::*,
+};
 use clap::Args;
-use flate2::write::GzEncoder;
-use flate2::{read::GzDecoder, Compression};
+use flate2::write::{GzEncoder, MultiGzDecoder};
+use flate2::{read, Compression, GzBuilder};
 use std::fs::File;
 use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
 
 #[derive(Args, Debug)]
 pub struct GzipArgs {
     #[clap(flatten)]
     pub common_args: CommonArgs,
 
+    #[clap(flatten)]
+    pub progress_args: ProgressArgs,
+
     #[clap(flatten)]
     pub level_args: LevelArgs,
+
+    /// Leave the original file name and modification time out of the header
+    #[arg(long, conflicts_with = "name")]
+    pub no_name: bool,
+
+    /// Store the original file name and modification time in the header, which is the default.
+    /// When extracting, name the output with the stored name and restore the stored time.
+    #[arg(long)]
+    pub name: bool,
 }
 
 pub struct Gzip {
     pub compression_level: u32,
+    /// Store the file name and modification time in the header
+    pub store_name: bool,
+    /// Name the extracted file with the stored name, and restore the stored modification time
+    pub restore_name: bool,
+    pub reproducible: Option<Reproducible>,
+    pub progress_args: ProgressArgs,
+    pub hooks: ProgressHooks,
 }
 
 impl Default for Gzip {
     fn default() -> Self {
         Gzip {
             compression_level: 6,
+            store_name: true,
+            restore_name: false,
+            reproducible: None,
+            progress_args: ProgressArgs::default(),
+            hooks: ProgressHooks::default(),
         }
     }
 }
@@ -30,6 +66,11 @@ impl Gzip {
     pub fn new(args: &GzipArgs) -> Gzip {
         Gzip {
             compression_level: args.level_args.level.level,
+            store_name: !args.no_name,
+            restore_name: args.name,
+            reproducible: None,
+            progress_args: args.progress_args,
+            hooks: ProgressHooks::default(),
         }
     }
 }
@@ -45,9 +86,46 @@ impl Compressor for Gzip {
         "gzip"
     }
 
+    fn is_stream(&self) -> bool {
+        true
+    }
+
+    fn set_limits(&mut self, limits: OutputLimits) {
+        self.hooks.limits = limits;
+    }
+
+    fn set_observer(&mut self, observer: Arc<dyn ProgressObserver>) {
+        self.hooks.observer = Some(observer);
+    }
+
+    fn set_reproducible(&mut self, reproducible: Reproducible) {
+        self.reproducible = Some(reproducible);
+    }
+
+    fn set_level(&mut self, level: i32) {
+        self.compression_level = level as u32;
+    }
+
+    fn level(&self) -> Option<i32> {
+        Some(self.compression_level as i32)
+    }
+
+    fn set_progress(&mut self, progress: ProgressDisplay) {
+        self.progress_args.progress = progress;
+    }
+
+    fn progress(&self) -> ProgressDisplay {
+        self.progress_args.progress
+    }
+
     /// Generate a default extracted filename
     /// gzip does not support extracting to a directory, so we return a default filename
-    fn default_extracted_filename(&self, in_path: &std::path::Path) -> String {
+    fn default_extracted_filename(&self, in_path: &Path) -> String {
+        if self.restore_name {
+            if let Some(name) = stored_name(in_path) {
+                return name;
+            }
+        }
         // If the file has no extension, return a default filename
         if in_path.extension().is_none() {
             return "archive".to_string();
@@ -72,46 +150,123 @@ impl Compressor for Gzip {
                 }
             }
         }
-        let mut input_stream = match input {
-            CmprssInput::Path(paths) => {
-                if paths.len() > 1 {
-                    return cmprss_error("only 1 file can be compressed at a time");
-                }
-                Box::new(File::open(paths[0].as_path())?)
-            }
-            CmprssInput::Pipe(pipe) => Box::new(pipe) as Box<dyn Read + Send>,
-        };
-        let output_stream = match output {
-            CmprssOutput::Path(path) => Box::new(File::create(path)?),
-            CmprssOutput::Pipe(pipe) => Box::new(pipe) as Box<dyn Write + Send>,
-        };
-
-        let mut encoder = GzEncoder::new(output_stream, Compression::new(self.compression_level));
-        std::io::copy(&mut input_stream, &mut encoder)?;
+        let header = self.header(&input)?;
+        let (mut input_stream, file_size) = input.into_stream()?;
+        let bar = progress_bar(file_size, self.progress_args.progress, &output, &self.hooks);
+        let output_stream = output.into_stream()?;
+        let mut encoder = header.write(
+            CountingWriter::new(output_stream),
+            Compression::new(self.compression_level),
+        );
+        copy_with_progress(
+            &mut input_stream,
+            &mut encoder,
+            self.progress_args.chunk_size.size_in_bytes,
+            &self.hooks,
+            bar.as_ref(),
+            |e| e.get_ref().count(),
+        )?;
         encoder.finish()?;
         Ok(())
     }
 
     /// Extract a gzip archive
     fn extract(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
-        let input_stream = match input {
-            CmprssInput::Path(paths) => {
-                if paths.len() > 1 {
-                    return cmprss_error("only 1 file can be extracted at a time");
-                }
-                Box::new(File::open(paths[0].as_path())?)
-            }
-            CmprssInput::Pipe(pipe) => Box::new(pipe) as Box<dyn Read + Send>,
-        };
-        let mut output_stream = match output {
-            CmprssOutput::Path(path) => Box::new(File::create(path)?),
-            CmprssOutput::Pipe(pipe) => Box::new(pipe) as Box<dyn Write + Send>,
+        let (mut input_stream, file_size) = input.into_stream()?;
+        let bar = progress_bar(file_size, self.progress_args.progress, &output, &self.hooks);
+        let out_path = match &output {
+            CmprssOutput::Path(path) if self.restore_name => Some(path.clone()),
+            _ => None,
         };
-
-        let mut decoder = GzDecoder::new(input_stream);
-        std::io::copy(&mut decoder, &mut output_stream)?;
+        let output_stream = self.hooks.limits.writer(output.into_stream()?, file_size);
+        // Like gunzip, every member of concatenated gzip files is extracted
+        let mut decoder = MultiGzDecoder::new(CountingWriter::new(output_stream));
+        copy_with_progress(
+            &mut input_stream,
+            &mut decoder,
+            self.progress_args.chunk_size.size_in_bytes,
+            &self.hooks,
+            bar.as_ref(),
+            |d| d.get_ref().count(),
+        )?;
+        let mtime = decoder.header().map_or(0, |header| header.mtime());
+        decoder.finish()?;
+        if let Some(path) = out_path.filter(|_| mtime != 0) {
+            File::options()
+                .write(true)
+                .open(path)?
+                .set_modified(UNIX_EPOCH + Duration::from_secs(mtime.into()))?;
+        }
         Ok(())
     }
+
+    /// Wrap a writer with a gzip encoder
+    fn wrap_writer<'a>(
+        &self,
+        writer: Box<dyn Write + Send + 'a>,
+    ) -> Result<Box<dyn Write + Send + 'a>, io::Error> {
+        Ok(Box::new(GzEncoder::new(
+            writer,
+            Compression::new(self.compression_level),
+        )))
+    }
+
+    /// Wrap a reader with a gzip decoder
+    fn wrap_reader<'a>(
+        &self,
+        reader: Box<dyn Read + Send + 'a>,
+    ) -> Result<Box<dyn Read + Send + 'a>, io::Error> {
+        Ok(Box::new(read::MultiGzDecoder::new(reader)))
+    }
+
+    /// Compress a stream, finishing the gzip stream explicitly to catch errors
+    fn compress_stream(
+        &self,
+        reader: &mut (dyn Read + Send),
+        writer: &mut (dyn Write + Send),
+    ) -> Result<(), io::Error> {
+        let mut encoder = GzEncoder::new(writer, Compression::new(self.compression_level));
+        io::copy(reader, &mut encoder)?;
+        encoder.finish()?.flush()
+    }
+}
+
+impl Gzip {
+    /// The header for compressing the input, with its name and modification time if they're stored
+    fn header(&self, input: &CmprssInput) -> Result<GzBuilder, io::Error> {
+        let mut header = GzBuilder::new();
+        if !self.store_name {
+            return Ok(header);
+        }
+        let path = match input {
+            CmprssInput::Path(paths) if paths.len() == 1 => Some(paths[0].as_path()),
+            _ => None,
+        };
+        // Like gzip, only the file name is stored
+        if let Some(name) = path
+            .or(input.stream_name().map(Path::new))
+            .and_then(Path::file_name)
+        {
+            header = header.filename(name.to_string_lossy().as_bytes());
+        }
+        if let Some(path) = path {
+            let mtime = modified_secs(&std::fs::metadata(path)?);
+            let mtime = match self.reproducible {
+                Some(reproducible) => reproducible.clamp(mtime),
+                None => mtime,
+            };
+            header = header.mtime(mtime.min(u32::MAX.into()) as u32);
+        }
+        Ok(header)
+    }
+}
+
+/// The file name stored in the header of a gzip file, without any directories
+fn stored_name(path: &Path) -> Option<String> {
+    let decoder = read::GzDecoder::new(File::open(path).ok()?);
+    let name = decoder.header()?.filename()?;
+    let name = Path::new(std::str::from_utf8(name).ok()?).file_name()?;
+    Some(name.to_str()?.to_string())
 }
 
 #[cfg(test)]
@@ -148,4 +303,112 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn stream_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Gzip::default();
+        let data = b"garbage data for testing";
+
+        let mut compressed = Vec::new();
+        compressor.compress_stream(&mut &data[..], &mut compressed)?;
+        assert_ne!(compressed, data);
+
+        let mut extracted = Vec::new();
+        compressor
+            .wrap_reader(Box::new(&compressed[..]))?
+            .read_to_end(&mut extracted)?;
+        assert_eq!(extracted, data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stored_name_and_mtime() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("original.txt");
+        file.write_str("garbage data for testing")?;
+        std::fs::File::options()
+            .write(true)
+            .open(file.path())?
+            .set_modified(UNIX_EPOCH + Duration::from_secs(1_000_000))?;
+        let archive = working_dir.child("renamed.gz");
+        Gzip::default().compress(
+            CmprssInput::Path(vec![file.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+
+        // The default name comes from the archive, the stored name is only used when restoring
+        assert_eq!(
+            Gzip::default().default_extracted_filename(archive.path()),
+            "renamed"
+        );
+        let compressor = Gzip {
+            restore_name: true,
+            ..Gzip::default()
+        };
+        assert_eq!(
+            compressor.default_extracted_filename(archive.path()),
+            "original.txt"
+        );
+
+        let extracted = working_dir.child("extracted.txt");
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(extracted.path().to_path_buf()),
+        )?;
+        extracted.assert(predicate::path::eq_file(file.path()));
+        assert_eq!(
+            modified_secs(&std::fs::metadata(extracted.path())?),
+            1_000_000
+        );
+
+        // Neither is stored without a name, and the time is clamped when reproducible
+        let header = Gzip {
+            store_name: false,
+            ..Gzip::default()
+        }
+        .header(&CmprssInput::Path(vec![file.path().to_path_buf()]))?;
+        let mut compressed = Vec::new();
+        header
+            .write(&mut compressed, Compression::default())
+            .finish()?;
+        assert_eq!(compressed[3..8], [0, 0, 0, 0, 0]);
+
+        let header = Gzip {
+            reproducible: Some(Reproducible { mtime: 100 }),
+            ..Gzip::default()
+        }
+        .header(&CmprssInput::Path(vec![file.path().to_path_buf()]))?;
+        let mut compressed = Vec::new();
+        header
+            .write(&mut compressed, Compression::default())
+            .finish()?;
+        assert_eq!(compressed[4..8], 100u32.to_le_bytes());
+        Ok(())
+    }
+
+    /// Every member of concatenated files is extracted, like `cat a.gz b.gz | gunzip`
+    #[test]
+    fn concatenated() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Gzip::default();
+        let mut compressed = Vec::new();
+        compressor.compress_stream(&mut &b"first "[..], &mut compressed)?;
+        compressor.compress_stream(&mut &b"second"[..], &mut compressed)?;
+
+        let mut extracted = Vec::new();
+        compressor
+            .wrap_reader(Box::new(&compressed[..]))?
+            .read_to_end(&mut extracted)?;
+        assert_eq!(extracted, b"first second");
+
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("archive.gz");
+        archive.write_binary(&compressed)?;
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(working_dir.child("test.txt").path().to_path_buf()),
+        )?;
+        working_dir.child("test.txt").assert("first second");
+        Ok(())
+    }
 }
@@ -0,0 +1,265 @@
+//! HTTP(S) URL inputs
+
+use crate::utils::*;
+use clap::Args;
+use std::io;
+#[cfg(feature = "http")]
+use std::io::Read;
+use std::path::PathBuf;
+#[cfg(feature = "http")]
+use std::time::Duration;
+
+/// Check if an input string is an HTTP(S) URL rather than a local path
+pub fn is_url(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
+/// Get the filename from the path component of a URL
+/// This is only used to infer the format and output names, so it falls back to a default name
+pub fn url_filename(url: &str) -> PathBuf {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let path = path.split_once("://").map_or(path, |(_, rest)| rest);
+    // The first segment is the host
+    match path.split_once('/') {
+        Some((_, path)) => match path.rsplit('/').find(|s| !s.is_empty()) {
+            Some(name) => PathBuf::from(name),
+            None => PathBuf::from("archive"),
+        },
+        None => PathBuf::from("archive"),
+    }
+}
+
+/// Options for downloading URL inputs
+#[derive(Args, Debug, Clone)]
+pub struct HttpArgs {
+    /// Number of times in a row to retry a failed download.
+    /// Retries resume from where the download stopped if the server supports it, and the
+    /// count starts again once a retry gets more of the download.
+    #[arg(long, default_value = "3")]
+    pub retries: u32,
+
+    /// Timeout in seconds for connecting and for each read of a download.
+    #[arg(long, default_value = "30")]
+    pub timeout: u64,
+
+    /// Proxy to use for downloads, e.g. http://proxy:8080.
+    /// Defaults to the proxy in the environment (HTTPS_PROXY, HTTP_PROXY, ALL_PROXY).
+    #[arg(long)]
+    pub proxy: Option<String>,
+}
+
+impl Default for HttpArgs {
+    fn default() -> Self {
+        HttpArgs {
+            retries: 3,
+            timeout: 30,
+            proxy: None,
+        }
+    }
+}
+
+/// Start downloading a URL, returning a stream of the body
+#[cfg(feature = "http")]
+pub fn open_url(url: &str, args: &HttpArgs) -> Result<CmprssRead, io::Error> {
+    let timeout = Duration::from_secs(args.timeout);
+    let mut agent = ureq::AgentBuilder::new()
+        .timeout_connect(timeout)
+        .timeout_read(timeout)
+        .try_proxy_from_env(true);
+    if let Some(proxy) = &args.proxy {
+        agent = agent.proxy(ureq::Proxy::new(proxy).map_err(io::Error::other)?);
+    }
+    let mut download = Download {
+        agent: agent.build(),
+        url: url.to_string(),
+        body: None,
+        position: 0,
+        size: None,
+        delimited: false,
+        retries: args.retries,
+        max_retries: args.retries,
+    };
+    download.connect()?;
+    let size = download.size;
+    Ok(CmprssRead {
+        reader: Box::new(download),
+        size,
+        name: None,
+    })
+}
+
+#[cfg(not(feature = "http"))]
+pub fn open_url(_url: &str, _args: &HttpArgs) -> Result<CmprssRead, io::Error> {
+    Err(io::Error::other(
+        "cmprss was built without support for URL inputs, enable the 'http' feature",
+    ))
+}
+
+/// A download that reconnects when the connection fails
+/// Reconnects use Range requests to resume from the current position
+#[cfg(feature = "http")]
+struct Download {
+    agent: ureq::Agent,
+    url: String,
+    /// Body of the current response
+    body: Option<Box<dyn Read + Send + Sync>>,
+    /// Number of bytes of the body already read
+    position: u64,
+    /// Total size of the body, if known
+    size: Option<u64>,
+    /// Whether the end of the current response is marked, by a Content-Length or by chunked
+    /// encoding, rather than by the server closing the connection
+    delimited: bool,
+    /// Number of retries remaining
+    retries: u32,
+    /// Number of retries after each failure that was followed by progress
+    max_retries: u32,
+}
+
+#[cfg(feature = "http")]
+impl Download {
+    /// Send a request for the rest of the body, retrying on failures
+    fn connect(&mut self) -> Result<(), io::Error> {
+        loop {
+            match self.request() {
+                Ok(()) => return Ok(()),
+                Err(e) => self.retry(e)?,
+            }
+        }
+    }
+
+    /// Use up a retry and wait before the next attempt, or return the error if none are left
+    fn retry(&mut self, error: io::Error) -> Result<(), io::Error> {
+        if self.retries == 0 {
+            return Err(error);
+        }
+        self.retries -= 1;
+        self.body = None;
+        std::thread::sleep(Duration::from_secs(1));
+        Ok(())
+    }
+
+    /// Send a single request, starting from the current position
+    fn request(&mut self) -> Result<(), io::Error> {
+        let mut request = self.agent.get(&self.url);
+        if self.position > 0 {
+            request = request.set("Range", &format!("bytes={}-", self.position));
+        }
+        let response = match request.call() {
+            Ok(response) => response,
+            // Client errors will not succeed on a retry
+            Err(ureq::Error::Status(code, _)) if (400..500).contains(&code) => {
+                return Err(io::Error::other(format!(
+                    "download failed with status {}",
+                    code
+                )));
+            }
+            Err(e) => return Err(io::Error::other(e)),
+        };
+        let partial = response.status() == 206;
+        let length = response
+            .header("Content-Length")
+            .and_then(|len| len.parse::<u64>().ok());
+        // HTTP/1.0 has no chunked encoding, so it ends with the connection like ureq reads it
+        let chunked =
+            response.header("Transfer-Encoding").is_some() && response.http_version() != "HTTP/1.0";
+        self.delimited = chunked || length.is_some();
+        // The total size of a partial response is after the '/' of 'bytes 100-199/1000'
+        let total = response
+            .header("Content-Range")
+            .and_then(|range| range.rsplit_once('/'))
+            .and_then(|(_, total)| total.parse::<u64>().ok());
+        let mut body = response.into_reader();
+        if self.position == 0 || !partial {
+            self.size = length;
+        }
+        if partial && self.size.is_none() {
+            self.size = total;
+        }
+        if self.position > 0 && !partial {
+            // The server ignored the range, so skip what we've already read
+            let skipped = io::copy(&mut (&mut body).take(self.position), &mut io::sink())?;
+            if skipped < self.position {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "download ended early",
+                ));
+            }
+        }
+        self.body = Some(body);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "http")]
+impl Read for Download {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.body.is_none() {
+                self.connect()?;
+            }
+            let result = self.body.as_mut().unwrap().read(buf);
+            match result {
+                Ok(0) if !buf.is_empty() && self.size.is_some_and(|s| self.position < s) => {
+                    self.retry(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "download ended early",
+                    ))?;
+                }
+                // A closed connection is the only end of the body, which may have been cut off
+                Ok(0) if !buf.is_empty() && self.size.is_none() && !self.delimited => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "download ended without the server giving its length, so it may be incomplete",
+                    ));
+                }
+                Ok(n) => {
+                    if n > 0 {
+                        self.retries = self.max_retries;
+                    }
+                    self.position += n as u64;
+                    return Ok(n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => self.retry(e)?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_detection() {
+        assert!(is_url("http://example.com/file.tar"));
+        assert!(is_url("https://example.com/file.tar"));
+        assert!(!is_url("file.tar"));
+        assert!(!is_url("ftp://example.com/file.tar"));
+    }
+
+    #[test]
+    fn url_filenames() {
+        assert_eq!(
+            url_filename("https://example.com/logs.tar.gz"),
+            PathBuf::from("logs.tar.gz")
+        );
+        assert_eq!(
+            url_filename("https://example.com/a/b/file.xz?token=abc#frag"),
+            PathBuf::from("file.xz")
+        );
+        assert_eq!(
+            url_filename("https://example.com/dir/"),
+            PathBuf::from("dir")
+        );
+        assert_eq!(
+            url_filename("https://example.com"),
+            PathBuf::from("archive")
+        );
+        assert_eq!(
+            url_filename("https://example.com/"),
+            PathBuf::from("archive")
+        );
+    }
+}
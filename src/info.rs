@@ -0,0 +1,833 @@
+//! Metadata recorded in the headers of compressed files and archives, for `cmprss info`
+//!
+//! The headers are parsed here rather than by the compressors, so that formats built without
+//! their backend are still described. Most of the formats only need their headers, or their
+//! frames and indexes to be walked without decompressing them. Gzip doesn't record where its
+//! members start, so those are decompressed to count them.
+
+use crate::job::get_compressor_from_filename;
+use crate::magic;
+use crate::utils::*;
+use clap::Args;
+use indicatif::HumanBytes;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Args, Debug)]
+pub struct InfoArgs {
+    /// Compressed files or archives to describe
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+}
+
+/// Value of a field of the metadata
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Text(String),
+    Number(u64),
+    /// A size in bytes, shown in a human readable form as well
+    Bytes(u64),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Text(text) => write!(f, "{}", text),
+            Value::Number(number) => write!(f, "{}", number),
+            Value::Bytes(bytes) => write!(f, "{} ({} bytes)", HumanBytes(*bytes), bytes),
+        }
+    }
+}
+
+/// The metadata of a file, as named fields in the order they are shown
+#[derive(Debug, Default)]
+pub struct Info {
+    pub fields: Vec<(&'static str, Value)>,
+}
+
+impl Info {
+    fn text(&mut self, name: &'static str, text: impl Into<String>) {
+        self.fields.push((name, Value::Text(text.into())));
+    }
+
+    fn number(&mut self, name: &'static str, number: u64) {
+        self.fields.push((name, Value::Number(number)));
+    }
+
+    fn bytes(&mut self, name: &'static str, bytes: u64) {
+        self.fields.push((name, Value::Bytes(bytes)));
+    }
+
+    /// The value of a field, if it was recorded
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.fields.iter().find(|(n, _)| *n == name).map(|(_, v)| v)
+    }
+
+    /// The fields as a JSON object after the file, with sizes in bytes and spaces in the names
+    /// replaced with underscores
+    pub fn json(&self, file: &Path) -> String {
+        let mut fields = vec![format!(
+            r#""file":{}"#,
+            json_string(&file.to_string_lossy())
+        )];
+        for (name, value) in &self.fields {
+            let value = match value {
+                Value::Text(text) => json_string(text),
+                Value::Number(number) | Value::Bytes(number) => number.to_string(),
+            };
+            fields.push(format!(r#""{}":{}"#, name.replace(' ', "_"), value));
+        }
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+impl fmt::Display for Info {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, value) in &self.fields {
+            writeln!(f, "{}: {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the fields of a header from a slice, returning None past the end
+struct Fields<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Fields { data, position: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.position..self.position.checked_add(n)?)?;
+        self.position += n;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16_le(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn u32_le(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64_le(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn u16_be(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn u32_be(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    /// A string ending with a NUL byte
+    fn c_string(&mut self) -> Option<String> {
+        let length = self
+            .data
+            .get(self.position..)?
+            .iter()
+            .position(|b| *b == 0)?;
+        let text = String::from_utf8_lossy(self.take(length)?).into_owned();
+        self.position += 1;
+        Some(text)
+    }
+
+    /// A variable length integer of xz, 7 bits per byte starting with the lowest
+    fn varint(&mut self) -> Option<u64> {
+        let mut value = 0u64;
+        for i in 0..9 {
+            let byte = self.u8()?;
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+fn corrupt(format: &str) -> io::Error {
+    io::Error::other(format!("corrupt {} header", format))
+}
+
+/// A time in seconds since the epoch, as a UTC date
+fn date(secs: i64) -> String {
+    let (year, month, day, hour, minute, second) = civil_from_unix(secs);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year, month, day, hour, minute, second
+    )
+}
+
+fn gzip(path: &Path, head: &[u8], info: &mut Info) -> Result<(), io::Error> {
+    let mut fields = Fields::new(head);
+    let header = || corrupt("gzip");
+    fields.take(2).ok_or_else(header)?;
+    let method = fields.u8().ok_or_else(header)?;
+    let flags = fields.u8().ok_or_else(header)?;
+    let mtime = fields.u32_le().ok_or_else(header)?;
+    let extra_flags = fields.u8().ok_or_else(header)?;
+    let os = fields.u8().ok_or_else(header)?;
+    info.text(
+        "method",
+        match method {
+            8 => "deflate".to_string(),
+            _ => format!("unknown ({})", method),
+        },
+    );
+    match extra_flags {
+        2 => info.text("level", "9, the best compression"),
+        4 => info.text("level", "1, the fastest"),
+        _ => {}
+    }
+    if flags & 0x04 != 0 {
+        let length = fields.u16_le().ok_or_else(header)?;
+        fields.take(length as usize).ok_or_else(header)?;
+    }
+    if flags & 0x08 != 0 {
+        info.text("name", fields.c_string().ok_or_else(header)?);
+    }
+    if mtime != 0 {
+        info.text("modified", date(mtime.into()));
+    }
+    if flags & 0x10 != 0 {
+        info.text("comment", fields.c_string().ok_or_else(header)?);
+    }
+    info.text(
+        "os",
+        match os {
+            0 => "FAT",
+            3 => "Unix",
+            7 => "Macintosh",
+            10 => "TOPS-20",
+            11 => "NTFS",
+            _ => "unknown",
+        },
+    );
+    info.text("checksum", "CRC-32");
+
+    // The members can only be found by decompressing them
+    let mut reader = BufReader::new(File::open(path)?);
+    let (mut members, mut size) = (0, 0);
+    while reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        let mut decoder = flate2::bufread::GzDecoder::new(&mut reader);
+        size += io::copy(&mut decoder, &mut io::sink())?;
+        members += 1;
+    }
+    info.number("members", members);
+    info.bytes("uncompressed", size);
+    Ok(())
+}
+
+/// Names of the xz filters
+fn xz_filter(id: u64) -> String {
+    match id {
+        0x21 => "LZMA2",
+        0x03 => "delta",
+        0x04 => "x86",
+        0x05 => "PowerPC",
+        0x06 => "IA-64",
+        0x07 => "ARM",
+        0x08 => "ARM-Thumb",
+        0x09 => "SPARC",
+        0x0a => "ARM64",
+        0x0b => "RISC-V",
+        _ => return format!("unknown ({:#x})", id),
+    }
+    .to_string()
+}
+
+/// Read the filters of the block header at the position, with the dictionary size of LZMA2
+fn xz_block_filters(file: &mut File, position: u64) -> Option<(Vec<String>, Option<u64>)> {
+    let mut size = [0u8];
+    file.seek(SeekFrom::Start(position)).ok()?;
+    file.read_exact(&mut size).ok()?;
+    let mut header = vec![0; (size[0] as usize + 1) * 4 - 1];
+    file.read_exact(&mut header).ok()?;
+    let mut fields = Fields::new(&header);
+    let flags = fields.u8()?;
+    if flags & 0x40 != 0 {
+        fields.varint()?;
+    }
+    if flags & 0x80 != 0 {
+        fields.varint()?;
+    }
+    let mut filters = Vec::new();
+    let mut dictionary = None;
+    for _ in 0..=(flags & 0x03) {
+        let id = fields.varint()?;
+        let properties = fields.varint()?;
+        let properties = fields.take(properties as usize)?;
+        if id == 0x21 {
+            let bits = u32::from(*properties.first()?);
+            dictionary = Some(match bits {
+                40 => u64::from(u32::MAX),
+                _ => (2 | u64::from(bits & 1)) << (bits / 2 + 11),
+            });
+        }
+        filters.push(xz_filter(id));
+    }
+    Some((filters, dictionary))
+}
+
+/// The presets of xz using a dictionary size
+fn xz_levels(dictionary: u64) -> Option<&'static str> {
+    const MIB: u64 = 1024 * 1024;
+    Some(match dictionary {
+        d if d == 256 * 1024 => "0",
+        d if d == MIB => "1",
+        d if d == 2 * MIB => "2",
+        d if d == 4 * MIB => "3 or 4",
+        d if d == 8 * MIB => "5 or 6",
+        d if d == 16 * MIB => "7",
+        d if d == 32 * MIB => "8",
+        d if d == 64 * MIB => "9",
+        _ => return None,
+    })
+}
+
+/// Walk the streams of an xz file from the end, reading their indexes
+fn xz(file: &mut File, info: &mut Info) -> Result<(), io::Error> {
+    let header = || corrupt("xz");
+    let mut end = file.metadata()?.len();
+    let (mut streams, mut blocks, mut size) = (0u64, 0u64, 0u64);
+    let mut check = 0;
+    let mut first_block = None;
+    while end > 0 {
+        let mut footer = [0u8; 12];
+        file.seek(SeekFrom::Start(end.checked_sub(12).ok_or_else(header)?))?;
+        file.read_exact(&mut footer)?;
+        // Streams can be followed by padding of zeros, in groups of four
+        if footer[8..] == [0; 4] {
+            end -= 4;
+            continue;
+        }
+        if &footer[10..] != b"YZ" {
+            return Err(header());
+        }
+        let backward = (u64::from(u32::from_le_bytes(footer[4..8].try_into().unwrap())) + 1) * 4;
+        let index_start = (end - 12).checked_sub(backward).ok_or_else(header)?;
+        let mut index = vec![0; backward as usize];
+        file.seek(SeekFrom::Start(index_start))?;
+        file.read_exact(&mut index)?;
+        let mut fields = Fields::new(&index);
+        if fields.u8() != Some(0) {
+            return Err(header());
+        }
+        let records = fields.varint().ok_or_else(header)?;
+        let mut compressed = 0u64;
+        for _ in 0..records {
+            let unpadded = fields.varint().ok_or_else(header)?;
+            compressed += unpadded.div_ceil(4) * 4;
+            size += fields.varint().ok_or_else(header)?;
+        }
+        let start = index_start
+            .checked_sub(compressed + 12)
+            .ok_or_else(header)?;
+        let mut stream_header = [0u8; 12];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut stream_header)?;
+        if stream_header[..6] != [0xfd, b'7', b'z', b'X', b'Z', 0x00] {
+            return Err(header());
+        }
+        check = stream_header[7] & 0x0f;
+        if records > 0 {
+            first_block = Some(start + 12);
+        }
+        streams += 1;
+        blocks += records;
+        end = start;
+    }
+    info.number("streams", streams);
+    info.number("blocks", blocks);
+    info.bytes("uncompressed", size);
+    info.text(
+        "checksum",
+        match check {
+            0 => "none",
+            1 => "CRC-32",
+            4 => "CRC-64",
+            10 => "SHA-256",
+            _ => "unknown",
+        },
+    );
+    if let Some((filters, dictionary)) = first_block.and_then(|block| xz_block_filters(file, block))
+    {
+        info.text("filters", filters.join(", "));
+        if let Some(dictionary) = dictionary {
+            info.bytes("dictionary", dictionary);
+            if let Some(levels) = xz_levels(dictionary) {
+                info.text("level", levels);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Walk the frames of a zstd file, skipping over their blocks
+fn zstd(file: File, info: &mut Info) -> Result<(), io::Error> {
+    let header = || corrupt("zstd");
+    let mut reader = BufReader::new(file);
+    let (mut frames, mut skippable) = (0u64, 0u64);
+    let mut size = Some(0u64);
+    let mut checksum = false;
+    let mut window = 0u64;
+    let mut dictionaries = Vec::new();
+    while !reader.fill_buf()?.is_empty() {
+        let mut buffer = [0u8; 14];
+        reader.read_exact(&mut buffer[..4])?;
+        let magic = u32::from_le_bytes(buffer[..4].try_into().unwrap());
+        if magic & 0xffff_fff0 == 0x184d_2a50 {
+            reader.read_exact(&mut buffer[..4])?;
+            let length = u32::from_le_bytes(buffer[..4].try_into().unwrap());
+            reader.seek_relative(length.into())?;
+            skippable += 1;
+            continue;
+        }
+        if magic != 0xfd2f_b528 {
+            return Err(header());
+        }
+        reader.read_exact(&mut buffer[..1])?;
+        let descriptor = buffer[0];
+        let single_segment = descriptor & 0x20 != 0;
+        let dictionary_bytes = [0, 1, 2, 4][(descriptor & 0x03) as usize];
+        let size_bytes = match descriptor >> 6 {
+            0 if single_segment => 1,
+            0 => 0,
+            1 => 2,
+            2 => 4,
+            _ => 8,
+        };
+        let length = usize::from(!single_segment) + dictionary_bytes + size_bytes;
+        reader.read_exact(&mut buffer[..length])?;
+        let mut fields = Fields::new(&buffer[..length]);
+        if !single_segment {
+            let descriptor = fields.u8().ok_or_else(header)?;
+            let base = 1u64 << (10 + (descriptor >> 3));
+            window = window.max(base + base / 8 * u64::from(descriptor & 0x07));
+        }
+        let mut little_endian = |n: usize| {
+            let bytes = fields.take(n)?;
+            Some((0..n).fold(0u64, |value, i| value | u64::from(bytes[i]) << (8 * i)))
+        };
+        let dictionary = little_endian(dictionary_bytes).ok_or_else(header)?;
+        if dictionary != 0 && !dictionaries.contains(&dictionary) {
+            dictionaries.push(dictionary);
+        }
+        let content = match size_bytes {
+            0 => None,
+            2 => Some(little_endian(2).ok_or_else(header)? + 256),
+            n => little_endian(n),
+        };
+        if single_segment {
+            window = window.max(content.unwrap_or(0));
+        }
+        size = size.zip(content).map(|(a, b)| a + b);
+        if descriptor & 0x04 != 0 {
+            checksum = true;
+        }
+        loop {
+            reader.read_exact(&mut buffer[..3])?;
+            let block = u32::from_le_bytes([buffer[0], buffer[1], buffer[2], 0]);
+            let length = match (block >> 1) & 0x03 {
+                1 => 1,
+                3 => return Err(header()),
+                _ => block >> 3,
+            };
+            reader.seek_relative(length.into())?;
+            if block & 1 != 0 {
+                break;
+            }
+        }
+        if descriptor & 0x04 != 0 {
+            reader.seek_relative(4)?;
+        }
+        frames += 1;
+    }
+    info.number("frames", frames);
+    if skippable > 0 {
+        info.number("skippable frames", skippable);
+    }
+    if let Some(size) = size.filter(|_| frames > 0) {
+        info.bytes("uncompressed", size);
+    }
+    info.text("checksum", if checksum { "XXH64" } else { "none" });
+    if window > 0 {
+        info.bytes("window", window);
+    }
+    if !dictionaries.is_empty() {
+        let ids: Vec<String> = dictionaries.iter().map(u64::to_string).collect();
+        info.text("dictionary id", ids.join(", "));
+    }
+    Ok(())
+}
+
+/// Walk the frames of an lz4 file, skipping over their blocks
+fn lz4(file: File, info: &mut Info) -> Result<(), io::Error> {
+    let header = || corrupt("lz4");
+    let mut reader = BufReader::new(file);
+    let mut frames = 0u64;
+    let mut size = Some(0u64);
+    let (mut block_size, mut content_checksum, mut block_checksum) = (0, false, false);
+    let mut dictionary = None;
+    while !reader.fill_buf()?.is_empty() {
+        let mut buffer = [0u8; 4];
+        reader.read_exact(&mut buffer)?;
+        let magic = u32::from_le_bytes(buffer);
+        if magic & 0xffff_fff0 == 0x184d_2a50 {
+            reader.read_exact(&mut buffer)?;
+            reader.seek_relative(u32::from_le_bytes(buffer).into())?;
+            continue;
+        }
+        if magic != 0x184d_2204 {
+            return Err(header());
+        }
+        let mut descriptor = [0u8; 2];
+        reader.read_exact(&mut descriptor)?;
+        let [flags, block] = descriptor;
+        if flags >> 6 != 1 {
+            return Err(header());
+        }
+        block_size = match (block >> 4) & 0x07 {
+            4 => 64 * 1024,
+            5 => 256 * 1024,
+            6 => 1024 * 1024,
+            7 => 4 * 1024 * 1024,
+            _ => return Err(header()),
+        };
+        block_checksum = flags & 0x10 != 0;
+        content_checksum |= flags & 0x04 != 0;
+        let mut content = None;
+        if flags & 0x08 != 0 {
+            let mut bytes = [0u8; 8];
+            reader.read_exact(&mut bytes)?;
+            content = Fields::new(&bytes).u64_le();
+        }
+        size = size.zip(content).map(|(a, b)| a + b);
+        if flags & 0x01 != 0 {
+            reader.read_exact(&mut buffer)?;
+            dictionary = Some(u32::from_le_bytes(buffer));
+        }
+        // Checksum of the descriptor
+        reader.seek_relative(1)?;
+        loop {
+            reader.read_exact(&mut buffer)?;
+            let length = u32::from_le_bytes(buffer) & 0x7fff_ffff;
+            if length == 0 {
+                break;
+            }
+            reader.seek_relative(i64::from(length) + if block_checksum { 4 } else { 0 })?;
+        }
+        if flags & 0x04 != 0 {
+            reader.seek_relative(4)?;
+        }
+        frames += 1;
+    }
+    info.number("frames", frames);
+    if let Some(size) = size.filter(|_| frames > 0) {
+        info.bytes("uncompressed", size);
+    }
+    info.bytes("block size", block_size);
+    info.text(
+        "checksum",
+        match (content_checksum, block_checksum) {
+            (true, true) => "XXH32 of the content and each block",
+            (true, false) => "XXH32 of the content",
+            (false, true) => "XXH32 of each block",
+            (false, false) => "none",
+        },
+    );
+    if let Some(dictionary) = dictionary {
+        info.number("dictionary id", dictionary.into());
+    }
+    Ok(())
+}
+
+fn bzip2(head: &[u8], info: &mut Info) -> Result<(), io::Error> {
+    let level = match head.get(3) {
+        Some(digit @ b'1'..=b'9') => u64::from(digit - b'0'),
+        _ => return Err(corrupt("bzip2")),
+    };
+    info.number("level", level);
+    info.bytes("block size", level * 100_000);
+    info.text("checksum", "CRC-32");
+    Ok(())
+}
+
+fn zlib(head: &[u8], info: &mut Info) -> Result<(), io::Error> {
+    let mut fields = Fields::new(head);
+    let header = || corrupt("zlib");
+    let method = fields.u8().ok_or_else(header)?;
+    let flags = fields.u8().ok_or_else(header)?;
+    info.text(
+        "method",
+        match method & 0x0f {
+            8 => "deflate".to_string(),
+            other => format!("unknown ({})", other),
+        },
+    );
+    info.text(
+        "level",
+        match flags >> 6 {
+            0 => "fastest",
+            1 => "fast",
+            2 => "default",
+            _ => "best compression",
+        },
+    );
+    info.bytes("window", 1 << ((method >> 4) + 8));
+    if flags & 0x20 != 0 {
+        info.number("dictionary id", fields.u32_be().ok_or_else(header)?.into());
+    }
+    info.text("checksum", "Adler-32");
+    Ok(())
+}
+
+fn lzw(head: &[u8], info: &mut Info) -> Result<(), io::Error> {
+    let flags = *head.get(2).ok_or_else(|| corrupt("lzw"))?;
+    info.number("max bits", (flags & 0x1f).into());
+    info.text("block mode", if flags & 0x80 != 0 { "yes" } else { "no" });
+    Ok(())
+}
+
+fn lzop(head: &[u8], info: &mut Info) -> Result<(), io::Error> {
+    let header = || corrupt("lzop");
+    let mut fields = Fields::new(head);
+    fields.take(9).ok_or_else(header)?;
+    let version = fields.u16_be().ok_or_else(header)?;
+    fields.u16_be().ok_or_else(header)?;
+    if version >= 0x0940 {
+        fields.u16_be().ok_or_else(header)?;
+    }
+    let method = fields.u8().ok_or_else(header)?;
+    info.text(
+        "method",
+        match method {
+            1 => "LZO1X-1".to_string(),
+            2 => "LZO1X-1(15)".to_string(),
+            3 => "LZO1X-999".to_string(),
+            other => format!("unknown ({})", other),
+        },
+    );
+    if version >= 0x0940 {
+        let level = fields.u8().ok_or_else(header)?;
+        if level > 0 {
+            info.number("level", level.into());
+        }
+    }
+    let flags = fields.u32_be().ok_or_else(header)?;
+    if flags & 0x800 != 0 {
+        fields.u32_be().ok_or_else(header)?;
+    }
+    fields.u32_be().ok_or_else(header)?;
+    let mtime = u64::from(fields.u32_be().ok_or_else(header)?);
+    let high = match version >= 0x0940 {
+        true => u64::from(fields.u32_be().ok_or_else(header)?),
+        false => 0,
+    };
+    let length = fields.u8().ok_or_else(header)?;
+    let name = fields.take(length.into()).ok_or_else(header)?;
+    if !name.is_empty() {
+        info.text("name", String::from_utf8_lossy(name));
+    }
+    if mtime | high != 0 {
+        info.text("modified", date((high << 32 | mtime) as i64));
+    }
+    let checksums: Vec<&str> = [(0x1, "Adler-32"), (0x100, "CRC-32")]
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    info.text(
+        "checksum",
+        match checksums.is_empty() {
+            true => "none".to_string(),
+            false => checksums.join(", "),
+        },
+    );
+    Ok(())
+}
+
+/// Describe a compressed file or an archive
+/// Archives list their entries, and the compression around them is described like a file.
+pub fn info(path: &Path) -> Result<Info, io::Error> {
+    let mut info = Info::default();
+    let Some(compressor) = get_compressor_from_filename(path) else {
+        return Err(io::Error::other(format!(
+            "unable to determine the format of {}",
+            path.display()
+        )));
+    };
+    info.text("format", compressor.name());
+    let mut file = File::open(path)?;
+    info.bytes("size", file.metadata()?.len());
+    let head = magic::read_head(&mut file)?;
+    let stream = match magic::detect(&head) {
+        Some(name) => name,
+        // Brotli has no signature
+        None if compressor.name().ends_with("brotli") => "brotli",
+        None => "",
+    };
+    match stream {
+        "gzip" => gzip(path, &head, &mut info)?,
+        "xz" => xz(&mut file, &mut info)?,
+        "zstd" => zstd(File::open(path)?, &mut info)?,
+        "lz4" => lz4(File::open(path)?, &mut info)?,
+        "bzip2" => bzip2(&head, &mut info)?,
+        "zlib" => zlib(&head, &mut info)?,
+        "lzw" => lzw(&head, &mut info)?,
+        "lzop" => lzop(&head, &mut info)?,
+        _ => {}
+    }
+
+    if !compressor.is_stream() {
+        let entries = compressor.list(CmprssInput::Path(vec![path.to_path_buf()]))?;
+        let files = entries.iter().filter(|entry| !entry.is_dir);
+        info.number("files", files.clone().count() as u64);
+        info.number(
+            "directories",
+            entries.iter().filter(|entry| entry.is_dir).count() as u64,
+        );
+        info.bytes("files size", files.map(|entry| entry.size).sum());
+    }
+    Ok(info)
+}
+
+/// Print the metadata of the files, separated by empty lines, or as a line of JSON for each
+pub fn print_info(args: &InfoArgs, output: OutputFormat) -> Result<(), io::Error> {
+    let mut stdout = io::stdout().lock();
+    for (i, path) in args.files.iter().enumerate() {
+        if output == OutputFormat::Json {
+            writeln!(stdout, "{}", info(path)?.json(path))?;
+            continue;
+        }
+        if i > 0 {
+            writeln!(stdout)?;
+        }
+        writeln!(stdout, "file: {}", path.display())?;
+        write!(stdout, "{}", info(path)?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+
+    fn compressed(
+        name: &str,
+        data: &[u8],
+    ) -> Result<assert_fs::TempDir, Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let input = working_dir.child("data");
+        input.write_binary(data)?;
+        let output = working_dir.child(name);
+        get_compressor_from_filename(output.path())
+            .unwrap()
+            .compress(
+                CmprssInput::Path(vec![input.path().to_path_buf()]),
+                CmprssOutput::Path(output.path().to_path_buf()),
+            )?;
+        Ok(working_dir)
+    }
+
+    #[test]
+    fn stream_formats() -> Result<(), Box<dyn std::error::Error>> {
+        let data = b"garbage data for testing ".repeat(100);
+        let size = Some(&Value::Bytes(data.len() as u64));
+        let mut names = vec![
+            "data.gz", "data.lz4", "data.zz", "data.Z", "data.lzo", "data.br",
+        ];
+        if cfg!(feature = "xz") {
+            names.push("data.xz");
+        }
+        if cfg!(feature = "zstd") {
+            names.push("data.zst");
+        }
+        if cfg!(feature = "bzip2") {
+            names.push("data.bz2");
+        }
+        for name in names {
+            let working_dir = compressed(name, &data)?;
+            let info = info(working_dir.child(name).path())?;
+            assert!(info.get("format").is_some(), "{}", name);
+            match name {
+                "data.gz" => {
+                    assert_eq!(info.get("uncompressed"), size);
+                    assert_eq!(info.get("members"), Some(&Value::Number(1)));
+                    assert_eq!(info.get("name"), Some(&Value::Text("data".to_string())));
+                }
+                "data.xz" => {
+                    assert_eq!(info.get("uncompressed"), size);
+                    assert_eq!(info.get("streams"), Some(&Value::Number(1)));
+                    assert_eq!(info.get("filters"), Some(&Value::Text("LZMA2".to_string())));
+                    assert_eq!(info.get("level"), Some(&Value::Text("5 or 6".to_string())));
+                }
+                // Streamed frames don't record their size
+                "data.zst" => assert_eq!(info.get("frames"), Some(&Value::Number(1))),
+                "data.lz4" => assert_eq!(info.get("frames"), Some(&Value::Number(1))),
+                "data.bz2" => assert_eq!(info.get("level"), Some(&Value::Number(6))),
+                "data.zz" => assert!(info.get("level").is_some()),
+                "data.Z" => assert_eq!(info.get("max bits"), Some(&Value::Number(16))),
+                "data.lzo" => assert!(info.get("method").is_some()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn concatenated_gzip() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let mut data = Vec::new();
+        for part in [&b"first"[..], b"second"] {
+            crate::gzip::Gzip::default().compress_stream(&mut &part[..], &mut data)?;
+        }
+        let file = working_dir.child("parts.gz");
+        file.write_binary(&data)?;
+        let info = info(file.path())?;
+        assert_eq!(info.get("members"), Some(&Value::Number(2)));
+        assert_eq!(info.get("uncompressed"), Some(&Value::Bytes(11)));
+        Ok(())
+    }
+
+    #[test]
+    fn archive() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir.child("dir/a.txt").write_str("aaaa")?;
+        working_dir.child("dir/sub/b.txt").write_str("bb")?;
+        let archive = working_dir.child("dir.tar.gz");
+        get_compressor_from_filename(archive.path())
+            .unwrap()
+            .compress(
+                CmprssInput::Path(vec![working_dir.child("dir").path().to_path_buf()]),
+                CmprssOutput::Path(archive.path().to_path_buf()),
+            )?;
+        let info = info(archive.path())?;
+        assert_eq!(info.get("format"), Some(&Value::Text("tar.gz".to_string())));
+        assert_eq!(info.get("members"), Some(&Value::Number(1)));
+        assert_eq!(info.get("files"), Some(&Value::Number(2)));
+        assert_eq!(info.get("directories"), Some(&Value::Number(2)));
+        assert_eq!(info.get("files size"), Some(&Value::Bytes(6)));
+        let json = info.json(Path::new("dir.tar.gz"));
+        assert!(json.starts_with(r#"{"file":"dir.tar.gz","format":"tar.gz","size":"#));
+        assert!(json.ends_with(r#","files":2,"directories":2,"files_size":6}"#));
+        Ok(())
+    }
+}
@@ -0,0 +1,703 @@
+//! ISO 9660 disc image backend, which only reads images
+//!
+//! The Rock Ridge extensions are used for the names and permissions of the files when the image
+//! has them, otherwise the Joliet names are used, and the plain ISO 9660 names as a last resort.
+
+use clap::Args;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::exclude::Exclude;
+use crate::limits::OutputLimits;
+use crate::progress::{
+    progress_bar, ArchiveProgress, ProgressArgs, ProgressDisplay, ProgressHooks, ProgressObserver,
+};
+use crate::utils::*;
+
+const SECTOR: u64 = 2048;
+/// The volume descriptors start after the system area
+const FIRST_DESCRIPTOR: u64 = 16;
+/// Directories deeper than this are corrupt, ISO 9660 itself allows 8 levels
+const MAX_DEPTH: usize = 64;
+
+#[derive(Args, Debug)]
+pub struct IsoArgs {
+    #[clap(flatten)]
+    pub common_args: CommonArgs,
+
+    #[clap(flatten)]
+    pub progress_args: ProgressArgs,
+}
+
+#[derive(Default, Clone)]
+pub struct Iso {
+    /// Extract entries with absolute paths or '..' outside of the output directory
+    pub allow_unsafe_paths: bool,
+    /// Leading directories to remove from the paths of extracted entries
+    pub strip_components: usize,
+    /// Entries to skip when extracting
+    pub exclude: Exclude,
+    pub progress_args: ProgressArgs,
+    /// Progress observer, cancellation, and limits on the total size of the extracted files
+    pub hooks: ProgressHooks,
+}
+
+impl Iso {
+    pub fn new(args: &IsoArgs) -> Iso {
+        Iso {
+            allow_unsafe_paths: args.common_args.allow_unsafe_paths,
+            strip_components: args.common_args.strip_components,
+            exclude: Exclude::default(),
+            progress_args: args.progress_args,
+            hooks: ProgressHooks::default(),
+        }
+    }
+}
+
+impl Compressor for Iso {
+    /// Full name for iso, also used for extension
+    fn name(&self) -> &str {
+        "iso"
+    }
+
+    /// Extraction needs to specify the directory, so use the current directory
+    fn default_extracted_filename(&self, _in_path: &Path) -> String {
+        ".".to_string()
+    }
+
+    fn set_allow_unsafe_paths(&mut self, allow: bool) {
+        self.allow_unsafe_paths = allow;
+    }
+
+    fn set_strip_components(&mut self, count: usize) {
+        self.strip_components = count;
+    }
+
+    fn set_limits(&mut self, limits: OutputLimits) {
+        self.hooks.limits = limits;
+    }
+
+    fn set_observer(&mut self, observer: Arc<dyn ProgressObserver>) {
+        self.hooks.observer = Some(observer);
+    }
+
+    fn set_progress(&mut self, progress: ProgressDisplay) {
+        self.progress_args.progress = progress;
+    }
+
+    fn progress(&self) -> ProgressDisplay {
+        self.progress_args.progress
+    }
+
+    fn set_exclude(&mut self, exclude: Exclude) {
+        self.exclude = exclude;
+    }
+
+    fn compress(&self, _input: CmprssInput, _output: CmprssOutput) -> Result<(), io::Error> {
+        cmprss_error("creating iso images is not supported, iso can only be extracted")
+    }
+
+    fn extract(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        let out_path = match &output {
+            CmprssOutput::Path(path) if path.is_dir() => long_path(path)?,
+            _ => return cmprss_error("error: iso can only extract to a directory"),
+        };
+        let (reader, input_size) = open_reader(input)?;
+        let bar = progress_bar(
+            Some(input_size),
+            self.progress_args.progress,
+            &output,
+            &self.hooks,
+        );
+        let progress = ArchiveProgress::new(&self.hooks, bar);
+        let mut image = IsoImage::open(progress.reader(reader))?;
+        // Directories are restored last, so that extracting their contents doesn't change them
+        let mut directories = Vec::new();
+        let mut written = 0;
+        for entry in image.entries()? {
+            let path = Path::new(&entry.path);
+            if !self.exclude.is_extracted(path) {
+                crate::info!("skipping {}, it is excluded", entry.path);
+                continue;
+            }
+            let Some(name) = strip_components(path, self.strip_components) else {
+                crate::info!(
+                    "skipping {}, it is removed by --strip-components",
+                    entry.path
+                );
+                continue;
+            };
+            if entry.is_link {
+                crate::warn!(
+                    "skipping {}, links in iso images aren't supported",
+                    entry.path
+                );
+                continue;
+            }
+            crate::debug!("extracting {}", entry.path);
+            let target = entry_path(&out_path, &name, self.allow_unsafe_paths)?;
+            if entry.is_dir {
+                fs::create_dir_all(&target)?;
+                directories.push((target, entry));
+                continue;
+            }
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut writer = self
+                .hooks
+                .limits
+                .writer(progress.writer(File::create(&target)?), Some(input_size))
+                .after(written);
+            image.copy_data(&entry, &mut writer)?;
+            written = writer.written();
+            drop(writer);
+            restore_metadata(&target, &entry)?;
+        }
+        for (path, entry) in directories.into_iter().rev() {
+            restore_metadata(&path, &entry)?;
+        }
+        progress.finish();
+        Ok(())
+    }
+
+    fn list(&self, input: CmprssInput) -> Result<Vec<ArchiveEntry>, io::Error> {
+        let (reader, _) = open_reader(input)?;
+        let entries = IsoImage::open(reader)?.entries()?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| ArchiveEntry {
+                size: entry.size(),
+                path: entry.path,
+                is_dir: entry.is_dir,
+                compressed_size: None,
+                mtime: entry.mtime,
+                mode: entry.mode,
+                comment: None,
+            })
+            .collect())
+    }
+
+    fn cat(&self, input: CmprssInput, entry: &str, output: CmprssOutput) -> Result<(), io::Error> {
+        let (reader, _) = open_reader(input)?;
+        let mut image = IsoImage::open(reader)?;
+        let Some(found) = image
+            .entries()?
+            .into_iter()
+            .find(|found| entry_matches(&found.path, entry))
+        else {
+            return Err(io::Error::other(format!("{} is not in the archive", entry)));
+        };
+        if found.is_dir {
+            return Err(io::Error::other(format!("{} is a directory", entry)));
+        }
+        let mut output = output.into_stream()?;
+        image.copy_data(&found, &mut output)?;
+        output.flush()
+    }
+}
+
+trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Open an image for seeking, with its size in bytes
+/// A stream is read into memory, because the directories can point anywhere in the image.
+fn open_reader(input: CmprssInput) -> Result<(Box<dyn ReadSeek>, u64), io::Error> {
+    match input {
+        CmprssInput::Path(paths) => {
+            if paths.len() > 1 {
+                return Err(io::Error::other(
+                    "only 1 archive can be extracted at a time",
+                ));
+            }
+            let file = File::open(&paths[0])?;
+            let size = file.metadata()?.len();
+            Ok((Box::new(file), size))
+        }
+        input => {
+            let (mut stream, _) = input.into_stream()?;
+            let mut buffer = Vec::new();
+            stream.read_to_end(&mut buffer)?;
+            let size = buffer.len() as u64;
+            Ok((Box::new(Cursor::new(buffer)), size))
+        }
+    }
+}
+
+/// Restore the modification time and Unix permissions of an extracted file or directory
+fn restore_metadata(path: &Path, entry: &Entry) -> Result<(), io::Error> {
+    if let Some(mtime) = entry.mtime.and_then(|mtime| u64::try_from(mtime).ok()) {
+        let file = File::open(path)?;
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime))?;
+    }
+    #[cfg(unix)]
+    if let Some(mode) = entry.mode {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+/// A file or directory in the image
+#[derive(Debug, Clone)]
+struct Entry {
+    /// Path with '/' separators
+    path: String,
+    is_dir: bool,
+    is_link: bool,
+    /// The sectors and lengths of the data, large files are split into several extents
+    extents: Vec<(u32, u64)>,
+    mtime: Option<i64>,
+    /// Unix permissions, from Rock Ridge
+    mode: Option<u32>,
+}
+
+impl Entry {
+    fn size(&self) -> u64 {
+        self.extents.iter().map(|(_, length)| length).sum()
+    }
+}
+
+/// Which names to use for the files of an image
+#[derive(Clone, Copy, PartialEq)]
+enum Names {
+    /// The plain ISO 9660 names, and the Rock Ridge names when present
+    Primary,
+    /// The UCS-2 names of the Joliet supplementary volume
+    Joliet,
+}
+
+/// A directory record, the entry of a file or directory in its parent
+struct Record {
+    name: String,
+    extent: u32,
+    length: u64,
+    flags: u8,
+    mtime: Option<i64>,
+    /// The Rock Ridge name, permissions, and whether it is a link or a relocated directory
+    rock_ridge_name: Option<String>,
+    mode: Option<u32>,
+    is_link: bool,
+    relocated: bool,
+}
+
+/// The 7 byte date of a directory record, in seconds since the Unix epoch
+fn record_time(date: &[u8]) -> Option<i64> {
+    if date[1] == 0 || date[2] == 0 {
+        return None;
+    }
+    let local = unix_from_civil(
+        1900 + date[0] as i64,
+        date[1] as u32,
+        date[2] as u32,
+        date[3] as u32,
+        date[4] as u32,
+        date[5] as u32,
+    );
+    // The offset from UTC is in 15 minute intervals
+    Some(local - date[6] as i8 as i64 * 15 * 60)
+}
+
+fn corrupt() -> io::Error {
+    io::Error::other("corrupt iso image")
+}
+
+/// An ISO 9660 image, read through its directories
+struct IsoImage<R: Read + Seek> {
+    inner: R,
+    /// The root directory record of the volume to read
+    root: (u32, u64),
+    names: Names,
+}
+
+impl<R: Read + Seek> IsoImage<R> {
+    /// Read the volume descriptors, choosing between the primary and Joliet volumes
+    fn open(mut inner: R) -> Result<Self, io::Error> {
+        let mut primary = None;
+        let mut joliet = None;
+        let mut descriptor = [0; SECTOR as usize];
+        for sector in FIRST_DESCRIPTOR.. {
+            inner.seek(SeekFrom::Start(sector * SECTOR))?;
+            if inner.read_exact(&mut descriptor).is_err() || &descriptor[1..6] != b"CD001" {
+                return Err(io::Error::other("not an iso image"));
+            }
+            // The root directory record is in the same place in both kinds of volume
+            let root = (
+                u32::from_le_bytes(descriptor[158..162].try_into().unwrap()),
+                u32::from_le_bytes(descriptor[166..170].try_into().unwrap()) as u64,
+            );
+            match descriptor[0] {
+                1 => primary = primary.or(Some(root)),
+                // The escape sequences of the UCS-2 levels of Joliet
+                2 if descriptor[88..90] == *b"%/" && b"@CE".contains(&descriptor[90]) => {
+                    joliet = joliet.or(Some(root))
+                }
+                255 => break,
+                _ => {}
+            }
+        }
+        let root = primary.ok_or_else(|| io::Error::other("iso image has no primary volume"))?;
+        let mut image = IsoImage {
+            inner,
+            root,
+            names: Names::Primary,
+        };
+        // Rock Ridge marks the "." entry of the root directory
+        let rock_ridge = image
+            .records(root)?
+            .first()
+            .is_some_and(|record| record.rock_ridge_name.is_some() || record.mode.is_some());
+        if let (false, Some(joliet)) = (rock_ridge, joliet) {
+            image.root = joliet;
+            image.names = Names::Joliet;
+        }
+        Ok(image)
+    }
+
+    /// Read the records of a directory, including its "." and ".." entries
+    fn records(&mut self, (extent, length): (u32, u64)) -> Result<Vec<Record>, io::Error> {
+        if length > 64 * 1024 * 1024 {
+            return Err(corrupt());
+        }
+        let mut data = vec![0; length as usize];
+        self.inner.seek(SeekFrom::Start(extent as u64 * SECTOR))?;
+        self.inner.read_exact(&mut data)?;
+        let mut records = Vec::new();
+        let mut position = 0;
+        while position < data.len() {
+            let size = data[position] as usize;
+            // Records don't cross sectors, the rest of a sector is padded with zeros
+            if size == 0 {
+                position = (position / SECTOR as usize + 1) * SECTOR as usize;
+                continue;
+            }
+            let record = data.get(position..position + size).ok_or_else(corrupt)?;
+            records.push(self.parse_record(record)?);
+            position += size;
+        }
+        Ok(records)
+    }
+
+    fn parse_record(&mut self, record: &[u8]) -> Result<Record, io::Error> {
+        if record.len() < 34 {
+            return Err(corrupt());
+        }
+        let name_length = record[32] as usize;
+        let raw_name = record.get(33..33 + name_length).ok_or_else(corrupt)?;
+        let name = match raw_name {
+            [0] => ".".to_string(),
+            [1] => "..".to_string(),
+            _ if self.names == Names::Joliet => {
+                let units: Vec<u16> = raw_name
+                    .chunks_exact(2)
+                    .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                    .collect();
+                plain_name(&String::from_utf16_lossy(&units))
+            }
+            _ => plain_name(&String::from_utf8_lossy(raw_name)),
+        };
+        let mut parsed = Record {
+            name,
+            extent: u32::from_le_bytes(record[2..6].try_into().unwrap()),
+            length: u32::from_le_bytes(record[10..14].try_into().unwrap()) as u64,
+            flags: record[25],
+            mtime: record_time(&record[18..25]),
+            rock_ridge_name: None,
+            mode: None,
+            is_link: false,
+            relocated: false,
+        };
+        if self.names == Names::Primary {
+            // The system use area follows the name, after a byte of padding for even lengths
+            let start = 33 + name_length + (1 - name_length % 2);
+            if let Some(system_use) = record.get(start..) {
+                self.parse_rock_ridge(system_use, &mut parsed, 0)?;
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Read the Rock Ridge entries of a record, following continuation areas
+    fn parse_rock_ridge(
+        &mut self,
+        mut data: &[u8],
+        record: &mut Record,
+        depth: usize,
+    ) -> Result<(), io::Error> {
+        let mut continuation = None;
+        while data.len() >= 4 {
+            let length = data[2] as usize;
+            if length < 4 || length > data.len() {
+                break;
+            }
+            let (signature, body) = (&data[..2], &data[4..length]);
+            match signature {
+                b"NM" if !body.is_empty() && body[0] & 0x06 == 0 => {
+                    let part = String::from_utf8_lossy(&body[1..]);
+                    record
+                        .rock_ridge_name
+                        .get_or_insert_with(String::new)
+                        .push_str(&part);
+                }
+                b"PX" if body.len() >= 4 => {
+                    let mode = u32::from_le_bytes(body[..4].try_into().unwrap());
+                    record.mode = Some(mode & 0o7777);
+                    record.is_link |= mode & 0o170000 == 0o120000;
+                }
+                b"SL" => record.is_link = true,
+                b"RE" => record.relocated = true,
+                b"CE" if body.len() >= 24 => {
+                    let field = |offset: usize| {
+                        u32::from_le_bytes(body[offset..offset + 4].try_into().unwrap()) as u64
+                    };
+                    continuation = Some((field(0), field(8), field(16)));
+                }
+                b"ST" => break,
+                _ => {}
+            }
+            data = &data[length..];
+        }
+        if let Some((sector, offset, length)) = continuation {
+            if depth > 16 || length > SECTOR {
+                return Err(corrupt());
+            }
+            let mut area = vec![0; length as usize];
+            self.inner.seek(SeekFrom::Start(sector * SECTOR + offset))?;
+            self.inner.read_exact(&mut area)?;
+            self.parse_rock_ridge(&area, record, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    /// List every file and directory in the image, directories before their contents
+    fn entries(&mut self) -> Result<Vec<Entry>, io::Error> {
+        let mut entries = Vec::new();
+        let mut visited = HashSet::new();
+        self.walk(self.root, "", 0, &mut visited, &mut entries)?;
+        Ok(entries)
+    }
+
+    fn walk(
+        &mut self,
+        directory: (u32, u64),
+        prefix: &str,
+        depth: usize,
+        visited: &mut HashSet<u32>,
+        entries: &mut Vec<Entry>,
+    ) -> Result<(), io::Error> {
+        if depth > MAX_DEPTH || !visited.insert(directory.0) {
+            return Err(corrupt());
+        }
+        let mut subdirectories = Vec::new();
+        // The previous record was the start of a file continued in the next record
+        let mut continued = false;
+        for record in self.records(directory)? {
+            if record.name == "." || record.name == ".." || record.relocated {
+                continue;
+            }
+            if continued {
+                continued = record.flags & 0x80 != 0;
+                if let Some(entry) = entries.last_mut() {
+                    entry.extents.push((record.extent, record.length));
+                }
+                continue;
+            }
+            continued = record.flags & 0x80 != 0;
+            let name = record.rock_ridge_name.unwrap_or(record.name);
+            let path = format!("{}{}", prefix, name);
+            let is_dir = record.flags & 0x02 != 0;
+            if is_dir {
+                subdirectories.push((entries.len(), (record.extent, record.length)));
+            }
+            entries.push(Entry {
+                path,
+                is_dir,
+                is_link: record.is_link,
+                extents: if is_dir {
+                    Vec::new()
+                } else {
+                    vec![(record.extent, record.length)]
+                },
+                mtime: record.mtime,
+                mode: record.mode,
+            });
+        }
+        for (index, subdirectory) in subdirectories {
+            let prefix = format!("{}/", entries[index].path);
+            self.walk(subdirectory, &prefix, depth + 1, visited, entries)?;
+        }
+        Ok(())
+    }
+
+    /// Copy the data of a file
+    fn copy_data(&mut self, entry: &Entry, writer: &mut dyn Write) -> Result<(), io::Error> {
+        for &(extent, length) in &entry.extents {
+            self.inner.seek(SeekFrom::Start(extent as u64 * SECTOR))?;
+            let copied = io::copy(&mut (&mut self.inner).take(length), writer)?;
+            if copied < length {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "truncated iso image",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Remove the version and a trailing dot from an ISO 9660 name, "README.;1" is "README"
+fn plain_name(name: &str) -> String {
+    let name = name.rsplit_once(';').map_or(name, |(name, _)| name);
+    name.strip_suffix('.').unwrap_or(name).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+
+    /// A directory record, with Rock Ridge entries in its system use area
+    fn record(name: &[u8], extent: u32, length: u32, dir: bool, system_use: &[u8]) -> Vec<u8> {
+        let mut record = vec![0; 33];
+        record[2..6].copy_from_slice(&extent.to_le_bytes());
+        record[6..10].copy_from_slice(&extent.to_be_bytes());
+        record[10..14].copy_from_slice(&length.to_le_bytes());
+        record[14..18].copy_from_slice(&length.to_be_bytes());
+        // 2023-11-14 22:13:20 UTC, written as 23:13:20 one hour ahead
+        record[18..25].copy_from_slice(&[123, 11, 14, 23, 13, 20, 4]);
+        record[25] = if dir { 2 } else { 0 };
+        record[28] = 1;
+        record[32] = name.len() as u8;
+        record.extend_from_slice(name);
+        if name.len().is_multiple_of(2) {
+            record.push(0);
+        }
+        record.extend_from_slice(system_use);
+        if record.len() % 2 == 1 {
+            record.push(0);
+        }
+        record[0] = record.len() as u8;
+        record
+    }
+
+    fn rock_ridge(name: &str, mode: u32) -> Vec<u8> {
+        let mut entries = vec![b'P', b'X', 12, 1];
+        entries.extend(mode.to_le_bytes());
+        entries.extend(mode.to_be_bytes());
+        entries.extend([b'N', b'M', 5 + name.len() as u8, 1, 0]);
+        entries.extend(name.bytes());
+        entries
+    }
+
+    /// An image with a directory holding a file, with Rock Ridge names or without them
+    fn image(with_rock_ridge: bool) -> Vec<u8> {
+        let data = b"garbage data for testing";
+        let (root, dir, file) = (20u32, 21u32, 22u32);
+        let mut image = vec![0; 23 * SECTOR as usize];
+        let sector = |n: u32| n as usize * SECTOR as usize;
+
+        let rr = |name: &str, mode| {
+            if with_rock_ridge {
+                rock_ridge(name, mode)
+            } else {
+                Vec::new()
+            }
+        };
+        let root_self = record(
+            &[0],
+            root,
+            SECTOR as u32,
+            true,
+            &rr("", 0o40755)[..if with_rock_ridge { 12 } else { 0 }],
+        );
+        let mut descriptor = vec![1];
+        descriptor.extend(b"CD001\x01");
+        image[sector(16)..sector(16) + 7].copy_from_slice(&descriptor);
+        image[sector(16) + 156..sector(16) + 156 + 34].copy_from_slice(&root_self[..34]);
+        image[sector(17)..sector(17) + 7].copy_from_slice(b"\xffCD001\x01");
+
+        let mut records = root_self.clone();
+        records.extend(record(&[1], root, SECTOR as u32, true, &[]));
+        records.extend(record(
+            b"DIR",
+            dir,
+            SECTOR as u32,
+            true,
+            &rr("Dir", 0o40700),
+        ));
+        image[sector(root)..sector(root) + records.len()].copy_from_slice(&records);
+
+        let mut records = record(&[0], dir, SECTOR as u32, true, &[]);
+        records.extend(record(&[1], root, SECTOR as u32, true, &[]));
+        records.extend(record(
+            b"TEST.TXT;1",
+            file,
+            data.len() as u32,
+            false,
+            &rr("test.txt", 0o100640),
+        ));
+        image[sector(dir)..sector(dir) + records.len()].copy_from_slice(&records);
+        image[sector(file)..sector(file) + data.len()].copy_from_slice(data);
+        image
+    }
+
+    #[test]
+    fn list() -> Result<(), Box<dyn std::error::Error>> {
+        let list = |image: Vec<u8>| {
+            Iso::default().list(CmprssInput::Reader(CmprssRead {
+                reader: Box::new(Cursor::new(image)),
+                size: None,
+                name: None,
+            }))
+        };
+        let entries = list(image(true))?;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path, "Dir");
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[1].path, "Dir/test.txt");
+        assert_eq!(entries[1].size, 24);
+        assert_eq!(entries[1].mode, Some(0o640));
+        assert_eq!(entries[1].mtime, Some(1_700_000_000));
+
+        let entries = list(image(false))?;
+        assert_eq!(entries[1].path, "DIR/TEST.TXT");
+        assert_eq!(entries[1].mode, None);
+
+        assert!(list(vec![0; 40_000]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn extract() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = assert_fs::TempDir::new()?;
+        let file = dir.child("image.iso");
+        file.write_binary(&image(true))?;
+        let out = dir.child("out");
+        out.create_dir_all()?;
+        Iso::default().extract(
+            CmprssInput::Path(vec![file.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        out.child("Dir/test.txt").assert("garbage data for testing");
+        let mtime = fs::metadata(out.child("Dir/test.txt").path())?.modified()?;
+        assert_eq!(mtime, UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+
+        let result = Iso::default().compress(
+            CmprssInput::Path(vec![out.path().to_path_buf()]),
+            CmprssOutput::Path(dir.child("new.iso").path().to_path_buf()),
+        );
+        assert!(result.unwrap_err().to_string().contains("not supported"));
+        Ok(())
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(plain_name("TEST.TXT;1"), "TEST.TXT");
+        assert_eq!(plain_name("README.;1"), "README");
+        assert_eq!(plain_name("DIR"), "DIR");
+    }
+}
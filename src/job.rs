@@ -0,0 +1,1205 @@
+//! Inference of the compressor, action, and I/O for a job from the CLI arguments
+
+use crate::ar::Ar;
+use crate::brotli::Brotli;
+#[cfg(feature = "bzip2")]
+use crate::bzip2::Bzip2;
+use crate::checksum::{self, ChecksumAlgorithm};
+use crate::config;
+use crate::exclude::Exclude;
+use crate::gzip::Gzip;
+use crate::iso::Iso;
+use crate::limits::OutputLimits;
+use crate::lz4::Lz4;
+use crate::lzop::Lzop;
+use crate::lzw::Lzw;
+use crate::pipeline::Pipeline;
+use crate::rar::Rar;
+use crate::tar::Tar;
+use crate::transform::Transform;
+use crate::utils::*;
+#[cfg(feature = "xz")]
+use crate::xz::Xz;
+use crate::zip::Zip;
+use crate::zlib::Zlib;
+#[cfg(feature = "zstd")]
+use crate::zstd::Zstd;
+use crate::{external, http, magic, plugin, s3, split, ssh};
+use std::fmt;
+use std::fs::File;
+use std::io::{Cursor, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::{io, vec};
+
+/// Get the input filename or return a default file
+/// This file will be used to generate the output filename
+fn get_input_filename(input: &CmprssInput) -> Result<&Path, io::Error> {
+    match input {
+        CmprssInput::Path(paths) => {
+            if paths.is_empty() {
+                return Err(io::Error::other("error: no input specified"));
+            }
+            Ok(paths.first().unwrap())
+        }
+        CmprssInput::Reader(CmprssRead {
+            name: Some(name), ..
+        }) => Ok(Path::new(name)),
+        CmprssInput::Pipe(_) | CmprssInput::Reader(_) => Ok(Path::new("archive")),
+    }
+}
+
+/// The action a job takes
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Action {
+    Compress,
+    Extract,
+    Unknown,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Compress => write!(f, "compress"),
+            Action::Extract => write!(f, "extract"),
+            Action::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// The format of a compressor, with the level and threads it uses, for --dry-run
+pub fn describe_compressor(compressor: &dyn Compressor) -> String {
+    let mut description = format!("format: {}\n", compressor.name());
+    if let Some(level) = compressor.level() {
+        description.push_str(&format!("level: {}\n", level));
+    }
+    if let Some(threads) = compressor.threads() {
+        description.push_str(&format!("threads: {}\n", threads));
+    }
+    description
+}
+
+/// Defines a single compress/extract action to take.
+#[derive(Debug)]
+pub struct Job {
+    pub compressor: Box<dyn Compressor>,
+    pub input: CmprssInput,
+    pub output: CmprssOutput,
+    pub action: Action,
+    /// Background upload of the output, which must be finished after the job
+    pub upload: Option<Upload>,
+    /// System tool to retry with if the compressor fails
+    pub fallback: Option<Box<dyn Compressor>>,
+    /// Remove the input files once the output is complete
+    pub rm_source: bool,
+    /// Write a sidecar checksum of the output once it is complete
+    pub checksum: Option<ChecksumAlgorithm>,
+    /// Check the inputs against their sidecar checksums before running
+    pub verify_checksum: bool,
+    /// Only extract this entry of the archive, to the output stream
+    pub entry: Option<String>,
+}
+
+impl Job {
+    /// Describe what running the job would do, for --dry-run
+    pub fn plan(&self) -> String {
+        let mut plan = format!("action: {}\n", self.action);
+        plan.push_str(&describe_compressor(self.compressor.as_ref()));
+        match &self.input {
+            CmprssInput::Path(paths) => {
+                for path in paths {
+                    plan.push_str(&format!("input: {}\n", path.display()));
+                }
+            }
+            CmprssInput::Pipe(_) | CmprssInput::Reader(_) => plan.push_str("input: stdin\n"),
+        }
+        match &self.output {
+            CmprssOutput::Path(path) => plan.push_str(&format!("output: {}\n", path.display())),
+            CmprssOutput::Pipe(_) => plan.push_str("output: stdout\n"),
+            CmprssOutput::Writer(_) => plan.push_str("output: remote\n"),
+        }
+        if let Some(algorithm) = self.checksum {
+            plan.push_str(&format!("checksum: {}\n", algorithm.extension()));
+        }
+        if self.rm_source {
+            plan.push_str("remove inputs: yes\n");
+        }
+        plan
+    }
+
+    /// Run the job to completion
+    pub fn run(self) -> Result<(), io::Error> {
+        let sources = match self.rm_source {
+            true => removable_sources(&self.input, &self.output)?,
+            false => Vec::new(),
+        };
+        // Files are written next to the output and only moved into place once complete
+        let output_file = match &self.output {
+            CmprssOutput::Path(path) if !path.is_dir() && !is_special_file(path) => {
+                Some(path.clone())
+            }
+            _ => None,
+        };
+        if self.checksum.is_some() && output_file.is_none() {
+            return cmprss_error("--checksum needs the output to be a file");
+        }
+        if self.verify_checksum {
+            let CmprssInput::Path(paths) = &self.input else {
+                return cmprss_error("--verify-checksum needs the input to be a file");
+            };
+            for path in paths {
+                checksum::verify_sidecar(path)?;
+            }
+        }
+        let atomic = match &self.output {
+            CmprssOutput::Path(path) if !path.is_dir() && !is_special_file(path) => {
+                Some(AtomicOutput::new(path))
+            }
+            _ => None,
+        };
+        let output = match &atomic {
+            Some(atomic) => CmprssOutput::Path(atomic.tmp().to_path_buf()),
+            None => self.output,
+        };
+        // Only paths can be read again from the start for the fallback
+        let retry = match (&self.fallback, &self.input, &output) {
+            (Some(_), CmprssInput::Path(paths), CmprssOutput::Path(path)) => {
+                Some((paths.clone(), path.clone()))
+            }
+            _ => None,
+        };
+        let result = match &self.entry {
+            Some(entry) => self.compressor.cat(self.input, entry, output),
+            None => run_action(self.compressor.as_ref(), self.action, self.input, output),
+        };
+        let result = match (result, self.fallback, retry) {
+            (Err(e), Some(fallback), Some((paths, path))) => {
+                // Start the system tool from a clean output
+                if let Some(atomic) = &atomic {
+                    atomic.discard();
+                }
+                run_action(
+                    fallback.as_ref(),
+                    self.action,
+                    CmprssInput::Path(paths),
+                    CmprssOutput::Path(path),
+                )
+                .map_err(|fallback_error| {
+                    io::Error::other(format!(
+                        "{}, and the system {} also failed: {}",
+                        e,
+                        fallback.name(),
+                        fallback_error
+                    ))
+                })
+            }
+            (result, _, _) => result,
+        };
+        let result = match atomic {
+            Some(atomic) => atomic.finish(result),
+            None => result,
+        };
+        let upload_result = self.upload.map(|upload| upload.finish(result.is_ok()));
+        // Report the job's error first, the upload will usually fail because of it
+        result?;
+        if let Some(upload_result) = upload_result {
+            upload_result?;
+        }
+        if let (Some(algorithm), Some(path)) = (self.checksum, &output_file) {
+            checksum::write_sidecar(path, algorithm)?;
+        }
+        if !sources.is_empty() {
+            // Make sure the output is on disk before the only other copy is gone
+            if let Some(path) = output_file {
+                std::fs::File::open(path)?.sync_all()?;
+            }
+            for source in sources {
+                remove_source(&source)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Get the inputs to remove with `--rm-source`
+/// They are kept when the output is stdout or a special file, where it can't be recovered from.
+fn removable_sources(
+    input: &CmprssInput,
+    output: &CmprssOutput,
+) -> Result<Vec<PathBuf>, io::Error> {
+    let paths = match (input, output) {
+        (CmprssInput::Path(paths), CmprssOutput::Path(path)) if !is_special_file(path) => {
+            // Removing a directory containing the output would remove the output with it
+            let output = std::path::absolute(path)?;
+            if let Some(parent) = paths
+                .iter()
+                .find(|p| std::path::absolute(p).is_ok_and(|p| output.starts_with(p)))
+            {
+                return Err(io::Error::other(format!(
+                    "can't remove {} with --rm-source, the output is inside it",
+                    parent.display()
+                )));
+            }
+            paths.clone()
+        }
+        (CmprssInput::Path(paths), CmprssOutput::Writer(_)) => paths.clone(),
+        _ => Vec::new(),
+    };
+    Ok(paths)
+}
+
+/// Remove an input file, or a directory with everything in it
+pub(crate) fn remove_source(path: &Path) -> Result<(), io::Error> {
+    if std::fs::symlink_metadata(path)?.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+fn run_action(
+    compressor: &dyn Compressor,
+    action: Action,
+    input: CmprssInput,
+    output: CmprssOutput,
+) -> Result<(), io::Error> {
+    match action {
+        Action::Compress => compressor.compress(input, output),
+        Action::Extract => compressor.extract(input, output),
+        _ => Err(io::Error::other("Unknown action requested")),
+    }
+}
+
+/// Get a compressor from a filename
+pub fn get_compressor_from_filename(filename: &Path) -> Option<Box<dyn Compressor>> {
+    find_compressor(false, filename)
+}
+
+/// Get a compressor from the name of a format, like zstd, zst or tar.gz
+pub fn get_compressor_from_name(name: &str) -> Option<Box<dyn Compressor>> {
+    builtin_compressors()
+        .into_iter()
+        .find(|c| c.name() == name || c.extension() == name)
+        .or_else(|| {
+            let stream = get_compressor_from_name(name.strip_prefix("tar.")?)?;
+            match stream.is_stream() {
+                true => {
+                    Some(Box::new(Pipeline::new(Tar::default(), stream)) as Box<dyn Compressor>)
+                }
+                false => None,
+            }
+        })
+        .or_else(|| {
+            let filename = PathBuf::from(format!("cmprss.{}", name));
+            stream_compressors()
+                .into_iter()
+                .map(|stream| {
+                    Box::new(Pipeline::new(Tar::default(), stream)) as Box<dyn Compressor>
+                })
+                .find(|c| c.is_archive(&filename))
+                .or_else(|| find_layer(false, &filename))
+        })
+}
+
+/// The built in compressors that work on a single stream
+pub(crate) fn stream_compressors() -> Vec<Box<dyn Compressor>> {
+    let mut compressors: Vec<Box<dyn Compressor>> = vec![
+        Box::<Gzip>::default(),
+        #[cfg(feature = "xz")]
+        Box::<Xz>::default(),
+        #[cfg(feature = "bzip2")]
+        Box::<Bzip2>::default(),
+        Box::<Brotli>::default(),
+        #[cfg(feature = "zstd")]
+        Box::<Zstd>::default(),
+        Box::<Lz4>::default(),
+        Box::<Lzop>::default(),
+        Box::<Lzw>::default(),
+        Box::<Zlib>::default(),
+    ];
+    for compressor in &mut compressors {
+        config::registered().apply(compressor.as_mut());
+    }
+    compressors
+}
+
+/// The compressors built into cmprss
+fn builtin_compressors() -> Vec<Box<dyn Compressor>> {
+    let mut compressors: Vec<Box<dyn Compressor>> = vec![
+        Box::<Tar>::default(),
+        Box::<Zip>::default(),
+        Box::<Ar>::default(),
+        Box::<Rar>::default(),
+        Box::<Iso>::default(),
+    ];
+    for compressor in &mut compressors {
+        config::registered().apply(compressor.as_mut());
+    }
+    compressors.extend(stream_compressors());
+    compressors
+}
+
+/// Get a compressor from a filename, including chains like tar.gz
+/// Existing files without a known extension are detected from their contents.
+fn find_compressor(allow_external: bool, filename: &Path) -> Option<Box<dyn Compressor>> {
+    stream_compressors()
+        .into_iter()
+        .map(|stream| Box::new(Pipeline::new(Tar::default(), stream)) as Box<dyn Compressor>)
+        .find(|c| c.is_archive(filename))
+        .or_else(|| find_layer(allow_external, filename))
+        .or_else(|| {
+            let head = magic::read_file_head(filename)?;
+            detect_compressor(allow_external, &head)
+        })
+}
+
+/// Get a compressor from the first bytes of the contents
+/// Compressed streams are peeked into, so that a tar inside is handled as a chain like tar.gz.
+fn detect_compressor(allow_external: bool, head: &[u8]) -> Option<Box<dyn Compressor>> {
+    let name = magic::detect(head)?;
+    let compressor = builtin_compressors()
+        .into_iter()
+        .find(|c| c.name() == name)
+        .or_else(|| {
+            if !allow_external {
+                return None;
+            }
+            let tool = external::find(name)?;
+            Some(Box::new(tool) as Box<dyn Compressor>)
+        })?;
+    if stream_compressors().iter().any(|c| c.name() == name) {
+        // The head is only the start of the stream, so the decoder errors after what it can decode
+        let mut inner = Vec::new();
+        if let Ok(reader) = compressor.wrap_reader(Box::new(head)) {
+            let _ = reader.take(512).read_to_end(&mut inner);
+        }
+        if magic::detect(&inner) == Some("tar") {
+            return Some(Box::new(Pipeline::new(Tar::default(), compressor)));
+        }
+    }
+    Some(compressor)
+}
+
+/// Detect the compressor from the contents of piped input
+/// The bytes read are put back in front of the stream.
+fn detect_pipe(
+    allow_external: bool,
+    input: CmprssInput,
+) -> Result<(CmprssInput, Option<Box<dyn Compressor>>), io::Error> {
+    let CmprssInput::Pipe(mut pipe) = input else {
+        return Ok((input, None));
+    };
+    let head = magic::read_head(&mut pipe)?;
+    let compressor = detect_compressor(allow_external, &head);
+    let input = CmprssInput::Pipe(Box::new(Cursor::new(head).chain(pipe)));
+    Ok((input, compressor))
+}
+
+/// Get the compressor for only the last extension of a filename, so just the gz of tar.gz
+/// Falls back to the system tools if allowed.
+fn find_layer(allow_external: bool, filename: &Path) -> Option<Box<dyn Compressor>> {
+    let mut compressors = builtin_compressors();
+    for plugin in plugin::registered() {
+        compressors.push(Box::new(plugin.clone()));
+    }
+    compressors
+        .into_iter()
+        .find(|c| c.is_archive(filename))
+        .or_else(|| {
+            if !allow_external {
+                return None;
+            }
+            let tool = external::for_filename(filename)?;
+            Some(Box::new(tool) as Box<dyn Compressor>)
+        })
+}
+
+/// Whether an input is downloaded rather than read from a local path
+fn is_remote_input(input: &str) -> bool {
+    http::is_url(input) || s3::is_s3_url(input)
+}
+
+/// The whole archive when extracting the first part of a split one, like archive.tar.zst.001
+/// Only names of archives are taken as split, so that files like data.001 can still be compressed.
+fn split_archive(
+    allow_external: bool,
+    compressor: &Option<Box<dyn Compressor>>,
+    action: Action,
+    path: &Path,
+) -> Option<PathBuf> {
+    if action == Action::Compress {
+        return None;
+    }
+    let joined = split::joined_path(path)?;
+    let known = match compressor {
+        Some(compressor) => compressor.is_archive(&joined),
+        None => find_compressor(allow_external, &joined).is_some(),
+    };
+    known.then_some(joined)
+}
+
+/// Apply the options shared by all the formats to the compressor
+pub fn configure(
+    compressor: &mut dyn Compressor,
+    common_args: &CommonArgs,
+) -> Result<(), io::Error> {
+    compressor.set_threads(common_args.threads.unwrap_or_else(default_threads));
+    compressor.set_allow_unsafe_paths(common_args.allow_unsafe_paths);
+    compressor.set_strip_components(common_args.strip_components);
+    compressor.set_from_list(common_args.files_from.is_some());
+    if let Some(directory) = &common_args.directory {
+        compressor.set_directory(directory.clone());
+    }
+    compressor
+        .set_transform(Transform::new(&common_args.transform)?.junk_paths(common_args.junk_paths));
+    compressor.set_symlinks(Symlinks::new(common_args));
+    compressor.set_exclude(Exclude::new(&common_args.exclude_args)?);
+    compressor.set_limits(OutputLimits::new(&common_args.limit_args));
+    if common_args.reproducible {
+        compressor.set_reproducible(Reproducible::from_env()?);
+    }
+    if let Some(mtime) = MtimeOverride::new(common_args) {
+        compressor.set_mtime(mtime);
+    }
+    Ok(())
+}
+
+/// Check that writing to the output won't replace an existing file, unless forced
+/// Directories are extracted into, and special files like /dev/null are written through.
+pub(crate) fn check_overwrite(path: &Path, common_args: &CommonArgs) -> Result<(), io::Error> {
+    if common_args.overwrite_args.force
+        || !path.try_exists()?
+        || path.is_dir()
+        || is_special_file(path)
+    {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!(
+            "output path {} already exists, use --force to overwrite it",
+            path.display()
+        ),
+    ))
+}
+
+/// Check if the last existing file of the io_list is the output, for --force
+/// That is when it is named like an archive of the compressor, or when there is a single
+/// archive before it to extract.
+pub(crate) fn forced_output(
+    allow_external: bool,
+    io_list: &[String],
+    compressor: &Option<Box<dyn Compressor>>,
+) -> bool {
+    let [inputs @ .., output] = io_list else {
+        return false;
+    };
+    if inputs.is_empty() {
+        return false;
+    }
+    let output = Path::new(output);
+    let named_archive = match compressor {
+        Some(c) => c.is_archive(output),
+        None => find_layer(allow_external, output).is_some(),
+    };
+    let extracting = match inputs {
+        [input] => match compressor {
+            Some(c) => c.is_archive(Path::new(input)),
+            None => find_compressor(allow_external, Path::new(input)).is_some(),
+        },
+        _ => false,
+    };
+    named_archive || extracting
+}
+
+/// Convert an input path into a Path
+/// Files to archive are looked for in the directory of -C first
+fn get_path(input: &str, directory: Option<&Path>) -> Option<PathBuf> {
+    if let Some(path) = directory
+        .map(|directory| directory.join(input))
+        .filter(|path| path.try_exists().unwrap_or(false))
+    {
+        return Some(path);
+    }
+    let path = PathBuf::from(input);
+    if !path.try_exists().unwrap_or(false) {
+        return None;
+    }
+    Some(path)
+}
+
+/// Read the paths listed in a file, or in stdin for '-', for --files-from
+/// Paths are separated by lines, or by NUL bytes like the output of `find -print0`.
+fn read_file_list(list: &Path, null: bool) -> Result<Vec<String>, io::Error> {
+    let mut contents = Vec::new();
+    match list == Path::new("-") {
+        true => io::stdin().read_to_end(&mut contents)?,
+        false => File::open(list)?.read_to_end(&mut contents)?,
+    };
+    let separator = if null { b'\0' } else { b'\n' };
+    Ok(contents
+        .split(|byte| *byte == separator)
+        .map(|path| String::from_utf8_lossy(path.strip_suffix(b"\r").unwrap_or(path)).into_owned())
+        .filter(|path| !path.is_empty())
+        .collect())
+}
+
+/// Guess compressor/action from the two filenames
+/// The compressor may already be given
+fn guess_from_filenames(
+    allow_external: bool,
+    input: &[PathBuf],
+    output: &Path,
+    compressor: Option<Box<dyn Compressor>>,
+) -> (Option<Box<dyn Compressor>>, Action) {
+    if input.len() != 1 {
+        // Keep the options of a compressor that was given, like the level
+        if compressor.as_ref().is_some_and(|c| c.is_archive(output)) {
+            return (compressor, Action::Compress);
+        }
+        if let Some(guessed_compressor) = find_compressor(allow_external, output) {
+            return (Some(guessed_compressor), Action::Compress);
+        }
+        // In theory we could be extracting multiple files to a directory
+        // We'll fail somewhere else if that's not the case
+        return (compressor, Action::Extract);
+    }
+    let input = input.first().unwrap();
+
+    let guessed_compressor = find_compressor(allow_external, output);
+    let guessed_extractor = find_compressor(allow_external, input);
+
+    if let Some(c) = &compressor {
+        if c.is_archive(output) {
+            return (compressor, Action::Compress);
+        } else if c.is_archive(input) {
+            return (compressor, Action::Extract);
+        } else {
+            // Default to compressing
+            return (compressor, Action::Compress);
+        }
+    }
+
+    match (guessed_compressor, guessed_extractor) {
+        (None, None) => (None, Action::Unknown),
+        (Some(c), None) => (Some(c), Action::Compress),
+        (None, Some(e)) => (Some(e), Action::Extract),
+        (Some(c), Some(e)) => {
+            if c.name() == e.name() {
+                return (Some(c), Action::Unknown);
+            }
+            // Compare the input and output extensions to see if one has an extra extension
+            let input_file = input.file_name().unwrap().to_str().unwrap();
+            let input_ext = input.extension().unwrap_or_default();
+            let output_file = output.file_name().unwrap().to_str().unwrap();
+            let output_ext = output.extension().unwrap_or_default();
+            let guessed_output = input_file.to_string() + "." + output_ext.to_str().unwrap();
+            let guessed_input = output_file.to_string() + "." + input_ext.to_str().unwrap();
+            // Only one layer is added or removed, e.g. archive.tar to archive.tar.gz
+            if guessed_output == output_file {
+                (find_layer(allow_external, output), Action::Compress)
+            } else if guessed_input == input_file {
+                (find_layer(allow_external, input), Action::Extract)
+            } else {
+                (None, Action::Unknown)
+            }
+        }
+    }
+}
+
+/// Wrap a stream compressor in a tar archive when it is given directories or multiple files
+/// to compress, which is only done with `--tar`, and is an error otherwise.
+fn wrap_in_tar(
+    compressor: Option<Box<dyn Compressor>>,
+    action: Action,
+    input: &CmprssInput,
+    common_args: &CommonArgs,
+) -> Result<Option<Box<dyn Compressor>>, io::Error> {
+    let (Some(stream), CmprssInput::Path(paths)) = (&compressor, input) else {
+        return Ok(compressor);
+    };
+    let dir = paths.iter().find(|path| path.is_dir());
+    if action == Action::Extract
+        || !stream.is_stream()
+        || (dir.is_none() && paths.len() < 2)
+        || paths.iter().any(|path| stream.is_archive(path))
+    {
+        return Ok(compressor);
+    }
+    if !common_args.tar {
+        return match dir {
+            Some(dir) => Err(io::Error::other(format!(
+                "{} is a directory, which {} can't compress on its own, use --tar to archive it first",
+                dir.display(),
+                stream.name()
+            ))),
+            None => Ok(compressor),
+        };
+    }
+    Ok(compressor.map(|stream| Box::new(Pipeline::new(Tar::default(), stream)) as _))
+}
+
+/// Where an output named after its input goes below --output-dir
+/// Archives extracted to the current directory are extracted to the directory itself.
+fn in_output_dir(dir: &Path, name: &Path) -> PathBuf {
+    match name == Path::new(".") {
+        true => dir.to_path_buf(),
+        false => dir.join(name),
+    }
+}
+
+/// Parse the common args and determine the details of the job requested
+///
+/// The compressor, the action, and the inputs/outputs are inferred from the arguments
+/// when they aren't given explicitly.
+pub fn get_job(
+    compressor: Option<Box<dyn Compressor>>,
+    common_args: &CommonArgs,
+) -> Result<Job, io::Error> {
+    let inferred = compressor.is_none();
+    let mut compressor = compressor;
+    let allow_external = common_args.allow_external;
+    let mut action = {
+        if common_args.compress {
+            Action::Compress
+        } else if common_args.extract || common_args.decompress {
+            Action::Extract
+        } else {
+            Action::Unknown
+        }
+    };
+
+    if let Some(dir) = &common_args.directory {
+        if !dir.is_dir() {
+            return Err(io::Error::other(format!(
+                "{} is not a directory to use with -C",
+                dir.display()
+            )));
+        }
+    }
+    // Archives to extract are still relative to the current directory
+    let directory = common_args
+        .directory
+        .as_deref()
+        .filter(|_| action != Action::Extract);
+
+    let mut inputs = Vec::new();
+    // A URL input is inferred using the filename in the URL, and opened once the job is known
+    // S3 URLs are inputs like HTTP URLs, unless they are where the output goes.
+    let mut url = None;
+    // Likewise the first part of a split archive is inferred using the name of the whole archive
+    let mut split = None;
+    // '-' reads stdin and writes stdout, whether or not they look like pipes
+    let mut stdin_given = false;
+    let mut stdout_given = common_args.stdout;
+    if let Some(in_file) = &common_args.input {
+        if in_file == "-" {
+            stdin_given = true;
+        } else if is_remote_input(in_file) {
+            url = Some(in_file.clone());
+            inputs.push(http::url_filename(in_file));
+        } else {
+            match get_path(in_file, directory) {
+                Some(path) => match split_archive(allow_external, &compressor, action, &path) {
+                    Some(joined) => {
+                        split = Some(path);
+                        inputs.push(joined);
+                    }
+                    None => inputs.push(path),
+                },
+                None => {
+                    return Err(io::Error::other("Specified input path does not exist"));
+                }
+            }
+        }
+    }
+
+    let mut output = match &common_args.output {
+        Some(output) if output == "-" => {
+            stdout_given = true;
+            None
+        }
+        Some(output) => {
+            let path = Path::new(output);
+            check_overwrite(path, common_args)?;
+            Some(path)
+        }
+        None => None,
+    };
+
+    // Process the io_list, check if there is an output first
+    let mut io_list = common_args.io_list.clone();
+    if output.is_none() && !stdout_given {
+        if let Some(possible_output) = common_args.io_list.last() {
+            let path = Path::new(possible_output);
+            // A lone '-' is stdin, like `gzip -`
+            let only_input = common_args.io_list.len() == 1 && common_args.input.is_none();
+            let url_input = common_args
+                .io_list
+                .first()
+                .is_some_and(|i| is_remote_input(i));
+            let stdin_input = !std::io::stdin().is_terminal()
+                && !common_args.ignore_pipes
+                && !common_args.ignore_stdin;
+            if possible_output == "-" {
+                if !only_input {
+                    stdout_given = true;
+                    io_list.pop();
+                }
+            } else if http::is_url(possible_output) {
+                // HTTP URLs are only supported as inputs
+            } else if s3::is_s3_url(possible_output)
+                && common_args.io_list.len() == 1
+                && common_args.input.is_none()
+                && !stdin_input
+                && action != Action::Compress
+            {
+                // A lone S3 URL with nothing else to compress is the input
+            } else if directory.is_some_and(|directory| directory.join(path).exists()) {
+                // A file in the directory of -C is one to archive
+            } else if !path.try_exists()? {
+                // Use the given path if it doesn't exist
+                output = Some(path);
+                io_list.pop();
+            } else if common_args.overwrite_args.force
+                && path.is_file()
+                && forced_output(allow_external, &common_args.io_list, &compressor)
+            {
+                // An existing file is only replaced when it is clearly the output
+                output = Some(path);
+                io_list.pop();
+            } else if is_special_file(path) && common_args.io_list.len() > 1 {
+                // A named pipe or device after the inputs is where the output goes
+                output = Some(path);
+                io_list.pop();
+            } else if path.is_dir() && url_input && common_args.io_list.len() > 1 {
+                // A URL input can't be combined with other inputs, so the directory is the output
+                output = Some(path);
+                io_list.pop();
+            } else if path.is_dir() {
+                match action {
+                    Action::Compress => {
+                        // A directory can potentially be a target output location or
+                        // an input, for now assume it is an input.
+                    }
+                    Action::Extract => {
+                        // Can extract to a directory, and it wouldn't make any sense as an input
+                        output = Some(path);
+                        io_list.pop();
+                    }
+                    _ => {
+                        // An archive followed by a directory is extracted into the directory
+                        let archive = match common_args.io_list.as_slice() {
+                            [archive, _] => Some(Path::new(archive)),
+                            _ => None,
+                        };
+                        if archive.is_some_and(|archive| {
+                            archive.is_file()
+                                && match &compressor {
+                                    Some(c) => c.is_archive(archive),
+                                    None => find_compressor(allow_external, archive).is_some(),
+                                }
+                        }) {
+                            output = Some(path);
+                            io_list.pop();
+                        }
+                        // TODO: otherwise we don't know if this is an input or output, assume we're
+                        // compressing this directory
+                        // This does cause problems for inferencing "cat archive.tar | cmprss tar ."
+                        // Probably need to add some special casing
+                    }
+                };
+            } else {
+                // TODO: check for scenarios where we want to append to an existing archive
+            }
+        }
+    }
+
+    // Validate the specified inputs
+    // Everything in the io_list should be an input
+    for input in &io_list {
+        if input == "-" {
+            if stdin_given {
+                return Err(io::Error::other("stdin can only be given once with '-'"));
+            }
+            stdin_given = true;
+        } else if is_remote_input(input) {
+            url = Some(input.clone());
+            inputs.push(http::url_filename(input));
+        } else if let Some(path) = get_path(input, directory) {
+            match split_archive(allow_external, &compressor, action, &path) {
+                Some(joined) => {
+                    split = Some(path);
+                    inputs.push(joined);
+                }
+                None => inputs.push(path),
+            }
+        } else {
+            return Err(io::Error::other("Specified input path does not exist"));
+        }
+    }
+    if stdin_given && common_args.files_from.as_deref() == Some(Path::new("-")) {
+        return Err(io::Error::other(
+            "stdin can't be both the input and the list of --files-from",
+        ));
+    }
+    if let Some(list) = &common_args.files_from {
+        let listed = read_file_list(list, common_args.null)?;
+        if listed.is_empty() && inputs.is_empty() {
+            return Err(io::Error::other(format!(
+                "no files are listed in {}",
+                list.display()
+            )));
+        }
+        for input in listed {
+            match get_path(&input, directory) {
+                Some(path) if !inputs.contains(&path) => inputs.push(path),
+                Some(_) => {}
+                None => {
+                    return Err(io::Error::other(format!(
+                        "{}, listed in {}, does not exist",
+                        input,
+                        list.display()
+                    )))
+                }
+            }
+        }
+    }
+
+    if url.is_some() && inputs.len() > 1 {
+        return Err(io::Error::other(
+            "A URL input can't be combined with other inputs",
+        ));
+    }
+    if split.is_some() && inputs.len() > 1 {
+        return Err(io::Error::other(
+            "A split archive can't be combined with other inputs",
+        ));
+    }
+
+    if stdin_given && !inputs.is_empty() {
+        return Err(io::Error::other(
+            "stdin, given with '-', can't be combined with other inputs",
+        ));
+    }
+
+    // Fallback to stdin/stdout if we're missing files
+    let mut cmprss_input = match inputs.is_empty() {
+        true => {
+            if stdin_given
+                || (!std::io::stdin().is_terminal()
+                    && !&common_args.ignore_pipes
+                    && !&common_args.ignore_stdin)
+            {
+                match &common_args.stdin_name {
+                    Some(name) => CmprssInput::Reader(CmprssRead {
+                        reader: Box::new(std::io::stdin()),
+                        size: None,
+                        name: Some(name.clone()),
+                    }),
+                    None => CmprssInput::Pipe(Box::new(std::io::stdin())),
+                }
+            } else {
+                return Err(io::Error::other("No specified input"));
+            }
+        }
+        false => CmprssInput::Path(inputs),
+    };
+
+    // Named after the archive, like dir.tar.zst
+    compressor = wrap_in_tar(compressor, action, &cmprss_input, common_args)?;
+
+    if (output.is_some() || stdout_given) && common_args.output_dir.is_some() {
+        return Err(io::Error::other(
+            "--output-dir is where the output is named after the input, it can't also be given",
+        ));
+    }
+    let cmprss_output = match output {
+        Some(path) => CmprssOutput::Path(path.to_path_buf()),
+        None if stdout_given => CmprssOutput::Pipe(Box::new(std::io::stdout())),
+        None => {
+            // Asking for an output directory is asking for a file, even when stdout is piped
+            let piped = !std::io::stdout().is_terminal()
+                && !&common_args.ignore_pipes
+                && !&common_args.ignore_stdout
+                && common_args.output_dir.is_none()
+                && (common_args.directory.is_none() || action != Action::Extract);
+            if piped || common_args.to_stdout.is_some() {
+                CmprssOutput::Pipe(Box::new(std::io::stdout()))
+            } else {
+                match action {
+                    Action::Compress => {
+                        if compressor.is_none() {
+                            return Err(io::Error::other("Must specify a compressor"));
+                        }
+                        CmprssOutput::Path(PathBuf::from(
+                            compressor
+                                .as_ref()
+                                .unwrap()
+                                .default_compressed_filename(get_input_filename(&cmprss_input)?),
+                        ))
+                    }
+                    Action::Extract => {
+                        if compressor.is_none() {
+                            compressor =
+                                find_compressor(allow_external, get_input_filename(&cmprss_input)?);
+                            if compressor.is_none() {
+                                return Err(io::Error::other("Must specify a compressor"));
+                            }
+                        }
+                        CmprssOutput::Path(PathBuf::from(
+                            compressor
+                                .as_ref()
+                                .unwrap()
+                                .default_extracted_filename(get_input_filename(&cmprss_input)?),
+                        ))
+                    }
+                    Action::Unknown => {
+                        if let Some(c) = &compressor {
+                            // We know the compressor, does the input have the same extension?
+                            let input_filename = get_input_filename(&cmprss_input)?;
+                            if c.is_archive(input_filename) {
+                                action = Action::Extract;
+                                CmprssOutput::Path(PathBuf::from(
+                                    c.default_extracted_filename(input_filename),
+                                ))
+                            } else {
+                                action = Action::Compress;
+                                CmprssOutput::Path(PathBuf::from(
+                                    c.default_compressed_filename(input_filename),
+                                ))
+                            }
+                        } else {
+                            // Can still work if the input is an archive
+                            compressor =
+                                find_compressor(allow_external, get_input_filename(&cmprss_input)?);
+                            if compressor.is_none() {
+                                return Err(io::Error::other("Must specify a compressor"));
+                            }
+                            action = Action::Extract;
+                            CmprssOutput::Path(PathBuf::from(
+                                compressor
+                                    .as_ref()
+                                    .unwrap()
+                                    .default_extracted_filename(get_input_filename(&cmprss_input)?),
+                            ))
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    // Outputs named after their inputs go to --output-dir, or are extracted into -C
+    let cmprss_output = match (cmprss_output, &common_args.output_dir) {
+        (CmprssOutput::Path(path), Some(dir)) => CmprssOutput::Path(in_output_dir(dir, &path)),
+        (CmprssOutput::Path(path), None) if output.is_none() && action == Action::Extract => {
+            match &common_args.directory {
+                Some(dir) => CmprssOutput::Path(in_output_dir(dir, &path)),
+                None => CmprssOutput::Path(path),
+            }
+        }
+        (cmprss_output, _) => cmprss_output,
+    };
+
+    // Piped input has no name, so check its contents unless the output names the compressor
+    if compressor.is_none() && action != Action::Compress {
+        let named_by_output = action == Action::Unknown
+            && match &cmprss_output {
+                CmprssOutput::Path(path) => find_compressor(allow_external, path).is_some(),
+                _ => false,
+            };
+        if !named_by_output {
+            let detected;
+            (cmprss_input, detected) = detect_pipe(allow_external, cmprss_input)?;
+            if detected.is_some() {
+                compressor = detected;
+                action = Action::Extract;
+            }
+        }
+    }
+
+    // If we don't have the compressor/action, we can attempt to infer
+    if compressor.is_none() || action == Action::Unknown {
+        match action {
+            Action::Compress => {
+                // Look at the output name
+                if let CmprssOutput::Path(path) = &cmprss_output {
+                    compressor = find_compressor(allow_external, path);
+                }
+            }
+            Action::Extract => {
+                // Look at the input name
+                if let CmprssInput::Path(paths) = &cmprss_input {
+                    if paths.len() != 1 {
+                        // Can't guess if there are multiple inputs
+                        return Err(io::Error::other(
+                            "Can't guess compressor with multiple inputs",
+                        ));
+                    }
+                    compressor = find_compressor(allow_external, paths.first().unwrap());
+                }
+            }
+            Action::Unknown => match (&cmprss_input, &cmprss_output) {
+                (CmprssInput::Pipe(_) | CmprssInput::Reader(_), CmprssOutput::Path(path)) => {
+                    if let Some(c) = &compressor {
+                        if c.is_archive(path) {
+                            action = Action::Compress;
+                        } else {
+                            action = Action::Extract;
+                        }
+                    } else {
+                        compressor = find_compressor(allow_external, path);
+                        if compressor.is_some() {
+                            action = Action::Compress;
+                        } else {
+                            return Err(io::Error::other("Can't guess compressor to use"));
+                        }
+                    }
+                }
+                (CmprssInput::Path(paths), CmprssOutput::Pipe(_) | CmprssOutput::Writer(_)) => {
+                    if let Some(c) = &compressor {
+                        if c.is_archive(paths.first().unwrap()) {
+                            action = Action::Extract;
+                        } else {
+                            action = Action::Compress;
+                        }
+                    } else {
+                        if paths.len() != 1 {
+                            return Err(io::Error::other(
+                                "Can't guess compressor with multiple inputs",
+                            ));
+                        }
+                        compressor = find_compressor(allow_external, paths.first().unwrap());
+                        if compressor.is_some() {
+                            action = Action::Extract;
+                        } else {
+                            return Err(io::Error::other("Can't guess compressor to use"));
+                        }
+                    }
+                }
+                (
+                    CmprssInput::Pipe(_) | CmprssInput::Reader(_),
+                    CmprssOutput::Pipe(_) | CmprssOutput::Writer(_),
+                ) => {
+                    action = Action::Compress;
+                }
+                (CmprssInput::Path(paths), CmprssOutput::Path(path)) => {
+                    let (guessed_compressor, guessed_action) =
+                        guess_from_filenames(allow_external, paths, path, compressor);
+                    compressor = guessed_compressor;
+                    action = guessed_action;
+                }
+            },
+        }
+    }
+
+    // The compressor may have only now been inferred from the output name
+    compressor = wrap_in_tar(compressor, action, &cmprss_input, common_args)?;
+    if compressor.is_none() {
+        return Err(io::Error::other("Could not determine compressor to use"));
+    }
+    if action == Action::Unknown {
+        return Err(io::Error::other("Could not determine action to take"));
+    }
+
+    if matches!(cmprss_output, CmprssOutput::Pipe(_))
+        && action == Action::Compress
+        && std::io::stdout().is_terminal()
+        && !common_args.overwrite_args.force
+    {
+        return Err(io::Error::other(
+            "refusing to write compressed data to a terminal, use --force to write it anyway",
+        ));
+    }
+    if common_args.to_stdout.is_some() && (output.is_some() || action != Action::Extract) {
+        return Err(io::Error::other(
+            "--to-stdout only extracts an entry of an archive to stdout",
+        ));
+    }
+    if common_args.split_size.is_some() {
+        let to_file = match &cmprss_output {
+            CmprssOutput::Path(path) => {
+                let name = path.to_string_lossy();
+                !s3::is_s3_url(&name) && !ssh::is_ssh_url(&name) && !path.is_dir()
+            }
+            _ => false,
+        };
+        if action != Action::Compress || !to_file {
+            return Err(io::Error::other(
+                "--split-size only splits compressed output written to files",
+            ));
+        }
+    }
+
+    // Only start the download once we know what to do with it
+    let cmprss_input = match url {
+        Some(url) if common_args.dry_run => CmprssInput::Path(vec![PathBuf::from(url)]),
+        Some(url) if s3::is_s3_url(&url) => CmprssInput::Reader(s3::download(&url)?),
+        Some(url) => CmprssInput::Reader(http::open_url(&url, &common_args.http_args)?),
+        None => cmprss_input,
+    };
+    let cmprss_input = match split {
+        Some(first) if common_args.dry_run => CmprssInput::Path(vec![first]),
+        Some(first) => CmprssInput::Reader(split::open_parts(&first)?),
+        None => cmprss_input,
+    };
+
+    // Remote outputs are also only started once the job is known
+    let (cmprss_output, upload) = match cmprss_output {
+        output if common_args.dry_run => (output, None),
+        CmprssOutput::Path(path) if common_args.split_size.is_some() => {
+            check_overwrite(&split::part_path(&path, 1), common_args)?;
+            let size = common_args.split_size.unwrap().size_in_bytes as u64;
+            let (writer, upload) = split::write(&path, size)?;
+            (CmprssOutput::Writer(writer), Some(upload))
+        }
+        CmprssOutput::Path(path) if s3::is_s3_url(&path.to_string_lossy()) => {
+            let (writer, upload) = s3::upload(&path.to_string_lossy())?;
+            (CmprssOutput::Writer(writer), Some(upload))
+        }
+        CmprssOutput::Path(path) if ssh::is_ssh_url(&path.to_string_lossy()) => {
+            let (writer, upload) = ssh::upload(&path.to_string_lossy())?;
+            (CmprssOutput::Writer(writer), Some(upload))
+        }
+        output => (output, None),
+    };
+
+    if let CmprssOutput::Path(path) = &cmprss_output {
+        check_overwrite(path, common_args)?;
+    }
+
+    if let Some(dir) = &common_args.output_dir {
+        if !common_args.dry_run {
+            std::fs::create_dir_all(dir)?;
+        }
+    }
+
+    let mut compressor = compressor.unwrap();
+    if inferred {
+        crate::info!("inferred {} to {}", compressor.name(), action);
+    }
+    configure(compressor.as_mut(), common_args)?;
+    // Built in backends can fall back to the system tool for the same format
+    let fallback = if allow_external
+        && builtin_compressors()
+            .iter()
+            .any(|c| c.name() == compressor.name())
+    {
+        external::find(compressor.name()).map(|tool| Box::new(tool) as Box<dyn Compressor>)
+    } else {
+        None
+    };
+
+    Ok(Job {
+        compressor,
+        input: cmprss_input,
+        output: cmprss_output,
+        action,
+        upload,
+        fallback,
+        rm_source: common_args.rm_source,
+        checksum: common_args.checksum,
+        verify_checksum: common_args.verify_checksum,
+        entry: common_args.to_stdout.clone(),
+    })
+}
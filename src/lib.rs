@@ -0,0 +1,119 @@
+//! A compression multi-tool.
+//!
+//! Each supported format implements the [`Compressor`](utils::Compressor) trait, which
+//! compresses or extracts between [`CmprssInput`](utils::CmprssInput) and
+//! [`CmprssOutput`](utils::CmprssOutput), which are also exported at the root of the crate.
+//!
+//! ```no_run
+//! use cmprss::gzip::Gzip;
+//! use cmprss::{CmprssInput, CmprssOutput, Compressor};
+//!
+//! let gzip = Gzip::default();
+//! gzip.compress(
+//!     CmprssInput::Path(vec!["file.txt".into()]),
+//!     CmprssOutput::Path("file.txt.gz".into()),
+//! )?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Stream compressors can also be composed into other pipelines, either by copying
+//! between a reader and a writer or by wrapping them:
+//!
+//! ```
+//! use cmprss::gzip::Gzip;
+//! use cmprss::utils::Compressor;
+//! use std::io::Read;
+//!
+//! let gzip = Gzip::default();
+//! let mut compressed = Vec::new();
+//! gzip.compress_stream(&mut &b"some data"[..], &mut compressed)?;
+//!
+//! let mut data = String::new();
+//! gzip.wrap_reader(Box::new(&compressed[..]))?
+//!     .read_to_string(&mut data)?;
+//! assert_eq!(data, "some data");
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! The stream compressors, tar and zip report their progress through the
+//! [`ProgressHooks`](progress::ProgressHooks) in their `hooks` field, which also carries a
+//! token to cancel a running job:
+//!
+//! ```no_run
+//! use cmprss::gzip::Gzip;
+//! use cmprss::utils::{CmprssInput, CmprssOutput, Compressor};
+//! use std::sync::Arc;
+//!
+//! let mut gzip = Gzip::default();
+//! gzip.hooks.observer = Some(Arc::new(|read: u64, written: u64| {
+//!     println!("{read} bytes in, {written} bytes out");
+//! }));
+//! let cancel = gzip.hooks.cancel.clone();
+//! // Calling cancel.cancel() from another thread stops the job with an error
+//! gzip.compress(
+//!     CmprssInput::Path(vec!["file.txt".into()]),
+//!     CmprssOutput::Path("file.txt.gz".into()),
+//! )?;
+//! # drop(cancel);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! With the `async` feature, the `asynchronous` module provides the same streaming
+//! API for tokio's `AsyncRead` and `AsyncWrite`.
+//!
+//! The [`job`] module contains the logic the `cmprss` CLI uses to infer the format and
+//! action from the filenames.
+
+pub mod ar;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod batch;
+pub mod benchmark;
+pub mod brotli;
+#[cfg(feature = "bzip2")]
+pub mod bzip2;
+pub mod cat;
+pub mod checksum;
+pub mod config;
+pub mod convert;
+pub mod diff;
+pub mod dropin;
+pub mod exclude;
+pub mod external;
+pub mod grep;
+pub mod gzip;
+pub mod http;
+pub mod info;
+pub mod iso;
+pub mod job;
+pub mod limits;
+pub mod list;
+pub mod log;
+pub mod lz4;
+pub mod lzo;
+pub mod lzop;
+pub mod lzw;
+pub mod magic;
+pub mod owner;
+pub mod package;
+pub mod pipeline;
+pub mod plugin;
+pub mod progress;
+pub mod rar;
+pub mod s3;
+pub mod signal;
+pub mod split;
+pub mod ssh;
+pub mod stats;
+pub mod tar;
+pub mod transform;
+pub mod utils;
+pub mod watch;
+#[cfg(feature = "xz")]
+pub mod xz;
+pub mod zip;
+pub mod zlib;
+#[cfg(feature = "zstd")]
+pub mod zstd;
+
+pub use utils::{ArchiveEntry, CmprssInput, CmprssOutput, CmprssRead, CmprssWrite, Compressor};
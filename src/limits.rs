@@ -0,0 +1,131 @@
+//! Limits on the size of extracted output, against decompression bombs
+
+use crate::progress::ChunkSize;
+use clap::Args;
+use std::io::{self, Write};
+
+/// The ratio is only checked past this much output, small files can legitimately compress very well
+const RATIO_MIN_OUTPUT: u64 = 1024 * 1024;
+
+/// Options for limiting the output of an extraction
+#[derive(Args, Debug, Clone, Default)]
+pub struct LimitArgs {
+    /// Abort extracting once the output is larger than this, like '10gb', to protect against decompression bombs.
+    #[arg(long, value_name = "SIZE")]
+    pub max_output_size: Option<ChunkSize>,
+
+    /// Abort extracting once the output is this many times larger than the input, like 1000.
+    /// Only checked once more than 1 MiB has been extracted.
+    #[arg(long, value_name = "RATIO", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_ratio: Option<u64>,
+}
+
+/// Limits on the output of an extraction
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OutputLimits {
+    /// Largest total output in bytes
+    pub max_size: Option<u64>,
+    /// Largest ratio of output to input bytes
+    pub max_ratio: Option<u64>,
+}
+
+impl OutputLimits {
+    pub fn new(args: &LimitArgs) -> OutputLimits {
+        OutputLimits {
+            max_size: args.max_output_size.map(|size| size.size_in_bytes as u64),
+            max_ratio: args.max_ratio,
+        }
+    }
+
+    /// Check the bytes written so far against the limits
+    pub fn check(&self, input_read: u64, output_written: u64) -> Result<(), io::Error> {
+        if let Some(max_size) = self.max_size {
+            if output_written > max_size {
+                return Err(io::Error::other(format!(
+                    "aborted, the output is larger than the --max-output-size of {} bytes",
+                    max_size
+                )));
+            }
+        }
+        if let Some(max_ratio) = self.max_ratio {
+            if output_written > RATIO_MIN_OUTPUT
+                && output_written > input_read.saturating_mul(max_ratio)
+            {
+                return Err(io::Error::other(format!(
+                    "aborted, the output is more than {} times larger than the input (--max-ratio)",
+                    max_ratio
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Wrap an output so that the limits are checked before every write
+    /// The ratio is only checked when the size of the input is known.
+    pub fn writer<W: Write>(&self, inner: W, input_size: Option<u64>) -> LimitedWriter<W> {
+        LimitedWriter {
+            inner,
+            limits: *self,
+            input_size: input_size.unwrap_or(u64::MAX),
+            written: 0,
+        }
+    }
+}
+
+/// Writer that fails instead of writing past the output limits
+pub struct LimitedWriter<W> {
+    inner: W,
+    limits: OutputLimits,
+    input_size: u64,
+    written: u64,
+}
+
+impl<W> LimitedWriter<W> {
+    /// Count output that was already written elsewhere, like the earlier files of an archive
+    pub fn after(mut self, written: u64) -> Self {
+        self.written = written;
+        self
+    }
+
+    /// Total bytes counted against the limits
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+}
+
+impl<W: Write> Write for LimitedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.limits
+            .check(self.input_size, self.written + buf.len() as u64)?;
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        let limits = OutputLimits {
+            max_size: Some(4 * 1024 * 1024),
+            max_ratio: Some(100),
+        };
+        assert!(limits.check(10, 1000).is_ok());
+        assert!(limits.check(1, 1024 * 1024).is_ok());
+        assert!(limits.check(1, 2 * 1024 * 1024).is_err());
+        assert!(limits.check(1024 * 1024, 5 * 1024 * 1024).is_err());
+        assert!(OutputLimits::default().check(1, u64::MAX).is_ok());
+
+        let mut writer = limits.writer(Vec::new(), None).after(4 * 1024 * 1024 - 4);
+        assert!(writer.write_all(b"data").is_ok());
+        assert!(writer.write_all(b"x").is_err());
+        assert_eq!(writer.written(), 4 * 1024 * 1024);
+    }
+}
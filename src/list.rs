@@ -0,0 +1,174 @@
+//! List the contents of an archive
+
+use crate::http::{self, HttpArgs};
+use crate::job::get_compressor_from_filename;
+use crate::utils::*;
+use crate::{s3, split};
+use clap::Args;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Args, Debug)]
+pub struct ListArgs {
+    /// Archive to list, a file, the first part of a split archive, or a URL
+    pub input: PathBuf,
+}
+
+/// The name of an archive to read entries from, used to find its format
+/// URLs are named by their path, and the first part of a split archive by the whole archive.
+pub(crate) fn archive_name(input: &Path) -> PathBuf {
+    let input_str = input.to_string_lossy();
+    if http::is_url(&input_str) || s3::is_s3_url(&input_str) {
+        return http::url_filename(&input_str);
+    }
+    split::joined_path(input)
+        .filter(|joined| get_compressor_from_filename(joined).is_some())
+        .unwrap_or_else(|| input.to_path_buf())
+}
+
+/// Open an archive to read entries from, downloading it if it is a URL
+pub(crate) fn open_archive(input: &Path) -> Result<CmprssInput, io::Error> {
+    let input_str = input.to_string_lossy();
+    if s3::is_s3_url(&input_str) {
+        return Ok(CmprssInput::Reader(s3::download(&input_str)?));
+    }
+    if http::is_url(&input_str) {
+        return Ok(CmprssInput::Reader(http::open_url(
+            &input_str,
+            &HttpArgs::default(),
+        )?));
+    }
+    if archive_name(input) != input {
+        return Ok(CmprssInput::Reader(split::open_parts(input)?));
+    }
+    Ok(CmprssInput::Path(vec![input.to_path_buf()]))
+}
+
+/// Format Unix permission bits like ls, e.g. drwxr-xr-x
+fn mode_string(entry: &ArchiveEntry) -> String {
+    let mut s = String::from(if entry.is_dir { "d" } else { "-" });
+    let Some(mode) = entry.mode else {
+        s.push_str("?????????");
+        return s;
+    };
+    for shift in [6, 3, 0] {
+        let bits = mode >> shift;
+        s.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        s.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+    s
+}
+
+/// Format an entry as a single line
+/// Columns are the permissions, size, compressed size, modification time (UTC) and path,
+/// followed by the comment of the entry if it has one.
+pub fn format_entry(entry: &ArchiveEntry) -> String {
+    let compressed = entry
+        .compressed_size
+        .map_or_else(|| "-".to_string(), |size| size.to_string());
+    let mtime = entry.mtime.map_or_else(
+        || "-".to_string(),
+        |mtime| {
+            let (year, month, day, hour, minute, _) = civil_from_unix(mtime);
+            format!("{year:04}-{month:02}-{day:02} {hour:02}:{minute:02}")
+        },
+    );
+    let mut line = format!(
+        "{}  {:>12}  {:>12}  {:>16}  {}",
+        mode_string(entry),
+        entry.size,
+        compressed,
+        mtime,
+        entry.path
+    );
+    if let Some(comment) = &entry.comment {
+        line.push_str("  # ");
+        line.push_str(comment);
+    }
+    line
+}
+
+/// Format an entry as a JSON object, with null for what the format doesn't record
+pub fn entry_json(entry: &ArchiveEntry) -> String {
+    let value = |v: Option<String>| v.unwrap_or_else(|| "null".to_string());
+    format!(
+        r#"{{"path":{},"is_dir":{},"size":{},"compressed_size":{},"mtime":{},"mode":{},"comment":{}}}"#,
+        json_string(&entry.path),
+        entry.is_dir,
+        entry.size,
+        value(entry.compressed_size.map(|size| size.to_string())),
+        value(entry.mtime.map(|mtime| mtime.to_string())),
+        value(entry.mode.map(|mode| mode.to_string())),
+        value(entry.comment.as_deref().map(json_string)),
+    )
+}
+
+/// Print the entries of an archive to stdout, after the comment of the archive if it has one
+/// As JSON, the archive is printed as a single object with its comment and entries.
+pub fn list(args: &ListArgs, output: OutputFormat) -> Result<(), io::Error> {
+    let Some(compressor) = get_compressor_from_filename(&archive_name(&args.input)) else {
+        return cmprss_error("unable to determine the format of the archive");
+    };
+    let entries = compressor.list(open_archive(&args.input)?)?;
+    // Downloads are only read once, so the comment of an archive at a URL isn't shown
+    let input_str = args.input.to_string_lossy();
+    let comment = match http::is_url(&input_str) || s3::is_s3_url(&input_str) {
+        true => None,
+        false => compressor.comment(open_archive(&args.input)?)?,
+    };
+    let mut stdout = io::stdout().lock();
+    if output == OutputFormat::Json {
+        let entries: Vec<String> = entries.iter().map(entry_json).collect();
+        writeln!(
+            stdout,
+            r#"{{"archive":{},"format":{},"comment":{},"entries":[{}]}}"#,
+            json_string(&args.input.to_string_lossy()),
+            json_string(compressor.name()),
+            comment
+                .as_deref()
+                .map_or_else(|| "null".to_string(), json_string),
+            entries.join(",")
+        )?;
+        return Ok(());
+    }
+    if let Some(comment) = comment {
+        writeln!(stdout, "{}", comment)?;
+    }
+    for entry in entries {
+        writeln!(stdout, "{}", format_entry(&entry))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_format() {
+        let entry = ArchiveEntry {
+            path: "dir/test.txt".to_string(),
+            is_dir: false,
+            size: 24,
+            compressed_size: None,
+            mtime: Some(1369353600),
+            mode: Some(0o644),
+            comment: None,
+        };
+        assert_eq!(
+            format_entry(&entry),
+            "-rw-r--r--            24             -  2013-05-24 00:00  dir/test.txt"
+        );
+
+        let entry = ArchiveEntry {
+            comment: Some("a comment".to_string()),
+            ..entry
+        };
+        assert!(format_entry(&entry).ends_with("dir/test.txt  # a comment"));
+        assert_eq!(
+            entry_json(&entry),
+            r#"{"path":"dir/test.txt","is_dir":false,"size":24,"compressed_size":null,"mtime":1369353600,"mode":420,"comment":"a comment"}"#
+        );
+    }
+}
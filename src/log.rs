@@ -0,0 +1,110 @@
+//! Messages on stderr about what cmprss is doing, chosen with `-v` and `-q`
+//!
+//! The messages are written with the [`warn!`](crate::warn), [`info!`](crate::info) and
+//! [`debug!`](crate::debug) macros, and only shown at or above their verbosity.
+
+use clap::Args;
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How much is reported on stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only errors
+    Quiet,
+    /// Errors and warnings, like jobs skipped with --no-clobber
+    Normal,
+    /// Also the inferred formats and the skipped entries
+    Verbose,
+    /// Also every file added to or extracted from an archive
+    Debug,
+}
+
+impl Verbosity {
+    /// The verbosity given by `-q`, or by the number of `-v`
+    pub fn from_flags(quiet: bool, verbose: u8) -> Verbosity {
+        match (quiet, verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, 0) => Verbosity::Normal,
+            (false, 1) => Verbosity::Verbose,
+            (false, _) => Verbosity::Debug,
+        }
+    }
+}
+
+/// The `-v` and `-q` flags
+#[derive(Args, Debug, Default, Clone, Copy)]
+pub struct VerbosityArgs {
+    /// Report what cmprss is doing on stderr.
+    /// `-v` reports the inferred format and the skipped entries, `-vv` also every file.
+    #[arg(short, long, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Only report errors
+    #[arg(short, long)]
+    pub quiet: bool,
+}
+
+impl VerbosityArgs {
+    /// The verbosity given by the flags
+    pub fn verbosity(&self) -> Verbosity {
+        Verbosity::from_flags(self.quiet, self.verbose)
+    }
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// Set how much is reported for the rest of the process
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+/// Whether messages of this verbosity are shown
+pub fn enabled(verbosity: Verbosity) -> bool {
+    VERBOSITY.load(Ordering::Relaxed) >= verbosity as u8
+}
+
+/// Write a message to stderr, if its verbosity is shown
+pub fn log(verbosity: Verbosity, message: fmt::Arguments) {
+    if enabled(verbosity) {
+        eprintln!("cmprss: {}", message);
+    }
+}
+
+/// Report something the user should know about, hidden by `-q`
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Verbosity::Normal, format_args!($($arg)*))
+    };
+}
+
+/// Report a decision cmprss made, shown with `-v`
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Verbosity::Verbose, format_args!($($arg)*))
+    };
+}
+
+/// Report every file, shown with `-vv`
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Verbosity::Debug, format_args!($($arg)*))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbosity_flags() {
+        assert_eq!(Verbosity::from_flags(false, 0), Verbosity::Normal);
+        assert_eq!(Verbosity::from_flags(false, 1), Verbosity::Verbose);
+        assert_eq!(Verbosity::from_flags(false, 3), Verbosity::Debug);
+        assert_eq!(Verbosity::from_flags(true, 0), Verbosity::Quiet);
+        assert!(Verbosity::Debug > Verbosity::Verbose);
+    }
+}
@@ -0,0 +1,1044 @@
+//! lz4 compression backend, in the lz4 frame format of the `lz4` tool
+//!
+//! Levels 1 and 2 compress with the fast greedy search, levels 3 to 12 are the high compression
+//! (HC) mode, which searches further back for longer matches and looks one byte ahead.
+//! Blocks are compressed independently, and both kinds of blocks are extracted.
+
+use crate::{
+    limits::OutputLimits,
+    progress::{
+        copy_with_progress, progress_bar, ProgressArgs, ProgressDisplay, ProgressHooks,
+        ProgressObserver,
+    },
+    utils::*,
+};
+use clap::{Args, ValueEnum};
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+const MAGIC: u32 = 0x184d_2204;
+/// Skippable frames have any of the 16 magic numbers from this one
+const SKIPPABLE_MAGIC: u32 = 0x184d_2a50;
+const FLAG_VERSION: u8 = 0x40;
+const FLAG_INDEPENDENT: u8 = 0x20;
+const FLAG_BLOCK_CHECKSUM: u8 = 0x10;
+const FLAG_CONTENT_SIZE: u8 = 0x08;
+const FLAG_CONTENT_CHECKSUM: u8 = 0x04;
+const FLAG_DICTIONARY: u8 = 0x01;
+/// The high bit of a block size marks a block stored without compression
+const UNCOMPRESSED: u32 = 0x8000_0000;
+
+const MIN_MATCH: usize = 4;
+/// The last 5 bytes of a block are always literals
+const LAST_LITERALS: usize = 5;
+/// The last match starts at least 12 bytes before the end of a block
+const MF_LIMIT: usize = 12;
+const MAX_DISTANCE: usize = 0xffff;
+const HASH_BITS: u32 = 16;
+const NONE: u32 = u32::MAX;
+
+#[derive(Args, Debug)]
+pub struct Lz4Args {
+    #[clap(flatten)]
+    pub common_args: CommonArgs,
+
+    #[clap(flatten)]
+    pub progress_args: ProgressArgs,
+
+    /// Level of compression.
+    /// This is an int 1-12, levels 3 and up use the slower high compression mode.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=12))]
+    pub level: u32,
+
+    /// Largest block to compress at once, larger blocks compress better but use more memory
+    #[arg(long, value_enum, default_value = "4M")]
+    pub block_size: BlockSize,
+
+    /// Leave out the checksum of the whole content at the end of the frame
+    #[arg(long)]
+    pub no_content_checksum: bool,
+}
+
+/// The block sizes of the lz4 frame format
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum BlockSize {
+    #[value(name = "64K")]
+    Max64K,
+    #[value(name = "256K")]
+    Max256K,
+    #[value(name = "1M")]
+    Max1M,
+    #[value(name = "4M")]
+    Max4M,
+}
+
+impl BlockSize {
+    /// The code of the block size in the frame header
+    fn code(self) -> u8 {
+        match self {
+            BlockSize::Max64K => 4,
+            BlockSize::Max256K => 5,
+            BlockSize::Max1M => 6,
+            BlockSize::Max4M => 7,
+        }
+    }
+
+    fn bytes(self) -> usize {
+        block_bytes(self.code())
+    }
+}
+
+/// The largest block for a block size code
+fn block_bytes(code: u8) -> usize {
+    1 << (8 + 2 * code as usize)
+}
+
+pub struct Lz4 {
+    pub level: u32, // 1-12
+    pub block_size: BlockSize,
+    pub content_checksum: bool,
+    pub progress_args: ProgressArgs,
+    pub hooks: ProgressHooks,
+}
+
+impl Default for Lz4 {
+    fn default() -> Self {
+        Lz4 {
+            level: 1,
+            block_size: BlockSize::Max4M,
+            content_checksum: true,
+            progress_args: ProgressArgs::default(),
+            hooks: ProgressHooks::default(),
+        }
+    }
+}
+
+impl Lz4 {
+    pub fn new(args: &Lz4Args) -> Lz4 {
+        Lz4 {
+            level: args.level,
+            block_size: args.block_size,
+            content_checksum: !args.no_content_checksum,
+            progress_args: args.progress_args,
+            hooks: ProgressHooks::default(),
+        }
+    }
+
+    fn check_level(&self) -> Result<(), io::Error> {
+        if !(1..=12).contains(&self.level) {
+            return Err(io::Error::other("Invalid compression level. Must be 1-12."));
+        }
+        Ok(())
+    }
+
+    fn encoder<W: Write>(&self, inner: W) -> Lz4Encoder<W> {
+        Lz4Encoder {
+            inner,
+            depth: match self.level {
+                1 => 1,
+                2 => 2,
+                level => 1 << (level - 1).min(11),
+            },
+            lazy: self.level >= 3,
+            block_size: self.block_size,
+            content_checksum: self.content_checksum.then(Xxh32::default),
+            header: true,
+            block: Vec::with_capacity(self.block_size.bytes()),
+            total_out: 0,
+            finished: false,
+        }
+    }
+}
+
+impl Compressor for Lz4 {
+    /// Full name for lz4, also used for extension
+    fn name(&self) -> &str {
+        "lz4"
+    }
+
+    fn is_stream(&self) -> bool {
+        true
+    }
+
+    fn set_limits(&mut self, limits: OutputLimits) {
+        self.hooks.limits = limits;
+    }
+
+    fn set_observer(&mut self, observer: Arc<dyn ProgressObserver>) {
+        self.hooks.observer = Some(observer);
+    }
+
+    fn set_level(&mut self, level: i32) {
+        self.level = level as u32;
+    }
+
+    fn level(&self) -> Option<i32> {
+        Some(self.level as i32)
+    }
+
+    fn set_progress(&mut self, progress: ProgressDisplay) {
+        self.progress_args.progress = progress;
+    }
+
+    fn progress(&self) -> ProgressDisplay {
+        self.progress_args.progress
+    }
+
+    /// Compress an input file or pipe to a lz4 frame
+    fn compress(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        self.check_level()?;
+        let (mut input_stream, file_size) = input.into_stream()?;
+        let bar = progress_bar(file_size, self.progress_args.progress, &output, &self.hooks);
+        let mut encoder = self.encoder(output.into_stream()?);
+        copy_with_progress(
+            &mut input_stream,
+            &mut encoder,
+            self.progress_args.chunk_size.size_in_bytes,
+            &self.hooks,
+            bar.as_ref(),
+            |e| e.total_out,
+        )?;
+        encoder.finish()?;
+        encoder.inner.flush()
+    }
+
+    /// Extract a lz4 file to a file or pipe
+    fn extract(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        let (mut input_stream, file_size) = input.into_stream()?;
+        let bar = progress_bar(file_size, self.progress_args.progress, &output, &self.hooks);
+        let output_stream = self.hooks.limits.writer(output.into_stream()?, file_size);
+        let mut decoder = Lz4Decoder {
+            inner: output_stream,
+            decoder: Decoder::default(),
+            out: Vec::new(),
+            total_out: 0,
+        };
+        copy_with_progress(
+            &mut input_stream,
+            &mut decoder,
+            self.progress_args.chunk_size.size_in_bytes,
+            &self.hooks,
+            bar.as_ref(),
+            |d| d.total_out,
+        )?;
+        decoder.decoder.finish()?;
+        decoder.inner.flush()
+    }
+
+    /// Wrap a writer with a lz4 encoder
+    fn wrap_writer<'a>(
+        &self,
+        writer: Box<dyn Write + Send + 'a>,
+    ) -> Result<Box<dyn Write + Send + 'a>, io::Error> {
+        self.check_level()?;
+        Ok(Box::new(self.encoder(writer)))
+    }
+
+    /// Wrap a reader with a lz4 decoder
+    fn wrap_reader<'a>(
+        &self,
+        reader: Box<dyn Read + Send + 'a>,
+    ) -> Result<Box<dyn Read + Send + 'a>, io::Error> {
+        Ok(Box::new(Lz4Reader {
+            inner: reader,
+            decoder: Decoder::default(),
+            buffer: Vec::new(),
+            position: 0,
+        }))
+    }
+
+    /// Compress a stream, finishing the lz4 frame explicitly to catch errors
+    fn compress_stream(
+        &self,
+        reader: &mut (dyn Read + Send),
+        writer: &mut (dyn Write + Send),
+    ) -> Result<(), io::Error> {
+        self.check_level()?;
+        let mut encoder = self.encoder(writer);
+        io::copy(reader, &mut encoder)?;
+        encoder.finish()?;
+        encoder.inner.flush()
+    }
+}
+
+/// The 32 bit xxHash, which lz4 frames use for their checksums
+#[derive(Clone)]
+struct Xxh32 {
+    lanes: [u32; 4],
+    /// Input that doesn't fill a 16 byte stripe yet
+    pending: Vec<u8>,
+    length: u64,
+}
+
+const PRIME1: u32 = 2_654_435_761;
+const PRIME2: u32 = 2_246_822_519;
+const PRIME3: u32 = 3_266_489_917;
+const PRIME4: u32 = 668_265_263;
+const PRIME5: u32 = 374_761_393;
+
+impl Default for Xxh32 {
+    fn default() -> Self {
+        Xxh32 {
+            lanes: [
+                PRIME1.wrapping_add(PRIME2),
+                PRIME2,
+                0,
+                0u32.wrapping_sub(PRIME1),
+            ],
+            pending: Vec::with_capacity(16),
+            length: 0,
+        }
+    }
+}
+
+impl Xxh32 {
+    fn round(lane: u32, value: u32) -> u32 {
+        lane.wrapping_add(value.wrapping_mul(PRIME2))
+            .rotate_left(13)
+            .wrapping_mul(PRIME1)
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (lane, value) in self.lanes.iter_mut().zip(stripe.chunks_exact(4)) {
+            *lane = Self::round(*lane, u32::from_le_bytes(value.try_into().unwrap()));
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        if !self.pending.is_empty() {
+            let n = data.len().min(16 - self.pending.len());
+            self.pending.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.pending.len() < 16 {
+                return;
+            }
+            let pending = std::mem::take(&mut self.pending);
+            self.stripe(&pending);
+        }
+        let mut stripes = data.chunks_exact(16);
+        for stripe in &mut stripes {
+            self.stripe(stripe);
+        }
+        self.pending.extend_from_slice(stripes.remainder());
+    }
+
+    fn digest(&self) -> u32 {
+        let [v1, v2, v3, v4] = self.lanes;
+        let mut hash = if self.length >= 16 {
+            v1.rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18))
+        } else {
+            PRIME5
+        };
+        hash = hash.wrapping_add(self.length as u32);
+        let mut words = self.pending.chunks_exact(4);
+        for word in &mut words {
+            let word = u32::from_le_bytes(word.try_into().unwrap());
+            hash = hash
+                .wrapping_add(word.wrapping_mul(PRIME3))
+                .rotate_left(17)
+                .wrapping_mul(PRIME4);
+        }
+        for &byte in words.remainder() {
+            hash = hash
+                .wrapping_add((byte as u32).wrapping_mul(PRIME5))
+                .rotate_left(11)
+                .wrapping_mul(PRIME1);
+        }
+        hash ^= hash >> 15;
+        hash = hash.wrapping_mul(PRIME2);
+        hash ^= hash >> 13;
+        hash = hash.wrapping_mul(PRIME3);
+        hash ^ (hash >> 16)
+    }
+}
+
+fn xxh32(data: &[u8]) -> u32 {
+    let mut hasher = Xxh32::default();
+    hasher.update(data);
+    hasher.digest()
+}
+
+fn hash(data: &[u8]) -> usize {
+    let value = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Write the rest of a length that didn't fit in the 4 bits of the token
+fn push_length(out: &mut Vec<u8>, mut length: usize) {
+    while length >= 255 {
+        out.push(255);
+        length -= 255;
+    }
+    out.push(length as u8);
+}
+
+/// Write a sequence, the literals before a match and then the match, if there is one
+fn push_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_length = matched.map_or(0, |(length, _)| length - MIN_MATCH);
+    out.push(((literals.len().min(15) as u8) << 4) | match_length.min(15) as u8);
+    if literals.len() >= 15 {
+        push_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((_, distance)) = matched {
+        out.extend_from_slice(&(distance as u16).to_le_bytes());
+        if match_length >= 15 {
+            push_length(out, match_length - 15);
+        }
+    }
+}
+
+/// Compress a block, trying up to `depth` earlier positions for each match
+/// With `lazy`, a match is put off by a byte when the next position has a longer one.
+fn compress_block(input: &[u8], depth: usize, lazy: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2 + 16);
+    if input.len() < MF_LIMIT + 1 {
+        push_sequence(&mut out, input, None);
+        return out;
+    }
+    let match_limit = input.len() - MF_LIMIT;
+    let end_limit = input.len() - LAST_LITERALS;
+    // The last position of each hash, and the position before each one with the same hash
+    let mut head = vec![NONE; 1 << HASH_BITS];
+    let mut previous = vec![NONE; match_limit + 1];
+    let mut inserted = 0;
+    let mut insert_until = |position: usize, previous: &mut [u32]| {
+        while inserted <= position.min(match_limit) {
+            let h = hash(&input[inserted..]);
+            previous[inserted] = head[h];
+            head[h] = inserted as u32;
+            inserted += 1;
+        }
+    };
+    let find = |position: usize, previous: &[u32]| {
+        let mut candidate = previous[position];
+        let mut best = (0, 0);
+        for _ in 0..depth {
+            if candidate == NONE || position - candidate as usize > MAX_DISTANCE {
+                break;
+            }
+            let start = candidate as usize;
+            let length = input[position..end_limit]
+                .iter()
+                .zip(&input[start..])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best.0 {
+                best = (length, position - start);
+            }
+            candidate = previous[start];
+        }
+        best
+    };
+
+    let mut literals = 0;
+    let mut position = 0;
+    while position <= match_limit {
+        insert_until(position, &mut previous);
+        // The candidate chain starts after the position itself
+        let (mut length, mut distance) = find(position, &previous);
+        if length < MIN_MATCH {
+            position += 1;
+            continue;
+        }
+        if lazy && position < match_limit {
+            insert_until(position + 1, &mut previous);
+            let next = find(position + 1, &previous);
+            if next.0 > length + 1 {
+                position += 1;
+                (length, distance) = next;
+            }
+        }
+        push_sequence(
+            &mut out,
+            &input[literals..position],
+            Some((length, distance)),
+        );
+        position += length;
+        literals = position;
+    }
+    push_sequence(&mut out, &input[literals..], None);
+    out
+}
+
+fn corrupt() -> io::Error {
+    io::Error::other("corrupt lz4 data")
+}
+
+/// Reads the sequences of a compressed block
+struct Input<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Input<'a> {
+    fn byte(&mut self) -> Result<usize, io::Error> {
+        let byte = *self.data.get(self.position).ok_or_else(corrupt)?;
+        self.position += 1;
+        Ok(byte as usize)
+    }
+
+    /// A length from the token, continued in the following bytes when it is 15
+    fn length(&mut self, initial: usize) -> Result<usize, io::Error> {
+        let mut length = initial;
+        if initial == 15 {
+            loop {
+                let extra = self.byte()?;
+                length += extra;
+                if extra != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(length)
+    }
+
+    fn literals(&mut self, count: usize) -> Result<&'a [u8], io::Error> {
+        let literals = self
+            .data
+            .get(self.position..self.position + count)
+            .ok_or_else(corrupt)?;
+        self.position += count;
+        Ok(literals)
+    }
+}
+
+/// Decompress a block onto the end of `out`, which holds the earlier data matches can reach
+fn decompress_block(data: &[u8], out: &mut Vec<u8>, max_size: usize) -> Result<(), io::Error> {
+    let limit = out.len() + max_size;
+    let mut input = Input { data, position: 0 };
+    loop {
+        let token = input.byte()?;
+        let count = input.length(token >> 4)?;
+        let literals = input.literals(count)?;
+        if out.len() + literals.len() > limit {
+            return Err(corrupt());
+        }
+        out.extend_from_slice(literals);
+        // The last sequence has only literals
+        if input.position == data.len() {
+            return Ok(());
+        }
+        let distance = input.byte()? | input.byte()? << 8;
+        let length = input.length(token & 15)? + MIN_MATCH;
+        if distance == 0 || distance > out.len() || out.len() + length > limit {
+            return Err(corrupt());
+        }
+        // Matches can overlap what they copy, so copy a byte at a time
+        let from = out.len() - distance;
+        for i in from..from + length {
+            out.push(out[i]);
+        }
+    }
+}
+
+/// Writes a lz4 frame of everything written to it to the inner writer
+struct Lz4Encoder<W: Write> {
+    inner: W,
+    /// How many earlier positions to try for each match
+    depth: usize,
+    lazy: bool,
+    block_size: BlockSize,
+    /// The checksum of all the data, if it's written at the end
+    content_checksum: Option<Xxh32>,
+    /// Whether the header still needs to be written
+    header: bool,
+    block: Vec<u8>,
+    total_out: u64,
+    finished: bool,
+}
+
+impl<W: Write> Lz4Encoder<W> {
+    fn write_out(&mut self, data: &[u8]) -> io::Result<()> {
+        self.inner.write_all(data)?;
+        self.total_out += data.len() as u64;
+        Ok(())
+    }
+
+    /// Write the header if it hasn't been, then the buffered block if there is one
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.header {
+            self.header = false;
+            let mut flags = FLAG_VERSION | FLAG_INDEPENDENT;
+            if self.content_checksum.is_some() {
+                flags |= FLAG_CONTENT_CHECKSUM;
+            }
+            let descriptor = [flags, self.block_size.code() << 4];
+            let mut header = MAGIC.to_le_bytes().to_vec();
+            header.extend_from_slice(&descriptor);
+            header.push((xxh32(&descriptor) >> 8) as u8);
+            self.write_out(&header)?;
+        }
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        if let Some(checksum) = &mut self.content_checksum {
+            checksum.update(&self.block);
+        }
+        let compressed = compress_block(&self.block, self.depth, self.lazy);
+        let mut block = Vec::with_capacity(compressed.len().min(self.block.len()) + 4);
+        if compressed.len() < self.block.len() {
+            block.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            block.extend_from_slice(&compressed);
+        } else {
+            block.extend_from_slice(&(self.block.len() as u32 | UNCOMPRESSED).to_le_bytes());
+            block.extend_from_slice(&self.block);
+        }
+        self.write_out(&block)?;
+        self.block.clear();
+        Ok(())
+    }
+
+    /// Write the last block, the end mark, and the checksum of the content
+    fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.write_block()?;
+        self.finished = true;
+        self.write_out(&0u32.to_le_bytes())?;
+        if let Some(checksum) = &self.content_checksum {
+            let checksum = checksum.digest();
+            self.write_out(&checksum.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Lz4Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.block_size.bytes();
+        let n = buf.len().min(size - self.block.len());
+        self.block.extend_from_slice(&buf[..n]);
+        if self.block.len() == size {
+            self.write_block()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for Lz4Encoder<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Reads the fields of the data buffered so far, None if it runs out
+struct Fields<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.position..self.position + n)?;
+        self.position += n;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+}
+
+/// Return from parsing until more data has been buffered
+macro_rules! need {
+    ($field:expr) => {
+        match $field {
+            Some(value) => value,
+            None => return Ok(None),
+        }
+    };
+}
+
+/// The settings of the current frame, from its header
+struct Frame {
+    independent: bool,
+    block_checksum: bool,
+    content_checksum: Option<Xxh32>,
+    content_size: Option<u64>,
+    max_block: usize,
+    /// The last 64KiB of output, which the blocks of a linked frame can reach into
+    history: Vec<u8>,
+    produced: u64,
+}
+
+/// The state of decoding, fed with the lz4 data as it comes
+#[derive(Default)]
+struct Decoder {
+    buffered: Vec<u8>,
+    /// The current frame, once its header has been read
+    frame: Option<Frame>,
+    /// Whether at least one frame ended, another can follow
+    ended: bool,
+}
+
+impl Decoder {
+    /// Parse the header of a frame, or skip a skippable frame and return None for it
+    fn header(fields: &mut Fields) -> Result<Option<Option<Frame>>, io::Error> {
+        let magic = need!(fields.u32());
+        if magic & 0xffff_fff0 == SKIPPABLE_MAGIC {
+            let size = need!(fields.u32()) as usize;
+            need!(fields.take(size));
+            return Ok(Some(None));
+        }
+        if magic != MAGIC {
+            return Err(io::Error::other("not a lz4 frame"));
+        }
+        let start = fields.position;
+        let flags = need!(fields.u8());
+        let block_code = need!(fields.u8()) >> 4;
+        if flags & 0xc0 != FLAG_VERSION || !(4..=7).contains(&block_code) {
+            return Err(io::Error::other("unsupported lz4 frame header"));
+        }
+        let content_size = if flags & FLAG_CONTENT_SIZE != 0 {
+            Some(need!(fields.u64()))
+        } else {
+            None
+        };
+        if flags & FLAG_DICTIONARY != 0 {
+            return Err(io::Error::other(
+                "lz4 frames with a dictionary aren't supported",
+            ));
+        }
+        let checksum = need!(fields.u8());
+        if checksum != (xxh32(&fields.data[start..fields.position - 1]) >> 8) as u8 {
+            return Err(io::Error::other("corrupt lz4 frame header"));
+        }
+        Ok(Some(Some(Frame {
+            independent: flags & FLAG_INDEPENDENT != 0,
+            block_checksum: flags & FLAG_BLOCK_CHECKSUM != 0,
+            content_checksum: (flags & FLAG_CONTENT_CHECKSUM != 0).then(Xxh32::default),
+            content_size,
+            max_block: block_bytes(block_code),
+            history: Vec::new(),
+            produced: 0,
+        })))
+    }
+
+    /// Parse and decompress a block, returning whether it's the end of the frame
+    fn block(
+        fields: &mut Fields,
+        frame: &mut Frame,
+        out: &mut Vec<u8>,
+    ) -> Result<Option<bool>, io::Error> {
+        let size = need!(fields.u32());
+        if size == 0 {
+            if let Some(checksum) = &frame.content_checksum {
+                if need!(fields.u32()) != checksum.digest() {
+                    return Err(io::Error::other(
+                        "lz4 checksum mismatch, the file is corrupt",
+                    ));
+                }
+            }
+            if frame
+                .content_size
+                .is_some_and(|size| size != frame.produced)
+            {
+                return Err(io::Error::other(
+                    "lz4 content size mismatch, the file is corrupt",
+                ));
+            }
+            return Ok(Some(true));
+        }
+        let stored = size & UNCOMPRESSED != 0;
+        let size = (size & !UNCOMPRESSED) as usize;
+        if size > frame.max_block {
+            return Err(corrupt());
+        }
+        let data = need!(fields.take(size));
+        if frame.block_checksum && need!(fields.u32()) != xxh32(data) {
+            return Err(io::Error::other(
+                "lz4 block checksum mismatch, the file is corrupt",
+            ));
+        }
+        let start = out.len();
+        if stored {
+            out.extend_from_slice(data);
+        } else if frame.independent {
+            decompress_block(data, out, frame.max_block)?;
+        } else {
+            let mut block = std::mem::take(&mut frame.history);
+            let history = block.len();
+            decompress_block(data, &mut block, frame.max_block)?;
+            out.extend_from_slice(&block[history..]);
+            block.drain(..block.len().saturating_sub(MAX_DISTANCE));
+            frame.history = block;
+        }
+        if stored && !frame.independent {
+            frame.history.extend_from_slice(data);
+            let excess = frame.history.len().saturating_sub(MAX_DISTANCE);
+            frame.history.drain(..excess);
+        }
+        if let Some(checksum) = &mut frame.content_checksum {
+            checksum.update(&out[start..]);
+        }
+        frame.produced += (out.len() - start) as u64;
+        Ok(Some(false))
+    }
+
+    /// Decode some more of the data, appending what it decodes to
+    fn decode(&mut self, data: &[u8], out: &mut Vec<u8>) -> Result<(), io::Error> {
+        self.buffered.extend_from_slice(data);
+        let mut fields = Fields {
+            data: &self.buffered,
+            position: 0,
+        };
+        loop {
+            let start = fields.position;
+            let parsed = match &mut self.frame {
+                // Frames can be concatenated, like gzip members
+                None => Self::header(&mut fields)?.map(|frame| match frame {
+                    Some(frame) => {
+                        self.frame = Some(frame);
+                        self.ended = false;
+                    }
+                    None => self.ended = true,
+                }),
+                Some(frame) => Self::block(&mut fields, frame, out)?.map(|ended| {
+                    if ended {
+                        self.frame = None;
+                        self.ended = true;
+                    }
+                }),
+            };
+            if parsed.is_none() {
+                fields.position = start;
+                break;
+            }
+        }
+        let consumed = fields.position;
+        self.buffered.drain(..consumed);
+        Ok(())
+    }
+
+    /// Check that the data ended at the end of a frame
+    fn finish(&self) -> Result<(), io::Error> {
+        if !self.ended || self.frame.is_some() || !self.buffered.is_empty() {
+            return Err(io::Error::other("unexpected end of lz4 file"));
+        }
+        Ok(())
+    }
+}
+
+/// Writes the decompressed data for everything written to it to the inner writer
+struct Lz4Decoder<W: Write> {
+    inner: W,
+    decoder: Decoder,
+    out: Vec<u8>,
+    total_out: u64,
+}
+
+impl<W: Write> Write for Lz4Decoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.clear();
+        self.decoder.decode(buf, &mut self.out)?;
+        self.inner.write_all(&self.out)?;
+        self.total_out += self.out.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads the decompressed data of the inner reader
+struct Lz4Reader<R: Read> {
+    inner: R,
+    decoder: Decoder,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> Read for Lz4Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut input = [0; 8 * 1024];
+        while self.position == self.buffer.len() {
+            self.buffer.clear();
+            self.position = 0;
+            let n = self.inner.read(&mut input)?;
+            if n == 0 {
+                self.decoder.finish()?;
+                return Ok(0);
+            }
+            self.decoder.decode(&input[..n], &mut self.buffer)?;
+        }
+        let n = buf.len().min(self.buffer.len() - self.position);
+        buf[..n].copy_from_slice(&self.buffer[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use predicates::prelude::*;
+
+    /// More than a 64K block of data, with some of it incompressible
+    fn sample() -> Vec<u8> {
+        let mut state: u32 = 1;
+        let mut data = b"garbage data for testing ".repeat(20_000);
+        data.extend((0..100_000).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        }));
+        data
+    }
+
+    #[test]
+    fn roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Lz4::default();
+
+        let file = assert_fs::NamedTempFile::new("test.txt")?;
+        file.write_binary(&sample())?;
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("archive.".to_owned() + compressor.extension());
+        archive.assert(predicate::path::missing());
+
+        // Roundtrip compress/extract
+        compressor.compress(
+            CmprssInput::Path(vec![file.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        archive.assert(predicate::path::is_file());
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(working_dir.child("test.txt").path().to_path_buf()),
+        )?;
+
+        // Assert the files are identical
+        working_dir
+            .child("test.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn stream_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let data = sample();
+        let mut sizes = Vec::new();
+        for (level, block_size, content_checksum) in [
+            (1, BlockSize::Max4M, true),
+            (2, BlockSize::Max64K, false),
+            (9, BlockSize::Max256K, true),
+            (12, BlockSize::Max1M, false),
+        ] {
+            let compressor = Lz4 {
+                level,
+                block_size,
+                content_checksum,
+                ..Lz4::default()
+            };
+
+            let mut compressed = Vec::new();
+            compressor.compress_stream(&mut &data[..], &mut compressed)?;
+            assert!(compressed.len() < data.len());
+            sizes.push(compressed.len());
+
+            let mut extracted = Vec::new();
+            compressor
+                .wrap_reader(Box::new(&compressed[..]))?
+                .read_to_end(&mut extracted)?;
+            assert_eq!(extracted, data);
+        }
+        // The high compression levels find longer matches
+        assert!(sizes[3] <= sizes[0]);
+        Ok(())
+    }
+
+    #[test]
+    fn xxh32_known_values() {
+        assert_eq!(xxh32(b""), 0x02cc_5d05);
+        let mut streaming = Xxh32::default();
+        let data = sample();
+        for chunk in data.chunks(7) {
+            streaming.update(chunk);
+        }
+        assert_eq!(streaming.digest(), xxh32(&data));
+    }
+
+    #[test]
+    fn known_frame() -> Result<(), Box<dyn std::error::Error>> {
+        // `lz4 -BX` of "hello hello hello hello hello\n", with a block checksum
+        let frame = [
+            0x04, 0x22, 0x4d, 0x18, 0x74, 0x40, 0xbd, 0x10, 0x00, 0x00, 0x00, 0x6f, 0x68, 0x65,
+            0x6c, 0x6c, 0x6f, 0x20, 0x06, 0x00, 0x00, 0x50, 0x65, 0x6c, 0x6c, 0x6f, 0x0a, 0x8f,
+            0xf1, 0x2d, 0x4f, 0x00, 0x00, 0x00, 0x00, 0x2d, 0x82, 0x03, 0x39,
+        ];
+        let mut extracted = String::new();
+        Lz4::default()
+            .wrap_reader(Box::new(&frame[..]))?
+            .read_to_string(&mut extracted)?;
+        assert_eq!(extracted, "hello hello hello hello hello\n");
+        Ok(())
+    }
+
+    #[test]
+    fn concatenated() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Lz4::default();
+        let mut compressed = Vec::new();
+        compressor.compress_stream(&mut &b"first "[..], &mut compressed)?;
+        // A skippable frame between the two
+        compressed.extend([0x50, 0x2a, 0x4d, 0x18, 0x02, 0x00, 0x00, 0x00, 0xff, 0xff]);
+        compressor.compress_stream(&mut &b"second"[..], &mut compressed)?;
+
+        let mut extracted = Vec::new();
+        compressor.extract_stream(&mut &compressed[..], &mut extracted)?;
+        assert_eq!(extracted, b"first second");
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_files() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Lz4::default();
+        let mut compressed = Vec::new();
+        compressor.compress_stream(&mut &sample()[..], &mut compressed)?;
+
+        // Truncated
+        let result = compressor.extract_stream(&mut &compressed[..1000], &mut Vec::new());
+        assert!(result.is_err());
+
+        // A changed byte fails the content checksum
+        let mut changed = compressed.clone();
+        changed[5000] ^= 1;
+        let result = compressor.extract_stream(&mut &changed[..], &mut Vec::new());
+        assert!(result.is_err());
+
+        let result = compressor.extract_stream(&mut &b"not compressed"[..], &mut Vec::new());
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_compression_level() {
+        for level in [0, 13] {
+            let compressor = Lz4 {
+                level,
+                ..Lz4::default()
+            };
+            assert!(compressor
+                .compress_stream(&mut &b"data"[..], &mut Vec::new())
+                .is_err());
+        }
+    }
+}
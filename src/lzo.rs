@@ -0,0 +1,341 @@
+//! The LZO1X compression algorithm, which compresses the blocks of lzop files
+//!
+//! This is only the format of a single block, the lzop backend adds the file format around it.
+//! Blocks are independent, so matches never reach into an earlier block.
+
+use std::io;
+
+/// Largest distance of the 2 byte matches of 3 to 8 bytes
+const M2_MAX_OFFSET: usize = 0x0800;
+/// Largest distance of the matches with a 14 bit distance
+const M3_MAX_OFFSET: usize = 0x4000;
+/// Largest distance of any match
+const M4_MAX_OFFSET: usize = 0xbfff;
+const MIN_MATCH: usize = 4;
+const HASH_BITS: u32 = 14;
+/// The M4 match with a distance of 0 that ends every block
+const END: [u8; 3] = [0x11, 0x00, 0x00];
+const NONE: u32 = u32::MAX;
+
+fn corrupt() -> io::Error {
+    io::Error::other("corrupt lzo data")
+}
+
+fn hash(data: &[u8]) -> usize {
+    let value = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Write the extra length of a long literal run or match, as 0 bytes for each 255 and a rest
+fn push_length(out: &mut Vec<u8>, mut length: usize) {
+    while length > 255 {
+        out.push(0);
+        length -= 255;
+    }
+    out.push(length as u8);
+}
+
+/// Write the literals before a match, or at the end
+/// Up to 3 literals after a match are counted in the low bits of the match itself.
+fn push_literals(out: &mut Vec<u8>, literals: &[u8]) {
+    let count = literals.len();
+    if count == 0 {
+        return;
+    }
+    if out.is_empty() && count <= 238 {
+        out.push(17 + count as u8);
+    } else if count <= 3 {
+        let last = out.len() - 2;
+        out[last] |= count as u8;
+    } else if count <= 18 {
+        out.push((count - 3) as u8);
+    } else {
+        out.push(0);
+        push_length(out, count - 18);
+    }
+    out.extend_from_slice(literals);
+}
+
+/// Write a match, with the shortest encoding for its length and distance
+fn push_match(out: &mut Vec<u8>, length: usize, distance: usize) {
+    if length <= 8 && distance <= M2_MAX_OFFSET {
+        let offset = distance - 1;
+        out.push((((length - 1) << 5) | ((offset & 7) << 2)) as u8);
+        out.push((offset >> 3) as u8);
+        return;
+    }
+    let offset = if distance <= M3_MAX_OFFSET {
+        if length <= 33 {
+            out.push(0x20 | (length - 2) as u8);
+        } else {
+            out.push(0x20);
+            push_length(out, length - 33);
+        }
+        distance - 1
+    } else {
+        let offset = distance - 0x4000;
+        let high = ((offset >> 11) & 8) as u8;
+        if length <= 9 {
+            out.push(0x10 | high | (length - 2) as u8);
+        } else {
+            out.push(0x10 | high);
+            push_length(out, length - 9);
+        }
+        offset
+    };
+    out.push((offset << 2) as u8);
+    out.push((offset >> 6) as u8);
+}
+
+/// Compress a block, trying up to `depth` earlier positions for each match
+pub fn compress(input: &[u8], depth: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2 + 16);
+    // The last position of each hash, and the position before each one with the same hash
+    let mut head = vec![NONE; 1 << HASH_BITS];
+    let mut previous = vec![NONE; input.len()];
+    let mut insert = |position: usize, previous: &mut Vec<u32>| {
+        let h = hash(&input[position..]);
+        previous[position] = head[h];
+        head[h] = position as u32;
+        previous[position]
+    };
+
+    let mut literals = 0;
+    let mut position = 0;
+    while position + MIN_MATCH <= input.len() {
+        let mut candidate = insert(position, &mut previous);
+        let mut best = (0, 0);
+        for _ in 0..depth {
+            if candidate == NONE || position - candidate as usize > M4_MAX_OFFSET {
+                break;
+            }
+            let start = candidate as usize;
+            let length = input[position..]
+                .iter()
+                .zip(&input[start..])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best.0 {
+                best = (length, position - start);
+            }
+            candidate = previous[start];
+        }
+
+        let (length, distance) = best;
+        if length < MIN_MATCH {
+            position += 1;
+            continue;
+        }
+        push_literals(&mut out, &input[literals..position]);
+        push_match(&mut out, length, distance);
+        for skipped in position + 1..position + length {
+            if skipped + MIN_MATCH <= input.len() {
+                insert(skipped, &mut previous);
+            }
+        }
+        position += length;
+        literals = position;
+    }
+    push_literals(&mut out, &input[literals..]);
+    out.extend_from_slice(&END);
+    out
+}
+
+/// Reads the instructions of a compressed block
+struct Input<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Input<'_> {
+    fn byte(&mut self) -> Result<usize, io::Error> {
+        let byte = *self.data.get(self.position).ok_or_else(corrupt)?;
+        self.position += 1;
+        Ok(byte as usize)
+    }
+
+    fn le16(&mut self) -> Result<usize, io::Error> {
+        Ok(self.byte()? | self.byte()? << 8)
+    }
+
+    /// The extra length of a long literal run or match, the reverse of `push_length`
+    fn length(&mut self) -> Result<usize, io::Error> {
+        let mut length = 0;
+        loop {
+            match self.byte()? {
+                0 => length += 255,
+                byte => return Ok(length + byte),
+            }
+        }
+    }
+
+    fn literals(&mut self, count: usize) -> Result<&[u8], io::Error> {
+        let literals = self
+            .data
+            .get(self.position..self.position + count)
+            .ok_or_else(corrupt)?;
+        self.position += count;
+        Ok(literals)
+    }
+}
+
+/// Decompress a block that holds `size` bytes
+pub fn decompress(data: &[u8], size: usize) -> Result<Vec<u8>, io::Error> {
+    let mut out = Vec::with_capacity(size);
+    let mut input = Input { data, position: 0 };
+    let push = |out: &mut Vec<u8>, literals: &[u8]| {
+        if out.len() + literals.len() > size {
+            return Err(corrupt());
+        }
+        out.extend_from_slice(literals);
+        Ok(())
+    };
+
+    // The number of literals after the last instruction, 4 for a whole run of them
+    let mut state = 0;
+    if data.first().is_some_and(|&first| first > 17) {
+        let count = input.byte()? - 17;
+        push(&mut out, input.literals(count)?)?;
+        state = count.min(4);
+    }
+    loop {
+        let instruction = input.byte()?;
+        let (length, distance, trailing) = match instruction {
+            0..=15 if state == 0 => {
+                let count = match instruction {
+                    0 => 18 + input.length()?,
+                    _ => instruction + 3,
+                };
+                push(&mut out, input.literals(count)?)?;
+                state = 4;
+                continue;
+            }
+            0..=15 if state < 4 => (
+                2,
+                1 + (instruction >> 2) + (input.byte()? << 2),
+                instruction,
+            ),
+            0..=15 => (
+                3,
+                2049 + (instruction >> 2) + (input.byte()? << 2),
+                instruction,
+            ),
+            16..=31 => {
+                let length = match instruction & 7 {
+                    0 => 9 + input.length()?,
+                    length => length + 2,
+                };
+                let value = input.le16()?;
+                let offset = ((instruction & 8) << 11) + (value >> 2);
+                if offset == 0 {
+                    break;
+                }
+                (length, offset + 0x4000, value)
+            }
+            32..=63 => {
+                let length = match instruction & 31 {
+                    0 => 33 + input.length()?,
+                    length => length + 2,
+                };
+                let value = input.le16()?;
+                (length, 1 + (value >> 2), value)
+            }
+            _ => (
+                (instruction >> 5) + 1,
+                1 + ((instruction >> 2) & 7) + (input.byte()? << 3),
+                instruction,
+            ),
+        };
+
+        if distance > out.len() || out.len() + length > size {
+            return Err(corrupt());
+        }
+        // Matches can overlap what they copy, so copy a byte at a time
+        let start = out.len() - distance;
+        for i in start..start + length {
+            out.push(out[i]);
+        }
+        state = trailing & 3;
+        push(&mut out, input.literals(state)?)?;
+    }
+    if input.position != data.len() || out.len() != size {
+        return Err(corrupt());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(data: &[u8]) {
+        for depth in [1, 64] {
+            let compressed = compress(data, depth);
+            assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn roundtrips() {
+        roundtrip(b"");
+        roundtrip(b"a");
+        roundtrip(b"abc");
+        roundtrip(b"abcdabcdabcdabcdabcd");
+        roundtrip(&[7; 100_000]);
+
+        // Literal runs and matches of every length and distance
+        let mut state: u32 = 1;
+        let mut data = Vec::new();
+        for i in 0..200_000 {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let random = (state >> 16) as usize;
+            if i % 3 == 0 && data.len() > 50_000 {
+                let distance = 1 + random % 49_000;
+                let start = data.len() - distance;
+                for j in 0..random % 300 {
+                    data.push(data[start + j]);
+                }
+            } else {
+                data.push(random as u8);
+            }
+        }
+        roundtrip(&data);
+    }
+
+    #[test]
+    fn known_block() {
+        // A literal run then a match overlapping itself
+        let compressed = [
+            0x15,
+            b'a',
+            b'b',
+            b'c',
+            b'd',
+            0x20 | 14,
+            0x0c,
+            0x00,
+            0x11,
+            0,
+            0,
+        ];
+        assert_eq!(
+            decompress(&compressed, 20).unwrap(),
+            b"abcdabcdabcdabcdabcd"
+        );
+    }
+
+    #[test]
+    fn corrupt_blocks() {
+        let compressed = compress(b"abcdabcdabcdabcdabcd", 1);
+        // Cut short, or decompressing to more than expected
+        assert!(decompress(&compressed[..compressed.len() - 1], 20).is_err());
+        assert!(decompress(&compressed, 10).is_err());
+        // A match reaching before the start of the block
+        assert!(decompress(
+            &[0x15, b'a', b'b', b'c', b'd', 0x22, 0xfc, 0x00, 0x11, 0, 0],
+            8
+        )
+        .is_err());
+        assert!(decompress(&[], 0).is_err());
+    }
+}
@@ -0,0 +1,681 @@
+//! lzop compression backend, the .lzo files of the lzop tool
+//!
+//! lzop splits the data into blocks of up to 256 KiB, each compressed with LZO1X and followed
+//! by checksums. Blocks that don't shrink are stored as they are.
+
+use crate::{
+    limits::OutputLimits,
+    lzo,
+    progress::{
+        copy_with_progress, progress_bar, ProgressArgs, ProgressDisplay, ProgressHooks,
+        ProgressObserver,
+    },
+    utils::*,
+};
+use clap::Args;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+const MAGIC: [u8; 9] = [0x89, b'L', b'Z', b'O', 0x00, 0x0d, 0x0a, 0x1a, 0x0a];
+/// The version of lzop that the written files claim, and the newest one that can be read
+const VERSION: u16 = 0x1030;
+const LIB_VERSION: u16 = 0x2080;
+/// Files from this version on have the version needed to extract, the level and 64 bit times
+const VERSION_NEEDED: u16 = 0x0940;
+const BLOCK_SIZE: usize = 256 * 1024;
+/// Largest block lzop itself accepts
+const MAX_BLOCK_SIZE: usize = 64 * 1024 * 1024;
+
+const METHOD_LZO1X_1: u8 = 1;
+const METHOD_LZO1X_1_15: u8 = 2;
+const METHOD_LZO1X_999: u8 = 3;
+
+const F_ADLER32_D: u32 = 0x0001;
+const F_ADLER32_C: u32 = 0x0002;
+const F_H_EXTRA_FIELD: u32 = 0x0040;
+const F_CRC32_D: u32 = 0x0100;
+const F_CRC32_C: u32 = 0x0200;
+const F_MULTIPART: u32 = 0x0400;
+const F_H_FILTER: u32 = 0x0800;
+const F_H_CRC32: u32 = 0x1000;
+const F_OS_UNIX: u32 = 0x0300_0000;
+
+#[derive(Args, Debug)]
+pub struct LzopArgs {
+    #[clap(flatten)]
+    pub common_args: CommonArgs,
+
+    #[clap(flatten)]
+    pub progress_args: ProgressArgs,
+
+    /// Level of compression.
+    /// This is an int 1-9, levels 1-6 are all the fast default and 7-9 are much slower.
+    /// Also supports 'fast', and 'best'.
+    #[arg(long, default_value = "3")]
+    pub level: CompressionLevel,
+}
+
+pub struct Lzop {
+    pub level: u32, // 1-9
+    pub progress_args: ProgressArgs,
+    pub hooks: ProgressHooks,
+}
+
+impl Default for Lzop {
+    fn default() -> Self {
+        Lzop {
+            level: 3,
+            progress_args: ProgressArgs::default(),
+            hooks: ProgressHooks::default(),
+        }
+    }
+}
+
+impl Lzop {
+    pub fn new(args: &LzopArgs) -> Lzop {
+        Lzop {
+            level: args.level.level,
+            progress_args: args.progress_args,
+            hooks: ProgressHooks::default(),
+        }
+    }
+
+    fn check_level(&self) -> Result<(), io::Error> {
+        if self.level < 1 || self.level > 9 {
+            return Err(io::Error::other("Invalid compression level. Must be 1-9."));
+        }
+        Ok(())
+    }
+}
+
+impl Compressor for Lzop {
+    /// The standard extension for the lzop format.
+    fn extension(&self) -> &str {
+        "lzo"
+    }
+
+    /// Full name for lzop.
+    fn name(&self) -> &str {
+        "lzop"
+    }
+
+    fn is_stream(&self) -> bool {
+        true
+    }
+
+    fn set_limits(&mut self, limits: OutputLimits) {
+        self.hooks.limits = limits;
+    }
+
+    fn set_observer(&mut self, observer: Arc<dyn ProgressObserver>) {
+        self.hooks.observer = Some(observer);
+    }
+
+    fn set_level(&mut self, level: i32) {
+        self.level = level as u32;
+    }
+
+    fn level(&self) -> Option<i32> {
+        Some(self.level as i32)
+    }
+
+    fn set_progress(&mut self, progress: ProgressDisplay) {
+        self.progress_args.progress = progress;
+    }
+
+    fn progress(&self) -> ProgressDisplay {
+        self.progress_args.progress
+    }
+
+    /// Compress an input file or pipe to a lzop file
+    fn compress(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        self.check_level()?;
+        let (mut input_stream, file_size) = input.into_stream()?;
+        let bar = progress_bar(file_size, self.progress_args.progress, &output, &self.hooks);
+        let output_stream = output.into_stream()?;
+        let mut encoder = LzopEncoder::new(output_stream, self.level);
+        copy_with_progress(
+            &mut input_stream,
+            &mut encoder,
+            self.progress_args.chunk_size.size_in_bytes,
+            &self.hooks,
+            bar.as_ref(),
+            |e| e.total_out,
+        )?;
+        encoder.finish()?;
+        encoder.inner.flush()
+    }
+
+    /// Extract a lzop file to a file or pipe
+    fn extract(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        let (mut input_stream, file_size) = input.into_stream()?;
+        let bar = progress_bar(file_size, self.progress_args.progress, &output, &self.hooks);
+        let output_stream = self.hooks.limits.writer(output.into_stream()?, file_size);
+        let mut decoder = LzopDecoder {
+            inner: output_stream,
+            decoder: Decoder::default(),
+            out: Vec::new(),
+            total_out: 0,
+        };
+        copy_with_progress(
+            &mut input_stream,
+            &mut decoder,
+            self.progress_args.chunk_size.size_in_bytes,
+            &self.hooks,
+            bar.as_ref(),
+            |d| d.total_out,
+        )?;
+        decoder.decoder.finish()?;
+        decoder.inner.flush()
+    }
+
+    /// Wrap a writer with a lzop encoder
+    fn wrap_writer<'a>(
+        &self,
+        writer: Box<dyn Write + Send + 'a>,
+    ) -> Result<Box<dyn Write + Send + 'a>, io::Error> {
+        self.check_level()?;
+        Ok(Box::new(LzopEncoder::new(writer, self.level)))
+    }
+
+    /// Wrap a reader with a lzop decoder
+    fn wrap_reader<'a>(
+        &self,
+        reader: Box<dyn Read + Send + 'a>,
+    ) -> Result<Box<dyn Read + Send + 'a>, io::Error> {
+        Ok(Box::new(LzopReader {
+            inner: reader,
+            decoder: Decoder::default(),
+            buffer: Vec::new(),
+            position: 0,
+        }))
+    }
+
+    /// Compress a stream, finishing the lzop file explicitly to catch errors
+    fn compress_stream(
+        &self,
+        reader: &mut (dyn Read + Send),
+        writer: &mut (dyn Write + Send),
+    ) -> Result<(), io::Error> {
+        self.check_level()?;
+        let mut encoder = LzopEncoder::new(writer, self.level);
+        io::copy(reader, &mut encoder)?;
+        encoder.finish()?;
+        encoder.inner.flush()
+    }
+}
+
+/// The file header, written without a name or times so that the output is reproducible
+fn header(level: u32) -> Vec<u8> {
+    let method = if level >= 7 {
+        METHOD_LZO1X_999
+    } else {
+        METHOD_LZO1X_1
+    };
+    let mut fields = Vec::new();
+    fields.extend_from_slice(&VERSION.to_be_bytes());
+    fields.extend_from_slice(&LIB_VERSION.to_be_bytes());
+    fields.extend_from_slice(&VERSION_NEEDED.to_be_bytes());
+    fields.push(method);
+    fields.push(level as u8);
+    fields.extend_from_slice(&(F_ADLER32_D | F_ADLER32_C | F_OS_UNIX).to_be_bytes());
+    // Mode, the low and high halves of the modification time, and an empty name
+    fields.extend_from_slice(&0o100644u32.to_be_bytes());
+    fields.extend_from_slice(&[0; 8]);
+    fields.push(0);
+    let checksum = adler2::adler32_slice(&fields);
+
+    let mut header = MAGIC.to_vec();
+    header.extend_from_slice(&fields);
+    header.extend_from_slice(&checksum.to_be_bytes());
+    header
+}
+
+/// Writes a lzop file of everything written to it to the inner writer
+struct LzopEncoder<W: Write> {
+    inner: W,
+    /// How many earlier positions to try for each match
+    depth: usize,
+    header: Option<Vec<u8>>,
+    block: Vec<u8>,
+    total_out: u64,
+    finished: bool,
+}
+
+impl<W: Write> LzopEncoder<W> {
+    fn new(inner: W, level: u32) -> Self {
+        LzopEncoder {
+            inner,
+            depth: match level {
+                0..=6 => 1,
+                7 => 16,
+                8 => 64,
+                _ => 256,
+            },
+            header: Some(header(level)),
+            block: Vec::with_capacity(BLOCK_SIZE),
+            total_out: 0,
+            finished: false,
+        }
+    }
+
+    fn write_out(&mut self, data: &[u8]) -> io::Result<()> {
+        self.inner.write_all(data)?;
+        self.total_out += data.len() as u64;
+        Ok(())
+    }
+
+    /// Write the header if it hasn't been, then the buffered block if there is one
+    fn write_block(&mut self) -> io::Result<()> {
+        if let Some(header) = self.header.take() {
+            self.write_out(&header)?;
+        }
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let compressed = lzo::compress(&self.block, self.depth);
+        let mut block = Vec::with_capacity(compressed.len() + 16);
+        block.extend_from_slice(&(self.block.len() as u32).to_be_bytes());
+        if compressed.len() < self.block.len() {
+            block.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+            block.extend_from_slice(&adler2::adler32_slice(&self.block).to_be_bytes());
+            block.extend_from_slice(&adler2::adler32_slice(&compressed).to_be_bytes());
+            block.extend_from_slice(&compressed);
+        } else {
+            block.extend_from_slice(&(self.block.len() as u32).to_be_bytes());
+            block.extend_from_slice(&adler2::adler32_slice(&self.block).to_be_bytes());
+            block.extend_from_slice(&self.block);
+        }
+        self.write_out(&block)?;
+        self.block.clear();
+        Ok(())
+    }
+
+    /// Write the last block and the end of the file
+    fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.write_block()?;
+        self.finished = true;
+        self.write_out(&0u32.to_be_bytes())
+    }
+}
+
+impl<W: Write> Write for LzopEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(BLOCK_SIZE - self.block.len());
+        self.block.extend_from_slice(&buf[..n]);
+        if self.block.len() == BLOCK_SIZE {
+            self.write_block()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for LzopEncoder<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Reads the fields of the data buffered so far, None if it runs out
+struct Fields<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.position..self.position + n)?;
+        self.position += n;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+}
+
+/// Return from parsing until more data has been buffered
+macro_rules! need {
+    ($field:expr) => {
+        match $field {
+            Some(value) => value,
+            None => return Ok(None),
+        }
+    };
+}
+
+/// Check a checksum field of a block
+fn check(expected: Option<u32>, actual: impl Fn() -> u32) -> Result<(), io::Error> {
+    match expected {
+        Some(expected) if expected != actual() => Err(io::Error::other(
+            "lzop checksum mismatch, the file is corrupt",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// The state of decoding, fed with the lzop file as it comes
+#[derive(Default)]
+struct Decoder {
+    buffered: Vec<u8>,
+    /// The flags of the current file, once its header has been read
+    flags: Option<u32>,
+    /// Whether the last file ended, a new one can follow
+    ended: bool,
+}
+
+impl Decoder {
+    /// Parse the header, returning its flags
+    fn header(fields: &mut Fields) -> Result<Option<u32>, io::Error> {
+        let start = fields.position;
+        if need!(fields.take(MAGIC.len())) != MAGIC {
+            return Err(io::Error::other("not a lzop file"));
+        }
+        let version = need!(fields.u16());
+        need!(fields.u16());
+        if version >= VERSION_NEEDED && need!(fields.u16()) > VERSION {
+            return Err(io::Error::other(
+                "the lzop file needs a newer version of lzop",
+            ));
+        }
+        let method = need!(fields.u8());
+        if ![METHOD_LZO1X_1, METHOD_LZO1X_1_15, METHOD_LZO1X_999].contains(&method) {
+            return Err(io::Error::other(format!(
+                "unsupported lzop compression method {}",
+                method
+            )));
+        }
+        if version >= VERSION_NEEDED {
+            need!(fields.u8());
+        }
+        let flags = need!(fields.u32());
+        if flags & (F_H_FILTER | F_MULTIPART) != 0 {
+            return Err(io::Error::other(
+                "lzop filters and multipart files aren't supported",
+            ));
+        }
+        // Mode and modification time, which aren't restored
+        need!(fields.take(if version >= VERSION_NEEDED { 12 } else { 8 }));
+        let name_length = need!(fields.u8()) as usize;
+        need!(fields.take(name_length));
+        let checksum = need!(fields.u32());
+        let header = &fields.data[start + MAGIC.len()..fields.position - 4];
+        let actual = if flags & F_H_CRC32 != 0 {
+            crc32fast::hash(header)
+        } else {
+            adler2::adler32_slice(header)
+        };
+        if checksum != actual {
+            return Err(io::Error::other("corrupt lzop header"));
+        }
+        if flags & F_H_EXTRA_FIELD != 0 {
+            let length = need!(fields.u32()) as usize;
+            need!(fields.take(length + 4));
+        }
+        Ok(Some(flags))
+    }
+
+    /// Parse and decompress a block, returning whether it's the end of the file
+    fn block(
+        fields: &mut Fields,
+        flags: u32,
+        out: &mut Vec<u8>,
+    ) -> Result<Option<bool>, io::Error> {
+        let size = need!(fields.u32()) as usize;
+        if size == 0 {
+            return Ok(Some(true));
+        }
+        let compressed_size = need!(fields.u32()) as usize;
+        if size > MAX_BLOCK_SIZE || compressed_size > size {
+            return Err(io::Error::other("corrupt lzop block"));
+        }
+        let compressed = compressed_size < size;
+        let mut checksum = |flag: u32, present: bool| -> Option<Option<u32>> {
+            if flags & flag != 0 && present {
+                fields.u32().map(Some)
+            } else {
+                Some(None)
+            }
+        };
+        let adler32_d = need!(checksum(F_ADLER32_D, true));
+        let crc32_d = need!(checksum(F_CRC32_D, true));
+        let adler32_c = need!(checksum(F_ADLER32_C, compressed));
+        let crc32_c = need!(checksum(F_CRC32_C, compressed));
+        let data = need!(fields.take(compressed_size));
+
+        check(adler32_c, || adler2::adler32_slice(data))?;
+        check(crc32_c, || crc32fast::hash(data))?;
+        let data = if compressed {
+            lzo::decompress(data, size)?
+        } else {
+            data.to_vec()
+        };
+        check(adler32_d, || adler2::adler32_slice(&data))?;
+        check(crc32_d, || crc32fast::hash(&data))?;
+        out.extend_from_slice(&data);
+        Ok(Some(false))
+    }
+
+    /// Decode some more of the file, appending what it decodes to
+    fn decode(&mut self, data: &[u8], out: &mut Vec<u8>) -> Result<(), io::Error> {
+        self.buffered.extend_from_slice(data);
+        let mut fields = Fields {
+            data: &self.buffered,
+            position: 0,
+        };
+        loop {
+            let start = fields.position;
+            let parsed = match self.flags {
+                // Files can be concatenated, like gzip members
+                None => Self::header(&mut fields)?.map(|flags| self.flags = Some(flags)),
+                Some(flags) => Self::block(&mut fields, flags, out)?.map(|ended| {
+                    if ended {
+                        self.flags = None;
+                        self.ended = true;
+                    }
+                }),
+            };
+            if parsed.is_none() {
+                fields.position = start;
+                break;
+            }
+            if self.flags.is_some() {
+                self.ended = false;
+            }
+        }
+        let consumed = fields.position;
+        self.buffered.drain(..consumed);
+        Ok(())
+    }
+
+    /// Check that the data ended at the end of a file
+    fn finish(&self) -> Result<(), io::Error> {
+        if !self.ended || self.flags.is_some() || !self.buffered.is_empty() {
+            return Err(io::Error::other("unexpected end of lzop file"));
+        }
+        Ok(())
+    }
+}
+
+/// Writes the decompressed data for everything written to it to the inner writer
+struct LzopDecoder<W: Write> {
+    inner: W,
+    decoder: Decoder,
+    out: Vec<u8>,
+    total_out: u64,
+}
+
+impl<W: Write> Write for LzopDecoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.clear();
+        self.decoder.decode(buf, &mut self.out)?;
+        self.inner.write_all(&self.out)?;
+        self.total_out += self.out.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads the decompressed data of the inner reader
+struct LzopReader<R: Read> {
+    inner: R,
+    decoder: Decoder,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> Read for LzopReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut input = [0; 8 * 1024];
+        while self.position == self.buffer.len() {
+            self.buffer.clear();
+            self.position = 0;
+            let n = self.inner.read(&mut input)?;
+            if n == 0 {
+                self.decoder.finish()?;
+                return Ok(0);
+            }
+            self.decoder.decode(&input[..n], &mut self.buffer)?;
+        }
+        let n = buf.len().min(self.buffer.len() - self.position);
+        buf[..n].copy_from_slice(&self.buffer[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use predicates::prelude::*;
+
+    /// More than a block of data, with some of it incompressible
+    fn sample() -> Vec<u8> {
+        let mut state: u32 = 1;
+        let mut data = b"garbage data for testing ".repeat(20_000);
+        data.extend((0..100_000).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        }));
+        data
+    }
+
+    #[test]
+    fn roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Lzop::default();
+
+        let file = assert_fs::NamedTempFile::new("test.txt")?;
+        file.write_binary(&sample())?;
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("archive.".to_owned() + compressor.extension());
+        archive.assert(predicate::path::missing());
+
+        // Roundtrip compress/extract
+        compressor.compress(
+            CmprssInput::Path(vec![file.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        archive.assert(predicate::path::is_file());
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(working_dir.child("test.txt").path().to_path_buf()),
+        )?;
+
+        // Assert the files are identical
+        working_dir
+            .child("test.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn stream_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        for level in [1, 9] {
+            let compressor = Lzop {
+                level,
+                ..Lzop::default()
+            };
+            let data = sample();
+
+            let mut compressed = Vec::new();
+            compressor.compress_stream(&mut &data[..], &mut compressed)?;
+            assert!(compressed.len() < data.len());
+
+            let mut extracted = Vec::new();
+            compressor
+                .wrap_reader(Box::new(&compressed[..]))?
+                .read_to_end(&mut extracted)?;
+            assert_eq!(extracted, data);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn concatenated() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Lzop::default();
+        let mut compressed = Vec::new();
+        compressor.compress_stream(&mut &b"first "[..], &mut compressed)?;
+        compressor.compress_stream(&mut &b""[..], &mut compressed)?;
+        compressor.compress_stream(&mut &b"second"[..], &mut compressed)?;
+
+        let mut extracted = Vec::new();
+        compressor.extract_stream(&mut &compressed[..], &mut extracted)?;
+        assert_eq!(extracted, b"first second");
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_files() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Lzop::default();
+        let mut compressed = Vec::new();
+        compressor.compress_stream(&mut &sample()[..], &mut compressed)?;
+
+        // Truncated
+        let mut extracted = Vec::new();
+        let result = compressor.extract_stream(&mut &compressed[..1000], &mut extracted);
+        assert!(result.is_err());
+
+        // A changed byte fails the checksums
+        let mut changed = compressed.clone();
+        changed[5000] ^= 1;
+        let result = compressor.extract_stream(&mut &changed[..], &mut Vec::new());
+        assert!(result.is_err());
+
+        let result = compressor.extract_stream(&mut &b"not compressed"[..], &mut Vec::new());
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    // Fail with a compression level of 0
+    #[test]
+    fn invalid_compression_level_0() {
+        let compressor = Lzop {
+            level: 0,
+            ..Lzop::default()
+        };
+        let mut compressed = Vec::new();
+        assert!(compressor
+            .compress_stream(&mut &b"data"[..], &mut compressed)
+            .is_err());
+    }
+}
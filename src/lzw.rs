@@ -0,0 +1,673 @@
+//! The .Z format of the unix `compress` tool, LZW compression with codes of 9 to 16 bits
+//!
+//! The codes are written in groups of 8, and a group is padded to its full size whenever
+//! the code width changes or the table is cleared, which every implementation has to copy.
+
+use crate::{
+    limits::OutputLimits,
+    progress::{
+        copy_with_progress, progress_bar, ProgressArgs, ProgressDisplay, ProgressHooks,
+        ProgressObserver,
+    },
+    utils::*,
+};
+use clap::Args;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+const MAGIC: [u8; 2] = [0x1f, 0x9d];
+/// Flag in the header for the table being cleared with the CLEAR code when it fills up
+const BLOCK_MODE: u8 = 0x80;
+const BITS_MASK: u8 = 0x1f;
+const INIT_BITS: u32 = 9;
+const INIT_MAX_CODE: u32 = (1 << INIT_BITS) - 1;
+const MAX_BITS: u32 = 16;
+const CLEAR: u32 = 256;
+const FIRST: u32 = 257;
+
+#[derive(Args, Debug)]
+pub struct LzwArgs {
+    #[clap(flatten)]
+    pub common_args: CommonArgs,
+
+    #[clap(flatten)]
+    pub progress_args: ProgressArgs,
+
+    /// Maximum size of the codes in bits, 9-16.
+    /// Old systems may need fewer bits to extract the file.
+    #[arg(long, default_value_t = MAX_BITS)]
+    pub bits: u32,
+}
+
+pub struct Lzw {
+    pub bits: u32, // 9-16
+    pub progress_args: ProgressArgs,
+    pub hooks: ProgressHooks,
+}
+
+impl Default for Lzw {
+    fn default() -> Self {
+        Lzw {
+            bits: MAX_BITS,
+            progress_args: ProgressArgs::default(),
+            hooks: ProgressHooks::default(),
+        }
+    }
+}
+
+impl Lzw {
+    pub fn new(args: &LzwArgs) -> Lzw {
+        Lzw {
+            bits: args.bits,
+            progress_args: args.progress_args,
+            hooks: ProgressHooks::default(),
+        }
+    }
+
+    fn check_bits(&self) -> Result<(), io::Error> {
+        if !(INIT_BITS..=MAX_BITS).contains(&self.bits) {
+            return Err(io::Error::other("Invalid number of bits. Must be 9-16."));
+        }
+        Ok(())
+    }
+}
+
+impl Compressor for Lzw {
+    /// The standard extension for the compress format.
+    fn extension(&self) -> &str {
+        "Z"
+    }
+
+    /// Full name for the compress format.
+    fn name(&self) -> &str {
+        "lzw"
+    }
+
+    fn is_stream(&self) -> bool {
+        true
+    }
+
+    fn set_limits(&mut self, limits: OutputLimits) {
+        self.hooks.limits = limits;
+    }
+
+    fn set_observer(&mut self, observer: Arc<dyn ProgressObserver>) {
+        self.hooks.observer = Some(observer);
+    }
+
+    fn set_progress(&mut self, progress: ProgressDisplay) {
+        self.progress_args.progress = progress;
+    }
+
+    fn progress(&self) -> ProgressDisplay {
+        self.progress_args.progress
+    }
+
+    /// Compress an input file or pipe to a .Z file
+    fn compress(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        self.check_bits()?;
+        let (mut input_stream, file_size) = input.into_stream()?;
+        let bar = progress_bar(file_size, self.progress_args.progress, &output, &self.hooks);
+        let output_stream = output.into_stream()?;
+        let mut encoder = LzwEncoder::new(output_stream, self.bits);
+        copy_with_progress(
+            &mut input_stream,
+            &mut encoder,
+            self.progress_args.chunk_size.size_in_bytes,
+            &self.hooks,
+            bar.as_ref(),
+            |e| e.total_out(),
+        )?;
+        encoder.finish()?;
+        encoder.inner.flush()
+    }
+
+    /// Extract a .Z file to a file or pipe
+    fn extract(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        let (mut input_stream, file_size) = input.into_stream()?;
+        let bar = progress_bar(file_size, self.progress_args.progress, &output, &self.hooks);
+        let output_stream = self.hooks.limits.writer(output.into_stream()?, file_size);
+        let mut decoder = LzwDecoder::new(output_stream);
+        copy_with_progress(
+            &mut input_stream,
+            &mut decoder,
+            self.progress_args.chunk_size.size_in_bytes,
+            &self.hooks,
+            bar.as_ref(),
+            |d| d.total_out(),
+        )?;
+        decoder.finish()
+    }
+
+    /// Wrap a writer with an LZW encoder
+    fn wrap_writer<'a>(
+        &self,
+        writer: Box<dyn Write + Send + 'a>,
+    ) -> Result<Box<dyn Write + Send + 'a>, io::Error> {
+        self.check_bits()?;
+        Ok(Box::new(LzwEncoder::new(writer, self.bits)))
+    }
+
+    /// Wrap a reader with an LZW decoder
+    fn wrap_reader<'a>(
+        &self,
+        reader: Box<dyn Read + Send + 'a>,
+    ) -> Result<Box<dyn Read + Send + 'a>, io::Error> {
+        Ok(Box::new(LzwReader {
+            inner: reader,
+            decoder: Decoder::default(),
+            buffer: Vec::new(),
+            position: 0,
+        }))
+    }
+
+    /// Compress a stream, finishing the LZW stream explicitly to catch errors
+    fn compress_stream(
+        &self,
+        reader: &mut (dyn Read + Send),
+        writer: &mut (dyn Write + Send),
+    ) -> Result<(), io::Error> {
+        self.check_bits()?;
+        let mut encoder = LzwEncoder::new(writer, self.bits);
+        io::copy(reader, &mut encoder)?;
+        encoder.finish()?;
+        encoder.inner.flush()
+    }
+}
+
+/// Number of codes to skip to the end of the current group of 8
+fn padding(codes: u32) -> u32 {
+    (8 - codes % 8) % 8
+}
+
+/// The largest code before codes of `n_bits` have to widen again
+/// The first codes always widen at `INIT_MAX_CODE`, even with `--bits 9`, as `compress` does.
+fn max_code(n_bits: u32, max_bits: u32) -> u32 {
+    if n_bits == max_bits {
+        1 << max_bits
+    } else {
+        (1 << n_bits) - 1
+    }
+}
+
+/// Writes the codes for everything written to it to the inner writer
+struct LzwEncoder<W: Write> {
+    inner: W,
+    max_bits: u32,
+    /// The table, from a prefix code and the next byte to the code of the string
+    table: HashMap<(u32, u8), u32>,
+    /// Code of the string matched so far, if any
+    current: Option<u32>,
+    n_bits: u32,
+    max_code: u32,
+    free_entry: u32,
+    /// Codes written with the current width, to pad the group they are in
+    codes: u32,
+    bits: u64,
+    n_buffered: u32,
+    out: Vec<u8>,
+    total_out: u64,
+    finished: bool,
+}
+
+impl<W: Write> LzwEncoder<W> {
+    fn new(inner: W, max_bits: u32) -> Self {
+        LzwEncoder {
+            inner,
+            max_bits,
+            table: HashMap::new(),
+            current: None,
+            n_bits: INIT_BITS,
+            max_code: INIT_MAX_CODE,
+            free_entry: FIRST,
+            codes: 0,
+            bits: 0,
+            n_buffered: 0,
+            out: vec![MAGIC[0], MAGIC[1], BLOCK_MODE | max_bits as u8],
+            total_out: 0,
+            finished: false,
+        }
+    }
+
+    fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    fn push_bits(&mut self, value: u32, n_bits: u32) {
+        self.bits |= (value as u64) << self.n_buffered;
+        self.n_buffered += n_bits;
+        while self.n_buffered >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.n_buffered -= 8;
+        }
+    }
+
+    /// Write a code, then widen the codes once the decoder's table outgrows them
+    fn push_code(&mut self, code: u32) {
+        self.push_bits(code, self.n_bits);
+        self.codes += 1;
+        let clear = code == CLEAR;
+        if clear || self.free_entry > self.max_code {
+            for _ in 0..padding(self.codes) {
+                self.push_bits(0, self.n_bits);
+            }
+            self.codes = 0;
+            self.n_bits = if clear { INIT_BITS } else { self.n_bits + 1 };
+            self.max_code = if clear {
+                INIT_MAX_CODE
+            } else {
+                max_code(self.n_bits, self.max_bits)
+            };
+        }
+    }
+
+    fn encode(&mut self, data: &[u8]) {
+        for &byte in data {
+            let Some(current) = self.current else {
+                self.current = Some(byte as u32);
+                continue;
+            };
+            if let Some(&code) = self.table.get(&(current, byte)) {
+                self.current = Some(code);
+                continue;
+            }
+            self.push_code(current);
+            if self.free_entry < 1 << self.max_bits {
+                self.table.insert((current, byte), self.free_entry);
+                self.free_entry += 1;
+            } else {
+                // Start over with an empty table, rather than keep using a stale one
+                self.push_code(CLEAR);
+                self.table.clear();
+                self.free_entry = FIRST;
+            }
+            self.current = Some(byte as u32);
+        }
+    }
+
+    fn write_out(&mut self) -> io::Result<()> {
+        self.inner.write_all(&self.out)?;
+        self.total_out += self.out.len() as u64;
+        self.out.clear();
+        Ok(())
+    }
+
+    /// Write the last code and the remaining bits
+    fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        if let Some(current) = self.current.take() {
+            self.push_code(current);
+        }
+        if self.n_buffered > 0 {
+            self.push_bits(0, 8 - self.n_buffered);
+        }
+        self.finished = true;
+        self.write_out()
+    }
+}
+
+impl<W: Write> Write for LzwEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.encode(buf);
+        if self.out.len() >= 64 * 1024 {
+            self.write_out()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_out()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for LzwEncoder<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// The state of decoding, fed with the compressed data as it comes
+struct Decoder {
+    header: Vec<u8>,
+    block_mode: bool,
+    max_bits: u32,
+    prefix: Vec<u16>,
+    suffix: Vec<u8>,
+    /// The previous code and the first byte of its string
+    previous: Option<(u32, u8)>,
+    n_bits: u32,
+    max_code: u32,
+    free_entry: u32,
+    /// Codes read with the current width, to skip the padding of their group
+    codes: u32,
+    skip_bits: u32,
+    bits: u32,
+    n_buffered: u32,
+    stack: Vec<u8>,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder {
+            header: Vec::with_capacity(3),
+            block_mode: false,
+            max_bits: MAX_BITS,
+            prefix: vec![0; 1 << MAX_BITS],
+            suffix: (0..1 << MAX_BITS).map(|code| code as u8).collect(),
+            previous: None,
+            n_bits: INIT_BITS,
+            max_code: INIT_MAX_CODE,
+            free_entry: FIRST,
+            codes: 0,
+            skip_bits: 0,
+            bits: 0,
+            n_buffered: 0,
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl Decoder {
+    fn read_header(&mut self) -> io::Result<()> {
+        if self.header[..2] != MAGIC {
+            return Err(io::Error::other("not a .Z file"));
+        }
+        self.block_mode = self.header[2] & BLOCK_MODE != 0;
+        self.max_bits = (self.header[2] & BITS_MASK) as u32;
+        if !(INIT_BITS..=MAX_BITS).contains(&self.max_bits) {
+            return Err(io::Error::other(format!(
+                "unsupported .Z file, it uses {} bit codes",
+                self.max_bits
+            )));
+        }
+        self.free_entry = if self.block_mode { FIRST } else { CLEAR };
+        Ok(())
+    }
+
+    /// Skip the rest of the group of codes, which is always a whole number of bytes
+    fn skip_group(&mut self) {
+        self.skip_bits = padding(self.codes) * self.n_bits;
+        self.codes = 0;
+        let skipped = self.skip_bits.min(self.n_buffered);
+        self.bits >>= skipped;
+        self.n_buffered -= skipped;
+        self.skip_bits -= skipped;
+    }
+
+    fn decode_code(&mut self, code: u32, out: &mut Vec<u8>) -> io::Result<()> {
+        let Some((previous, first)) = self.previous else {
+            if code > 255 {
+                return Err(io::Error::other("corrupt .Z file"));
+            }
+            self.previous = Some((code, code as u8));
+            out.push(code as u8);
+            return Ok(());
+        };
+        if code == CLEAR && self.block_mode {
+            self.skip_group();
+            self.n_bits = INIT_BITS;
+            self.max_code = INIT_MAX_CODE;
+            // The code read after a clear adds an unused entry at CLEAR itself
+            self.free_entry = FIRST - 1;
+            return Ok(());
+        }
+
+        self.stack.clear();
+        let mut string = code;
+        if code >= self.free_entry {
+            // The string being defined by this code, the previous one and its first byte
+            if code > self.free_entry {
+                return Err(io::Error::other("corrupt .Z file"));
+            }
+            self.stack.push(first);
+            string = previous;
+        }
+        while string > 255 {
+            if self.stack.len() >= 1 << MAX_BITS {
+                return Err(io::Error::other("corrupt .Z file"));
+            }
+            self.stack.push(self.suffix[string as usize]);
+            string = self.prefix[string as usize] as u32;
+        }
+        let first = string as u8;
+        self.stack.push(first);
+        out.extend(self.stack.iter().rev());
+
+        if self.free_entry < 1 << self.max_bits {
+            self.prefix[self.free_entry as usize] = previous as u16;
+            self.suffix[self.free_entry as usize] = first;
+            self.free_entry += 1;
+        }
+        self.previous = Some((code, first));
+        if self.free_entry > self.max_code {
+            self.skip_group();
+            self.n_bits += 1;
+            self.max_code = max_code(self.n_bits, self.max_bits);
+        }
+        Ok(())
+    }
+
+    /// Decode some more of the compressed data, appending what it decodes to
+    fn decode(&mut self, data: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        for &byte in data {
+            if self.header.len() < 3 {
+                self.header.push(byte);
+                if self.header.len() == 3 {
+                    self.read_header()?;
+                }
+                continue;
+            }
+            if self.skip_bits > 0 {
+                self.skip_bits -= 8;
+                continue;
+            }
+            self.bits |= (byte as u32) << self.n_buffered;
+            self.n_buffered += 8;
+            while self.n_buffered >= self.n_bits && self.skip_bits == 0 {
+                let code = self.bits & ((1 << self.n_bits) - 1);
+                self.bits >>= self.n_bits;
+                self.n_buffered -= self.n_bits;
+                self.codes += 1;
+                self.decode_code(code, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the data ended after the header
+    /// The bits left over are padding, there is no end marker.
+    fn finish(&self) -> io::Result<()> {
+        if self.header.len() < 3 {
+            return Err(io::Error::other("unexpected end of .Z file"));
+        }
+        Ok(())
+    }
+}
+
+/// Writes the decompressed data for everything written to it to the inner writer
+struct LzwDecoder<W: Write> {
+    inner: W,
+    decoder: Decoder,
+    out: Vec<u8>,
+    total_out: u64,
+}
+
+impl<W: Write> LzwDecoder<W> {
+    fn new(inner: W) -> Self {
+        LzwDecoder {
+            inner,
+            decoder: Decoder::default(),
+            out: Vec::new(),
+            total_out: 0,
+        }
+    }
+
+    fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.decoder.finish()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for LzwDecoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.clear();
+        self.decoder.decode(buf, &mut self.out)?;
+        self.inner.write_all(&self.out)?;
+        self.total_out += self.out.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads the decompressed data of the inner reader
+struct LzwReader<R: Read> {
+    inner: R,
+    decoder: Decoder,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> Read for LzwReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut input = [0; 8 * 1024];
+        while self.position == self.buffer.len() {
+            self.buffer.clear();
+            self.position = 0;
+            let n = self.inner.read(&mut input)?;
+            if n == 0 {
+                self.decoder.finish()?;
+                return Ok(0);
+            }
+            self.decoder.decode(&input[..n], &mut self.buffer)?;
+        }
+        let n = buf.len().min(self.buffer.len() - self.position);
+        buf[..n].copy_from_slice(&self.buffer[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use predicates::prelude::*;
+
+    /// Data that fills the table a few times, so the codes widen and the table is cleared
+    fn sample() -> Vec<u8> {
+        let mut state: u32 = 1;
+        (0..400_000)
+            .map(|i| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                // Mostly text-like with some noise, so both short and long strings repeat
+                if i % 7 == 0 {
+                    (state >> 16) as u8
+                } else {
+                    b"the quick brown fox "[i % 20]
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Lzw::default();
+
+        let file = assert_fs::NamedTempFile::new("test.txt")?;
+        file.write_binary(&sample())?;
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("archive.".to_owned() + compressor.extension());
+        archive.assert(predicate::path::missing());
+
+        // Roundtrip compress/extract
+        compressor.compress(
+            CmprssInput::Path(vec![file.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        archive.assert(predicate::path::is_file());
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(working_dir.child("test.txt").path().to_path_buf()),
+        )?;
+
+        // Assert the files are identical
+        working_dir
+            .child("test.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn stream_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        for bits in [9, 12, 16] {
+            let compressor = Lzw {
+                bits,
+                ..Lzw::default()
+            };
+            let data = sample();
+
+            let mut compressed = Vec::new();
+            compressor.compress_stream(&mut &data[..], &mut compressed)?;
+            assert_eq!(compressed[..3], [0x1f, 0x9d, 0x80 | bits as u8]);
+
+            let mut extracted = Vec::new();
+            compressor
+                .wrap_reader(Box::new(&compressed[..]))?
+                .read_to_end(&mut extracted)?;
+            assert_eq!(extracted, data);
+        }
+        Ok(())
+    }
+
+    // A small file in the layout other tools expect, `gzip -d` extracts the same data
+    #[test]
+    fn known_file() -> Result<(), Box<dyn std::error::Error>> {
+        let data = b"TOBEORNOTTOBEORTOBEORNOT";
+        let expected: &[u8] = &[
+            0x1f, 0x9d, 0x90, 0x54, 0x9e, 0x08, 0x29, 0xf2, 0x44, 0x8a, 0x93, 0x27, 0x54, 0x02,
+            0x0e, 0x2c, 0xa8, 0x90, 0xa0, 0x41, 0x84,
+        ];
+        let mut compressed = Vec::new();
+        Lzw::default().compress_stream(&mut &data[..], &mut compressed)?;
+        assert_eq!(compressed, expected);
+
+        let mut extracted = Vec::new();
+        Lzw::default().extract_stream(&mut &expected[..], &mut extracted)?;
+        assert_eq!(extracted, data);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_input() {
+        let mut extracted = Vec::new();
+        let result = Lzw::default().extract_stream(&mut &b"not compressed"[..], &mut extracted);
+        assert!(result.is_err());
+        let result = Lzw::default().extract_stream(&mut &[0x1f, 0x9d][..], &mut extracted);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn invalid_bits() {
+        let compressor = Lzw {
+            bits: 17,
+            ..Lzw::default()
+        };
+        let mut compressed = Vec::new();
+        assert!(compressor
+            .compress_stream(&mut &b"data"[..], &mut compressed)
+            .is_err());
+    }
+}
@@ -0,0 +1,114 @@
+//! Detection of formats from the first bytes of their contents
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Number of bytes to read to detect a format
+/// The tar header is 512 bytes, the rest leaves room to look for a tar inside a compressed stream,
+/// and reaches the first volume descriptor of an iso image at 32KiB.
+pub const HEAD_SIZE: usize = 36 * 1024;
+
+/// Signatures of the known formats, as (format name, offset, magic bytes)
+const SIGNATURES: &[(&str, usize, &[u8])] = &[
+    ("gzip", 0, &[0x1f, 0x8b]),
+    ("bzip2", 0, b"BZh"),
+    ("xz", 0, &[0xfd, b'7', b'z', b'X', b'Z', 0x00]),
+    ("zstd", 0, &[0x28, 0xb5, 0x2f, 0xfd]),
+    ("lz4", 0, &[0x04, 0x22, 0x4d, 0x18]),
+    ("lzw", 0, &[0x1f, 0x9d]),
+    // zlib headers with the default window, only for the common levels
+    ("zlib", 0, &[0x78, 0x9c]),
+    ("zlib", 0, &[0x78, 0xda]),
+    ("zlib", 0, &[0x78, 0x01]),
+    (
+        "lzop",
+        0,
+        &[0x89, b'L', b'Z', b'O', 0x00, 0x0d, 0x0a, 0x1a, 0x0a],
+    ),
+    ("zip", 0, b"PK\x03\x04"),
+    // An empty zip archive is only the end of the central directory
+    ("zip", 0, b"PK\x05\x06"),
+    ("tar", 257, b"ustar"),
+    ("ar", 0, b"!<arch>\n"),
+    // RAR 4 and RAR 5 share the start of their signature
+    ("rar", 0, b"Rar!\x1a\x07"),
+    // The first volume descriptor follows the 32KiB system area
+    ("iso", 32769, b"CD001"),
+];
+
+/// Detect the format of some data from its first bytes
+/// Returns the name of the format, matching the name of its compressor.
+pub fn detect(head: &[u8]) -> Option<&'static str> {
+    SIGNATURES
+        .iter()
+        .find(|(_, offset, magic)| head.get(*offset..offset + magic.len()) == Some(*magic))
+        .map(|(name, _, _)| *name)
+}
+
+/// Read the first bytes of a stream, up to `HEAD_SIZE`
+/// Reads from pipes can be short, so keep reading until there is enough data or the end.
+pub fn read_head(reader: &mut dyn Read) -> Result<Vec<u8>, io::Error> {
+    let mut head = Vec::with_capacity(HEAD_SIZE);
+    reader.take(HEAD_SIZE as u64).read_to_end(&mut head)?;
+    Ok(head)
+}
+
+/// Read the first bytes of a regular file, or None if it isn't a readable file
+pub fn read_file_head(path: &Path) -> Option<Vec<u8>> {
+    if !path.is_file() {
+        return None;
+    }
+    read_head(&mut File::open(path).ok()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures() {
+        assert_eq!(detect(&[0x1f, 0x8b, 0x08, 0x00]), Some("gzip"));
+        assert_eq!(detect(b"BZh91AY&SY"), Some("bzip2"));
+        assert_eq!(detect(b"\xfd7zXZ\x00\x00"), Some("xz"));
+        assert_eq!(detect(&[0x28, 0xb5, 0x2f, 0xfd, 0x00]), Some("zstd"));
+        assert_eq!(detect(&[0x04, 0x22, 0x4d, 0x18]), Some("lz4"));
+        assert_eq!(detect(&[0x1f, 0x9d, 0x90]), Some("lzw"));
+        assert_eq!(detect(b"\x89LZO\x00\r\n\x1a\n\x10\x30"), Some("lzop"));
+        assert_eq!(detect(b"PK\x03\x04\x14\x00"), Some("zip"));
+
+        let mut tar = vec![0; 512];
+        tar[257..263].copy_from_slice(b"ustar\0");
+        assert_eq!(detect(&tar), Some("tar"));
+
+        assert_eq!(detect(b"!<arch>\ndebian-binary"), Some("ar"));
+        assert_eq!(detect(&[0x78, 0x9c, 0xcb, 0x48]), Some("zlib"));
+        assert_eq!(detect(b"Rar!\x1a\x07\x01\x00"), Some("rar"));
+        let mut iso = vec![0; 34 * 1024];
+        iso[32769..32774].copy_from_slice(b"CD001");
+        assert_eq!(detect(&iso), Some("iso"));
+        assert_eq!(detect(b"plain text"), None);
+        assert_eq!(detect(&[0x1f]), None);
+        assert_eq!(detect(&[]), None);
+    }
+
+    #[test]
+    fn short_reads() -> Result<(), io::Error> {
+        // A reader that returns one byte at a time, like a slow pipe
+        struct Trickle<'a>(&'a [u8]);
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let Some((first, rest)) = self.0.split_first() else {
+                    return Ok(0);
+                };
+                buf[0] = *first;
+                self.0 = rest;
+                Ok(1)
+            }
+        }
+        let data = vec![7; HEAD_SIZE + 10];
+        assert_eq!(read_head(&mut Trickle(&data))?.len(), HEAD_SIZE);
+        assert_eq!(read_head(&mut Trickle(b"abc"))?, b"abc");
+        Ok(())
+    }
+}
@@ -1,19 +1,44 @@
-mod bzip2;
-mod gzip;
-mod progress;
-mod tar;
-mod utils;
-mod xz;
-
-use bzip2::{Bzip2, Bzip2Args};
-use clap::{Parser, Subcommand};
-use gzip::{Gzip, GzipArgs};
-use is_terminal::IsTerminal;
-use std::path::{Path, PathBuf};
-use std::{io, vec};
-use tar::{Tar, TarArgs};
-use utils::*;
-use xz::{Xz, XzArgs};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use cmprss::ar::{Ar, ArArgs};
+use cmprss::batch;
+use cmprss::benchmark::{self, BenchmarkArgs};
+use cmprss::brotli::{Brotli, BrotliArgs};
+#[cfg(feature = "bzip2")]
+use cmprss::bzip2::{Bzip2, Bzip2Args};
+use cmprss::cat::{self, CatArgs};
+use cmprss::checksum::{self, ChecksumArgs};
+use cmprss::config;
+use cmprss::convert::{self, ConvertArgs};
+use cmprss::diff::{self, DiffArgs};
+use cmprss::dropin;
+use cmprss::external;
+use cmprss::grep::{self, GrepArgs};
+use cmprss::gzip::{Gzip, GzipArgs};
+use cmprss::info::{self, InfoArgs};
+use cmprss::iso::{Iso, IsoArgs};
+use cmprss::job::{get_job, Action};
+use cmprss::list::{self, ListArgs};
+use cmprss::log;
+use cmprss::lz4::{Lz4, Lz4Args};
+use cmprss::lzop::{Lzop, LzopArgs};
+use cmprss::lzw::{Lzw, LzwArgs};
+use cmprss::package::{self, ExtractArgs};
+use cmprss::plugin::{self, PluginArgs};
+use cmprss::rar::{Rar, RarArgs};
+use cmprss::signal;
+use cmprss::stats::{Stats, StatsFormat};
+use cmprss::tar::{Tar, TarArgs};
+use cmprss::utils::*;
+use cmprss::watch::{self, WatchArgs};
+#[cfg(feature = "xz")]
+use cmprss::xz::{Xz, XzArgs};
+use cmprss::zip::{self, Zip, ZipArgs};
+use cmprss::zlib::{Zlib, ZlibArgs};
+#[cfg(feature = "zstd")]
+use cmprss::zstd::{self, Zstd, ZstdArgs, ZstdCommand};
+use std::io;
+use std::path::PathBuf;
 
 /// A compression multi-tool
 #[derive(Parser, Debug)]
@@ -26,6 +51,17 @@ struct CmprssArgs {
     // Base arguments for the non-subcommand behavior
     #[clap(flatten)]
     pub base_args: CommonArgs,
+
+    /// Print the reports of list, info, diff, benchmark, checksum, grep and --stats as JSON
+    /// for scripts instead of text
+    #[arg(
+        long,
+        value_enum,
+        global = true,
+        value_name = "FORMAT",
+        default_value = "text"
+    )]
+    format_output: OutputFormat,
 }
 
 #[derive(Subcommand, Debug)]
@@ -38,466 +74,334 @@ enum Format {
     Gzip(GzipArgs),
 
     /// xz compression
+    #[cfg(feature = "xz")]
     Xz(XzArgs),
 
     /// bzip2 compression
+    #[cfg(feature = "bzip2")]
     #[clap(visible_alias = "bz2")]
     Bzip2(Bzip2Args),
-}
 
-/// Get the input filename or return a default file
-/// This file will be used to generate the output filename
-fn get_input_filename(input: &CmprssInput) -> Result<&Path, io::Error> {
-    match input {
-        CmprssInput::Path(paths) => {
-            if paths.is_empty() {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "error: no input specified",
-                ));
-            }
-            Ok(paths.first().unwrap())
-        }
-        CmprssInput::Pipe(_) => Ok(Path::new("archive")),
-    }
-}
+    /// brotli compression
+    #[clap(visible_alias = "br")]
+    Brotli(BrotliArgs),
 
-#[derive(Debug, PartialEq, Clone, Copy)]
-enum Action {
-    Compress,
-    Extract,
-    Unknown,
-}
+    /// zstd compression
+    #[cfg(feature = "zstd")]
+    #[clap(visible_alias = "zst")]
+    Zstd(ZstdArgs),
 
-/// Defines a single compress/extract action to take.
-#[derive(Debug)]
-struct Job {
-    compressor: Box<dyn Compressor>,
-    input: CmprssInput,
-    output: CmprssOutput,
-    action: Action,
-}
+    /// zip archive format
+    Zip(ZipArgs),
+
+    /// ar archive format, used by static libraries and .deb packages
+    Ar(ArArgs),
+
+    /// rar archive format, which can only be extracted
+    Rar(RarArgs),
+
+    /// ISO 9660 disc images, which can only be extracted
+    Iso(IsoArgs),
+
+    /// lz4 compression, very fast with a high compression mode for levels 3-12
+    Lz4(Lz4Args),
+
+    /// lzop compression, fast LZO compression in the .lzo format
+    #[clap(visible_alias = "lzo")]
+    Lzop(LzopArgs),
+
+    /// LZW compression of the unix compress tool, the .Z format
+    #[clap(visible_alias = "Z")]
+    Lzw(LzwArgs),
+
+    /// zlib compression, bare zlib streams or raw deflate data
+    #[clap(visible_alias = "zz")]
+    Zlib(ZlibArgs),
+
+    /// List the contents of an archive
+    List(ListArgs),
+
+    /// Print a single file from an archive to stdout
+    Cat(CatArgs),
+
+    /// Print the metadata recorded in compressed files and archives, like levels and sizes
+    Info(InfoArgs),
+
+    /// Extract the files of a .deb or .rpm package into a directory
+    Extract(ExtractArgs),
+
+    /// Convert a file to another format, like tar.bz2 to tar.zst or zip to tar
+    Convert(ConvertArgs),
+
+    /// Compare the speed and ratio of the formats and levels on a sample of a file
+    Benchmark(BenchmarkArgs),
 
-/// Get a compressor from a filename
-fn get_compressor_from_filename(filename: &Path) -> Option<Box<dyn Compressor>> {
-    // TODO: Support multi-level files, like tar.gz
-    let compressors: Vec<Box<dyn Compressor>> = vec![
-        Box::<Tar>::default(),
-        Box::<Gzip>::default(),
-        Box::<Xz>::default(),
-        Box::<Bzip2>::default(),
-    ];
-    compressors.into_iter().find(|c| c.is_archive(filename))
+    /// Print, write or verify the sidecar checksums of files
+    Checksum(ChecksumArgs),
+
+    /// Compare an archive with a directory, printing the added, removed and modified paths
+    Diff(DiffArgs),
+
+    /// Print the lines matching a pattern in the files inside archives, without extracting them
+    Grep(GrepArgs),
+
+    /// Compress the files written to a directory as they appear
+    Watch(WatchArgs),
 }
 
-/// Convert an input path into a Path
-fn get_path(input: &str) -> Option<PathBuf> {
-    let path = PathBuf::from(input);
-    if !path.try_exists().unwrap_or(false) {
-        return None;
-    }
-    Some(path)
+/// Arguments for a format declared as a plugin in the config file
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Format implemented by an external command")]
+struct PluginCommand {
+    #[clap(flatten)]
+    args: PluginArgs,
+
+    /// Print the reports of list, info, diff, benchmark, checksum, grep and --stats as JSON
+    /// for scripts instead of text
+    #[arg(
+        long,
+        value_enum,
+        global = true,
+        value_name = "FORMAT",
+        default_value = "text"
+    )]
+    format_output: OutputFormat,
 }
 
-/// Guess compressor/action from the two filenames
-/// The compressor may already be given
-fn guess_from_filenames(
-    input: &[PathBuf],
-    output: &Path,
+fn command(
     compressor: Option<Box<dyn Compressor>>,
-) -> (Option<Box<dyn Compressor>>, Action) {
-    if input.len() != 1 {
-        if let Some(guessed_compressor) = get_compressor_from_filename(output) {
-            return (Some(guessed_compressor), Action::Compress);
-        }
-        // In theory we could be extracting multiple files to a directory
-        // We'll fail somewhere else if that's not the case
-        return (compressor, Action::Extract);
+    args: &CommonArgs,
+    output: OutputFormat,
+) -> Result<(), io::Error> {
+    log::set_verbosity(args.verbosity_args.verbosity());
+    if args.recursive {
+        return batch::recursive(compressor, args);
     }
-    let input = input.first().unwrap();
-
-    let guessed_compressor = get_compressor_from_filename(output);
-    let guessed_extractor = get_compressor_from_filename(input);
-    let guessed_compressor_name = if let Some(c) = &guessed_compressor {
-        c.name()
-    } else {
-        ""
-    };
-    let guessed_extractor_name = if let Some(e) = &guessed_extractor {
-        e.name()
-    } else {
-        ""
-    };
-
-    if let Some(c) = &compressor {
-        if guessed_compressor_name == c.name() {
-            return (compressor, Action::Compress);
-        } else if guessed_extractor_name == c.name() {
-            return (compressor, Action::Extract);
-        } else {
-            // Default to compressing
-            return (compressor, Action::Compress);
-        }
+    if batch::is_batch(&compressor, args) {
+        return batch::batch(compressor, args);
     }
-
-    match (guessed_compressor, guessed_extractor) {
-        (None, None) => (None, Action::Unknown),
-        (Some(c), None) => (Some(c), Action::Compress),
-        (None, Some(e)) => (Some(e), Action::Extract),
-        (Some(c), Some(e)) => {
-            if c.name() == e.name() {
-                return (Some(c), Action::Unknown);
-            }
-            // Compare the input and output extensions to see if one has an extra extension
-            let input_file = input.file_name().unwrap().to_str().unwrap();
-            let input_ext = input.extension().unwrap_or_default();
-            let output_file = output.file_name().unwrap().to_str().unwrap();
-            let output_ext = output.extension().unwrap_or_default();
-            let guessed_output = input_file.to_string() + "." + output_ext.to_str().unwrap();
-            let guessed_input = output_file.to_string() + "." + input_ext.to_str().unwrap();
-            if guessed_output == output_file {
-                (Some(c), Action::Compress)
-            } else if guessed_input == input_file {
-                (Some(e), Action::Extract)
-            } else {
-                (None, Action::Unknown)
-            }
-        }
+    if batch::is_concatenation(&compressor, args) {
+        return batch::concatenate(compressor, args);
     }
-}
-
-/// Parse the common args and determine the details of the job requested
-fn get_job(
-    compressor: Option<Box<dyn Compressor>>,
-    common_args: &CommonArgs,
-) -> Result<Job, io::Error> {
-    let mut compressor = compressor;
-    let mut action = {
-        if common_args.compress {
-            Action::Compress
-        } else if common_args.extract || common_args.decompress {
-            Action::Extract
-        } else {
-            Action::Unknown
+    if args.output_template.is_some() {
+        return Err(io::Error::other(
+            "--output-template only names the outputs of --recursive and of several files",
+        ));
+    }
+    let mut job = match get_job(compressor, args) {
+        Err(e) if args.overwrite_args.no_clobber && e.kind() == io::ErrorKind::AlreadyExists => {
+            cmprss::warn!("skipping, {}", e);
+            return Ok(());
         }
+        job => job?,
     };
-
-    let mut inputs = Vec::new();
-    if let Some(in_file) = &common_args.input {
-        match get_path(in_file) {
-            Some(path) => inputs.push(path),
-            None => {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Specified input path does not exist",
-                ));
-            }
-        }
+    if args.dry_run {
+        print!("{}", job.plan());
+        return Ok(());
     }
+    let stats = args.stats.map(|format| {
+        let format = match output {
+            OutputFormat::Json => StatsFormat::Json,
+            OutputFormat::Text => format,
+        };
+        let stats = Stats::start(
+            job.compressor.name(),
+            job.action == Action::Compress,
+            &mut job.input,
+            &mut job.output,
+        );
+        (stats, format)
+    });
+    let stdout_output = matches!(job.output, CmprssOutput::Pipe(_));
 
-    let mut output = match &common_args.output {
-        Some(output) => {
-            let path = Path::new(output);
-            if path.try_exists()? && !path.is_dir() {
-                // Output path exists, bail out
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Specified output path already exists",
-                ));
-            }
-            Some(path)
-        }
-        None => None,
-    };
+    job.run()?;
 
-    // Process the io_list, check if there is an output first
-    let mut io_list = common_args.io_list.clone();
-    if output.is_none() {
-        if let Some(possible_output) = common_args.io_list.last() {
-            let path = Path::new(possible_output);
-            if !path.try_exists()? {
-                // Use the given path if it doesn't exist
-                output = Some(path);
-                io_list.pop();
-            } else if path.is_dir() {
-                match action {
-                    Action::Compress => {
-                        // A directory can potentially be a target output location or
-                        // an input, for now assume it is an input.
-                    }
-                    Action::Extract => {
-                        // Can extract to a directory, and it wouldn't make any sense as an input
-                        output = Some(path);
-                        io_list.pop();
-                    }
-                    _ => {
-                        // TODO: don't know if this is an input or output, assume we're compressing this directory
-                        // This does cause problems for inferencing "cat archive.tar | cmprss tar ."
-                        // Probably need to add some special casing
-                    }
-                };
-            } else {
-                // TODO: check for scenarios where we want to append to an existing archive
-            }
-        }
+    match stats {
+        Some((stats, StatsFormat::Json)) if !stdout_output => println!("{}", stats.finish().json()),
+        Some((stats, StatsFormat::Json)) => eprintln!("{}", stats.finish().json()),
+        Some((stats, StatsFormat::Text)) => eprintln!("{}", stats.finish()),
+        None => {}
     }
 
-    // Validate the specified inputs
-    // Everything in the io_list should be an input
-    for input in &io_list {
-        if let Some(path) = get_path(input) {
-            inputs.push(path);
-        } else {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Specified input path does not exist",
-            ));
-        }
-    }
+    Ok(())
+}
 
-    // Fallback to stdin/stdout if we're missing files
-    let cmprss_input = match inputs.is_empty() {
-        true => {
-            if !std::io::stdin().is_terminal()
-                && !&common_args.ignore_pipes
-                && !&common_args.ignore_stdin
-            {
-                CmprssInput::Pipe(std::io::stdin())
-            } else {
-                return Err(io::Error::new(io::ErrorKind::Other, "No specified input"));
-            }
-        }
-        false => CmprssInput::Path(inputs),
+/// Find the plugin or system tool named by the first argument, if any
+/// These aren't known to clap, so they are dispatched before parsing the arguments.
+fn plugin_command(
+    mut argv: Vec<String>,
+) -> Result<Option<(plugin::Plugin, PluginCommand)>, io::Error> {
+    let Some(name) = argv
+        .get(1)
+        .filter(|name| CmprssArgs::command().find_subcommand(name).is_none())
+    else {
+        return Ok(None);
     };
-
-    let cmprss_output = match output {
-        Some(path) => CmprssOutput::Path(path.to_path_buf()),
-        None => {
-            if !std::io::stdout().is_terminal()
-                && !&common_args.ignore_pipes
-                && !&common_args.ignore_stdout
-            {
-                CmprssOutput::Pipe(std::io::stdout())
-            } else {
-                match action {
-                    Action::Compress => {
-                        if compressor.is_none() {
-                            return Err(io::Error::new(
-                                io::ErrorKind::Other,
-                                "Must specify a compressor",
-                            ));
-                        }
-                        CmprssOutput::Path(PathBuf::from(
-                            compressor
-                                .as_ref()
-                                .unwrap()
-                                .default_compressed_filename(get_input_filename(&cmprss_input)?),
-                        ))
-                    }
-                    Action::Extract => {
-                        if compressor.is_none() {
-                            compressor =
-                                get_compressor_from_filename(get_input_filename(&cmprss_input)?);
-                            if compressor.is_none() {
-                                return Err(io::Error::new(
-                                    io::ErrorKind::Other,
-                                    "Must specify a compressor",
-                                ));
-                            }
-                        }
-                        CmprssOutput::Path(PathBuf::from(
-                            compressor
-                                .as_ref()
-                                .unwrap()
-                                .default_extracted_filename(get_input_filename(&cmprss_input)?),
-                        ))
-                    }
-                    Action::Unknown => {
-                        if compressor.is_none() {
-                            // Can still work if the input is an archive
-                            compressor =
-                                get_compressor_from_filename(get_input_filename(&cmprss_input)?);
-                            if compressor.is_none() {
-                                return Err(io::Error::new(
-                                    io::ErrorKind::Other,
-                                    "Must specify a compressor",
-                                ));
-                            }
-                            action = Action::Extract;
-                            CmprssOutput::Path(PathBuf::from(
-                                compressor
-                                    .as_ref()
-                                    .unwrap()
-                                    .default_extracted_filename(get_input_filename(&cmprss_input)?),
-                            ))
-                        } else {
-                            // We know the compressor, does the input have the same extension?
-                            if let Some(compressor_from_input) =
-                                get_compressor_from_filename(get_input_filename(&cmprss_input)?)
-                            {
-                                if compressor.as_ref().unwrap().name()
-                                    == compressor_from_input.name()
-                                {
-                                    action = Action::Extract;
-                                    CmprssOutput::Path(PathBuf::from(
-                                        compressor.as_ref().unwrap().default_extracted_filename(
-                                            get_input_filename(&cmprss_input)?,
-                                        ),
-                                    ))
-                                } else {
-                                    action = Action::Compress;
-                                    CmprssOutput::Path(PathBuf::from(
-                                        compressor.as_ref().unwrap().default_compressed_filename(
-                                            get_input_filename(&cmprss_input)?,
-                                        ),
-                                    ))
-                                }
-                            } else {
-                                action = Action::Compress;
-                                CmprssOutput::Path(PathBuf::from(
-                                    compressor.as_ref().unwrap().default_compressed_filename(
-                                        get_input_filename(&cmprss_input)?,
-                                    ),
-                                ))
-                            }
-                        }
-                    }
-                }
-            }
-        }
+    let (plugin, external) = match plugin::find(name) {
+        Some(plugin) => (plugin.clone(), false),
+        None => match external::find(name) {
+            Some(tool) => (tool, true),
+            None => return Ok(None),
+        },
     };
-
-    // If we don't have the compressor/action, we can attempt to infer
-    if compressor.is_none() || action == Action::Unknown {
-        match action {
-            Action::Compress => {
-                // Look at the output name
-                // TODO: tar.gz ??
-                if let CmprssOutput::Path(path) = &cmprss_output {
-                    compressor = get_compressor_from_filename(path);
-                }
-            }
-            Action::Extract => {
-                // Look at the input name
-                if let CmprssInput::Path(paths) = &cmprss_input {
-                    if paths.len() != 1 {
-                        // Can't guess if there are multiple inputs
-                        return Err(io::Error::new(
-                            io::ErrorKind::Other,
-                            "Can't guess compressor with multiple inputs",
-                        ));
-                    }
-                    compressor = get_compressor_from_filename(paths.first().unwrap());
-                }
-            }
-            Action::Unknown => match (&cmprss_input, &cmprss_output) {
-                (CmprssInput::Pipe(_), CmprssOutput::Path(path)) => {
-                    if compressor.is_none() {
-                        compressor = get_compressor_from_filename(path);
-                        if compressor.is_some() {
-                            action = Action::Compress;
-                        } else {
-                            return Err(io::Error::new(
-                                io::ErrorKind::Other,
-                                "Can't guess compressor to use",
-                            ));
-                        }
-                    } else if compressor.as_ref().unwrap().name()
-                        == get_compressor_from_filename(path).unwrap().name()
-                    {
-                        action = Action::Compress;
-                    } else {
-                        action = Action::Extract;
-                    }
-                }
-                (CmprssInput::Path(paths), CmprssOutput::Pipe(_)) => {
-                    if compressor.is_none() {
-                        if paths.len() != 1 {
-                            return Err(io::Error::new(
-                                io::ErrorKind::Other,
-                                "Can't guess compressor with multiple inputs",
-                            ));
-                        }
-                        compressor = get_compressor_from_filename(paths.first().unwrap());
-                        if compressor.is_some() {
-                            action = Action::Extract;
-                        } else {
-                            return Err(io::Error::new(
-                                io::ErrorKind::Other,
-                                "Can't guess compressor to use",
-                            ));
-                        }
-                    } else if let Some(c) = get_compressor_from_filename(paths.first().unwrap()) {
-                        if compressor.as_ref().unwrap().name() == c.name() {
-                            action = Action::Extract;
-                        } else {
-                            action = Action::Compress;
-                        }
-                    } else {
-                        action = Action::Compress;
-                    }
-                }
-                (CmprssInput::Pipe(_), CmprssOutput::Pipe(_)) => {
-                    action = Action::Compress;
-                }
-                (CmprssInput::Path(paths), CmprssOutput::Path(path)) => {
-                    let (guessed_compressor, guessed_action) =
-                        guess_from_filenames(paths, path, compressor);
-                    compressor = guessed_compressor;
-                    action = guessed_action;
-                }
-            },
-        }
+    argv.splice(0..2, [format!("cmprss {}", plugin.name)]);
+    let args: PluginCommand = parse_args(argv);
+    if external && !args.args.common_args.allow_external {
+        return Err(io::Error::other(format!(
+            "{} is not built into this cmprss, use --allow-external to use the system tool",
+            plugin.name
+        )));
     }
+    Ok(Some((plugin, args)))
+}
 
-    if compressor.is_none() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Could not determine compressor to use",
-        ));
-    }
-    if action == Action::Unknown {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Could not determine action to take",
-        ));
-    }
+/// Add the --config and --profile options
+/// They are read from the arguments before they are parsed, since the config sets the defaults
+/// of the other options.
+fn with_config_args(command: clap::Command) -> clap::Command {
+    command
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("PATH")
+                .global(true)
+                .help("Config file to load instead of ~/.config/cmprss/config.toml"),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_name("NAME")
+                .global(true)
+                .help("Use the defaults of a [profile.NAME] table in the config file"),
+        )
+}
+
+/// Parse the arguments, with the defaults from the config file beneath them
+fn parse_matches<T: CommandFactory>(argv: Vec<String>) -> ArgMatches {
+    let command = config::registered().apply_defaults(with_config_args(T::command()));
+    command.get_matches_from(argv)
+}
 
-    Ok(Job {
-        compressor: compressor.unwrap(),
-        input: cmprss_input,
-        output: cmprss_output,
-        action,
-    })
+fn parse_args<T: CommandFactory + FromArgMatches>(argv: Vec<String>) -> T {
+    T::from_arg_matches(&parse_matches::<T>(argv)).unwrap_or_else(|e| e.exit())
 }
 
-fn command(compressor: Option<Box<dyn Compressor>>, args: &CommonArgs) -> Result<(), io::Error> {
-    let job = get_job(compressor, args)?;
+/// Check that the options of the default command aren't given before a subcommand
+/// They would be ignored, so `cmprss --dry-run gzip file` would compress the file.
+fn check_base_args(matches: &ArgMatches) -> Result<(), io::Error> {
+    let Some((name, _)) = matches.subcommand() else {
+        return Ok(());
+    };
+    let command = CmprssArgs::command();
+    let misplaced = command.get_arguments().find(|arg| {
+        !arg.is_global_set()
+            && matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+    });
+    match misplaced {
+        Some(arg) => Err(io::Error::other(format!(
+            "{} must be given after the {} command",
+            arg.get_long()
+                .map_or_else(|| arg.get_id().to_string(), |long| format!("--{}", long)),
+            name
+        ))),
+        None => Ok(()),
+    }
+}
 
-    match job.action {
-        Action::Compress => job.compressor.compress(job.input, job.output)?,
-        Action::Extract => job.compressor.extract(job.input, job.output)?,
-        _ => {
+/// Check if an option of a format's command takes a value, for the arguments of drop-in tools
+fn takes_value(format: &str, option: &str) -> bool {
+    let command = with_config_args(CmprssArgs::command());
+    let format_args = command
+        .find_subcommand(format)
+        .into_iter()
+        .flat_map(|format| format.get_arguments());
+    let arg = format_args
+        .chain(command.get_arguments())
+        .find(|arg| arg.get_long() == Some(option));
+    arg.is_some_and(|arg| arg.get_action().takes_values())
+}
+
+fn run() -> Result<(), io::Error> {
+    signal::install()?;
+    // Run as gzip, zcat, unxz... through a symlink
+    let argv = dropin::translate(std::env::args().collect(), takes_value)?;
+    let config_path = match config::value_from_args(&argv, "--config").map(PathBuf::from) {
+        Some(path) if !path.is_file() => {
             return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Unknown action requested",
+                io::ErrorKind::NotFound,
+                format!("config file {} not found", path.display()),
             ));
         }
+        Some(path) => Some(path),
+        None => config::config_path(),
     };
+    let mut config = config::Config::default();
+    if let Some(path) = config_path {
+        plugin::register(plugin::load(&path)?);
+        config = config::load(&path)?;
+        config
+            .validate(&CmprssArgs::command())
+            .map_err(|e| io::Error::other(format!("{}: {}", path.display(), e)))?;
+    }
+    config.read_env(&CmprssArgs::command())?;
+    if let Some(profile) = config::value_from_args(&argv, "--profile") {
+        config.select_profile(&profile)?;
+    }
+    config::register(config);
+    if let Some((plugin, args)) = plugin_command(argv.clone())? {
+        return command(
+            Some(Box::new(plugin)),
+            &args.args.common_args,
+            args.format_output,
+        );
+    }
 
-    Ok(())
+    let matches = parse_matches::<CmprssArgs>(argv);
+    check_base_args(&matches)?;
+    let args = CmprssArgs::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let output = args.format_output;
+    match args.format {
+        Some(Format::Tar(a)) => command(Some(Box::new(Tar::new(&a))), &a.common_args, output),
+        Some(Format::Gzip(a)) => command(Some(Box::new(Gzip::new(&a))), &a.common_args, output),
+        #[cfg(feature = "xz")]
+        Some(Format::Xz(a)) => command(Some(Box::new(Xz::new(&a))), &a.common_args, output),
+        #[cfg(feature = "bzip2")]
+        Some(Format::Bzip2(a)) => command(Some(Box::new(Bzip2::new(&a))), &a.common_args, output),
+        Some(Format::Brotli(a)) => command(Some(Box::new(Brotli::new(&a))), &a.common_args, output),
+        #[cfg(feature = "zstd")]
+        Some(Format::Zstd(a)) => match &a.command {
+            Some(ZstdCommand::Train(t)) => zstd::train(t),
+            None => command(Some(Box::new(Zstd::new(&a)?)), &a.common_args, output),
+        },
+        Some(Format::Zip(a)) if a.update || !a.delete.is_empty() => zip::update(&a),
+        Some(Format::Zip(a)) => command(Some(Box::new(Zip::new(&a)?)), &a.common_args, output),
+        Some(Format::Ar(a)) => command(Some(Box::new(Ar::new(&a))), &a.common_args, output),
+        Some(Format::Rar(a)) => command(Some(Box::new(Rar::new(&a))), &a.common_args, output),
+        Some(Format::Iso(a)) => command(Some(Box::new(Iso::new(&a))), &a.common_args, output),
+        Some(Format::Lz4(a)) => command(Some(Box::new(Lz4::new(&a))), &a.common_args, output),
+        Some(Format::Lzop(a)) => command(Some(Box::new(Lzop::new(&a))), &a.common_args, output),
+        Some(Format::Lzw(a)) => command(Some(Box::new(Lzw::new(&a))), &a.common_args, output),
+        Some(Format::Zlib(a)) => command(Some(Box::new(Zlib::new(&a))), &a.common_args, output),
+        Some(Format::List(a)) => list::list(&a, output),
+        Some(Format::Cat(a)) => cat::cat(&a),
+        Some(Format::Info(a)) => info::print_info(&a, output),
+        Some(Format::Extract(a)) => package::extract(&a),
+        Some(Format::Convert(a)) => convert::convert(&a),
+        Some(Format::Benchmark(a)) => benchmark::benchmark(&a, output),
+        Some(Format::Checksum(a)) => checksum::checksum(&a, output),
+        Some(Format::Diff(a)) => diff::diff(&a, output),
+        Some(Format::Grep(a)) => grep::grep(&a, output),
+        Some(Format::Watch(a)) => watch::watch(&a),
+        _ => command(
+            config::registered().compressor(&args.base_args),
+            &args.base_args,
+            output,
+        ),
+    }
 }
 
 fn main() {
-    let args = CmprssArgs::parse();
-    match args.format {
-        Some(Format::Tar(a)) => command(Some(Box::new(Tar::new(&a))), &a.common_args),
-        Some(Format::Gzip(a)) => command(Some(Box::new(Gzip::new(&a))), &a.common_args),
-        Some(Format::Xz(a)) => command(Some(Box::new(Xz::new(&a))), &a.common_args),
-        Some(Format::Bzip2(a)) => command(Some(Box::new(Bzip2::new(&a))), &a.common_args),
-        _ => command(None, &args.base_args),
-    }
-    .unwrap_or_else(|e| {
+    run().unwrap_or_else(|e| {
         eprintln!("ERROR(cmprss): {}", e);
         std::process::exit(1);
     });
@@ -0,0 +1,279 @@
+//! Owners and groups of archive entries, like the ownership options of GNU tar
+
+use clap::Args;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+use tar::Header;
+
+/// Options for the owners stored in archives and restored when extracting
+#[derive(Args, Debug, Clone, Default)]
+pub struct OwnerArgs {
+    /// Store this user as the owner of the entries, as 'NAME', 'UID', or 'NAME:UID'.
+    /// When extracting as root, the extracted files are owned by this user instead.
+    #[arg(long, value_name = "NAME[:UID]", value_parser = parse_owner)]
+    pub owner: Option<Account>,
+
+    /// Store this group as the group of the entries, as 'NAME', 'GID', or 'NAME:GID'.
+    /// When extracting as root, the extracted files get this group instead.
+    #[arg(long, value_name = "NAME[:GID]", value_parser = parse_group)]
+    pub group: Option<Account>,
+
+    /// Only store the numeric IDs of owners, and restore the IDs without looking up the names
+    #[arg(long)]
+    pub numeric_owner: bool,
+}
+
+/// A user or group, with its name if it has one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Account {
+    pub name: Option<String>,
+    pub id: u32,
+}
+
+/// Parse an --owner, looking up the part that isn't given
+fn parse_owner(value: &str) -> Result<Account, String> {
+    parse_account(value, user_name, user_id)
+}
+
+/// Parse a --group, looking up the part that isn't given
+fn parse_group(value: &str) -> Result<Account, String> {
+    parse_account(value, group_name, group_id)
+}
+
+fn parse_account(
+    value: &str,
+    name_of: fn(u32) -> Option<String>,
+    id_of: fn(&str) -> Option<u32>,
+) -> Result<Account, String> {
+    if let Some((name, id)) = value.split_once(':') {
+        let id = id.parse().map_err(|_| format!("invalid ID '{}'", id))?;
+        return Ok(Account {
+            name: Some(name.to_string()),
+            id,
+        });
+    }
+    // Like GNU tar, a leading '+' is always an ID
+    if let Ok(id) = value.trim_start_matches('+').parse() {
+        return Ok(Account {
+            name: name_of(id),
+            id,
+        });
+    }
+    match id_of(value) {
+        Some(id) => Ok(Account {
+            name: Some(value.to_string()),
+            id,
+        }),
+        None => Err(format!(
+            "'{}' is not known here, give its ID as NAME:ID",
+            value
+        )),
+    }
+}
+
+/// Settings for the owners of entries
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ownership {
+    /// Owner to store or restore instead of the real one
+    pub owner: Option<Account>,
+    /// Group to store or restore instead of the real one
+    pub group: Option<Account>,
+    /// Leave out the names, and restore the IDs as they are
+    pub numeric: bool,
+}
+
+impl Ownership {
+    pub fn new(args: &OwnerArgs) -> Ownership {
+        Ownership {
+            owner: args.owner.clone(),
+            group: args.group.clone(),
+            numeric: args.numeric_owner,
+        }
+    }
+
+    /// Fill in the owner of a header that has the IDs of a file
+    /// The names of the IDs are looked up unless `names` is false, like for reproducible archives.
+    pub fn apply(&self, header: &mut Header, names: bool) -> Result<(), io::Error> {
+        if names && !self.numeric {
+            // Names too long for the header are left out, the IDs are still there
+            if let Some(name) = user_name(header.uid()? as u32) {
+                let _ = header.set_username(&name);
+            }
+            if let Some(name) = group_name(header.gid()? as u32) {
+                let _ = header.set_groupname(&name);
+            }
+        }
+        if let Some(owner) = &self.owner {
+            header.set_uid(owner.id.into());
+            let name = owner.name.as_deref().filter(|_| !self.numeric);
+            header.set_username(name.unwrap_or_default())?;
+        }
+        if let Some(group) = &self.group {
+            header.set_gid(group.id.into());
+            let name = group.name.as_deref().filter(|_| !self.numeric);
+            header.set_groupname(name.unwrap_or_default())?;
+        }
+        Ok(())
+    }
+
+    /// The owner and group to give an extracted entry, only when running as root
+    /// The names in the archive are used if they exist here, otherwise the IDs.
+    /// Either is None to leave it as it is, like for headers that have no IDs.
+    pub fn extracted(&self, header: &Header) -> (Option<u32>, Option<u32>) {
+        if !is_root() {
+            return (None, None);
+        }
+        let local = |name: Option<&str>, lookup: fn(&str) -> Option<u32>| {
+            name.filter(|name| !self.numeric && !name.is_empty())
+                .and_then(lookup)
+        };
+        let uid = match &self.owner {
+            Some(owner) => Some(owner.id),
+            None => local(header.username().ok().flatten(), user_id)
+                .or_else(|| header.uid().ok().map(|uid| uid as u32)),
+        };
+        let gid = match &self.group {
+            Some(group) => Some(group.id),
+            None => local(header.groupname().ok().flatten(), group_id)
+                .or_else(|| header.gid().ok().map(|gid| gid as u32)),
+        };
+        (uid, gid)
+    }
+}
+
+/// Give an extracted file its owner, without following it if it is a link
+#[cfg(unix)]
+pub fn restore(path: &Path, (uid, gid): (Option<u32>, Option<u32>)) -> Result<(), io::Error> {
+    if uid.is_none() && gid.is_none() {
+        return Ok(());
+    }
+    std::os::unix::fs::lchown(path, uid, gid)
+}
+
+/// Owners aren't restored on other platforms
+#[cfg(not(unix))]
+pub fn restore(_path: &Path, _owner: (Option<u32>, Option<u32>)) -> Result<(), io::Error> {
+    Ok(())
+}
+
+/// Whether cmprss can change the owners of files
+#[cfg(unix)]
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+pub fn is_root() -> bool {
+    false
+}
+
+/// Names and IDs from a file like /etc/passwd, read once
+/// Only the local files are read, accounts from other sources like LDAP aren't found.
+fn accounts(path: &str, cache: &'static OnceLock<Vec<(String, u32)>>) -> &'static [(String, u32)] {
+    cache.get_or_init(|| {
+        let contents = fs::read_to_string(path).unwrap_or_default();
+        contents
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split(':');
+                let name = fields.next()?;
+                let id = fields.nth(1)?.parse().ok()?;
+                Some((name.to_string(), id))
+            })
+            .collect()
+    })
+}
+
+fn users() -> &'static [(String, u32)] {
+    static USERS: OnceLock<Vec<(String, u32)>> = OnceLock::new();
+    accounts("/etc/passwd", &USERS)
+}
+
+fn groups() -> &'static [(String, u32)] {
+    static GROUPS: OnceLock<Vec<(String, u32)>> = OnceLock::new();
+    accounts("/etc/group", &GROUPS)
+}
+
+pub fn user_name(uid: u32) -> Option<String> {
+    users()
+        .iter()
+        .find(|(_, id)| *id == uid)
+        .map(|(name, _)| name.clone())
+}
+
+pub fn user_id(name: &str) -> Option<u32> {
+    users()
+        .iter()
+        .find(|(user, _)| user == name)
+        .map(|(_, id)| *id)
+}
+
+pub fn group_name(gid: u32) -> Option<String> {
+    groups()
+        .iter()
+        .find(|(_, id)| *id == gid)
+        .map(|(name, _)| name.clone())
+}
+
+pub fn group_id(name: &str) -> Option<u32> {
+    groups()
+        .iter()
+        .find(|(group, _)| group == name)
+        .map(|(_, id)| *id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_accounts() -> Result<(), String> {
+        let given = parse_owner("builder:1234")?;
+        assert_eq!(given.name.as_deref(), Some("builder"));
+        assert_eq!(given.id, 1234);
+        assert_eq!(parse_owner("+4321")?.id, 4321);
+        assert!(parse_owner("builder:x").is_err());
+        assert!(parse_owner("no-such-user-here").is_err());
+        if cfg!(unix) {
+            assert_eq!(parse_owner("root")?.id, 0);
+            assert_eq!(parse_owner("0")?.name.as_deref(), Some("root"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn headers() -> Result<(), io::Error> {
+        let ownership = Ownership {
+            owner: Some(Account {
+                name: Some("builder".to_string()),
+                id: 1234,
+            }),
+            group: None,
+            numeric: false,
+        };
+        let mut header = Header::new_ustar();
+        header.set_uid(0);
+        header.set_gid(0);
+        ownership.apply(&mut header, true)?;
+        assert_eq!(header.uid()?, 1234);
+        assert_eq!(header.username().ok().flatten(), Some("builder"));
+        if cfg!(unix) {
+            assert_eq!(header.groupname().ok().flatten(), Some("root"));
+        }
+
+        let numeric = Ownership {
+            numeric: true,
+            ..ownership
+        };
+        let mut header = Header::new_ustar();
+        header.set_uid(0);
+        header.set_gid(0);
+        numeric.apply(&mut header, true)?;
+        assert_eq!(header.uid()?, 1234);
+        assert_eq!(header.username().ok().flatten(), Some(""));
+        assert_eq!(header.groupname().ok().flatten(), Some(""));
+        Ok(())
+    }
+}
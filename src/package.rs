@@ -0,0 +1,433 @@
+//! Extraction of the files installed by .deb and .rpm packages
+//!
+//! Both are containers around a compressed archive of the files. A .deb is an ar archive with
+//! the files in its data.tar.* member, and an .rpm is a few headers followed by a compressed cpio
+//! archive. The containers and the compression are unwrapped in one pass.
+
+use clap::Args;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::ar;
+use crate::job::stream_compressors;
+use crate::magic;
+use crate::tar::Tar;
+use crate::utils::*;
+
+const RPM_MAGIC: [u8; 4] = [0xed, 0xab, 0xee, 0xdb];
+const RPM_HEADER_MAGIC: [u8; 3] = [0x8e, 0xad, 0xe8];
+/// Largest symlink target in a cpio archive
+const MAX_LINK_TARGET: u64 = 4096;
+
+#[derive(Args, Debug)]
+pub struct ExtractArgs {
+    /// Package to extract, a .deb or .rpm
+    pub input: PathBuf,
+
+    /// Directory to extract the files into, named after the package by default
+    pub output: Option<PathBuf>,
+
+    /// Extract entries with absolute paths or '..' outside of the output directory
+    #[arg(long)]
+    pub allow_unsafe_paths: bool,
+}
+
+/// The kinds of packages
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Package {
+    Deb,
+    Rpm,
+}
+
+impl Package {
+    /// Recognize a package from its extension, or from its contents
+    fn detect(path: &Path) -> Option<Package> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("deb" | "udeb") => return Some(Package::Deb),
+            Some("rpm") => return Some(Package::Rpm),
+            _ => {}
+        }
+        let head = magic::read_file_head(path)?;
+        if head.starts_with(&RPM_MAGIC) {
+            Some(Package::Rpm)
+        } else if head.starts_with(b"!<arch>\ndebian-binary") {
+            Some(Package::Deb)
+        } else {
+            None
+        }
+    }
+}
+
+/// Extract the files of a package into a directory
+pub fn extract(args: &ExtractArgs) -> Result<(), io::Error> {
+    let Some(package) = Package::detect(&args.input) else {
+        return Err(io::Error::other(format!(
+            "{} is not a .deb or .rpm package",
+            args.input.display()
+        )));
+    };
+    let output = match &args.output {
+        Some(output) => output.clone(),
+        None => PathBuf::from(args.input.file_stem().unwrap_or_default()),
+    };
+    fs::create_dir_all(&output)?;
+    let file = File::open(&args.input)?;
+    match package {
+        Package::Deb => extract_deb(file, &output, args.allow_unsafe_paths),
+        Package::Rpm => extract_rpm(file, &output, args.allow_unsafe_paths),
+    }
+}
+
+/// Extract the data.tar.* member of a .deb, decompressing it by its extension
+fn extract_deb(file: File, output: &Path, allow_unsafe_paths: bool) -> Result<(), io::Error> {
+    let Some((name, data)) = ar::open_member(file, |name| name.starts_with("data.tar"))? else {
+        return Err(io::Error::other("the package has no data.tar member"));
+    };
+    crate::debug!("extracting the files in {}", name);
+    let data = if name == "data.tar" {
+        data
+    } else {
+        let Some(compressor) = stream_compressors()
+            .into_iter()
+            .find(|c| c.is_archive(Path::new(&name)))
+        else {
+            return Err(io::Error::other(format!(
+                "the compression of {} is not supported",
+                name
+            )));
+        };
+        compressor.wrap_reader(data)?
+    };
+    let tar = Tar {
+        allow_unsafe_paths,
+        ..Tar::default()
+    };
+    tar.extract(
+        CmprssInput::Reader(CmprssRead {
+            reader: data,
+            size: None,
+            name: Some(name),
+        }),
+        CmprssOutput::Path(output.to_path_buf()),
+    )
+}
+
+/// Skip the lead and headers of an .rpm, then extract its compressed cpio payload
+fn extract_rpm(mut file: File, output: &Path, allow_unsafe_paths: bool) -> Result<(), io::Error> {
+    let mut lead = [0; 96];
+    file.read_exact(&mut lead)?;
+    if lead[..4] != RPM_MAGIC {
+        return Err(io::Error::other("not an rpm package"));
+    }
+    // The signature header is padded to a multiple of 8 bytes, the main header isn't
+    skip_rpm_header(&mut file, true)?;
+    skip_rpm_header(&mut file, false)?;
+
+    let head = magic::read_head(&mut file)?;
+    let payload: Box<dyn Read + Send> = Box::new(Cursor::new(head.clone()).chain(file));
+    let mut payload = if head.starts_with(b"0707") {
+        payload
+    } else {
+        let Some(compressor) = magic::detect(&head)
+            .and_then(|name| stream_compressors().into_iter().find(|c| c.name() == name))
+        else {
+            return Err(io::Error::other(
+                "the compression of the rpm payload is not supported",
+            ));
+        };
+        compressor.wrap_reader(payload)?
+    };
+    let out_path = long_path(output)?;
+    extract_cpio(&mut payload, &out_path, allow_unsafe_paths)
+}
+
+/// Skip a header structure of an .rpm, an index of tags followed by their data
+fn skip_rpm_header(reader: &mut impl Read, padded: bool) -> Result<(), io::Error> {
+    let mut intro = [0; 16];
+    reader.read_exact(&mut intro)?;
+    if intro[..3] != RPM_HEADER_MAGIC {
+        return Err(io::Error::other("corrupt rpm header"));
+    }
+    let count = u32::from_be_bytes(intro[8..12].try_into().unwrap()) as u64;
+    let size = u32::from_be_bytes(intro[12..16].try_into().unwrap()) as u64;
+    let mut length = count * 16 + size;
+    if padded {
+        length = length.next_multiple_of(8);
+    }
+    let skipped = io::copy(&mut reader.take(length), &mut io::sink())?;
+    if skipped < length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "truncated rpm package",
+        ));
+    }
+    Ok(())
+}
+
+/// Read `length` bytes and then the padding to a multiple of 4 after `end`
+fn read_padded(reader: &mut dyn Read, length: u64, end: u64) -> Result<Vec<u8>, io::Error> {
+    let mut data = vec![0; length as usize];
+    reader.read_exact(&mut data)?;
+    skip_padding(reader, end)?;
+    Ok(data)
+}
+
+fn skip_padding(reader: &mut dyn Read, end: u64) -> Result<(), io::Error> {
+    let mut padding = [0; 3];
+    reader.read_exact(&mut padding[..(end.next_multiple_of(4) - end) as usize])
+}
+
+/// Restore the modification time and permissions of an extracted file or directory
+fn restore_metadata(path: &Path, mode: u32, mtime: u64) -> Result<(), io::Error> {
+    File::open(path)?.set_modified(UNIX_EPOCH + Duration::from_secs(mtime))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    Ok(())
+}
+
+/// Extract a cpio archive in the "new" format with ASCII headers, which rpm uses
+fn extract_cpio(
+    reader: &mut dyn Read,
+    out_path: &Path,
+    allow_unsafe_paths: bool,
+) -> Result<(), io::Error> {
+    let corrupt = || io::Error::other("corrupt cpio archive");
+    // Directories are restored last, so that extracting their contents doesn't change them
+    let mut directories = Vec::new();
+    // Hard links share an inode, and only the last of them has the data
+    let mut links: HashMap<u32, Vec<PathBuf>> = HashMap::new();
+    loop {
+        let mut header = [0; 110];
+        reader.read_exact(&mut header)?;
+        if &header[..6] != b"070701" && &header[..6] != b"070702" {
+            return Err(corrupt());
+        }
+        let field = |index: usize| {
+            let hex = std::str::from_utf8(&header[6 + index * 8..14 + index * 8]).ok();
+            hex.and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .ok_or_else(corrupt)
+        };
+        let (inode, mode, links_count, mtime) = (field(0)?, field(1)?, field(4)?, field(5)?);
+        let size = field(6)? as u64;
+        let name_size = field(11)? as u64;
+        if name_size == 0 || name_size > 4096 {
+            return Err(corrupt());
+        }
+        let name = read_padded(reader, name_size, 110 + name_size)?;
+        let name = String::from_utf8_lossy(&name[..name.len() - 1]).into_owned();
+        if name == "TRAILER!!!" {
+            break;
+        }
+        let relative = name.trim_start_matches("./");
+        if relative.is_empty() || relative == "." {
+            io::copy(&mut reader.take(size), &mut io::sink())?;
+            skip_padding(reader, size)?;
+            continue;
+        }
+        crate::debug!("extracting {}", relative);
+        let target = entry_path(out_path, Path::new(relative), allow_unsafe_paths)?;
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+            // An earlier symlink could point the parent out of the output
+            if !allow_unsafe_paths && !parent.canonicalize()?.starts_with(out_path.canonicalize()?)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "entry would be extracted outside of the output through a symlink: {}",
+                        target.display()
+                    ),
+                ));
+            }
+        }
+        // Replace an earlier symlink rather than writing through it
+        if fs::symlink_metadata(&target).is_ok_and(|m| m.file_type().is_symlink()) {
+            fs::remove_file(&target)?;
+        }
+        match mode & 0o170000 {
+            0o040000 => {
+                fs::create_dir_all(&target)?;
+                directories.push((target, mode, mtime));
+            }
+            0o100000 => {
+                let mut file = File::create(&target)?;
+                let copied = io::copy(&mut reader.take(size), &mut file)?;
+                if copied < size {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "truncated cpio archive",
+                    ));
+                }
+                drop(file);
+                restore_metadata(&target, mode, mtime as u64)?;
+                if links_count > 1 {
+                    let earlier = links.entry(inode).or_default();
+                    if size > 0 {
+                        for link in earlier.drain(..) {
+                            fs::remove_file(&link)?;
+                            fs::hard_link(&target, &link)?;
+                        }
+                    } else {
+                        earlier.push(target);
+                    }
+                }
+            }
+            0o120000 => {
+                if size > MAX_LINK_TARGET {
+                    return Err(corrupt());
+                }
+                let mut link = vec![0; size as usize];
+                reader.read_exact(&mut link)?;
+                #[cfg(unix)]
+                std::os::unix::fs::symlink(String::from_utf8_lossy(&link).as_ref(), &target)?;
+                #[cfg(not(unix))]
+                crate::warn!("skipping {}, symlinks aren't supported here", relative);
+            }
+            _ => {
+                crate::info!("skipping {}, it is not a file or directory", relative);
+                io::copy(&mut reader.take(size), &mut io::sink())?;
+            }
+        }
+        skip_padding(reader, size)?;
+    }
+    for (path, mode, mtime) in directories.into_iter().rev() {
+        restore_metadata(&path, mode, mtime as u64)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ar::Ar;
+    use crate::gzip::Gzip;
+    use assert_fs::prelude::*;
+    use std::io::Write;
+
+    /// A cpio entry in the "new" format
+    fn cpio_entry(name: &str, mode: u32, links: u32, data: &[u8]) -> Vec<u8> {
+        let mut entry = format!(
+            "070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+            1,
+            mode,
+            0,
+            0,
+            links,
+            1_700_000_000,
+            data.len(),
+            0,
+            0,
+            0,
+            0,
+            name.len() + 1,
+            0
+        )
+        .into_bytes();
+        entry.extend(name.bytes());
+        entry.push(0);
+        entry.resize(entry.len().next_multiple_of(4), 0);
+        entry.extend_from_slice(data);
+        entry.resize(entry.len().next_multiple_of(4), 0);
+        entry
+    }
+
+    /// An rpm header structure with no tags, padded like the signature
+    fn rpm_header(data_size: u32, padded: bool) -> Vec<u8> {
+        let mut header = vec![0x8e, 0xad, 0xe8, 0x01, 0, 0, 0, 0];
+        header.extend(0u32.to_be_bytes());
+        header.extend(data_size.to_be_bytes());
+        header.extend(vec![0; data_size as usize]);
+        if padded {
+            header.resize(header.len().next_multiple_of(8), 0);
+        }
+        header
+    }
+
+    #[test]
+    fn rpm() -> Result<(), Box<dyn std::error::Error>> {
+        let mut cpio = cpio_entry(".", 0o040755, 2, b"");
+        cpio.extend(cpio_entry("./usr/bin", 0o040755, 2, b""));
+        cpio.extend(cpio_entry("./usr/bin/tool", 0o100755, 1, b"#!/bin/sh\n"));
+        cpio.extend(cpio_entry("./usr/bin/alias", 0o120777, 1, b"tool"));
+        cpio.extend(cpio_entry("TRAILER!!!", 0, 1, b""));
+        let mut payload = Vec::new();
+        Gzip::default()
+            .wrap_writer(Box::new(&mut payload))?
+            .write_all(&cpio)?;
+
+        let mut package = RPM_MAGIC.to_vec();
+        package.resize(96, 0);
+        package.extend(rpm_header(5, true));
+        package.extend(rpm_header(5, false));
+        package.extend(payload);
+
+        let dir = assert_fs::TempDir::new()?;
+        let file = dir.child("tool-1.0.x86_64.rpm");
+        file.write_binary(&package)?;
+        extract(&ExtractArgs {
+            input: file.path().to_path_buf(),
+            output: Some(dir.child("out").path().to_path_buf()),
+            allow_unsafe_paths: false,
+        })?;
+        dir.child("out/usr/bin/tool").assert("#!/bin/sh\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dir.child("out/usr/bin/tool").path())?.permissions();
+            assert_eq!(mode.mode() & 0o777, 0o755);
+            assert_eq!(
+                fs::read_link(dir.child("out/usr/bin/alias").path())?,
+                Path::new("tool")
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn deb() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = assert_fs::TempDir::new()?;
+        let files = dir.child("files");
+        files
+            .child("usr/share/doc/readme")
+            .write_str("documentation")?;
+        let data = dir.child("data.tar.gz");
+        crate::pipeline::Pipeline::new(Tar::default(), Box::<Gzip>::default()).compress(
+            CmprssInput::Path(vec![files.child("usr").path().to_path_buf()]),
+            CmprssOutput::Path(data.path().to_path_buf()),
+        )?;
+        let debian_binary = dir.child("debian-binary");
+        debian_binary.write_str("2.0\n")?;
+        let package = dir.child("tool_1.0_amd64.deb");
+        Ar::default().compress(
+            CmprssInput::Path(vec![
+                debian_binary.path().to_path_buf(),
+                data.path().to_path_buf(),
+            ]),
+            CmprssOutput::Path(package.path().to_path_buf()),
+        )?;
+
+        extract(&ExtractArgs {
+            input: package.path().to_path_buf(),
+            output: Some(dir.child("out").path().to_path_buf()),
+            allow_unsafe_paths: false,
+        })?;
+        dir.child("out/usr/share/doc/readme")
+            .assert("documentation");
+
+        let result = extract(&ExtractArgs {
+            input: debian_binary.path().to_path_buf(),
+            output: None,
+            allow_unsafe_paths: false,
+        });
+        assert!(result.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,300 @@
+//! Chains of a tar archive and a stream compressor, like .tar.gz
+
+use crate::exclude::Exclude;
+use crate::limits::OutputLimits;
+use crate::progress::{ProgressDisplay, ProgressObserver};
+use crate::tar::Tar;
+use crate::transform::Transform;
+use crate::utils::*;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+/// A tar archive compressed with a stream compressor in a single pass
+///
+/// The tar side runs on a separate thread, connected to the stream compressor with a pipe.
+pub struct Pipeline {
+    name: String,
+    /// Short forms of the extension, like tgz
+    short_extensions: &'static [&'static str],
+    tar: Tar,
+    stream: Box<dyn Compressor>,
+}
+
+impl Pipeline {
+    pub fn new(mut tar: Tar, stream: Box<dyn Compressor>) -> Pipeline {
+        // The stream compressor shows the progress of the whole pipeline
+        tar.progress_args.progress = ProgressDisplay::Off;
+        let short_extensions: &[&str] = match stream.name() {
+            "gzip" => &["tgz", "taz"],
+            "xz" => &["txz"],
+            "bzip2" => &["tbz2", "tbz"],
+            "zstd" => &["tzst"],
+            "lzw" => &["tZ", "taZ"],
+            "lzop" => &["tzo"],
+            _ => &[],
+        };
+        Pipeline {
+            name: format!("tar.{}", stream.extension()),
+            short_extensions,
+            tar,
+            stream,
+        }
+    }
+}
+
+/// Combine the results of the two sides of a pipeline
+/// When one side fails, the other usually fails with a broken pipe, so report the real error.
+pub(crate) fn pipeline_result<T>(
+    stream: Result<(), io::Error>,
+    tar: thread::Result<Result<T, io::Error>>,
+) -> Result<T, io::Error> {
+    let tar = tar.unwrap_or_else(|_| Err(io::Error::other("tar thread panicked")));
+    match (stream, tar) {
+        (Err(e), Err(tar_error)) if e.kind() == io::ErrorKind::BrokenPipe => Err(tar_error),
+        (stream, tar) => stream.and(tar),
+    }
+}
+
+impl Compressor for Pipeline {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn extension(&self) -> &str {
+        &self.name
+    }
+
+    fn set_threads(&mut self, threads: u32) {
+        self.stream.set_threads(threads);
+    }
+
+    fn set_allow_unsafe_paths(&mut self, allow: bool) {
+        self.tar.set_allow_unsafe_paths(allow);
+    }
+
+    fn set_strip_components(&mut self, count: usize) {
+        self.tar.set_strip_components(count);
+    }
+
+    fn set_from_list(&mut self, from_list: bool) {
+        self.tar.set_from_list(from_list);
+    }
+
+    fn set_directory(&mut self, directory: PathBuf) {
+        self.tar.set_directory(directory);
+    }
+
+    fn set_transform(&mut self, transform: Transform) {
+        self.tar.set_transform(transform);
+    }
+
+    fn set_symlinks(&mut self, symlinks: Symlinks) {
+        self.tar.set_symlinks(symlinks);
+    }
+
+    fn set_limits(&mut self, limits: OutputLimits) {
+        self.tar.set_limits(limits);
+        self.stream.set_limits(limits);
+    }
+
+    fn set_observer(&mut self, observer: Arc<dyn ProgressObserver>) {
+        self.stream.set_observer(observer);
+    }
+
+    fn set_exclude(&mut self, exclude: Exclude) {
+        self.tar.set_exclude(exclude);
+    }
+
+    fn set_reproducible(&mut self, reproducible: Reproducible) {
+        self.tar.set_reproducible(reproducible);
+        self.stream.set_reproducible(reproducible);
+    }
+
+    fn set_mtime(&mut self, mtime: MtimeOverride) {
+        self.tar.set_mtime(mtime);
+    }
+
+    fn set_level(&mut self, level: i32) {
+        self.stream.set_level(level);
+    }
+
+    fn level(&self) -> Option<i32> {
+        self.stream.level()
+    }
+
+    fn threads(&self) -> Option<u32> {
+        self.stream.threads()
+    }
+
+    fn set_progress(&mut self, progress: ProgressDisplay) {
+        self.stream.set_progress(progress);
+    }
+
+    fn progress(&self) -> ProgressDisplay {
+        self.stream.progress()
+    }
+
+    /// Check for the full extension, like .tar.gz, or one of the short forms, like .tgz
+    fn is_archive(&self, in_path: &Path) -> bool {
+        let Some(filename) = in_path.file_name().and_then(|f| f.to_str()) else {
+            return false;
+        };
+        if filename.ends_with(&format!(".{}", self.name)) {
+            return true;
+        }
+        in_path
+            .extension()
+            .is_some_and(|ext| self.short_extensions.iter().any(|short| ext == *short))
+    }
+
+    /// Extracts like tar, so use the current directory
+    fn default_extracted_filename(&self, _in_path: &Path) -> String {
+        ".".to_string()
+    }
+
+    fn compress(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        let size = match &input {
+            CmprssInput::Path(paths) => Some(self.tar.archive_size(paths)),
+            _ => None,
+        };
+        let (reader, writer) = io::pipe()?;
+        let tar = self.tar.clone();
+        let archiver = thread::Builder::new().spawn(move || {
+            tar.compress(
+                input,
+                CmprssOutput::Writer(CmprssWrite {
+                    writer: Box::new(writer),
+                }),
+            )
+        })?;
+        let result = self.stream.compress(
+            CmprssInput::Reader(CmprssRead {
+                reader: Box::new(reader),
+                size,
+                name: None,
+            }),
+            output,
+        );
+        pipeline_result(result, archiver.join())
+    }
+
+    fn extract(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        let (reader, writer) = io::pipe()?;
+        let tar = self.tar.clone();
+        let archiver = thread::Builder::new().spawn(move || {
+            tar.extract(
+                CmprssInput::Reader(CmprssRead {
+                    reader: Box::new(reader),
+                    size: None,
+                    name: None,
+                }),
+                output,
+            )
+        })?;
+        let result = self.stream.extract(
+            input,
+            CmprssOutput::Writer(CmprssWrite {
+                writer: Box::new(writer),
+            }),
+        );
+        pipeline_result(result, archiver.join())
+    }
+
+    fn list(&self, input: CmprssInput) -> Result<Vec<ArchiveEntry>, io::Error> {
+        let (reader, writer) = io::pipe()?;
+        let tar = self.tar.clone();
+        let lister = thread::Builder::new().spawn(move || {
+            tar.list(CmprssInput::Reader(CmprssRead {
+                reader: Box::new(reader),
+                size: None,
+                name: None,
+            }))
+        })?;
+        let result = self.stream.extract(
+            input,
+            CmprssOutput::Writer(CmprssWrite {
+                writer: Box::new(writer),
+            }),
+        );
+        pipeline_result(result, lister.join())
+    }
+
+    fn cat(&self, input: CmprssInput, entry: &str, output: CmprssOutput) -> Result<(), io::Error> {
+        let (reader, writer) = io::pipe()?;
+        let tar = self.tar.clone();
+        let entry = entry.to_string();
+        let reader_thread = thread::Builder::new().spawn(move || {
+            tar.cat(
+                CmprssInput::Reader(CmprssRead {
+                    reader: Box::new(reader),
+                    size: None,
+                    name: None,
+                }),
+                &entry,
+                output,
+            )
+        })?;
+        let result = self.stream.extract(
+            input,
+            CmprssOutput::Writer(CmprssWrite {
+                writer: Box::new(writer),
+            }),
+        );
+        pipeline_result(result, reader_thread.join())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gzip::Gzip;
+    use assert_fs::prelude::*;
+    use predicates::prelude::*;
+
+    #[test]
+    fn roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Pipeline::new(Tar::default(), Box::<Gzip>::default());
+        assert_eq!(compressor.name(), "tar.gz");
+        assert!(compressor.is_archive(Path::new("dir/archive.tar.gz")));
+        assert!(compressor.is_archive(Path::new("archive.tgz")));
+        assert!(!compressor.is_archive(Path::new("archive.gz")));
+
+        let file = assert_fs::NamedTempFile::new("test.txt")?;
+        file.write_str("garbage data for testing")?;
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("archive.".to_owned() + compressor.extension());
+
+        compressor.compress(
+            CmprssInput::Path(vec![file.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        archive.assert(predicate::path::is_file());
+
+        let extract_dir = working_dir.child("out");
+        extract_dir.create_dir_all()?;
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(extract_dir.path().to_path_buf()),
+        )?;
+        extract_dir
+            .child("test.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        let entries = compressor.list(CmprssInput::Path(vec![archive.path().to_path_buf()]))?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, "test.txt");
+
+        // Extracting to a file fails on the tar side, which is the error to report
+        let error = compressor
+            .extract(
+                CmprssInput::Path(vec![archive.path().to_path_buf()]),
+                CmprssOutput::Path(file.path().to_path_buf()),
+            )
+            .unwrap_err();
+        assert!(error.to_string().contains("directory"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,333 @@
+//! Formats implemented by external commands, declared in the config file
+
+use crate::utils::*;
+use clap::Args;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+#[derive(Args, Debug)]
+pub struct PluginArgs {
+    #[clap(flatten)]
+    pub common_args: CommonArgs,
+}
+
+/// How a plugin is declared in the config file
+///
+/// ```toml
+/// [plugins.lrzip]
+/// extension = "lrz"
+/// compress = ["lrzip", "--quiet"]
+/// extract = ["lrzip", "--decompress", "--quiet"]
+/// ```
+///
+/// By default the commands read their input from stdin and write their output to stdout.
+/// An argument of `{inputs}` is replaced by the input paths instead, and `{output}` anywhere
+/// in an argument is replaced by the output path.
+#[derive(Deserialize, Debug, Clone)]
+struct PluginSpec {
+    /// Extension of the format, defaults to the plugin name
+    extension: Option<String>,
+    /// Whether the format holds multiple files, and so extracts to a directory
+    #[serde(default)]
+    archive: bool,
+    compress: Vec<String>,
+    extract: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Config {
+    #[serde(default)]
+    plugins: BTreeMap<String, PluginSpec>,
+}
+
+/// A format implemented by external commands
+#[derive(Debug, Clone)]
+pub struct Plugin {
+    pub name: String,
+    pub extension: String,
+    /// Whether the format holds multiple files, and so extracts to a directory
+    pub archive: bool,
+    /// Command and arguments used to compress stdin to stdout
+    pub compress: Vec<String>,
+    /// Command and arguments used to extract stdin to stdout
+    pub extract: Vec<String>,
+}
+
+static PLUGINS: OnceLock<Vec<Plugin>> = OnceLock::new();
+
+/// Make the plugins available to the filename inference
+/// Only the first call has any effect.
+pub fn register(plugins: Vec<Plugin>) {
+    let _ = PLUGINS.set(plugins);
+}
+
+/// The plugins that were registered
+pub fn registered() -> &'static [Plugin] {
+    PLUGINS.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+/// Find a registered plugin by name
+pub fn find(name: &str) -> Option<&'static Plugin> {
+    registered().iter().find(|p| p.name == name)
+}
+
+/// Load the plugins declared in the config file
+/// A missing config file is not an error, there are just no plugins.
+pub fn load(path: &Path) -> Result<Vec<Plugin>, io::Error> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    parse(&contents).map_err(|e| io::Error::other(format!("{}: {}", path.display(), e)))
+}
+
+fn parse(contents: &str) -> Result<Vec<Plugin>, io::Error> {
+    let config: Config = toml::from_str(contents).map_err(io::Error::other)?;
+    config
+        .plugins
+        .into_iter()
+        .map(|(name, spec)| {
+            if spec.compress.is_empty() || spec.extract.is_empty() {
+                return Err(io::Error::other(format!(
+                    "plugin {} needs both a compress and an extract command",
+                    name
+                )));
+            }
+            Ok(Plugin {
+                extension: spec.extension.unwrap_or_else(|| name.clone()),
+                name,
+                archive: spec.archive,
+                compress: spec.compress,
+                extract: spec.extract,
+            })
+        })
+        .collect()
+}
+
+const INPUTS: &str = "{inputs}";
+const OUTPUT: &str = "{output}";
+
+impl Plugin {
+    /// Run one of the commands, connecting the input to its stdin and its stdout to the output
+    /// unless the command takes them as paths.
+    fn run(
+        &self,
+        command: &[String],
+        input: CmprssInput,
+        output: CmprssOutput,
+    ) -> Result<(), io::Error> {
+        let Some((program, args)) = command.split_first() else {
+            return Err(io::Error::other(format!(
+                "plugin {} has an empty command",
+                self.name
+            )));
+        };
+        let takes_inputs = args.iter().any(|arg| arg == INPUTS);
+        let takes_output = args.iter().any(|arg| arg.contains(OUTPUT));
+        let mut cmd = Command::new(program);
+
+        let mut reader = None;
+        let mut input_paths = Vec::new();
+        match input {
+            CmprssInput::Path(paths) if takes_inputs => {
+                cmd.stdin(Stdio::null());
+                input_paths = paths;
+            }
+            CmprssInput::Path(paths) => {
+                if paths.len() > 1 {
+                    return cmprss_error("only 1 file can be processed by a plugin at a time");
+                }
+                if paths[0].is_dir() {
+                    return cmprss_error("plugins do not support directories");
+                }
+                cmd.stdin(File::open(&paths[0])?);
+            }
+            _ if takes_inputs => {
+                return Err(io::Error::other(format!(
+                    "{} only supports input files",
+                    program
+                )));
+            }
+            CmprssInput::Pipe(pipe) => {
+                cmd.stdin(Stdio::piped());
+                reader = Some(pipe);
+            }
+            CmprssInput::Reader(input) => {
+                cmd.stdin(Stdio::piped());
+                reader = Some(input.reader);
+            }
+        }
+        let mut writer = None;
+        let mut output_path = PathBuf::new();
+        match output {
+            CmprssOutput::Path(path) if takes_output => {
+                // The tool writes the output itself, its own messages go to stderr
+                cmd.stdout(io::stderr());
+                output_path = path;
+            }
+            CmprssOutput::Path(path) => {
+                cmd.stdout(File::create(path)?);
+            }
+            _ if takes_output => {
+                return Err(io::Error::other(format!(
+                    "{} only supports an output path",
+                    program
+                )));
+            }
+            CmprssOutput::Pipe(pipe) => {
+                cmd.stdout(Stdio::piped());
+                writer = Some(pipe);
+            }
+            CmprssOutput::Writer(output) => {
+                cmd.stdout(Stdio::piped());
+                writer = Some(output.writer);
+            }
+        }
+        for arg in args {
+            if arg == INPUTS {
+                cmd.args(&input_paths);
+            } else {
+                let output_path = output_path.to_string_lossy();
+                cmd.arg(arg.replace(OUTPUT, &output_path));
+            }
+        }
+
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| io::Error::other(format!("failed to run {}: {}", program, e)))?;
+        // Feed the input from another thread so that the output can be read at the same time
+        let feeder = match (reader, child.stdin.take()) {
+            (Some(mut reader), Some(mut stdin)) => Some(
+                std::thread::Builder::new()
+                    .spawn(move || io::copy(&mut reader, &mut stdin).map(|_| ()))?,
+            ),
+            _ => None,
+        };
+        let copied = match (writer, child.stdout.take()) {
+            (Some(mut writer), Some(mut stdout)) => {
+                io::copy(&mut stdout, &mut writer).and_then(|_| writer.flush())
+            }
+            _ => Ok(()),
+        };
+        let status = child.wait()?;
+        let fed = match feeder {
+            Some(feeder) => feeder
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("plugin input thread panicked"))),
+            None => Ok(()),
+        };
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "{} failed with {}",
+                program, status
+            )));
+        }
+        fed?;
+        copied
+    }
+}
+
+impl Compressor for Plugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn extension(&self) -> &str {
+        &self.extension
+    }
+
+    fn is_stream(&self) -> bool {
+        !self.archive
+    }
+
+    /// Archives extract to the current directory, streams strip the extension like the other
+    /// stream formats
+    fn default_extracted_filename(&self, in_path: &Path) -> String {
+        if self.archive {
+            return ".".to_string();
+        }
+        if in_path.extension().is_none() {
+            return "archive".to_string();
+        }
+        in_path.file_stem().unwrap().to_str().unwrap().to_string()
+    }
+
+    fn compress(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        self.run(&self.compress, input, output)
+    }
+
+    fn extract(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        self.run(&self.extract, input, output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_parsing() {
+        let plugins = parse(
+            r#"
+            [plugins.lrzip]
+            extension = "lrz"
+            compress = ["lrzip", "--quiet"]
+            extract = ["lrzip", "--decompress", "--quiet"]
+
+            [plugins.zpaq]
+            compress = ["zpaq-stream", "c"]
+            extract = ["zpaq-stream", "d"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(plugins.len(), 2);
+        assert_eq!(plugins[0].name, "lrzip");
+        assert_eq!(plugins[0].extension, "lrz");
+        assert_eq!(plugins[0].extract, ["lrzip", "--decompress", "--quiet"]);
+        assert_eq!(plugins[1].extension, "zpaq");
+
+        assert!(parse("").unwrap().is_empty());
+        assert!(parse("[plugins.empty]\ncompress = []\nextract = []").is_err());
+        assert!(parse("[plugins.missing]\ncompress = [\"cat\"]").is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        use assert_fs::prelude::*;
+
+        let plugin = Plugin {
+            name: "upper".to_string(),
+            extension: "up".to_string(),
+            archive: false,
+            compress: vec!["tr".into(), "a-z".into(), "A-Z".into()],
+            extract: vec!["tr".into(), "A-Z".into(), "a-z".into()],
+        };
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("test.txt.up");
+        plugin.compress(
+            CmprssInput::Reader(CmprssRead {
+                reader: Box::new(&b"garbage data for testing"[..]),
+                size: None,
+                name: None,
+            }),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        archive.assert("GARBAGE DATA FOR TESTING");
+
+        let extracted = working_dir.child("test.txt");
+        plugin.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(extracted.path().to_path_buf()),
+        )?;
+        extracted.assert("garbage data for testing");
+
+        Ok(())
+    }
+}
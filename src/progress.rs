@@ -1,7 +1,17 @@
+//! Progress bar shown while compressing or extracting
+
+use crate::limits::OutputLimits;
+use crate::log::{self, Verbosity};
 use crate::utils::CmprssOutput;
 use clap::Args;
-use indicatif::{HumanBytes, ProgressBar};
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget};
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
 
 #[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
 pub enum ProgressDisplay {
@@ -9,6 +19,8 @@ pub enum ProgressDisplay {
     Auto,
     On,
     Off,
+    /// Line-delimited JSON events on stderr, for other programs to parse
+    Json,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -36,8 +48,11 @@ impl FromStr for ChunkSize {
             return Ok(ChunkSize { size_in_bytes: num });
         }
         // Simplify so that we always assume base 2, regardless of whether we see
-        // 'kb' or 'kib'
+        // 'k', 'kb' or 'kib'
         let mut s = s.to_lowercase();
+        if s.ends_with(['k', 'm', 'g']) {
+            s.push('b');
+        }
         if s.ends_with("ib") {
             s.truncate(s.len() - 2);
             s.push('b');
@@ -70,26 +85,394 @@ pub struct ProgressArgs {
     pub chunk_size: ChunkSize,
 }
 
+/// Receives progress updates from a running job
+/// Library users can implement this to show their own progress display.
+pub trait ProgressObserver: Send + Sync {
+    /// Called after each chunk with the total bytes read from the input and written to the output
+    fn update(&self, input_read: u64, output_written: u64);
+
+    /// Called once all the data has been copied
+    fn finish(&self) {}
+}
+
+impl<F: Fn(u64, u64) + Send + Sync> ProgressObserver for F {
+    fn update(&self, input_read: u64, output_written: u64) {
+        self(input_read, output_written)
+    }
+}
+
+/// Token that cancels a running job when triggered, e.g. from another thread
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the jobs using this token
+    /// Jobs check the token between chunks, and fail with an error once cancelled
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Progress reporting and cancellation hooks of a compressor, for library users
+#[derive(Clone, Default)]
+pub struct ProgressHooks {
+    /// Observer that receives the progress, used instead of the progress bar when set
+    pub observer: Option<Arc<dyn ProgressObserver>>,
+    /// Token to cancel the job
+    pub cancel: CancellationToken,
+    /// Limits on the output, checked after each chunk
+    pub limits: OutputLimits,
+}
+
+impl fmt::Debug for ProgressHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressHooks")
+            .field("observer", &self.observer.is_some())
+            .field("cancel", &self.cancel)
+            .field("limits", &self.limits)
+            .finish()
+    }
+}
+
+/// Copy the input to the output in chunks, reporting progress and checking for cancellation
+///
+/// The writer is usually an encoder or decoder, so `output_written` is used to get the number
+/// of bytes that have made it to the final output.
+/// The observer is the hooks' observer if set, otherwise the progress bar if there is one.
+pub fn copy_with_progress<W: Write>(
+    reader: &mut dyn Read,
+    writer: &mut W,
+    chunk_size: usize,
+    hooks: &ProgressHooks,
+    bar: Option<&Progress>,
+    output_written: impl Fn(&W) -> u64,
+) -> Result<(), io::Error> {
+    let observer = match &hooks.observer {
+        Some(observer) => Some(observer.as_ref()),
+        None => bar.map(|bar| bar as &dyn ProgressObserver),
+    };
+    let mut buffer = vec![0; chunk_size];
+    let mut input_read = 0;
+    loop {
+        if hooks.cancel.is_cancelled() {
+            return Err(io::Error::other("cancelled"));
+        }
+        let bytes_read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buffer[..bytes_read])?;
+        input_read += bytes_read as u64;
+        let written = output_written(writer);
+        hooks.limits.check(input_read, written)?;
+        if let Some(observer) = observer {
+            observer.update(input_read, written);
+        }
+    }
+    writer.flush()?;
+    if let Some(observer) = observer {
+        observer.update(input_read, output_written(writer));
+        observer.finish();
+    }
+    Ok(())
+}
+
+/// Writer that counts the bytes written through it
+/// Used to measure the output of encoders that don't track it themselves.
+pub struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        CountingWriter { inner, count: 0 }
+    }
+
+    /// Number of bytes written so far
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Progress of an archive format, which copies many entries instead of a single stream
+///
+/// Readers and writers wrapped by it count the bytes read from the input and written to the
+/// output, report them after every call, and fail once the job is cancelled.
+/// Clones share the same counters, so the wrappers can be moved into the archive libraries.
+#[derive(Clone)]
+pub struct ArchiveProgress {
+    inner: Arc<ArchiveCounters>,
+}
+
+struct ArchiveCounters {
+    observer: Option<Arc<dyn ProgressObserver>>,
+    bar: Option<Progress>,
+    cancel: CancellationToken,
+    input_read: AtomicU64,
+    output_written: AtomicU64,
+}
+
+impl ArchiveProgress {
+    /// Report to the hooks' observer if set, otherwise to the progress bar if there is one
+    pub fn new(hooks: &ProgressHooks, bar: Option<Progress>) -> Self {
+        ArchiveProgress {
+            inner: Arc::new(ArchiveCounters {
+                observer: hooks.observer.clone(),
+                bar: bar.filter(|_| hooks.observer.is_none()),
+                cancel: hooks.cancel.clone(),
+                input_read: AtomicU64::new(0),
+                output_written: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Count the bytes read through the reader as input
+    pub fn reader<R>(&self, inner: R) -> ProgressReader<R> {
+        ProgressReader {
+            inner,
+            progress: self.clone(),
+        }
+    }
+
+    /// Count the bytes written through the writer as output
+    pub fn writer<W>(&self, inner: W) -> ProgressWriter<W> {
+        ProgressWriter {
+            inner,
+            progress: self.clone(),
+        }
+    }
+
+    /// Count output written without a wrapped writer, like files unpacked by the tar crate
+    pub fn add_output(&self, bytes: u64) {
+        self.inner
+            .output_written
+            .fetch_add(bytes, Ordering::Relaxed);
+        self.update();
+    }
+
+    /// Total bytes read from the input and written to the output so far
+    pub fn totals(&self) -> (u64, u64) {
+        (
+            self.inner.input_read.load(Ordering::Relaxed),
+            self.inner.output_written.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Report the final totals, once all the entries have been copied
+    pub fn finish(&self) {
+        self.update();
+        if let Some(observer) = self.observer() {
+            observer.finish();
+        }
+    }
+
+    fn observer(&self) -> Option<&dyn ProgressObserver> {
+        match &self.inner.observer {
+            Some(observer) => Some(observer.as_ref()),
+            None => self
+                .inner
+                .bar
+                .as_ref()
+                .map(|bar| bar as &dyn ProgressObserver),
+        }
+    }
+
+    fn update(&self) {
+        if let Some(observer) = self.observer() {
+            let (input_read, output_written) = self.totals();
+            observer.update(input_read, output_written);
+        }
+    }
+
+    fn check_cancelled(&self) -> io::Result<()> {
+        match self.inner.cancel.is_cancelled() {
+            true => Err(io::Error::other("cancelled")),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Reader counting its bytes as the input of an [`ArchiveProgress`]
+pub struct ProgressReader<R> {
+    inner: R,
+    progress: ArchiveProgress,
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.progress.check_cancelled()?;
+        let read = self.inner.read(buf)?;
+        let counters = &self.progress.inner;
+        counters
+            .input_read
+            .fetch_add(read as u64, Ordering::Relaxed);
+        self.progress.update();
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for ProgressReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Writer counting its bytes as the output of an [`ArchiveProgress`]
+pub struct ProgressWriter<W> {
+    inner: W,
+    progress: ArchiveProgress,
+}
+
+impl<W> ProgressWriter<W> {
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for ProgressWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.progress.check_cancelled()?;
+        let written = self.inner.write(buf)?;
+        self.progress.add_output(written as u64);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Seek> Seek for ProgressWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Interval between the JSON progress events
+const JSON_INTERVAL: Duration = Duration::from_millis(500);
+
 /// Progress bar for the compress process
 pub struct Progress {
     /// The progress bar
     bar: ProgressBar,
-    /// The number of bytes read from the input
-    input_read: u64,
-    /// The number of bytes written to the output
-    output_written: u64,
+    /// Emits the JSON events, when they are shown instead of the bar
+    json: Option<Arc<JsonEvents>>,
+}
+
+/// State shared with the thread emitting the JSON events
+struct JsonEvents {
+    /// Hidden bar, which tracks the position and estimates the time left
+    bar: ProgressBar,
+    output_written: AtomicU64,
+    /// Set once the job is done, which stops the thread
+    finished: Mutex<bool>,
+    wake: Condvar,
+}
+
+impl JsonEvents {
+    /// One event as a line of JSON, with null for the values that aren't known
+    fn event(&self, done: bool) -> String {
+        let input_read = self.bar.position();
+        let output_written = self.output_written.load(Ordering::Relaxed);
+        let null = || "null".to_string();
+        let total = self
+            .bar
+            .length()
+            .map_or_else(null, |total| total.to_string());
+        let ratio = match input_read {
+            0 => null(),
+            _ => format!("{:.4}", output_written as f64 / input_read as f64),
+        };
+        let eta = match (done, self.bar.length()) {
+            (true, _) => "0".to_string(),
+            (false, Some(_)) => format!("{:.1}", self.bar.eta().as_secs_f64()),
+            (false, None) => null(),
+        };
+        format!(
+            r#"{{"bytes_in":{},"bytes_out":{},"total":{},"ratio":{},"elapsed":{:.1},"eta":{},"done":{}}}"#,
+            input_read,
+            output_written,
+            total,
+            ratio,
+            self.bar.elapsed().as_secs_f64(),
+            eta,
+            done
+        )
+    }
+
+    /// Emit an event every interval until the job is finished
+    /// Events are written while holding the lock, so none follows the final one.
+    fn run(&self) {
+        let mut finished = self.finished.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            finished = match self.wake.wait_timeout(finished, JSON_INTERVAL) {
+                Ok((finished, _)) => finished,
+                Err(e) => e.into_inner().0,
+            };
+            if *finished {
+                return;
+            }
+            eprintln!("{}", self.event(false));
+        }
+    }
+
+    /// Stop the thread, emitting the final event if the job completed
+    fn stop(&self, done: bool) {
+        let mut finished = self.finished.lock().unwrap_or_else(|e| e.into_inner());
+        if done && !*finished {
+            eprintln!("{}", self.event(true));
+        }
+        *finished = true;
+        self.wake.notify_all();
+    }
 }
 
 /// Create a progress bar if necessary
+/// There is none when the progress goes to an observer, like the bar of a batch.
 pub fn progress_bar(
     input_size: Option<u64>,
     progress: ProgressDisplay,
     output: &CmprssOutput,
+    hooks: &ProgressHooks,
 ) -> Option<Progress> {
     match (progress, output) {
+        _ if hooks.observer.is_some() => None,
         (ProgressDisplay::Auto, CmprssOutput::Pipe(_)) => None,
-        (ProgressDisplay::Off, _) => None,
-        (_, _) => Some(Progress::new(input_size)),
+        (progress, _) => file_progress_bar(input_size, progress),
+    }
+}
+
+/// Create a progress bar if necessary, for output written to files
+pub fn file_progress_bar(input_size: Option<u64>, progress: ProgressDisplay) -> Option<Progress> {
+    match progress {
+        // The bar would be drawn over the messages
+        ProgressDisplay::Auto if log::enabled(Verbosity::Verbose) => None,
+        ProgressDisplay::Off => None,
+        ProgressDisplay::Json => Some(Progress::json(input_size)),
+        _ => Some(Progress::new(input_size)),
     }
 }
 
@@ -101,34 +484,68 @@ impl Progress {
             Some(size) => ProgressBar::new(size),
             None => ProgressBar::new_spinner(),
         };
-        bar.set_style(
-            indicatif::ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] ({eta}) [{bar:40.cyan/blue}] {bytes}/{total_bytes} => {msg}").unwrap()
-                .progress_chars("#>-"),
-        );
+        let style = indicatif::ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] ({eta}) [{bar:40.cyan/blue}] {bytes}/{total_bytes} => {msg}").unwrap()
+            .progress_chars("#>-");
+        // The default spinner uses characters the legacy Windows console can't display
+        #[cfg(windows)]
+        let style = style.tick_chars(r"-\|/ ");
+        bar.set_style(style);
+        Progress { bar, json: None }
+    }
+
+    /// Report the progress as JSON events on stderr instead of drawing a bar
+    /// An event is written every interval while the job runs, and a final one once it's done.
+    pub fn json(input_size: Option<u64>) -> Self {
+        let bar = ProgressBar::with_draw_target(input_size, ProgressDrawTarget::hidden());
+        let json = Arc::new(JsonEvents {
+            bar: bar.clone(),
+            output_written: AtomicU64::new(0),
+            finished: Mutex::new(false),
+            wake: Condvar::new(),
+        });
+        let events = json.clone();
+        // Without the thread there are only the final events, which is still usable
+        let _ = thread::Builder::new()
+            .name("progress".to_string())
+            .spawn(move || events.run());
         Progress {
             bar,
-            input_read: 0,
-            output_written: 0,
+            json: Some(json),
         }
     }
 
-    /// Update the progress bar with the number of bytes read from the input
-    pub fn update_input(&mut self, bytes_read: u64) {
-        self.input_read = bytes_read;
-        self.bar.set_position(self.input_read);
+    /// Print a message to stderr without breaking the progress bar
+    pub fn println(&self, message: impl fmt::Display) {
+        self.bar.suspend(|| eprintln!("{}", message));
     }
+}
 
-    /// Update the progress bar with the number of bytes written to the output
-    pub fn update_output(&mut self, bytes_written: u64) {
-        self.output_written = bytes_written;
-        self.bar
-            .set_message(HumanBytes(self.output_written).to_string());
+impl ProgressObserver for Progress {
+    /// Update the progress bar with the bytes read from the input and written to the output
+    fn update(&self, input_read: u64, output_written: u64) {
+        self.bar.set_position(input_read);
+        match &self.json {
+            Some(json) => json.output_written.store(output_written, Ordering::Relaxed),
+            None => self.bar.set_message(HumanBytes(output_written).to_string()),
+        }
     }
 
     /// Finish the progress bar
-    pub fn finish(&self) {
+    fn finish(&self) {
         self.bar.finish();
+        if let Some(json) = &self.json {
+            json.stop(true);
+        }
+    }
+}
+
+impl Drop for Progress {
+    /// Stop the JSON events of a job that failed, without a final event
+    fn drop(&mut self) {
+        if let Some(json) = &self.json {
+            json.stop(false);
+        }
     }
 }
 
@@ -136,6 +553,81 @@ impl Progress {
 mod tests {
     use super::*;
 
+    #[test]
+    fn copy_with_progress_reports_and_cancels() {
+        let data = vec![7u8; 10];
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let recorded = updates.clone();
+        let hooks = ProgressHooks {
+            observer: Some(Arc::new(move |input, output| {
+                recorded.lock().unwrap().push((input, output))
+            })),
+            cancel: CancellationToken::new(),
+            limits: OutputLimits::default(),
+        };
+        let mut output = Vec::new();
+        copy_with_progress(&mut &data[..], &mut output, 4, &hooks, None, |o| {
+            o.len() as u64
+        })
+        .unwrap();
+        assert_eq!(output, data);
+        assert_eq!(
+            *updates.lock().unwrap(),
+            vec![(4, 4), (8, 8), (10, 10), (10, 10)]
+        );
+
+        hooks.cancel.cancel();
+        let mut output = Vec::new();
+        assert!(copy_with_progress(&mut &data[..], &mut output, 4, &hooks, None, |_| 0).is_err());
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn archive_progress_counts_and_cancels() {
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let recorded = updates.clone();
+        let hooks = ProgressHooks {
+            observer: Some(Arc::new(move |input, output| {
+                recorded.lock().unwrap().push((input, output))
+            })),
+            ..ProgressHooks::default()
+        };
+        let progress = ArchiveProgress::new(&hooks, None);
+        let mut output = progress.writer(Vec::new());
+        io::copy(&mut progress.reader(&[7u8; 10][..]), &mut output).unwrap();
+        progress.add_output(5);
+        progress.finish();
+        assert_eq!(output.into_inner(), vec![7u8; 10]);
+        assert_eq!(progress.totals(), (10, 15));
+        assert_eq!(updates.lock().unwrap().last(), Some(&(10, 15)));
+
+        hooks.cancel.cancel();
+        let mut buffer = [0; 4];
+        assert!(progress.reader(&[7u8; 10][..]).read(&mut buffer).is_err());
+        assert!(progress.writer(Vec::new()).write(&buffer).is_err());
+    }
+
+    #[test]
+    fn json_events() {
+        let progress = Progress::json(Some(200));
+        let json = progress.json.clone().unwrap();
+        assert!(json
+            .event(false)
+            .starts_with(r#"{"bytes_in":0,"bytes_out":0,"total":200,"ratio":null,"elapsed":"#));
+        progress.update(100, 25);
+        let event = json.event(false);
+        assert!(event.contains(r#""bytes_in":100,"bytes_out":25,"total":200,"ratio":0.2500,"#));
+        assert!(event.ends_with(r#""done":false}"#));
+        progress.finish();
+        assert!(*json.finished.lock().unwrap());
+        assert!(json.event(true).ends_with(r#""eta":0,"done":true}"#));
+
+        let spinner = Progress::json(None);
+        let event = spinner.json.as_ref().unwrap().event(false);
+        assert!(event.contains(r#""total":null"#));
+        assert!(event.contains(r#""eta":null"#));
+    }
+
     #[test]
     fn chunk_size_parsing() {
         assert!(ChunkSize::from_str("0").is_err());
@@ -150,6 +642,12 @@ mod tests {
                 size_in_bytes: 1024
             }
         );
+        assert_eq!(
+            ChunkSize::from_str("64k").unwrap(),
+            ChunkSize {
+                size_in_bytes: 64 * 1024
+            }
+        );
         assert_eq!(
             ChunkSize::from_str("16kib").unwrap(),
             ChunkSize {
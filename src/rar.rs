@@ -0,0 +1,766 @@
+//! rar archive backend, which only reads archives
+//!
+//! The headers of both RAR 4 and RAR 5 archives are read, so any archive can be listed, but
+//! only files stored without compression can be extracted. The compression methods of RAR
+//! aren't implemented, those archives are extracted with the system `unrar` when it is
+//! installed and `--allow-external` is given.
+
+use clap::Args;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::exclude::Exclude;
+use crate::limits::OutputLimits;
+use crate::progress::{
+    progress_bar, ArchiveProgress, ProgressArgs, ProgressDisplay, ProgressHooks, ProgressObserver,
+};
+use crate::utils::*;
+
+const RAR4_MAGIC: &[u8; 7] = b"Rar!\x1a\x07\x00";
+const RAR5_MAGIC: &[u8; 8] = b"Rar!\x1a\x07\x01\x00";
+/// Largest header to read, anything larger is corrupt
+const MAX_HEADER_SIZE: u64 = 2 * 1024 * 1024;
+
+#[derive(Args, Debug)]
+pub struct RarArgs {
+    #[clap(flatten)]
+    pub common_args: CommonArgs,
+
+    #[clap(flatten)]
+    pub progress_args: ProgressArgs,
+}
+
+#[derive(Default, Clone)]
+pub struct Rar {
+    /// Extract entries with absolute paths or '..' outside of the output directory
+    pub allow_unsafe_paths: bool,
+    /// Leading directories to remove from the paths of extracted entries
+    pub strip_components: usize,
+    /// Entries to skip when extracting
+    pub exclude: Exclude,
+    pub progress_args: ProgressArgs,
+    /// Progress observer, cancellation, and limits on the total size of the extracted files
+    pub hooks: ProgressHooks,
+}
+
+impl Rar {
+    pub fn new(args: &RarArgs) -> Rar {
+        Rar {
+            allow_unsafe_paths: args.common_args.allow_unsafe_paths,
+            strip_components: args.common_args.strip_components,
+            exclude: Exclude::default(),
+            progress_args: args.progress_args,
+            hooks: ProgressHooks::default(),
+        }
+    }
+}
+
+impl Compressor for Rar {
+    /// Full name for rar, also used for extension
+    fn name(&self) -> &str {
+        "rar"
+    }
+
+    /// Extraction needs to specify the directory, so use the current directory
+    fn default_extracted_filename(&self, _in_path: &Path) -> String {
+        ".".to_string()
+    }
+
+    fn set_allow_unsafe_paths(&mut self, allow: bool) {
+        self.allow_unsafe_paths = allow;
+    }
+
+    fn set_strip_components(&mut self, count: usize) {
+        self.strip_components = count;
+    }
+
+    fn set_limits(&mut self, limits: OutputLimits) {
+        self.hooks.limits = limits;
+    }
+
+    fn set_observer(&mut self, observer: Arc<dyn ProgressObserver>) {
+        self.hooks.observer = Some(observer);
+    }
+
+    fn set_progress(&mut self, progress: ProgressDisplay) {
+        self.progress_args.progress = progress;
+    }
+
+    fn progress(&self) -> ProgressDisplay {
+        self.progress_args.progress
+    }
+
+    fn set_exclude(&mut self, exclude: Exclude) {
+        self.exclude = exclude;
+    }
+
+    fn compress(&self, _input: CmprssInput, _output: CmprssOutput) -> Result<(), io::Error> {
+        cmprss_error("creating rar archives is not supported, rar can only be extracted")
+    }
+
+    fn extract(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        let out_path = match &output {
+            CmprssOutput::Path(path) if path.is_dir() => long_path(path)?,
+            _ => return cmprss_error("error: rar can only extract to a directory"),
+        };
+        let (reader, input_size) = open_input(input)?;
+        let bar = progress_bar(
+            input_size,
+            self.progress_args.progress,
+            &output,
+            &self.hooks,
+        );
+        let progress = ArchiveProgress::new(&self.hooks, bar);
+        let mut archive = RarReader::new(progress.reader(reader))?;
+        let mut written = 0u64;
+        while let Some(entry) = archive.next_entry()? {
+            let path = Path::new(&entry.name);
+            if !self.exclude.is_extracted(path) {
+                crate::info!("skipping {}, it is excluded", entry.name);
+                continue;
+            }
+            let Some(name) = strip_components(path, self.strip_components) else {
+                crate::info!(
+                    "skipping {}, it is removed by --strip-components",
+                    entry.name
+                );
+                continue;
+            };
+            if entry.is_link {
+                crate::warn!(
+                    "skipping {}, links in rar archives aren't supported",
+                    entry.name
+                );
+                continue;
+            }
+            crate::debug!("extracting {}", entry.name);
+            let target = entry_path(&out_path, &name, self.allow_unsafe_paths)?;
+            if entry.is_dir {
+                fs::create_dir_all(&target)?;
+                continue;
+            }
+            entry.check_supported()?;
+            written = written.saturating_add(entry.size);
+            self.hooks
+                .limits
+                .check(input_size.unwrap_or(u64::MAX), written)?;
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut file = File::create(&target)?;
+            archive.copy_data(&entry, &mut file)?;
+            drop(file);
+            restore_metadata(&target, &entry)?;
+            progress.add_output(entry.size);
+        }
+        progress.finish();
+        Ok(())
+    }
+
+    fn list(&self, input: CmprssInput) -> Result<Vec<ArchiveEntry>, io::Error> {
+        let (reader, _) = open_input(input)?;
+        let mut archive = RarReader::new(reader)?;
+        let mut entries = Vec::new();
+        while let Some(entry) = archive.next_entry()? {
+            entries.push(ArchiveEntry {
+                path: entry.name,
+                is_dir: entry.is_dir,
+                size: entry.size,
+                compressed_size: Some(entry.packed_size),
+                mtime: entry.mtime,
+                mode: entry.mode,
+                comment: None,
+            });
+        }
+        Ok(entries)
+    }
+
+    fn cat(&self, input: CmprssInput, entry: &str, output: CmprssOutput) -> Result<(), io::Error> {
+        let (reader, _) = open_input(input)?;
+        let mut archive = RarReader::new(reader)?;
+        while let Some(found) = archive.next_entry()? {
+            if !entry_matches(&found.name, entry) {
+                continue;
+            }
+            if found.is_dir {
+                return Err(io::Error::other(format!("{} is a directory", entry)));
+            }
+            found.check_supported()?;
+            let mut output = output.into_stream()?;
+            archive.copy_data(&found, &mut output)?;
+            return output.flush();
+        }
+        Err(io::Error::other(format!("{} is not in the archive", entry)))
+    }
+}
+
+/// Open the single archive to read, with its size if known
+fn open_input(input: CmprssInput) -> Result<(Box<dyn Read + Send>, Option<u64>), io::Error> {
+    if let CmprssInput::Path(paths) = &input {
+        if paths.len() > 1 {
+            return Err(io::Error::other(
+                "only 1 archive can be extracted at a time",
+            ));
+        }
+    }
+    input.into_stream()
+}
+
+/// Restore the modification time and Unix permissions of an extracted file
+fn restore_metadata(path: &Path, entry: &Entry) -> Result<(), io::Error> {
+    if let Some(mtime) = entry.mtime.and_then(|mtime| u64::try_from(mtime).ok()) {
+        let file = fs::OpenOptions::new().write(true).open(path)?;
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime))?;
+    }
+    #[cfg(unix)]
+    if let Some(mode) = entry.mode {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))?;
+    }
+    Ok(())
+}
+
+/// A file or directory in an archive, from its header
+#[derive(Debug, Default)]
+struct Entry {
+    /// Path with '/' separators
+    name: String,
+    is_dir: bool,
+    is_link: bool,
+    size: u64,
+    packed_size: u64,
+    mtime: Option<i64>,
+    /// Unix permissions, for archives created on Unix
+    mode: Option<u32>,
+    crc: Option<u32>,
+    /// Stored without compression
+    stored: bool,
+    encrypted: bool,
+    /// Continued from or in another volume
+    split: bool,
+}
+
+impl Entry {
+    /// Check that the data of the entry can be extracted
+    fn check_supported(&self) -> Result<(), io::Error> {
+        let problem = if self.encrypted {
+            "it is encrypted"
+        } else if self.split {
+            "it is split across volumes"
+        } else if !self.stored {
+            "it is compressed, and cmprss only extracts files stored without compression"
+        } else {
+            return Ok(());
+        };
+        Err(io::Error::other(format!(
+            "unable to extract {}, {}, give --allow-external to extract it with unrar",
+            self.name, problem
+        )))
+    }
+}
+
+/// Convert a DOS date and time, which RAR 4 uses, to seconds since the Unix epoch
+fn unix_from_dos(time: u32) -> i64 {
+    let (date, time) = (time >> 16, time & 0xffff);
+    unix_from_civil(
+        1980 + (date >> 9) as i64,
+        (date >> 5) & 0xf,
+        date & 0x1f,
+        time >> 11,
+        (time >> 5) & 0x3f,
+        (time & 0x1f) * 2,
+    )
+}
+
+/// Reads the fields of a header
+struct Fields<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], io::Error> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + n)
+            .ok_or_else(|| io::Error::other("corrupt rar header"))?;
+        self.position += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, io::Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, io::Error> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, io::Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, io::Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// The variable length integers of RAR 5, 7 bits in each byte
+    fn vint(&mut self) -> Result<u64, io::Error> {
+        let mut value = 0;
+        for shift in (0..70).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(io::Error::other("corrupt rar header"))
+    }
+}
+
+/// Reads the entries of an archive in order
+struct RarReader<R: Read> {
+    inner: R,
+    rar5: bool,
+    /// Data of the current entry that hasn't been read
+    remaining: u64,
+}
+
+impl<R: Read> RarReader<R> {
+    fn new(mut inner: R) -> Result<Self, io::Error> {
+        let mut magic = [0; 7];
+        inner
+            .read_exact(&mut magic)
+            .map_err(|_| io::Error::other("not a rar archive"))?;
+        if &magic != RAR4_MAGIC {
+            let mut last = [0];
+            inner.read_exact(&mut last)?;
+            if magic[..] != RAR5_MAGIC[..7] || last[0] != RAR5_MAGIC[7] {
+                return Err(io::Error::other("not a rar archive"));
+            }
+        }
+        Ok(RarReader {
+            rar5: &magic != RAR4_MAGIC,
+            inner,
+            remaining: 0,
+        })
+    }
+
+    fn read_header(&mut self, size: u64) -> Result<Vec<u8>, io::Error> {
+        if size > MAX_HEADER_SIZE {
+            return Err(io::Error::other("corrupt rar header"));
+        }
+        let mut header = vec![0; size as usize];
+        self.inner.read_exact(&mut header)?;
+        Ok(header)
+    }
+
+    /// Read the header of the next file or directory, skipping the data of the current one
+    fn next_entry(&mut self) -> Result<Option<Entry>, io::Error> {
+        loop {
+            io::copy(&mut (&mut self.inner).take(self.remaining), &mut io::sink())?;
+            self.remaining = 0;
+            let entry = if self.rar5 {
+                self.next_rar5()?
+            } else {
+                self.next_rar4()?
+            };
+            match entry {
+                Some(Some(entry)) => return Ok(Some(entry)),
+                Some(None) => continue,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Read a RAR 4 block, None at the end of the archive and Some(None) for other blocks
+    fn next_rar4(&mut self) -> Result<Option<Option<Entry>>, io::Error> {
+        let mut base = [0; 7];
+        match self.inner.read_exact(&mut base) {
+            // Old archives can end without an end block
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        let crc = u16::from_le_bytes([base[0], base[1]]);
+        let kind = base[2];
+        let flags = u16::from_le_bytes([base[3], base[4]]);
+        let size = u16::from_le_bytes([base[5], base[6]]);
+        if size < 7 {
+            return Err(io::Error::other("corrupt rar header"));
+        }
+        let header = self.read_header(size as u64 - 7)?;
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&base[2..]);
+        hasher.update(&header);
+        if hasher.finalize() as u16 != crc {
+            return Err(io::Error::other("corrupt rar header"));
+        }
+        let mut fields = Fields {
+            data: &header,
+            position: 0,
+        };
+        // Long blocks are followed by data, its size is the first field
+        if flags & 0x8000 != 0 {
+            self.remaining = fields.u32()? as u64;
+            fields.position = 0;
+        }
+        match kind {
+            0x73 if flags & 0x0080 != 0 => {
+                Err(io::Error::other("encrypted rar archives aren't supported"))
+            }
+            0x74 => {
+                let mut packed_size = fields.u32()? as u64;
+                let mut size = fields.u32()? as u64;
+                let host_os = fields.u8()?;
+                let crc = fields.u32()?;
+                let time = fields.u32()?;
+                fields.u8()?;
+                let method = fields.u8()?;
+                let name_size = fields.u16()? as usize;
+                let attributes = fields.u32()?;
+                if flags & 0x0100 != 0 {
+                    packed_size |= (fields.u32()? as u64) << 32;
+                    size |= (fields.u32()? as u64) << 32;
+                }
+                self.remaining = packed_size;
+                // Unicode names follow the plain name after a 0, the plain name is enough
+                let name = fields.take(name_size)?;
+                let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+                let name = String::from_utf8_lossy(&name[..end]).replace('\\', "/");
+                let unix = host_os == 3;
+                Ok(Some(Some(Entry {
+                    name,
+                    is_dir: flags & 0x00e0 == 0x00e0,
+                    is_link: unix && attributes & 0o170000 == 0o120000,
+                    size,
+                    packed_size,
+                    mtime: Some(unix_from_dos(time)),
+                    mode: unix.then_some(attributes & 0o7777),
+                    crc: Some(crc),
+                    stored: method == 0x30,
+                    encrypted: flags & 0x0004 != 0,
+                    split: flags & 0x0003 != 0,
+                })))
+            }
+            0x7b => Ok(None),
+            _ => Ok(Some(None)),
+        }
+    }
+
+    /// Read a RAR 5 header, None at the end of the archive and Some(None) for other headers
+    fn next_rar5(&mut self) -> Result<Option<Option<Entry>>, io::Error> {
+        let mut crc = [0; 4];
+        match self.inner.read_exact(&mut crc) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        // The size is a vint, which the checksum covers with the rest of the header
+        let mut size_bytes = Vec::new();
+        loop {
+            let mut byte = [0];
+            self.inner.read_exact(&mut byte)?;
+            size_bytes.push(byte[0]);
+            if byte[0] & 0x80 == 0 || size_bytes.len() == 3 {
+                break;
+            }
+        }
+        let size = Fields {
+            data: &size_bytes,
+            position: 0,
+        }
+        .vint()?;
+        let header = self.read_header(size)?;
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&size_bytes);
+        hasher.update(&header);
+        if hasher.finalize() != u32::from_le_bytes(crc) {
+            return Err(io::Error::other("corrupt rar header"));
+        }
+
+        let mut fields = Fields {
+            data: &header,
+            position: 0,
+        };
+        let kind = fields.vint()?;
+        let flags = fields.vint()?;
+        let extra_size = if flags & 0x0001 != 0 {
+            fields.vint()?
+        } else {
+            0
+        };
+        if flags & 0x0002 != 0 {
+            self.remaining = fields.vint()?;
+        }
+        match kind {
+            2 => {
+                let file_flags = fields.vint()?;
+                let size = fields.vint()?;
+                let attributes = fields.vint()?;
+                let mut mtime = if file_flags & 0x0002 != 0 {
+                    Some(fields.u32()? as i64)
+                } else {
+                    None
+                };
+                let crc = if file_flags & 0x0004 != 0 {
+                    Some(fields.u32()?)
+                } else {
+                    None
+                };
+                let compression = fields.vint()?;
+                let host_os = fields.vint()?;
+                let name_size = fields.vint()? as usize;
+                let name = String::from_utf8_lossy(fields.take(name_size)?).into_owned();
+
+                let mut entry = Entry {
+                    name,
+                    is_dir: file_flags & 0x0001 != 0,
+                    size,
+                    packed_size: self.remaining,
+                    mode: (host_os == 1).then_some(attributes as u32 & 0o7777),
+                    crc,
+                    stored: (compression >> 7) & 7 == 0,
+                    split: flags & 0x0018 != 0,
+                    ..Entry::default()
+                };
+                let extra_start = header
+                    .len()
+                    .checked_sub(extra_size as usize)
+                    .ok_or_else(|| io::Error::other("corrupt rar header"))?;
+                let mut extra = Fields {
+                    data: &header[extra_start..],
+                    position: 0,
+                };
+                while extra.position < extra.data.len() {
+                    let record_size = extra.vint()? as usize;
+                    let mut record = Fields {
+                        data: extra.take(record_size)?,
+                        position: 0,
+                    };
+                    match record.vint()? {
+                        0x01 => entry.encrypted = true,
+                        0x03 => {
+                            let time_flags = record.vint()?;
+                            if time_flags & 0x0002 != 0 {
+                                mtime = Some(if time_flags & 0x0001 != 0 {
+                                    record.u32()? as i64
+                                } else {
+                                    // Windows FILETIME, 100ns intervals since 1601
+                                    (record.u64()? / 10_000_000) as i64 - 11_644_473_600
+                                });
+                            }
+                        }
+                        0x05 => entry.is_link = true,
+                        _ => {}
+                    }
+                }
+                entry.mtime = mtime;
+                Ok(Some(Some(entry)))
+            }
+            4 => Err(io::Error::other("encrypted rar archives aren't supported")),
+            5 => Ok(None),
+            _ => Ok(Some(None)),
+        }
+    }
+
+    /// Copy the data of a stored entry, checking its checksum
+    fn copy_data(&mut self, entry: &Entry, writer: &mut dyn Write) -> Result<(), io::Error> {
+        let mut reader = (&mut self.inner).take(entry.packed_size);
+        let mut hasher = crc32fast::Hasher::new();
+        let mut buffer = vec![0; 64 * 1024];
+        let mut copied = 0;
+        loop {
+            let n = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            hasher.update(&buffer[..n]);
+            writer.write_all(&buffer[..n])?;
+            copied += n as u64;
+        }
+        self.remaining = 0;
+        if copied < entry.packed_size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated rar archive",
+            ));
+        }
+        if entry.crc.is_some_and(|crc| crc != hasher.finalize()) {
+            return Err(io::Error::other(format!(
+                "checksum mismatch for {}, the archive is corrupt",
+                entry.name
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+
+    fn vint(mut value: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    /// A RAR 5 header, with its checksum and size
+    fn rar5_header(fields: &[u8]) -> Vec<u8> {
+        let mut header = vint(fields.len() as u64);
+        header.extend_from_slice(fields);
+        let mut block = crc32fast::hash(&header).to_le_bytes().to_vec();
+        block.extend(header);
+        block
+    }
+
+    /// A RAR 5 file header, stored unless a compression method is given
+    fn rar5_file(name: &str, data: &[u8], method: u64, dir: bool) -> Vec<u8> {
+        let mut fields = vint(2);
+        fields.extend(vint(0x0002));
+        fields.extend(vint(data.len() as u64));
+        fields.extend(vint(0x0002 | 0x0004 | u64::from(dir)));
+        fields.extend(vint(data.len() as u64));
+        fields.extend(vint(0o644));
+        fields.extend(1_700_000_000u32.to_le_bytes());
+        fields.extend(crc32fast::hash(data).to_le_bytes());
+        fields.extend(vint(method << 7));
+        fields.extend(vint(1));
+        fields.extend(vint(name.len() as u64));
+        fields.extend(name.bytes());
+        let mut block = rar5_header(&fields);
+        block.extend_from_slice(data);
+        block
+    }
+
+    fn rar5_archive(files: &[Vec<u8>]) -> Vec<u8> {
+        let mut archive = RAR5_MAGIC.to_vec();
+        archive.extend(rar5_header(&[1, 0, 0]));
+        for file in files {
+            archive.extend(file);
+        }
+        archive.extend(rar5_header(&[5, 0, 0]));
+        archive
+    }
+
+    /// A RAR 4 block, with its checksum
+    fn rar4_block(kind: u8, flags: u16, fields: &[u8]) -> Vec<u8> {
+        let mut header = vec![kind];
+        header.extend(flags.to_le_bytes());
+        header.extend((7 + fields.len() as u16).to_le_bytes());
+        header.extend_from_slice(fields);
+        let mut block = (crc32fast::hash(&header) as u16).to_le_bytes().to_vec();
+        block.extend(header);
+        block
+    }
+
+    fn rar4_archive(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut archive = RAR4_MAGIC.to_vec();
+        archive.extend(rar4_block(0x73, 0, &[0; 6]));
+        let mut fields = Vec::new();
+        fields.extend((data.len() as u32).to_le_bytes());
+        fields.extend((data.len() as u32).to_le_bytes());
+        fields.push(3);
+        fields.extend(crc32fast::hash(data).to_le_bytes());
+        // 2023-11-14 22:13:20
+        fields
+            .extend(((43u32 << 9 | 11 << 5 | 14) << 16 | (22 << 11 | 13 << 5 | 10)).to_le_bytes());
+        fields.push(29);
+        fields.push(0x30);
+        fields.extend((name.len() as u16).to_le_bytes());
+        fields.extend(0o100644u32.to_le_bytes());
+        fields.extend(name.bytes());
+        archive.extend(rar4_block(0x74, 0x8000, &fields));
+        archive.extend_from_slice(data);
+        archive.extend(rar4_block(0x7b, 0, &[]));
+        archive
+    }
+
+    #[test]
+    fn extract_stored() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = assert_fs::TempDir::new()?;
+        let rar5 = rar5_archive(&[
+            rar5_file("dir", b"", 0, true),
+            rar5_file("dir/test.txt", b"garbage data for testing", 0, false),
+        ]);
+        let rar4 = rar4_archive("dir\\test.txt", b"garbage data for testing");
+        for (name, archive) in [("rar5", rar5), ("rar4", rar4)] {
+            let file = dir.child(format!("{}.rar", name));
+            file.write_binary(&archive)?;
+            let out = dir.child(name);
+            out.create_dir_all()?;
+            Rar::default().extract(
+                CmprssInput::Path(vec![file.path().to_path_buf()]),
+                CmprssOutput::Path(out.path().to_path_buf()),
+            )?;
+            out.child("dir/test.txt").assert("garbage data for testing");
+            let mtime = fs::metadata(out.child("dir/test.txt").path())?.modified()?;
+            assert_eq!(mtime, UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn list() -> Result<(), Box<dyn std::error::Error>> {
+        let archive = rar5_archive(&[
+            rar5_file("dir", b"", 0, true),
+            rar5_file("dir/packed.txt", b"compressed", 3, false),
+        ]);
+        let entries = Rar::default().list(CmprssInput::Reader(CmprssRead {
+            reader: Box::new(io::Cursor::new(archive)),
+            size: None,
+            name: None,
+        }))?;
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[1].path, "dir/packed.txt");
+        assert_eq!(entries[1].size, 10);
+        assert_eq!(entries[1].mtime, Some(1_700_000_000));
+        assert_eq!(entries[1].mode, Some(0o644));
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = assert_fs::TempDir::new()?;
+        let file = dir.child("archive.rar");
+        file.write_binary(&rar5_archive(&[rar5_file("packed.txt", b"x", 3, false)]))?;
+        let result = Rar::default().extract(
+            CmprssInput::Path(vec![file.path().to_path_buf()]),
+            CmprssOutput::Path(dir.path().to_path_buf()),
+        );
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("compressed"));
+        assert!(error.contains("--allow-external"));
+
+        let result = Rar::default().compress(
+            CmprssInput::Path(vec![file.path().to_path_buf()]),
+            CmprssOutput::Path(dir.child("new.rar").path().to_path_buf()),
+        );
+        assert!(result.unwrap_err().to_string().contains("not supported"));
+
+        // A changed header fails its checksum
+        let mut corrupt = rar5_archive(&[rar5_file("test.txt", b"data", 0, false)]);
+        let last = corrupt.len() - 6;
+        corrupt[last] ^= 1;
+        let result = Rar::default().list(CmprssInput::Reader(CmprssRead {
+            reader: Box::new(io::Cursor::new(corrupt)),
+            size: None,
+            name: None,
+        }));
+        assert!(result.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+//! Cleanup of temporary outputs when the CLI is interrupted with SIGINT or SIGTERM
+//!
+//! Outputs being written are registered here while the job runs. The signal handler only
+//! wakes a thread, which removes them and exits with `128 + signal` like a shell would.
+//! Signals aren't handled on other platforms, where an interrupted job can leave its
+//! temporary file behind.
+//!
+//! Long running commands like `cmprss watch` can ask to be stopped gracefully instead, so that
+//! the first signal only sets a flag they check between files. A second signal still exits.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Temporary outputs to remove when interrupted
+static TEMP_FILES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Whether the first signal only asks the command to stop
+static GRACEFUL: AtomicBool = AtomicBool::new(false);
+
+/// Set by the first signal when stopping gracefully
+static STOP: AtomicBool = AtomicBool::new(false);
+
+/// Handle the first SIGINT or SIGTERM by asking the command to stop, see [`stop_requested`]
+pub fn stop_gracefully() {
+    GRACEFUL.store(true, Ordering::SeqCst);
+}
+
+/// Whether the command was asked to stop by a signal, after [`stop_gracefully`]
+pub fn stop_requested() -> bool {
+    STOP.load(Ordering::SeqCst)
+}
+
+/// Remove the path if the process is interrupted before it is unregistered
+pub fn register(path: &Path) {
+    if let Ok(mut paths) = TEMP_FILES.lock() {
+        paths.push(path.to_path_buf());
+    }
+}
+
+/// Stop removing the path when interrupted, e.g. once it has been moved into place
+pub fn unregister(path: &Path) {
+    if let Ok(mut paths) = TEMP_FILES.lock() {
+        if let Some(index) = paths.iter().rposition(|p| p == path) {
+            paths.remove(index);
+        }
+    }
+}
+
+/// Remove all the registered paths, and exit
+/// The lock is kept until the process exits, so that no new outputs are started.
+#[cfg(unix)]
+fn cleanup_and_exit(signal: i32) -> ! {
+    let paths = TEMP_FILES.lock().unwrap_or_else(|e| e.into_inner());
+    for path in paths.iter() {
+        let _ = match path.is_dir() {
+            true => std::fs::remove_dir_all(path),
+            false => std::fs::remove_file(path),
+        };
+    }
+    eprintln!("ERROR(cmprss): interrupted");
+    std::process::exit(128 + signal);
+}
+
+/// Handle SIGINT and SIGTERM by removing the registered outputs and exiting
+/// Only the CLI should install this, since it exits the process.
+#[cfg(unix)]
+pub fn install() -> Result<(), std::io::Error> {
+    use std::io::Read;
+    use std::os::fd::IntoRawFd;
+    use std::sync::atomic::AtomicI32;
+
+    /// Write end of the pipe waking the cleanup thread
+    static WAKE: AtomicI32 = AtomicI32::new(-1);
+
+    extern "C" fn handler(signal: libc::c_int) {
+        // Only async-signal-safe calls are allowed here, so hand the signal over to the thread
+        let byte = signal as u8;
+        // SAFETY: write is async-signal-safe, the pointer and length are those of `byte`, and
+        // the write end of the pipe is never closed, so it stays open for the whole process
+        unsafe {
+            libc::write(
+                WAKE.load(Ordering::Relaxed),
+                &byte as *const u8 as *const libc::c_void,
+                1,
+            );
+        }
+    }
+
+    let (mut reader, writer) = std::io::pipe()?;
+    WAKE.store(writer.into_raw_fd(), Ordering::Relaxed);
+    std::thread::Builder::new()
+        .name("signals".to_string())
+        .spawn(move || {
+            let mut signal = [0u8];
+            while reader.read_exact(&mut signal).is_ok() {
+                if !GRACEFUL.load(Ordering::SeqCst) || STOP.swap(true, Ordering::SeqCst) {
+                    cleanup_and_exit(signal[0] as i32);
+                }
+            }
+        })?;
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: `handler` is an extern "C" fn that lives for the whole process and only makes
+        // async-signal-safe calls, and the pipe it writes to is set up above
+        let previous = unsafe { libc::signal(signal, handler as *const () as libc::sighandler_t) };
+        if previous == libc::SIG_ERR {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Signals aren't handled on this platform
+#[cfg(not(unix))]
+pub fn install() -> Result<(), std::io::Error> {
+    Ok(())
+}
@@ -0,0 +1,190 @@
+//! Outputs split into numbered parts, like archive.tar.zst.001, and joining them again
+
+use crate::utils::*;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Path of a part of a split file, numbered from 1
+pub fn part_path(path: &Path, number: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{:03}", number));
+    PathBuf::from(name)
+}
+
+/// The path of the whole file if this is the first part of a split file, like archive.zip.001
+pub fn joined_path(path: &Path) -> Option<PathBuf> {
+    let extension = path.extension()?.to_str()?;
+    if extension.len() < 3 || !extension.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    if extension.parse::<usize>().ok()? != 1 {
+        return None;
+    }
+    let joined = path.with_extension("");
+    joined.extension()?;
+    Some(joined)
+}
+
+/// Open the parts of a split file as a single stream, given the first part
+/// The parts are read until the next number is missing.
+pub fn open_parts(first: &Path) -> Result<CmprssRead, io::Error> {
+    let joined = joined_path(first).ok_or_else(|| {
+        io::Error::other(format!(
+            "{} is not the first part of a file",
+            first.display()
+        ))
+    })?;
+    let mut parts = Vec::new();
+    let mut size = 0;
+    loop {
+        let part = part_path(&joined, parts.len() + 1);
+        match fs::metadata(&part) {
+            Ok(metadata) => size += metadata.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !parts.is_empty() => break,
+            Err(e) => return Err(e),
+        }
+        parts.push(part);
+    }
+    crate::debug!("joining {} parts of {}", parts.len(), joined.display());
+    Ok(CmprssRead {
+        reader: Box::new(Parts {
+            parts: parts.into_iter(),
+            current: None,
+        }),
+        size: Some(size),
+        name: joined
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned()),
+    })
+}
+
+/// Reads the parts one after another, only opening each one when it's needed
+struct Parts {
+    parts: std::vec::IntoIter<PathBuf>,
+    current: Option<File>,
+}
+
+impl Read for Parts {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(file) = &mut self.current {
+                let read = file.read(buf)?;
+                if read > 0 || buf.is_empty() {
+                    return Ok(read);
+                }
+            }
+            match self.parts.next() {
+                Some(part) => self.current = Some(File::open(part)?),
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Start writing the output in parts of at most `size` bytes, named after the path
+///
+/// The parts are written under temporary names, and only moved into place after the job
+/// succeeds. Parts left over from an earlier, longer output are removed.
+pub fn write(path: &Path, size: u64) -> Result<(CmprssWrite, Upload), io::Error> {
+    let path = path.to_path_buf();
+    Upload::spawn(move |reader, success| {
+        let mut parts = Vec::new();
+        let mut reader = BufReader::new(reader);
+        let mut write_parts = || -> Result<(), io::Error> {
+            loop {
+                // Only the first part is written for an empty output
+                if !parts.is_empty() && reader.fill_buf()?.is_empty() {
+                    return Ok(());
+                }
+                let part = AtomicOutput::new(&part_path(&path, parts.len() + 1));
+                let mut file = File::create(part.tmp())?;
+                parts.push(part);
+                io::copy(&mut (&mut reader).take(size), &mut file)?;
+            }
+        };
+        let written = write_parts();
+        // Closing the pipe makes the job fail if we stopped reading early
+        drop(reader);
+        let succeeded = success.recv().unwrap_or(false) && written.is_ok();
+        let count = parts.len();
+        for part in parts {
+            match succeeded {
+                true => part.finish(Ok(()))?,
+                false => part
+                    .finish(cmprss_error("the job failed"))
+                    .unwrap_or_default(),
+            }
+        }
+        written?;
+        if succeeded {
+            let mut stale = part_path(&path, count + 1);
+            let mut number = count + 1;
+            while stale.is_file() {
+                fs::remove_file(&stale)?;
+                number += 1;
+                stale = part_path(&path, number);
+            }
+            crate::info!("wrote {} parts of {}", count, path.display());
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use std::io::Write;
+
+    #[test]
+    fn part_names() {
+        let path = Path::new("dir/archive.tar.zst");
+        assert_eq!(part_path(path, 1), Path::new("dir/archive.tar.zst.001"));
+        assert_eq!(part_path(path, 1234), Path::new("dir/archive.tar.zst.1234"));
+        assert_eq!(
+            joined_path(Path::new("dir/archive.tar.zst.001")),
+            Some(PathBuf::from("dir/archive.tar.zst"))
+        );
+        assert_eq!(joined_path(Path::new("archive.tar.zst.002")), None);
+        assert_eq!(joined_path(Path::new("archive.tar.zst.1")), None);
+        assert_eq!(joined_path(Path::new("archive.tar.zst")), None);
+        assert_eq!(joined_path(Path::new("archive.001")), None);
+    }
+
+    #[test]
+    fn roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let path = working_dir.child("data.gz");
+        // Left over from an earlier output with more parts
+        working_dir.child("data.gz.004").write_str("stale")?;
+        let data: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+
+        let (mut output, upload) = write(path.path(), 1000)?;
+        output.writer.write_all(&data)?;
+        drop(output);
+        upload.finish(true)?;
+        assert_eq!(fs::read(working_dir.child("data.gz.001"))?, &data[..1000]);
+        assert_eq!(fs::read(working_dir.child("data.gz.003"))?, &data[2000..]);
+        working_dir
+            .child("data.gz.004")
+            .assert(predicates::path::missing());
+
+        let mut joined = open_parts(&part_path(path.path(), 1))?;
+        assert_eq!(joined.size, Some(2500));
+        assert_eq!(joined.name.as_deref(), Some("data.gz"));
+        let mut read = Vec::new();
+        joined.reader.read_to_end(&mut read)?;
+        assert_eq!(read, data);
+
+        // A failed job leaves nothing behind
+        let failed = working_dir.child("failed.gz");
+        let (mut output, upload) = write(failed.path(), 1000)?;
+        output.writer.write_all(&data)?;
+        drop(output);
+        upload.finish(false)?;
+        assert_eq!(fs::read_dir(working_dir.path())?.count(), 3);
+
+        Ok(())
+    }
+}
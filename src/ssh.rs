@@ -0,0 +1,208 @@
+//! Streaming uploads to a remote host over SSH
+
+use crate::utils::*;
+use std::io;
+use std::process::{Command, Stdio};
+
+/// Check if an output string is an SSH URL, or an scp style [user@]host:path
+pub fn is_ssh_url(output: &str) -> bool {
+    output.starts_with("ssh://") || scp_target(output).is_some()
+}
+
+/// Remote location parsed from an SSH URL
+#[derive(Debug, PartialEq)]
+struct Target {
+    /// Host to connect to, optionally including the user
+    host: String,
+    port: Option<String>,
+    /// Path on the remote host
+    path: String,
+}
+
+/// Parse an scp style location, like user@host:/backups/archive.tar
+/// Without a user the path has to be absolute or start with ~/, so that local names with a colon,
+/// like notes:v2.txt, aren't taken for hosts. Relative paths are in the home directory.
+fn scp_target(output: &str) -> Option<Target> {
+    let (host, path) = output.split_once(':')?;
+    // Single letters are Windows drives, and a slash before the colon is a local path
+    // Names starting with '-' would be options of ssh, so they stay local too.
+    if host.len() < 2
+        || host.contains('/')
+        || host.starts_with('-')
+        || path.is_empty()
+        || path.starts_with("//")
+    {
+        return None;
+    }
+    if !host.contains('@') && !path.starts_with('/') && !path.starts_with("~/") {
+        return None;
+    }
+    Some(Target {
+        host: host.to_string(),
+        port: None,
+        path: path.to_string(),
+    })
+}
+
+/// Parse an SSH URL of the form ssh://[user@]host[:port]/path, or an scp style location
+/// Paths are absolute, use ssh://host/~/path for a path relative to the home directory.
+fn parse_url(url: &str) -> Result<Target, io::Error> {
+    if !url.starts_with("ssh://") {
+        if let Some(target) = scp_target(url) {
+            return Ok(target);
+        }
+    }
+    let rest = url.strip_prefix("ssh://").unwrap_or(url);
+    let (authority, path) = match rest.split_once('/') {
+        Some((authority, path)) if !authority.is_empty() && !path.is_empty() => (authority, path),
+        _ => {
+            return Err(io::Error::other(
+                "SSH URLs must include a host and a path, e.g. ssh://host/path/archive.tar",
+            ))
+        }
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host.to_string(), Some(port.to_string())),
+        None => (authority.to_string(), None),
+    };
+    if host.starts_with('-') {
+        return Err(io::Error::other(format!(
+            "invalid SSH host {}, hosts can't start with '-'",
+            host
+        )));
+    }
+    if port
+        .as_ref()
+        .is_some_and(|port| port.parse::<u16>().is_err())
+    {
+        return Err(io::Error::other(format!("invalid SSH port in {}", url)));
+    }
+    let path = if path.starts_with("~/") {
+        path.to_string()
+    } else {
+        format!("/{}", path)
+    };
+    Ok(Target { host, port, path })
+}
+
+/// Quote a path for the remote shell
+/// A leading ~/ is left unquoted so that the shell expands it
+fn quote(path: &str) -> String {
+    let (prefix, path) = match path.strip_prefix("~/") {
+        Some(path) => ("~/", path),
+        None => ("", path),
+    };
+    format!("{}'{}'", prefix, path.replace('\'', r"'\''"))
+}
+
+/// Build an ssh command that runs the given shell command on the target
+/// The ssh program can be overridden with the CMPRSS_SSH environment variable.
+fn ssh(target: &Target, command: &str) -> Command {
+    let program = std::env::var("CMPRSS_SSH").unwrap_or_else(|_| "ssh".to_string());
+    let mut ssh = Command::new(program);
+    if let Some(port) = &target.port {
+        ssh.arg("-p").arg(port);
+    }
+    // The host comes from the command line, so it can't be taken for an option
+    ssh.arg("--").arg(&target.host).arg(command);
+    ssh
+}
+
+/// Start streaming the output to a file on a remote host over SSH
+///
+/// The data is written to a temporary file next to the target, and only moved into place
+/// after the job succeeds.
+pub fn upload(url: &str) -> Result<(CmprssWrite, Upload), io::Error> {
+    let target = parse_url(url)?;
+    let partial = format!("{}.part", target.path);
+    let mut child = ssh(&target, &format!("cat > {}", quote(&partial)))
+        .stdin(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    Upload::spawn(move |mut reader, success| {
+        let copied = io::copy(&mut reader, &mut stdin);
+        // Closing the pipe makes the job fail if we stopped reading early
+        drop(reader);
+        drop(stdin);
+        let status = child.wait()?;
+        let succeeded = success.recv().unwrap_or(false) && copied.is_ok() && status.success();
+        let finish = if succeeded {
+            format!("mv -f {} {}", quote(&partial), quote(&target.path))
+        } else {
+            format!("rm -f {}", quote(&partial))
+        };
+        let finished = ssh(&target, &finish).status()?;
+        copied?;
+        if !status.success() {
+            return Err(io::Error::other(format!("ssh failed with {}", status)));
+        }
+        if succeeded && !finished.success() {
+            return Err(io::Error::other(format!(
+                "failed to move the output into place on {}",
+                target.host
+            )));
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_parsing() {
+        assert_eq!(
+            parse_url("ssh://user@host:2222/backups/archive.tar").unwrap(),
+            Target {
+                host: "user@host".to_string(),
+                port: Some("2222".to_string()),
+                path: "/backups/archive.tar".to_string(),
+            }
+        );
+        assert_eq!(
+            parse_url("ssh://host/~/archive.tar").unwrap(),
+            Target {
+                host: "host".to_string(),
+                port: None,
+                path: "~/archive.tar".to_string(),
+            }
+        );
+        assert!(parse_url("ssh://host").is_err());
+        assert!(parse_url("ssh://host/").is_err());
+        assert!(parse_url("ssh:///path").is_err());
+        assert!(parse_url("ssh://-oProxyCommand=touch%20x/path").is_err());
+        assert!(parse_url("ssh://host:-oX/path").is_err());
+    }
+
+    #[test]
+    fn scp_locations() {
+        assert_eq!(
+            parse_url("user@host:/backups/archive.tar").unwrap(),
+            Target {
+                host: "user@host".to_string(),
+                port: None,
+                path: "/backups/archive.tar".to_string(),
+            }
+        );
+        assert_eq!(
+            parse_url("user@host:archive.tar").unwrap().path,
+            "archive.tar"
+        );
+        assert_eq!(parse_url("host:~/archive.tar").unwrap().host, "host");
+        assert!(is_ssh_url("host:/archive.tar"));
+        assert!(!is_ssh_url("notes:v2.txt"));
+        assert!(!is_ssh_url("dir/notes:v2.txt"));
+        assert!(!is_ssh_url("C:/archive.tar"));
+        assert!(!is_ssh_url("user@host:"));
+        assert!(!is_ssh_url("-oProxyCommand=touch x:/tmp/archive.tar"));
+        assert!(!is_ssh_url("s3://bucket/archive.tar"));
+        assert!(!is_ssh_url("archive.tar"));
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote("/tmp/archive.tar"), "'/tmp/archive.tar'");
+        assert_eq!(quote("~/it's.tar"), r"~/'it'\''s.tar'");
+    }
+}
@@ -0,0 +1,262 @@
+//! Statistics summary of a finished job
+
+use crate::progress::{ArchiveProgress, ProgressHooks};
+use crate::utils::{CmprssInput, CmprssOutput};
+use indicatif::HumanBytes;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How the summary is printed
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum StatsFormat {
+    /// A line of text on stderr
+    Text,
+    /// A line of JSON, for scripts
+    Json,
+}
+
+/// Get the total size of a file or directory, or None if it can't be read
+fn path_size(path: &Path) -> Option<u64> {
+    let metadata = std::fs::symlink_metadata(path).ok()?;
+    if metadata.is_file() || metadata.is_symlink() {
+        return Some(metadata.len());
+    }
+    if !metadata.is_dir() {
+        // Special files, like named pipes, don't have a size
+        return None;
+    }
+    let mut total = 0;
+    for entry in std::fs::read_dir(path).ok()? {
+        total += path_size(&entry.ok()?.path())?;
+    }
+    Some(total)
+}
+
+/// Sum the sizes of all the inputs, or None if the size is unknown
+fn input_size(input: &CmprssInput) -> Option<u64> {
+    match input {
+        CmprssInput::Path(paths) => paths.iter().map(|p| path_size(p)).sum(),
+        CmprssInput::Pipe(_) => None,
+        CmprssInput::Reader(reader) => reader.size,
+    }
+}
+
+/// Collects the statistics of a single job
+pub struct Stats {
+    /// Name of the compressor used
+    name: String,
+    /// Whether the job is compressing, otherwise it is extracting
+    compressing: bool,
+    /// Size of the input, if known
+    input_size: Option<u64>,
+    /// Output path and its size before the job started
+    /// Extraction can target an existing directory, so only the difference is counted. A file
+    /// is replaced as a whole, so it counts from nothing.
+    output: Option<(PathBuf, u64)>,
+    /// Counts the bytes of the streams, whose size isn't known from a path
+    counters: ArchiveProgress,
+    start: Instant,
+}
+
+impl Stats {
+    /// Start tracking a job, must be called before the job runs
+    /// Streams in the input and output are wrapped to count the bytes going through them.
+    pub fn start(
+        name: &str,
+        compressing: bool,
+        input: &mut CmprssInput,
+        output: &mut CmprssOutput,
+    ) -> Self {
+        let counters = ArchiveProgress::new(&ProgressHooks::default(), None);
+        match input {
+            CmprssInput::Path(_) => {}
+            CmprssInput::Pipe(pipe) => {
+                let inner = std::mem::replace(pipe, Box::new(io::empty()));
+                *pipe = Box::new(counters.reader(inner));
+            }
+            CmprssInput::Reader(reader) => {
+                let inner = std::mem::replace(&mut reader.reader, Box::new(io::empty()));
+                reader.reader = Box::new(counters.reader(inner));
+            }
+        }
+        let output = match output {
+            CmprssOutput::Path(path) => {
+                let before = match path.is_dir() {
+                    true => path_size(path).unwrap_or(0),
+                    false => 0,
+                };
+                Some((path.clone(), before))
+            }
+            CmprssOutput::Pipe(pipe) => {
+                let inner = std::mem::replace(pipe, Box::new(io::sink()));
+                *pipe = Box::new(counters.writer(inner));
+                None
+            }
+            CmprssOutput::Writer(writer) => {
+                let inner = std::mem::replace(&mut writer.writer, Box::new(io::sink()));
+                writer.writer = Box::new(counters.writer(inner));
+                None
+            }
+        };
+        Stats {
+            name: name.to_string(),
+            compressing,
+            input_size: input_size(input),
+            output,
+            counters,
+            start: Instant::now(),
+        }
+    }
+
+    /// Finish tracking the job and generate the summary
+    pub fn finish(self) -> Summary {
+        let (read, written) = self.counters.totals();
+        let input_size = self.input_size.or((read > 0).then_some(read));
+        let output_size = match self.output {
+            Some((path, before)) => path_size(&path).map(|after| after.saturating_sub(before)),
+            None => Some(written),
+        };
+        let (original, compressed) = if self.compressing {
+            (input_size, output_size)
+        } else {
+            (output_size, input_size)
+        };
+        Summary {
+            name: self.name,
+            compressing: self.compressing,
+            original,
+            compressed,
+            elapsed: self.start.elapsed(),
+        }
+    }
+}
+
+/// Final statistics for a job
+#[derive(Debug)]
+pub struct Summary {
+    name: String,
+    compressing: bool,
+    /// Uncompressed size in bytes
+    original: Option<u64>,
+    /// Compressed size in bytes
+    compressed: Option<u64>,
+    elapsed: Duration,
+}
+
+impl Summary {
+    /// Uncompressed bytes per second
+    fn throughput(&self) -> Option<f64> {
+        let seconds = self.elapsed.as_secs_f64();
+        self.original
+            .filter(|_| seconds > 0.0)
+            .map(|original| original as f64 / seconds)
+    }
+
+    /// The summary as a line of JSON, with null for the sizes that aren't known
+    /// Throughput is in decimal megabytes per second, of the uncompressed side like the text.
+    pub fn json(&self) -> String {
+        let (input, output) = match self.compressing {
+            true => (self.original, self.compressed),
+            false => (self.compressed, self.original),
+        };
+        let value = |v: Option<String>| v.unwrap_or_else(|| "null".to_string());
+        let ratio = match (self.original, self.compressed) {
+            (Some(original), Some(compressed)) if original > 0 => {
+                Some(format!("{:.4}", compressed as f64 / original as f64))
+            }
+            _ => None,
+        };
+        format!(
+            r#"{{"format":"{}","action":"{}","input_bytes":{},"output_bytes":{},"ratio":{},"seconds":{:.3},"mb_per_sec":{}}}"#,
+            self.name,
+            if self.compressing {
+                "compress"
+            } else {
+                "extract"
+            },
+            value(input.map(|v| v.to_string())),
+            value(output.map(|v| v.to_string())),
+            value(ratio),
+            self.elapsed.as_secs_f64(),
+            value(self.throughput().map(|v| format!("{:.2}", v / 1e6))),
+        )
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let size = |s: Option<u64>| match s {
+            Some(s) => HumanBytes(s).to_string(),
+            None => "?".to_string(),
+        };
+        write!(
+            f,
+            "{}: {} => {}",
+            self.name,
+            size(self.original),
+            size(self.compressed)
+        )?;
+        if let (Some(original), Some(compressed)) = (self.original, self.compressed) {
+            if original > 0 {
+                write!(f, " ({:.2}%)", compressed as f64 * 100.0 / original as f64)?;
+            }
+        }
+        write!(f, " in {:.2}s", self.elapsed.as_secs_f64())?;
+        // Throughput is measured on the uncompressed side for both directions
+        if let Some(throughput) = self.throughput() {
+            write!(f, " ({}/s)", HumanBytes(throughput as u64))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_display() {
+        let summary = Summary {
+            name: "gzip".to_string(),
+            compressing: true,
+            original: Some(4 * 1024 * 1024),
+            compressed: Some(1024 * 1024),
+            elapsed: Duration::from_secs(2),
+        };
+        assert_eq!(
+            summary.to_string(),
+            "gzip: 4.00 MiB => 1.00 MiB (25.00%) in 2.00s (2.00 MiB/s)"
+        );
+
+        let summary = Summary {
+            name: "xz".to_string(),
+            compressing: false,
+            original: None,
+            compressed: Some(1024),
+            elapsed: Duration::from_millis(500),
+        };
+        assert_eq!(summary.to_string(), "xz: ? => 1.00 KiB in 0.50s");
+        assert_eq!(
+            summary.json(),
+            r#"{"format":"xz","action":"extract","input_bytes":1024,"output_bytes":null,"ratio":null,"seconds":0.500,"mb_per_sec":null}"#
+        );
+    }
+
+    #[test]
+    fn stream_counters() -> Result<(), io::Error> {
+        use std::io::Write;
+        let mut input = CmprssInput::Pipe(Box::new(&[1u8; 100][..]));
+        let mut output = CmprssOutput::Pipe(Box::new(io::sink()));
+        let stats = Stats::start("gzip", true, &mut input, &mut output);
+        let (mut reader, _) = input.into_stream()?;
+        let mut writer = output.into_stream()?;
+        io::copy(&mut reader, &mut writer)?;
+        writer.write_all(b"trailer")?;
+        let summary = stats.finish();
+        assert_eq!(summary.original, Some(100));
+        assert_eq!(summary.compressed, Some(107));
+        Ok(())
+    }
+}
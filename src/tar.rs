@@ -1,25 +1,114 @@
+//! tar archive backend
+
 extern crate tar;
 
-use clap::Args;
-use std::fs::File;
-use std::io::{self, Read, Write};
-use std::path::Path;
-use tar::{Archive, Builder};
+use clap::{Args, ValueEnum};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tar::{Archive, Builder, Header, HeaderMode};
 
+use crate::exclude::Exclude;
+use crate::limits::OutputLimits;
+use crate::owner::{self, OwnerArgs, Ownership};
+use crate::progress::{
+    progress_bar, ArchiveProgress, ProgressArgs, ProgressDisplay, ProgressHooks, ProgressObserver,
+};
+use crate::transform::{RenamedFiles, Transform};
 use crate::utils::*;
 
 #[derive(Args, Debug)]
 pub struct TarArgs {
     #[clap(flatten)]
     pub common_args: CommonArgs,
+
+    #[clap(flatten)]
+    pub progress_args: ProgressArgs,
+
+    #[clap(flatten)]
+    pub owner_args: OwnerArgs,
+
+    /// Format of the headers of new archives.
+    /// pax stores long paths, large files and precise times in extended headers that other
+    /// tools read, gnu uses the GNU extensions, and ustar fails on anything that doesn't fit.
+    #[arg(long, value_enum, default_value = "pax")]
+    pub format: TarFormat,
 }
 
-#[derive(Default)]
-pub struct Tar {}
+/// The formats of tar headers
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+pub enum TarFormat {
+    Ustar,
+    Gnu,
+    #[default]
+    Pax,
+}
+
+/// Archived files with more than one link, by device and inode, with the name of their entry
+type HardLinks = HashMap<(u64, u64), PathBuf>;
+
+/// The device and inode of a file that has other hard links to it
+#[cfg(unix)]
+fn link_key(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.is_file() && metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+/// Hard links can't be detected through the stable API on other platforms
+#[cfg(not(unix))]
+fn link_key(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[derive(Default, Clone)]
+pub struct Tar {
+    /// Extract entries with absolute paths or '..' outside of the output directory
+    pub allow_unsafe_paths: bool,
+    /// Leading directories to remove from the paths of extracted entries
+    pub strip_components: usize,
+    /// Substitutions renaming the entries when archiving and extracting
+    pub transform: Transform,
+    /// Archive the inputs as they are listed, named by their paths and without the contents
+    /// of directories
+    pub from_list: bool,
+    /// Directory of -C that the listed inputs are relative to
+    pub directory: Option<PathBuf>,
+    /// How symbolic links are archived and extracted
+    pub symlinks: Symlinks,
+    /// Files to leave out when archiving directories, and entries to skip when extracting
+    pub exclude: Exclude,
+    /// Leave out the metadata that differs between runs
+    pub reproducible: Option<Reproducible>,
+    /// Modification time to store instead of the times of the files
+    pub mtime: Option<MtimeOverride>,
+    /// Format of the headers of new archives
+    pub format: TarFormat,
+    /// Owners to store instead of the real ones, and to restore when extracting as root
+    pub ownership: Ownership,
+    pub progress_args: ProgressArgs,
+    /// Progress observer, cancellation, and limits on the total size of the extracted files
+    pub hooks: ProgressHooks,
+}
 
 impl Tar {
-    pub fn new(_args: &TarArgs) -> Tar {
-        Tar {}
+    pub fn new(args: &TarArgs) -> Tar {
+        Tar {
+            allow_unsafe_paths: args.common_args.allow_unsafe_paths,
+            strip_components: args.common_args.strip_components,
+            transform: Transform::default(),
+            from_list: false,
+            directory: None,
+            symlinks: Symlinks::new(&args.common_args),
+            exclude: Exclude::default(),
+            reproducible: None,
+            mtime: None,
+            format: args.format,
+            ownership: Ownership::new(&args.owner_args),
+            progress_args: args.progress_args,
+            hooks: ProgressHooks::default(),
+        }
     }
 }
 
@@ -34,25 +123,144 @@ impl Compressor for Tar {
         ".".to_string()
     }
 
+    fn set_allow_unsafe_paths(&mut self, allow: bool) {
+        self.allow_unsafe_paths = allow;
+    }
+
+    fn set_strip_components(&mut self, count: usize) {
+        self.strip_components = count;
+    }
+
+    fn set_from_list(&mut self, from_list: bool) {
+        self.from_list = from_list;
+    }
+
+    fn set_directory(&mut self, directory: PathBuf) {
+        self.directory = Some(directory);
+    }
+
+    fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+    }
+
+    fn set_symlinks(&mut self, symlinks: Symlinks) {
+        self.symlinks = symlinks;
+    }
+
+    fn set_limits(&mut self, limits: OutputLimits) {
+        self.hooks.limits = limits;
+    }
+
+    fn set_observer(&mut self, observer: Arc<dyn ProgressObserver>) {
+        self.hooks.observer = Some(observer);
+    }
+
+    fn set_progress(&mut self, progress: ProgressDisplay) {
+        self.progress_args.progress = progress;
+    }
+
+    fn progress(&self) -> ProgressDisplay {
+        self.progress_args.progress
+    }
+
+    fn set_exclude(&mut self, exclude: Exclude) {
+        self.exclude = exclude;
+    }
+
+    fn set_reproducible(&mut self, reproducible: Reproducible) {
+        self.reproducible = Some(reproducible);
+    }
+
+    fn set_mtime(&mut self, mtime: MtimeOverride) {
+        self.mtime = Some(mtime);
+    }
+
     fn compress(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        let input_size = match &input {
+            CmprssInput::Path(paths) => Some(self.input_size(paths)),
+            _ => None,
+        };
+        let bar = progress_bar(
+            input_size,
+            self.progress_args.progress,
+            &output,
+            &self.hooks,
+        );
+        let progress = ArchiveProgress::new(&self.hooks, bar);
         match output {
-            CmprssOutput::Pipe(pipe) => self.compress_internal(input, Builder::new(pipe)),
-            CmprssOutput::Path(path) => {
-                self.compress_internal(input, Builder::new(File::create(path)?))
+            CmprssOutput::Pipe(pipe) => {
+                self.compress_internal(input, Builder::new(progress.writer(pipe)), &progress)
             }
-        }
+            CmprssOutput::Path(path) => self.compress_internal(
+                input,
+                Builder::new(progress.writer(File::create(path)?)),
+                &progress,
+            ),
+            CmprssOutput::Writer(writer) => self.compress_internal(
+                input,
+                Builder::new(progress.writer(writer.writer)),
+                &progress,
+            ),
+        }?;
+        progress.finish();
+        Ok(())
     }
 
     fn extract(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
-        match input {
+        let (reader, input_size): (Box<dyn Read>, _) = match input {
             CmprssInput::Path(paths) => {
                 if paths.len() > 1 {
                     return cmprss_error("only 1 archive can be extracted at a time");
                 }
-                self.extract_internal(Archive::new(File::open(paths[0].as_path())?), output)
+                let file = File::open(paths[0].as_path())?;
+                let size = file.metadata()?.len();
+                (Box::new(file), Some(size))
             }
-            CmprssInput::Pipe(pipe) => self.extract_internal(Archive::new(pipe), output),
+            CmprssInput::Pipe(pipe) => (Box::new(pipe), None),
+            CmprssInput::Reader(reader) => (reader.reader, reader.size),
+        };
+        let bar = progress_bar(
+            input_size,
+            self.progress_args.progress,
+            &output,
+            &self.hooks,
+        );
+        let progress = ArchiveProgress::new(&self.hooks, bar);
+        let archive = Archive::new(progress.reader(reader));
+        self.extract_internal(archive, input_size, output, &progress)?;
+        progress.finish();
+        Ok(())
+    }
+
+    fn list(&self, input: CmprssInput) -> Result<Vec<ArchiveEntry>, io::Error> {
+        let (reader, _) = input.into_stream()?;
+        self.list_internal(Archive::new(reader))
+    }
+
+    fn cat(&self, input: CmprssInput, entry: &str, output: CmprssOutput) -> Result<(), io::Error> {
+        let (reader, _) = input.into_stream()?;
+        let mut archive = Archive::new(reader);
+        let mut found = false;
+        for file in archive.entries()? {
+            let mut file = file?;
+            if !entry_matches(&file.path()?.to_string_lossy(), entry) {
+                continue;
+            }
+            if file.header().entry_type().is_dir() {
+                return Err(io::Error::other(format!("{} is a directory", entry)));
+            }
+            let mut output = output.into_stream()?;
+            io::copy(&mut file, &mut output)?;
+            output.flush()?;
+            found = true;
+            break;
         }
+        // Consume the rest of the archive, so a compressor feeding a pipe can finish
+        io::copy(&mut archive.into_inner(), &mut io::sink())?;
+        if !found {
+            return Err(io::Error::other(format!("{} is not in the archive", entry)));
+        }
+        Ok(())
     }
 }
 
@@ -61,18 +269,266 @@ impl Tar {
     fn extract_internal<R: Read>(
         &self,
         mut archive: Archive<R>,
+        input_size: Option<u64>,
         output: CmprssOutput,
+        progress: &ArchiveProgress,
     ) -> Result<(), io::Error> {
         let out_path = match output {
-            CmprssOutput::Pipe(_) => {
-                return cmprss_error("error: tar does not support stdout as extract output")
-            }
             CmprssOutput::Path(path) => path,
+            output => return self.extract_single(archive, input_size, output),
         };
         if !out_path.is_dir() {
             return cmprss_error("error: tar can only extract to a directory");
         }
-        archive.unpack(out_path)
+        let out_path = long_path(&out_path)?;
+        // Directories are unpacked last, so that extracting their contents doesn't change them
+        let mut directories = Vec::new();
+        let mut written = 0u64;
+        for entry in archive.entries()? {
+            let entry = entry?;
+            // Entry names always use '/', the tar crate converts them for the platform
+            let path = entry.path()?;
+            if !self.exclude.is_extracted(&path) {
+                crate::info!("skipping {}, it is excluded", path.display());
+                continue;
+            }
+            let Some(name) = self.extracted_name(&path, entry.header().entry_type().is_dir())
+            else {
+                crate::info!(
+                    "skipping {}, it is removed by --strip-components, --transform or --junk-paths",
+                    path.display()
+                );
+                continue;
+            };
+            if entry.header().entry_type().is_symlink() {
+                if self.symlinks == Symlinks::Skip {
+                    crate::info!("skipping {}, it is a symbolic link", path.display());
+                    continue;
+                }
+                let link = entry.link_name()?.unwrap_or_default();
+                if !self.allow_unsafe_paths && symlink_escapes(&name, &link) {
+                    crate::warn!(
+                        "skipping {}, it links to {} outside of the output, use --allow-unsafe-paths to extract it",
+                        path.display(),
+                        link.display()
+                    );
+                    continue;
+                }
+            }
+            crate::debug!("extracting {}", path.display());
+            let target = entry_path(&out_path, &name, self.allow_unsafe_paths)?;
+            written = written.saturating_add(entry.size());
+            self.hooks
+                .limits
+                .check(input_size.unwrap_or(u64::MAX), written)?;
+            if entry.header().entry_type().is_dir() {
+                directories.push((entry, target));
+            } else {
+                let size = entry.size();
+                self.unpack_entry(entry, &out_path, &target)?;
+                progress.add_output(size);
+            }
+        }
+        for (entry, target) in directories.into_iter().rev() {
+            self.unpack_entry(entry, &out_path, &target)?;
+        }
+        Ok(())
+    }
+
+    /// Write the only file of the archive to a stream, like stdout
+    /// The archive is read as it streams, so a second file is only found after the first was written.
+    fn extract_single<R: Read>(
+        &self,
+        mut archive: Archive<R>,
+        input_size: Option<u64>,
+        output: CmprssOutput,
+    ) -> Result<(), io::Error> {
+        let mut output = self.hooks.limits.writer(output.into_stream()?, input_size);
+        let mut extracted: Option<PathBuf> = None;
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            if !entry.header().entry_type().is_file() || !self.exclude.is_extracted(&path) {
+                continue;
+            }
+            if let Some(first) = &extracted {
+                return Err(io::Error::other(format!(
+                    "tar can only extract a single file to stdout, but the archive has {} and {}, use --to-stdout ENTRY to choose one",
+                    first.display(),
+                    path.display()
+                )));
+            }
+            crate::debug!("extracting {} to stdout", path.display());
+            io::copy(&mut entry, &mut output)?;
+            extracted = Some(path);
+        }
+        if extracted.is_none() {
+            return cmprss_error("the archive has no files to extract to stdout");
+        }
+        output.flush()
+    }
+
+    /// Unpack one entry of the archive, giving it its owner when running as root
+    fn unpack_entry<R: Read>(
+        &self,
+        entry: tar::Entry<R>,
+        out_path: &Path,
+        target: &Path,
+    ) -> Result<(), io::Error> {
+        let owner = self.ownership.extracted(entry.header());
+        self.unpack_contents(entry, out_path, target)?;
+        if fs::symlink_metadata(target).is_ok() {
+            owner::restore(target, owner)?;
+        }
+        Ok(())
+    }
+
+    /// Unpack the contents of one entry of the archive
+    /// Safe paths are unpacked by the tar crate, which also refuses to follow symlinks out of the output.
+    fn unpack_contents<R: Read>(
+        &self,
+        mut entry: tar::Entry<R>,
+        out_path: &Path,
+        target: &Path,
+    ) -> Result<(), io::Error> {
+        if !self.allow_unsafe_paths && self.strip_components == 0 && self.transform.is_empty() {
+            entry.unpack_in(out_path)?;
+            return Ok(());
+        }
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+            // The tar crate only checks the original path, so check for symlinks out of the output
+            if !self.allow_unsafe_paths
+                && !parent.canonicalize()?.starts_with(out_path.canonicalize()?)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "entry would be extracted outside of the output through a symlink: {}",
+                        target.display()
+                    ),
+                ));
+            }
+        }
+        // The tar crate only resolves hard links inside the output with unpack_in
+        if entry.header().entry_type().is_hard_link() {
+            let link = entry.link_name()?.unwrap_or_default();
+            let Some(source) = self.extracted_name(&link, false) else {
+                return Err(io::Error::other(format!(
+                    "{} links to {}, which is removed by --strip-components, --transform or --junk-paths",
+                    target.display(),
+                    link.display()
+                )));
+            };
+            let source = entry_path(out_path, &source, self.allow_unsafe_paths)?;
+            if fs::symlink_metadata(target).is_ok() {
+                fs::remove_file(target)?;
+            }
+            return fs::hard_link(source, target);
+        }
+        entry.unpack(target)?;
+        Ok(())
+    }
+
+    /// Path of an entry inside the output, without the stripped directories and renamed
+    fn extracted_name(&self, path: &Path, is_dir: bool) -> Option<PathBuf> {
+        self.transform
+            .apply(&strip_components(path, self.strip_components)?, is_dir)
+    }
+
+    /// Internal list helper
+    fn list_internal<R: Read>(
+        &self,
+        mut archive: Archive<R>,
+    ) -> Result<Vec<ArchiveEntry>, io::Error> {
+        let mut entries = Vec::new();
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let header = entry.header();
+            entries.push(ArchiveEntry {
+                path: entry.path()?.to_string_lossy().into_owned(),
+                is_dir: header.entry_type().is_dir(),
+                size: header.size()?,
+                compressed_size: None,
+                mtime: header.mtime().ok().map(|mtime| mtime as i64),
+                mode: header.mode().ok(),
+                comment: None,
+            });
+        }
+        // Consume the padding after the last entry, so a compressor feeding a pipe can finish
+        io::copy(&mut archive.into_inner(), &mut io::sink())?;
+        Ok(entries)
+    }
+
+    /// Total size of the files that will be archived, for the progress bar
+    /// Files that can't be read are left out, archiving them reports the error.
+    pub fn input_size(&self, paths: &[PathBuf]) -> u64 {
+        self.file_sizes(paths).iter().map(|(size, _)| size).sum()
+    }
+
+    /// Size of the archive of the files, for the progress bar of a compressed archive
+    /// Every entry has its headers and is padded to a multiple of 512 bytes, and the
+    /// archive ends with two empty blocks. Long names add headers that aren't counted.
+    pub fn archive_size(&self, paths: &[PathBuf]) -> u64 {
+        let entries: u64 = self
+            .file_sizes(paths)
+            .iter()
+            .map(|(size, headers)| headers + size.div_ceil(512) * 512)
+            .sum();
+        entries + 1024
+    }
+
+    /// Sizes of the entries that will be archived, 0 for directories, with the size of their headers
+    fn file_sizes(&self, paths: &[PathBuf]) -> Vec<(u64, u64)> {
+        let mut sizes = Vec::new();
+        let mut linked = HashSet::new();
+        for path in paths {
+            let Ok(name) = input_entry_name(path, self.from_list, self.directory.as_deref()) else {
+                continue;
+            };
+            if !self.exclude.is_excluded(&name) {
+                self.walk_sizes(path, &name, &mut sizes, &mut linked);
+            }
+        }
+        sizes
+    }
+
+    fn walk_sizes(
+        &self,
+        path: &Path,
+        name: &Path,
+        sizes: &mut Vec<(u64, u64)>,
+        linked: &mut HashSet<(u64, u64)>,
+    ) {
+        let Ok(metadata) = self.symlinks.metadata(path) else {
+            return;
+        };
+        if metadata.is_symlink() && self.symlinks == Symlinks::Skip {
+            return;
+        }
+        if !metadata.is_dir() {
+            // Later links to the same file are stored without its contents
+            let first = link_key(&metadata).is_none_or(|key| linked.insert(key));
+            let size = match metadata.is_file() && first {
+                true => metadata.len(),
+                false => 0,
+            };
+            sizes.push((size, self.header_size(&metadata)));
+            return;
+        }
+        sizes.push((0, self.header_size(&metadata)));
+        if self.from_list {
+            return;
+        }
+        let Ok(children) = fs::read_dir(path) else {
+            return;
+        };
+        for child in children.flatten() {
+            let child_name = name.join(child.file_name());
+            if !self.exclude.is_excluded_file(&child_name, &child.path()) {
+                self.walk_sizes(&child.path(), &child_name, sizes, linked);
+            }
+        }
     }
 
     /// Internal compress helper
@@ -80,21 +536,53 @@ impl Tar {
         &self,
         input: CmprssInput,
         mut archive: Builder<W>,
+        progress: &ArchiveProgress,
     ) -> Result<(), io::Error> {
         let input_files = match input {
             CmprssInput::Path(paths) => paths,
-            CmprssInput::Pipe(_) => {
-                return cmprss_error("error: tar does not support stdin as input")
+            input => {
+                // A stream is stored as a single file, named like the output of other formats
+                let name = PathBuf::from(input.stream_name().unwrap_or("archive"));
+                let (stream, _) = input.into_stream()?;
+                self.append_stream(&mut archive, &name, stream, progress)?;
+                return archive.finish();
             }
         };
+        let mut links = HardLinks::new();
+        let mut renamed = RenamedFiles::default();
         for in_file in input_files {
-            if in_file.is_file() {
-                archive.append_file(
-                    in_file.file_name().unwrap(),
-                    &mut File::open(in_file.as_path())?,
+            let name = input_entry_name(&in_file, self.from_list, self.directory.as_deref())?;
+            let name = name.as_path();
+            if self.exclude.is_excluded(name) {
+                crate::info!("skipping {}, it is excluded", in_file.display());
+                continue;
+            }
+            let metadata = self.symlinks.metadata(&in_file)?;
+            if metadata.is_symlink() && self.symlinks == Symlinks::Skip {
+                crate::info!("skipping {}, it is a symbolic link", in_file.display());
+                continue;
+            }
+            // Listed directories are stored without their contents
+            if metadata.is_file() || metadata.is_symlink() || (metadata.is_dir() && self.from_list)
+            {
+                self.append_entry(
+                    &mut archive,
+                    &in_file,
+                    name,
+                    &mut links,
+                    &mut renamed,
+                    progress,
+                )?;
+            } else if metadata.is_dir() {
+                let path = long_path(&in_file)?;
+                self.append_dir(
+                    &mut archive,
+                    &path,
+                    name,
+                    &mut links,
+                    &mut renamed,
+                    progress,
                 )?;
-            } else if in_file.is_dir() {
-                archive.append_dir_all(in_file.file_name().unwrap(), in_file.as_path())?;
             } else {
                 return Err(io::Error::new(
                     io::ErrorKind::InvalidInput,
@@ -104,9 +592,291 @@ impl Tar {
         }
         archive.finish()
     }
+
+    /// Add a directory tree to the archive, leaving out the excluded files
+    fn append_dir<W: Write>(
+        &self,
+        archive: &mut Builder<W>,
+        path: &Path,
+        name: &Path,
+        links: &mut HardLinks,
+        renamed: &mut RenamedFiles,
+        progress: &ArchiveProgress,
+    ) -> Result<(), io::Error> {
+        self.append_entry(archive, path, name, links, renamed, progress)?;
+        let mut children = fs::read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+        children.sort_by_key(|child| child.file_name());
+        for child in children {
+            let child_name = name.join(child.file_name());
+            if self.exclude.is_excluded_file(&child_name, &child.path()) {
+                crate::info!("skipping {}, it is excluded", child.path().display());
+                continue;
+            }
+            let child_path = child.path();
+            let metadata = self.symlinks.metadata(&child_path)?;
+            if metadata.is_symlink() && self.symlinks == Symlinks::Skip {
+                crate::info!("skipping {}, it is a symbolic link", child_path.display());
+                continue;
+            }
+            if metadata.is_dir() {
+                self.append_dir(archive, &child_path, &child_name, links, renamed, progress)?;
+            } else {
+                self.append_entry(archive, &child_path, &child_name, links, renamed, progress)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a single file or directory to the archive, without the contents of a directory
+    /// The contents of files are read through the progress, which counts them as the input.
+    /// Files that were already archived through another hard link are stored as links to it.
+    fn append_entry<W: Write>(
+        &self,
+        archive: &mut Builder<W>,
+        path: &Path,
+        name: &Path,
+        links: &mut HardLinks,
+        renamed: &mut RenamedFiles,
+        progress: &ArchiveProgress,
+    ) -> Result<(), io::Error> {
+        let metadata = self.symlinks.metadata(path)?;
+        let Some(name) = self
+            .transform
+            .apply_to_file(path, name, metadata.is_dir(), renamed)?
+        else {
+            crate::debug!(
+                "skipping {}, it is removed by --transform or --junk-paths",
+                path.display()
+            );
+            return Ok(());
+        };
+        let name = name.as_path();
+        crate::debug!("adding {}", name.display());
+        let mut header = self.header(&metadata)?;
+        if metadata.is_symlink() {
+            header.set_size(0);
+            let target = fs::read_link(path)?;
+            return self.append(archive, header, &metadata, name, Some(&target), io::empty());
+        }
+        if let Some(key) = link_key(&metadata) {
+            if let Some(first) = links.get(&key) {
+                header.set_entry_type(tar::EntryType::Link);
+                header.set_size(0);
+                return self.append(archive, header, &metadata, name, Some(first), io::empty());
+            }
+            links.insert(key, name.to_path_buf());
+        }
+        if metadata.is_file() {
+            let reader = progress.reader(File::open(path)?);
+            self.append(archive, header, &metadata, name, None, reader)
+        } else {
+            self.append(archive, header, &metadata, name, None, io::empty())
+        }
+    }
+
+    /// Add a stream as a single file
+    /// Headers need the size before the contents, so the stream is written to a temporary file
+    /// first, which also gives the entry its permissions and time.
+    fn append_stream<W: Write>(
+        &self,
+        archive: &mut Builder<W>,
+        name: &Path,
+        stream: Box<dyn Read + Send>,
+        progress: &ArchiveProgress,
+    ) -> Result<(), io::Error> {
+        let Some(name) = self.transform.apply(name, false) else {
+            return Err(io::Error::other(format!(
+                "{} is removed by --transform",
+                name.display()
+            )));
+        };
+        let tmp =
+            std::env::temp_dir().join(format!(".cmprss-{}.tar.cmprss-tmp", std::process::id()));
+        crate::signal::register(&tmp);
+        let append = || -> Result<(), io::Error> {
+            let mut file = fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&tmp)?;
+            io::copy(&mut progress.reader(stream), &mut file)?;
+            file.rewind()?;
+            let metadata = file.metadata()?;
+            let header = self.header(&metadata)?;
+            crate::debug!("adding {}", name.display());
+            self.append(archive, header, &metadata, &name, None, file)
+        };
+        let result = append();
+        let _ = fs::remove_file(&tmp);
+        crate::signal::unregister(&tmp);
+        result
+    }
+
+    /// Size of the headers of an entry, with the pax extended header for a precise time
+    fn header_size(&self, metadata: &fs::Metadata) -> u64 {
+        let extended = self.format == TarFormat::Pax && self.precise_mtime(metadata).is_some();
+        if extended {
+            1536
+        } else {
+            512
+        }
+    }
+
+    /// The time of a file with fractions of a second, unless the stored time is changed
+    fn precise_mtime(&self, metadata: &fs::Metadata) -> Option<String> {
+        let replaced = self
+            .mtime
+            .is_some_and(|mtime| mtime.replaces(modified_secs(metadata)));
+        if self.reproducible.is_some() || replaced {
+            return None;
+        }
+        precise_mtime(metadata)
+    }
+
+    /// Header of an entry in the format of the archive, filled in from the metadata
+    fn header(&self, metadata: &fs::Metadata) -> Result<Header, io::Error> {
+        let mut header = match self.format {
+            TarFormat::Gnu => Header::new_gnu(),
+            TarFormat::Ustar | TarFormat::Pax => Header::new_ustar(),
+        };
+        match self.reproducible {
+            Some(reproducible) => {
+                // The deterministic mode uses a fixed time, clamp the real one instead
+                header.set_metadata_in_mode(metadata, HeaderMode::Deterministic);
+                header.set_mtime(reproducible.clamp(modified_secs(metadata)));
+            }
+            None => header.set_metadata(metadata),
+        }
+        if let Some(mtime) = self.mtime {
+            header.set_mtime(mtime.apply(modified_secs(metadata)));
+        }
+        self.ownership
+            .apply(&mut header, self.reproducible.is_none())?;
+        Ok(header)
+    }
+
+    /// Write an entry with its name, and the target of a link
+    /// GNU archives store long names in extra entries, pax archives store what doesn't fit the
+    /// header in an extended header before it, and ustar archives can't store them at all.
+    fn append<W: Write, R: Read>(
+        &self,
+        archive: &mut Builder<W>,
+        mut header: Header,
+        metadata: &fs::Metadata,
+        name: &Path,
+        link: Option<&Path>,
+        data: R,
+    ) -> Result<(), io::Error> {
+        if self.format == TarFormat::Gnu {
+            return match link {
+                Some(link) => archive.append_link(&mut header, name, link),
+                None => archive.append_data(&mut header, name, data),
+            };
+        }
+        let mut records = Vec::new();
+        if header.set_path(name).is_err() {
+            records.push(("path", path_bytes(name)?));
+            header.set_path(truncated(name, 100))?;
+        }
+        if let Some(link) = link {
+            if header.set_link_name(link).is_err() {
+                records.push(("linkpath", path_bytes(link)?));
+                header.set_link_name(truncated(link, 100))?;
+            }
+        }
+        if header.size()? > USTAR_MAX {
+            records.push(("size", header.size()?.to_string().into_bytes()));
+        }
+        for (key, id) in [("uid", header.uid()?), ("gid", header.gid()?)] {
+            if id > USTAR_MAX_ID {
+                records.push((key, id.to_string().into_bytes()));
+            }
+        }
+        if let Some(mtime) = self.precise_mtime(metadata) {
+            records.push(("mtime", mtime.into_bytes()));
+        }
+        if self.format == TarFormat::Ustar {
+            // The times are only kept to the second, which needs no extension
+            if let Some((key, _)) = records.iter().find(|(key, _)| *key != "mtime") {
+                return Err(io::Error::other(format!(
+                    "the {} of {} doesn't fit in a ustar archive, use --format pax",
+                    key,
+                    name.display()
+                )));
+            }
+        } else if !records.is_empty() {
+            let extensions = pax_records(&records);
+            let mut pax = Header::new_ustar();
+            pax.set_path(Path::new("PaxHeaders").join(truncated(name, 88)))?;
+            pax.set_entry_type(tar::EntryType::XHeader);
+            pax.set_size(extensions.len() as u64);
+            pax.set_mode(0o644);
+            pax.set_mtime(header.mtime()?);
+            pax.set_cksum();
+            archive.append(&pax, &extensions[..])?;
+        }
+        header.set_cksum();
+        archive.append(&header, data)
+    }
+}
+
+/// Largest size and time that fit in the octal fields of a ustar header
+const USTAR_MAX: u64 = 0o77777777777;
+
+/// Largest user and group ID that fit in the octal fields of a ustar header
+const USTAR_MAX_ID: u64 = 0o7777777;
+
+/// The bytes of a path, with '/' separators on every platform
+fn path_bytes(path: &Path) -> Result<Vec<u8>, io::Error> {
+    let path = path
+        .to_str()
+        .ok_or_else(|| io::Error::other(format!("{} is not valid UTF-8", path.display())))?;
+    Ok(path.replace('\\', "/").into_bytes())
+}
+
+/// The end of a path that fits in a header field, for readers that don't know the extension
+fn truncated(path: &Path, max: usize) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut start = name.len().saturating_sub(max);
+    while !name.is_char_boundary(start) {
+        start += 1;
+    }
+    PathBuf::from(&name[start..])
+}
+
+/// Modification time with the fraction of a second, if it has one
+fn precise_mtime(metadata: &fs::Metadata) -> Option<String> {
+    let time = metadata.modified().ok()?;
+    let duration = time.duration_since(std::time::UNIX_EPOCH).ok()?;
+    if duration.subsec_nanos() == 0 {
+        return None;
+    }
+    let fraction = format!("{:09}", duration.subsec_nanos());
+    Some(format!(
+        "{}.{}",
+        duration.as_secs(),
+        fraction.trim_end_matches('0')
+    ))
+}
+
+/// Encode pax extended header records, each prefixed with its own length in bytes
+fn pax_records(records: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut extensions = Vec::new();
+    for (key, value) in records {
+        // The length includes the digits of the length itself
+        let rest = key.len() + value.len() + 3;
+        let mut length = rest + 1;
+        while length != rest + length.to_string().len() {
+            length = rest + length.to_string().len();
+        }
+        extensions.extend_from_slice(format!("{} {}=", length, key).as_bytes());
+        extensions.extend_from_slice(value);
+        extensions.push(b'\n');
+    }
+    extensions
 }
 
-// TODO: Tests will be largely the same for all Compressors, should be able to combine
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -141,4 +911,592 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn exclude() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Tar {
+            exclude: Exclude::from_patterns(&["node_modules", "*.o"])?,
+            ..Tar::default()
+        };
+
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        dir.child("keep.txt")
+            .write_str("garbage data for testing")?;
+        dir.child("main.o").write_str("object")?;
+        dir.child("node_modules/dep/index.js")
+            .write_str("dependency")?;
+        let archive = working_dir.child("archive.tar");
+        compressor.compress(
+            CmprssInput::Path(vec![dir.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+
+        let entries = compressor.list(CmprssInput::Path(vec![archive.path().to_path_buf()]))?;
+        let paths: Vec<_> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, ["dir", "dir/keep.txt"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn strip_components() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("project-1.2.3");
+        dir.child("README").write_str("readme")?;
+        dir.child("src/main.rs").write_str("fn main() {}")?;
+        let archive = working_dir.child("archive.tar");
+        Tar::default().compress(
+            CmprssInput::Path(vec![dir.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+
+        let compressor = Tar {
+            strip_components: 1,
+            ..Tar::default()
+        };
+        let out = working_dir.child("out");
+        out.create_dir_all()?;
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        out.child("README").assert("readme");
+        out.child("src/main.rs").assert("fn main() {}");
+        out.child("project-1.2.3")
+            .assert(predicate::path::missing());
+
+        Ok(())
+    }
+
+    #[test]
+    fn transform() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let build = working_dir.child("build");
+        build.child("lib/app.so").write_str("library")?;
+        build.child("README").write_str("readme")?;
+        let archive = working_dir.child("archive.tar");
+        let compressor = Tar {
+            transform: Transform::new(&["s,^build(/|$),,", "s/README/README.txt/"])?,
+            ..Tar::default()
+        };
+        compressor.compress(
+            CmprssInput::Path(vec![build.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        let entries = Tar::default().list(CmprssInput::Path(vec![archive.path().to_path_buf()]))?;
+        let paths: Vec<_> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, ["README.txt", "lib", "lib/app.so"]);
+
+        let compressor = Tar {
+            transform: Transform::new(&["s/^/vendor\\//"])?,
+            ..Tar::default()
+        };
+        let out = working_dir.child("out");
+        out.create_dir_all()?;
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        out.child("vendor/README.txt").assert("readme");
+        out.child("vendor/lib/app.so").assert("library");
+
+        let compressor = Tar {
+            transform: Transform::default().junk_paths(true),
+            ..Tar::default()
+        };
+        let flat = working_dir.child("flat.tar");
+        compressor.compress(
+            CmprssInput::Path(vec![build.path().to_path_buf()]),
+            CmprssOutput::Path(flat.path().to_path_buf()),
+        )?;
+        let entries = compressor.list(CmprssInput::Path(vec![flat.path().to_path_buf()]))?;
+        let paths: Vec<_> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, ["README", "app.so"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn selective_extract() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        dir.child("docs/guide/intro.md").write_str("intro")?;
+        dir.child("docs/drafts/todo.md").write_str("todo")?;
+        dir.child("docs/logo.png").write_str("png")?;
+        dir.child("src/main.rs").write_str("fn main() {}")?;
+        let archive = working_dir.child("archive.tar");
+        Tar::default().compress(
+            CmprssInput::Path(vec![dir.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+
+        let compressor = Tar {
+            exclude: Exclude::from_patterns(&["drafts"])?.include(&["dir/docs/**/*.md"])?,
+            ..Tar::default()
+        };
+        let out = working_dir.child("out");
+        out.create_dir_all()?;
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        out.child("dir/docs/guide/intro.md").assert("intro");
+        out.child("dir/docs/drafts")
+            .assert(predicate::path::missing());
+        out.child("dir/docs/logo.png")
+            .assert(predicate::path::missing());
+        out.child("dir/src").assert(predicate::path::missing());
+
+        Ok(())
+    }
+
+    #[test]
+    fn progress() -> Result<(), Box<dyn std::error::Error>> {
+        use std::sync::Mutex;
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        dir.child("a.txt").write_str(&"a".repeat(1000))?;
+        dir.child("b/c.txt").write_str("garbage data for testing")?;
+        let archive = working_dir.child("archive.tar");
+        let last = Arc::new(Mutex::new((0, 0)));
+        let recorded = last.clone();
+        let mut compressor = Tar::default();
+        compressor.set_observer(Arc::new(move |input, output| {
+            *recorded.lock().unwrap() = (input, output)
+        }));
+
+        let paths = vec![dir.path().to_path_buf()];
+        assert_eq!(compressor.input_size(&paths), 1024);
+        compressor.compress(
+            CmprssInput::Path(paths.clone()),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        let archive_size = fs::metadata(archive.path())?.len();
+        assert_eq!(*last.lock().unwrap(), (1024, archive_size));
+        assert_eq!(compressor.archive_size(&paths), archive_size);
+
+        let out = working_dir.child("out");
+        out.create_dir_all()?;
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        let (input, output) = *last.lock().unwrap();
+        assert!(input > 0 && input <= archive_size);
+        assert_eq!(output, 1024);
+        out.child("dir/a.txt").assert("a".repeat(1000));
+
+        // A cancelled job fails instead of finishing the archive
+        compressor.hooks.cancel.cancel();
+        assert!(compressor
+            .compress(
+                CmprssInput::Path(paths),
+                CmprssOutput::Path(working_dir.child("cancelled.tar").path().to_path_buf()),
+            )
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn pipe_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Tar::default();
+
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("test.txt");
+        file.write_str("garbage data for testing")?;
+        let archive = working_dir.child("archive.tar");
+        let out = working_dir.child("out");
+        out.create_dir_all()?;
+
+        // Any stream can be used as a pipe
+        compressor.compress(
+            CmprssInput::Path(vec![file.path().to_path_buf()]),
+            CmprssOutput::Pipe(Box::new(File::create(archive.path())?)),
+        )?;
+        compressor.extract(
+            CmprssInput::Pipe(Box::new(File::open(archive.path())?)),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        out.child("test.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn stream_input() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("dump.tar");
+        let compressor = Tar::default();
+        compressor.compress(
+            CmprssInput::Reader(CmprssRead {
+                reader: Box::new(&b"INSERT INTO t VALUES (1);"[..]),
+                size: None,
+                name: Some("dump.sql".to_string()),
+            }),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        let entries = compressor.list(CmprssInput::Path(vec![archive.path().to_path_buf()]))?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, "dump.sql");
+        assert_eq!(entries[0].size, 25);
+
+        // Without a name, the entry is named like the output of other formats
+        compressor.compress(
+            CmprssInput::Pipe(Box::new(&b"data"[..])),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        let entries = compressor.list(CmprssInput::Path(vec![archive.path().to_path_buf()]))?;
+        assert_eq!(entries[0].path, "archive");
+
+        Ok(())
+    }
+
+    #[test]
+    fn list() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Tar::default();
+
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        dir.child("test.txt")
+            .write_str("garbage data for testing")?;
+        let archive = working_dir.child("archive.tar");
+        compressor.compress(
+            CmprssInput::Path(vec![dir.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+
+        let entries = compressor.list(CmprssInput::Path(vec![archive.path().to_path_buf()]))?;
+        let file = entries
+            .iter()
+            .find(|entry| entry.path == "dir/test.txt")
+            .expect("file is listed");
+        assert_eq!(file.size, 24);
+        assert!(!file.is_dir);
+        assert!(file.mtime.is_some());
+        assert!(entries.iter().any(|entry| entry.is_dir));
+
+        Ok(())
+    }
+    /// Build a tar with the given entry names, which the tar builder would refuse
+    fn unsafe_archive(names: &[&str]) -> Result<Vec<u8>, io::Error> {
+        let mut builder = Builder::new(Vec::new());
+        for name in names {
+            let data = b"escaped";
+            let mut header = tar::Header::new_gnu();
+            header.as_mut_bytes()[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, &data[..])?;
+        }
+        builder.into_inner()
+    }
+
+    #[test]
+    fn unsafe_paths() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("archive.tar");
+        let out = working_dir.child("out");
+        out.create_dir_all()?;
+
+        // Absolute paths are extracted inside the output directory
+        archive.write_binary(&unsafe_archive(&["/absolute.txt"])?)?;
+        Tar::default().extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        out.child("absolute.txt").assert("escaped");
+
+        // Parent directories are rejected, unless explicitly allowed
+        archive.write_binary(&unsafe_archive(&["../escape.txt"])?)?;
+        let result = Tar::default().extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        );
+        assert!(result.is_err());
+        working_dir
+            .child("escape.txt")
+            .assert(predicate::path::missing());
+
+        let compressor = Tar {
+            allow_unsafe_paths: true,
+            ..Tar::default()
+        };
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        working_dir.child("escape.txt").assert("escaped");
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlinks() -> Result<(), Box<dyn std::error::Error>> {
+        use std::os::unix::fs::symlink;
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        dir.child("test.txt")
+            .write_str("garbage data for testing")?;
+        symlink("test.txt", dir.child("link").path())?;
+        symlink("../../outside", dir.child("escape").path())?;
+        symlink("missing", dir.child("broken").path())?;
+        let input = || CmprssInput::Path(vec![dir.path().to_path_buf()]);
+        let archive = working_dir.child("archive.tar");
+
+        // Links are stored as links, and the ones pointing out of the output are skipped
+        Tar::default().compress(input(), CmprssOutput::Path(archive.path().to_path_buf()))?;
+        let out = working_dir.child("out");
+        out.create_dir_all()?;
+        Tar::default().extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        assert_eq!(
+            fs::read_link(out.child("dir/link").path())?,
+            Path::new("test.txt")
+        );
+        assert_eq!(
+            fs::read_link(out.child("dir/broken").path())?,
+            Path::new("missing")
+        );
+        out.child("dir/escape").assert(predicate::path::missing());
+
+        // Following the links archives the files they point to
+        let compressor = Tar {
+            symlinks: Symlinks::Follow,
+            ..Tar::default()
+        };
+        fs::remove_file(dir.child("broken").path())?;
+        fs::remove_file(dir.child("escape").path())?;
+        compressor.compress(input(), CmprssOutput::Path(archive.path().to_path_buf()))?;
+        let out = working_dir.child("followed");
+        out.create_dir_all()?;
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        assert!(!fs::symlink_metadata(out.child("dir/link").path())?.is_symlink());
+        out.child("dir/link").assert("garbage data for testing");
+
+        // Skipping the links leaves them out of the archive
+        let compressor = Tar {
+            symlinks: Symlinks::Skip,
+            ..Tar::default()
+        };
+        compressor.compress(input(), CmprssOutput::Path(archive.path().to_path_buf()))?;
+        let paths: Vec<_> = compressor
+            .list(CmprssInput::Path(vec![archive.path().to_path_buf()]))?
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+        assert!(paths.iter().all(|path| !path.ends_with("link")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn mtime() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        dir.child("old.txt").write_str("old")?;
+        dir.child("new.txt").write_str("new")?;
+        let old = std::time::UNIX_EPOCH + std::time::Duration::from_millis(1_000_500);
+        File::options()
+            .write(true)
+            .open(dir.child("old.txt").path())?
+            .set_modified(old)?;
+        let archive = working_dir.child("archive.tar");
+        let paths = vec![dir.path().to_path_buf()];
+
+        for (clamp, old_mtime) in [(false, 5000), (true, 1000)] {
+            let mut compressor = Tar::default();
+            compressor.set_mtime(MtimeOverride { mtime: 5000, clamp });
+            compressor.compress(
+                CmprssInput::Path(paths.clone()),
+                CmprssOutput::Path(archive.path().to_path_buf()),
+            )?;
+            assert_eq!(
+                compressor.archive_size(&paths),
+                fs::metadata(archive.path())?.len()
+            );
+            let entries = compressor.list(CmprssInput::Path(vec![archive.path().to_path_buf()]))?;
+            let mtime = |name: &str| {
+                entries
+                    .iter()
+                    .find(|entry| entry.path.ends_with(name))
+                    .and_then(|entry| entry.mtime)
+            };
+            assert_eq!(mtime("new.txt"), Some(5000));
+            assert_eq!(mtime("old.txt"), Some(old_mtime));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn hard_links() -> Result<(), Box<dyn std::error::Error>> {
+        use std::os::unix::fs::MetadataExt;
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        let content = "garbage data for testing".repeat(1000);
+        dir.child("a.txt").write_str(&content)?;
+        dir.child("nested").create_dir_all()?;
+        fs::hard_link(dir.child("a.txt").path(), dir.child("b.txt").path())?;
+        fs::hard_link(dir.child("a.txt").path(), dir.child("nested/c.txt").path())?;
+        let archive = working_dir.child("archive.tar");
+        let compressor = Tar::default();
+        let paths = vec![dir.path().to_path_buf()];
+        compressor.compress(
+            CmprssInput::Path(paths.clone()),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+
+        // The contents are only stored once
+        let archive_size = fs::metadata(archive.path())?.len();
+        assert!(archive_size < 2 * content.len() as u64);
+        assert_eq!(compressor.archive_size(&paths), archive_size);
+        let entries = compressor.list(CmprssInput::Path(vec![archive.path().to_path_buf()]))?;
+        assert_eq!(entries.len(), 5);
+
+        for strip in [0, 1] {
+            let compressor = Tar {
+                strip_components: strip,
+                ..Tar::default()
+            };
+            let out = working_dir.child(format!("out{}", strip));
+            out.create_dir_all()?;
+            compressor.extract(
+                CmprssInput::Path(vec![archive.path().to_path_buf()]),
+                CmprssOutput::Path(out.path().to_path_buf()),
+            )?;
+            let out = match strip {
+                0 => out.child("dir"),
+                _ => out,
+            };
+            let inode = |name: &str| fs::metadata(out.child(name).path()).map(|m| m.ino());
+            assert_eq!(inode("a.txt")?, inode("b.txt")?);
+            assert_eq!(inode("a.txt")?, inode("nested/c.txt")?);
+            out.child("nested/c.txt").assert(content.as_str());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn formats() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        let long = ["long directory name".repeat(4), "file name ".repeat(12)].join("/");
+        dir.child(&long).write_str("garbage data for testing")?;
+        dir.child("short.txt").write_str("short")?;
+        let paths = vec![dir.path().to_path_buf()];
+
+        for (format, marker) in [
+            (TarFormat::Pax, &b"PaxHeaders"[..]),
+            (TarFormat::Gnu, &b"././@LongLink"[..]),
+        ] {
+            let compressor = Tar {
+                format,
+                ..Tar::default()
+            };
+            let archive = working_dir.child("archive.tar");
+            compressor.compress(
+                CmprssInput::Path(paths.clone()),
+                CmprssOutput::Path(archive.path().to_path_buf()),
+            )?;
+            let data = fs::read(archive.path())?;
+            assert!(data.windows(marker.len()).any(|window| window == marker));
+            let out = working_dir.child(format!("{:?}", format));
+            out.create_dir_all()?;
+            compressor.extract(
+                CmprssInput::Path(vec![archive.path().to_path_buf()]),
+                CmprssOutput::Path(out.path().to_path_buf()),
+            )?;
+            out.child("dir")
+                .child(&long)
+                .assert("garbage data for testing");
+        }
+
+        // Paths that are too long for ustar are an error, short ones are fine
+        let compressor = Tar {
+            format: TarFormat::Ustar,
+            ..Tar::default()
+        };
+        let archive = working_dir.child("ustar.tar");
+        assert!(compressor
+            .compress(
+                CmprssInput::Path(paths),
+                CmprssOutput::Path(archive.path().to_path_buf()),
+            )
+            .is_err());
+        compressor.compress(
+            CmprssInput::Path(vec![dir.child("short.txt").path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        assert_eq!(&fs::read(archive.path())?[257..263], b"ustar\0");
+
+        Ok(())
+    }
+
+    #[test]
+    fn pax_record_lengths() {
+        assert_eq!(pax_records(&[("path", b"a".to_vec())]), b"9 path=a\n");
+        // The digits of the length count towards it
+        assert_eq!(
+            pax_records(&[("path", b"abcdef".to_vec())]),
+            b"15 path=abcdef\n"
+        );
+    }
+
+    #[test]
+    fn owners() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("test.txt");
+        file.write_str("garbage data for testing")?;
+        let archive = working_dir.child("archive.tar");
+        let compressor = Tar {
+            ownership: Ownership {
+                owner: Some(owner::Account {
+                    name: Some("builder".to_string()),
+                    id: 1234,
+                }),
+                group: Some(owner::Account {
+                    name: None,
+                    id: 2345,
+                }),
+                numeric: false,
+            },
+            ..Tar::default()
+        };
+        compressor.compress(
+            CmprssInput::Path(vec![file.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        let mut tar = Archive::new(File::open(archive.path())?);
+        let entry = tar.entries()?.next().unwrap()?;
+        assert_eq!(entry.header().uid()?, 1234);
+        assert_eq!(entry.header().gid()?, 2345);
+        assert_eq!(entry.header().username().ok().flatten(), Some("builder"));
+
+        // Only root can give the extracted files their owners
+        let out = working_dir.child("out");
+        out.create_dir_all()?;
+        Tar::default().extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        #[cfg(unix)]
+        if owner::is_root() {
+            use std::os::unix::fs::MetadataExt;
+            let metadata = fs::metadata(out.child("test.txt").path())?;
+            assert_eq!((metadata.uid(), metadata.gid()), (1234, 2345));
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,268 @@
+//! Renaming the entries of archives with sed-like substitutions, like `tar --transform`
+
+use regex::{Regex, RegexBuilder};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A single `s/REGEX/REPLACEMENT/FLAGS` expression
+#[derive(Debug, Clone)]
+struct Substitution {
+    regex: Regex,
+    /// The replacement in the syntax of the regex crate, with `$1` for `\1`
+    replacement: String,
+    /// Replace every match instead of only the first
+    global: bool,
+}
+
+impl Substitution {
+    /// Parse an expression like sed's, where any character after the 's' separates the parts.
+    /// `\1` to `\9` and `&` in the replacement are the groups and the whole match, and the flags
+    /// are `g` to replace every match and `i` to match regardless of case.
+    fn parse(expression: &str) -> Result<Substitution, io::Error> {
+        let invalid = |reason: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid transform {}: {}", expression, reason),
+            )
+        };
+        let mut chars = expression.chars();
+        if chars.next() != Some('s') {
+            return Err(invalid("it must look like s/REGEX/REPLACEMENT/"));
+        }
+        let delimiter = chars
+            .next()
+            .filter(|c| *c != '\\' && !c.is_alphanumeric())
+            .ok_or_else(|| invalid("it must look like s/REGEX/REPLACEMENT/"))?;
+        let mut parts = vec![String::new()];
+        while let Some(c) = chars.next() {
+            match c {
+                // An escaped delimiter is the character itself, other escapes are kept as they are
+                '\\' => match chars.next() {
+                    Some(next) if next == delimiter => parts.last_mut().unwrap().push(next),
+                    Some(next) => parts.last_mut().unwrap().extend(['\\', next]),
+                    None => return Err(invalid("it ends with a '\\'")),
+                },
+                c if c == delimiter && parts.len() < 3 => parts.push(String::new()),
+                c => parts.last_mut().unwrap().push(c),
+            }
+        }
+        let [pattern, replacement, flags] = &parts[..] else {
+            return Err(invalid("it must look like s/REGEX/REPLACEMENT/"));
+        };
+        let mut global = false;
+        let mut ignore_case = false;
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => ignore_case = true,
+                flag => return Err(invalid(&format!("unknown flag {}", flag))),
+            }
+        }
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|e| invalid(&e.to_string()))?;
+        Ok(Substitution {
+            regex,
+            replacement: replacement_syntax(replacement),
+            global,
+        })
+    }
+
+    fn apply(&self, name: &str) -> String {
+        let replaced = match self.global {
+            true => self.regex.replace_all(name, self.replacement.as_str()),
+            false => self.regex.replace(name, self.replacement.as_str()),
+        };
+        replaced.into_owned()
+    }
+}
+
+/// Convert a sed replacement to the syntax of the regex crate
+fn replacement_syntax(replacement: &str) -> String {
+    let mut converted = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(group @ '0'..='9') => converted.push_str(&format!("${{{}}}", group)),
+                Some('$') => converted.push_str("$$"),
+                Some(escaped) => converted.push(escaped),
+                None => converted.push('\\'),
+            },
+            '&' => converted.push_str("${0}"),
+            '$' => converted.push_str("$$"),
+            c => converted.push(c),
+        }
+    }
+    converted
+}
+
+/// The substitutions to apply to the names of entries, in order
+#[derive(Debug, Clone, Default)]
+pub struct Transform {
+    substitutions: Vec<Substitution>,
+    /// Only keep the last component of the names, leaving directories out
+    junk_paths: bool,
+}
+
+impl Transform {
+    /// Parse the expressions given with --transform
+    pub fn new<S: AsRef<str>>(expressions: &[S]) -> Result<Transform, io::Error> {
+        let substitutions = expressions
+            .iter()
+            .map(|expression| Substitution::parse(expression.as_ref()))
+            .collect::<Result<_, _>>()?;
+        Ok(Transform {
+            substitutions,
+            junk_paths: false,
+        })
+    }
+
+    /// Only keep the names of the files after the substitutions, like `zip -j`
+    pub fn junk_paths(mut self, junk_paths: bool) -> Transform {
+        self.junk_paths = junk_paths;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.substitutions.is_empty() && !self.junk_paths
+    }
+
+    /// The new name of an entry, or None when nothing is left of it
+    /// Names always use '/', so the substitutions work the same on every platform.
+    /// Directories have no name of their own left when junking the paths.
+    pub fn apply(&self, name: &Path, is_dir: bool) -> Option<PathBuf> {
+        if self.is_empty() {
+            return Some(name.to_path_buf());
+        }
+        if self.junk_paths && is_dir {
+            return None;
+        }
+        let name = name.to_string_lossy();
+        #[cfg(windows)]
+        let name = name.replace('\\', "/");
+        let name = self
+            .substitutions
+            .iter()
+            .fold(name.to_string(), |name, substitution| {
+                substitution.apply(&name)
+            });
+        let mut name = name.trim_start_matches("./").trim_end_matches('/');
+        if self.junk_paths {
+            name = name.rsplit('/').next().unwrap_or_default();
+        }
+        (!name.is_empty() && name != "..").then(|| PathBuf::from(name))
+    }
+
+    /// The new name of a file added to an archive, like [`Transform::apply`]
+    /// Two files renamed to the same entry are an error, like `a/f` and `b/f` with
+    /// --junk-paths, since one of them would be lost when extracting.
+    pub fn apply_to_file(
+        &self,
+        path: &Path,
+        name: &Path,
+        is_dir: bool,
+        renamed: &mut RenamedFiles,
+    ) -> Result<Option<PathBuf>, io::Error> {
+        let Some(new_name) = self.apply(name, is_dir) else {
+            return Ok(None);
+        };
+        if self.is_empty() || is_dir {
+            return Ok(Some(new_name));
+        }
+        match renamed.0.insert(new_name.clone(), path.to_path_buf()) {
+            Some(other) if other != path => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "{} and {} would both be stored as {}",
+                    other.display(),
+                    path.display(),
+                    new_name.display()
+                ),
+            )),
+            _ => Ok(Some(new_name)),
+        }
+    }
+}
+
+/// The files already renamed into an archive, with the names they were given
+#[derive(Debug, Default)]
+pub struct RenamedFiles(HashMap<PathBuf, PathBuf>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rename(expressions: &[&str], name: &str) -> Option<String> {
+        Transform::new(expressions)
+            .unwrap()
+            .apply(Path::new(name), false)
+            .map(|name| name.to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn substitutions() -> Result<(), io::Error> {
+        assert_eq!(
+            rename(&[r"s/^build\///"], "build/lib/app.so"),
+            Some("lib/app.so".to_string())
+        );
+        assert_eq!(
+            rename(&[r"s/^build\///"], "build"),
+            Some("build".to_string())
+        );
+        assert_eq!(rename(&["s,^build/,,"], "build/"), None);
+        assert_eq!(
+            rename(&["s|^|vendor/|"], "lib/a.c"),
+            Some("vendor/lib/a.c".to_string())
+        );
+        assert_eq!(
+            rename(&["s/o/0/g", r"s/\.c$/.h/"], "foo/boo.c"),
+            Some("f00/b00.h".to_string())
+        );
+        assert_eq!(
+            rename(&[r"s/([a-z]+)-(\d+)/\2-\1/"], "pkg-12/x"),
+            Some("12-pkg/x".to_string())
+        );
+        assert_eq!(
+            rename(&["s/LIB/[&]/i"], "lib/a"),
+            Some("[lib]/a".to_string())
+        );
+        assert_eq!(rename(&["s/a/$1/"], "a"), Some("$1".to_string()));
+        assert_eq!(rename(&[], "any/name"), Some("any/name".to_string()));
+
+        let junk = Transform::default().junk_paths(true);
+        assert_eq!(
+            junk.apply(Path::new("dist/linux/app"), false),
+            Some(PathBuf::from("app"))
+        );
+        assert_eq!(junk.apply(Path::new("dist/linux"), true), None);
+        let junk = Transform::new(&["s/app$/tool/"])?.junk_paths(true);
+        assert_eq!(
+            junk.apply(Path::new("app/bin/app"), false),
+            Some(PathBuf::from("tool"))
+        );
+
+        assert!(Transform::new(&["y/a/b/"]).is_err());
+        assert!(Transform::new(&["s/a/b"]).is_err());
+        assert!(Transform::new(&["s/a/b/x"]).is_err());
+        assert!(Transform::new(&["s/(/b/"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn renamed_files() -> Result<(), io::Error> {
+        let junk = Transform::default().junk_paths(true);
+        let mut renamed = RenamedFiles::default();
+        let mut add =
+            |name: &str| junk.apply_to_file(Path::new(name), Path::new(name), false, &mut renamed);
+        assert_eq!(add("a/f")?, Some(PathBuf::from("f")));
+        // The same file given twice is still the same entry
+        assert_eq!(add("a/f")?, Some(PathBuf::from("f")));
+        assert_eq!(add("a/g")?, Some(PathBuf::from("g")));
+        let error = add("b/f").unwrap_err();
+        assert_eq!(error.to_string(), "a/f and b/f would both be stored as f");
+        Ok(())
+    }
+}
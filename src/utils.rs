@@ -1,22 +1,35 @@
+//! The [`Compressor`] trait and the types shared by all the backends
+
+use crate::checksum::ChecksumAlgorithm;
+use crate::exclude::{Exclude, ExcludeArgs};
+use crate::http::HttpArgs;
+use crate::limits::{LimitArgs, OutputLimits};
+use crate::log::VerbosityArgs;
+use crate::progress::{ChunkSize, ProgressDisplay, ProgressObserver};
+use crate::stats::StatsFormat;
+use crate::transform::Transform;
 use clap::Args;
 use std::ffi::OsStr;
 use std::fmt;
-use std::io;
-use std::path::{Path, PathBuf};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
 
 #[derive(Args, Debug)]
 pub struct CommonArgs {
-    /// Input file/directory
+    /// Input file/directory, or - for stdin
     #[arg(short, long)]
     pub input: Option<String>,
 
-    /// Output file/directory
+    /// Output file/directory, or - for stdout
     #[arg(short, long)]
     pub output: Option<String>,
 
     /// Compress the input (default)
-    #[arg(short, long)]
+    #[arg(short = 'z', long)]
     pub compress: bool,
 
     /// Extract the input
@@ -32,6 +45,11 @@ pub struct CommonArgs {
     #[arg()]
     pub io_list: Vec<String>,
 
+    /// Write the output to stdout, like `gzip -c`, even when it could be named after the input.
+    /// Compressed data isn't written to a terminal unless --force is given.
+    #[arg(short = 'c', long, conflicts_with = "output")]
+    pub stdout: bool,
+
     /// Ignore pipes when inferring I/O
     #[arg(long)]
     pub ignore_pipes: bool,
@@ -43,6 +61,203 @@ pub struct CommonArgs {
     /// Ignore stdout when inferring I/O
     #[arg(long)]
     pub ignore_stdout: bool,
+
+    #[clap(flatten)]
+    pub overwrite_args: OverwriteArgs,
+
+    /// Print the format, action, inputs and output that were inferred, and exit without
+    /// reading or writing any files
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    #[clap(flatten)]
+    pub verbosity_args: VerbosityArgs,
+
+    /// Name of the data piped to stdin, used to name the output and the entry in a tar or zip
+    /// archive, which is `archive` without it.
+    /// gzip also stores it in its header, the other stream formats have nowhere to store it.
+    #[arg(long, visible_alias = "arcname", value_name = "NAME")]
+    pub stdin_name: Option<String>,
+
+    /// Use the system tools for formats that aren't built in, or if the built in backend fails
+    #[arg(long)]
+    pub allow_external: bool,
+
+    /// Extract archive entries with absolute paths or '..' where they point, even outside the output directory.
+    /// By default absolute paths are extracted inside the output, and entries with '..' are an error.
+    #[arg(long)]
+    pub allow_unsafe_paths: bool,
+
+    /// Remove this many leading directories from the paths of extracted entries, like `tar --strip-components`.
+    /// Entries with nothing left, like the top directory itself, are skipped.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub strip_components: usize,
+
+    /// Rename the entries of tar and zip archives with a sed-like expression, like
+    /// 's/^build\///', when archiving and extracting. Given several times, they are applied in order.
+    /// `\1` and `&` in the replacement are the groups and the match, the flags are `g` and `i`.
+    #[arg(long, value_name = "EXPRESSION")]
+    pub transform: Vec<String>,
+
+    /// Read the files to archive from a file, one per line, or from stdin with '-'.
+    /// Entries are named by the listed paths, and directories are stored without their contents
+    /// since tools like find list those too.
+    #[arg(short = 'T', long, value_name = "FILE")]
+    pub files_from: Option<PathBuf>,
+
+    /// Separate the files of --files-from with NUL bytes instead of lines, like `find -print0`
+    #[arg(short = '0', long, requires = "files_from")]
+    pub null: bool,
+
+    /// Find the files to archive in DIR, like `tar -C`, and extract archives into it.
+    /// Entries listed with --files-from are named relative to it, the archive itself is still
+    /// relative to the current directory.
+    #[arg(short = 'C', long, value_name = "DIR")]
+    pub directory: Option<PathBuf>,
+
+    /// Only store the names of the files in tar and zip archives, without their directories, like
+    /// `zip -j`. Extracting with it writes every file straight into the output directory.
+    #[arg(long)]
+    pub junk_paths: bool,
+
+    /// Archive the files that symbolic links point to, instead of storing the links.
+    #[arg(long, conflicts_with = "skip_symlinks")]
+    pub dereference: bool,
+
+    /// Leave symbolic links out when archiving, and skip the links in an archive when extracting.
+    /// Otherwise links are restored, unless they point outside the output directory.
+    #[arg(long)]
+    pub skip_symlinks: bool,
+
+    /// Archive directories and multiple inputs with tar before compressing them with a stream
+    /// format, so that `cmprss zstd --tar dir` creates dir.tar.zst
+    #[arg(long)]
+    pub tar: bool,
+
+    /// Compress every file in the input directories to its own file next to it, like `gzip -r`.
+    /// With --extract, every file of the format below the directories is extracted instead.
+    #[arg(short, long)]
+    pub recursive: bool,
+
+    /// Directory to write the output to instead of the current directory, created if missing.
+    /// The outputs of --recursive mirror the input directories below it.
+    #[arg(long, value_name = "DIR")]
+    pub output_dir: Option<PathBuf>,
+
+    /// Name the outputs of --recursive and of several files with a template like
+    /// '{dir}/{stem}-{date}.{ext}'. The placeholders are {dir}, {name}, {stem} and {ext} of the
+    /// output that would be written otherwise, the UTC {date} and a counter {n} starting at 1.
+    #[arg(long, value_name = "TEMPLATE")]
+    pub output_template: Option<String>,
+
+    /// Remove the input files once the output has been written, like gzip and xz do by default.
+    /// Directories are removed with everything in them, and inputs are kept when writing to stdout.
+    #[arg(long, overrides_with = "keep")]
+    pub rm_source: bool,
+
+    /// Keep the input files, which is the default, overriding an earlier --rm-source
+    #[arg(short, long, overrides_with = "rm_source")]
+    pub keep: bool,
+
+    /// Write the checksum of the output to a sidecar file next to it, like archive.tar.gz.sha256
+    #[arg(long, value_enum, value_name = "ALGORITHM")]
+    pub checksum: Option<ChecksumAlgorithm>,
+
+    /// Extract only this entry of the archive, writing its contents to stdout.
+    /// Archives with a single file are extracted to stdout without it when stdout is a pipe.
+    #[arg(long, value_name = "ENTRY")]
+    pub to_stdout: Option<String>,
+
+    /// Write the output in parts of at most this size, like '2gb', named archive.tar.zst.001, .002...
+    /// Extracting the first part reads all of them.
+    #[arg(long, value_name = "SIZE")]
+    pub split_size: Option<ChunkSize>,
+
+    /// Check the inputs against their sidecar checksum files first, like an archive before
+    /// extracting it
+    #[arg(long)]
+    pub verify_checksum: bool,
+
+    /// Number of files to compress or extract at once, with --recursive or several inputs.
+    /// Defaults to the number of logical CPUs.
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub jobs: Option<u32>,
+
+    /// Number of threads to compress with, for the formats that support it (zstd, xz).
+    /// Defaults to the number of logical CPUs.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub threads: Option<u32>,
+
+    /// Print a summary of the sizes, ratio, and throughput to stderr when finished.
+    /// `--stats=json` prints it as JSON to stdout, or to stderr when the output is stdout.
+    #[arg(long, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "text")]
+    pub stats: Option<StatsFormat>,
+
+    /// Create byte-identical archives from identical inputs.
+    /// Owners are left out, permissions only keep the executable bit, and modification times
+    /// are set to 0, or clamped to SOURCE_DATE_EPOCH if it is set.
+    #[arg(long)]
+    pub reproducible: bool,
+
+    /// Store this modification time for all the archived files, instead of their own.
+    /// Given as seconds since the epoch with an optional '@', a UTC date like '2024-01-31' or
+    /// '2024-01-31 12:30:00', or the path of a file to use the time of.
+    #[arg(long, value_name = "TIMESTAMP", value_parser = parse_mtime)]
+    pub mtime: Option<u64>,
+
+    /// Only change the modification times later than --mtime, keeping the earlier ones
+    #[arg(long, requires = "mtime")]
+    pub clamp_mtime: bool,
+
+    #[clap(flatten)]
+    pub exclude_args: ExcludeArgs,
+
+    #[clap(flatten)]
+    pub limit_args: LimitArgs,
+
+    #[clap(flatten)]
+    pub http_args: HttpArgs,
+}
+
+/// What to do with outputs that already exist
+#[derive(Args, Debug, Default, Clone, Copy)]
+pub struct OverwriteArgs {
+    /// Overwrite the output if it already exists
+    #[arg(short, long, conflicts_with = "no_clobber")]
+    pub force: bool,
+
+    /// Never overwrite an existing output, skip the job instead of failing
+    #[arg(long)]
+    pub no_clobber: bool,
+}
+
+/// How the reports of list, info, diff, benchmark, checksum, grep and --stats are printed
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    /// Text for people to read
+    #[default]
+    Text,
+    /// JSON, for scripts
+    Json,
+}
+
+/// A string as a quoted JSON string
+pub fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -89,7 +304,7 @@ pub struct LevelArgs {
 
 /// Common interface for all compressor implementations
 #[allow(unused_variables)]
-pub trait Compressor {
+pub trait Compressor: Send + Sync {
     /// Name of this Compressor
     fn name(&self) -> &str;
 
@@ -108,6 +323,84 @@ pub trait Compressor {
         in_path.extension().unwrap() == self.extension()
     }
 
+    /// Whether this format compresses a single stream of data, rather than archiving files
+    /// Directories have to be archived with tar first to be compressed with these.
+    fn is_stream(&self) -> bool {
+        false
+    }
+
+    /// Set the number of threads to compress with
+    /// Only some formats compress in parallel, the rest ignore this.
+    fn set_threads(&mut self, threads: u32) {}
+
+    /// Allow extracting entries outside of the output directory
+    /// Only formats with multiple entries, like tar and zip, use this.
+    fn set_allow_unsafe_paths(&mut self, allow: bool) {}
+
+    /// Remove leading directories from the paths of entries when extracting
+    /// Only formats with multiple entries, like tar and zip, use this.
+    fn set_strip_components(&mut self, count: usize) {}
+
+    /// Rename the entries when archiving and extracting
+    /// Only formats with multiple entries, like tar and zip, use this.
+    fn set_transform(&mut self, transform: Transform) {}
+
+    /// Archive the inputs as a list of files from --files-from, named by their paths, with
+    /// directories stored without their contents
+    /// Only formats with multiple entries, like tar and zip, use this.
+    fn set_from_list(&mut self, from_list: bool) {}
+
+    /// Set the directory the inputs were found in for -C, which the entries listed with
+    /// --files-from are named relative to
+    /// Only formats with multiple entries, like tar and zip, use this.
+    fn set_directory(&mut self, directory: PathBuf) {}
+
+    /// Set how symbolic links are archived and extracted
+    /// Only formats with multiple entries, like tar and zip, use this.
+    fn set_symlinks(&mut self, symlinks: Symlinks) {}
+
+    /// Limit the size of the output when extracting, against decompression bombs
+    fn set_limits(&mut self, limits: OutputLimits) {}
+
+    /// Report the progress to the observer instead of showing a progress bar
+    /// Formats that don't report their progress, like plugins, ignore this.
+    fn set_observer(&mut self, observer: Arc<dyn ProgressObserver>) {}
+
+    /// Set the files to leave out when archiving directories, and the entries to extract
+    /// Only archive formats, like tar and zip, use this.
+    fn set_exclude(&mut self, exclude: Exclude) {}
+
+    /// Leave out the metadata that differs between runs when compressing
+    /// Only formats that store file metadata, like tar and zip, use this.
+    fn set_reproducible(&mut self, reproducible: Reproducible) {}
+
+    /// Replace or clamp the modification times of the files when compressing
+    /// Only formats that store file metadata, like tar and zip, use this.
+    fn set_mtime(&mut self, mtime: MtimeOverride) {}
+
+    /// Set the level of compression, in the range of the format
+    /// Used for the defaults from the config file, formats without levels ignore this.
+    fn set_level(&mut self, level: i32) {}
+
+    /// The level of compression, for the formats that have levels
+    fn level(&self) -> Option<i32> {
+        None
+    }
+
+    /// The number of threads to compress with, for the formats that compress in parallel
+    fn threads(&self) -> Option<u32> {
+        None
+    }
+
+    /// Set how the progress is shown
+    /// Formats that don't report their progress, like plugins, ignore this.
+    fn set_progress(&mut self, progress: ProgressDisplay) {}
+
+    /// How the progress is shown
+    fn progress(&self) -> ProgressDisplay {
+        ProgressDisplay::Auto
+    }
+
     /// Generate the default name for the compressed file
     fn default_compressed_filename(&self, in_path: &Path) -> String {
         format!(
@@ -123,16 +416,15 @@ pub trait Compressor {
 
     /// Generate the default extracted filename
     fn default_extracted_filename(&self, in_path: &Path) -> String {
-        // If the file has the extension for this type, return the filename without the extension
-        if in_path.extension().unwrap() == self.extension() {
-            return in_path.file_stem().unwrap().to_str().unwrap().to_string();
-        }
-        // If the file has no extension, return the current directory
-        if in_path.extension().is_none() {
-            return ".".to_string();
+        // If the file has an extension, return the filename without it
+        // The format may have been detected from the contents, so it can be any extension
+        if in_path.extension().is_some() {
+            if let Some(stem) = in_path.file_stem().and_then(|s| s.to_str()) {
+                return stem.to_string();
+            }
         }
-        // Otherwise, return the current directory and hope for the best
-        ".".to_string()
+        // Otherwise there is nothing to strip, so use a default filename
+        "archive".to_string()
     }
 
     fn compress(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
@@ -142,6 +434,75 @@ pub trait Compressor {
     fn extract(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
         cmprss_error("extract_target unimplemented")
     }
+
+    /// List the entries of an archive without extracting it
+    /// Stream formats hold a single unnamed stream, so they have nothing to list.
+    fn list(&self, input: CmprssInput) -> Result<Vec<ArchiveEntry>, io::Error> {
+        Err(io::Error::other(format!(
+            "{} is a stream format, it has no entries to list",
+            self.name()
+        )))
+    }
+
+    /// The comment stored in an archive, for formats that have one
+    fn comment(&self, input: CmprssInput) -> Result<Option<String>, io::Error> {
+        Ok(None)
+    }
+
+    /// Write the contents of a single entry of an archive to the output, without extracting the rest
+    /// Stream formats hold a single unnamed stream, so they have no entries to choose from.
+    fn cat(&self, input: CmprssInput, entry: &str, output: CmprssOutput) -> Result<(), io::Error> {
+        Err(io::Error::other(format!(
+            "{} is a stream format, extract it instead of choosing an entry",
+            self.name()
+        )))
+    }
+
+    /// Wrap a writer so that everything written to it is compressed
+    /// The end of the stream is written when the returned writer is dropped, use
+    /// `compress_stream` to catch any errors while finishing the stream.
+    fn wrap_writer<'a>(
+        &self,
+        writer: Box<dyn Write + Send + 'a>,
+    ) -> Result<Box<dyn Write + Send + 'a>, io::Error> {
+        Err(io::Error::other(format!(
+            "{} does not support streaming compression",
+            self.name()
+        )))
+    }
+
+    /// Wrap a reader so that everything read from it is extracted
+    fn wrap_reader<'a>(
+        &self,
+        reader: Box<dyn Read + Send + 'a>,
+    ) -> Result<Box<dyn Read + Send + 'a>, io::Error> {
+        Err(io::Error::other(format!(
+            "{} does not support streaming extraction",
+            self.name()
+        )))
+    }
+
+    /// Compress everything from the reader into the writer
+    fn compress_stream(
+        &self,
+        reader: &mut (dyn Read + Send),
+        writer: &mut (dyn Write + Send),
+    ) -> Result<(), io::Error> {
+        let mut encoder = self.wrap_writer(Box::new(writer))?;
+        io::copy(reader, &mut encoder)?;
+        encoder.flush()
+    }
+
+    /// Extract everything from the reader into the writer
+    fn extract_stream(
+        &self,
+        reader: &mut (dyn Read + Send),
+        writer: &mut (dyn Write + Send),
+    ) -> Result<(), io::Error> {
+        let mut decoder = self.wrap_reader(Box::new(reader))?;
+        io::copy(&mut decoder, writer)?;
+        writer.flush()
+    }
 }
 
 impl fmt::Debug for dyn Compressor {
@@ -151,29 +512,751 @@ impl fmt::Debug for dyn Compressor {
 }
 
 pub fn cmprss_error(message: &str) -> Result<(), io::Error> {
-    Err(io::Error::new(io::ErrorKind::Other, message))
+    Err(io::Error::other(message))
+}
+
+/// Check if a path is a special file, like a named pipe or a device
+/// These can be used as inputs and outputs even though they already exist and have no size.
+pub fn is_special_file(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|metadata| !metadata.is_file() && !metadata.is_dir())
+}
+
+/// Get a path that works for deep directory trees
+/// On Windows this is the absolute `\\?\` form, which isn't limited to MAX_PATH characters.
+/// The path must exist.
+pub fn long_path(path: &Path) -> Result<PathBuf, io::Error> {
+    if cfg!(windows) {
+        std::fs::canonicalize(path)
+    } else {
+        Ok(path.to_path_buf())
+    }
+}
+
+/// Path to extract an archive entry to, inside the output directory
+/// Absolute entries are made relative to the output, and entries containing '..' are rejected
+/// so that a malicious archive can't write anywhere else. `allow_unsafe` skips the checks.
+pub fn entry_path(out_dir: &Path, name: &Path, allow_unsafe: bool) -> Result<PathBuf, io::Error> {
+    if allow_unsafe {
+        return Ok(out_dir.join(name));
+    }
+    let mut path = out_dir.to_path_buf();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+            Component::ParentDir => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "unsafe path in the archive, use --allow-unsafe-paths to extract it: {}",
+                        name.display()
+                    ),
+                ))
+            }
+        }
+    }
+    Ok(path)
+}
+
+/// Remove the first `count` components of the path of an archive entry, for --strip-components
+/// Returns None when nothing is left, so the entry is skipped.
+pub fn strip_components(name: &Path, count: usize) -> Option<PathBuf> {
+    if count == 0 {
+        return Some(name.to_path_buf());
+    }
+    let stripped: PathBuf = name
+        .components()
+        .filter(|component| {
+            !matches!(
+                component,
+                Component::CurDir | Component::RootDir | Component::Prefix(_)
+            )
+        })
+        .skip(count)
+        .collect();
+    (!stripped.as_os_str().is_empty()).then_some(stripped)
+}
+
+/// Name of the entry of an input to an archive
+/// Inputs are named after the file, or by their whole path when they come from a list of files
+/// for --files-from, leaving out a leading '/' and '..' like tar does. Listed paths are relative
+/// to the directory of -C, if one is given.
+pub fn input_entry_name(
+    path: &Path,
+    from_list: bool,
+    directory: Option<&Path>,
+) -> Result<PathBuf, io::Error> {
+    let name: PathBuf = match from_list {
+        true => directory
+            .and_then(|directory| path.strip_prefix(directory).ok())
+            .unwrap_or(path)
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect(),
+        false => path.file_name().map(PathBuf::from).unwrap_or_default(),
+    };
+    if name.as_os_str().is_empty() {
+        return Err(io::Error::other(format!(
+            "{} has no name to archive it as",
+            path.display()
+        )));
+    }
+    Ok(name)
+}
+
+/// Check if the path of an archive entry is the one asked for
+/// Leading "./" and trailing '/' are ignored, as archivers differ in whether they add them.
+pub fn entry_matches(entry: &str, wanted: &str) -> bool {
+    let normalize = |path: &str| -> String {
+        path.trim_start_matches("./")
+            .trim_end_matches('/')
+            .to_string()
+    };
+    normalize(entry) == normalize(wanted)
+}
+
+/// How symbolic links are archived and extracted
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Symlinks {
+    /// Store links as links, and restore the ones that stay inside the output
+    #[default]
+    Store,
+    /// Archive the files the links point to
+    Follow,
+    /// Leave links out of archives, and skip them when extracting
+    Skip,
+}
+
+impl Symlinks {
+    pub fn new(common_args: &CommonArgs) -> Symlinks {
+        if common_args.dereference {
+            Symlinks::Follow
+        } else if common_args.skip_symlinks {
+            Symlinks::Skip
+        } else {
+            Symlinks::Store
+        }
+    }
+
+    /// Metadata of a file to archive, of the link itself unless links are followed
+    pub fn metadata(&self, path: &Path) -> Result<std::fs::Metadata, io::Error> {
+        match self {
+            Symlinks::Follow => std::fs::metadata(path),
+            _ => std::fs::symlink_metadata(path),
+        }
+    }
+}
+
+/// Check if a link extracted to `name` would point outside of the output directory
+/// The target is resolved against the directory of the link without touching the filesystem.
+pub fn symlink_escapes(name: &Path, target: &Path) -> bool {
+    let mut depth = name.components().count() as isize - 1;
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => depth -= 1,
+            Component::RootDir | Component::Prefix(_) => return true,
+        }
+        if depth < 0 {
+            return true;
+        }
+    }
+    false
+}
+
+/// Settings for creating byte-identical archives from the same inputs
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Reproducible {
+    /// Latest modification time to store, later times are clamped to it
+    pub mtime: u64,
+}
+
+impl Reproducible {
+    /// Clamp times to SOURCE_DATE_EPOCH if it is set, or zero them otherwise
+    /// See <https://reproducible-builds.org/specs/source-date-epoch/>
+    pub fn from_env() -> Result<Reproducible, io::Error> {
+        let Ok(epoch) = std::env::var("SOURCE_DATE_EPOCH") else {
+            return Ok(Reproducible::default());
+        };
+        match epoch.trim().parse() {
+            Ok(mtime) => Ok(Reproducible { mtime }),
+            Err(_) => Err(io::Error::other(format!(
+                "invalid SOURCE_DATE_EPOCH: {}",
+                epoch
+            ))),
+        }
+    }
+
+    /// The modification time to store for a file
+    pub fn clamp(&self, mtime: u64) -> u64 {
+        mtime.min(self.mtime)
+    }
+
+    /// The permissions to store for a file, only keeping whether it is executable
+    pub fn mode(&self, mode: u32, is_dir: bool) -> u32 {
+        if is_dir || mode & 0o111 != 0 {
+            0o755
+        } else {
+            0o644
+        }
+    }
+}
+
+/// A modification time to store instead of the real ones, for --mtime
+/// It takes precedence over the times of --reproducible.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MtimeOverride {
+    /// Seconds since the Unix epoch
+    pub mtime: u64,
+    /// Only replace the times that are later
+    pub clamp: bool,
+}
+
+impl MtimeOverride {
+    pub fn new(common_args: &CommonArgs) -> Option<MtimeOverride> {
+        common_args.mtime.map(|mtime| MtimeOverride {
+            mtime,
+            clamp: common_args.clamp_mtime,
+        })
+    }
+
+    /// Whether the time of a file is replaced
+    /// Replaced times lose their fractions of a second too.
+    pub fn replaces(&self, mtime: u64) -> bool {
+        !self.clamp || mtime >= self.mtime
+    }
+
+    /// The modification time to store for a file
+    pub fn apply(&self, mtime: u64) -> u64 {
+        if self.replaces(mtime) {
+            self.mtime
+        } else {
+            mtime
+        }
+    }
+}
+
+/// Parse a --mtime, as seconds, a UTC date and time, or the time of a file
+/// Paths are only tried when the value isn't a time, like GNU tar.
+fn parse_mtime(value: &str) -> Result<u64, String> {
+    let trimmed = value.trim();
+    if let Ok(secs) = trimmed.trim_start_matches('@').parse() {
+        return Ok(secs);
+    }
+    if let Some(secs) = parse_date(trimmed) {
+        return u64::try_from(secs).map_err(|_| format!("'{}' is before 1970", value));
+    }
+    match std::fs::metadata(value) {
+        Ok(metadata) => Ok(modified_secs(&metadata)),
+        Err(_) => Err(format!(
+            "'{}' is not a timestamp like '2024-01-31 12:30:00' or an existing file",
+            value
+        )),
+    }
+}
+
+/// Parse a UTC 'YYYY-MM-DD', optionally followed by ' HH:MM[:SS]' or 'THH:MM[:SS][Z]'
+fn parse_date(value: &str) -> Option<i64> {
+    let value = value.strip_suffix('Z').unwrap_or(value);
+    let (date, time) = match value.split_once([' ', 'T']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+    let mut date = date.splitn(3, '-');
+    let year: i64 = date.next()?.parse().ok()?;
+    let month: u32 = date.next()?.parse().ok()?;
+    let day: u32 = date.next()?.parse().ok()?;
+    let (hour, minute, second) = match time {
+        Some(time) => {
+            let mut time = time.splitn(3, ':');
+            let hour: u32 = time.next()?.parse().ok()?;
+            let minute: u32 = time.next()?.parse().ok()?;
+            let second: u32 = time.next().map_or(Some(0), |s| s.parse().ok())?;
+            (hour, minute, second)
+        }
+        None => (0, 0, 0),
+    };
+    if hour >= 24 || minute >= 60 || second >= 60 {
+        return None;
+    }
+    // Dates that don't exist, like February 30th, come back as a different day
+    let secs = unix_from_civil(year, month, day, hour, minute, second);
+    let (y, m, d, ..) = civil_from_unix(secs);
+    ((y, m, d) == (year, month, day)).then_some(secs)
+}
+
+/// Modification time of a file in seconds since the Unix epoch, 0 if it is earlier or unknown
+pub fn modified_secs(metadata: &std::fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// Default number of threads to compress with, the number of logical CPUs
+pub fn default_threads() -> u32 {
+    std::thread::available_parallelism().map_or(1, |n| n.get() as u32)
+}
+
+/// An entry of an archive
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveEntry {
+    /// Path of the entry inside the archive
+    pub path: String,
+    pub is_dir: bool,
+    /// Uncompressed size in bytes
+    pub size: u64,
+    /// Size in the archive, for formats that compress each entry
+    pub compressed_size: Option<u64>,
+    /// Modification time in seconds since the Unix epoch
+    pub mtime: Option<i64>,
+    /// Unix permission bits
+    pub mode: Option<u32>,
+    /// Comment stored with the entry, for formats that have them
+    pub comment: Option<String>,
+}
+
+/// Convert seconds since the Unix epoch to a UTC (year, month, day, hour, minute, second)
+pub fn civil_from_unix(secs: i64) -> (i64, u32, u32, u32, u32, u32) {
+    let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // From http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (
+        year,
+        month as u32,
+        day as u32,
+        (secs / 3600) as u32,
+        (secs % 3600 / 60) as u32,
+        (secs % 60) as u32,
+    )
+}
+
+/// Convert a UTC date and time to seconds since the Unix epoch
+pub fn unix_from_civil(
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+) -> i64 {
+    // From http://howardhinnant.github.io/date_algorithms.html
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (i64::from(month) + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    days * 86400 + i64::from(hour) * 3600 + i64::from(minute) * 60 + i64::from(second)
 }
 
 /// Defines the possible inputs of a compressor
-#[derive(Debug)]
 pub enum CmprssInput {
     /// Path(s) to the input files.
     Path(Vec<PathBuf>),
-    /// Input pipe
-    Pipe(std::io::Stdin),
+    /// Input pipe, usually stdin
+    Pipe(Box<dyn Read + Send>),
+    /// Generic input stream, e.g. a download
+    Reader(CmprssRead),
+}
+
+impl CmprssInput {
+    /// Open a single input as a stream, along with its size if it is known
+    /// Used by the formats that work on a single stream.
+    pub fn into_stream(self) -> Result<(Box<dyn Read + Send>, Option<u64>), io::Error> {
+        match self {
+            CmprssInput::Path(paths) => {
+                if paths.len() > 1 {
+                    return Err(io::Error::other(
+                        "only 1 file can be compressed or extracted at a time",
+                    ));
+                }
+                let Some(path) = paths.first() else {
+                    return Err(io::Error::other("no input specified"));
+                };
+                let file = File::open(path)?;
+                // The size is used for the progress bar, special files don't have one
+                let size = file
+                    .metadata()
+                    .ok()
+                    .filter(|metadata| metadata.is_file())
+                    .map(|metadata| metadata.len());
+                Ok((Box::new(file), size))
+            }
+            CmprssInput::Pipe(pipe) => Ok((pipe, None)),
+            CmprssInput::Reader(reader) => Ok((reader.reader, reader.size)),
+        }
+    }
+
+    /// Original name of a stream input, if it is known
+    pub fn stream_name(&self) -> Option<&str> {
+        match self {
+            CmprssInput::Reader(reader) => reader.name.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for CmprssInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CmprssInput::Path(paths) => f.debug_tuple("Path").field(paths).finish(),
+            CmprssInput::Pipe(_) => write!(f, "Pipe"),
+            CmprssInput::Reader(reader) => f.debug_tuple("Reader").field(reader).finish(),
+        }
+    }
+}
+
+/// An input stream with an optional known size
+pub struct CmprssRead {
+    pub reader: Box<dyn Read + Send>,
+    /// Total size of the stream, if known, used for the progress bar
+    pub size: Option<u64>,
+    /// Original name of the data, if known, used to name outputs and archive entries
+    pub name: Option<String>,
+}
+
+impl fmt::Debug for CmprssRead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CmprssRead {{ size: {:?}, name: {:?} }}",
+            self.size, self.name
+        )
+    }
 }
 
 /// Defines the possible outputs of a compressor
-#[derive(Debug)]
 pub enum CmprssOutput {
     Path(PathBuf),
-    Pipe(std::io::Stdout),
+    /// Output pipe, usually stdout
+    Pipe(Box<dyn Write + Send>),
+    /// Generic output stream, e.g. an upload
+    Writer(CmprssWrite),
+}
+
+impl CmprssOutput {
+    /// Open the output as a stream, creating the file if it is a path
+    pub fn into_stream(self) -> Result<Box<dyn Write + Send>, io::Error> {
+        match self {
+            CmprssOutput::Path(path) => Ok(Box::new(File::create(path)?)),
+            CmprssOutput::Pipe(pipe) => Ok(pipe),
+            CmprssOutput::Writer(writer) => Ok(writer.writer),
+        }
+    }
+}
+
+impl fmt::Debug for CmprssOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CmprssOutput::Path(path) => f.debug_tuple("Path").field(path).finish(),
+            CmprssOutput::Pipe(_) => write!(f, "Pipe"),
+            CmprssOutput::Writer(writer) => f.debug_tuple("Writer").field(writer).finish(),
+        }
+    }
+}
+
+/// A generic output stream
+pub struct CmprssWrite {
+    pub writer: Box<dyn Write + Send>,
+}
+
+impl fmt::Debug for CmprssWrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CmprssWrite")
+    }
+}
+
+/// A background task consuming the output of a job, e.g. uploading it somewhere
+///
+/// The task receives the output through a pipe, and must be finished after the job
+/// so that it knows whether to commit or discard what it received.
+pub struct Upload {
+    /// Tells the task whether the job succeeded
+    success: mpsc::Sender<bool>,
+    handle: JoinHandle<Result<(), io::Error>>,
+}
+
+impl fmt::Debug for Upload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Upload")
+    }
+}
+
+impl Upload {
+    /// Spawn a task that consumes the output written to the returned stream
+    /// The task is given a channel that reports whether the job succeeded once the stream is closed
+    pub fn spawn<F>(task: F) -> Result<(CmprssWrite, Upload), io::Error>
+    where
+        F: FnOnce(io::PipeReader, mpsc::Receiver<bool>) -> Result<(), io::Error> + Send + 'static,
+    {
+        let (reader, writer) = io::pipe()?;
+        let (success, receiver) = mpsc::channel();
+        // Threads may not be available, e.g. on wasm32-wasip1, so report that as an error
+        let handle = std::thread::Builder::new().spawn(move || task(reader, receiver))?;
+        Ok((
+            CmprssWrite {
+                writer: Box::new(writer),
+            },
+            Upload { success, handle },
+        ))
+    }
+
+    /// Report the result of the job and wait for the task to finish
+    pub fn finish(self, success: bool) -> Result<(), io::Error> {
+        // The task may have already exited with an error
+        let _ = self.success.send(success);
+        match self.handle.join() {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::other("upload task panicked")),
+        }
+    }
+}
+
+/// An output written under a temporary name next to its path, and moved into place once complete
+///
+/// A failed or interrupted job then never leaves a truncated file behind, and an existing file
+/// being replaced with `--force` is kept until the new one is finished.
+#[derive(Debug)]
+pub struct AtomicOutput {
+    path: PathBuf,
+    tmp: PathBuf,
+}
+
+impl AtomicOutput {
+    pub fn new(path: &Path) -> AtomicOutput {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let tmp = path.with_file_name(format!(".{}.cmprss-tmp", name));
+        // Removed if the CLI is interrupted before the output is finished
+        crate::signal::register(&tmp);
+        AtomicOutput {
+            path: path.to_path_buf(),
+            tmp,
+        }
+    }
+
+    /// The path to write the output to
+    pub fn tmp(&self) -> &Path {
+        &self.tmp
+    }
+
+    /// Remove anything written to the temporary path
+    /// Tools extracting archives may have created a directory there.
+    pub fn discard(&self) {
+        let _ = match self.tmp.is_dir() {
+            true => std::fs::remove_dir_all(&self.tmp),
+            false => std::fs::remove_file(&self.tmp),
+        };
+    }
+
+    /// Move the output into place if the job succeeded, otherwise remove it
+    pub fn finish<T>(self, result: Result<T, io::Error>) -> Result<T, io::Error> {
+        let result = result.and_then(|value| {
+            std::fs::rename(&self.tmp, &self.path)?;
+            Ok(value)
+        });
+        if result.is_err() {
+            self.discard();
+        }
+        crate::signal::unregister(&self.tmp);
+        result
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn atomic_output() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.path();
+        let path = dir.join("out.gz");
+        std::fs::write(&path, "old")?;
+
+        let output = AtomicOutput::new(&path);
+        assert_eq!(output.tmp(), dir.join(".out.gz.cmprss-tmp"));
+        std::fs::write(output.tmp(), "partial")?;
+        assert!(output
+            .finish(Err::<(), _>(io::Error::other("failed")))
+            .is_err());
+        assert!(!dir.join(".out.gz.cmprss-tmp").exists());
+        assert_eq!(std::fs::read_to_string(&path)?, "old");
+
+        let output = AtomicOutput::new(&path);
+        std::fs::write(output.tmp(), "new")?;
+        output.finish(Ok(()))?;
+        assert_eq!(std::fs::read_to_string(&path)?, "new");
+        assert_eq!(std::fs::read_dir(dir)?.count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn civil_time_conversion() {
+        assert_eq!(civil_from_unix(0), (1970, 1, 1, 0, 0, 0));
+        assert_eq!(civil_from_unix(1369353600), (2013, 5, 24, 0, 0, 0));
+        assert_eq!(civil_from_unix(951782400 + 3723), (2000, 2, 29, 1, 2, 3));
+        for secs in [0, 951782400 + 3723, 1369353600, 4102444799, -86400] {
+            let (y, mo, d, h, mi, s) = civil_from_unix(secs);
+            assert_eq!(unix_from_civil(y, mo, d, h, mi, s), secs);
+        }
+    }
+
+    #[test]
+    fn entry_paths() -> Result<(), io::Error> {
+        let out = Path::new("out");
+        assert_eq!(
+            entry_path(out, Path::new("dir/file.txt"), false)?,
+            out.join("dir/file.txt")
+        );
+        assert_eq!(
+            entry_path(out, Path::new("./file.txt"), false)?,
+            out.join("file.txt")
+        );
+        assert_eq!(
+            entry_path(out, Path::new("/etc/passwd"), false)?,
+            out.join("etc/passwd")
+        );
+        assert!(entry_path(out, Path::new("../file.txt"), false).is_err());
+        assert!(entry_path(out, Path::new("dir/../../file.txt"), false).is_err());
+        assert_eq!(
+            entry_path(out, Path::new("../file.txt"), true)?,
+            out.join("../file.txt")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn stripped_components() {
+        let strip = |name: &str, count| strip_components(Path::new(name), count);
+        assert_eq!(
+            strip("project-1.2.3/src/main.rs", 0),
+            Some("project-1.2.3/src/main.rs".into())
+        );
+        assert_eq!(
+            strip("project-1.2.3/src/main.rs", 1),
+            Some("src/main.rs".into())
+        );
+        assert_eq!(strip("./project-1.2.3/README", 1), Some("README".into()));
+        assert_eq!(strip("/project-1.2.3/README", 1), Some("README".into()));
+        assert_eq!(strip("project-1.2.3/", 1), None);
+        assert_eq!(strip("project-1.2.3/README", 2), None);
+    }
+
+    #[test]
+    fn input_entry_names() -> Result<(), io::Error> {
+        let name = |path: &str, from_list| input_entry_name(Path::new(path), from_list, None);
+        assert_eq!(name("./src/main.rs", false)?, Path::new("main.rs"));
+        assert_eq!(name("./src/main.rs", true)?, Path::new("src/main.rs"));
+        assert_eq!(name("/etc/hosts", true)?, Path::new("etc/hosts"));
+        assert_eq!(name("../lib/a.c", true)?, Path::new("lib/a.c"));
+        assert!(name(".", true).is_err());
+        assert!(name("/", false).is_err());
+        let in_dir = |path: &str| input_entry_name(Path::new(path), true, Some(Path::new("/srv")));
+        assert_eq!(in_dir("/srv/www/index.html")?, Path::new("www/index.html"));
+        assert_eq!(in_dir("/opt/app")?, Path::new("opt/app"));
+        Ok(())
+    }
+
+    #[test]
+    fn entry_matching() {
+        assert!(entry_matches("dir/file.txt", "dir/file.txt"));
+        assert!(entry_matches("./dir/file.txt", "dir/file.txt"));
+        assert!(entry_matches("dir/", "dir"));
+        assert!(!entry_matches("dir/file.txt", "file.txt"));
+    }
+
+    #[test]
+    fn escaping_symlinks() {
+        assert!(!symlink_escapes(Path::new("dir/link"), Path::new("file")));
+        assert!(!symlink_escapes(
+            Path::new("dir/link"),
+            Path::new("../file")
+        ));
+        assert!(!symlink_escapes(
+            Path::new("a/b/link"),
+            Path::new("../../a/./c")
+        ));
+        assert!(symlink_escapes(
+            Path::new("dir/link"),
+            Path::new("../../file")
+        ));
+        assert!(symlink_escapes(Path::new("link"), Path::new("../dir/file")));
+        assert!(symlink_escapes(Path::new("link"), Path::new("sub/../../x")));
+        assert!(symlink_escapes(
+            Path::new("dir/link"),
+            Path::new("/etc/passwd")
+        ));
+    }
+
+    #[test]
+    fn mtime_overrides() {
+        assert_eq!(parse_mtime("1700000000"), Ok(1700000000));
+        assert_eq!(parse_mtime("@1700000000"), Ok(1700000000));
+        assert_eq!(parse_mtime("2013-05-24"), Ok(1369353600));
+        assert_eq!(parse_mtime("2000-02-29 01:02:03"), Ok(951782400 + 3723));
+        assert_eq!(parse_mtime("2000-02-29T01:02:03Z"), Ok(951782400 + 3723));
+        assert_eq!(parse_mtime("2000-02-29 01:02"), Ok(951782400 + 3720));
+        assert!(parse_mtime("2001-02-29").is_err());
+        assert!(parse_mtime("1969-12-31").is_err());
+        assert!(parse_mtime("yesterday").is_err());
+
+        let file = assert_fs::NamedTempFile::new("file").unwrap();
+        std::fs::write(file.path(), "").unwrap();
+        let metadata = std::fs::metadata(file.path()).unwrap();
+        assert_eq!(
+            parse_mtime(file.path().to_str().unwrap()),
+            Ok(modified_secs(&metadata))
+        );
+
+        let set = MtimeOverride {
+            mtime: 1000,
+            clamp: false,
+        };
+        assert_eq!(set.apply(500), 1000);
+        assert_eq!(set.apply(2000), 1000);
+        let clamp = MtimeOverride { clamp: true, ..set };
+        assert_eq!(clamp.apply(500), 500);
+        assert_eq!(clamp.apply(2000), 1000);
+        assert!(clamp.replaces(1000));
+        assert!(!clamp.replaces(999));
+    }
+
+    #[test]
+    fn reproducible_metadata() {
+        let reproducible = Reproducible { mtime: 1000 };
+        assert_eq!(reproducible.clamp(500), 500);
+        assert_eq!(reproducible.clamp(2000), 1000);
+        assert_eq!(Reproducible::default().clamp(2000), 0);
+        assert_eq!(reproducible.mode(0o600, false), 0o644);
+        assert_eq!(reproducible.mode(0o700, false), 0o755);
+        assert_eq!(reproducible.mode(0o700, true), 0o755);
+    }
+
+    #[test]
+    fn long_paths() -> Result<(), io::Error> {
+        let dir = std::env::temp_dir();
+        let path = long_path(&dir)?;
+        if cfg!(windows) {
+            assert!(path.to_string_lossy().starts_with(r"\\?\"));
+        } else {
+            assert_eq!(path, dir);
+        }
+        Ok(())
+    }
+
     #[test]
     fn compression_level_parsing() {
         assert_eq!(CompressionLevel::from_str("0").unwrap().level, 0);
@@ -186,4 +1269,13 @@ mod tests {
         assert!(CompressionLevel::from_str("10").is_err());
         assert!(CompressionLevel::from_str("foo").is_err());
     }
+
+    #[test]
+    fn json_strings() {
+        assert_eq!(json_string("dir/file.txt"), r#""dir/file.txt""#);
+        assert_eq!(
+            json_string("a \"quote\"\\\n\u{1}"),
+            r#""a \"quote\"\\\n\u0001""#
+        );
+    }
 }
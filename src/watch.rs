@@ -0,0 +1,407 @@
+//! Compressing the files written to a directory as they appear, for `cmprss watch`
+//!
+//! Linux is notified of the changes with inotify, other platforms look for them every
+//! [`POLL_INTERVAL`]. A file is compressed once it hasn't changed for the debounce time, so
+//! that files still being written aren't picked up half done.
+
+use crate::job::get_compressor_from_name;
+use crate::log::{self, VerbosityArgs};
+use crate::progress::ProgressDisplay;
+use crate::signal;
+use crate::utils::*;
+use clap::Args;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// The longest wait for changes, so that a request to stop is noticed quickly
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    /// Directory to watch for new files
+    pub dir: PathBuf,
+
+    /// Format to compress the files with, like zstd, gzip or tar.xz
+    #[arg(long, value_name = "FORMAT")]
+    pub format: String,
+
+    /// Level to compress with, in the range of the format
+    #[arg(short, long)]
+    pub level: Option<i32>,
+
+    /// Directory to write the compressed files to, instead of next to the originals
+    #[arg(long, value_name = "DIR")]
+    pub output_dir: Option<PathBuf>,
+
+    /// Remove each file once it has been compressed
+    #[arg(long)]
+    pub rm_source: bool,
+
+    /// Milliseconds a file has to stay unchanged before it is compressed
+    #[arg(long, value_name = "MS", default_value = "1000")]
+    pub debounce: u64,
+
+    #[clap(flatten)]
+    pub overwrite_args: OverwriteArgs,
+
+    #[clap(flatten)]
+    pub verbosity_args: VerbosityArgs,
+}
+
+/// The regular files directly in a directory, sorted by name
+fn list_files(dir: &Path) -> Result<Vec<PathBuf>, io::Error> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Changes to the files of a directory, reported by inotify
+#[cfg(target_os = "linux")]
+struct Events {
+    dir: PathBuf,
+    fd: std::os::fd::OwnedFd,
+}
+
+#[cfg(target_os = "linux")]
+impl Events {
+    fn new(dir: &Path) -> Result<Events, io::Error> {
+        use std::os::fd::{AsRawFd, FromRawFd};
+        use std::os::unix::ffi::OsStrExt;
+
+        // SAFETY: inotify_init1 takes no pointers, and only returns a new descriptor or -1
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the descriptor was just opened and checked above, and nothing else owns it
+        let fd = unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) };
+        let path = std::ffi::CString::new(dir.as_os_str().as_bytes())?;
+        let mask = libc::IN_CLOSE_WRITE
+            | libc::IN_MODIFY
+            | libc::IN_MOVED_TO
+            | libc::IN_DELETE_SELF
+            | libc::IN_MOVE_SELF
+            | libc::IN_ONLYDIR;
+        // SAFETY: the descriptor is open for as long as `fd`, and `path` is a valid C string
+        // that lives past the call
+        if unsafe { libc::inotify_add_watch(fd.as_raw_fd(), path.as_ptr(), mask) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Events {
+            dir: dir.to_path_buf(),
+            fd,
+        })
+    }
+
+    /// Wait up to the timeout for changes, returning the paths of the changed files
+    fn wait(&mut self, timeout: Duration) -> Result<Vec<PathBuf>, io::Error> {
+        use std::os::fd::AsRawFd;
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut poll = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `poll` is a single valid pollfd, matching the count of 1, and the descriptor is
+        // open for as long as `self`
+        let ready = unsafe { libc::poll(&mut poll, 1, timeout.as_millis() as libc::c_int) };
+        if ready <= 0 {
+            let error = io::Error::last_os_error();
+            return match ready == 0 || error.kind() == io::ErrorKind::Interrupted {
+                true => Ok(Vec::new()),
+                false => Err(error),
+            };
+        }
+        let mut buffer = [0u8; 4096];
+        // SAFETY: the pointer and length are those of `buffer`, which the kernel writes at most
+        // `buffer.len()` bytes to, and the descriptor is open for as long as `self`
+        let read = unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+            )
+        };
+        if read < 0 {
+            let error = io::Error::last_os_error();
+            return match error.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => Ok(Vec::new()),
+                _ => Err(error),
+            };
+        }
+
+        // Each event is a struct inotify_event followed by the name of the file
+        let field = |at: usize| u32::from_ne_bytes(buffer[at..at + 4].try_into().unwrap());
+        let mut paths = Vec::new();
+        let mut offset = 0;
+        while offset + 16 <= read as usize {
+            let mask = field(offset + 4);
+            let len = field(offset + 12) as usize;
+            let name = &buffer[offset + 16..offset + 16 + len];
+            let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(len)];
+            offset += 16 + len;
+            if mask & (libc::IN_DELETE_SELF | libc::IN_MOVE_SELF | libc::IN_IGNORED) != 0 {
+                return Err(io::Error::other(format!(
+                    "{} was removed or moved",
+                    self.dir.display()
+                )));
+            }
+            if mask & libc::IN_Q_OVERFLOW != 0 {
+                // Events were dropped, so look at every file again
+                paths.extend(list_files(&self.dir)?);
+            } else if !name.is_empty() {
+                paths.push(self.dir.join(std::ffi::OsStr::from_bytes(name)));
+            }
+        }
+        Ok(paths)
+    }
+}
+
+/// Changes to the files of a directory, found by comparing their sizes and times
+#[cfg(not(target_os = "linux"))]
+struct Events {
+    dir: PathBuf,
+    seen: HashMap<PathBuf, (u64, Option<std::time::SystemTime>)>,
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Events {
+    fn new(dir: &Path) -> Result<Events, io::Error> {
+        Ok(Events {
+            dir: dir.to_path_buf(),
+            seen: Self::snapshot(dir)?,
+        })
+    }
+
+    fn snapshot(
+        dir: &Path,
+    ) -> Result<HashMap<PathBuf, (u64, Option<std::time::SystemTime>)>, io::Error> {
+        let mut files = HashMap::new();
+        for path in list_files(dir)? {
+            if let Ok(metadata) = fs::metadata(&path) {
+                files.insert(path, (metadata.len(), metadata.modified().ok()));
+            }
+        }
+        Ok(files)
+    }
+
+    /// Wait for the timeout, returning the paths of the files that changed since the last look
+    fn wait(&mut self, timeout: Duration) -> Result<Vec<PathBuf>, io::Error> {
+        std::thread::sleep(timeout);
+        let current = Self::snapshot(&self.dir)?;
+        let changed = current
+            .iter()
+            .filter(|(path, state)| self.seen.get(*path) != Some(state))
+            .map(|(path, _)| path.clone())
+            .collect();
+        self.seen = current;
+        Ok(changed)
+    }
+}
+
+/// Compresses the files of the watched directory
+struct Watcher<'a> {
+    args: &'a WatchArgs,
+    compressor: Box<dyn Compressor>,
+}
+
+impl Watcher<'_> {
+    /// Where the compressed file goes
+    fn output_path(&self, path: &Path) -> PathBuf {
+        let name = self.compressor.default_compressed_filename(path);
+        match &self.args.output_dir {
+            Some(dir) => dir.join(name),
+            None => path.with_file_name(name),
+        }
+    }
+
+    /// Whether a changed file should be compressed
+    /// Hidden files are skipped, which include the temporary outputs of cmprss and the
+    /// partial files of tools like rsync, as are the outputs written next to the originals.
+    fn wants(&self, path: &Path) -> bool {
+        let hidden = path
+            .file_name()
+            .is_none_or(|name| name.to_string_lossy().starts_with('.'));
+        !hidden && !self.compressor.is_archive(path)
+    }
+
+    fn compress(&self, path: &Path) -> Result<(), io::Error> {
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_file() => {}
+            // Removed or replaced with something else since it changed
+            Ok(_) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let output_path = self.output_path(path);
+        if !self.args.overwrite_args.force && output_path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "output path {} already exists, use --force to overwrite it",
+                    output_path.display()
+                ),
+            ));
+        }
+        let output = AtomicOutput::new(&output_path);
+        let result = self.compressor.compress(
+            CmprssInput::Path(vec![path.to_path_buf()]),
+            CmprssOutput::Path(output.tmp().to_path_buf()),
+        );
+        output.finish(result)?;
+        crate::info!("compressed {} to {}", path.display(), output_path.display());
+        if self.args.rm_source {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compress the files of a directory as they are written, until interrupted
+/// Files already in the directory without an output are compressed first. The first SIGINT
+/// or SIGTERM lets the current file finish before stopping.
+pub fn watch(args: &WatchArgs) -> Result<(), io::Error> {
+    log::set_verbosity(args.verbosity_args.verbosity());
+    let Some(mut compressor) = get_compressor_from_name(&args.format) else {
+        return Err(io::Error::other(format!("unknown format {}", args.format)));
+    };
+    compressor.set_threads(default_threads());
+    compressor.set_progress(ProgressDisplay::Off);
+    if let Some(level) = args.level {
+        compressor.set_level(level);
+    }
+    if !args.dir.is_dir() {
+        return Err(io::Error::other(format!(
+            "{} is not a directory",
+            args.dir.display()
+        )));
+    }
+    if let Some(dir) = &args.output_dir {
+        fs::create_dir_all(dir)?;
+    }
+    let watcher = Watcher { args, compressor };
+
+    signal::stop_gracefully();
+    let mut events = Events::new(&args.dir)?;
+    let debounce = Duration::from_millis(args.debounce);
+    let start = Instant::now();
+    let mut pending: HashMap<PathBuf, Instant> = list_files(&args.dir)?
+        .into_iter()
+        .filter(|path| watcher.wants(path) && !watcher.output_path(path).exists())
+        .map(|path| (path, start))
+        .collect();
+    crate::info!(
+        "watching {} for files to compress with {}",
+        args.dir.display(),
+        watcher.compressor.name()
+    );
+
+    while !signal::stop_requested() {
+        let now = Instant::now();
+        let mut settled: Vec<PathBuf> = pending
+            .iter()
+            .filter(|(_, changed)| now.duration_since(**changed) >= debounce)
+            .map(|(path, _)| path.clone())
+            .collect();
+        settled.sort();
+        for path in settled {
+            if signal::stop_requested() {
+                break;
+            }
+            pending.remove(&path);
+            match watcher.compress(&path) {
+                Err(e)
+                    if args.overwrite_args.no_clobber
+                        && e.kind() == io::ErrorKind::AlreadyExists =>
+                {
+                    crate::info!("skipping {}: {}", path.display(), e);
+                }
+                Err(e) => crate::warn!("skipping {}: {}", path.display(), e),
+                Ok(()) => {}
+            }
+        }
+
+        let now = Instant::now();
+        let timeout = pending
+            .values()
+            .map(|changed| (*changed + debounce).saturating_duration_since(now))
+            .min()
+            .map_or(POLL_INTERVAL, |wait| wait.min(POLL_INTERVAL));
+        for path in events.wait(timeout)? {
+            if watcher.wants(&path) {
+                pending.insert(path, Instant::now());
+            }
+        }
+    }
+    crate::info!("stopped watching {}", args.dir.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+
+    /// Wait for a change to the path, giving up after a few seconds
+    fn changed(events: &mut Events, path: &Path) -> Result<bool, io::Error> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if events.wait(POLL_INTERVAL)?.iter().any(|p| p == path) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    #[test]
+    fn events() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = assert_fs::TempDir::new()?;
+        let mut events = Events::new(dir.path())?;
+        let file = dir.child("new.log");
+        file.write_str("a line")?;
+        assert!(changed(&mut events, file.path())?);
+
+        // Files moved into the directory are seen too
+        let outside = assert_fs::TempDir::new()?;
+        let moved = outside.child("moved.log");
+        moved.write_str("another line")?;
+        fs::rename(moved.path(), dir.child("moved.log").path())?;
+        assert!(changed(&mut events, &dir.child("moved.log"))?);
+        Ok(())
+    }
+
+    #[test]
+    fn wanted_files() {
+        let args = WatchArgs {
+            dir: PathBuf::from("incoming"),
+            format: "gzip".to_string(),
+            level: None,
+            output_dir: Some(PathBuf::from("out")),
+            rm_source: false,
+            debounce: 1000,
+            overwrite_args: OverwriteArgs::default(),
+            verbosity_args: VerbosityArgs::default(),
+        };
+        let watcher = Watcher {
+            args: &args,
+            compressor: get_compressor_from_name(&args.format).unwrap(),
+        };
+        assert!(watcher.wants(Path::new("incoming/app.log")));
+        assert!(!watcher.wants(Path::new("incoming/app.log.gz")));
+        assert!(!watcher.wants(Path::new("incoming/.app.log.gz.cmprss-tmp")));
+        assert_eq!(
+            watcher.output_path(Path::new("incoming/app.log")),
+            Path::new("out/app.log.gz")
+        );
+    }
+}
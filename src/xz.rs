@@ -1,12 +1,17 @@
+//! xz compression backend
+
 use crate::{
-    progress::{progress_bar, ProgressArgs},
+    limits::OutputLimits,
+    progress::{
+        copy_with_progress, progress_bar, ProgressArgs, ProgressDisplay, ProgressHooks,
+        ProgressObserver,
+    },
     utils::*,
 };
-use clap::Args;
-use std::{
-    fs::File,
-    io::{self, Read, Write},
-};
+use clap::{Args, ValueEnum};
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use xz2::stream::{Check, MtStreamBuilder, Stream};
 use xz2::write::{XzDecoder, XzEncoder};
 
 #[derive(Args, Debug)]
@@ -19,18 +24,57 @@ pub struct XzArgs {
 
     #[clap(flatten)]
     pub level_args: LevelArgs,
+
+    /// Integrity check of the uncompressed data, verified when extracting
+    #[arg(long, value_enum, default_value = "crc64")]
+    pub check: XzCheck,
+
+    /// Compress harder at the same level and memory usage, which is much slower
+    #[arg(long)]
+    pub extreme: bool,
+}
+
+/// The integrity checks of the xz format
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum XzCheck {
+    None,
+    Crc32,
+    Crc64,
+    Sha256,
 }
 
+impl From<XzCheck> for Check {
+    fn from(check: XzCheck) -> Check {
+        match check {
+            XzCheck::None => Check::None,
+            XzCheck::Crc32 => Check::Crc32,
+            XzCheck::Crc64 => Check::Crc64,
+            XzCheck::Sha256 => Check::Sha256,
+        }
+    }
+}
+
+/// Flag of liblzma for the extreme variant of a preset
+const PRESET_EXTREME: u32 = 1 << 31;
+
 pub struct Xz {
     pub level: u32,
+    pub check: XzCheck,
+    pub extreme: bool,
+    pub threads: u32,
     pub progress_args: ProgressArgs,
+    pub hooks: ProgressHooks,
 }
 
 impl Default for Xz {
     fn default() -> Self {
         Xz {
             level: 6,
+            check: XzCheck::Crc64,
+            extreme: false,
+            threads: default_threads(),
             progress_args: ProgressArgs::default(),
+            hooks: ProgressHooks::default(),
         }
     }
 }
@@ -39,11 +83,35 @@ impl Xz {
     pub fn new(args: &XzArgs) -> Xz {
         Xz {
             level: args.level_args.level.level,
+            check: args.check,
+            extreme: args.extreme,
+            threads: args.common_args.threads.unwrap_or_else(default_threads),
             progress_args: args.progress_args,
+            hooks: ProgressHooks::default(),
         }
     }
 }
 
+impl Xz {
+    /// Create an encoder, using the multithreaded encoder of liblzma for more than 1 thread
+    fn encoder<W: Write>(&self, writer: W) -> Result<XzEncoder<W>, io::Error> {
+        let preset = match self.extreme {
+            true => self.level | PRESET_EXTREME,
+            false => self.level,
+        };
+        if self.threads <= 1 {
+            let stream = Stream::new_easy_encoder(preset, self.check.into())?;
+            return Ok(XzEncoder::new_stream(writer, stream));
+        }
+        let stream = MtStreamBuilder::new()
+            .preset(preset)
+            .threads(self.threads)
+            .check(self.check.into())
+            .encoder()?;
+        Ok(XzEncoder::new_stream(writer, stream))
+    }
+}
+
 impl Compressor for Xz {
     /// The standard extension for the xz format.
     fn extension(&self) -> &str {
@@ -55,91 +123,103 @@ impl Compressor for Xz {
         "xz"
     }
 
+    fn is_stream(&self) -> bool {
+        true
+    }
+
+    fn set_limits(&mut self, limits: OutputLimits) {
+        self.hooks.limits = limits;
+    }
+
+    fn set_observer(&mut self, observer: Arc<dyn ProgressObserver>) {
+        self.hooks.observer = Some(observer);
+    }
+
+    fn set_level(&mut self, level: i32) {
+        self.level = level as u32;
+    }
+
+    fn level(&self) -> Option<i32> {
+        Some(self.level as i32)
+    }
+
+    fn set_progress(&mut self, progress: ProgressDisplay) {
+        self.progress_args.progress = progress;
+    }
+
+    fn progress(&self) -> ProgressDisplay {
+        self.progress_args.progress
+    }
+
+    fn set_threads(&mut self, threads: u32) {
+        self.threads = threads;
+    }
+
+    fn threads(&self) -> Option<u32> {
+        Some(self.threads)
+    }
+
     fn compress(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
-        let mut file_size = None;
-        let mut input_stream = match input {
-            CmprssInput::Path(paths) => {
-                if paths.len() > 1 {
-                    return cmprss_error("only 1 file can be compressed at a time");
-                }
-                let file = Box::new(File::open(paths[0].as_path())?);
-                // Get the file size for the progress bar
-                if let Ok(metadata) = file.metadata() {
-                    file_size = Some(metadata.len());
-                }
-                file
-            }
-            CmprssInput::Pipe(pipe) => Box::new(pipe) as Box<dyn Read + Send>,
-        };
-        let output_stream: Box<dyn Write + Send> = match &output {
-            CmprssOutput::Path(path) => Box::new(File::create(path)?),
-            CmprssOutput::Pipe(pipe) => Box::new(pipe) as Box<dyn Write + Send>,
-        };
-        let mut encoder = XzEncoder::new(output_stream, self.level);
-        let mut bar = progress_bar(file_size, self.progress_args.progress, &output);
-        if let Some(progress) = &mut bar {
-            // Copy the input to the output in chunks so that we can update the progress bar
-            let mut buffer = vec![0; self.progress_args.chunk_size.size_in_bytes];
-            loop {
-                let bytes_read = input_stream.read(&mut buffer)?;
-                if bytes_read == 0 {
-                    break;
-                }
-                encoder.write_all(&buffer[..bytes_read])?;
-                progress.update_input(encoder.total_in());
-                progress.update_output(encoder.total_out());
-            }
-            encoder.flush()?;
-            progress.update_output(encoder.total_out());
-            progress.finish();
-        } else {
-            io::copy(&mut input_stream, &mut encoder)?;
-        }
+        let (mut input_stream, file_size) = input.into_stream()?;
+        let bar = progress_bar(file_size, self.progress_args.progress, &output, &self.hooks);
+        let output_stream = output.into_stream()?;
+        let mut encoder = self.encoder(output_stream)?;
+        copy_with_progress(
+            &mut input_stream,
+            &mut encoder,
+            self.progress_args.chunk_size.size_in_bytes,
+            &self.hooks,
+            bar.as_ref(),
+            |e| e.total_out(),
+        )?;
+        encoder.finish()?;
         Ok(())
     }
 
     fn extract(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
-        let mut file_size = None;
-        let mut input_stream = match input {
-            CmprssInput::Path(paths) => {
-                if paths.len() > 1 {
-                    return cmprss_error("only 1 file can be extracted at a time");
-                }
-                let file = Box::new(File::open(paths[0].as_path())?);
-                // Get the file size for the progress bar
-                if let Ok(metadata) = file.metadata() {
-                    file_size = Some(metadata.len());
-                }
-                file
-            }
-            CmprssInput::Pipe(pipe) => Box::new(pipe) as Box<dyn Read + Send>,
-        };
-        let output_stream: Box<dyn Write + Send> = match &output {
-            CmprssOutput::Path(path) => Box::new(File::create(path)?),
-            CmprssOutput::Pipe(pipe) => Box::new(pipe) as Box<dyn Write + Send>,
-        };
-        let mut decoder = XzDecoder::new(output_stream);
-        let mut bar = progress_bar(file_size, self.progress_args.progress, &output);
-        if let Some(progress) = &mut bar {
-            // Copy the input to the output in chunks so that we can update the progress bar
-            let mut buffer = vec![0; self.progress_args.chunk_size.size_in_bytes];
-            loop {
-                let bytes_read = input_stream.read(&mut buffer)?;
-                if bytes_read == 0 {
-                    break;
-                }
-                decoder.write_all(&buffer[..bytes_read])?;
-                progress.update_input(decoder.total_in());
-                progress.update_output(decoder.total_out());
-            }
-            decoder.flush()?;
-            progress.update_output(decoder.total_out());
-            progress.finish();
-        } else {
-            io::copy(&mut input_stream, &mut decoder)?;
-        }
+        let (mut input_stream, file_size) = input.into_stream()?;
+        let bar = progress_bar(file_size, self.progress_args.progress, &output, &self.hooks);
+        let output_stream = self.hooks.limits.writer(output.into_stream()?, file_size);
+        // Like xz, every stream of concatenated xz files is extracted
+        let mut decoder = XzDecoder::new_multi_decoder(output_stream);
+        copy_with_progress(
+            &mut input_stream,
+            &mut decoder,
+            self.progress_args.chunk_size.size_in_bytes,
+            &self.hooks,
+            bar.as_ref(),
+            |d| d.total_out(),
+        )?;
+        decoder.finish()?;
         Ok(())
     }
+
+    /// Wrap a writer with an xz encoder
+    fn wrap_writer<'a>(
+        &self,
+        writer: Box<dyn Write + Send + 'a>,
+    ) -> Result<Box<dyn Write + Send + 'a>, io::Error> {
+        Ok(Box::new(self.encoder(writer)?))
+    }
+
+    /// Wrap a reader with an xz decoder
+    fn wrap_reader<'a>(
+        &self,
+        reader: Box<dyn Read + Send + 'a>,
+    ) -> Result<Box<dyn Read + Send + 'a>, io::Error> {
+        Ok(Box::new(xz2::read::XzDecoder::new_multi_decoder(reader)))
+    }
+
+    /// Compress a stream, finishing the xz stream explicitly to catch errors
+    fn compress_stream(
+        &self,
+        reader: &mut (dyn Read + Send),
+        writer: &mut (dyn Write + Send),
+    ) -> Result<(), io::Error> {
+        let mut encoder = self.encoder(writer)?;
+        io::copy(reader, &mut encoder)?;
+        encoder.finish()?.flush()
+    }
 }
 
 #[cfg(test)]
@@ -176,4 +256,99 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn multithreaded_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Xz {
+            threads: 4,
+            ..Xz::default()
+        };
+        // Enough data to be split between the threads
+        let data: Vec<u8> = (0..4 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+
+        let mut compressed = Vec::new();
+        compressor.compress_stream(&mut &data[..], &mut compressed)?;
+
+        let mut extracted = Vec::new();
+        compressor
+            .wrap_reader(Box::new(&compressed[..]))?
+            .read_to_end(&mut extracted)?;
+        assert_eq!(extracted, data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stream_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Xz::default();
+        let data = b"garbage data for testing";
+
+        let mut compressed = Vec::new();
+        compressor.compress_stream(&mut &data[..], &mut compressed)?;
+        assert_ne!(compressed, data);
+
+        let mut extracted = Vec::new();
+        compressor
+            .wrap_reader(Box::new(&compressed[..]))?
+            .read_to_end(&mut extracted)?;
+        assert_eq!(extracted, data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn checks_and_extreme() -> Result<(), Box<dyn std::error::Error>> {
+        let data = b"garbage data for testing".repeat(100);
+        // The check id is the low nibble of the second stream flags byte
+        for (check, id) in [
+            (XzCheck::None, 0x00),
+            (XzCheck::Crc32, 0x01),
+            (XzCheck::Crc64, 0x04),
+            (XzCheck::Sha256, 0x0a),
+        ] {
+            for threads in [1, 2] {
+                let compressor = Xz {
+                    check,
+                    extreme: true,
+                    threads,
+                    ..Xz::default()
+                };
+                let mut compressed = Vec::new();
+                compressor.compress_stream(&mut &data[..], &mut compressed)?;
+                assert_eq!(compressed[7], id);
+
+                let mut extracted = Vec::new();
+                compressor
+                    .wrap_reader(Box::new(&compressed[..]))?
+                    .read_to_end(&mut extracted)?;
+                assert_eq!(extracted, data);
+            }
+        }
+        Ok(())
+    }
+
+    /// Every member of concatenated files is extracted, like `cat a.xz b.xz | unxz`
+    #[test]
+    fn concatenated() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Xz::default();
+        let mut compressed = Vec::new();
+        compressor.compress_stream(&mut &b"first "[..], &mut compressed)?;
+        compressor.compress_stream(&mut &b"second"[..], &mut compressed)?;
+
+        let mut extracted = Vec::new();
+        compressor
+            .wrap_reader(Box::new(&compressed[..]))?
+            .read_to_end(&mut extracted)?;
+        assert_eq!(extracted, b"first second");
+
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("archive.xz");
+        archive.write_binary(&compressed)?;
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(working_dir.child("test.txt").path().to_path_buf()),
+        )?;
+        working_dir.child("test.txt").assert("first second");
+        Ok(())
+    }
 }
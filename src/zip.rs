@@ -0,0 +1,1732 @@
+//! zip archive backend
+
+use crate::exclude::Exclude;
+use crate::limits::OutputLimits;
+use crate::progress::{
+    progress_bar, ArchiveProgress, ProgressArgs, ProgressDisplay, ProgressHooks, ProgressObserver,
+};
+use crate::transform::{RenamedFiles, Transform};
+use crate::utils::*;
+use clap::{Args, ValueEnum};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Cursor, IsTerminal, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use zip::result::ZipError;
+use zip::write::SimpleFileOptions;
+use zip::{AesMode, CompressionMethod, DateTime, ZipArchive, ZipWriter};
+
+/// Files larger than this need Zip64 records for their sizes
+const ZIP64_THRESHOLD: u64 = u32::MAX as u64;
+
+#[derive(Args, Debug)]
+pub struct ZipArgs {
+    #[clap(flatten)]
+    pub common_args: CommonArgs,
+
+    #[clap(flatten)]
+    pub progress_args: ProgressArgs,
+
+    #[clap(flatten)]
+    pub level_args: LevelArgs,
+
+    /// Password to encrypt the archive with AES-256, or to extract an encrypted archive.
+    /// Extracting supports both AES and the legacy ZipCrypto encryption.
+    #[arg(long, conflicts_with_all = ["password_file", "encrypt"])]
+    pub password: Option<String>,
+
+    /// Read the password from the first line of a file
+    #[arg(long, conflicts_with = "encrypt")]
+    pub password_file: Option<PathBuf>,
+
+    /// Prompt for a password to encrypt the archive with
+    #[arg(long)]
+    pub encrypt: bool,
+
+    /// Don't restore the permissions and modification times of the files when extracting
+    #[arg(long)]
+    pub no_preserve_permissions: bool,
+
+    /// Update an existing archive instead of creating a new one.
+    /// Files newer than their entry replace it, and files not in the archive are added.
+    #[arg(long)]
+    pub update: bool,
+
+    /// Delete the entries matching a glob pattern from an existing archive, can be repeated.
+    /// Patterns match like --exclude, and deleting a directory deletes everything in it.
+    #[arg(long, value_name = "PATTERN")]
+    pub delete: Vec<String>,
+
+    /// Compression method of the entries.
+    /// Files that are already compressed, like jpg, mp4 or zst files, are stored regardless.
+    #[arg(long, value_enum, default_value = "deflate")]
+    pub method: ZipMethod,
+
+    /// Compression method for the files with an extension, like 'log=zstd' or 'png=deflate'.
+    /// Overrides --method and the storing of compressed files, can be repeated.
+    #[arg(long, value_name = "EXT=METHOD", value_parser = parse_method_for)]
+    pub method_for: Vec<(String, ZipMethod)>,
+
+    /// Comment to store in the archive, shown when listing it.
+    /// With --update or --delete it replaces the comment of the archive.
+    #[arg(long, value_name = "TEXT")]
+    pub comment: Option<String>,
+}
+
+/// The compression methods of zip entries
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ZipMethod {
+    Store,
+    Deflate,
+    #[cfg(feature = "bzip2")]
+    Bzip2,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl ZipMethod {
+    /// The method and level of an entry, with the level clamped to the range of the method
+    fn options(self, level: u32) -> (CompressionMethod, Option<i64>) {
+        let level = i64::from(level);
+        match self {
+            ZipMethod::Store => (CompressionMethod::Stored, None),
+            ZipMethod::Deflate => (CompressionMethod::Deflated, Some(level)),
+            #[cfg(feature = "bzip2")]
+            ZipMethod::Bzip2 => (CompressionMethod::Bzip2, Some(level.max(1))),
+            #[cfg(feature = "zstd")]
+            ZipMethod::Zstd => (CompressionMethod::Zstd, Some(level.max(1))),
+        }
+    }
+}
+
+/// Parse an 'EXT=METHOD' pair of --method-for
+fn parse_method_for(value: &str) -> Result<(String, ZipMethod), String> {
+    let (extension, method) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected EXT=METHOD, got '{}'", value))?;
+    let method = ZipMethod::from_str(method, true)?;
+    let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+    Ok((extension, method))
+}
+
+/// Extensions of files that are already compressed, which are stored instead of compressed again
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "apk", "avi", "br", "bz2", "docx", "flac", "gif", "gz", "heic", "jar", "jpeg", "jpg",
+    "lz4", "lzo", "m4a", "mkv", "mov", "mp3", "mp4", "odt", "ogg", "png", "pptx", "rar", "tbz2",
+    "tgz", "txz", "webm", "webp", "xlsx", "xz", "z", "zip", "zst",
+];
+
+/// Update or delete the entries of an existing archive, for --update and --delete
+/// The last file given is the archive, and the files before it are added to it.
+pub fn update(args: &ZipArgs) -> Result<(), io::Error> {
+    let common_args = &args.common_args;
+    let mut files: Vec<PathBuf> = common_args
+        .input
+        .iter()
+        .chain(&common_args.io_list)
+        .chain(&common_args.output)
+        .map(PathBuf::from)
+        .collect();
+    let Some(archive) = files.pop() else {
+        return cmprss_error("no zip archive to update");
+    };
+    if !archive.is_file() {
+        return Err(io::Error::other(format!(
+            "{} does not exist, create it without --update or --delete",
+            archive.display()
+        )));
+    }
+    if args.update && files.is_empty() {
+        return cmprss_error("no files to update the archive with");
+    }
+    if !args.update && !files.is_empty() {
+        return cmprss_error("use --update to add files to the archive");
+    }
+    let mut zip = Zip::new(args)?;
+    zip.set_exclude(Exclude::new(&common_args.exclude_args)?);
+    zip.set_transform(Transform::new(&common_args.transform)?.junk_paths(common_args.junk_paths));
+    if common_args.reproducible {
+        zip.set_reproducible(Reproducible::from_env()?);
+    }
+    if let Some(mtime) = MtimeOverride::new(common_args) {
+        zip.set_mtime(mtime);
+    }
+    zip.update_archive(&archive, &files, &Exclude::from_patterns(&args.delete)?)
+}
+
+pub struct Zip {
+    pub compression_level: u32,
+    /// Extract entries with absolute paths or '..' outside of the output directory
+    pub allow_unsafe_paths: bool,
+    /// Leading directories to remove from the paths of extracted entries
+    pub strip_components: usize,
+    /// Substitutions renaming the entries when archiving and extracting
+    pub transform: Transform,
+    /// Archive the inputs as they are listed, named by their paths and without the contents
+    /// of directories
+    pub from_list: bool,
+    /// Directory of -C that the listed inputs are relative to
+    pub directory: Option<PathBuf>,
+    /// How symbolic links are archived and extracted
+    pub symlinks: Symlinks,
+    /// Files to leave out when archiving directories, and entries to skip when extracting
+    pub exclude: Exclude,
+    /// Leave out the metadata that differs between runs
+    pub reproducible: Option<Reproducible>,
+    /// Modification time to store instead of the times of the files
+    pub mtime: Option<MtimeOverride>,
+    /// Password to encrypt new archives with, and to extract encrypted archives
+    /// Extracting an encrypted archive without one prompts for it on a terminal.
+    pub password: Option<String>,
+    /// Restore the Unix permissions and modification times stored in the archive when extracting
+    pub preserve_permissions: bool,
+    /// Compression method of the entries, besides the files that are already compressed
+    pub method: ZipMethod,
+    /// Compression methods for the files with an extension, overriding the others
+    pub method_for: HashMap<String, ZipMethod>,
+    /// Comment of the archive
+    pub comment: Option<String>,
+    pub progress_args: ProgressArgs,
+    /// Progress observer, cancellation, and limits on the total size of the extracted files
+    pub hooks: ProgressHooks,
+}
+
+impl Default for Zip {
+    fn default() -> Self {
+        Zip {
+            compression_level: 6,
+            allow_unsafe_paths: false,
+            strip_components: 0,
+            transform: Transform::default(),
+            from_list: false,
+            directory: None,
+            symlinks: Symlinks::Store,
+            exclude: Exclude::default(),
+            reproducible: None,
+            mtime: None,
+            password: None,
+            preserve_permissions: true,
+            method: ZipMethod::Deflate,
+            method_for: HashMap::new(),
+            comment: None,
+            progress_args: ProgressArgs::default(),
+            hooks: ProgressHooks::default(),
+        }
+    }
+}
+
+impl Zip {
+    pub fn new(args: &ZipArgs) -> Result<Zip, io::Error> {
+        let password = if let Some(password) = &args.password {
+            Some(password.clone())
+        } else if let Some(path) = &args.password_file {
+            let contents = fs::read_to_string(path)?;
+            Some(contents.lines().next().unwrap_or_default().to_string())
+        } else if args.encrypt {
+            let password = rpassword::prompt_password("Password: ")?;
+            if rpassword::prompt_password("Repeat password: ")? != password {
+                return Err(io::Error::other("passwords don't match"));
+            }
+            Some(password)
+        } else {
+            None
+        };
+        if password.as_ref().is_some_and(|p| p.is_empty()) {
+            return Err(io::Error::other("the password can't be empty"));
+        }
+        Ok(Zip {
+            compression_level: args.level_args.level.level,
+            allow_unsafe_paths: args.common_args.allow_unsafe_paths,
+            strip_components: args.common_args.strip_components,
+            transform: Transform::default(),
+            from_list: false,
+            directory: None,
+            symlinks: Symlinks::new(&args.common_args),
+            exclude: Exclude::default(),
+            reproducible: None,
+            mtime: None,
+            password,
+            preserve_permissions: !args.no_preserve_permissions,
+            method: args.method,
+            method_for: args.method_for.iter().cloned().collect(),
+            comment: args.comment.clone(),
+            progress_args: args.progress_args,
+            hooks: ProgressHooks::default(),
+        })
+    }
+}
+
+impl Compressor for Zip {
+    /// Full name for zip, also used for extension
+    fn name(&self) -> &str {
+        "zip"
+    }
+
+    /// Zip extraction needs to specify the directory, so use the current directory
+    fn default_extracted_filename(&self, _in_path: &Path) -> String {
+        ".".to_string()
+    }
+
+    fn set_allow_unsafe_paths(&mut self, allow: bool) {
+        self.allow_unsafe_paths = allow;
+    }
+
+    fn set_strip_components(&mut self, count: usize) {
+        self.strip_components = count;
+    }
+
+    fn set_from_list(&mut self, from_list: bool) {
+        self.from_list = from_list;
+    }
+
+    fn set_directory(&mut self, directory: PathBuf) {
+        self.directory = Some(directory);
+    }
+
+    fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+    }
+
+    fn set_symlinks(&mut self, symlinks: Symlinks) {
+        self.symlinks = symlinks;
+    }
+
+    fn set_limits(&mut self, limits: OutputLimits) {
+        self.hooks.limits = limits;
+    }
+
+    fn set_observer(&mut self, observer: Arc<dyn ProgressObserver>) {
+        self.hooks.observer = Some(observer);
+    }
+
+    fn set_level(&mut self, level: i32) {
+        self.compression_level = level as u32;
+    }
+
+    fn level(&self) -> Option<i32> {
+        Some(self.compression_level as i32)
+    }
+
+    fn set_progress(&mut self, progress: ProgressDisplay) {
+        self.progress_args.progress = progress;
+    }
+
+    fn progress(&self) -> ProgressDisplay {
+        self.progress_args.progress
+    }
+
+    fn set_exclude(&mut self, exclude: Exclude) {
+        self.exclude = exclude;
+    }
+
+    fn set_reproducible(&mut self, reproducible: Reproducible) {
+        self.reproducible = Some(reproducible);
+    }
+
+    fn set_mtime(&mut self, mtime: MtimeOverride) {
+        self.mtime = Some(mtime);
+    }
+
+    fn compress(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        let bar = progress_bar(
+            self.input_size(&input),
+            self.progress_args.progress,
+            &output,
+            &self.hooks,
+        );
+        let progress = ArchiveProgress::new(&self.hooks, bar);
+        match output {
+            CmprssOutput::Path(path) => {
+                let writer = ZipWriter::new(progress.writer(File::create(path)?));
+                self.compress_internal(input, writer, false, &progress)?;
+            }
+            CmprssOutput::Pipe(pipe) => self.compress_to_stream(input, pipe, &progress)?,
+            CmprssOutput::Writer(writer) => {
+                self.compress_to_stream(input, writer.writer, &progress)?
+            }
+        }
+        progress.finish();
+        Ok(())
+    }
+
+    fn extract(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        let out_path = match &output {
+            CmprssOutput::Path(path) => path,
+            _ => return self.extract_single(input, output),
+        };
+        if !out_path.is_dir() {
+            return cmprss_error("error: zip can only extract to a directory");
+        }
+        let (reader, input_size) = open_reader(input)?;
+        let bar = progress_bar(
+            Some(input_size),
+            self.progress_args.progress,
+            &output,
+            &self.hooks,
+        );
+        let progress = ArchiveProgress::new(&self.hooks, bar);
+        let mut archive = ZipArchive::new(Box::new(progress.reader(reader)) as Box<dyn ReadSeek>)?;
+        let mut password = self.password.clone();
+        // Directories are restored last, so that extracting their contents doesn't change them
+        let mut directories = Vec::new();
+        let mut written = 0;
+        // Once links are restored, files could be written through them out of the output
+        let mut linked = false;
+        for i in 0..archive.len() {
+            let Some(entry) = archive.name_for_index(i).map(Path::new) else {
+                continue;
+            };
+            if !self.exclude.is_extracted(entry) {
+                crate::info!("skipping {}, it is excluded", entry.display());
+                continue;
+            }
+            let stripped = strip_components(entry, self.strip_components);
+            let is_dir = entry.to_string_lossy().ends_with('/');
+            let Some(name) = stripped.and_then(|name| self.transform.apply(&name, is_dir)) else {
+                crate::info!(
+                    "skipping {}, it is removed by --strip-components, --transform or --junk-paths",
+                    entry.display()
+                );
+                continue;
+            };
+            crate::debug!("extracting {}", entry.display());
+            let path = entry_path(&long_path(out_path)?, &name, self.allow_unsafe_paths)?;
+            let mut file = open_entry(&mut archive, i, &mut password)?;
+            if file.is_dir() {
+                fs::create_dir_all(&path)?;
+                directories.push((path, file.unix_mode(), file.last_modified()));
+                continue;
+            }
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+                if linked
+                    && !self.allow_unsafe_paths
+                    && !parent.canonicalize()?.starts_with(out_path.canonicalize()?)
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "entry would be extracted outside of the output through a symlink: {}",
+                            name.display()
+                        ),
+                    ));
+                }
+            }
+            if file.is_symlink() {
+                if self.symlinks == Symlinks::Skip {
+                    crate::info!("skipping {}, it is a symbolic link", name.display());
+                    continue;
+                }
+                let mut target = String::new();
+                file.read_to_string(&mut target)?;
+                if !self.allow_unsafe_paths && symlink_escapes(&name, Path::new(&target)) {
+                    crate::warn!(
+                        "skipping {}, it links to {} outside of the output, use --allow-unsafe-paths to extract it",
+                        name.display(),
+                        target
+                    );
+                    continue;
+                }
+                restore_symlink(&path, &target)?;
+                linked = true;
+                continue;
+            }
+            // The sizes in the archive can't be trusted, so the limits are checked while writing
+            let mut writer = self
+                .hooks
+                .limits
+                .writer(progress.writer(File::create(&path)?), Some(input_size))
+                .after(written);
+            io::copy(&mut file, &mut writer)?;
+            written = writer.written();
+            if self.preserve_permissions {
+                restore_metadata(&path, file.unix_mode(), file.last_modified())?;
+            }
+        }
+        if self.preserve_permissions {
+            for (path, mode, mtime) in directories.into_iter().rev() {
+                restore_metadata(&path, mode, mtime)?;
+            }
+        }
+        progress.finish();
+        Ok(())
+    }
+
+    fn cat(&self, input: CmprssInput, entry: &str, output: CmprssOutput) -> Result<(), io::Error> {
+        let mut archive = open_archive(input)?;
+        let Some(index) = (0..archive.len()).find(|&i| {
+            archive
+                .name_for_index(i)
+                .is_some_and(|name| entry_matches(name, entry))
+        }) else {
+            return Err(io::Error::other(format!("{} is not in the archive", entry)));
+        };
+        let mut password = self.password.clone();
+        let mut file = open_entry(&mut archive, index, &mut password)?;
+        if file.is_dir() {
+            return Err(io::Error::other(format!("{} is a directory", entry)));
+        }
+        let mut output = output.into_stream()?;
+        io::copy(&mut file, &mut output)?;
+        output.flush()
+    }
+
+    fn list(&self, input: CmprssInput) -> Result<Vec<ArchiveEntry>, io::Error> {
+        let mut archive = open_archive(input)?;
+        let mut entries = Vec::new();
+        for i in 0..archive.len() {
+            let file = archive.by_index_raw(i)?;
+            entries.push(ArchiveEntry {
+                path: file.name().to_string(),
+                is_dir: file.is_dir(),
+                size: file.size(),
+                compressed_size: Some(file.compressed_size()),
+                mtime: file.last_modified().map(unix_time),
+                mode: file.unix_mode().map(|mode| mode & 0o7777),
+                comment: Some(file.comment().to_string()).filter(|c| !c.is_empty()),
+            });
+        }
+        Ok(entries)
+    }
+
+    fn comment(&self, input: CmprssInput) -> Result<Option<String>, io::Error> {
+        let archive = open_archive(input)?;
+        let comment = String::from_utf8_lossy(archive.comment()).into_owned();
+        Ok(Some(comment).filter(|comment| !comment.is_empty()))
+    }
+}
+
+/// Open a zip archive, reading a stream into memory because the index is at the end
+fn open_archive(input: CmprssInput) -> Result<ZipArchive<Box<dyn ReadSeek>>, io::Error> {
+    Ok(ZipArchive::new(open_reader(input)?.0)?)
+}
+
+/// Open the data of a zip archive for seeking, along with its size in bytes
+fn open_reader(input: CmprssInput) -> Result<(Box<dyn ReadSeek>, u64), io::Error> {
+    let (reader, size): (Box<dyn ReadSeek>, u64) = match input {
+        CmprssInput::Path(paths) => {
+            if paths.len() > 1 {
+                return Err(io::Error::other(
+                    "only 1 archive can be extracted at a time",
+                ));
+            }
+            let file = File::open(&paths[0])?;
+            let size = file.metadata()?.len();
+            (Box::new(file), size)
+        }
+        input => {
+            let (mut stream, _) = input.into_stream()?;
+            let mut buffer = Vec::new();
+            stream.read_to_end(&mut buffer)?;
+            let size = buffer.len() as u64;
+            (Box::new(Cursor::new(buffer)), size)
+        }
+    };
+    Ok((reader, size))
+}
+
+/// Open an entry of the archive for reading, decrypting it if needed
+/// The password is asked for the first time an encrypted entry is found, and kept for the rest.
+fn open_entry<'a>(
+    archive: &'a mut ZipArchive<Box<dyn ReadSeek>>,
+    index: usize,
+    password: &mut Option<String>,
+) -> Result<zip::read::ZipFile<'a, Box<dyn ReadSeek>>, io::Error> {
+    if password.is_none() && archive.by_index_raw(index)?.encrypted() {
+        *password = Some(prompt_password()?);
+    }
+    match password {
+        Some(password) => match archive.by_index_decrypt(index, password.as_bytes()) {
+            Err(ZipError::InvalidPassword) => {
+                Err(io::Error::other("incorrect password for the zip archive"))
+            }
+            file => Ok(file?),
+        },
+        None => Ok(archive.by_index(index)?),
+    }
+}
+
+/// Ask for the password of an encrypted archive, if there is a terminal to ask on
+fn prompt_password() -> Result<String, io::Error> {
+    if !io::stdin().is_terminal() {
+        return Err(io::Error::other(
+            "zip archive is encrypted, use --password or --password-file",
+        ));
+    }
+    rpassword::prompt_password("Password: ")
+}
+
+trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// A time as a zip timestamp, if it fits in the format
+fn zip_time(secs: u64) -> Option<DateTime> {
+    let (year, month, day, hour, minute, second) = civil_from_unix(secs as i64);
+    DateTime::from_date_and_time(
+        year.try_into().ok()?,
+        month as u8,
+        day as u8,
+        hour as u8,
+        minute as u8,
+        second as u8,
+    )
+    .ok()
+}
+
+/// A zip timestamp as seconds since the Unix epoch, treating it as UTC
+fn unix_time(time: DateTime) -> i64 {
+    unix_from_civil(
+        time.year().into(),
+        time.month().into(),
+        time.day().into(),
+        time.hour().into(),
+        time.minute().into(),
+        time.second().into(),
+    )
+}
+
+/// The Unix permissions of a file, to store in the archive
+#[cfg(unix)]
+fn permissions(path: &Path) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(fs::metadata(path).ok()?.permissions().mode() & 0o7777)
+}
+
+/// Permissions aren't stored on other platforms, so the zip defaults are used
+#[cfg(not(unix))]
+fn permissions(_path: &Path) -> Option<u32> {
+    None
+}
+
+/// Create an extracted symbolic link, replacing an existing file
+#[cfg(unix)]
+fn restore_symlink(path: &Path, target: &str) -> Result<(), io::Error> {
+    if fs::symlink_metadata(path).is_ok() {
+        fs::remove_file(path)?;
+    }
+    std::os::unix::fs::symlink(target, path)
+}
+
+/// Creating links needs extra privileges on other platforms, so the link is a file with its target
+#[cfg(not(unix))]
+fn restore_symlink(path: &Path, target: &str) -> Result<(), io::Error> {
+    fs::write(path, target)
+}
+
+/// Restore the modification time and Unix permissions of an extracted file or directory
+/// The times in a zip have no time zone, they are stored in UTC by cmprss.
+fn restore_metadata(
+    path: &Path,
+    mode: Option<u32>,
+    mtime: Option<DateTime>,
+) -> Result<(), io::Error> {
+    // Directories can't be opened to set their times on Windows
+    if let Some(time) = mtime.filter(|_| cfg!(unix) || path.is_file()) {
+        if let Ok(secs) = u64::try_from(unix_time(time)) {
+            // Windows needs write access to change the times of a file
+            let file = if path.is_dir() {
+                File::open(path)?
+            } else {
+                fs::OpenOptions::new().write(true).open(path)?
+            };
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(secs))?;
+        }
+    }
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    Ok(())
+}
+
+impl Zip {
+    /// Write an archive to a stream
+    /// The sizes of each entry follow its data, instead of seeking back to fill them in.
+    /// Encryption still needs to seek back, so encrypted archives are built in memory first.
+    /// The zip crate can't write the Zip64 sizes of an entry after its data, so archives with
+    /// files over 4GB are built in a temporary file first.
+    fn compress_to_stream(
+        &self,
+        input: CmprssInput,
+        writer: Box<dyn Write + Send>,
+        progress: &ArchiveProgress,
+    ) -> Result<(), io::Error> {
+        let mut writer = progress.writer(writer);
+        let large = self
+            .file_sizes(&input)
+            .is_some_and(|sizes| sizes.iter().any(|&size| size > ZIP64_THRESHOLD));
+        if large {
+            let tmp =
+                std::env::temp_dir().join(format!(".cmprss-{}.zip.cmprss-tmp", std::process::id()));
+            crate::signal::register(&tmp);
+            let result = self.compress_to_file(input, &tmp, &mut writer, progress);
+            let _ = fs::remove_file(&tmp);
+            crate::signal::unregister(&tmp);
+            result?;
+        } else if self.password.is_some() {
+            let archive = ZipWriter::new(Cursor::new(Vec::new()));
+            let archive = self.compress_internal(input, archive, false, progress)?;
+            writer.write_all(&archive.into_inner())?;
+        } else {
+            writer = self
+                .compress_internal(input, ZipWriter::new_stream(writer), true, progress)?
+                .into_inner();
+        }
+        writer.flush()
+    }
+
+    /// Build an archive in a temporary file, then copy it to the writer
+    fn compress_to_file<W: Write>(
+        &self,
+        input: CmprssInput,
+        tmp: &Path,
+        writer: &mut W,
+        progress: &ArchiveProgress,
+    ) -> Result<(), io::Error> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(tmp)?;
+        let mut file = self.compress_internal(input, ZipWriter::new(file), false, progress)?;
+        file.rewind()?;
+        io::copy(&mut file, writer)?;
+        Ok(())
+    }
+
+    /// Total size of the files that will be archived, for the progress bar
+    fn input_size(&self, input: &CmprssInput) -> Option<u64> {
+        match input {
+            CmprssInput::Reader(reader) => reader.size,
+            input => Some(self.file_sizes(input)?.iter().sum()),
+        }
+    }
+
+    /// Sizes of each file that will be archived, if the input is a list of paths
+    fn file_sizes(&self, input: &CmprssInput) -> Option<Vec<u64>> {
+        let CmprssInput::Path(paths) = input else {
+            return None;
+        };
+        let mut entries = Vec::new();
+        for path in paths {
+            let name = input_entry_name(path, self.from_list, self.directory.as_deref())
+                .ok()?
+                .to_string_lossy()
+                .into_owned();
+            if !self.exclude.is_excluded(Path::new(&name)) {
+                self.walk(path, name, &mut entries).ok()?;
+            }
+        }
+        let sizes = entries
+            .iter()
+            .filter_map(|(path, _)| self.symlinks.metadata(path).ok())
+            .filter(|metadata| metadata.is_file())
+            .map(|metadata| metadata.len())
+            .collect();
+        Some(sizes)
+    }
+
+    /// Rewrite an archive without the deleted entries, adding the files that are new or newer
+    /// Kept entries are copied without recompressing them, to a new file that replaces the archive.
+    pub fn update_archive(
+        &self,
+        archive_path: &Path,
+        files: &[PathBuf],
+        delete: &Exclude,
+    ) -> Result<(), io::Error> {
+        let mut archive = open_archive(CmprssInput::Path(vec![archive_path.to_path_buf()]))?;
+        // Deleting a directory deletes everything in it
+        let deleted = |name: &str| {
+            Path::new(name.trim_end_matches('/'))
+                .ancestors()
+                .any(|path| !path.as_os_str().is_empty() && delete.is_excluded(path))
+        };
+        let mut existing = HashMap::new();
+        for i in 0..archive.len() {
+            let file = archive.by_index_raw(i)?;
+            existing.insert(file.name().to_string(), file.last_modified().map(unix_time));
+        }
+
+        let mut entries = Vec::new();
+        for file in files {
+            let name = input_entry_name(file, self.from_list, self.directory.as_deref())?
+                .to_string_lossy()
+                .into_owned();
+            if !self.exclude.is_excluded(Path::new(&name)) {
+                self.walk(file, name, &mut entries)?;
+            }
+        }
+        let mut replaced = HashSet::new();
+        let mut added = Vec::new();
+        let mut renamed = RenamedFiles::default();
+        for (path, name) in entries {
+            let Some(name) = self.stored_name(&path, &name, &mut renamed)? else {
+                continue;
+            };
+            let metadata = self.symlinks.metadata(&path)?;
+            let key = match metadata.is_dir() {
+                true => format!("{}/", name),
+                false => name.clone(),
+            };
+            if deleted(&key) {
+                continue;
+            }
+            // Zip times only have a resolution of 2 seconds
+            let newer = match existing.get(&key) {
+                None => true,
+                Some(_) if metadata.is_dir() => false,
+                Some(Some(mtime)) => modified_secs(&metadata) as i64 > mtime + 1,
+                Some(None) => true,
+            };
+            if newer {
+                replaced.insert(key);
+                added.push((path, name));
+            }
+        }
+
+        let output = AtomicOutput::new(archive_path);
+        let mut rewrite = || -> Result<(), io::Error> {
+            let mut writer = ZipWriter::new(File::create(output.tmp())?);
+            match &self.comment {
+                Some(comment) => writer.set_comment(comment.as_str()),
+                None => writer.set_raw_comment(archive.comment().into()),
+            }
+            for i in 0..archive.len() {
+                let file = archive.by_index_raw(i)?;
+                if replaced.contains(file.name()) || deleted(file.name()) {
+                    continue;
+                }
+                writer.raw_copy_file(file)?;
+            }
+            let progress = ArchiveProgress::new(&self.hooks, None);
+            for (path, name) in &added {
+                self.append(&mut writer, path, name, false, &progress)?;
+            }
+            writer.finish()?;
+            Ok(())
+        };
+        let result = rewrite();
+        output.finish(result)
+    }
+
+    /// Rewrite a zip archive as a tar archive, keeping the permissions and times of the entries
+    pub fn extract_to_tar<W: Write>(&self, input: &Path, writer: W) -> Result<W, io::Error> {
+        let mut archive = open_archive(CmprssInput::Path(vec![input.to_path_buf()]))?;
+        let mut password = self.password.clone();
+        let mut builder = tar::Builder::new(writer);
+        for i in 0..archive.len() {
+            let mut file = open_entry(&mut archive, i, &mut password)?;
+            let name = file.name().to_string();
+            let mut header = tar::Header::new_gnu();
+            let mtime = file.last_modified().map_or(0, unix_time);
+            header.set_mtime(mtime.try_into().unwrap_or(0));
+            let mode = file.unix_mode().map(|mode| mode & 0o7777);
+            if file.is_dir() {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(mode.unwrap_or(0o755));
+                header.set_size(0);
+                builder.append_data(&mut header, &name, io::empty())?;
+            } else if file.is_symlink() {
+                let mut target = String::new();
+                file.read_to_string(&mut target)?;
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_mode(mode.unwrap_or(0o777));
+                header.set_size(0);
+                builder.append_link(&mut header, &name, target)?;
+            } else {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_mode(mode.unwrap_or(0o644));
+                header.set_size(file.size());
+                builder.append_data(&mut header, &name, &mut file)?;
+            }
+        }
+        builder.into_inner()
+    }
+
+    /// Write the entries of a tar archive to a new zip archive
+    /// Zip has no hard links, so they are stored as copies of the file they link to.
+    pub fn compress_tar<R: Read>(&self, reader: R, output: &Path) -> Result<(), io::Error> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(output)?;
+        let mut zip = ZipWriter::new(file);
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+            let header = entry.header();
+            let mut options = self.file_options(&name);
+            if let Some(time) = header
+                .mtime()
+                .ok()
+                .map(|mtime| self.stored_mtime(mtime))
+                .and_then(zip_time)
+            {
+                options = options.last_modified_time(time);
+            }
+            if let Ok(mode) = header.mode() {
+                options = options.unix_permissions(mode & 0o7777);
+            }
+            match header.entry_type() {
+                tar::EntryType::Directory => zip.add_directory(name, options)?,
+                tar::EntryType::Regular | tar::EntryType::Continuous => {
+                    if let Some(password) = &self.password {
+                        options = options.with_aes_encryption(AesMode::Aes256, password);
+                    }
+                    zip.start_file(name, options)?;
+                    io::copy(&mut entry, &mut zip)?;
+                }
+                tar::EntryType::Symlink => {
+                    let target = entry
+                        .link_name()?
+                        .ok_or_else(|| io::Error::other(format!("{} has no link target", name)))?;
+                    zip.add_symlink(name, target.to_string_lossy(), options)?;
+                }
+                tar::EntryType::Link => {
+                    let source = entry
+                        .link_name()?
+                        .ok_or_else(|| io::Error::other(format!("{} has no link target", name)))?;
+                    zip.deep_copy_file(&source.to_string_lossy(), &name)?;
+                }
+                _ => {
+                    return Err(io::Error::other(format!(
+                        "{} can't be stored in a zip archive",
+                        name
+                    )))
+                }
+            }
+        }
+        zip.finish()?;
+        Ok(())
+    }
+
+    /// Options of an entry, with the compression method picked by its name
+    fn file_options(&self, name: &str) -> SimpleFileOptions {
+        let extension = Path::new(name)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        let method = match extension {
+            Some(extension) => match self.method_for.get(&extension) {
+                Some(method) => *method,
+                None if COMPRESSED_EXTENSIONS.contains(&extension.as_str()) => ZipMethod::Store,
+                None => self.method,
+            },
+            None => self.method,
+        };
+        let (method, level) = method.options(self.compression_level);
+        SimpleFileOptions::default()
+            .compression_method(method)
+            .compression_level(level)
+            .large_file(true)
+    }
+
+    /// Write the only file of the archive to a stream, like stdout
+    fn extract_single(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        let (reader, input_size) = open_reader(input)?;
+        let mut archive = ZipArchive::new(reader)?;
+        let mut files = Vec::new();
+        for i in 0..archive.len() {
+            let file = archive.by_index_raw(i)?;
+            if !file.is_dir()
+                && !file.is_symlink()
+                && self.exclude.is_extracted(Path::new(file.name()))
+            {
+                files.push(i);
+            }
+        }
+        let [index] = files[..] else {
+            return Err(io::Error::other(format!(
+                "zip can only extract a single file to stdout, but the archive has {}, use --to-stdout ENTRY to choose one",
+                files.len()
+            )));
+        };
+        let mut password = self.password.clone();
+        let mut file = open_entry(&mut archive, index, &mut password)?;
+        crate::debug!("extracting {} to stdout", file.name());
+        let mut output = self
+            .hooks
+            .limits
+            .writer(output.into_stream()?, Some(input_size));
+        io::copy(&mut file, &mut output)?;
+        output.flush()
+    }
+
+    /// The modification time to store for a file, changed by --mtime
+    fn stored_mtime(&self, mtime: u64) -> u64 {
+        self.mtime.map_or(mtime, |stored| stored.apply(mtime))
+    }
+
+    /// Internal compress helper
+    fn compress_internal<W: Write + Seek>(
+        &self,
+        input: CmprssInput,
+        mut archive: ZipWriter<W>,
+        streaming: bool,
+        progress: &ArchiveProgress,
+    ) -> Result<W, io::Error> {
+        if let Some(comment) = &self.comment {
+            archive.set_comment(comment.as_str());
+        }
+        let input_files = match input {
+            CmprssInput::Path(paths) => paths,
+            input => {
+                // A stream is stored as a single file, named like the output of other formats
+                let name = input.stream_name().unwrap_or("archive").to_string();
+                let (stream, _) = input.into_stream()?;
+                let mtime = match self.reproducible {
+                    Some(reproducible) => reproducible.mtime,
+                    None => SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |time| time.as_secs()),
+                };
+                let mut options = self
+                    .file_options(&name)
+                    .last_modified_time(zip_time(self.stored_mtime(mtime)).unwrap_or_default())
+                    .unix_permissions(0o644);
+                if let Some(password) = &self.password {
+                    options = options.with_aes_encryption(AesMode::Aes256, password);
+                }
+                archive.start_file(name, options)?;
+                io::copy(&mut progress.reader(stream), &mut archive)?;
+                return Ok(archive.finish()?);
+            }
+        };
+        let mut renamed = RenamedFiles::default();
+        for in_file in input_files {
+            let name = input_entry_name(&in_file, self.from_list, self.directory.as_deref())?
+                .to_string_lossy()
+                .into_owned();
+            if self.exclude.is_excluded(Path::new(&name)) {
+                crate::info!("skipping {}, it is excluded", in_file.display());
+                continue;
+            }
+            let mut entries = Vec::new();
+            self.walk(&in_file, name, &mut entries)?;
+            for (path, name) in entries {
+                if let Some(name) = self.stored_name(&path, &name, &mut renamed)? {
+                    self.append(&mut archive, &path, &name, streaming, progress)?;
+                }
+            }
+        }
+        Ok(archive.finish()?)
+    }
+
+    /// Collect a file or a directory tree, with the names of the entries in the archive
+    /// Entry names always use '/', regardless of the platform.
+    fn walk(
+        &self,
+        path: &Path,
+        name: String,
+        entries: &mut Vec<(PathBuf, String)>,
+    ) -> Result<(), io::Error> {
+        let metadata = self.symlinks.metadata(path)?;
+        if metadata.is_symlink() && self.symlinks == Symlinks::Skip {
+            crate::info!("skipping {}, it is a symbolic link", path.display());
+            return Ok(());
+        }
+        entries.push((path.to_path_buf(), name.clone()));
+        if !metadata.is_dir() || self.from_list {
+            return Ok(());
+        }
+        let mut children = fs::read_dir(long_path(path)?)?.collect::<Result<Vec<_>, _>>()?;
+        children.sort_by_key(|child| child.file_name());
+        for child in children {
+            let child_name = format!("{}/{}", name, child.file_name().to_string_lossy());
+            if self
+                .exclude
+                .is_excluded_file(Path::new(&child_name), &child.path())
+            {
+                crate::info!("skipping {}, it is excluded", child.path().display());
+                continue;
+            }
+            self.walk(&path.join(child.file_name()), child_name, entries)?;
+        }
+        Ok(())
+    }
+
+    /// Name of the entry of a file, renamed by --transform and --junk-paths
+    fn stored_name(
+        &self,
+        path: &Path,
+        name: &str,
+        renamed: &mut RenamedFiles,
+    ) -> Result<Option<String>, io::Error> {
+        let is_dir = self
+            .symlinks
+            .metadata(path)
+            .is_ok_and(|metadata| metadata.is_dir());
+        let stored = self
+            .transform
+            .apply_to_file(path, Path::new(name), is_dir, renamed)?;
+        if stored.is_none() {
+            crate::debug!(
+                "skipping {}, it is removed by --transform or --junk-paths",
+                path.display()
+            );
+        }
+        Ok(stored.map(|stored| stored.to_string_lossy().into_owned()))
+    }
+
+    /// Add a file, or a directory without its contents, to the archive
+    fn append<W: Write + Seek>(
+        &self,
+        archive: &mut ZipWriter<W>,
+        path: &Path,
+        name: &str,
+        streaming: bool,
+        progress: &ArchiveProgress,
+    ) -> Result<(), io::Error> {
+        crate::debug!("adding {}", name);
+        let mut options = self.file_options(name);
+        let metadata = self.symlinks.metadata(path)?;
+        match self.reproducible {
+            Some(reproducible) => {
+                // Times before 1980 don't fit, so zeroed times are the earliest zip time
+                let secs = match self.mtime {
+                    Some(mtime) => mtime.apply(modified_secs(&metadata)),
+                    None => reproducible.clamp(modified_secs(&metadata)),
+                };
+                let mode = permissions(path).unwrap_or(0o644);
+                options = options
+                    .last_modified_time(zip_time(secs).unwrap_or_default())
+                    .unix_permissions(reproducible.mode(mode, metadata.is_dir()));
+            }
+            None => {
+                if let Some(time) = zip_time(self.stored_mtime(modified_secs(&metadata))) {
+                    options = options.last_modified_time(time);
+                }
+                if let Some(mode) = permissions(path) {
+                    options = options.unix_permissions(mode);
+                }
+            }
+        }
+        if metadata.is_symlink() {
+            // The target is stored as the contents of the entry, with the mode marking it a link
+            let target = fs::read_link(path)?;
+            archive.add_symlink(
+                name,
+                target.to_string_lossy(),
+                options.unix_permissions(0o777),
+            )?;
+        } else if metadata.is_file() {
+            if let Some(password) = &self.password {
+                options = options.with_aes_encryption(AesMode::Aes256, password);
+            }
+            archive.start_file(name, options)?;
+            io::copy(&mut progress.reader(File::open(long_path(path)?)?), archive)?;
+        } else if metadata.is_dir() {
+            if streaming {
+                // add_directory marks the entry as followed by its sizes without writing them,
+                // so write an empty entry that is finished like a file instead
+                archive.start_file(
+                    format!("{}/", name),
+                    options
+                        .compression_method(CompressionMethod::Stored)
+                        .compression_level(None),
+                )?;
+            } else {
+                archive.add_directory(name, options)?;
+            }
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unknown file type",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use predicates::prelude::*;
+
+    #[test]
+    fn roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Zip::default();
+
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        dir.child("test.txt")
+            .write_str("garbage data for testing")?;
+        dir.child("nested/other.txt").write_str("more garbage")?;
+        let archive = working_dir.child("archive.".to_owned() + compressor.extension());
+        archive.assert(predicate::path::missing());
+
+        // Roundtrip compress/extract
+        compressor.compress(
+            CmprssInput::Path(vec![dir.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        archive.assert(predicate::path::is_file());
+        let extract_dir = working_dir.child("out");
+        extract_dir.create_dir_all()?;
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(extract_dir.path().to_path_buf()),
+        )?;
+
+        // Assert the files are identical
+        extract_dir
+            .child("dir/test.txt")
+            .assert(predicate::path::eq_file(dir.child("test.txt").path()));
+        extract_dir
+            .child("dir/nested/other.txt")
+            .assert(predicate::path::eq_file(
+                dir.child("nested/other.txt").path(),
+            ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn progress() -> Result<(), Box<dyn std::error::Error>> {
+        use std::sync::Mutex;
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        dir.child("a.txt").write_str(&"a".repeat(1000))?;
+        dir.child("b/c.txt").write_str("garbage data for testing")?;
+        let archive = working_dir.child("archive.zip");
+        let last = Arc::new(Mutex::new((0, 0)));
+        let recorded = last.clone();
+        let mut compressor = Zip::default();
+        compressor.set_observer(Arc::new(move |input, output| {
+            *recorded.lock().unwrap() = (input, output)
+        }));
+
+        let input = CmprssInput::Path(vec![dir.path().to_path_buf()]);
+        assert_eq!(compressor.input_size(&input), Some(1024));
+        compressor.compress(input, CmprssOutput::Path(archive.path().to_path_buf()))?;
+        let (input, output) = *last.lock().unwrap();
+        assert_eq!(input, 1024);
+        // Headers rewritten after seeking back are counted again
+        assert!(output >= fs::metadata(archive.path())?.len());
+
+        let out = working_dir.child("out");
+        out.create_dir_all()?;
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        let (input, output) = *last.lock().unwrap();
+        assert!(input > 0);
+        assert_eq!(output, 1024);
+        out.child("dir/a.txt").assert("a".repeat(1000));
+        Ok(())
+    }
+
+    #[test]
+    fn exclude() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Zip {
+            exclude: Exclude::from_patterns(&["target/", "dir/skip.txt"])?,
+            ..Zip::default()
+        };
+
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        dir.child("keep.txt")
+            .write_str("garbage data for testing")?;
+        dir.child("skip.txt").write_str("skipped")?;
+        dir.child("target/debug/binary").write_str("build output")?;
+        let archive = working_dir.child("archive.zip");
+        compressor.compress(
+            CmprssInput::Path(vec![dir.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+
+        let entries = compressor.list(CmprssInput::Path(vec![archive.path().to_path_buf()]))?;
+        let paths: Vec<_> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, ["dir/", "dir/keep.txt"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn transform() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let build = working_dir.child("build");
+        build.child("lib/app.so").write_str("library")?;
+        let archive = working_dir.child("archive.zip");
+        let compressor = Zip {
+            transform: Transform::new(&["s,^build(/|$),,"])?,
+            ..Zip::default()
+        };
+        compressor.compress(
+            CmprssInput::Path(vec![build.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        let entries = Zip::default().list(CmprssInput::Path(vec![archive.path().to_path_buf()]))?;
+        let paths: Vec<_> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, ["lib/", "lib/app.so"]);
+
+        let compressor = Zip {
+            transform: Transform::new(&["s/^lib/vendor/"])?,
+            ..Zip::default()
+        };
+        let out = working_dir.child("out");
+        out.create_dir_all()?;
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        out.child("vendor/app.so").assert("library");
+        out.child("lib").assert(predicate::path::missing());
+
+        let compressor = Zip {
+            transform: Transform::default().junk_paths(true),
+            ..Zip::default()
+        };
+        let flat = working_dir.child("flat");
+        flat.create_dir_all()?;
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(flat.path().to_path_buf()),
+        )?;
+        flat.child("app.so").assert("library");
+        flat.child("lib").assert(predicate::path::missing());
+
+        Ok(())
+    }
+
+    #[test]
+    fn list() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Zip::default();
+
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("test.txt");
+        file.write_str("garbage data for testing")?;
+        let archive = working_dir.child("archive.zip");
+        compressor.compress(
+            CmprssInput::Path(vec![file.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+
+        let entries = compressor.list(CmprssInput::Path(vec![archive.path().to_path_buf()]))?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, "test.txt");
+        assert_eq!(entries[0].size, 24);
+        assert!(entries[0].compressed_size.is_some());
+        assert!(entries[0].mtime.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn selective_extract() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        dir.child("docs/index.md").write_str("index")?;
+        dir.child("docs/logo.png").write_str("png")?;
+        dir.child("src/main.rs").write_str("fn main() {}")?;
+        let archive = working_dir.child("archive.zip");
+        Zip::default().compress(
+            CmprssInput::Path(vec![dir.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+
+        let compressor = Zip {
+            exclude: Exclude::from_patterns(&["*.png"])?.include(&["dir/docs"])?,
+            ..Zip::default()
+        };
+        let out = working_dir.child("out");
+        out.create_dir_all()?;
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        out.child("dir/docs/index.md").assert("index");
+        out.child("dir/docs/logo.png")
+            .assert(predicate::path::missing());
+        out.child("dir/src").assert(predicate::path::missing());
+
+        Ok(())
+    }
+
+    #[test]
+    fn update_and_delete() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Zip::default();
+
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        dir.child("kept.txt").write_str("kept")?;
+        dir.child("changed.txt").write_str("old")?;
+        dir.child("debug.log").write_str("log")?;
+        let archive = working_dir.child("archive.zip");
+        compressor.compress(
+            CmprssInput::Path(vec![dir.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+
+        // Only files newer than their entry are replaced
+        dir.child("changed.txt").write_str("new")?;
+        File::options()
+            .write(true)
+            .open(dir.child("changed.txt").path())?
+            .set_modified(SystemTime::now() + Duration::from_secs(10))?;
+        dir.child("added.txt").write_str("added")?;
+        compressor.update_archive(
+            archive.path(),
+            &[dir.path().to_path_buf()],
+            &Exclude::from_patterns(&["*.log"])?,
+        )?;
+
+        let entries = compressor.list(CmprssInput::Path(vec![archive.path().to_path_buf()]))?;
+        let mut paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            ["dir/", "dir/added.txt", "dir/changed.txt", "dir/kept.txt"]
+        );
+        let output = working_dir.child("changed.txt");
+        compressor.cat(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            "dir/changed.txt",
+            CmprssOutput::Path(output.path().to_path_buf()),
+        )?;
+        output.assert("new");
+
+        // Deleting a directory deletes its contents
+        compressor.update_archive(archive.path(), &[], &Exclude::from_patterns(&["dir/"])?)?;
+        let entries = compressor.list(CmprssInput::Path(vec![archive.path().to_path_buf()]))?;
+        assert!(entries.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn encrypted_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Zip {
+            password: Some("secret".to_string()),
+            ..Zip::default()
+        };
+
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("test.txt");
+        file.write_str("garbage data for testing")?;
+        let archive = working_dir.child("archive.zip");
+        compressor.compress(
+            CmprssInput::Path(vec![file.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        let extract_dir = working_dir.child("out");
+        extract_dir.create_dir_all()?;
+
+        // A wrong password is reported, and nothing is extracted
+        let wrong = Zip {
+            password: Some("wrong".to_string()),
+            ..Zip::default()
+        };
+        let result = wrong.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(extract_dir.path().to_path_buf()),
+        );
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("incorrect password"));
+        extract_dir
+            .child("test.txt")
+            .assert(predicate::path::missing());
+
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(extract_dir.path().to_path_buf()),
+        )?;
+        extract_dir
+            .child("test.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+    #[test]
+    fn unsafe_paths() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("archive.zip");
+        let mut writer = ZipWriter::new(File::create(archive.path())?);
+        writer.start_file("../escape.txt", SimpleFileOptions::default())?;
+        writer.write_all(b"escaped")?;
+        writer.finish()?;
+        let out = working_dir.child("out");
+        out.create_dir_all()?;
+
+        let result = Zip::default().extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        );
+        assert!(result.is_err());
+        working_dir
+            .child("escape.txt")
+            .assert(predicate::path::missing());
+
+        let compressor = Zip {
+            allow_unsafe_paths: true,
+            ..Zip::default()
+        };
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        working_dir.child("escape.txt").assert("escaped");
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn preserve_permissions() -> Result<(), Box<dyn std::error::Error>> {
+        use std::os::unix::fs::PermissionsExt;
+        use std::time::SystemTime;
+
+        let working_dir = assert_fs::TempDir::new()?;
+        let script = working_dir.child("dir/script.sh");
+        script.write_str("#!/bin/sh\n")?;
+        fs::set_permissions(script.path(), fs::Permissions::from_mode(0o755))?;
+        // Zip times have a resolution of 2 seconds
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        File::options()
+            .write(true)
+            .open(script.path())?
+            .set_modified(mtime)?;
+        let archive = working_dir.child("archive.zip");
+        Zip::default().compress(
+            CmprssInput::Path(vec![working_dir.child("dir").path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+
+        let extract = |compressor: Zip, out: &str| -> Result<fs::Metadata, io::Error> {
+            let out = working_dir.child(out);
+            fs::create_dir_all(out.path())?;
+            compressor.extract(
+                CmprssInput::Path(vec![archive.path().to_path_buf()]),
+                CmprssOutput::Path(out.path().to_path_buf()),
+            )?;
+            fs::metadata(out.child("dir/script.sh").path())
+        };
+
+        let metadata = extract(Zip::default(), "out")?;
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o755);
+        assert_eq!(metadata.modified()?, mtime);
+
+        let compressor = Zip {
+            preserve_permissions: false,
+            ..Zip::default()
+        };
+        let metadata = extract(compressor, "plain")?;
+        assert_eq!(metadata.permissions().mode() & 0o111, 0);
+        assert!(metadata.modified()? > SystemTime::now() - Duration::from_secs(60));
+
+        Ok(())
+    }
+
+    #[test]
+    fn methods() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        dir.child("test.txt")
+            .write_str(&"garbage data for testing".repeat(100))?;
+        dir.child("photo.JPG")
+            .write_str(&"garbage data for testing".repeat(100))?;
+        dir.child("debug.log")
+            .write_str(&"garbage data for testing".repeat(100))?;
+
+        for method in [
+            ZipMethod::Store,
+            ZipMethod::Deflate,
+            #[cfg(feature = "bzip2")]
+            ZipMethod::Bzip2,
+            #[cfg(feature = "zstd")]
+            ZipMethod::Zstd,
+        ] {
+            let compressor = Zip {
+                method,
+                method_for: HashMap::from([("log".to_string(), ZipMethod::Store)]),
+                ..Zip::default()
+            };
+            let archive = working_dir.child("archive.zip");
+            compressor.compress(
+                CmprssInput::Path(vec![dir.path().to_path_buf()]),
+                CmprssOutput::Path(archive.path().to_path_buf()),
+            )?;
+
+            let mut zip = ZipArchive::new(File::open(archive.path())?)?;
+            let (expected, _) = method.options(6);
+            assert_eq!(zip.by_name("dir/test.txt")?.compression(), expected);
+            // Compressed files and the overridden extension are stored
+            for stored in ["dir/photo.JPG", "dir/debug.log"] {
+                assert_eq!(
+                    zip.by_name(stored)?.compression(),
+                    CompressionMethod::Stored
+                );
+            }
+
+            let extract_dir = working_dir.child("out");
+            extract_dir.create_dir_all()?;
+            compressor.extract(
+                CmprssInput::Path(vec![archive.path().to_path_buf()]),
+                CmprssOutput::Path(extract_dir.path().to_path_buf()),
+            )?;
+            extract_dir
+                .child("dir/test.txt")
+                .assert(predicate::path::eq_file(dir.child("test.txt").path()));
+            fs::remove_dir_all(extract_dir.path())?;
+            fs::remove_file(archive.path())?;
+        }
+
+        assert_eq!(
+            parse_method_for(".PNG=deflate"),
+            Ok(("png".to_string(), ZipMethod::Deflate))
+        );
+        assert!(parse_method_for("png").is_err());
+        assert!(parse_method_for("png=lzma").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn many_entries() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Zip {
+            method: ZipMethod::Store,
+            ..Zip::default()
+        };
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        dir.create_dir_all()?;
+        // More entries than fit in the end of central directory record
+        let count = u16::MAX as usize + 10;
+        for i in 0..count {
+            File::create(dir.path().join(i.to_string()))?;
+        }
+
+        let archive = working_dir.child("archive.zip");
+        let streamed = working_dir.child("streamed.zip");
+        compressor.compress(
+            CmprssInput::Path(vec![dir.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        compressor.compress(
+            CmprssInput::Path(vec![dir.path().to_path_buf()]),
+            CmprssOutput::Pipe(Box::new(File::create(streamed.path())?)),
+        )?;
+
+        for path in [archive.path(), streamed.path()] {
+            let zip = ZipArchive::new(File::open(path)?)?;
+            // The directory itself is an entry too
+            assert_eq!(zip.len(), count + 1);
+            // The Zip64 end of central directory record
+            let data = fs::read(path)?;
+            assert!(data.windows(4).any(|window| window == b"PK\x06\x06"));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn comment() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("test.txt");
+        file.write_str("garbage data for testing")?;
+        let archive = working_dir.child("archive.zip");
+
+        let compressor = Zip {
+            comment: Some("build 42".to_string()),
+            ..Zip::default()
+        };
+        compressor.compress(
+            CmprssInput::Path(vec![file.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        let input = || CmprssInput::Path(vec![archive.path().to_path_buf()]);
+        assert_eq!(compressor.comment(input())?.as_deref(), Some("build 42"));
+        assert_eq!(compressor.list(input())?[0].comment, None);
+
+        // Updating the archive keeps its comment unless a new one is given
+        Zip::default().update_archive(archive.path(), &[], &Exclude::default())?;
+        assert_eq!(
+            Zip::default().comment(input())?.as_deref(),
+            Some("build 42")
+        );
+        let compressor = Zip {
+            comment: Some("build 43".to_string()),
+            ..Zip::default()
+        };
+        compressor.update_archive(archive.path(), &[], &Exclude::default())?;
+        assert_eq!(compressor.comment(input())?.as_deref(), Some("build 43"));
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlinks() -> Result<(), Box<dyn std::error::Error>> {
+        use std::os::unix::fs::symlink;
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        dir.child("test.txt")
+            .write_str("garbage data for testing")?;
+        symlink("test.txt", dir.child("link").path())?;
+        symlink("../../outside", dir.child("escape").path())?;
+        symlink("missing", dir.child("broken").path())?;
+        let input = || CmprssInput::Path(vec![dir.path().to_path_buf()]);
+        let archive = working_dir.child("archive.zip");
+
+        // Links are stored as links, and the ones pointing out of the output are skipped
+        Zip::default().compress(input(), CmprssOutput::Path(archive.path().to_path_buf()))?;
+        let out = working_dir.child("out");
+        out.create_dir_all()?;
+        Zip::default().extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        assert_eq!(
+            fs::read_link(out.child("dir/link").path())?,
+            Path::new("test.txt")
+        );
+        assert_eq!(
+            fs::read_link(out.child("dir/broken").path())?,
+            Path::new("missing")
+        );
+        out.child("dir/escape").assert(predicate::path::missing());
+
+        // Following the links archives the files they point to
+        let compressor = Zip {
+            symlinks: Symlinks::Follow,
+            ..Zip::default()
+        };
+        fs::remove_file(dir.child("broken").path())?;
+        fs::remove_file(dir.child("escape").path())?;
+        compressor.compress(input(), CmprssOutput::Path(archive.path().to_path_buf()))?;
+        let out = working_dir.child("followed");
+        out.create_dir_all()?;
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(out.path().to_path_buf()),
+        )?;
+        assert!(!fs::symlink_metadata(out.child("dir/link").path())?.is_symlink());
+        out.child("dir/link").assert("garbage data for testing");
+
+        // Skipping the links leaves them out of the archive
+        let compressor = Zip {
+            symlinks: Symlinks::Skip,
+            ..Zip::default()
+        };
+        compressor.compress(input(), CmprssOutput::Path(archive.path().to_path_buf()))?;
+        let paths: Vec<_> = compressor
+            .list(CmprssInput::Path(vec![archive.path().to_path_buf()]))?
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+        assert!(paths.iter().all(|path| !path.ends_with("link")));
+
+        Ok(())
+    }
+}
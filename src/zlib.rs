@@ -0,0 +1,278 @@
+//! zlib compression backend, for bare zlib streams and raw deflate data
+//!
+//! These are the streams inside PNG chunks, git objects, and HTTP bodies. With `--raw-deflate`
+//! the deflate data is read and written without the zlib header and checksum.
+
+use crate::{
+    limits::OutputLimits,
+    progress::{
+        copy_with_progress, progress_bar, CountingWriter, ProgressArgs, ProgressDisplay,
+        ProgressHooks, ProgressObserver,
+    },
+    utils::*,
+};
+use clap::Args;
+use flate2::write::{DeflateDecoder, DeflateEncoder, ZlibDecoder, ZlibEncoder};
+use flate2::{read, Compression};
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+#[derive(Args, Debug)]
+pub struct ZlibArgs {
+    #[clap(flatten)]
+    pub common_args: CommonArgs,
+
+    #[clap(flatten)]
+    pub progress_args: ProgressArgs,
+
+    #[clap(flatten)]
+    pub level_args: LevelArgs,
+
+    /// Raw deflate data, without the zlib header and checksum
+    #[arg(long)]
+    pub raw_deflate: bool,
+}
+
+pub struct Zlib {
+    pub compression_level: u32,
+    /// Raw deflate data, without the zlib header and checksum
+    pub raw_deflate: bool,
+    pub progress_args: ProgressArgs,
+    pub hooks: ProgressHooks,
+}
+
+impl Default for Zlib {
+    fn default() -> Self {
+        Zlib {
+            compression_level: 6,
+            raw_deflate: false,
+            progress_args: ProgressArgs::default(),
+            hooks: ProgressHooks::default(),
+        }
+    }
+}
+
+impl Zlib {
+    pub fn new(args: &ZlibArgs) -> Zlib {
+        Zlib {
+            compression_level: args.level_args.level.level,
+            raw_deflate: args.raw_deflate,
+            progress_args: args.progress_args,
+            hooks: ProgressHooks::default(),
+        }
+    }
+}
+
+impl Compressor for Zlib {
+    /// The extension used by pigz and qpdf for zlib streams
+    fn extension(&self) -> &str {
+        "zz"
+    }
+
+    /// Full name for zlib.
+    fn name(&self) -> &str {
+        "zlib"
+    }
+
+    fn is_stream(&self) -> bool {
+        true
+    }
+
+    fn set_limits(&mut self, limits: OutputLimits) {
+        self.hooks.limits = limits;
+    }
+
+    fn set_observer(&mut self, observer: Arc<dyn ProgressObserver>) {
+        self.hooks.observer = Some(observer);
+    }
+
+    fn set_level(&mut self, level: i32) {
+        self.compression_level = level as u32;
+    }
+
+    fn level(&self) -> Option<i32> {
+        Some(self.compression_level as i32)
+    }
+
+    fn set_progress(&mut self, progress: ProgressDisplay) {
+        self.progress_args.progress = progress;
+    }
+
+    fn progress(&self) -> ProgressDisplay {
+        self.progress_args.progress
+    }
+
+    /// Generate a default extracted filename
+    /// zlib does not support extracting to a directory, so we return a default filename
+    fn default_extracted_filename(&self, in_path: &std::path::Path) -> String {
+        if in_path.extension().is_none() {
+            return "archive".to_string();
+        }
+        in_path.file_stem().unwrap().to_str().unwrap().to_string()
+    }
+
+    /// Compress an input file or pipe to a zlib stream
+    fn compress(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        if let CmprssOutput::Path(out_path) = &output {
+            if out_path.is_dir() {
+                return cmprss_error("zlib does not support compressing to a directory. Please specify an output file.");
+            }
+        }
+        if let CmprssInput::Path(input_paths) = &input {
+            if input_paths.iter().any(|path| path.is_dir()) {
+                return cmprss_error(
+                    "zlib does not support compressing a directory. Please specify only files.",
+                );
+            }
+        }
+        let (mut input_stream, file_size) = input.into_stream()?;
+        let bar = progress_bar(file_size, self.progress_args.progress, &output, &self.hooks);
+        let output_stream = CountingWriter::new(output.into_stream()?);
+        let level = Compression::new(self.compression_level);
+        let chunk_size = self.progress_args.chunk_size.size_in_bytes;
+        if self.raw_deflate {
+            let mut encoder = DeflateEncoder::new(output_stream, level);
+            copy_with_progress(
+                &mut input_stream,
+                &mut encoder,
+                chunk_size,
+                &self.hooks,
+                bar.as_ref(),
+                |e| e.get_ref().count(),
+            )?;
+            encoder.finish()?;
+        } else {
+            let mut encoder = ZlibEncoder::new(output_stream, level);
+            copy_with_progress(
+                &mut input_stream,
+                &mut encoder,
+                chunk_size,
+                &self.hooks,
+                bar.as_ref(),
+                |e| e.get_ref().count(),
+            )?;
+            encoder.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Extract a zlib stream
+    fn extract(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        let (mut input_stream, file_size) = input.into_stream()?;
+        let bar = progress_bar(file_size, self.progress_args.progress, &output, &self.hooks);
+        let output_stream =
+            CountingWriter::new(self.hooks.limits.writer(output.into_stream()?, file_size));
+        let chunk_size = self.progress_args.chunk_size.size_in_bytes;
+        if self.raw_deflate {
+            let mut decoder = DeflateDecoder::new(output_stream);
+            copy_with_progress(
+                &mut input_stream,
+                &mut decoder,
+                chunk_size,
+                &self.hooks,
+                bar.as_ref(),
+                |d| d.get_ref().count(),
+            )?;
+            decoder.finish()?;
+        } else {
+            let mut decoder = ZlibDecoder::new(output_stream);
+            copy_with_progress(
+                &mut input_stream,
+                &mut decoder,
+                chunk_size,
+                &self.hooks,
+                bar.as_ref(),
+                |d| d.get_ref().count(),
+            )?;
+            decoder.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Wrap a writer with a zlib encoder
+    fn wrap_writer<'a>(
+        &self,
+        writer: Box<dyn Write + Send + 'a>,
+    ) -> Result<Box<dyn Write + Send + 'a>, io::Error> {
+        let level = Compression::new(self.compression_level);
+        if self.raw_deflate {
+            Ok(Box::new(DeflateEncoder::new(writer, level)))
+        } else {
+            Ok(Box::new(ZlibEncoder::new(writer, level)))
+        }
+    }
+
+    /// Wrap a reader with a zlib decoder
+    fn wrap_reader<'a>(
+        &self,
+        reader: Box<dyn Read + Send + 'a>,
+    ) -> Result<Box<dyn Read + Send + 'a>, io::Error> {
+        if self.raw_deflate {
+            Ok(Box::new(read::DeflateDecoder::new(reader)))
+        } else {
+            Ok(Box::new(read::ZlibDecoder::new(reader)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use predicates::prelude::*;
+
+    #[test]
+    fn roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        for raw_deflate in [false, true] {
+            let compressor = Zlib {
+                raw_deflate,
+                ..Zlib::default()
+            };
+
+            let file = assert_fs::NamedTempFile::new("test.txt")?;
+            file.write_str("garbage data for testing")?;
+            let working_dir = assert_fs::TempDir::new()?;
+            let archive = working_dir.child("archive.zz");
+            compressor.compress(
+                CmprssInput::Path(vec![file.path().to_path_buf()]),
+                CmprssOutput::Path(archive.path().to_path_buf()),
+            )?;
+            compressor.extract(
+                CmprssInput::Path(vec![archive.path().to_path_buf()]),
+                CmprssOutput::Path(working_dir.child("test.txt").path().to_path_buf()),
+            )?;
+            working_dir
+                .child("test.txt")
+                .assert(predicate::path::eq_file(file.path()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn known_streams() -> Result<(), Box<dyn std::error::Error>> {
+        // "hello" compressed by zlib.compress in Python, and the same deflate data alone
+        let zlib = [
+            0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00, 0x06, 0x2c, 0x02, 0x15,
+        ];
+        let mut extracted = String::new();
+        Zlib::default()
+            .wrap_reader(Box::new(&zlib[..]))?
+            .read_to_string(&mut extracted)?;
+        assert_eq!(extracted, "hello");
+
+        let raw = Zlib {
+            raw_deflate: true,
+            ..Zlib::default()
+        };
+        let mut extracted = String::new();
+        raw.wrap_reader(Box::new(&zlib[2..zlib.len() - 4]))?
+            .read_to_string(&mut extracted)?;
+        assert_eq!(extracted, "hello");
+
+        let mut compressed = Vec::new();
+        raw.wrap_writer(Box::new(&mut compressed))?
+            .write_all(b"hello")?;
+        assert_ne!(compressed[..2], zlib[..2]);
+        Ok(())
+    }
+}
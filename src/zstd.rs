@@ -0,0 +1,671 @@
+//! zstd compression backend
+
+use crate::{
+    limits::OutputLimits,
+    progress::{
+        copy_with_progress, progress_bar, ChunkSize, CountingWriter, ProgressArgs, ProgressDisplay,
+        ProgressHooks, ProgressObserver,
+    },
+    utils::*,
+};
+use clap::{Args, Subcommand};
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use zstd::stream::raw;
+use zstd::stream::write::Encoder;
+use zstd::stream::zio;
+
+#[derive(Args, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct ZstdArgs {
+    #[command(subcommand)]
+    pub command: Option<ZstdCommand>,
+
+    #[clap(flatten)]
+    pub common_args: CommonArgs,
+
+    #[clap(flatten)]
+    pub progress_args: ProgressArgs,
+
+    /// Level of compression.
+    /// This is an int 1-22, with 1 being the fastest and 22 being the highest compression.
+    /// Levels 20-22 are the "ultra" levels, which use larger windows and much more memory.
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(i32).range(1..=22))]
+    pub level: i32,
+
+    /// Long distance matching with a window of 2^WINDOW_LOG bytes, 2^27 (128MiB) by default.
+    /// This finds repeats far apart in big files like VM images. Files compressed with a window
+    /// above 2^27 must also be extracted with --long and at least the same window.
+    #[arg(
+        long,
+        value_name = "WINDOW_LOG",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "27",
+        value_parser = clap::value_parser!(u32).range(10..=31)
+    )]
+    pub long: Option<u32>,
+
+    /// Tune the level while compressing, starting at --level, to keep up with the output.
+    /// The level rises while writes to a slow pipe or network wait, and falls while the
+    /// compression itself is the bottleneck, like `zstd --adapt`.
+    #[arg(long)]
+    pub adapt: bool,
+
+    /// Dictionary to compress or extract with, created by `cmprss zstd train`.
+    /// Files compressed with a dictionary can only be extracted with the same dictionary.
+    #[arg(long, value_name = "FILE")]
+    pub dictionary: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ZstdCommand {
+    /// Train a dictionary on sample files, for compressing many small similar files
+    Train(TrainArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct TrainArgs {
+    /// Sample files, or directories to read them from recursively
+    #[arg(required = true)]
+    pub samples: Vec<PathBuf>,
+
+    /// Maximum size of the dictionary.
+    /// Accepts a plain number of bytes, or a size like '64k' or '1mb'
+    #[arg(long, default_value = "110k")]
+    pub max_dict_size: ChunkSize,
+
+    /// File to write the dictionary to
+    #[arg(short, long)]
+    pub output: PathBuf,
+}
+
+/// Train a zstd dictionary on the sample files
+pub fn train(args: &TrainArgs) -> Result<(), io::Error> {
+    if args.output.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", args.output.display()),
+        ));
+    }
+    let mut samples = Vec::new();
+    for path in &args.samples {
+        collect_samples(path, &mut samples)?;
+    }
+    if samples.is_empty() {
+        return Err(io::Error::other(
+            "no sample files to train the dictionary on",
+        ));
+    }
+    let dictionary = zstd::dict::from_files(&samples, args.max_dict_size.size_in_bytes)?;
+    fs::write(&args.output, dictionary)
+}
+
+/// Add the files at path to the samples, walking directories in a stable order
+fn collect_samples(path: &Path, samples: &mut Vec<PathBuf>) -> Result<(), io::Error> {
+    if !path.is_dir() {
+        samples.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut children = fs::read_dir(path)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    children.sort();
+    for child in children {
+        collect_samples(&child, samples)?;
+    }
+    Ok(())
+}
+
+pub struct Zstd {
+    pub level: i32, // 1-22
+    pub threads: u32,
+    pub dictionary: Option<Vec<u8>>,
+    /// Window log of long distance matching, which is also the largest window to extract with
+    pub long: Option<u32>,
+    /// Tune the level to the speed of the output
+    pub adapt: bool,
+    pub progress_args: ProgressArgs,
+    pub hooks: ProgressHooks,
+}
+
+impl Default for Zstd {
+    fn default() -> Self {
+        Zstd {
+            level: 3,
+            threads: default_threads(),
+            dictionary: None,
+            long: None,
+            adapt: false,
+            progress_args: ProgressArgs::default(),
+            hooks: ProgressHooks::default(),
+        }
+    }
+}
+
+impl Zstd {
+    pub fn new(args: &ZstdArgs) -> Result<Zstd, io::Error> {
+        let dictionary = match &args.dictionary {
+            Some(path) => Some(
+                fs::read(path)
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?,
+            ),
+            None => None,
+        };
+        Ok(Zstd {
+            level: args.level,
+            threads: args.common_args.threads.unwrap_or_else(default_threads),
+            dictionary,
+            long: args.long,
+            adapt: args.adapt,
+            progress_args: args.progress_args,
+            hooks: ProgressHooks::default(),
+        })
+    }
+
+    fn encoder<W: Write>(&self, writer: W) -> Result<Encoder<'static, W>, io::Error> {
+        if !(1..=22).contains(&self.level) {
+            return Err(io::Error::other("Invalid compression level. Must be 1-22."));
+        }
+        let mut encoder = match &self.dictionary {
+            Some(dictionary) => Encoder::with_dictionary(writer, self.level, dictionary)?,
+            None => Encoder::new(writer, self.level)?,
+        };
+        // Level changes only apply mid-frame to the worker threads
+        if self.threads > 1 || self.adapt {
+            encoder.multithread(self.threads.max(1))?;
+        }
+        if let Some(window_log) = self.long {
+            encoder.long_distance_matching(true)?;
+            encoder.window_log(window_log)?;
+        }
+        Ok(encoder)
+    }
+
+    fn adaptive<W: Write>(&self, writer: W) -> Result<Adaptive<W>, io::Error> {
+        let encoder = self.encoder(TimedWriter {
+            inner: writer,
+            blocked: Duration::ZERO,
+        })?;
+        Ok(Adaptive {
+            encoder,
+            level: self.level,
+            window_start: Instant::now(),
+            window_blocked: Duration::ZERO,
+        })
+    }
+
+    fn raw_decoder(&self) -> Result<raw::Decoder<'static>, io::Error> {
+        let mut decoder = match &self.dictionary {
+            Some(dictionary) => raw::Decoder::with_dictionary(dictionary)?,
+            None => raw::Decoder::new()?,
+        };
+        if let Some(window_log) = self.long {
+            decoder.set_parameter(raw::DParameter::WindowLogMax(window_log))?;
+        }
+        Ok(decoder)
+    }
+}
+
+/// Writer that measures the time spent waiting on the inner writer
+struct TimedWriter<W> {
+    inner: W,
+    blocked: Duration,
+}
+
+impl<W: Write> Write for TimedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let start = Instant::now();
+        let result = self.inner.write(buf);
+        self.blocked += start.elapsed();
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let start = Instant::now();
+        let result = self.inner.flush();
+        self.blocked += start.elapsed();
+        result
+    }
+}
+
+/// The slowest and fastest levels --adapt moves between, leaving out the ultra levels
+const ADAPT_LEVELS: std::ops::RangeInclusive<i32> = 1..=19;
+/// How often --adapt reconsiders the level
+const ADAPT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// zstd encoder that tunes the level to the speed of its output
+/// Waiting on the output for most of an interval means there is time to compress harder, and
+/// barely waiting means the compression is holding the output back.
+struct Adaptive<W: Write> {
+    encoder: Encoder<'static, TimedWriter<W>>,
+    level: i32,
+    window_start: Instant,
+    window_blocked: Duration,
+}
+
+impl<W: Write> Adaptive<W> {
+    fn adapt(&mut self) -> io::Result<()> {
+        let elapsed = self.window_start.elapsed();
+        if elapsed < ADAPT_INTERVAL {
+            return Ok(());
+        }
+        let blocked = self.encoder.get_ref().blocked;
+        let waiting = (blocked - self.window_blocked).as_secs_f64() / elapsed.as_secs_f64();
+        let level = if waiting > 0.5 {
+            self.level + 1
+        } else if waiting < 0.05 {
+            self.level - 1
+        } else {
+            self.level
+        };
+        if level != self.level && ADAPT_LEVELS.contains(&level) {
+            crate::debug!("zstd level {} -> {}", self.level, level);
+            self.encoder
+                .set_parameter(zstd::zstd_safe::CParameter::CompressionLevel(level))?;
+            self.level = level;
+        }
+        self.window_start = Instant::now();
+        self.window_blocked = blocked;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Adaptive<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.encoder.write(buf)?;
+        self.adapt()?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder.flush()
+    }
+}
+
+impl<W: Write> Drop for Adaptive<W> {
+    fn drop(&mut self) {
+        let _ = self.encoder.do_finish();
+    }
+}
+
+/// Point to --long when a file needs a larger window than the decoder allows
+fn window_error(e: io::Error) -> io::Error {
+    if e.to_string().contains("Frame requires too much memory") {
+        return io::Error::other(format!(
+            "{}, it was compressed with a large window and needs --long=WINDOW_LOG to extract",
+            e
+        ));
+    }
+    e
+}
+
+impl Compressor for Zstd {
+    /// The standard extension for the zstd format.
+    fn extension(&self) -> &str {
+        "zst"
+    }
+
+    /// Full name for zstd.
+    fn name(&self) -> &str {
+        "zstd"
+    }
+
+    fn is_stream(&self) -> bool {
+        true
+    }
+
+    fn set_limits(&mut self, limits: OutputLimits) {
+        self.hooks.limits = limits;
+    }
+
+    fn set_observer(&mut self, observer: Arc<dyn ProgressObserver>) {
+        self.hooks.observer = Some(observer);
+    }
+
+    fn set_level(&mut self, level: i32) {
+        self.level = level;
+    }
+
+    fn level(&self) -> Option<i32> {
+        Some(self.level)
+    }
+
+    fn set_progress(&mut self, progress: ProgressDisplay) {
+        self.progress_args.progress = progress;
+    }
+
+    fn progress(&self) -> ProgressDisplay {
+        self.progress_args.progress
+    }
+
+    fn set_threads(&mut self, threads: u32) {
+        self.threads = threads;
+    }
+
+    fn threads(&self) -> Option<u32> {
+        Some(self.threads)
+    }
+
+    /// Compress an input file or pipe to a zstd archive
+    fn compress(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        let (mut input_stream, file_size) = input.into_stream()?;
+        let bar = progress_bar(file_size, self.progress_args.progress, &output, &self.hooks);
+        let output_stream = output.into_stream()?;
+        if self.adapt {
+            let mut encoder = self.adaptive(CountingWriter::new(output_stream))?;
+            copy_with_progress(
+                &mut input_stream,
+                &mut encoder,
+                self.progress_args.chunk_size.size_in_bytes,
+                &self.hooks,
+                bar.as_ref(),
+                |e| e.encoder.get_ref().inner.count(),
+            )?;
+            encoder.encoder.do_finish()?;
+            return encoder.flush();
+        }
+        let mut encoder = self.encoder(CountingWriter::new(output_stream))?;
+        copy_with_progress(
+            &mut input_stream,
+            &mut encoder,
+            self.progress_args.chunk_size.size_in_bytes,
+            &self.hooks,
+            bar.as_ref(),
+            |e| e.get_ref().count(),
+        )?;
+        encoder.finish()?.flush()
+    }
+
+    /// Extract a zstd archive to a file or pipe
+    fn extract(&self, input: CmprssInput, output: CmprssOutput) -> Result<(), io::Error> {
+        let (mut input_stream, file_size) = input.into_stream()?;
+        let bar = progress_bar(file_size, self.progress_args.progress, &output, &self.hooks);
+        let output_stream = self.hooks.limits.writer(output.into_stream()?, file_size);
+        // The raw writer reports a truncated stream when finishing, unlike write::Decoder
+        let mut decoder = zio::Writer::new(CountingWriter::new(output_stream), self.raw_decoder()?);
+        copy_with_progress(
+            &mut input_stream,
+            &mut decoder,
+            self.progress_args.chunk_size.size_in_bytes,
+            &self.hooks,
+            bar.as_ref(),
+            |d| d.writer().count(),
+        )
+        .map_err(window_error)?;
+        decoder.finish().map_err(window_error)?;
+        decoder.flush()
+    }
+
+    /// Wrap a writer with a zstd encoder
+    fn wrap_writer<'a>(
+        &self,
+        writer: Box<dyn Write + Send + 'a>,
+    ) -> Result<Box<dyn Write + Send + 'a>, io::Error> {
+        if self.adapt {
+            return Ok(Box::new(self.adaptive(writer)?));
+        }
+        Ok(Box::new(self.encoder(writer)?.auto_finish()))
+    }
+
+    /// Wrap a reader with a zstd decoder
+    fn wrap_reader<'a>(
+        &self,
+        reader: Box<dyn Read + Send + 'a>,
+    ) -> Result<Box<dyn Read + Send + 'a>, io::Error> {
+        let mut decoder = match &self.dictionary {
+            Some(dictionary) => {
+                zstd::stream::read::Decoder::with_dictionary(BufReader::new(reader), dictionary)?
+            }
+            None => zstd::stream::read::Decoder::new(reader)?,
+        };
+        if let Some(window_log) = self.long {
+            decoder.window_log_max(window_log)?;
+        }
+        Ok(Box::new(decoder))
+    }
+
+    /// Compress a stream, finishing the zstd stream explicitly to catch errors
+    fn compress_stream(
+        &self,
+        reader: &mut (dyn Read + Send),
+        writer: &mut (dyn Write + Send),
+    ) -> Result<(), io::Error> {
+        if self.adapt {
+            let mut encoder = self.adaptive(writer)?;
+            io::copy(reader, &mut encoder)?;
+            encoder.encoder.do_finish()?;
+            return encoder.flush();
+        }
+        let mut encoder = self.encoder(writer)?;
+        io::copy(reader, &mut encoder)?;
+        encoder.finish()?.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use predicates::prelude::*;
+
+    #[test]
+    fn roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Zstd::default();
+
+        let file = assert_fs::NamedTempFile::new("test.txt")?;
+        file.write_str("garbage data for testing")?;
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("archive.".to_owned() + compressor.extension());
+        archive.assert(predicate::path::missing());
+
+        // Roundtrip compress/extract
+        compressor.compress(
+            CmprssInput::Path(vec![file.path().to_path_buf()]),
+            CmprssOutput::Path(archive.path().to_path_buf()),
+        )?;
+        archive.assert(predicate::path::is_file());
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(working_dir.child("test.txt").path().to_path_buf()),
+        )?;
+
+        // Assert the files are identical
+        working_dir
+            .child("test.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn multithreaded_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Zstd {
+            threads: 4,
+            ..Zstd::default()
+        };
+        // Enough data to be split between the threads
+        let data: Vec<u8> = (0..4 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+
+        let mut compressed = Vec::new();
+        compressor.compress_stream(&mut &data[..], &mut compressed)?;
+
+        let mut extracted = Vec::new();
+        compressor
+            .wrap_reader(Box::new(&compressed[..]))?
+            .read_to_end(&mut extracted)?;
+        assert_eq!(extracted, data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stream_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Zstd::default();
+        let data = b"garbage data for testing";
+
+        let mut compressed = Vec::new();
+        compressor.compress_stream(&mut &data[..], &mut compressed)?;
+        assert_ne!(compressed, data);
+
+        let mut extracted = Vec::new();
+        compressor
+            .wrap_reader(Box::new(&compressed[..]))?
+            .read_to_end(&mut extracted)?;
+        assert_eq!(extracted, data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_stream() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Zstd::default();
+        let mut compressed = Vec::new();
+        compressor.compress_stream(&mut &b"garbage data for testing"[..], &mut compressed)?;
+        compressed.truncate(compressed.len() - 4);
+
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("archive.zst");
+        archive.write_binary(&compressed)?;
+        let result = compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(working_dir.child("test.txt").path().to_path_buf()),
+        );
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    /// Compress small similar files with a trained dictionary, which is needed to extract them
+    #[test]
+    fn dictionary_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let samples: Vec<Vec<u8>> = (0..1000)
+            .map(|i| {
+                format!(
+                    r#"{{"id":{i},"event":"login","user":"user{}","ok":true}}"#,
+                    i % 37
+                )
+                .into_bytes()
+            })
+            .collect();
+        let compressor = Zstd {
+            dictionary: Some(zstd::dict::from_samples(&samples, 4096)?),
+            ..Zstd::default()
+        };
+        let data = br#"{"id":1234,"event":"login","user":"user5","ok":true}"#;
+
+        let mut compressed = Vec::new();
+        compressor.compress_stream(&mut &data[..], &mut compressed)?;
+        let mut plain = Vec::new();
+        Zstd::default().compress_stream(&mut &data[..], &mut plain)?;
+        assert!(compressed.len() < plain.len());
+
+        let mut extracted = Vec::new();
+        compressor
+            .wrap_reader(Box::new(&compressed[..]))?
+            .read_to_end(&mut extracted)?;
+        assert_eq!(extracted, data);
+
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("event.json.zst");
+        archive.write_binary(&compressed)?;
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(working_dir.child("event.json").path().to_path_buf()),
+        )?;
+        working_dir.child("event.json").assert(&data[..]);
+
+        let result = Zstd::default().extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(working_dir.child("missing.json").path().to_path_buf()),
+        );
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    /// A window above the default can only be extracted with a window at least as large
+    #[test]
+    fn long_distance_matching() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Zstd {
+            long: Some(28),
+            ..Zstd::default()
+        };
+        let data = b"garbage data for testing ".repeat(1000);
+        let mut compressed = Vec::new();
+        compressor.compress_stream(&mut &data[..], &mut compressed)?;
+
+        let mut extracted = Vec::new();
+        compressor
+            .wrap_reader(Box::new(&compressed[..]))?
+            .read_to_end(&mut extracted)?;
+        assert_eq!(extracted, data);
+
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("archive.zst");
+        archive.write_binary(&compressed)?;
+        let result = Zstd::default().extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(working_dir.child("small.txt").path().to_path_buf()),
+        );
+        assert!(result.unwrap_err().to_string().contains("--long"));
+        compressor.extract(
+            CmprssInput::Path(vec![archive.path().to_path_buf()]),
+            CmprssOutput::Path(working_dir.child("test.txt").path().to_path_buf()),
+        )?;
+        assert_eq!(std::fs::read(working_dir.child("test.txt").path())?, data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn adaptive_level() -> Result<(), Box<dyn std::error::Error>> {
+        let compressor = Zstd {
+            adapt: true,
+            ..Zstd::default()
+        };
+        let mut encoder = compressor.adaptive(Vec::new())?;
+
+        // Waiting on the output for most of the interval raises the level
+        encoder.window_start -= Duration::from_secs(1);
+        encoder.encoder.get_mut().blocked = Duration::from_millis(900);
+        encoder.write_all(b"garbage data for testing")?;
+        assert_eq!(encoder.level, 4);
+
+        // And not waiting lowers it
+        encoder.window_start -= Duration::from_secs(1);
+        encoder.write_all(b"garbage data for testing")?;
+        assert_eq!(encoder.level, 3);
+
+        // Never below the fastest level
+        encoder.level = 1;
+        encoder.window_start -= Duration::from_secs(1);
+        encoder.write_all(b"garbage data for testing")?;
+        assert_eq!(encoder.level, 1);
+
+        encoder.encoder.do_finish()?;
+        let compressed = std::mem::take(&mut encoder.encoder.get_mut().inner);
+        let mut extracted = Vec::new();
+        compressor
+            .wrap_reader(Box::new(&compressed[..]))?
+            .read_to_end(&mut extracted)?;
+        assert_eq!(extracted, b"garbage data for testing".repeat(3));
+
+        Ok(())
+    }
+
+    // Fail with a compression level of 23
+    #[test]
+    fn invalid_compression_level() {
+        let compressor = Zstd {
+            level: 23,
+            ..Zstd::default()
+        };
+        let mut compressed = Vec::new();
+        let result = compressor.compress_stream(&mut &b"data"[..], &mut compressed);
+        assert!(result.is_err());
+    }
+}
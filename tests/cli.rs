@@ -270,6 +270,7 @@ mod cli {
     /// cmprss xz --extract --ignore-pipes test.txt.xz
     /// ```
     #[test]
+    #[cfg(feature = "xz")]
     fn xz_roundtrip_explicit() -> Result<(), Box<dyn std::error::Error>> {
         let file = assert_fs::NamedTempFile::new("test.txt")?;
         file.write_str("garbage data for testing")?;
@@ -312,6 +313,7 @@ mod cli {
     /// cat test.txt.xz | cmprss xz --extract out.txt
     /// ```
     #[test]
+    #[cfg(feature = "xz")]
     fn xz_roundtrip_stdin() -> Result<(), Box<dyn std::error::Error>> {
         let file = assert_fs::NamedTempFile::new("test.txt")?;
         file.write_str("garbage data for testing")?;
@@ -355,6 +357,7 @@ mod cli {
     /// cmprss xz --extract test.txt.xz > out.txt
     /// ```
     #[test]
+    #[cfg(feature = "xz")]
     fn xz_roundtrip_stdout() -> Result<(), Box<dyn std::error::Error>> {
         let file = assert_fs::NamedTempFile::new("test.txt")?;
         file.write_str("garbage data for testing")?;
@@ -402,6 +405,7 @@ mod cli {
     /// cmprss bzip2 --extract --ignore-pipes test.txt.bz2
     /// ```
     #[test]
+    #[cfg(feature = "bzip2")]
     fn bzip2_roundtrip_explicit() -> Result<(), Box<dyn std::error::Error>> {
         let file = assert_fs::NamedTempFile::new("test.txt")?;
         file.write_str("garbage data for testing")?;
@@ -436,6 +440,62 @@ mod cli {
         Ok(())
     }
 
+    /// Brotli roundtrip using the alias and filename inference
+    /// Compressing: input = test.txt, output = test.txt.br
+    /// Extracting:  input = test.txt.br, output = out.txt
+    ///
+    /// ``` bash
+    /// cmprss br --quality 5 --window 18 test.txt test.txt.br
+    /// cmprss test.txt.br out.txt
+    /// ```
+    #[test]
+    fn brotli_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let file = assert_fs::NamedTempFile::new("test.txt")?;
+        file.write_str("garbage data for testing")?;
+
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("test.txt.br");
+        archive.assert(predicate::path::missing());
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(&working_dir)
+            .arg("br")
+            .arg("--quality")
+            .arg("5")
+            .arg("--window")
+            .arg("18")
+            .arg(file.path())
+            .arg(archive.path());
+        compress.assert().success();
+        archive.assert(predicate::path::is_file());
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(&working_dir)
+            .arg("--ignore-pipes")
+            .arg(archive.path())
+            .arg("out.txt");
+        extract.assert().success();
+
+        // Assert the files are identical
+        working_dir
+            .child("out.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        let mut invalid = Command::cargo_bin("cmprss")?;
+        invalid
+            .current_dir(&working_dir)
+            .arg("brotli")
+            .arg("--quality")
+            .arg("12")
+            .arg(file.path())
+            .arg("invalid.br");
+        invalid.assert().failure();
+
+        Ok(())
+    }
+
     /// Bzip2 roundtrip using stdin
     /// Compressing: input = stdin, output = test.txt.bz2
     /// Extracting:  input = stdin(test.txt.bz2), output = test.txt
@@ -445,6 +505,7 @@ mod cli {
     /// cat test.txt.bz2 | cmprss bzip2 --extract out.txt
     /// ```
     #[test]
+    #[cfg(feature = "bzip2")]
     fn bzip2_roundtrip_stdin() -> Result<(), Box<dyn std::error::Error>> {
         let file = assert_fs::NamedTempFile::new("test.txt")?;
         file.write_str("garbage data for testing")?;
@@ -489,6 +550,7 @@ mod cli {
     /// cmprss bzip2 --extract test.txt.bz2 > out.txt
     /// ```
     #[test]
+    #[cfg(feature = "bzip2")]
     fn bzip2_roundtrip_stdout() -> Result<(), Box<dyn std::error::Error>> {
         let file = assert_fs::NamedTempFile::new("test.txt")?;
         file.write_str("garbage data for testing")?;
@@ -870,6 +932,66 @@ mod cli {
         Ok(())
     }
 
+    /// Magic roundtrip with tar.gz in a single step each way
+    ///
+    /// ``` bash
+    /// cmprss dir archive.tar.gz
+    /// cmprss archive.tar.gz output_dir
+    /// cmprss archive.tgz
+    /// ```
+    #[test]
+    fn magic_roundtrip_tar_gz_single_step() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        dir.child("test.txt")
+            .write_str("garbage data for testing")?;
+        dir.child("nested/test2.txt")
+            .write_str("more garbage data for testing")?;
+        let archive = working_dir.child("archive.tar.gz");
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(&working_dir)
+            .arg("--ignore-pipes")
+            .arg("dir")
+            .arg("archive.tar.gz");
+        compress.assert().success();
+        archive.assert(predicate::path::is_file());
+
+        let output_dir = working_dir.child("output_dir");
+        output_dir.create_dir_all()?;
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(&working_dir)
+            .arg("--ignore-pipes")
+            .arg("archive.tar.gz")
+            .arg("output_dir");
+        extract.assert().success();
+        output_dir
+            .child("dir/test.txt")
+            .assert(predicate::path::eq_file(dir.child("test.txt").path()));
+        output_dir
+            .child("dir/nested/test2.txt")
+            .assert(predicate::path::eq_file(
+                dir.child("nested/test2.txt").path(),
+            ));
+
+        // The short extension extracts to the current directory
+        let extract_dir = assert_fs::TempDir::new()?;
+        std::fs::copy(archive.path(), extract_dir.child("archive.tgz").path())?;
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(&extract_dir)
+            .arg("--ignore-pipes")
+            .arg("archive.tgz");
+        extract.assert().success();
+        extract_dir
+            .child("dir/test.txt")
+            .assert(predicate::path::eq_file(dir.child("test.txt").path()));
+
+        Ok(())
+    }
+
     /// Magic roundtrip with tar.gz using pipes
     /// Infer things as much as possible
     /// Compressing: input = test.txt + test2.txt, output = test.tar.gz
@@ -942,4 +1064,4768 @@ mod cli {
 
         Ok(())
     }
+
+    /// Tar extraction reads stdin as a stream, so it works from a pipe that can't seek
+    ///
+    /// ``` bash
+    /// cat archive.tar | cmprss tar --extract out
+    /// ```
+    #[test]
+    fn tar_extract_from_pipe() -> Result<(), Box<dyn std::error::Error>> {
+        use std::io::Write;
+
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("dir/test.txt");
+        file.write_str("garbage data for testing")?;
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "tar",
+            "--ignore-pipes",
+            "dir",
+            "archive.tar",
+        ]);
+        compress.assert().success();
+        let archive = std::fs::read(working_dir.child("archive.tar").path())?;
+
+        let output_dir = working_dir.child("out");
+        output_dir.create_dir_all()?;
+        let mut extract = Command::cargo_bin("cmprss")?
+            .current_dir(working_dir.path())
+            .args(["tar", "--ignore-stdout", "--extract", "out"])
+            .stdin(Stdio::piped())
+            .spawn()?;
+        // Feed the archive in small pieces, like a slow producer
+        let mut stdin = extract.stdin.take().unwrap();
+        for chunk in archive.chunks(100) {
+            stdin.write_all(chunk)?;
+        }
+        drop(stdin);
+        assert!(extract.wait()?.success());
+        output_dir
+            .child("dir/test.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// Print statistics after compressing
+    ///
+    /// ``` bash
+    /// cmprss gzip --stats test.txt archive.gz
+    /// cmprss gzip --stats=json --force test.txt archive.gz
+    /// ```
+    #[test]
+    fn gzip_stats() -> Result<(), Box<dyn std::error::Error>> {
+        let file = assert_fs::NamedTempFile::new("test.txt")?;
+        file.write_str("garbage data for testing")?;
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("archive.gz");
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .arg("gzip")
+            .arg("--stats")
+            .arg(file.path())
+            .arg(archive.path());
+        compress
+            .assert()
+            .success()
+            .stdout(predicate::str::is_empty())
+            .stderr(predicate::str::starts_with("gzip: 24 B => "));
+        archive.assert(predicate::path::is_file());
+
+        // Overwriting the output counts all of the new file
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .args(["gzip", "--stats=json", "--force"])
+            .arg(file.path())
+            .arg(archive.path());
+        let output = compress.assert().success().get_output().stdout.clone();
+        let size = std::fs::metadata(archive.path())?.len();
+        assert!(String::from_utf8(output)?.contains(&format!(r#""output_bytes":{},"#, size)));
+
+        Ok(())
+    }
+
+    /// Print statistics as JSON, counting the bytes read from stdin
+    ///
+    /// ``` bash
+    /// cat test.txt | cmprss gzip --stats=json archive.gz
+    /// ```
+    #[test]
+    fn json_stats() -> Result<(), Box<dyn std::error::Error>> {
+        let file = assert_fs::NamedTempFile::new("test.txt")?;
+        file.write_str("garbage data for testing")?;
+        let working_dir = assert_fs::TempDir::new()?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(&working_dir)
+            .args(["gzip", "--stats=json", "archive.gz"])
+            .stdin(Stdio::from(File::open(file.path())?));
+        compress
+            .assert()
+            .success()
+            .stdout(predicate::str::is_match(
+                r#"^\{"format":"gzip","action":"compress","input_bytes":24,"output_bytes":\d+,"ratio":[0-9.]+,"seconds":[0-9.]+,"mb_per_sec":[0-9.]+\}\n$"#,
+            )?);
+
+        Ok(())
+    }
+
+    /// Serve each of the raw HTTP responses to one connection on a random local port
+    /// Returns the port and a receiver for the headers of each request
+    #[cfg(any(feature = "http", feature = "s3"))]
+    fn serve(
+        responses: Vec<Vec<u8>>,
+    ) -> Result<(u16, std::sync::mpsc::Receiver<String>), Box<dyn std::error::Error>> {
+        use std::io::{BufRead, BufReader, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request = String::new();
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap() <= 2 {
+                        break;
+                    }
+                    request.push_str(&line);
+                }
+                // The caller may not care about the requests
+                let _ = sender.send(request);
+                let mut stream = reader.into_inner();
+                stream.write_all(&response).unwrap();
+            }
+        });
+        Ok((port, receiver))
+    }
+
+    /// Serve the given data once over HTTP on a random local port, returning the port
+    #[cfg(feature = "http")]
+    fn serve_once(data: Vec<u8>) -> Result<u16, Box<dyn std::error::Error>> {
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            data.len()
+        )
+        .into_bytes();
+        response.extend(data);
+        Ok(serve(vec![response])?.0)
+    }
+
+    /// Extract a gzip file directly from a URL
+    ///
+    /// ``` bash
+    /// cmprss gzip test.txt test.txt.gz
+    /// cmprss http://127.0.0.1:port/test.txt.gz out.txt
+    /// ```
+    #[test]
+    #[cfg(feature = "http")]
+    fn gzip_extract_url() -> Result<(), Box<dyn std::error::Error>> {
+        let file = assert_fs::NamedTempFile::new("test.txt")?;
+        file.write_str("garbage data for testing")?;
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("test.txt.gz");
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.arg("gzip").arg(file.path()).arg(archive.path());
+        compress.assert().success();
+
+        let port = serve_once(std::fs::read(archive.path())?)?;
+        let output = working_dir.child("out.txt");
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .arg("--ignore-pipes")
+            .arg(format!("http://127.0.0.1:{}/test.txt.gz", port))
+            .arg(output.path());
+        extract.assert().success();
+
+        output.assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// Resume a download that is cut off partway through
+    ///
+    /// ``` bash
+    /// cmprss gzip test.txt test.txt.gz
+    /// cmprss http://127.0.0.1:port/test.txt.gz out.txt
+    /// ```
+    #[test]
+    #[cfg(feature = "http")]
+    fn gzip_extract_url_resume() -> Result<(), Box<dyn std::error::Error>> {
+        let file = assert_fs::NamedTempFile::new("test.txt")?;
+        file.write_str("garbage data for testing")?;
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("test.txt.gz");
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.arg("gzip").arg(file.path()).arg(archive.path());
+        compress.assert().success();
+
+        // The first response closes the connection halfway through the body
+        let data = std::fs::read(archive.path())?;
+        let half = data.len() / 2;
+        let mut first = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            data.len()
+        )
+        .into_bytes();
+        first.extend(&data[..half]);
+        let mut second = format!(
+            "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\nConnection: close\r\n\r\n",
+            data.len() - half,
+            half,
+            data.len() - 1,
+            data.len()
+        )
+        .into_bytes();
+        second.extend(&data[half..]);
+        let (port, requests) = serve(vec![first, second])?;
+
+        let output = working_dir.child("out.txt");
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .arg("--ignore-pipes")
+            .arg("--retries=1")
+            .arg(format!("http://127.0.0.1:{}/test.txt.gz", port))
+            .arg(output.path());
+        extract.assert().success();
+
+        output.assert(predicate::path::eq_file(file.path()));
+        assert!(!requests.recv()?.to_lowercase().contains("range:"));
+        assert!(requests
+            .recv()?
+            .to_lowercase()
+            .contains(&format!("range: bytes={}-", half)));
+
+        Ok(())
+    }
+
+    /// Keep retrying a download that is cut off several times, as long as each retry gets more
+    ///
+    /// ``` bash
+    /// cmprss --retries=1 http://127.0.0.1:port/test.txt.gz out.txt
+    /// ```
+    #[test]
+    #[cfg(feature = "http")]
+    fn gzip_extract_url_retries_progress() -> Result<(), Box<dyn std::error::Error>> {
+        let file = assert_fs::NamedTempFile::new("test.txt")?;
+        file.write_str("garbage data for testing")?;
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("test.txt.gz");
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.arg("gzip").arg(file.path()).arg(archive.path());
+        compress.assert().success();
+
+        // Each response but the last closes the connection after a third of the body
+        let data = std::fs::read(archive.path())?;
+        let cuts = [0, data.len() / 3, data.len() * 2 / 3, data.len()];
+        let responses = cuts
+            .windows(2)
+            .map(|cut| {
+                let (start, end) = (cut[0], cut[1]);
+                let mut response = match start {
+                    0 => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        data.len()
+                    ),
+                    _ => format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\nConnection: close\r\n\r\n",
+                        data.len() - start,
+                        start,
+                        data.len() - 1,
+                        data.len()
+                    ),
+                }
+                .into_bytes();
+                response.extend(&data[start..end]);
+                response
+            })
+            .collect();
+        let (port, _) = serve(responses)?;
+
+        let output = working_dir.child("out.txt");
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .arg("--ignore-pipes")
+            .arg("--retries=1")
+            .arg(format!("http://127.0.0.1:{}/test.txt.gz", port))
+            .arg(output.path());
+        extract.assert().success();
+
+        output.assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// Downloads that only end by the server closing the connection can't be known to be
+    /// complete, unlike those with a length or in chunks
+    ///
+    /// ``` bash
+    /// cmprss http://127.0.0.1:port/test.txt out.txt.gz
+    /// ```
+    #[test]
+    #[cfg(feature = "http")]
+    fn url_without_length() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let data = "garbage data for testing";
+
+        let mut closed = b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n".to_vec();
+        closed.extend(data.as_bytes());
+        let (port, _) = serve(vec![closed])?;
+        let output = working_dir.child("closed.txt.gz");
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .args(["gzip", "--ignore-pipes"])
+            .arg(format!("http://127.0.0.1:{}/test.txt", port))
+            .arg(output.path());
+        compress
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("may be incomplete"));
+        output.assert(predicate::path::missing());
+
+        let mut chunked =
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n".to_vec();
+        chunked.extend(format!("{:x}\r\n{}\r\n0\r\n\r\n", data.len(), data).as_bytes());
+        let (port, _) = serve(vec![chunked])?;
+        let output = working_dir.child("chunked.txt.gz");
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .args(["gzip", "--ignore-pipes"])
+            .arg(format!("http://127.0.0.1:{}/test.txt", port))
+            .arg(output.path());
+        compress.assert().success();
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .args(["gzip", "--decompress", "--stdout"])
+            .arg(output.path());
+        extract.assert().success().stdout(data);
+
+        Ok(())
+    }
+
+    /// Upload to S3 using a multipart upload
+    ///
+    /// ``` bash
+    /// cmprss gzip test.txt s3://bucket/archive.gz
+    /// ```
+    #[test]
+    #[cfg(feature = "s3")]
+    fn gzip_s3_output() -> Result<(), Box<dyn std::error::Error>> {
+        let file = assert_fs::NamedTempFile::new("test.txt")?;
+        file.write_str("garbage data for testing")?;
+
+        let response = |headers: &str, body: &str| {
+            format!(
+                "HTTP/1.1 200 OK\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                headers,
+                body.len(),
+                body
+            )
+            .into_bytes()
+        };
+        let (port, requests) = serve(vec![
+            response(
+                "",
+                "<InitiateMultipartUploadResult><UploadId>ID</UploadId></InitiateMultipartUploadResult>",
+            ),
+            // A failed part is sent again
+            b"HTTP/1.1 503 Slow Down\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec(),
+            response("ETag: \"etag1\"\r\n", ""),
+            response("", "<CompleteMultipartUploadResult></CompleteMultipartUploadResult>"),
+        ])?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .env("AWS_ENDPOINT_URL", format!("http://127.0.0.1:{}", port))
+            .env("AWS_ACCESS_KEY_ID", "key")
+            .env("AWS_SECRET_ACCESS_KEY", "secret")
+            .arg("gzip")
+            .arg("--ignore-pipes")
+            .arg(file.path())
+            .arg("s3://bucket/archive.gz");
+        compress.assert().success();
+
+        assert!(requests
+            .recv()?
+            .starts_with("POST /bucket/archive.gz?uploads= "));
+        for _ in 0..2 {
+            assert!(requests
+                .recv()?
+                .starts_with("PUT /bucket/archive.gz?partNumber=1&uploadId=ID "));
+        }
+        assert!(requests
+            .recv()?
+            .starts_with("POST /bucket/archive.gz?uploadId=ID "));
+
+        Ok(())
+    }
+
+    /// Stream the output over SSH, using a fake ssh that runs the command locally
+    ///
+    /// ``` bash
+    /// cmprss gzip test.txt ssh://host/path/archive.gz
+    /// ```
+    #[test]
+    #[cfg(unix)]
+    fn gzip_ssh_output() -> Result<(), Box<dyn std::error::Error>> {
+        use std::os::unix::fs::PermissionsExt;
+        let file = assert_fs::NamedTempFile::new("test.txt")?;
+        file.write_str("garbage data for testing")?;
+        let working_dir = assert_fs::TempDir::new()?;
+        let fake_ssh = working_dir.child("ssh");
+        // Called as `ssh host command`
+        fake_ssh.write_str("#!/bin/sh\nfor command; do :; done\nexec sh -c \"$command\"\n")?;
+        std::fs::set_permissions(fake_ssh.path(), std::fs::Permissions::from_mode(0o755))?;
+        let archive = working_dir.child("archive.gz");
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .env("CMPRSS_SSH", fake_ssh.path())
+            .arg("gzip")
+            .arg("--ignore-pipes")
+            .arg(file.path())
+            .arg(format!("ssh://host{}", archive.path().display()));
+        compress.assert().success();
+        archive.assert(predicate::path::is_file());
+        working_dir
+            .child("archive.gz.part")
+            .assert(predicate::path::missing());
+
+        let output = working_dir.child("test.txt");
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .arg("gzip")
+            .arg("--extract")
+            .arg("--ignore-pipes")
+            .arg(archive.path())
+            .arg(output.path());
+        extract.assert().success();
+        output.assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// Plugin declared in the config file, used explicitly and through inference
+    ///
+    /// ``` bash
+    /// cmprss test.txt test.txt.up
+    /// cmprss upper --extract test.txt.up out.txt
+    /// ```
+    #[test]
+    #[cfg(unix)]
+    fn plugin_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let file = assert_fs::NamedTempFile::new("test.txt")?;
+        file.write_str("garbage data for testing")?;
+        let config_dir = assert_fs::TempDir::new()?;
+        config_dir.child("cmprss/config.toml").write_str(
+            r#"
+            [plugins.upper]
+            extension = "up"
+            compress = ["tr", "a-z", "A-Z"]
+            extract = ["tr", "A-Z", "a-z"]
+            "#,
+        )?;
+        let working_dir = assert_fs::TempDir::new()?;
+        let archive = working_dir.child("test.txt.up");
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .env("XDG_CONFIG_HOME", config_dir.path())
+            .arg("--ignore-pipes")
+            .arg(file.path())
+            .arg(archive.path());
+        compress.assert().success();
+        archive.assert("GARBAGE DATA FOR TESTING");
+
+        let output = working_dir.child("out.txt");
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .env("XDG_CONFIG_HOME", config_dir.path())
+            .arg("upper")
+            .arg("--extract")
+            .arg("--ignore-pipes")
+            .arg(archive.path())
+            .arg(output.path());
+        extract.assert().success();
+        output.assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// 7z through the system 7z, which is only used with --allow-external
+    ///
+    /// ``` bash
+    /// cmprss --allow-external dir backup.7z
+    /// cmprss --allow-external --extract backup.7z out
+    /// ```
+    #[test]
+    fn external_7z_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        if Command::new("7z").arg("i").output().is_err() {
+            // 7z is not installed
+            return Ok(());
+        }
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("dir/test.txt");
+        file.write_str("garbage data for testing")?;
+        let archive = working_dir.child("backup.7z");
+
+        let mut unsupported = Command::cargo_bin("cmprss")?;
+        unsupported
+            .current_dir(working_dir.path())
+            .arg("--ignore-pipes")
+            .arg("dir")
+            .arg("backup.7z");
+        unsupported.assert().failure();
+        archive.assert(predicate::path::missing());
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .arg("--allow-external")
+            .arg("--ignore-pipes")
+            .arg("dir")
+            .arg("backup.7z");
+        compress.assert().success();
+        archive.assert(predicate::path::is_file());
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .arg("--allow-external")
+            .arg("--extract")
+            .arg("--ignore-pipes")
+            .arg("backup.7z")
+            .arg("out");
+        extract.assert().success();
+        working_dir
+            .child("out/dir/test.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// Named pipes and devices are used as inputs and outputs even though they exist
+    ///
+    /// ``` bash
+    /// cmprss gzip test.txt /dev/null
+    /// cmprss gzip --extract fifo out.txt
+    /// ```
+    #[test]
+    #[cfg(unix)]
+    fn gzip_special_files() -> Result<(), Box<dyn std::error::Error>> {
+        let file = assert_fs::NamedTempFile::new("test.txt")?;
+        file.write_str("garbage data for testing")?;
+        let working_dir = assert_fs::TempDir::new()?;
+
+        let mut discard = Command::cargo_bin("cmprss")?;
+        discard
+            .arg("gzip")
+            .arg("--ignore-pipes")
+            .arg(file.path())
+            .arg("/dev/null");
+        discard.assert().success();
+
+        let archive = working_dir.child("test.txt.gz");
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .arg("gzip")
+            .arg("--ignore-pipes")
+            .arg(file.path())
+            .arg(archive.path());
+        compress.assert().success();
+
+        let fifo = working_dir.child("fifo");
+        assert!(Command::new("mkfifo").arg(fifo.path()).status()?.success());
+        let writer = {
+            let (archive, fifo) = (archive.to_path_buf(), fifo.to_path_buf());
+            std::thread::spawn(move || std::fs::copy(archive, fifo))
+        };
+        let output = working_dir.child("out.txt");
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .arg("gzip")
+            .arg("--extract")
+            .arg("--ignore-pipes")
+            .arg(fifo.path())
+            .arg(output.path());
+        extract.assert().success();
+        writer.join().unwrap()?;
+        output.assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// Zip roundtrip inferring the format from the filename
+    ///
+    /// ``` bash
+    /// cmprss dir archive.zip
+    /// cmprss archive.zip out
+    /// ```
+    #[test]
+    fn zip_roundtrip_implicit() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("dir/test.txt");
+        file.write_str("garbage data for testing")?;
+        let archive = working_dir.child("archive.zip");
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .arg("--ignore-pipes")
+            .arg("dir")
+            .arg("archive.zip");
+        compress.assert().success();
+        archive.assert(predicate::path::is_file());
+
+        let output_dir = working_dir.child("out");
+        output_dir.create_dir_all()?;
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .arg("--ignore-pipes")
+            .arg("archive.zip")
+            .arg("out");
+        extract.assert().success();
+        output_dir
+            .child("dir/test.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// Zip archives are streamed to a pipe without seeking
+    ///
+    /// ``` bash
+    /// cmprss zip dir | cat > archive.zip
+    /// cmprss archive.zip out
+    /// ```
+    #[test]
+    fn zip_roundtrip_pipe() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("dir/test.txt");
+        file.write_str("garbage data for testing")?;
+        working_dir.child("dir/empty").create_dir_all()?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["zip", "--ignore-stdin", "dir"]);
+        let output = compress.output()?;
+        assert!(output.status.success());
+        working_dir
+            .child("archive.zip")
+            .write_binary(&output.stdout)?;
+
+        let output_dir = working_dir.child("out");
+        output_dir.create_dir_all()?;
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "archive.zip", "out"]);
+        extract.assert().success();
+        output_dir
+            .child("dir/test.txt")
+            .assert(predicate::path::eq_file(file.path()));
+        output_dir
+            .child("dir/empty")
+            .assert(predicate::path::is_dir());
+
+        Ok(())
+    }
+
+    /// List the contents of archives, which fails for stream formats
+    ///
+    /// ``` bash
+    /// cmprss list archive.tar.gz
+    /// cmprss list archive.zip
+    /// cmprss list test.txt.gz
+    /// ```
+    #[test]
+    fn list_archives() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("dir/test.txt")
+            .write_str("garbage data for testing")?;
+
+        for archive in ["archive.tar.gz", "archive.zip"] {
+            let mut compress = Command::cargo_bin("cmprss")?;
+            compress
+                .current_dir(working_dir.path())
+                .arg("--ignore-pipes")
+                .arg("dir")
+                .arg(archive);
+            compress.assert().success();
+
+            let mut list = Command::cargo_bin("cmprss")?;
+            list.current_dir(working_dir.path())
+                .arg("list")
+                .arg(archive);
+            list.assert()
+                .success()
+                .stdout(predicate::str::contains("dir/test.txt"))
+                .stdout(predicate::str::contains(" 24 "));
+        }
+
+        let mut gzip = Command::cargo_bin("cmprss")?;
+        gzip.current_dir(working_dir.path())
+            .arg("gzip")
+            .arg("--ignore-pipes")
+            .arg("dir/test.txt")
+            .arg("test.txt.gz");
+        gzip.assert().success();
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.current_dir(working_dir.path())
+            .arg("list")
+            .arg("test.txt.gz");
+        list.assert()
+            .failure()
+            .stderr(predicate::str::contains("stream format"));
+
+        Ok(())
+    }
+
+    /// Print a single file from an archive
+    ///
+    /// ``` bash
+    /// cmprss cat archive.tar.gz dir/test.txt
+    /// cmprss cat archive.zip dir/test.txt
+    /// ```
+    #[test]
+    fn cat_entry() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("dir/test.txt")
+            .write_str("garbage data for testing")?;
+        working_dir.child("dir/other.txt").write_str("other")?;
+
+        for archive in ["archive.tar.gz", "archive.zip"] {
+            let mut compress = Command::cargo_bin("cmprss")?;
+            compress
+                .current_dir(working_dir.path())
+                .arg("--ignore-pipes")
+                .arg("dir")
+                .arg(archive);
+            compress.assert().success();
+
+            let mut cat = Command::cargo_bin("cmprss")?;
+            cat.current_dir(working_dir.path())
+                .args(["cat", archive, "dir/test.txt"]);
+            cat.assert().success().stdout("garbage data for testing");
+
+            let mut missing = Command::cargo_bin("cmprss")?;
+            missing
+                .current_dir(working_dir.path())
+                .args(["cat", archive, "dir/missing.txt"]);
+            missing
+                .assert()
+                .failure()
+                .stderr(predicate::str::contains("not in the archive"));
+
+            let mut dir = Command::cargo_bin("cmprss")?;
+            dir.current_dir(working_dir.path())
+                .args(["cat", archive, "dir"]);
+            dir.assert()
+                .failure()
+                .stderr(predicate::str::contains("is a directory"));
+        }
+
+        Ok(())
+    }
+
+    /// Formats are detected from the contents when the extension doesn't name one
+    ///
+    /// ``` bash
+    /// cmprss gzip test.txt mystery.bin
+    /// cmprss mystery.bin out.txt
+    /// cat mystery.bin | cmprss > piped.txt
+    /// ```
+    #[test]
+    fn detect_from_contents() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("test.txt");
+        file.write_str("garbage data for testing")?;
+        let mystery = working_dir.child("mystery.bin");
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .arg("gzip")
+            .arg("--ignore-pipes")
+            .arg("test.txt")
+            .arg("mystery.bin");
+        compress.assert().success();
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .arg("--ignore-pipes")
+            .arg("mystery.bin")
+            .arg("out.txt");
+        extract.assert().success();
+        working_dir
+            .child("out.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        let mut piped = Command::cargo_bin("cmprss")?;
+        piped
+            .current_dir(working_dir.path())
+            .stdin(Stdio::from(File::open(mystery.path())?))
+            .stdout(Stdio::from(File::create(
+                working_dir.child("piped.txt").path(),
+            )?));
+        piped.assert().success();
+        working_dir
+            .child("piped.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// A tar inside a compressed stream is detected and extracted in one step
+    ///
+    /// ``` bash
+    /// cmprss dir archive.tar.gz && mv archive.tar.gz backup
+    /// cat backup | cmprss --extract out
+    /// ```
+    #[test]
+    fn detect_tar_gz_from_contents() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("dir/test.txt");
+        file.write_str("garbage data for testing")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .arg("--ignore-pipes")
+            .arg("dir")
+            .arg("archive.tar.gz");
+        compress.assert().success();
+        std::fs::rename(
+            working_dir.child("archive.tar.gz").path(),
+            working_dir.child("backup").path(),
+        )?;
+
+        let output_dir = working_dir.child("out");
+        output_dir.create_dir_all()?;
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .arg("--ignore-stdout")
+            .arg("--extract")
+            .arg("out")
+            .stdin(Stdio::from(File::open(working_dir.child("backup").path())?));
+        extract.assert().success();
+        output_dir
+            .child("dir/test.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// Tar chains with the other stream compressors are created and extracted in one step
+    ///
+    /// ``` bash
+    /// cmprss dir archive.tar.zst
+    /// cmprss archive.tar.zst out
+    /// ```
+    #[test]
+    fn tar_chains_single_step() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("dir/test.txt");
+        file.write_str("garbage data for testing")?;
+
+        let mut archives = vec!["archive.tar.br"];
+        if cfg!(feature = "zstd") {
+            archives.extend(["archive.tar.zst", "archive.tzst"]);
+        }
+        for archive in archives {
+            let mut compress = Command::cargo_bin("cmprss")?;
+            compress
+                .current_dir(working_dir.path())
+                .arg("--ignore-pipes")
+                .arg("dir")
+                .arg(archive);
+            compress.assert().success();
+
+            let output_dir = working_dir.child(format!("{archive}.out"));
+            output_dir.create_dir_all()?;
+            let mut extract = Command::cargo_bin("cmprss")?;
+            extract
+                .current_dir(working_dir.path())
+                .arg("--ignore-pipes")
+                .arg(archive)
+                .arg(output_dir.path());
+            extract.assert().success();
+            output_dir
+                .child("dir/test.txt")
+                .assert(predicate::path::eq_file(file.path()));
+        }
+
+        Ok(())
+    }
+
+    /// Compress with several threads, which must be at least 1
+    ///
+    /// ``` bash
+    /// cmprss --threads 2 test.txt test.txt.zst
+    /// cmprss --threads 0 test.txt test.txt.xz
+    /// ```
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn zstd_threads() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("test.txt");
+        file.write_str("garbage data for testing")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .arg("--ignore-pipes")
+            .arg("--threads")
+            .arg("2")
+            .arg("test.txt")
+            .arg("test.txt.zst");
+        compress.assert().success();
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .arg("--ignore-pipes")
+            .arg("test.txt.zst")
+            .arg("out.txt");
+        extract.assert().success();
+        working_dir
+            .child("out.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        let mut invalid = Command::cargo_bin("cmprss")?;
+        invalid
+            .current_dir(working_dir.path())
+            .arg("--ignore-pipes")
+            .arg("--threads")
+            .arg("0")
+            .arg("test.txt")
+            .arg("test.txt.xz");
+        invalid.assert().failure();
+
+        Ok(())
+    }
+
+    /// Existing outputs are only replaced with --force, and skipped with --no-clobber
+    ///
+    /// ``` bash
+    /// cmprss gzip test.txt              # test.txt.gz already exists
+    /// cmprss gzip --no-clobber test.txt
+    /// cmprss gzip --force test.txt
+    /// cmprss --force test.txt test.txt.gz
+    /// ```
+    #[test]
+    fn overwrite_control() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("test.txt");
+        file.write_str("garbage data for testing")?;
+        let archive = working_dir.child("test.txt.gz");
+        archive.write_str("existing")?;
+
+        let mut refused = Command::cargo_bin("cmprss")?;
+        refused
+            .current_dir(working_dir.path())
+            .arg("gzip")
+            .arg("--ignore-pipes")
+            .arg("test.txt");
+        refused
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("already exists"));
+        archive.assert("existing");
+
+        let mut skipped = Command::cargo_bin("cmprss")?;
+        skipped
+            .current_dir(working_dir.path())
+            .arg("gzip")
+            .arg("--ignore-pipes")
+            .arg("--no-clobber")
+            .arg("test.txt");
+        skipped.assert().success();
+        archive.assert("existing");
+
+        let mut forced = Command::cargo_bin("cmprss")?;
+        forced
+            .current_dir(working_dir.path())
+            .arg("gzip")
+            .arg("--ignore-pipes")
+            .arg("--force")
+            .arg("test.txt");
+        forced.assert().success();
+
+        // An existing file named like an archive is the output with --force
+        archive.write_str("existing")?;
+        let mut forced_io_list = Command::cargo_bin("cmprss")?;
+        forced_io_list
+            .current_dir(working_dir.path())
+            .arg("--ignore-pipes")
+            .arg("--force")
+            .arg("test.txt")
+            .arg("test.txt.gz");
+        forced_io_list.assert().success();
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .arg("--ignore-pipes")
+            .arg("--force")
+            .arg("test.txt.gz")
+            .arg("test.txt");
+        extract.assert().success();
+        file.assert("garbage data for testing");
+
+        Ok(())
+    }
+
+    /// Encrypted zip roundtrip, with the password given directly or in a file
+    ///
+    /// ``` bash
+    /// cmprss zip --password secret test.txt archive.zip
+    /// cmprss zip --extract --password wrong archive.zip out
+    /// cmprss zip --extract --password-file password.txt archive.zip out
+    /// ```
+    #[test]
+    fn zip_password() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("test.txt");
+        file.write_str("garbage data for testing")?;
+        working_dir.child("password.txt").write_str("secret\n")?;
+        let output_dir = working_dir.child("out");
+        output_dir.create_dir_all()?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["zip", "--ignore-pipes", "--password", "secret"])
+            .args(["test.txt", "archive.zip"]);
+        compress.assert().success();
+
+        let mut missing = Command::cargo_bin("cmprss")?;
+        missing.current_dir(working_dir.path()).args([
+            "zip",
+            "--ignore-pipes",
+            "--extract",
+            "archive.zip",
+            "out",
+        ]);
+        missing
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("--password"));
+
+        let mut wrong = Command::cargo_bin("cmprss")?;
+        wrong
+            .current_dir(working_dir.path())
+            .args(["zip", "--ignore-pipes", "--extract", "--password", "wrong"])
+            .args(["archive.zip", "out"]);
+        wrong
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("incorrect password"));
+        output_dir
+            .child("test.txt")
+            .assert(predicate::path::missing());
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["zip", "--ignore-pipes", "--extract"])
+            .args(["--password-file", "password.txt", "archive.zip", "out"]);
+        extract.assert().success();
+        output_dir
+            .child("test.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// Extract a zip encrypted with the legacy ZipCrypto scheme by the zip tool
+    ///
+    /// ``` bash
+    /// zip -P secret archive.zip test.txt
+    /// cmprss zip --extract --password secret archive.zip out
+    /// ```
+    #[test]
+    fn zip_zipcrypto_extract() -> Result<(), Box<dyn std::error::Error>> {
+        if Command::new("zip").arg("-v").output().is_err() {
+            // zip is not installed
+            return Ok(());
+        }
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("test.txt");
+        file.write_str("garbage data for testing")?;
+        let output_dir = working_dir.child("out");
+        output_dir.create_dir_all()?;
+
+        let mut zip = Command::new("zip");
+        zip.current_dir(working_dir.path())
+            .args(["-q", "-P", "secret", "archive.zip", "test.txt"]);
+        zip.assert().success();
+
+        let mut wrong = Command::cargo_bin("cmprss")?;
+        wrong
+            .current_dir(working_dir.path())
+            .args(["zip", "--ignore-pipes", "--extract", "--password", "wrong"])
+            .args(["archive.zip", "out"]);
+        wrong.assert().failure();
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["zip", "--ignore-pipes", "--extract", "--password", "secret"])
+            .args(["archive.zip", "out"]);
+        extract.assert().success();
+        output_dir
+            .child("test.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// Leave files out of an archive with patterns
+    ///
+    /// ``` bash
+    /// cmprss --exclude '*.o' --exclude-from excludes.txt dir archive.tar.gz
+    /// cmprss archive.tar.gz out
+    /// ```
+    #[test]
+    fn exclude_patterns() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        dir.child("keep.txt")
+            .write_str("garbage data for testing")?;
+        dir.child("main.o").write_str("object")?;
+        dir.child("node_modules/dep.js").write_str("dependency")?;
+        working_dir
+            .child("excludes.txt")
+            .write_str("# dependencies\nnode_modules/\n")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "--exclude", "*.o"])
+            .args(["--exclude-from", "excludes.txt", "dir", "archive.tar.gz"]);
+        compress.assert().success();
+
+        let out = working_dir.child("out");
+        out.create_dir_all()?;
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "archive.tar.gz", "out"]);
+        extract.assert().success();
+        out.child("dir/keep.txt")
+            .assert(predicate::path::eq_file(dir.child("keep.txt").path()));
+        out.child("dir/main.o").assert(predicate::path::missing());
+        out.child("dir/node_modules")
+            .assert(predicate::path::missing());
+
+        Ok(())
+    }
+
+    /// Reproducible archives are identical when only the file metadata changed
+    ///
+    /// ``` bash
+    /// cmprss --reproducible dir first.tar.gz
+    /// touch dir/test.txt
+    /// cmprss --reproducible dir second.tar.gz
+    /// ```
+    #[test]
+    fn reproducible_archives() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("dir/test.txt");
+        file.write_str("garbage data for testing")?;
+
+        for extension in ["tar.gz", "zip"] {
+            let first = format!("first.{extension}");
+            let second = format!("second.{extension}");
+            let mut compress = Command::cargo_bin("cmprss")?;
+            compress.current_dir(working_dir.path()).args([
+                "--ignore-pipes",
+                "--reproducible",
+                "dir",
+                &first,
+            ]);
+            compress.assert().success();
+
+            let mtime = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+            std::fs::File::options()
+                .write(true)
+                .open(file.path())?
+                .set_modified(mtime)?;
+            let mut compress = Command::cargo_bin("cmprss")?;
+            compress.current_dir(working_dir.path()).args([
+                "--ignore-pipes",
+                "--reproducible",
+                "dir",
+                &second,
+            ]);
+            compress.assert().success();
+
+            assert_eq!(
+                std::fs::read(working_dir.child(&first).path())?,
+                std::fs::read(working_dir.child(&second).path())?
+            );
+        }
+
+        // Times are clamped to SOURCE_DATE_EPOCH
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .env("SOURCE_DATE_EPOCH", "1369353600")
+            .args(["--ignore-pipes", "--reproducible", "dir", "epoch.tar"]);
+        compress.assert().success();
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.current_dir(working_dir.path())
+            .args(["list", "epoch.tar"]);
+        list.assert()
+            .success()
+            .stdout(predicate::str::contains("2013-05-24 00:00  dir/test.txt"));
+
+        Ok(())
+    }
+
+    /// Name piped input, for the output filename, the gzip header, and zip entries
+    ///
+    /// ``` bash
+    /// cat report.txt | cmprss gzip --stdin-name report.txt
+    /// cat report.txt | cmprss zip --stdin-name report.txt out.zip
+    /// ```
+    #[test]
+    fn stdin_name() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("input.txt");
+        file.write_str("garbage data for testing")?;
+
+        let mut gzip = Command::cargo_bin("cmprss")?;
+        gzip.current_dir(working_dir.path())
+            .args(["gzip", "--ignore-stdout", "--stdin-name", "report.txt"])
+            .stdin(Stdio::from(File::open(file.path())?));
+        gzip.assert().success();
+        let archive = std::fs::read(working_dir.child("report.txt.gz").path())?;
+        // The FNAME flag is set, and the name follows the 10 byte header
+        assert_eq!(archive[3] & 0x08, 0x08);
+        assert_eq!(&archive[10..21], b"report.txt\0");
+
+        let mut zip = Command::cargo_bin("cmprss")?;
+        zip.current_dir(working_dir.path())
+            .args(["zip", "--stdin-name", "report.txt", "out.zip"])
+            .stdin(Stdio::from(File::open(file.path())?));
+        zip.assert().success();
+        let mut cat = Command::cargo_bin("cmprss")?;
+        cat.current_dir(working_dir.path())
+            .args(["cat", "out.zip", "report.txt"]);
+        cat.assert().success().stdout("garbage data for testing");
+
+        Ok(())
+    }
+
+    /// Train a zstd dictionary, and use it to compress and extract a small file
+    ///
+    /// ``` bash
+    /// cmprss zstd train --max-dict-size 4k samples -o dict.bin
+    /// cmprss zstd --dictionary dict.bin event.json event.json.zst
+    /// cmprss zstd --dictionary dict.bin --extract event.json.zst out.json
+    /// ```
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn zstd_dictionary() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        for i in 0..500 {
+            working_dir
+                .child(format!("samples/{}/{i}.json", i % 10))
+                .write_str(&format!(
+                    r#"{{"id":{i},"event":"login","user":"user{}","ok":true}}"#,
+                    i % 37
+                ))?;
+        }
+        let file = working_dir.child("event.json");
+        file.write_str(r#"{"id":1234,"event":"login","user":"user5","ok":true}"#)?;
+
+        let mut train = Command::cargo_bin("cmprss")?;
+        train.current_dir(working_dir.path()).args([
+            "zstd",
+            "train",
+            "--max-dict-size",
+            "4k",
+            "samples",
+            "-o",
+            "dict.bin",
+        ]);
+        train.assert().success();
+        working_dir
+            .child("dict.bin")
+            .assert(predicate::path::is_file());
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "zstd",
+            "--ignore-pipes",
+            "--dictionary",
+            "dict.bin",
+            "event.json",
+            "event.json.zst",
+        ]);
+        compress.assert().success();
+
+        // The dictionary is needed to extract it
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "zstd",
+            "--ignore-pipes",
+            "--extract",
+            "event.json.zst",
+            "missing.json",
+        ]);
+        extract.assert().failure();
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "zstd",
+            "--ignore-pipes",
+            "--dictionary",
+            "dict.bin",
+            "--extract",
+            "event.json.zst",
+            "out.json",
+        ]);
+        extract.assert().success();
+        working_dir
+            .child("out.json")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// Convert between compression formats and archive formats
+    ///
+    /// ``` bash
+    /// cmprss convert dir.tar.bz2 dir.tar.zst
+    /// cmprss convert dir.tar.zst dir.zip
+    /// cmprss convert test.txt.gz test.txt.zst
+    /// ```
+    #[test]
+    #[cfg(all(feature = "bzip2", feature = "zstd"))]
+    fn convert() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("dir/test.txt");
+        file.write_str("garbage data for testing")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "dir", "dir.tar.bz2"]);
+        compress.assert().success();
+
+        for (input, output) in [("dir.tar.bz2", "dir.tar.zst"), ("dir.tar.zst", "dir.zip")] {
+            let mut convert = Command::cargo_bin("cmprss")?;
+            convert
+                .current_dir(working_dir.path())
+                .args(["convert", input, output]);
+            convert.assert().success();
+        }
+        let mut cat = Command::cargo_bin("cmprss")?;
+        cat.current_dir(working_dir.path())
+            .args(["cat", "dir.zip", "dir/test.txt"]);
+        cat.assert().success().stdout("garbage data for testing");
+
+        // Existing files aren't replaced
+        let mut convert = Command::cargo_bin("cmprss")?;
+        convert
+            .current_dir(working_dir.path())
+            .args(["convert", "dir.tar.bz2", "dir.tar.zst"]);
+        convert
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("already exists"));
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "dir/test.txt",
+            "test.txt.gz",
+        ]);
+        compress.assert().success();
+        let mut convert = Command::cargo_bin("cmprss")?;
+        convert
+            .current_dir(working_dir.path())
+            .args(["convert", "test.txt.gz", "test.txt.zst"]);
+        convert.assert().success();
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "test.txt.zst", "out.txt"]);
+        extract.assert().success();
+        working_dir
+            .child("out.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// Update and delete entries of an existing zip archive
+    ///
+    /// ``` bash
+    /// cmprss zip --update dir bundle.zip
+    /// cmprss zip --delete '*.log' bundle.zip
+    /// ```
+    #[test]
+    fn zip_update_delete() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("dir/test.txt")
+            .write_str("garbage data")?;
+        working_dir.child("dir/debug.log").write_str("log")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "dir", "bundle.zip"]);
+        compress.assert().success();
+
+        working_dir.child("dir/new.txt").write_str("new file")?;
+        let mut update = Command::cargo_bin("cmprss")?;
+        update
+            .current_dir(working_dir.path())
+            .args(["zip", "--update", "dir", "bundle.zip"]);
+        update.assert().success();
+
+        let mut delete = Command::cargo_bin("cmprss")?;
+        delete
+            .current_dir(working_dir.path())
+            .args(["zip", "--delete", "*.log", "bundle.zip"]);
+        delete.assert().success();
+
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.current_dir(working_dir.path())
+            .args(["list", "bundle.zip"]);
+        list.assert()
+            .success()
+            .stdout(predicate::str::contains("dir/new.txt"))
+            .stdout(predicate::str::contains("dir/test.txt"))
+            .stdout(predicate::str::contains("debug.log").not());
+
+        // The archive has to exist already
+        let mut update = Command::cargo_bin("cmprss")?;
+        update
+            .current_dir(working_dir.path())
+            .args(["zip", "--update", "dir", "missing.zip"]);
+        update
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("does not exist"));
+
+        Ok(())
+    }
+
+    /// Only extract the entries matching a pattern
+    ///
+    /// ``` bash
+    /// cmprss tar --extract archive.tar --include 'docs/**/*.md' --exclude drafts out
+    /// ```
+    #[test]
+    fn selective_extract() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("docs/guide/intro.md")
+            .write_str("intro")?;
+        working_dir.child("docs/drafts/todo.md").write_str("todo")?;
+        working_dir.child("docs/logo.png").write_str("png")?;
+        working_dir.child("out").create_dir_all()?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "docs", "archive.tar"]);
+        compress.assert().success();
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "tar",
+            "--extract",
+            "archive.tar",
+            "--include",
+            "docs/**/*.md",
+            "--exclude",
+            "drafts",
+            "out",
+        ]);
+        extract.assert().success();
+        working_dir.child("out/docs/guide/intro.md").assert("intro");
+        working_dir
+            .child("out/docs/drafts")
+            .assert(predicate::path::missing());
+        working_dir
+            .child("out/docs/logo.png")
+            .assert(predicate::path::missing());
+
+        Ok(())
+    }
+
+    /// Remove the top directory of the entries when extracting
+    ///
+    /// ``` bash
+    /// cmprss zip --extract --strip-components 1 project.zip out
+    /// ```
+    #[test]
+    fn strip_components() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("project-1.2.3/src/main.rs")
+            .write_str("fn main() {}")?;
+        working_dir.child("out").create_dir_all()?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "project-1.2.3",
+            "project.zip",
+        ]);
+        compress.assert().success();
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "zip",
+            "--extract",
+            "--strip-components",
+            "1",
+            "project.zip",
+            "out",
+        ]);
+        extract.assert().success();
+        working_dir.child("out/src/main.rs").assert("fn main() {}");
+        working_dir
+            .child("out/project-1.2.3")
+            .assert(predicate::path::missing());
+
+        Ok(())
+    }
+
+    /// Abort extracting decompression bombs
+    ///
+    /// ``` bash
+    /// cmprss --max-output-size 1mb zeros.gz zeros
+    /// cmprss --max-ratio 100 zeros.zip out
+    /// ```
+    #[test]
+    fn output_limits() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("zeros")
+            .write_binary(&vec![0; 8 * 1024 * 1024])?;
+        working_dir.child("out").create_dir_all()?;
+        for archive in ["zeros.gz", "zeros.br", "zeros.zip"] {
+            let mut compress = Command::cargo_bin("cmprss")?;
+            compress
+                .current_dir(working_dir.path())
+                .args(["--ignore-pipes", "zeros", archive]);
+            compress.assert().success();
+        }
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "--max-output-size",
+            "1mb",
+            "zeros.gz",
+            "zeros.out",
+        ]);
+        extract
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("--max-output-size"));
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "--max-ratio",
+            "100",
+            "zeros.br",
+            "zeros.br.out",
+        ]);
+        extract
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("--max-ratio"));
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "--max-ratio",
+            "100",
+            "zeros.zip",
+            "out",
+        ]);
+        extract
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("--max-ratio"));
+
+        // Within the limits, extracting works as usual
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "--max-output-size",
+            "8mb",
+            "zeros.gz",
+            "zeros.gz.out",
+        ]);
+        extract.assert().success();
+
+        Ok(())
+    }
+
+    /// Failed jobs don't leave a partial output, and forced outputs are kept until replaced
+    ///
+    /// ``` bash
+    /// cmprss zeros zeros.gz
+    /// cmprss --max-output-size 1mb zeros.gz zeros.out # fails, no zeros.out
+    /// cmprss --force --max-output-size 1mb zeros.gz existing # fails, existing is unchanged
+    /// ```
+    #[test]
+    fn atomic_output() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("zeros")
+            .write_binary(&vec![0; 4 * 1024 * 1024])?;
+        let existing = working_dir.child("existing");
+        existing.write_str("keep me")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "zeros", "zeros.gz"]);
+        compress.assert().success();
+
+        for (output, force) in [("zeros.out", false), ("existing", true)] {
+            let mut extract = Command::cargo_bin("cmprss")?;
+            extract.current_dir(working_dir.path()).args([
+                "--ignore-pipes",
+                "--max-output-size",
+                "1mb",
+                "zeros.gz",
+                output,
+            ]);
+            if force {
+                extract.arg("--force");
+            }
+            extract.assert().failure();
+        }
+        working_dir
+            .child("zeros.out")
+            .assert(predicate::path::missing());
+        existing.assert("keep me");
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "--force",
+            "zeros.gz",
+            "existing",
+        ]);
+        extract.assert().success();
+        existing.assert(predicate::path::is_file());
+        assert_eq!(std::fs::metadata(existing.path())?.len(), 4 * 1024 * 1024);
+
+        // Only the outputs are left, without any temporary files
+        let mut names: Vec<_> = std::fs::read_dir(working_dir.path())?
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["existing", "zeros", "zeros.gz"]);
+
+        Ok(())
+    }
+
+    /// Interrupted jobs remove their partial output and exit with 128 + the signal
+    ///
+    /// ``` bash
+    /// producer | cmprss gzip out.gz # killed with SIGTERM, no out.gz is left
+    /// ```
+    #[test]
+    #[cfg(unix)]
+    fn interrupted_cleanup() -> Result<(), Box<dyn std::error::Error>> {
+        use std::io::Write;
+        use std::os::unix::process::ExitStatusExt;
+        let working_dir = assert_fs::TempDir::new()?;
+        let mut compress = Command::cargo_bin("cmprss")?
+            .current_dir(working_dir.path())
+            .args(["gzip", "--ignore-stdout", "out.gz"])
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let mut stdin = compress.stdin.take().unwrap();
+        stdin.write_all(b"garbage data for testing")?;
+
+        // Wait for the job to start writing, keeping stdin open so it doesn't finish
+        let tmp = working_dir.child(".out.gz.cmprss-tmp");
+        for _ in 0..500 {
+            if tmp.path().exists() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        tmp.assert(predicate::path::is_file());
+        unsafe {
+            libc::kill(compress.id() as libc::pid_t, libc::SIGTERM);
+        }
+        let output = compress.wait_with_output()?;
+        drop(stdin);
+        assert_eq!(output.status.code(), Some(128 + libc::SIGTERM));
+        assert_eq!(output.status.signal(), None);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("interrupted"));
+        tmp.assert(predicate::path::missing());
+        working_dir
+            .child("out.gz")
+            .assert(predicate::path::missing());
+
+        Ok(())
+    }
+
+    /// Directories are archived with tar before being compressed with a stream format
+    ///
+    /// ``` bash
+    /// cmprss zstd dir # fails, suggesting --tar
+    /// cmprss zstd --tar dir # creates dir.tar.zst
+    /// cmprss dir.tar.zst out
+    /// ```
+    #[test]
+    fn tar_wrap() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("dir/test.txt")
+            .write_str("garbage data for testing")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["gzip", "--ignore-pipes", "dir"]);
+        compress
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("use --tar"));
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["gzip", "--ignore-pipes", "--tar", "dir"]);
+        compress.assert().success();
+        working_dir
+            .child("dir.gz")
+            .assert(predicate::path::missing());
+
+        let out = working_dir.child("out");
+        out.create_dir_all()?;
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "dir.tar.gz", "out"]);
+        extract.assert().success();
+        out.child("dir/test.txt").assert("garbage data for testing");
+
+        Ok(())
+    }
+
+    /// Compress every file in a directory tree to its own file, and extract them again
+    ///
+    /// ``` bash
+    /// cmprss gzip --recursive --rm-source logs
+    /// cmprss gzip --recursive --extract --output-dir out logs
+    /// ```
+    #[test]
+    fn recursive() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir.child("logs/a.log").write_str("first log")?;
+        working_dir
+            .child("logs/old/b.log")
+            .write_str("second log")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "gzip",
+            "--recursive",
+            "--rm-source",
+            "logs",
+        ]);
+        compress.assert().success();
+        working_dir
+            .child("logs/a.log")
+            .assert(predicate::path::missing());
+        working_dir
+            .child("logs/old/b.log")
+            .assert(predicate::path::missing());
+        working_dir
+            .child("logs/old/b.log.gz")
+            .assert(predicate::path::is_file());
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "gzip",
+            "--recursive",
+            "--extract",
+            "--output-dir",
+            "out",
+            "logs",
+        ]);
+        extract.assert().success();
+        working_dir.child("out/logs/a.log").assert("first log");
+        working_dir.child("out/logs/old/b.log").assert("second log");
+        working_dir
+            .child("logs/a.log.gz")
+            .assert(predicate::path::is_file());
+
+        // Existing outputs fail, but the other files are still processed
+        working_dir.child("logs/c.log").write_str("third log")?;
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "gzip",
+            "--recursive",
+            "--extract",
+            "--output-dir",
+            "out",
+            "logs",
+        ]);
+        extract
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("2 of 2 files failed"));
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "gzip",
+            "--recursive",
+            "--no-clobber",
+            "logs",
+        ]);
+        compress.assert().success();
+        working_dir
+            .child("logs/c.log.gz")
+            .assert(predicate::path::is_file());
+
+        Ok(())
+    }
+
+    /// Remove the inputs once the output is written, unless --keep is given after --rm-source
+    ///
+    /// ``` bash
+    /// cmprss gzip --rm-source test.txt
+    /// cmprss --rm-source test.txt.gz
+    /// cmprss tar --rm-source --keep dir
+    /// cmprss tar --rm-source dir dir/archive.tar # fails, the output is inside dir
+    /// ```
+    #[test]
+    fn rm_source() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("test.txt");
+        file.write_str("garbage data for testing")?;
+        working_dir.child("dir/test.txt").write_str("more data")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "gzip",
+            "--ignore-pipes",
+            "--rm-source",
+            "test.txt",
+        ]);
+        compress.assert().success();
+        file.assert(predicate::path::missing());
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "--rm-source",
+            "test.txt.gz",
+        ]);
+        extract.assert().success();
+        file.assert("garbage data for testing");
+        working_dir
+            .child("test.txt.gz")
+            .assert(predicate::path::missing());
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "tar",
+            "--ignore-pipes",
+            "--rm-source",
+            "--keep",
+            "dir",
+        ]);
+        compress.assert().success();
+        working_dir.child("dir/test.txt").assert("more data");
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "tar",
+            "--ignore-pipes",
+            "--rm-source",
+            "dir",
+            "dir/archive.tar",
+        ]);
+        compress
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("the output is inside it"));
+        working_dir.child("dir/test.txt").assert("more data");
+
+        Ok(())
+    }
+
+    /// Compress several files at once, each to its own output
+    ///
+    /// ``` bash
+    /// cmprss gzip --jobs 2 a.log b.log c.log
+    /// cmprss gzip --output-dir out a.log.gz b.log.gz c.log.gz
+    /// ```
+    #[test]
+    fn batch() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let names = ["a.log", "b.log", "c.log"];
+        for name in names {
+            working_dir.child(name).write_str(name)?;
+        }
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["gzip", "--ignore-pipes", "--jobs", "2"])
+            .args(names);
+        compress.assert().success();
+
+        let archives = names.map(|name| format!("{}.gz", name));
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["gzip", "--ignore-pipes", "--output-dir", "out"])
+            .args(&archives);
+        extract.assert().success();
+        for name in names {
+            working_dir.child("out").child(name).assert(name);
+        }
+
+        // Existing outputs are an error, without stopping the other files
+        working_dir.child("d.log").write_str("d.log")?;
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["gzip", "--ignore-pipes", "a.log", "d.log"]);
+        compress
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("1 of 2 files failed"));
+        working_dir
+            .child("d.log.gz")
+            .assert(predicate::path::is_file());
+
+        Ok(())
+    }
+
+    /// Show the progress of tar and zip archives
+    ///
+    /// ``` bash
+    /// cmprss tar --progress on dir archive.tar
+    /// cmprss zip --progress on dir archive.zip
+    /// cmprss zip --progress on --extract archive.zip out
+    /// ```
+    #[test]
+    fn archive_progress() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        dir.child("file.txt")
+            .write_str("garbage data for testing")?;
+        working_dir.child("out").create_dir_all()?;
+
+        let commands: [&[&str]; 3] = [
+            &["tar", "--progress", "on", "dir", "archive.tar"],
+            &["zip", "--progress", "on", "dir", "archive.zip"],
+            &["zip", "--progress", "on", "--extract", "archive.zip", "out"],
+        ];
+        for args in commands {
+            let mut cmd = Command::cargo_bin("cmprss")?;
+            cmd.current_dir(working_dir.path())
+                .args(args)
+                .arg("--ignore-pipes");
+            cmd.assert().success();
+        }
+        working_dir
+            .child("out/dir/file.txt")
+            .assert("garbage data for testing");
+
+        Ok(())
+    }
+
+    /// Report the progress as JSON events for other programs
+    ///
+    /// ``` bash
+    /// cmprss gzip --progress json test.txt test.txt.gz
+    /// ```
+    #[test]
+    fn json_progress() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("test.txt");
+        file.write_str("garbage data for testing")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["gzip", "--progress", "json", "--ignore-pipes"])
+            .args(["test.txt", "test.txt.gz"]);
+        compress.assert().success().stderr(
+            predicate::str::is_match(
+                r#"(?m)^\{"bytes_in":24,"bytes_out":\d+,"total":24,"ratio":[0-9.]+,"elapsed":[0-9.]+,"eta":0,"done":true\}$"#,
+            )?,
+        );
+
+        Ok(())
+    }
+
+    /// Report the progress of a batch as a single stream of JSON events
+    ///
+    /// ``` bash
+    /// cmprss gzip --progress json --jobs 2 a.log b.log
+    /// ```
+    #[test]
+    fn json_batch_progress() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir.child("a.log").write_str("garbage data")?;
+        working_dir.child("b.log").write_str("more garbage data")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args([
+                "gzip",
+                "--progress",
+                "json",
+                "--ignore-pipes",
+                "--jobs",
+                "2",
+            ])
+            .args(["a.log", "b.log"]);
+        let output = compress.assert().success().get_output().stderr.clone();
+        let events = String::from_utf8(output)?;
+        let done: Vec<_> = events
+            .lines()
+            .filter(|line| line.contains(r#""done":true"#))
+            .collect();
+        assert_eq!(done.len(), 1, "{}", events);
+        assert!(done[0].starts_with(r#"{"bytes_in":29,"#), "{}", events);
+        assert!(done[0].contains(r#""total":29,"#), "{}", events);
+        working_dir
+            .child("b.log.gz")
+            .assert(predicate::path::is_file());
+
+        Ok(())
+    }
+
+    /// Compare the formats on a sample of a file
+    ///
+    /// ``` bash
+    /// cmprss --format-output json benchmark --format gzip test.txt
+    /// ```
+    #[test]
+    fn benchmark() -> Result<(), Box<dyn std::error::Error>> {
+        let file = assert_fs::NamedTempFile::new("test.txt")?;
+        file.write_str(&"garbage data for testing ".repeat(100))?;
+
+        let mut benchmark = Command::cargo_bin("cmprss")?;
+        benchmark
+            .args(["--format-output", "json", "benchmark", "--format", "gzip"])
+            .arg(file.path());
+        let output = benchmark.assert().success().get_output().stdout.clone();
+        let lines: Vec<_> = String::from_utf8(output)?
+            .lines()
+            .map(String::from)
+            .collect();
+        assert_eq!(lines.len(), 3);
+        for line in lines {
+            assert!(line.starts_with(r#"{"format":"gzip","level":"#));
+            assert!(line.contains(r#""original_bytes":2500,"#));
+        }
+
+        let mut unknown = Command::cargo_bin("cmprss")?;
+        unknown
+            .args(["benchmark", "--format", "rar"])
+            .arg(file.path());
+        unknown.assert().failure().stderr(predicate::str::contains(
+            "no built in stream format matches rar",
+        ));
+
+        Ok(())
+    }
+
+    /// Write a checksum next to the archive, and verify it before extracting
+    ///
+    /// ``` bash
+    /// cmprss gzip --checksum sha256 test.txt test.txt.gz
+    /// cmprss checksum --verify test.txt.gz
+    /// cmprss checksum --verify --format-output json test.txt.gz
+    /// cmprss gzip --verify-checksum --extract test.txt.gz out.txt
+    /// ```
+    #[test]
+    fn checksum() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("test.txt")
+            .write_str("garbage data for testing")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["gzip", "--ignore-pipes", "--checksum", "sha256"])
+            .args(["test.txt", "test.txt.gz"]);
+        compress.assert().success();
+        working_dir
+            .child("test.txt.gz.sha256")
+            .assert(predicate::str::ends_with("  test.txt.gz\n"));
+
+        let mut verify = Command::cargo_bin("cmprss")?;
+        verify
+            .current_dir(working_dir.path())
+            .args(["checksum", "--verify", "test.txt.gz"]);
+        verify.assert().success().stdout("test.txt.gz: OK\n");
+
+        let mut verify = Command::cargo_bin("cmprss")?;
+        verify.current_dir(working_dir.path()).args([
+            "checksum",
+            "--verify",
+            "--format-output",
+            "json",
+            "test.txt.gz",
+        ]);
+        verify
+            .assert()
+            .success()
+            .stdout("{\"path\":\"test.txt.gz\",\"ok\":true,\"error\":null}\n");
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["gzip", "--ignore-pipes", "--verify-checksum", "--extract"])
+            .args(["test.txt.gz", "out.txt"]);
+        extract.assert().success();
+        working_dir
+            .child("out.txt")
+            .assert("garbage data for testing");
+
+        // A corrupted archive isn't extracted
+        let mut archive = std::fs::read(working_dir.child("test.txt.gz").path())?;
+        archive[12] ^= 0xff;
+        std::fs::write(working_dir.child("test.txt.gz").path(), archive)?;
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["gzip", "--ignore-pipes", "--verify-checksum", "--extract"])
+            .args(["test.txt.gz", "corrupt.txt"]);
+        extract
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("doesn't match its checksum"));
+        working_dir
+            .child("corrupt.txt")
+            .assert(predicate::path::missing());
+
+        Ok(())
+    }
+
+    /// Defaults from a config file, beneath the options given on the command line
+    ///
+    /// ``` bash
+    /// cmprss --config config.toml test.txt inferred.zst
+    /// cmprss zstd --config config.toml --level 3 test.txt explicit.zst
+    /// ```
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn config_defaults() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let data: String = (0..20000)
+            .map(|i| format!("line {} has {}\n", i, i * 7919 % 1000))
+            .collect();
+        working_dir.child("test.txt").write_str(&data)?;
+        working_dir
+            .child("config.toml")
+            .write_str("[zstd]\nlevel = 19\n")?;
+
+        let compress =
+            |args: &[&str], output: &str| -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+                let mut cmd = Command::cargo_bin("cmprss")?;
+                cmd.current_dir(working_dir.path())
+                    .env("XDG_CONFIG_HOME", working_dir.path())
+                    .args(args)
+                    .args(["--ignore-pipes", "test.txt", output]);
+                cmd.assert().success();
+                Ok(std::fs::read(working_dir.child(output).path())?)
+            };
+        let level_19 = compress(&["zstd", "--level", "19"], "level_19.zst")?;
+        let level_3 = compress(&["zstd", "--level", "3"], "level_3.zst")?;
+        assert_ne!(level_19, level_3);
+
+        // The config applies to the subcommand and to the formats inferred from the filename
+        let config = ["--config", "config.toml"];
+        assert_eq!(compress(&config, "inferred.zst")?, level_19);
+        assert_eq!(
+            compress(&[&["zstd"], &config[..]].concat(), "sub.zst")?,
+            level_19
+        );
+        let explicit = [&["zstd", "--level", "3"], &config[..]].concat();
+        assert_eq!(compress(&explicit, "explicit.zst")?, level_3);
+
+        working_dir
+            .child("invalid.toml")
+            .write_str("[zstd]\nlevel = 30\n")?;
+        let mut invalid = Command::cargo_bin("cmprss")?;
+        invalid.current_dir(working_dir.path()).args([
+            "--config",
+            "invalid.toml",
+            "--ignore-pipes",
+            "test.txt",
+        ]);
+        invalid
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("invalid value for zstd.level"));
+
+        let mut missing = Command::cargo_bin("cmprss")?;
+        missing.current_dir(working_dir.path()).args([
+            "--config",
+            "missing.toml",
+            "--ignore-pipes",
+            "test.txt",
+        ]);
+        missing.assert().failure().stderr(predicate::str::contains(
+            "config file missing.toml not found",
+        ));
+
+        Ok(())
+    }
+
+    /// A profile from the config file, which picks the format when no filename names one
+    ///
+    /// ``` bash
+    /// cmprss --profile backup dir
+    /// ```
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn profile() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("dir/notes.txt")
+            .write_str("garbage data")?;
+        working_dir.child("dir/build.o").write_str("object")?;
+        working_dir.child("cmprss/config.toml").write_str(
+            r#"
+            [profile.backup]
+            format = "tar.zst"
+            level = 19
+            checksum = "sha256"
+            exclude = ["*.o"]
+            "#,
+        )?;
+
+        let mut backup = Command::cargo_bin("cmprss")?;
+        backup
+            .current_dir(working_dir.path())
+            .env("XDG_CONFIG_HOME", working_dir.path())
+            .args(["--profile", "backup", "--ignore-pipes", "dir"]);
+        backup.assert().success();
+        working_dir
+            .child("dir.tar.zst.sha256")
+            .assert(predicate::path::exists());
+
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.current_dir(working_dir.path())
+            .args(["list", "dir.tar.zst"]);
+        list.assert()
+            .success()
+            .stdout(predicate::str::contains("notes.txt"))
+            .stdout(predicate::str::contains("build.o").not());
+
+        let mut unknown = Command::cargo_bin("cmprss")?;
+        unknown
+            .current_dir(working_dir.path())
+            .env("XDG_CONFIG_HOME", working_dir.path())
+            .args(["--profile", "quick", "--ignore-pipes", "dir"]);
+        unknown
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("no profile named quick"));
+
+        Ok(())
+    }
+
+    /// Defaults from environment variables, beneath the options given on the command line
+    ///
+    /// ``` bash
+    /// CMPRSS_LEVEL=19 cmprss test.txt test.txt.zst
+    /// CMPRSS_FORMAT=tar.zst cmprss dir
+    /// ```
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn environment_defaults() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let data: String = (0..20000)
+            .map(|i| format!("line {} has {}\n", i, i * 7919 % 1000))
+            .collect();
+        working_dir.child("test.txt").write_str(&data)?;
+        working_dir.child("dir/test.txt").write_str(&data)?;
+
+        let run = |env: &[(&str, &str)], args: &[&str]| -> Result<(), Box<dyn std::error::Error>> {
+            let mut cmd = Command::cargo_bin("cmprss")?;
+            cmd.current_dir(working_dir.path())
+                .env("XDG_CONFIG_HOME", working_dir.path())
+                .envs(env.iter().copied())
+                .args(args);
+            cmd.assert().success();
+            Ok(())
+        };
+        let read = |name: &str| std::fs::read(working_dir.child(name).path());
+        run(
+            &[],
+            &[
+                "zstd",
+                "--level",
+                "19",
+                "--ignore-pipes",
+                "test.txt",
+                "level_19.zst",
+            ],
+        )?;
+        let level_19 = read("level_19.zst")?;
+
+        let level = [("CMPRSS_LEVEL", "19")];
+        run(&level, &["--ignore-pipes", "test.txt", "inferred.zst"])?;
+        assert_eq!(read("inferred.zst")?, level_19);
+        run(&level, &["zstd", "--ignore-pipes", "test.txt", "sub.zst"])?;
+        assert_eq!(read("sub.zst")?, level_19);
+        run(
+            &level,
+            &[
+                "zstd",
+                "--level",
+                "3",
+                "--ignore-pipes",
+                "test.txt",
+                "explicit.zst",
+            ],
+        )?;
+        assert_ne!(read("explicit.zst")?, level_19);
+        // The level doesn't suit gzip, which keeps its own default
+        run(
+            &level,
+            &["gzip", "--ignore-pipes", "test.txt", "test.txt.gz"],
+        )?;
+
+        run(&[("CMPRSS_FORMAT", "tar.zst")], &["--ignore-pipes", "dir"])?;
+        working_dir
+            .child("dir.tar.zst")
+            .assert(predicate::path::exists());
+
+        let mut invalid = Command::cargo_bin("cmprss")?;
+        invalid
+            .current_dir(working_dir.path())
+            .env("CMPRSS_THREADS", "many")
+            .args(["--ignore-pipes", "test.txt", "threads.gz"]);
+        invalid
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("invalid value for CMPRSS_THREADS"));
+
+        Ok(())
+    }
+
+    /// Print the inferred plan without writing anything
+    ///
+    /// ``` bash
+    /// cmprss --dry-run dir dir.tar.gz
+    /// cmprss gzip -n dir/test.txt other.txt
+    /// ```
+    #[test]
+    fn dry_run() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("dir/test.txt")
+            .write_str("garbage data")?;
+
+        let mut dry_run = Command::cargo_bin("cmprss")?;
+        dry_run.current_dir(working_dir.path()).args([
+            "--dry-run",
+            "--ignore-pipes",
+            "--rm-source",
+            "dir",
+            "dir.tar.gz",
+        ]);
+        dry_run.assert().success().stdout(
+            "action: compress\nformat: tar.gz\nlevel: 6\ninput: dir\noutput: dir.tar.gz\nremove inputs: yes\n",
+        );
+        working_dir
+            .child("dir.tar.gz")
+            .assert(predicate::path::missing());
+        working_dir
+            .child("dir/test.txt")
+            .assert(predicate::path::exists());
+
+        // Several files are compressed to their own outputs
+        working_dir.child("other.txt").write_str("garbage data")?;
+        let mut batch = Command::cargo_bin("cmprss")?;
+        batch.current_dir(working_dir.path()).args([
+            "gzip",
+            "-n",
+            "--ignore-pipes",
+            "dir/test.txt",
+            "other.txt",
+        ]);
+        batch.assert().success().stdout(predicate::str::contains(
+            "compress: other.txt -> other.txt.gz",
+        ));
+        working_dir
+            .child("other.txt.gz")
+            .assert(predicate::path::missing());
+
+        // Before a subcommand it would be ignored, so it's an error instead of a real run
+        let mut misplaced = Command::cargo_bin("cmprss")?;
+        misplaced.current_dir(working_dir.path()).args([
+            "--dry-run",
+            "gzip",
+            "--ignore-pipes",
+            "--rm-source",
+            "other.txt",
+        ]);
+        misplaced
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "--dry-run must be given after the gzip command",
+            ));
+        working_dir
+            .child("other.txt.gz")
+            .assert(predicate::path::missing());
+        working_dir
+            .child("other.txt")
+            .assert(predicate::path::exists());
+
+        Ok(())
+    }
+
+    /// Report what cmprss does with -v and -vv, and nothing but errors with -q
+    ///
+    /// ``` bash
+    /// cmprss -vv --exclude '*.o' dir dir.tar.gz
+    /// cmprss gzip -q --no-clobber dir/test.txt
+    /// ```
+    #[test]
+    fn verbosity() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("dir/test.txt")
+            .write_str("garbage data")?;
+        working_dir.child("dir/build.o").write_str("object")?;
+
+        let mut verbose = Command::cargo_bin("cmprss")?;
+        verbose.current_dir(working_dir.path()).args([
+            "-vv",
+            "--ignore-pipes",
+            "--exclude",
+            "*.o",
+            "dir",
+            "dir.tar.gz",
+        ]);
+        verbose
+            .assert()
+            .success()
+            .stderr(predicate::str::contains(
+                "cmprss: inferred tar.gz to compress",
+            ))
+            .stderr(predicate::str::contains("cmprss: adding dir/test.txt"))
+            .stderr(
+                predicate::str::contains("cmprss: skipping")
+                    .and(predicate::str::contains("build.o, it is excluded")),
+            );
+
+        let mut gzip = Command::cargo_bin("cmprss")?;
+        gzip.current_dir(working_dir.path())
+            .args(["gzip", "--ignore-pipes", "dir/test.txt"]);
+        gzip.assert().success();
+
+        let mut normal = Command::cargo_bin("cmprss")?;
+        normal.current_dir(working_dir.path()).args([
+            "gzip",
+            "--ignore-pipes",
+            "--no-clobber",
+            "dir/test.txt",
+        ]);
+        normal
+            .assert()
+            .success()
+            .stderr(predicate::str::contains("skipping, output path"));
+
+        let mut quiet = Command::cargo_bin("cmprss")?;
+        quiet.current_dir(working_dir.path()).args([
+            "gzip",
+            "-q",
+            "--ignore-pipes",
+            "--no-clobber",
+            "dir/test.txt",
+        ]);
+        quiet.assert().success().stderr("");
+
+        Ok(())
+    }
+
+    /// Extract an old .tar.Z archive, inferred from the extension or the contents
+    ///
+    /// ``` bash
+    /// cmprss dir dir.tar.Z
+    /// cmprss dir.tar.Z out
+    /// ```
+    #[test]
+    fn lzw() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("dir/test.txt")
+            .write_str("garbage data for testing")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "dir", "dir.tar.Z"]);
+        compress.assert().success();
+
+        working_dir.child("out").create_dir_all()?;
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "dir.tar.Z", "out"]);
+        extract.assert().success();
+        working_dir
+            .child("out/dir/test.txt")
+            .assert(predicate::path::eq_file(
+                working_dir.child("dir/test.txt").path(),
+            ));
+
+        // Without the extension, the contents show it's a compressed tar
+        std::fs::rename(
+            working_dir.child("dir.tar.Z").path(),
+            working_dir.child("old").path(),
+        )?;
+        working_dir.child("detected").create_dir_all()?;
+        let mut detect = Command::cargo_bin("cmprss")?;
+        detect.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "--extract",
+            "old",
+            "detected",
+        ]);
+        detect.assert().success();
+        working_dir
+            .child("detected/dir/test.txt")
+            .assert(predicate::path::exists());
+
+        Ok(())
+    }
+
+    /// Compress with lzop at a chosen level, inferring the format when extracting
+    ///
+    /// ``` bash
+    /// cmprss lzop --level 9 test.txt
+    /// cmprss test.txt.lzo out.txt
+    /// ```
+    #[test]
+    fn lzop() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("test.txt");
+        file.write_str(&"garbage data for testing ".repeat(100))?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "lzop",
+            "--level",
+            "9",
+            "--ignore-pipes",
+            "test.txt",
+        ]);
+        compress.assert().success();
+        working_dir
+            .child("test.txt.lzo")
+            .assert(predicate::path::is_file());
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "test.txt.lzo", "out.txt"]);
+        extract.assert().success();
+        working_dir
+            .child("out.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// Create, list and extract ar archives like static libraries and .deb packages
+    ///
+    /// ``` bash
+    /// cmprss one.o two.o libobjects.a
+    /// cmprss list libobjects.a
+    /// cmprss libobjects.a out
+    /// ```
+    #[test]
+    fn ar() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir.child("one.o").write_str("first object")?;
+        working_dir.child("two.o").write_str("second")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "one.o",
+            "two.o",
+            "libobjects.a",
+        ]);
+        compress.assert().success();
+
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.current_dir(working_dir.path())
+            .args(["list", "libobjects.a"]);
+        list.assert()
+            .success()
+            .stdout(predicate::str::contains("one.o").and(predicate::str::contains("two.o")));
+
+        working_dir.child("out").create_dir_all()?;
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "libobjects.a", "out"]);
+        extract.assert().success();
+        working_dir.child("out/one.o").assert("first object");
+        working_dir.child("out/two.o").assert("second");
+
+        Ok(())
+    }
+
+    /// Extract a rar archive, which cmprss can't create
+    ///
+    /// ``` bash
+    /// cmprss list archive.rar
+    /// cmprss archive.rar out
+    /// ```
+    #[test]
+    fn rar() -> Result<(), Box<dyn std::error::Error>> {
+        // A RAR 5 header, its checksum covers its size and fields
+        fn header(fields: &[u8]) -> Vec<u8> {
+            let mut header = vec![fields.len() as u8];
+            header.extend_from_slice(fields);
+            let mut block = crc32fast::hash(&header).to_le_bytes().to_vec();
+            block.extend(header);
+            block
+        }
+        let data = b"garbage data for testing";
+        let mut archive = b"Rar!\x1a\x07\x01\x00".to_vec();
+        archive.extend(header(&[1, 0, 0]));
+        // A file stored without compression, with its checksum
+        let mut file = vec![2, 2, data.len() as u8, 4, data.len() as u8, 0];
+        file.extend(crc32fast::hash(data).to_le_bytes());
+        file.extend([0, 1, 8]);
+        file.extend(b"test.txt");
+        archive.extend(header(&file));
+        archive.extend(data);
+        archive.extend(header(&[5, 0, 0]));
+
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir.child("archive.rar").write_binary(&archive)?;
+
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.current_dir(working_dir.path())
+            .args(["list", "archive.rar"]);
+        list.assert()
+            .success()
+            .stdout(predicate::str::contains("test.txt"));
+
+        working_dir.child("out").create_dir_all()?;
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "archive.rar", "out"]);
+        extract.assert().success();
+        working_dir
+            .child("out/test.txt")
+            .assert("garbage data for testing");
+
+        Ok(())
+    }
+
+    /// List and extract the files of an ISO 9660 disc image
+    ///
+    /// ``` bash
+    /// cmprss list image.iso
+    /// cmprss image.iso out
+    /// ```
+    #[test]
+    fn iso() -> Result<(), Box<dyn std::error::Error>> {
+        // A directory record, without any extensions
+        fn record(name: &[u8], sector: u32, length: u32, dir: bool) -> Vec<u8> {
+            let mut record = vec![0; 33];
+            record[0] = (33 + name.len() + (1 - name.len() % 2)) as u8;
+            record[2..6].copy_from_slice(&sector.to_le_bytes());
+            record[10..14].copy_from_slice(&length.to_le_bytes());
+            record[18..21].copy_from_slice(&[124, 1, 1]);
+            record[25] = if dir { 2 } else { 0 };
+            record[32] = name.len() as u8;
+            record.extend_from_slice(name);
+            record.resize(record[0] as usize, 0);
+            record
+        }
+        let data = b"garbage data for testing";
+        // The primary volume descriptor, the terminator, the root directory, then the file
+        let mut image = vec![0; 20 * 2048];
+        image[16 * 2048..16 * 2048 + 6].copy_from_slice(b"\x01CD001");
+        image[16 * 2048 + 156..16 * 2048 + 190].copy_from_slice(&record(&[0], 18, 2048, true));
+        image[17 * 2048..17 * 2048 + 6].copy_from_slice(b"\xffCD001");
+        let mut root = record(&[0], 18, 2048, true);
+        root.extend(record(&[1], 18, 2048, true));
+        root.extend(record(b"TEST.TXT;1", 19, data.len() as u32, false));
+        image[18 * 2048..18 * 2048 + root.len()].copy_from_slice(&root);
+        image[19 * 2048..19 * 2048 + data.len()].copy_from_slice(data);
+
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir.child("image.iso").write_binary(&image)?;
+
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.current_dir(working_dir.path())
+            .args(["list", "image.iso"]);
+        list.assert()
+            .success()
+            .stdout(predicate::str::contains("TEST.TXT"));
+
+        working_dir.child("out").create_dir_all()?;
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "image.iso", "out"]);
+        extract.assert().success();
+        working_dir
+            .child("out/TEST.TXT")
+            .assert("garbage data for testing");
+
+        Ok(())
+    }
+
+    /// Extract the files of a .deb package, unwrapping the ar archive and the compressed tar
+    ///
+    /// ``` bash
+    /// cmprss usr data.tar.gz
+    /// cmprss debian-binary data.tar.gz tool_1.0_all.deb
+    /// cmprss extract tool_1.0_all.deb
+    /// ```
+    #[test]
+    fn extract_package() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir.child("usr/bin/tool").write_str("#!/bin/sh\n")?;
+        working_dir.child("debian-binary").write_str("2.0\n")?;
+
+        let mut data = Command::cargo_bin("cmprss")?;
+        data.current_dir(working_dir.path())
+            .args(["--ignore-pipes", "usr", "data.tar.gz"]);
+        data.assert().success();
+
+        let mut package = Command::cargo_bin("cmprss")?;
+        package.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "debian-binary",
+            "data.tar.gz",
+            "tool_1.0_all.deb",
+        ]);
+        package.assert().success();
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["extract", "tool_1.0_all.deb"]);
+        extract.assert().success();
+        working_dir
+            .child("tool_1.0_all/usr/bin/tool")
+            .assert("#!/bin/sh\n");
+
+        Ok(())
+    }
+
+    /// Compress and extract bare zlib streams and raw deflate data
+    ///
+    /// ``` bash
+    /// cmprss zlib test.txt test.txt.zz
+    /// cmprss test.txt.zz out.txt
+    /// cmprss zlib --raw-deflate test.txt test.deflate
+    /// cmprss zlib --raw-deflate --extract test.deflate raw.txt
+    /// ```
+    #[test]
+    fn zlib() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("test.txt");
+        file.write_str("garbage data for testing")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "zlib",
+            "--ignore-pipes",
+            "test.txt",
+            "test.txt.zz",
+        ]);
+        compress.assert().success();
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "test.txt.zz", "out.txt"]);
+        extract.assert().success();
+        working_dir
+            .child("out.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "zlib",
+            "--raw-deflate",
+            "--ignore-pipes",
+            "test.txt",
+            "test.deflate",
+        ]);
+        compress.assert().success();
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "zlib",
+            "--raw-deflate",
+            "--extract",
+            "--ignore-pipes",
+            "test.deflate",
+            "raw.txt",
+        ]);
+        extract.assert().success();
+        working_dir
+            .child("raw.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// Compress with lz4 in its high compression mode, inferring the format when extracting
+    ///
+    /// ``` bash
+    /// cmprss lz4 --level 12 --block-size 64K --no-content-checksum test.txt
+    /// cmprss test.txt.lz4 out.txt
+    /// ```
+    #[test]
+    fn lz4() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("test.txt");
+        file.write_str(&"garbage data for testing ".repeat(5000))?;
+
+        let mut invalid = Command::cargo_bin("cmprss")?;
+        invalid.current_dir(working_dir.path()).args([
+            "lz4",
+            "--level",
+            "13",
+            "--ignore-pipes",
+            "test.txt",
+        ]);
+        invalid.assert().failure();
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "lz4",
+            "--level",
+            "12",
+            "--block-size",
+            "64K",
+            "--no-content-checksum",
+            "--ignore-pipes",
+            "test.txt",
+        ]);
+        compress.assert().success();
+        working_dir
+            .child("test.txt.lz4")
+            .assert(predicate::path::is_file());
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "test.txt.lz4", "out.txt"]);
+        extract.assert().success();
+        working_dir
+            .child("out.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// Store the original name in the gzip header, and restore it when extracting
+    ///
+    /// ``` bash
+    /// cmprss gzip test.txt renamed.gz
+    /// cmprss gzip --name --extract renamed.gz
+    /// cmprss gzip --no-name test.txt bare.gz
+    /// ```
+    #[test]
+    fn gzip_name() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let source = working_dir.child("source");
+        source.create_dir_all()?;
+        let file = source.child("test.txt");
+        file.write_str("garbage data for testing")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "gzip",
+            "--ignore-pipes",
+            "source/test.txt",
+            "renamed.gz",
+        ]);
+        compress.assert().success();
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "gzip",
+            "--name",
+            "--extract",
+            "--ignore-pipes",
+            "renamed.gz",
+        ]);
+        extract.assert().success();
+        working_dir
+            .child("test.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        let mut bare = Command::cargo_bin("cmprss")?;
+        bare.current_dir(working_dir.path()).args([
+            "gzip",
+            "--no-name",
+            "--ignore-pipes",
+            "source/test.txt",
+            "bare.gz",
+        ]);
+        bare.assert().success();
+        working_dir
+            .child("bare.gz")
+            .assert(predicate::function(|path: &std::path::Path| {
+                !std::fs::read(path)
+                    .unwrap()
+                    .windows(8)
+                    .any(|w| w == b"test.txt")
+            }));
+
+        Ok(())
+    }
+
+    /// Long distance matching with a large window, which is also needed to extract
+    ///
+    /// ``` bash
+    /// cmprss zstd --long=30 --level 22 test.txt
+    /// cmprss zstd --long=30 --extract test.txt.zst out.txt
+    /// ```
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn zstd_long() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("test.txt");
+        file.write_str(&"garbage data for testing ".repeat(100))?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "zstd",
+            "--long=30",
+            "--level",
+            "22",
+            "--ignore-pipes",
+            "test.txt",
+        ]);
+        compress.assert().success();
+
+        let mut default_window = Command::cargo_bin("cmprss")?;
+        default_window.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "test.txt.zst",
+            "out.txt",
+        ]);
+        default_window
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("--long"));
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "zstd",
+            "--long=30",
+            "--extract",
+            "--ignore-pipes",
+            "test.txt.zst",
+            "out.txt",
+        ]);
+        extract.assert().success();
+        working_dir
+            .child("out.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// Tune the zstd level to the speed of the output pipe
+    ///
+    /// ``` bash
+    /// cmprss zstd --adapt test.txt > test.txt.zst
+    /// cmprss zstd --extract test.txt.zst out.txt
+    /// ```
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn zstd_adapt() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("test.txt");
+        file.write_str(&"garbage data for testing ".repeat(100))?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["zstd", "--adapt", "test.txt"]);
+        let output = compress.assert().success().get_output().stdout.clone();
+        working_dir.child("test.txt.zst").write_binary(&output)?;
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "zstd",
+            "--extract",
+            "--ignore-pipes",
+            "test.txt.zst",
+            "out.txt",
+        ]);
+        extract.assert().success();
+        working_dir
+            .child("out.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// Pick the integrity check of xz and the extreme variant of the level
+    ///
+    /// ``` bash
+    /// cmprss xz --check sha256 --extreme test.txt
+    /// cmprss xz --check none --extreme test.txt other.xz
+    /// cmprss test.txt.xz out.txt
+    /// ```
+    #[test]
+    #[cfg(feature = "xz")]
+    fn xz_check() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("test.txt");
+        file.write_str(&"garbage data for testing ".repeat(100))?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "xz",
+            "--check",
+            "sha256",
+            "--extreme",
+            "--ignore-pipes",
+            "test.txt",
+        ]);
+        compress.assert().success();
+
+        let mut no_check = Command::cargo_bin("cmprss")?;
+        no_check.current_dir(working_dir.path()).args([
+            "xz",
+            "--check",
+            "none",
+            "--extreme",
+            "--ignore-pipes",
+            "test.txt",
+            "other.xz",
+        ]);
+        no_check.assert().success();
+        let with_check = std::fs::metadata(working_dir.child("test.txt.xz").path())?.len();
+        let without = std::fs::metadata(working_dir.child("other.xz").path())?.len();
+        assert_eq!(with_check, without + 32);
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "test.txt.xz", "out.txt"]);
+        extract.assert().success();
+        working_dir
+            .child("out.txt")
+            .assert(predicate::path::eq_file(file.path()));
+
+        Ok(())
+    }
+
+    /// Concatenated gzip files are extracted whole, like with gunzip
+    ///
+    /// ``` bash
+    /// cat a.gz b.gz > ab.gz
+    /// cmprss gzip --extract < ab.gz > out.txt
+    /// ```
+    #[test]
+    fn gzip_concatenated() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        for (name, content) in [("a.txt", "first "), ("b.txt", "second")] {
+            working_dir.child(name).write_str(content)?;
+            let mut compress = Command::cargo_bin("cmprss")?;
+            compress
+                .current_dir(working_dir.path())
+                .args(["gzip", "--ignore-pipes", name]);
+            compress.assert().success();
+        }
+        let mut concatenated = std::fs::read(working_dir.child("a.txt.gz").path())?;
+        concatenated.extend(std::fs::read(working_dir.child("b.txt.gz").path())?);
+        let archive = working_dir.child("ab.gz");
+        archive.write_binary(&concatenated)?;
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["gzip", "--extract"])
+            .stdin(Stdio::from(File::open(archive.path())?));
+        extract.assert().success().stdout("first second");
+
+        Ok(())
+    }
+
+    /// Pick the compression method of zip entries, storing compressed files
+    ///
+    /// ``` bash
+    /// cmprss zip --method store --method-for txt=deflate dir archive.zip
+    /// cmprss archive.zip out
+    /// ```
+    #[test]
+    fn zip_method() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let dir = working_dir.child("dir");
+        let text = dir.child("test.txt");
+        text.write_str(&"garbage data for testing ".repeat(100))?;
+        let other = dir.child("other.dat");
+        other.write_str(&"garbage data for testing ".repeat(100))?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "zip",
+            "--method",
+            "store",
+            "--method-for",
+            "txt=deflate",
+            "--ignore-pipes",
+            "dir",
+            "archive.zip",
+        ]);
+        compress.assert().success();
+        // The stored file is in the archive as it is
+        let archive = std::fs::read(working_dir.child("archive.zip").path())?;
+        let data = std::fs::read(other.path())?;
+        assert!(archive.windows(data.len()).any(|w| w == data));
+        assert!(archive.len() < 2 * data.len());
+
+        let mut invalid = Command::cargo_bin("cmprss")?;
+        invalid.current_dir(working_dir.path()).args([
+            "zip",
+            "--method-for",
+            "txt",
+            "--ignore-pipes",
+            "dir",
+            "invalid.zip",
+        ]);
+        invalid.assert().failure();
+
+        working_dir.child("out").create_dir_all()?;
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "archive.zip", "out"]);
+        extract.assert().success();
+        working_dir
+            .child("out/dir/test.txt")
+            .assert(predicate::path::eq_file(text.path()));
+        working_dir
+            .child("out/dir/other.dat")
+            .assert(predicate::path::eq_file(other.path()));
+
+        Ok(())
+    }
+
+    /// The options of the format are kept when archiving several inputs
+    ///
+    /// ``` bash
+    /// cmprss zip --method store a.dat b.dat archive.zip
+    /// ```
+    #[test]
+    fn zip_options_multiple_inputs() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let content = "garbage data for testing ".repeat(100);
+        working_dir.child("a.dat").write_str(&content)?;
+        working_dir.child("b.dat").write_str(&content)?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "zip",
+            "--method",
+            "store",
+            "--ignore-pipes",
+            "a.dat",
+            "b.dat",
+            "archive.zip",
+        ]);
+        compress.assert().success();
+        let archive = std::fs::read(working_dir.child("archive.zip").path())?;
+        assert!(archive.len() > 2 * content.len());
+
+        Ok(())
+    }
+
+    /// Zip64 archives with an entry over 4GB, written to a file and to stdout
+    /// The input is a sparse file, but the archive and the extracted file are not, so this
+    /// needs about 15GB of disk space and only runs with `cargo test -- --ignored`.
+    ///
+    /// ``` bash
+    /// cmprss zip --method store big.bin small.txt archive.zip
+    /// cmprss zip --method store big.bin small.txt > streamed.zip
+    /// cmprss list archive.zip
+    /// cmprss zip --extract streamed.zip out
+    /// ```
+    #[test]
+    #[ignore]
+    fn zip64_large_file() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let size: u64 = 4_500_000_000;
+        File::create(working_dir.child("big.bin").path())?.set_len(size)?;
+        working_dir
+            .child("small.txt")
+            .write_str("garbage data for testing")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "zip",
+            "--method",
+            "store",
+            "--ignore-pipes",
+            "big.bin",
+            "small.txt",
+            "archive.zip",
+        ]);
+        compress.assert().success();
+
+        let mut stream = Command::cargo_bin("cmprss")?;
+        stream
+            .current_dir(working_dir.path())
+            .args(["zip", "--method", "store", "big.bin", "small.txt"])
+            .stdin(Stdio::null())
+            .stdout(File::create(working_dir.child("streamed.zip").path())?);
+        stream.assert().success();
+
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.current_dir(working_dir.path())
+            .args(["list", "archive.zip"]);
+        list.assert()
+            .success()
+            .stdout(predicate::str::contains(size.to_string()))
+            .stdout(predicate::str::contains("small.txt"));
+        std::fs::remove_file(working_dir.child("archive.zip").path())?;
+
+        working_dir.child("out").create_dir_all()?;
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "zip",
+            "--extract",
+            "--ignore-pipes",
+            "streamed.zip",
+            "out",
+        ]);
+        extract.assert().success();
+        assert_eq!(
+            std::fs::metadata(working_dir.child("out/big.bin").path())?.len(),
+            size
+        );
+        working_dir
+            .child("out/small.txt")
+            .assert(predicate::path::eq_file(
+                working_dir.child("small.txt").path(),
+            ));
+
+        Ok(())
+    }
+
+    /// Store a comment in a zip archive, and show it when listing the archive
+    ///
+    /// ``` bash
+    /// cmprss zip --comment "build 42" test.txt archive.zip
+    /// cmprss list archive.zip
+    /// ```
+    #[test]
+    fn zip_comment() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("test.txt")
+            .write_str("garbage data for testing")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "zip",
+            "--comment",
+            "build 42",
+            "--ignore-pipes",
+            "test.txt",
+            "archive.zip",
+        ]);
+        compress.assert().success();
+
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.current_dir(working_dir.path())
+            .args(["list", "archive.zip"]);
+        list.assert()
+            .success()
+            .stdout(predicate::str::starts_with("build 42\n"))
+            .stdout(predicate::str::contains("test.txt"));
+
+        // Other tools read the comment too
+        if let Ok(output) = Command::new("unzip")
+            .arg("-z")
+            .arg(working_dir.child("archive.zip").path())
+            .output()
+        {
+            assert!(String::from_utf8_lossy(&output.stdout).contains("build 42"));
+        }
+
+        Ok(())
+    }
+
+    /// Symbolic links are stored as links, unless they are dereferenced or skipped
+    ///
+    /// ``` bash
+    /// cmprss tar dir archive.tar
+    /// cmprss tar --dereference dir followed.tar
+    /// cmprss tar --extract --skip-symlinks archive.tar out
+    /// ```
+    #[test]
+    #[cfg(unix)]
+    fn symlinks() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("dir/test.txt")
+            .write_str("garbage data for testing")?;
+        std::os::unix::fs::symlink("test.txt", working_dir.child("dir/link").path())?;
+
+        for (flag, archive) in [
+            (None, "archive.tar"),
+            (Some("--dereference"), "followed.tar"),
+        ] {
+            let mut compress = Command::cargo_bin("cmprss")?;
+            compress
+                .current_dir(working_dir.path())
+                .arg("tar")
+                .args(flag)
+                .args(["--ignore-pipes", "dir", archive]);
+            compress.assert().success();
+        }
+
+        for (flag, archive, out) in [
+            (None, "archive.tar", "linked"),
+            (None, "followed.tar", "followed"),
+            (Some("--skip-symlinks"), "archive.tar", "skipped"),
+        ] {
+            working_dir.child(out).create_dir_all()?;
+            let mut extract = Command::cargo_bin("cmprss")?;
+            extract
+                .current_dir(working_dir.path())
+                .args(["tar", "--extract"])
+                .args(flag)
+                .args(["--ignore-pipes", archive, out]);
+            extract.assert().success();
+        }
+        let link =
+            |out: &str| std::fs::symlink_metadata(working_dir.child(out).child("dir/link").path());
+        assert!(link("linked")?.is_symlink());
+        assert!(link("followed")?.is_file());
+        assert!(link("skipped").is_err());
+
+        Ok(())
+    }
+
+    /// Hard links are stored once in tar archives, and linked again when extracted
+    ///
+    /// ``` bash
+    /// cmprss tar dir archive.tar
+    /// cmprss tar --extract archive.tar out
+    /// ```
+    #[test]
+    #[cfg(unix)]
+    fn tar_hard_links() -> Result<(), Box<dyn std::error::Error>> {
+        use std::os::unix::fs::MetadataExt;
+        let working_dir = assert_fs::TempDir::new()?;
+        let content = "garbage data for testing".repeat(1000);
+        working_dir.child("dir/a.txt").write_str(&content)?;
+        std::fs::hard_link(
+            working_dir.child("dir/a.txt").path(),
+            working_dir.child("dir/b.txt").path(),
+        )?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "tar",
+            "--ignore-pipes",
+            "dir",
+            "archive.tar",
+        ]);
+        compress.assert().success();
+        let archive = std::fs::metadata(working_dir.child("archive.tar").path())?;
+        assert!(archive.len() < 2 * content.len() as u64);
+
+        working_dir.child("out").create_dir_all()?;
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "tar",
+            "--extract",
+            "--ignore-pipes",
+            "archive.tar",
+            "out",
+        ]);
+        extract.assert().success();
+        let inode = |name: &str| std::fs::metadata(working_dir.child(name).path()).map(|m| m.ino());
+        assert_eq!(inode("out/dir/a.txt")?, inode("out/dir/b.txt")?);
+        working_dir.child("out/dir/b.txt").assert(content.as_str());
+
+        Ok(())
+    }
+
+    /// Pick the format of the tar headers
+    ///
+    /// ``` bash
+    /// cmprss tar --format ustar dir archive.tar
+    /// ```
+    #[test]
+    fn tar_format() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("dir/test.txt")
+            .write_str("garbage data for testing")?;
+        working_dir
+            .child("long")
+            .child("name ".repeat(40))
+            .write_str("garbage data for testing")?;
+
+        for format in ["ustar", "gnu", "pax"] {
+            let mut compress = Command::cargo_bin("cmprss")?;
+            compress.current_dir(working_dir.path()).args([
+                "tar",
+                "--format",
+                format,
+                "--ignore-pipes",
+                "dir",
+                &format!("{}.tar", format),
+            ]);
+            compress.assert().success();
+        }
+        let ustar = std::fs::read(working_dir.child("ustar.tar").path())?;
+        assert_eq!(&ustar[257..263], b"ustar\0");
+        let gnu = std::fs::read(working_dir.child("gnu.tar").path())?;
+        assert_eq!(&gnu[257..265], b"ustar  \0");
+
+        let mut long = Command::cargo_bin("cmprss")?;
+        long.current_dir(working_dir.path()).args([
+            "tar",
+            "--format",
+            "ustar",
+            "--ignore-pipes",
+            "long",
+            "long.tar",
+        ]);
+        long.assert()
+            .failure()
+            .stderr(predicate::str::contains("--format pax"));
+
+        Ok(())
+    }
+
+    /// Override the owners stored in a tar archive, like for a root filesystem image
+    ///
+    /// ``` bash
+    /// cmprss tar --owner root:0 --group 100 --numeric-owner rootfs rootfs.tar
+    /// ```
+    #[test]
+    fn tar_owner() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("rootfs/etc/hostname")
+            .write_str("builder")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "tar",
+            "--owner",
+            "root:0",
+            "--group",
+            "100",
+            "--numeric-owner",
+            "--ignore-pipes",
+            "rootfs",
+            "rootfs.tar",
+        ]);
+        compress.assert().success();
+
+        let mut archive = tar::Archive::new(File::open(working_dir.child("rootfs.tar").path())?);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let header = entry.header();
+            assert_eq!((header.uid()?, header.gid()?), (0, 100));
+            assert_eq!(header.username().ok().flatten(), Some(""));
+        }
+
+        let mut invalid = Command::cargo_bin("cmprss")?;
+        invalid.current_dir(working_dir.path()).args([
+            "tar",
+            "--owner",
+            "no-such-user-here",
+            "--ignore-pipes",
+            "rootfs",
+            "invalid.tar",
+        ]);
+        invalid.assert().failure();
+
+        Ok(())
+    }
+
+    /// Store the same modification time for all the files of an archive
+    ///
+    /// ``` bash
+    /// cmprss tar --mtime "2024-01-31 12:30:00" dir archive.tar
+    /// cmprss zip --mtime @1706704200 --clamp-mtime dir archive.zip
+    /// ```
+    #[test]
+    fn mtime() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("dir/test.txt")
+            .write_str("garbage data for testing")?;
+
+        for (archive, flags) in [
+            ("set.tar", &["--mtime", "2024-01-31 12:30:00"][..]),
+            ("clamped.zip", &["--mtime", "@1706704200", "--clamp-mtime"]),
+        ] {
+            let mut compress = Command::cargo_bin("cmprss")?;
+            compress.current_dir(working_dir.path()).args(flags).args([
+                "--ignore-pipes",
+                "dir",
+                archive,
+            ]);
+            compress.assert().success();
+            let mut list = Command::cargo_bin("cmprss")?;
+            list.current_dir(working_dir.path()).args(["list", archive]);
+            list.assert()
+                .success()
+                .stdout(predicate::str::contains("2024-01-31 12:30  dir/test.txt"));
+        }
+
+        let mut invalid = Command::cargo_bin("cmprss")?;
+        invalid.current_dir(working_dir.path()).args([
+            "tar",
+            "--mtime",
+            "yesterday",
+            "dir",
+            "invalid.tar",
+        ]);
+        invalid
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("not a timestamp"));
+
+        Ok(())
+    }
+
+    /// Split an archive into parts, and extract it from the first part
+    ///
+    /// ``` bash
+    /// cmprss --split-size 2gb dir archive.tar.zst
+    /// cmprss archive.tar.zst.001 out
+    /// ```
+    #[test]
+    fn split_size() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        // Random data so that the archives are larger than a part even when compressed
+        let content: Vec<u8> = {
+            use rand::Rng;
+            let mut rng = rand::thread_rng();
+            (0..5000).map(|_| rng.gen()).collect()
+        };
+        working_dir.child("dir/test.bin").write_binary(&content)?;
+        working_dir.child("out").create_dir_all()?;
+
+        for archive in ["archive.tar", "archive.zip"] {
+            let mut compress = Command::cargo_bin("cmprss")?;
+            compress.current_dir(working_dir.path()).args([
+                "--split-size",
+                "1000",
+                "--ignore-pipes",
+                "dir",
+                archive,
+            ]);
+            compress.assert().success();
+            working_dir
+                .child(archive)
+                .assert(predicate::path::missing());
+            let first = format!("{}.001", archive);
+            let size = std::fs::metadata(working_dir.child(&first).path())?.len();
+            assert_eq!(size, 1000);
+            working_dir
+                .child(format!("{}.002", archive))
+                .assert(predicate::path::exists());
+
+            let mut extract = Command::cargo_bin("cmprss")?;
+            extract.current_dir(working_dir.path()).args([
+                "--ignore-pipes",
+                "--force",
+                &first,
+                "out",
+            ]);
+            extract.assert().success();
+            assert_eq!(
+                std::fs::read(working_dir.child("out/dir/test.bin").path())?,
+                content
+            );
+        }
+
+        let mut stdout = Command::cargo_bin("cmprss")?;
+        stdout
+            .current_dir(working_dir.path())
+            .args(["tar", "--split-size", "1000", "dir"]);
+        stdout
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("--split-size"));
+
+        Ok(())
+    }
+
+    /// Extract the only file of an archive, or a chosen one, to stdout
+    ///
+    /// ``` bash
+    /// cmprss zip -d one-file.zip | jq .
+    /// cmprss --to-stdout dir/b.txt archive.tar.gz
+    /// ```
+    #[test]
+    fn extract_to_stdout() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir.child("one/data.json").write_str("{\"a\":1}")?;
+        working_dir.child("dir/a.txt").write_str("first")?;
+        working_dir.child("dir/b.txt").write_str("second")?;
+
+        for archive in ["one.zip", "one.tar.gz"] {
+            let mut compress = Command::cargo_bin("cmprss")?;
+            compress
+                .current_dir(working_dir.path())
+                .args(["--ignore-pipes", "one", archive]);
+            compress.assert().success();
+            let mut extract = Command::cargo_bin("cmprss")?;
+            extract
+                .current_dir(working_dir.path())
+                .args(["--extract", archive]);
+            extract.assert().success().stdout("{\"a\":1}");
+        }
+
+        for archive in ["dir.zip", "dir.tar.gz"] {
+            let mut compress = Command::cargo_bin("cmprss")?;
+            compress
+                .current_dir(working_dir.path())
+                .args(["--ignore-pipes", "dir", archive]);
+            compress.assert().success();
+            let mut extract = Command::cargo_bin("cmprss")?;
+            extract
+                .current_dir(working_dir.path())
+                .args(["--extract", archive]);
+            extract
+                .assert()
+                .failure()
+                .stderr(predicate::str::contains("--to-stdout"));
+            let mut entry = Command::cargo_bin("cmprss")?;
+            entry
+                .current_dir(working_dir.path())
+                .args(["--to-stdout", "dir/b.txt", archive]);
+            entry.assert().success().stdout("second");
+        }
+
+        Ok(())
+    }
+
+    /// List an archive or print one of its files straight from a URL
+    ///
+    /// ``` bash
+    /// cmprss list http://127.0.0.1:port/dir.tar.gz
+    /// cmprss cat http://127.0.0.1:port/dir.tar.gz dir/test.txt
+    /// ```
+    #[test]
+    #[cfg(feature = "http")]
+    fn list_url() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("dir/test.txt")
+            .write_str("garbage data for testing")?;
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "dir", "dir.tar.gz"]);
+        compress.assert().success();
+        let archive = std::fs::read(working_dir.child("dir.tar.gz").path())?;
+
+        let port = serve_once(archive.clone())?;
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.args(["list", &format!("http://127.0.0.1:{}/dir.tar.gz", port)]);
+        list.assert()
+            .success()
+            .stdout(predicate::str::contains("dir/test.txt"));
+
+        let port = serve_once(archive)?;
+        let mut cat = Command::cargo_bin("cmprss")?;
+        cat.args([
+            "cat",
+            &format!("http://127.0.0.1:{}/dir.tar.gz?download=1", port),
+            "dir/test.txt",
+        ]);
+        cat.assert().success().stdout("garbage data for testing");
+
+        Ok(())
+    }
+
+    /// Extract an archive straight from S3
+    ///
+    /// ``` bash
+    /// cmprss s3://bucket/test.txt.gz out.txt
+    /// cmprss s3://bucket/dir.tar.gz
+    /// ```
+    #[test]
+    #[cfg(feature = "s3")]
+    fn s3_input() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("dir/test.txt")
+            .write_str("garbage data for testing")?;
+        for archive in ["test.txt.gz", "dir.tar.gz"] {
+            let input = match archive {
+                "test.txt.gz" => "dir/test.txt",
+                _ => "dir",
+            };
+            let mut compress = Command::cargo_bin("cmprss")?;
+            compress
+                .current_dir(working_dir.path())
+                .args(["--ignore-pipes", input, archive]);
+            compress.assert().success();
+        }
+        let response = |archive: &str| -> Result<Vec<u8>, std::io::Error> {
+            let body = std::fs::read(working_dir.child(archive).path())?;
+            let mut response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .into_bytes();
+            response.extend(body);
+            Ok(response)
+        };
+        let (port, requests) = serve(vec![response("test.txt.gz")?, response("dir.tar.gz")?])?;
+        let out = working_dir.child("out");
+        out.create_dir_all()?;
+
+        let s3 = |args: &[&str]| -> Result<Command, Box<dyn std::error::Error>> {
+            let mut command = Command::cargo_bin("cmprss")?;
+            command
+                .current_dir(out.path())
+                .env("AWS_ENDPOINT_URL", format!("http://127.0.0.1:{}", port))
+                .env("AWS_ACCESS_KEY_ID", "key")
+                .env("AWS_SECRET_ACCESS_KEY", "secret")
+                .arg("--ignore-pipes")
+                .args(args);
+            Ok(command)
+        };
+        s3(&["s3://bucket/test.txt.gz", "out.txt"])?
+            .assert()
+            .success();
+        out.child("out.txt").assert("garbage data for testing");
+        assert!(requests.recv()?.starts_with("GET /bucket/test.txt.gz "));
+
+        s3(&["s3://bucket/backups/dir.tar.gz"])?.assert().success();
+        out.child("dir/test.txt").assert("garbage data for testing");
+        assert!(requests
+            .recv()?
+            .starts_with("GET /bucket/backups/dir.tar.gz "));
+
+        Ok(())
+    }
+
+    /// Stream the output to an scp style location, using a fake ssh that runs the command locally
+    ///
+    /// ``` bash
+    /// cmprss dir user@host:/backups/dir.tar.gz
+    /// ```
+    #[test]
+    #[cfg(unix)]
+    fn scp_output() -> Result<(), Box<dyn std::error::Error>> {
+        use std::os::unix::fs::PermissionsExt;
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("dir/test.txt")
+            .write_str("garbage data for testing")?;
+        let fake_ssh = working_dir.child("ssh");
+        // Called as `ssh host command`
+        fake_ssh.write_str("#!/bin/sh\nfor command; do :; done\nexec sh -c \"$command\"\n")?;
+        std::fs::set_permissions(fake_ssh.path(), std::fs::Permissions::from_mode(0o755))?;
+        let archive = working_dir.child("dir.tar.gz");
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .env("CMPRSS_SSH", fake_ssh.path())
+            .args(["--ignore-pipes", "dir"])
+            .arg(format!("user@host:{}", archive.path().display()));
+        compress.assert().success();
+        archive.assert(predicate::path::is_file());
+
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.arg("list").arg(archive.path());
+        list.assert()
+            .success()
+            .stdout(predicate::str::contains("dir/test.txt"));
+
+        Ok(())
+    }
+
+    /// Compress the files written to a directory until stopped with SIGTERM
+    ///
+    /// ``` bash
+    /// cmprss watch --format zstd --output-dir compressed --rm-source incoming
+    /// ```
+    #[test]
+    #[cfg(unix)]
+    fn watch() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let incoming = working_dir.child("incoming");
+        incoming
+            .child("old.log")
+            .write_str("written before watching")?;
+        let compressed = working_dir.child("compressed");
+        let watch = Command::cargo_bin("cmprss")?
+            .current_dir(working_dir.path())
+            .args(["watch", "--format", "gzip", "--debounce", "100"])
+            .args(["--output-dir", "compressed", "--rm-source", "incoming"])
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let wait_for = |path: &std::path::Path| {
+            for _ in 0..500 {
+                if path.exists() {
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+        };
+        wait_for(compressed.child("old.log.gz").path());
+        incoming
+            .child("new.log")
+            .write_str("garbage data for testing")?;
+        wait_for(compressed.child("new.log.gz").path());
+        unsafe {
+            libc::kill(watch.id() as libc::pid_t, libc::SIGTERM);
+        }
+        let output = watch.wait_with_output()?;
+        assert!(output.status.success());
+        incoming.child("new.log").assert(predicate::path::missing());
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(compressed.path())
+            .args(["--ignore-pipes", "new.log.gz"]);
+        extract.assert().success();
+        compressed
+            .child("new.log")
+            .assert("garbage data for testing");
+        compressed
+            .child("old.log.gz")
+            .assert(predicate::path::is_file());
+
+        Ok(())
+    }
+
+    /// Compare a backup with the directory it was made from
+    ///
+    /// ``` bash
+    /// cmprss diff backup.tar.gz ./src
+    /// ```
+    #[test]
+    fn diff() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let src = working_dir.child("src");
+        src.child("main.rs").write_str("fn main() {}")?;
+        src.child("lib.rs").write_str("pub mod a;")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "src", "backup.tar.gz"]);
+        compress.assert().success();
+
+        let mut diff = Command::cargo_bin("cmprss")?;
+        diff.current_dir(working_dir.path())
+            .args(["diff", "backup.tar.gz", "./src"]);
+        diff.assert().success().stdout("");
+
+        src.child("main.rs").write_str("fn main() { todo!() }")?;
+        std::fs::remove_file(src.child("lib.rs"))?;
+        src.child("new.rs").write_str("")?;
+        let mut diff = Command::cargo_bin("cmprss")?;
+        diff.current_dir(working_dir.path())
+            .args(["diff", "backup.tar.gz", "./src"]);
+        diff.assert()
+            .failure()
+            .stdout("D lib.rs\nM main.rs\nA new.rs\n")
+            .stderr(predicate::str::contains("3 paths differ"));
+
+        Ok(())
+    }
+
+    /// Search the files inside an archive
+    ///
+    /// ``` bash
+    /// cmprss grep -n 'port\s*=' config.tar.gz
+    /// cmprss --format-output json grep 8080 config.tar.gz
+    /// ```
+    #[test]
+    fn grep() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("config/app.toml")
+            .write_str("name = \"app\"\nport = 8080\n")?;
+        working_dir
+            .child("config/README")
+            .write_str("no ports here\n")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "config",
+            "config.tar.gz",
+        ]);
+        compress.assert().success();
+
+        let mut grep = Command::cargo_bin("cmprss")?;
+        grep.current_dir(working_dir.path())
+            .args(["grep", "-n", r"port\s*=", "config.tar.gz"]);
+        grep.assert()
+            .success()
+            .stdout("config/app.toml:2:port = 8080\n");
+
+        let mut grep = Command::cargo_bin("cmprss")?;
+        grep.current_dir(working_dir.path()).args([
+            "--format-output",
+            "json",
+            "grep",
+            "8080",
+            "config.tar.gz",
+        ]);
+        grep.assert().success().stdout(
+            r#"{"archive":"config.tar.gz","path":"config/app.toml","binary":false,"line_number":2,"line":"port = 8080"}"#
+                .to_string()
+                + "\n",
+        );
+
+        let mut grep = Command::cargo_bin("cmprss")?;
+        grep.current_dir(working_dir.path())
+            .args(["grep", "-F", "8081", "config.tar.gz"]);
+        grep.assert().failure().stdout("");
+
+        Ok(())
+    }
+
+    /// Print the metadata of a compressed file
+    ///
+    /// ``` bash
+    /// cmprss info file.txt.gz
+    /// ```
+    #[test]
+    fn info() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("file.txt")
+            .write_str("garbage data for testing")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "gzip",
+            "--ignore-pipes",
+            "--level",
+            "9",
+            "file.txt",
+        ]);
+        compress.assert().success();
+
+        let mut info = Command::cargo_bin("cmprss")?;
+        info.current_dir(working_dir.path())
+            .args(["info", "file.txt.gz"]);
+        info.assert()
+            .success()
+            .stdout(predicate::str::starts_with(
+                "file: file.txt.gz\nformat: gzip\n",
+            ))
+            .stdout(predicate::str::contains("level: 9, the best compression\n"))
+            .stdout(predicate::str::contains("name: file.txt\n"))
+            .stdout(predicate::str::contains("uncompressed: 24 B (24 bytes)\n"));
+
+        Ok(())
+    }
+
+    /// Print the reports as JSON
+    ///
+    /// ``` bash
+    /// cmprss --format-output json list archive.tar.gz
+    /// cmprss --format-output json info archive.tar.gz
+    /// ```
+    #[test]
+    fn format_output_json() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir
+            .child("dir/file.txt")
+            .write_str("garbage data for testing")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["--ignore-pipes", "dir", "archive.tar.gz"]);
+        compress.assert().success();
+
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.current_dir(working_dir.path()).args([
+            "--format-output",
+            "json",
+            "list",
+            "archive.tar.gz",
+        ]);
+        list.assert()
+            .success()
+            .stdout(predicate::str::starts_with(
+                r#"{"archive":"archive.tar.gz","format":"tar.gz","comment":null,"entries":[{"path":"dir","is_dir":true,"#,
+            ))
+            .stdout(predicate::str::contains(
+                r#"{"path":"dir/file.txt","is_dir":false,"size":24,"#,
+            ));
+
+        let mut info = Command::cargo_bin("cmprss")?;
+        info.current_dir(working_dir.path()).args([
+            "info",
+            "--format-output",
+            "json",
+            "archive.tar.gz",
+        ]);
+        info.assert()
+            .success()
+            .stdout(predicate::str::starts_with(
+                r#"{"file":"archive.tar.gz","format":"tar.gz","size":"#,
+            ))
+            .stdout(predicate::str::ends_with(
+                r#","files":1,"directories":1,"files_size":24}"#.to_owned() + "\n",
+            ));
+
+        Ok(())
+    }
+
+    /// Rename the entries when archiving and extracting
+    ///
+    /// ``` bash
+    /// cmprss --transform 's,^build(/|$),,' build vendor.tar.gz
+    /// cmprss --transform 's/^/vendor\//' vendor.tar.gz out
+    /// ```
+    #[test]
+    fn transform() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir.child("build/lib/app.so").write_str("library")?;
+        working_dir.child("out").create_dir_all()?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "--transform",
+            "s,^build(/|$),,",
+            "build",
+            "vendor.tar.gz",
+        ]);
+        compress.assert().success();
+
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.current_dir(working_dir.path())
+            .args(["list", "vendor.tar.gz"]);
+        list.assert()
+            .success()
+            .stdout(predicate::str::contains("build").not())
+            .stdout(predicate::str::contains("lib/app.so"));
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "--transform",
+            r"s/^/vendor\//",
+            "vendor.tar.gz",
+            "out",
+        ]);
+        extract.assert().success();
+        working_dir.child("out/vendor/lib/app.so").assert("library");
+
+        let mut invalid = Command::cargo_bin("cmprss")?;
+        invalid.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "--transform",
+            "s/missing",
+            "build",
+            "other.tar.gz",
+        ]);
+        invalid
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("invalid transform s/missing"));
+
+        Ok(())
+    }
+
+    /// Store the files without their directories
+    ///
+    /// ``` bash
+    /// cmprss zip --junk-paths dist/linux/app dist/docs bundle.zip
+    /// cmprss --junk-paths a b same.tar
+    /// ```
+    #[test]
+    fn junk_paths() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir.child("dist/linux/app").write_str("binary")?;
+        working_dir.child("dist/docs/README").write_str("readme")?;
+        working_dir
+            .child("dist/docs/guide/intro.md")
+            .write_str("intro")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "zip",
+            "--ignore-pipes",
+            "--junk-paths",
+            "dist/linux/app",
+            "dist/docs",
+            "bundle.zip",
+        ]);
+        compress.assert().success();
+
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.current_dir(working_dir.path())
+            .args(["list", "bundle.zip"]);
+        list.assert()
+            .success()
+            .stdout(predicate::str::contains("/").not())
+            .stdout(predicate::str::contains("app"))
+            .stdout(predicate::str::contains("README"))
+            .stdout(predicate::str::contains("intro.md"));
+
+        // Files with the same name in different directories can't both be stored
+        working_dir.child("a/f").write_str("a")?;
+        working_dir.child("b/f").write_str("b")?;
+        for archive in ["same.zip", "same.tar"] {
+            let mut compress = Command::cargo_bin("cmprss")?;
+            compress.current_dir(working_dir.path()).args([
+                "--ignore-pipes",
+                "--junk-paths",
+                "a",
+                "b",
+                archive,
+            ]);
+            compress
+                .assert()
+                .failure()
+                .stderr(predicate::str::contains("would both be stored as f"));
+            working_dir
+                .child(archive)
+                .assert(predicate::path::missing());
+        }
+
+        Ok(())
+    }
+
+    /// Name the entry of piped input in tar and zip archives
+    ///
+    /// ``` bash
+    /// mysqldump | cmprss --arcname dump.sql backup.tar.gz
+    /// mysqldump | cmprss zip --arcname dump.sql backup.zip
+    /// ```
+    #[test]
+    fn arcname() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("input.sql");
+        file.write_str("INSERT INTO t VALUES (1);")?;
+
+        for archive in ["backup.tar.gz", "backup.zip"] {
+            let mut compress = Command::cargo_bin("cmprss")?;
+            compress
+                .current_dir(working_dir.path())
+                .args(["--arcname", "dump.sql", archive])
+                .stdin(Stdio::from(File::open(file.path())?));
+            compress.assert().success();
+
+            let mut cat = Command::cargo_bin("cmprss")?;
+            cat.current_dir(working_dir.path())
+                .args(["cat", archive, "dump.sql"]);
+            cat.assert().success().stdout("INSERT INTO t VALUES (1);");
+
+            let mut list = Command::cargo_bin("cmprss")?;
+            list.current_dir(working_dir.path()).args(["list", archive]);
+            list.assert()
+                .success()
+                .stdout(predicate::str::contains("dump.sql"));
+        }
+
+        Ok(())
+    }
+
+    /// Leave out the files ignored by .gitignore when archiving
+    ///
+    /// ``` bash
+    /// cmprss --gitignore project project.zip
+    /// ```
+    #[test]
+    fn gitignore() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let project = working_dir.child("project");
+        project.child(".gitignore").write_str("target/\n*.log\n")?;
+        project.child("src/main.rs").write_str("fn main() {}")?;
+        project.child("target/debug/app").write_str("binary")?;
+        project.child("src/debug.log").write_str("log")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "--gitignore",
+            "project",
+            "project.zip",
+        ]);
+        compress.assert().success();
+
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.current_dir(working_dir.path())
+            .args(["list", "project.zip"]);
+        list.assert()
+            .success()
+            .stdout(predicate::str::contains("project/.gitignore"))
+            .stdout(predicate::str::contains("project/src/main.rs"))
+            .stdout(predicate::str::contains("target").not())
+            .stdout(predicate::str::contains("debug.log").not());
+
+        Ok(())
+    }
+
+    /// Leave out version control metadata when archiving
+    ///
+    /// ``` bash
+    /// cmprss --exclude-vcs project project.tar
+    /// ```
+    #[test]
+    fn exclude_vcs() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let project = working_dir.child("project");
+        project
+            .child(".git/HEAD")
+            .write_str("ref: refs/heads/main")?;
+        project.child(".gitignore").write_str("target/")?;
+        project.child("vendor/.hg/hgrc").write_str("[paths]")?;
+        project.child("src/main.rs").write_str("fn main() {}")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "--exclude-vcs",
+            "project",
+            "project.tar",
+        ]);
+        compress.assert().success();
+
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.current_dir(working_dir.path())
+            .args(["list", "project.tar"]);
+        list.assert()
+            .success()
+            .stdout(predicate::str::contains("project/src/main.rs"))
+            .stdout(predicate::str::contains("project/vendor"))
+            .stdout(predicate::str::contains(".git").not())
+            .stdout(predicate::str::contains(".hg").not());
+
+        Ok(())
+    }
+
+    /// Archive the files listed by another tool
+    ///
+    /// ``` bash
+    /// find . -name '*.rs' -print0 | cmprss tar --files-from - -0 out.tar
+    /// cmprss --files-from list.txt out.zip
+    /// ```
+    #[test]
+    fn files_from() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir.child("src/main.rs").write_str("fn main() {}")?;
+        working_dir.child("src/lib/mod.rs").write_str("mod lib;")?;
+        working_dir.child("README").write_str("readme")?;
+
+        let found = working_dir.child("found");
+        found.write_str("./src/lib/mod.rs\0README\0./src/main.rs\0")?;
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["tar", "--files-from", "-", "-0", "out.tar"])
+            .stdin(Stdio::from(File::open(found.path())?));
+        compress.assert().success();
+
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.current_dir(working_dir.path())
+            .args(["list", "out.tar"]);
+        list.assert()
+            .success()
+            .stdout(predicate::str::contains("src/lib/mod.rs"))
+            .stdout(predicate::str::contains("README"))
+            .stdout(predicate::str::contains("src/main.rs"));
+
+        // Directories are stored without their contents
+        working_dir.child("list.txt").write_str("src\nREADME\n")?;
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "--files-from",
+            "list.txt",
+            "out.zip",
+        ]);
+        compress.assert().success();
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.current_dir(working_dir.path())
+            .args(["list", "out.zip"]);
+        list.assert()
+            .success()
+            .stdout(predicate::str::contains("src/"))
+            .stdout(predicate::str::contains("README"))
+            .stdout(predicate::str::contains("main.rs").not());
+
+        working_dir
+            .child("missing.txt")
+            .write_str("nothing-here\n")?;
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "--files-from",
+            "missing.txt",
+            "other.zip",
+        ]);
+        compress.assert().failure().stderr(predicate::str::contains(
+            "nothing-here, listed in missing.txt",
+        ));
+
+        Ok(())
+    }
+
+    /// Name the outputs of several files with a template
+    ///
+    /// ``` bash
+    /// cmprss gzip --output-template 'out/{n}-{stem}.{ext}' a.log b.log
+    /// cmprss gzip --recursive --output-template '{dir}/{stem}-{date}.{ext}' logs
+    /// ```
+    #[test]
+    fn output_template() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir.child("a.log").write_str("a")?;
+        working_dir.child("b.log").write_str("b")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["gzip", "--ignore-pipes", "--output-template"])
+            .args(["out/{n}-{stem}.{ext}", "a.log", "b.log"]);
+        compress.assert().success();
+        working_dir
+            .child("out/1-a.log.gz")
+            .assert(predicate::path::is_file());
+        working_dir
+            .child("out/2-b.log.gz")
+            .assert(predicate::path::is_file());
+
+        working_dir.child("logs/app.log").write_str("app")?;
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["gzip", "--recursive", "--output-template"])
+            .args(["{dir}/{stem}-{date}.{ext}", "logs"]);
+        compress.assert().success();
+        let names: Vec<String> = std::fs::read_dir(working_dir.child("logs").path())?
+            .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<_, _>>()?;
+        assert!(names.iter().any(|name| name.starts_with("app-20")
+            && name.ends_with(".log.gz")
+            && name.len() == "app-2024-01-31.log.gz".len()));
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["gzip", "--recursive", "--output-template"])
+            .args(["{dir}/{size}", "logs"]);
+        compress
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("unknown placeholder {size}"));
+
+        Ok(())
+    }
+
+    /// Write the output named after the input to another directory
+    ///
+    /// ``` bash
+    /// cmprss gzip --output-dir out/compressed file.txt
+    /// cmprss --output-dir restored out/compressed/file.txt.gz
+    /// cmprss tar --extract --output-dir tree archive.tar
+    /// ```
+    #[test]
+    fn output_dir() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir.child("file.txt").write_str("garbage data")?;
+        working_dir.child("dir/a.txt").write_str("a")?;
+
+        // Piped stdout doesn't take the output when a directory is asked for
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "gzip",
+            "--output-dir",
+            "out/compressed",
+            "file.txt",
+        ]);
+        compress.assert().success();
+        let compressed = working_dir.child("out/compressed/file.txt.gz");
+        compressed.assert(predicate::path::is_file());
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "--ignore-pipes",
+            "--output-dir",
+            "restored",
+            "out/compressed/file.txt.gz",
+        ]);
+        extract.assert().success();
+        working_dir
+            .child("restored/file.txt")
+            .assert("garbage data");
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "tar",
+            "--ignore-pipes",
+            "dir",
+            "archive.tar",
+        ]);
+        compress.assert().success();
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "tar",
+            "--ignore-pipes",
+            "--extract",
+            "--output-dir",
+            "tree",
+            "archive.tar",
+        ]);
+        extract.assert().success();
+        working_dir.child("tree/dir/a.txt").assert("a");
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "gzip",
+            "--ignore-pipes",
+            "--output-dir",
+            "elsewhere",
+            "file.txt",
+            "named.gz",
+        ]);
+        compress
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("--output-dir"));
+
+        Ok(())
+    }
+
+    /// Archive files from another directory and extract into one, like `tar -C`
+    ///
+    /// ``` bash
+    /// cmprss tar -C src a.txt lib out.tar
+    /// cmprss tar -C dest --extract out.tar
+    /// cmprss zip -C src --files-from list.txt listed.zip
+    /// ```
+    #[test]
+    fn directory() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        working_dir.child("src/a.txt").write_str("a")?;
+        working_dir.child("src/lib/b.txt").write_str("b")?;
+        working_dir.child("dest").create_dir_all()?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "tar",
+            "--ignore-pipes",
+            "-C",
+            "src",
+            "a.txt",
+            "lib",
+            "out.tar",
+        ]);
+        compress.assert().success();
+        working_dir
+            .child("out.tar")
+            .assert(predicate::path::is_file());
+
+        // Piped stdout doesn't take the extracted archive
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["tar", "-C", "dest", "--extract", "out.tar"]);
+        extract.assert().success();
+        working_dir.child("dest/a.txt").assert("a");
+        working_dir.child("dest/lib/b.txt").assert("b");
+
+        working_dir.child("list.txt").write_str("lib/b.txt\n")?;
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "zip",
+            "--ignore-pipes",
+            "-C",
+            "src",
+            "--files-from",
+            "list.txt",
+            "listed.zip",
+        ]);
+        compress.assert().success();
+        let mut list = Command::cargo_bin("cmprss")?;
+        list.current_dir(working_dir.path())
+            .args(["list", "listed.zip"]);
+        list.assert()
+            .success()
+            .stdout(predicate::str::contains("lib/b.txt"))
+            .stdout(predicate::str::contains("src/").not());
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress.current_dir(working_dir.path()).args([
+            "tar",
+            "--ignore-pipes",
+            "-C",
+            "missing",
+            "a.txt",
+            "x.tar",
+        ]);
+        compress
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("missing is not a directory"));
+
+        Ok(())
+    }
+
+    /// Use '-' for stdin and stdout, even when they don't look like pipes
+    ///
+    /// ``` bash
+    /// cmprss gzip --ignore-pipes - out.gz < file.txt
+    /// cmprss gzip --ignore-pipes --extract out.gz -
+    /// cmprss gzip --ignore-pipes --extract --input - --output - < out.gz
+    /// ```
+    #[test]
+    fn dash_stdio() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("file.txt");
+        file.write_str("garbage data")?;
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["gzip", "--ignore-pipes", "-", "out.gz"])
+            .stdin(Stdio::from(File::open(file.path())?));
+        compress.assert().success();
+        working_dir
+            .child("out.gz")
+            .assert(predicate::path::is_file());
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "gzip",
+            "--ignore-pipes",
+            "--extract",
+            "out.gz",
+            "-",
+        ]);
+        extract.assert().success().stdout("garbage data");
+        working_dir.child("-").assert(predicate::path::missing());
+
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract
+            .current_dir(working_dir.path())
+            .args(["gzip", "--ignore-pipes", "--extract", "--input", "-"])
+            .args(["--output", "-"])
+            .stdin(Stdio::from(File::open(working_dir.child("out.gz").path())?));
+        extract.assert().success().stdout("garbage data");
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["tar", "--ignore-pipes", "file.txt", "-", "out.tar"])
+            .stdin(Stdio::from(File::open(file.path())?));
+        compress.assert().failure().stderr(predicate::str::contains(
+            "can't be combined with other inputs",
+        ));
+
+        Ok(())
+    }
+
+    /// Force the output to stdout, like `gzip -c`
+    ///
+    /// ``` bash
+    /// cmprss gzip --ignore-pipes -c file.txt > file.txt.gz
+    /// cmprss gzip --ignore-pipes -d -c file.txt.gz
+    /// ```
+    #[test]
+    fn stdout() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let file = working_dir.child("file.txt");
+        file.write_str("garbage data")?;
+        let archive = working_dir.child("file.txt.gz");
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["gzip", "--ignore-pipes", "-z", "-c", "file.txt"])
+            .stdout(Stdio::from(File::create(archive.path())?));
+        compress.assert().success();
+        file.assert("garbage data");
+
+        // An existing last file is an input, not the output
+        let mut extract = Command::cargo_bin("cmprss")?;
+        extract.current_dir(working_dir.path()).args([
+            "gzip",
+            "--ignore-pipes",
+            "-d",
+            "-c",
+            "file.txt.gz",
+        ]);
+        extract.assert().success().stdout("garbage data");
+        archive.assert(predicate::path::is_file());
+
+        let mut compress = Command::cargo_bin("cmprss")?;
+        compress
+            .current_dir(working_dir.path())
+            .args(["gzip", "-c", "file.txt", "--output", "out.gz"]);
+        compress.assert().failure();
+
+        Ok(())
+    }
+
+    /// Run cmprss as gzip, gunzip and zcat through symlinks
+    ///
+    /// ``` bash
+    /// ln -s cmprss gzip && ln -s cmprss gunzip && ln -s cmprss zcat
+    /// gzip -9k logs/app.log
+    /// zcat logs/app.log.gz
+    /// gzip -c logs/app.log logs/other.log > both.gz
+    /// zcat logs/app.log.gz both.gz
+    /// gunzip -f logs/app.log.gz
+    /// gzip -r logs
+    /// ```
+    #[test]
+    fn drop_in() -> Result<(), Box<dyn std::error::Error>> {
+        let working_dir = assert_fs::TempDir::new()?;
+        let cmprss = assert_cmd::cargo::cargo_bin("cmprss");
+        for tool in ["gzip", "gunzip", "zcat"] {
+            std::os::unix::fs::symlink(&cmprss, working_dir.child(tool).path())?;
+        }
+        let file = working_dir.child("logs/app.log");
+        file.write_str("garbage data")?;
+        let archive = working_dir.child("logs/app.log.gz");
+
+        // Written next to the input even when stdout is piped, like gzip
+        let mut compress = Command::new(working_dir.child("gzip").path());
+        compress
+            .current_dir(working_dir.path())
+            .args(["-9k", "logs/app.log"]);
+        compress.assert().success().stdout("");
+        archive.assert(predicate::path::is_file());
+        file.assert("garbage data");
+
+        let mut cat = Command::new(working_dir.child("zcat").path());
+        cat.current_dir(working_dir.path()).arg("logs/app.log.gz");
+        cat.assert().success().stdout("garbage data");
+
+        let mut cat = Command::new(working_dir.child("zcat").path());
+        cat.current_dir(working_dir.path())
+            .stdin(Stdio::from(File::open(archive.path())?));
+        cat.assert().success().stdout("garbage data");
+
+        // Several files are written to stdout one after the other
+        working_dir.child("logs/other.log").write_str(" and more")?;
+        let mut compress = Command::new(working_dir.child("gzip").path());
+        compress
+            .current_dir(working_dir.path())
+            .args(["-c", "logs/app.log", "logs/other.log"]);
+        let both = compress.assert().success().get_output().stdout.clone();
+        working_dir.child("both.gz").write_binary(&both)?;
+        working_dir
+            .child("logs/other.log")
+            .assert(predicate::path::is_file());
+
+        let mut cat = Command::new(working_dir.child("zcat").path());
+        cat.current_dir(working_dir.path())
+            .args(["logs/app.log.gz", "both.gz"]);
+        cat.assert()
+            .success()
+            .stdout("garbage datagarbage data and more");
+
+        let mut cat = Command::new(working_dir.child("gunzip").path());
+        cat.current_dir(working_dir.path())
+            .args(["-c", "both.gz", "logs/app.log.gz"]);
+        cat.assert()
+            .success()
+            .stdout("garbage data and moregarbage data");
+        archive.assert(predicate::path::is_file());
+
+        // The archive is removed once extracted, like gunzip
+        let mut extract = Command::new(working_dir.child("gunzip").path());
+        extract
+            .current_dir(working_dir.path())
+            .args(["-f", "logs/app.log.gz"]);
+        extract.assert().success();
+        file.assert("garbage data");
+        archive.assert(predicate::path::missing());
+
+        let mut compress = Command::new(working_dir.child("gzip").path());
+        compress
+            .current_dir(working_dir.path())
+            .args(["-S", ".z", "logs/app.log"]);
+        compress
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("-S is not supported"));
+
+        // Directories are left alone without -r, like gzip
+        let mut compress = Command::new(working_dir.child("gzip").path());
+        compress.current_dir(working_dir.path()).arg("logs");
+        compress
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("logs is a directory"));
+        file.assert("garbage data");
+        archive.assert(predicate::path::missing());
+
+        let mut compress = Command::new(working_dir.child("gzip").path());
+        compress
+            .current_dir(working_dir.path())
+            .args(["-r", "logs"]);
+        compress.assert().success();
+        file.assert(predicate::path::missing());
+        archive.assert(predicate::path::is_file());
+
+        Ok(())
+    }
 }